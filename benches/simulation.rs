@@ -0,0 +1,67 @@
+//! Measures how long a 10,000 gate circuit takes to settle, first from
+//! scratch and then after a single input changes, and how long the same
+//! circuit takes to evaluate once compiled. Run with
+//! `cargo bench --bench simulation`.
+
+use std::time::Instant;
+
+use tenorite_ui::circuit::{Circuit, ComponentId};
+use tenorite_ui::component::Interaction;
+use tenorite_ui::libraries::default_library;
+use tenorite_ui::simulation::Simulator;
+use tenorite_ui::compiled::CompiledCircuit;
+
+const ROWS: i32 = 100;
+const GATES_PER_ROW: i32 = 100;
+
+// Builds independent chains of NOT gates, each driven by its own input pin.
+fn build() -> (Circuit, Vec<ComponentId>) {
+    let library = default_library();
+    let mut circuit = Circuit::new();
+    let mut inputs = Vec::new();
+    for row in 0..ROWS {
+        let y = row * 3;
+        inputs.push(circuit.add_component(library.create("input_pin").unwrap(), -2, y));
+        for i in 0..GATES_PER_ROW {
+            circuit.add_component(library.create("not_gate").unwrap(), i * 3, y);
+        }
+    }
+    (circuit, inputs)
+}
+
+fn main() {
+    let (circuit, inputs) = build();
+    let mut compiled = CompiledCircuit::compile(&circuit).unwrap();
+    let mut sim = Simulator::new(circuit);
+
+    let start = Instant::now();
+    sim.run_until_stable().unwrap();
+    println!(
+        "initial settle: {:?} ({} evaluations of {} components)",
+        start.elapsed(), sim.evaluations(), sim.circuit().num_components()
+    );
+
+    let iterations = 100;
+    let before = sim.evaluations();
+    let start = Instant::now();
+    for i in 0..iterations {
+        sim.interact(inputs[i % inputs.len()], Interaction::Press);
+        sim.run_until_stable().unwrap();
+    }
+    println!(
+        "single input change: {:?} per change ({} evaluations per change)",
+        start.elapsed() / iterations as u32,
+        (sim.evaluations() - before) / iterations as u64
+    );
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        compiled.interact(inputs[i % inputs.len()], Interaction::Press);
+        compiled.run();
+    }
+    println!(
+        "compiled settle: {:?} ({} components evaluated)",
+        start.elapsed() / iterations as u32,
+        compiled.len()
+    );
+}
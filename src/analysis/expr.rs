@@ -0,0 +1,238 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::error::Error;
+
+use crate::simulation::Bit;
+use crate::compiled::GateOp;
+
+/// A boolean expression over named single bit variables.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BooleanExpr {
+    Const(bool),
+    Var(String),
+    Not(Box<BooleanExpr>),
+    And(Vec<BooleanExpr>),
+    Or(Vec<BooleanExpr>),
+    /// True when an odd number of the operands are true.
+    Xor(Vec<BooleanExpr>),
+}
+
+impl BooleanExpr {
+    pub fn var<S: Into<String>>(name: S) -> Self {
+        BooleanExpr::Var(name.into())
+    }
+    pub fn not(expr: BooleanExpr) -> Self {
+        BooleanExpr::Not(Box::new(expr))
+    }
+    /// Parses an expression such as `a & !b | (c ^ 1)`. The operators, from
+    /// lowest to highest precedence, are `|` (or `+`), `^`, `&` (or `*`) and
+    /// `!` (or `~`).
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { input, position: 0 };
+        let result = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.position < input.len() {
+            return Err(parser.error("Unexpected character"));
+        }
+        Ok(result)
+    }
+    /// The names of the variables used by the expression, in sorted order.
+    pub fn variables(&self) -> Vec<String> {
+        let mut result = BTreeSet::new();
+        self.collect_variables(&mut result);
+        result.into_iter().collect()
+    }
+    fn collect_variables(&self, result: &mut BTreeSet<String>) {
+        match self {
+            BooleanExpr::Const(_) => {},
+            BooleanExpr::Var(name) => { result.insert(name.clone()); },
+            BooleanExpr::Not(expr) => expr.collect_variables(result),
+            BooleanExpr::And(exprs) | BooleanExpr::Or(exprs) | BooleanExpr::Xor(exprs) => {
+                for expr in exprs {
+                    expr.collect_variables(result);
+                }
+            },
+        }
+    }
+    /// Evaluates the expression, looking up the value of each variable with
+    /// the given function.
+    pub fn evaluate<F: Fn(&str) -> bool>(&self, lookup: &F) -> bool {
+        match self {
+            BooleanExpr::Const(value) => *value,
+            BooleanExpr::Var(name) => lookup(name),
+            BooleanExpr::Not(expr) => !expr.evaluate(lookup),
+            BooleanExpr::And(exprs) => exprs.iter().all(|e| e.evaluate(lookup)),
+            BooleanExpr::Or(exprs) => exprs.iter().any(|e| e.evaluate(lookup)),
+            BooleanExpr::Xor(exprs) => exprs.iter().filter(|e| e.evaluate(lookup)).count() % 2 == 1,
+        }
+    }
+    /// Evaluates the expression over simulated bits, so that unknown and
+    /// error values propagate as they would through the equivalent gates.
+    /// A floating variable is unknown.
+    pub fn evaluate_bits<F: Fn(&str) -> Bit>(&self, lookup: &F) -> Bit {
+        let apply = |op: GateOp, exprs: &[BooleanExpr]| op.apply(exprs.iter().map(|e| e.evaluate_bits(lookup)));
+        match self {
+            BooleanExpr::Const(value) => Bit::from_bool(*value),
+            BooleanExpr::Var(name) => match lookup(name) {
+                Bit::HighZ => Bit::Unknown,
+                bit => bit,
+            },
+            BooleanExpr::Not(expr) => !expr.evaluate_bits(lookup),
+            BooleanExpr::And(exprs) => apply(GateOp::And, exprs),
+            BooleanExpr::Or(exprs) => apply(GateOp::Or, exprs),
+            BooleanExpr::Xor(exprs) => apply(GateOp::Parity, exprs),
+        }
+    }
+}
+
+impl fmt::Display for BooleanExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, exprs: &[BooleanExpr], op: &str| {
+            write!(f, "(")?;
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", op)?;
+                }
+                write!(f, "{}", expr)?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            BooleanExpr::Const(value) => write!(f, "{}", *value as u8),
+            BooleanExpr::Var(name) => write!(f, "{}", name),
+            BooleanExpr::Not(expr) => write!(f, "!{}", expr),
+            BooleanExpr::And(exprs) => join(f, exprs, "&"),
+            BooleanExpr::Or(exprs) => join(f, exprs, "|"),
+            BooleanExpr::Xor(exprs) => join(f, exprs, "^"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte offset in the input at which the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for ParseError {}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { position: self.position, message: message.into() }
+    }
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+    }
+    // Consumes the next character if it is one of `chars`
+    fn eat(&mut self, chars: &[char]) -> bool {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if chars.contains(&c) => {
+                self.position += c.len_utf8();
+                true
+            },
+            _ => false,
+        }
+    }
+    fn parse_binary<F>(&mut self, ops: &[char], make: fn(Vec<BooleanExpr>) -> BooleanExpr, mut operand: F)
+        -> Result<BooleanExpr, ParseError>
+    where
+        F: FnMut(&mut Self) -> Result<BooleanExpr, ParseError>
+    {
+        let mut operands = vec![operand(self)?];
+        while self.eat(ops) {
+            operands.push(operand(self)?);
+        }
+        Ok(if operands.len() == 1 { operands.pop().unwrap() } else { make(operands) })
+    }
+    fn parse_or(&mut self) -> Result<BooleanExpr, ParseError> {
+        self.parse_binary(&['|', '+'], BooleanExpr::Or, Self::parse_xor)
+    }
+    fn parse_xor(&mut self) -> Result<BooleanExpr, ParseError> {
+        self.parse_binary(&['^'], BooleanExpr::Xor, Self::parse_and)
+    }
+    fn parse_and(&mut self) -> Result<BooleanExpr, ParseError> {
+        self.parse_binary(&['&', '*'], BooleanExpr::And, Self::parse_unary)
+    }
+    fn parse_unary(&mut self) -> Result<BooleanExpr, ParseError> {
+        if self.eat(&['!', '~']) {
+            return Ok(BooleanExpr::not(self.parse_unary()?));
+        }
+        if self.eat(&['(']) {
+            let result = self.parse_or()?;
+            if !self.eat(&[')']) {
+                return Err(self.error("Expected `)`"));
+            }
+            return Ok(result);
+        }
+        self.skip_whitespace();
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+        match &self.input[start..self.position] {
+            "" => Err(self.error("Expected a variable or constant")),
+            "0" => Ok(BooleanExpr::Const(false)),
+            "1" => Ok(BooleanExpr::Const(true)),
+            name if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                self.position = start;
+                Err(self.error("Variable names cannot start with a digit"))
+            },
+            name => Ok(BooleanExpr::var(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_evaluate() {
+        let expr = BooleanExpr::parse("a & !b | (c ^ 1) & a").unwrap();
+        assert_eq!(expr.to_string(), "((a & !b) | ((c ^ 1) & a))");
+        assert_eq!(expr.variables(), vec!["a", "b", "c"]);
+        assert!(expr.evaluate(&|v| v == "a"));
+        assert!(!expr.evaluate(&|v| v != "a"));
+        assert!(expr.evaluate(&|v| v == "a" || v == "b"));
+
+        assert_eq!(BooleanExpr::parse("a &").unwrap_err().position, 3);
+        assert_eq!(BooleanExpr::parse("(a").unwrap_err().message, "Expected `)`");
+        assert!(BooleanExpr::parse("a b").is_err());
+        assert!(BooleanExpr::parse("2a").is_err());
+    }
+
+    #[test]
+    fn evaluate_bits() {
+        let expr = BooleanExpr::parse("a & b | !c").unwrap();
+        let bits = |a, b, c| move |v: &str| match v { "a" => a, "b" => b, _ => c };
+        assert_eq!(expr.evaluate_bits(&bits(Bit::One, Bit::One, Bit::One)), Bit::One);
+        assert_eq!(expr.evaluate_bits(&bits(Bit::Zero, Bit::Unknown, Bit::One)), Bit::Zero);
+        assert_eq!(expr.evaluate_bits(&bits(Bit::One, Bit::Unknown, Bit::One)), Bit::Unknown);
+        assert_eq!(expr.evaluate_bits(&bits(Bit::One, Bit::HighZ, Bit::Zero)), Bit::One);
+        assert_eq!(expr.evaluate_bits(&bits(Bit::Error, Bit::One, Bit::One)), Bit::Error);
+    }
+}
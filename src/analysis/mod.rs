@@ -0,0 +1,175 @@
+use std::fmt;
+use std::error::Error;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::Interaction;
+use crate::compiled::CompiledCircuit;
+use crate::simulation::{Simulator, SimulationError, Value};
+use crate::subcircuit::{ports, INPUT_PIN, OUTPUT_PIN};
+
+mod expr;
+mod synthesis;
+
+pub use self::expr::{BooleanExpr, ParseError};
+pub use self::synthesis::{Implicant, minimize, synthesize, synthesize_table, lookup_table};
+
+/// The largest number of input bits for which a truth table is generated by
+/// default. Each additional bit doubles the number of rows.
+pub const MAX_INPUT_BITS: u32 = 16;
+
+#[derive(Debug, Clone)]
+pub enum AnalysisError {
+    TooManyInputs { bits: u32, limit: u32 },
+    Simulation(SimulationError),
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalysisError::TooManyInputs { bits, limit } =>
+                write!(f, "The circuit has {} input bits, but at most {} are supported", bits, limit),
+            AnalysisError::Simulation(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for AnalysisError {}
+
+impl From<SimulationError> for AnalysisError {
+    fn from(e: SimulationError) -> Self {
+        AnalysisError::Simulation(e)
+    }
+}
+
+/// An input or output pin of the circuit being analysed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub component: ComponentId,
+    pub bits: u32,
+}
+
+/// The outputs of a circuit for every combination of its inputs. Row `i`
+/// holds the outputs when the inputs, taken together as a single number with
+/// the first input in the most significant bits, are equal to `i`.
+#[derive(Debug, Clone)]
+pub struct TruthTable {
+    pub inputs: Vec<Port>,
+    pub outputs: Vec<Port>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl TruthTable {
+    /// Builds the truth table for the input and output pins of a circuit,
+    /// ordered from top to bottom and then left to right.
+    pub fn generate(circuit: &Circuit) -> Result<Self, AnalysisError> {
+        Self::generate_with_limit(circuit, MAX_INPUT_BITS)
+    }
+    pub fn generate_with_limit(circuit: &Circuit, limit: u32) -> Result<Self, AnalysisError> {
+        let to_ports = |id| ports(circuit, id).into_iter()
+            .map(|(component, bits)| Port { component, bits })
+            .collect::<Vec<_>>();
+        Self::generate_for(circuit, to_ports(INPUT_PIN), to_ports(OUTPUT_PIN), limit)
+    }
+    /// Builds the truth table for a specific set of inputs and outputs. The
+    /// inputs must accept `Interaction::Set`, and the outputs must report
+    /// their value via `display_value`.
+    pub fn generate_for(
+        circuit: &Circuit,
+        inputs: Vec<Port>,
+        outputs: Vec<Port>,
+        limit: u32,
+    ) -> Result<Self, AnalysisError> {
+        let bits: u32 = inputs.iter().map(|p| p.bits).sum();
+        if bits > limit {
+            return Err(AnalysisError::TooManyInputs { bits, limit });
+        }
+        let mut table = TruthTable { inputs, outputs, rows: Vec::new() };
+
+        // Circuits without feedback are much faster to evaluate when compiled
+        if let Ok(mut compiled) = CompiledCircuit::compile(circuit) {
+            for row in 0..1u64 << bits {
+                for (port, value) in table.inputs.iter().zip(table.input_values(row)) {
+                    compiled.interact(port.component, Interaction::Set(value));
+                }
+                compiled.run();
+                let outputs = table.output_values(|id| compiled.display_value(id));
+                table.rows.push(outputs);
+            }
+            return Ok(table);
+        }
+
+        let mut sim = Simulator::new(circuit.clone());
+        for row in 0..1u64 << bits {
+            for (port, value) in table.inputs.iter().zip(table.input_values(row)) {
+                sim.interact(port.component, Interaction::Set(value));
+            }
+            sim.run_until_stable()?;
+            let outputs = table.output_values(|id| sim.display_value(id));
+            table.rows.push(outputs);
+        }
+        Ok(table)
+    }
+    fn output_values<F: Fn(ComponentId) -> Option<Value>>(&self, display_value: F) -> Vec<Value> {
+        self.outputs.iter().map(|port| {
+            display_value(port.component).unwrap_or_else(|| Value::floating(port.bits))
+        }).collect()
+    }
+    pub fn input_bits(&self) -> u32 {
+        self.inputs.iter().map(|p| p.bits).sum()
+    }
+    /// The value of each input in the given row.
+    pub fn input_values(&self, row: u64) -> Vec<Value> {
+        let mut shift = self.input_bits();
+        self.inputs.iter().map(|port| {
+            shift -= port.bits;
+            Value::from_u64(row >> shift, port.bits)
+        }).collect()
+    }
+    /// Returns the input values of the rows where an output bit is one, or
+    /// `None` if the output is not fully defined for every row.
+    pub fn minterms(&self, output: usize, bit: u32) -> Option<Vec<u64>> {
+        let mut result = Vec::new();
+        for (row, outputs) in self.rows.iter().enumerate() {
+            if outputs[output].bit(bit).to_bool()? {
+                result.push(row as u64);
+            }
+        }
+        Some(result)
+    }
+}
+
+impl fmt::Display for TruthTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (row, outputs) in self.rows.iter().enumerate() {
+            let inputs: Vec<_> = self.input_values(row as u64).iter().map(|v| v.to_string()).collect();
+            let outputs: Vec<_> = outputs.iter().map(|v| v.to_string()).collect();
+            writeln!(f, "{} | {}", inputs.join(" "), outputs.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::default_library;
+
+    #[test]
+    fn xor_truth_table() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let gate = circuit.add_component(library.create("xor_gate").unwrap(), 0, 0);
+        let pins: Vec<_> = circuit.get_component(gate).unwrap().get_shape().pins
+            .iter().map(|p| (p.x, p.y)).collect();
+        circuit.add_component(library.create("input_pin").unwrap(), pins[0].0 - 2, pins[0].1 - 1);
+        circuit.add_component(library.create("input_pin").unwrap(), pins[1].0 - 2, pins[1].1 - 1);
+        circuit.add_component(library.create("output_pin").unwrap(), pins[2].0, pins[2].1 - 1);
+
+        let table = TruthTable::generate(&circuit).unwrap();
+        assert_eq!(table.to_string(), "0 0 | 0\n0 1 | 1\n1 0 | 1\n1 1 | 0\n");
+        assert_eq!(table.minterms(0, 0), Some(vec![1, 2]));
+
+        let error = TruthTable::generate_with_limit(&circuit, 1).unwrap_err();
+        assert!(matches!(error, AnalysisError::TooManyInputs { bits: 2, limit: 1 }));
+    }
+}
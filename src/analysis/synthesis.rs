@@ -0,0 +1,336 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::circuit::{Circuit, Wire};
+use crate::component::{ComponentInfo, PinDirection, PropertyError};
+use crate::bits::BitString;
+use crate::library::Library;
+use crate::libraries::default_library;
+use super::TruthTable;
+use super::expr::BooleanExpr;
+
+// The largest number of inputs supported by the n-ary gates
+const MAX_GATE_INPUTS: usize = 32;
+
+/// A product term covering the rows whose bits match `value` everywhere
+/// except where `mask` is set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Implicant {
+    pub value: u64,
+    pub mask: u64,
+}
+
+impl Implicant {
+    pub fn covers(self, row: u64) -> bool {
+        row & !self.mask == self.value
+    }
+    /// Converts the implicant to a conjunction of literals. The first
+    /// variable corresponds to the most significant bit.
+    pub fn to_expr(self, variables: &[String]) -> BooleanExpr {
+        let n = variables.len();
+        let literals: Vec<_> = variables.iter().enumerate()
+            .filter(|&(i, _)| self.mask >> (n - 1 - i) & 1 == 0)
+            .map(|(i, name)| {
+                let var = BooleanExpr::var(name.as_str());
+                if self.value >> (n - 1 - i) & 1 == 1 { var } else { BooleanExpr::not(var) }
+            })
+            .collect();
+        BooleanExpr::And(literals)
+    }
+}
+
+/// Finds a minimal set of implicants covering every minterm using the
+/// Quine-McCluskey algorithm. Rows listed in `dont_cares` may be covered or
+/// not, whichever gives the simpler result.
+pub fn minimize(minterms: &[u64], dont_cares: &[u64]) -> Vec<Implicant> {
+    // Repeatedly merge implicants differing in a single bit, keeping those
+    // which could not be merged as the prime implicants.
+    let mut current: BTreeSet<_> = minterms.iter().chain(dont_cares)
+        .map(|&value| Implicant { value, mask: 0 })
+        .collect();
+    let mut primes = Vec::new();
+    while !current.is_empty() {
+        let mut next = BTreeSet::new();
+        let mut merged = BTreeSet::new();
+        for &a in &current {
+            for &b in current.range(a..) {
+                let diff = a.value ^ b.value;
+                if a.mask == b.mask && diff.count_ones() == 1 {
+                    next.insert(Implicant { value: a.value & !diff, mask: a.mask | diff });
+                    merged.insert(a);
+                    merged.insert(b);
+                }
+            }
+        }
+        primes.extend(current.difference(&merged).cloned());
+        current = next;
+    }
+
+    // Take the essential prime implicants, and then greedily cover the
+    // remaining minterms.
+    let mut remaining: BTreeSet<_> = minterms.iter().cloned().collect();
+    let mut result = Vec::new();
+    for &row in minterms {
+        let mut covering = primes.iter().filter(|p| p.covers(row));
+        if let (Some(&prime), None) = (covering.next(), covering.next()) {
+            if !result.contains(&prime) {
+                result.push(prime);
+                remaining.retain(|&r| !prime.covers(r));
+            }
+        }
+    }
+    while !remaining.is_empty() {
+        let &best = primes.iter()
+            .max_by_key(|p| (remaining.iter().filter(|&&r| p.covers(r)).count(), p.mask.count_ones()))
+            .expect("Every minterm is covered by a prime implicant");
+        result.push(best);
+        remaining.retain(|&r| !best.covers(r));
+    }
+    result.sort();
+    result
+}
+
+/// Builds a circuit computing an expression, with an input pin for each
+/// variable in sorted order and a single output pin.
+pub fn synthesize(expr: &BooleanExpr) -> Circuit {
+    let mut builder = Builder::new();
+    builder.build(&expr.variables(), &[expr.clone()])
+}
+
+/// Builds a minimal two-level circuit with the same behaviour as a truth
+/// table. The circuit has a single bit input pin for each input bit and a
+/// single bit output pin for each output bit, most significant first. Rows
+/// where an output bit is not fully defined are treated as don't-cares.
+pub fn synthesize_table(table: &TruthTable) -> Circuit {
+    let variables: Vec<_> = (0..table.input_bits()).map(|i| format!("i{}", i)).collect();
+    let mut outputs = Vec::new();
+    for (index, port) in table.outputs.iter().enumerate() {
+        for bit in (0..port.bits).rev() {
+            let (mut minterms, mut dont_cares) = (Vec::new(), Vec::new());
+            for (row, values) in table.rows.iter().enumerate() {
+                match values[index].bit(bit).to_bool() {
+                    Some(true) => minterms.push(row as u64),
+                    Some(false) => {},
+                    None => dont_cares.push(row as u64),
+                }
+            }
+            let terms = minimize(&minterms, &dont_cares).into_iter()
+                .map(|implicant| implicant.to_expr(&variables))
+                .collect();
+            outputs.push(BooleanExpr::Or(terms));
+        }
+    }
+    let mut builder = Builder::new();
+    builder.build(&variables, &outputs)
+}
+
+/// Creates a lookup table component with the same behaviour as a truth
+/// table. The inputs are concatenated onto the table's input and the outputs
+/// onto its output, first port most significant. Output bits which are not
+/// fully defined become zero. Fails if the table has too many input bits.
+pub fn lookup_table(table: &TruthTable) -> Result<ComponentInfo, PropertyError> {
+    let out_bits = table.outputs.iter().map(|port| port.bits).sum::<u32>().max(1);
+    let rows: Vec<_> = table.rows.iter().map(|values| {
+        let bits = values.iter().rev()
+            .flat_map(|value| value.bits().iter().map(|bit| bit.to_bool().unwrap_or(false)))
+            .collect();
+        BitString::from_bits(bits).resized(out_bits).to_string()
+    }).collect();
+    let mut result = default_library().create("lookup_table").expect("Lookup tables are a standard component");
+    result.set_property("in_bits", table.input_bits().max(1).into())?;
+    result.set_property("out_bits", out_bits.into())?;
+    // A table with no inputs has a single row, repeated for the unused bit
+    let len = 1 << table.input_bits().max(1);
+    result.set_property("rows", rows.into_iter().cycle().take(len).collect::<Vec<_>>().into())?;
+    Ok(result)
+}
+
+// Removes redundant nesting so that each node maps directly to a component.
+fn simplify(expr: &BooleanExpr) -> BooleanExpr {
+    let nary = |exprs: &[BooleanExpr], make: fn(Vec<BooleanExpr>) -> BooleanExpr, empty: bool| {
+        let mut exprs: Vec<_> = exprs.iter().map(simplify).collect();
+        match exprs.len() {
+            0 => BooleanExpr::Const(empty),
+            1 => exprs.pop().unwrap(),
+            n if n > MAX_GATE_INPUTS => simplify(&make(exprs.chunks(MAX_GATE_INPUTS).map(|c| make(c.to_vec())).collect())),
+            _ => make(exprs),
+        }
+    };
+    match expr {
+        BooleanExpr::Not(inner) => BooleanExpr::not(simplify(inner)),
+        BooleanExpr::And(exprs) => nary(exprs, BooleanExpr::And, true),
+        BooleanExpr::Or(exprs) => nary(exprs, BooleanExpr::Or, false),
+        BooleanExpr::Xor(exprs) => nary(exprs, BooleanExpr::Xor, false),
+        _ => expr.clone(),
+    }
+}
+
+// Each node is given its own column and its own band of rows, increasing
+// down and to the right. The output of a node is connected to a vertical
+// rail to the right of its column, and each consumer is connected by a
+// horizontal wire from the rail to its input pin. Since no two pins share a
+// row and rails never share a column with a pin, wires only ever meet where
+// they are meant to connect.
+const COLUMN_WIDTH: i32 = 6;
+const RAIL_OFFSET: i32 = 5;
+
+struct Builder {
+    library: Library,
+    circuit: Circuit,
+    nodes: HashMap<BooleanExpr, usize>,
+    // The column of the rail of each node, and how far down it extends
+    rails: Vec<(i32, i32)>,
+    y: i32,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            library: default_library(),
+            circuit: Circuit::new(),
+            nodes: HashMap::new(),
+            rails: Vec::new(),
+            y: 0,
+        }
+    }
+    fn build(&mut self, variables: &[String], outputs: &[BooleanExpr]) -> Circuit {
+        // Inputs are added first so that the pins are ordered to match
+        for name in variables {
+            self.node(&BooleanExpr::var(name.as_str()));
+        }
+        let outputs: Vec<_> = outputs.iter().map(|expr| self.node(&simplify(expr))).collect();
+        for source in outputs {
+            let component = self.library.create("output_pin").expect("Output pins are built in");
+            self.place(component, &[source]);
+        }
+        std::mem::replace(&mut self.circuit, Circuit::new())
+    }
+    fn create(&self, id: &str, num_inputs: usize) -> ComponentInfo {
+        let mut component = self.library.create(id).expect("Gates are built in");
+        if num_inputs > 2 {
+            component.set_property("num_inputs", (num_inputs as u32).into())
+                .expect("Gate input count is within range");
+        }
+        component
+    }
+    // Returns the index of the node computing an expression, adding it if
+    // it does not exist yet.
+    fn node(&mut self, expr: &BooleanExpr) -> usize {
+        if let Some(&index) = self.nodes.get(expr) {
+            return index;
+        }
+        let (component, inputs) = match expr {
+            BooleanExpr::Const(value) => {
+                let mut component = self.create("constant", 0);
+                component.set_property("value", if *value { "1" } else { "0" }.into())
+                    .expect("Constant value is valid");
+                (component, Vec::new())
+            },
+            BooleanExpr::Var(_) => (self.create("input_pin", 0), Vec::new()),
+            BooleanExpr::Not(inner) => (self.create("not_gate", 1), vec![self.node(inner)]),
+            BooleanExpr::And(exprs) => (self.create("and_gate", exprs.len()), self.nodes_of(exprs)),
+            BooleanExpr::Or(exprs) => (self.create("or_gate", exprs.len()), self.nodes_of(exprs)),
+            BooleanExpr::Xor(exprs) => {
+                let id = if exprs.len() == 2 { "xor_gate" } else { "odd_parity" };
+                (self.create(id, exprs.len()), self.nodes_of(exprs))
+            },
+        };
+        let index = self.place(component, &inputs);
+        self.nodes.insert(expr.clone(), index);
+        index
+    }
+    fn nodes_of(&mut self, exprs: &[BooleanExpr]) -> Vec<usize> {
+        exprs.iter().map(|expr| self.node(expr)).collect()
+    }
+    // Adds a component in the next column, and connects its inputs
+    fn place(&mut self, component: ComponentInfo, inputs: &[usize]) -> usize {
+        let index = self.rails.len();
+        let x = index as i32 * COLUMN_WIDTH;
+        let shape = component.get_shape();
+        let (cx, cy) = (x + 1, self.y);
+        self.y += shape.height + 2;
+        self.circuit.add_component(component, cx, cy);
+
+        let input_pins = shape.pins.iter().filter(|p| p.direction == PinDirection::Input);
+        for (pin, &source) in input_pins.zip(inputs) {
+            let (rail, ref mut end) = self.rails[source];
+            let target = (cx + pin.x, cy + pin.y);
+            self.circuit.add_wire(Wire::new((rail, *end), (rail, target.1)));
+            self.circuit.add_wire(Wire::new((rail, target.1), target));
+            *end = target.1;
+        }
+
+        let rail = x + RAIL_OFFSET;
+        let output = shape.pins.iter().rev().find(|p| p.direction == PinDirection::Output);
+        let end = match output {
+            Some(pin) => {
+                let start = (cx + pin.x, cy + pin.y);
+                if start.0 != rail {
+                    self.circuit.add_wire(Wire::new(start, (rail, start.1)));
+                }
+                start.1
+            },
+            None => cy,
+        };
+        self.rails.push((rail, end));
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(circuit: &Circuit, variables: usize, expected: impl Fn(u64) -> bool) {
+        let table = TruthTable::generate(circuit).unwrap();
+        assert_eq!(table.input_bits() as usize, variables);
+        for row in 0..1u64 << variables {
+            assert_eq!(table.rows[row as usize][0].to_u64(), Some(expected(row) as u64), "row {}", row);
+        }
+    }
+
+    #[test]
+    fn synthesize_expression() {
+        let expr = BooleanExpr::parse("(a & !b) | (b ^ c ^ d) | !(a | 0)").unwrap();
+        let circuit = synthesize(&expr);
+        let variables = expr.variables();
+        check(&circuit, variables.len(), |row| expr.evaluate(&|name| {
+            let i = variables.iter().position(|v| v == name).unwrap();
+            row >> (variables.len() - 1 - i) & 1 == 1
+        }));
+    }
+
+    #[test]
+    fn minimize_and_synthesize_table() {
+        // f(a, b, c) = a | (b & c)
+        let implicants = minimize(&[3, 4, 5, 6, 7], &[]);
+        assert_eq!(implicants, vec![Implicant { value: 3, mask: 4 }, Implicant { value: 4, mask: 3 }]);
+        assert!(implicants.iter().all(|p| p.covers(7)));
+        assert_eq!(minimize(&[4, 5, 6], &[7]), vec![Implicant { value: 4, mask: 3 }]);
+        assert_eq!(minimize(&[], &[1]), vec![]);
+
+        let variables: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        assert_eq!(Implicant { value: 4, mask: 1 }.to_expr(&variables).to_string(), "(a & !b)");
+
+        let expr = BooleanExpr::parse("a ^ (b & c)").unwrap();
+        let table = TruthTable::generate(&synthesize(&expr)).unwrap();
+        let circuit = synthesize_table(&table);
+        let result = TruthTable::generate(&circuit).unwrap();
+        assert_eq!(result.to_string(), table.to_string());
+    }
+
+    #[test]
+    fn import_lookup_table() {
+        let expr = BooleanExpr::parse("a ^ (b & c)").unwrap();
+        let table = TruthTable::generate(&synthesize(&expr)).unwrap();
+        let lut = lookup_table(&table).unwrap();
+        assert_eq!(lut.get_property("in_bits"), Some(3.into()));
+        for (row, outputs) in table.rows.iter().enumerate() {
+            let result = &lut.evaluate(&vec![crate::simulation::Value::from_u64(row as u64, 3)])[1];
+            assert_eq!(result.to_u64(), outputs[0].to_u64(), "row {}", row);
+        }
+
+        let mut wide = table.clone();
+        wide.inputs.extend(wide.inputs.clone().into_iter().cycle().take(8));
+        assert!(lookup_table(&wide).is_err());
+    }
+}
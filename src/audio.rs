@@ -0,0 +1,152 @@
+//! Sound produced by components such as buzzers, rendered as PCM samples so
+//! that a front-end can play it.
+//!
+//! A component records the tone it plays on each simulation tick, and the
+//! tones are only converted to samples when asked for, given the length of
+//! a tick in real time.
+
+use std::f64::consts::PI;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::component::{FieldType, ReflectType};
+
+/// The shape of each cycle of a tone.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl ReflectType for Waveform {
+    fn field_type() -> FieldType {
+        use self::Waveform::*;
+        FieldType::for_enum(&[Square, Sine, Triangle, Sawtooth])
+    }
+}
+
+impl Waveform {
+    /// The level at a point within a cycle, where `phase` is between zero
+    /// and one.
+    pub fn level(self, phase: f64) -> f64 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// The tones played by a component over time. A frequency of zero is
+/// silence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AudioTrack {
+    pub waveform: Waveform,
+    /// Between zero and one.
+    pub volume: f64,
+    // The tick at which each tone started, in increasing order of time
+    changes: Vec<(u64, f64)>,
+}
+
+impl AudioTrack {
+    pub fn new(waveform: Waveform, volume: f64) -> Self {
+        Self { waveform, volume, changes: Vec::new() }
+    }
+    pub fn changes(&self) -> &[(u64, f64)] {
+        &self.changes
+    }
+    /// Records the frequency played from the given tick onwards. Ticks must
+    /// be recorded in order.
+    pub fn record(&mut self, tick: u64, frequency: f64) {
+        if self.frequency_at(tick) == frequency {
+            return;
+        }
+        match self.changes.last_mut() {
+            Some(last) if last.0 == tick => last.1 = frequency,
+            _ => self.changes.push((tick, frequency)),
+        }
+    }
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+    pub fn frequency_at(&self, tick: u64) -> f64 {
+        match self.changes.binary_search_by_key(&tick, |&(t, _)| t) {
+            Ok(i) => self.changes[i].1,
+            Err(0) => 0.0,
+            Err(i) => self.changes[i - 1].1,
+        }
+    }
+    // The number of cycles played before the given time, so that the tone
+    // continues smoothly from one rendered window to the next
+    fn cycles_before(&self, time: f64, tick_duration: f64) -> f64 {
+        let mut result = 0.0;
+        for (i, &(tick, frequency)) in self.changes.iter().enumerate() {
+            let start = tick as f64 * tick_duration;
+            if start >= time {
+                break;
+            }
+            let end = self.changes.get(i + 1).map_or(time, |&(t, _)| (t as f64 * tick_duration).min(time));
+            result += frequency * (end - start);
+        }
+        result
+    }
+    /// Renders the ticks from `start` up to `end` as samples between minus
+    /// one and one, given the length of a tick in seconds.
+    pub fn render(&self, start: u64, end: u64, tick_duration: f64, sample_rate: u32) -> Vec<f32> {
+        let count = ((end.saturating_sub(start)) as f64 * tick_duration * sample_rate as f64).round() as usize;
+        let sample_duration = 1.0 / sample_rate as f64;
+        let start_time = start as f64 * tick_duration;
+        let mut cycles = self.cycles_before(start_time, tick_duration);
+        (0..count).map(|i| {
+            let time = start_time + i as f64 * sample_duration;
+            let frequency = self.frequency_at((time / tick_duration) as u64);
+            let level = if frequency > 0.0 { self.waveform.level(cycles.fract()) * self.volume } else { 0.0 };
+            cycles += frequency * sample_duration;
+            level as f32
+        }).collect()
+    }
+}
+
+/// Adds several tracks together, clipping the result.
+pub fn mix(tracks: &[Vec<f32>]) -> Vec<f32> {
+    let len = tracks.iter().map(|t| t.len()).max().unwrap_or(0);
+    (0..len).map(|i| {
+        let sum: f32 = tracks.iter().filter_map(|t| t.get(i)).sum();
+        sum.max(-1.0).min(1.0)
+    }).collect()
+}
+
+/// Converts samples between minus one and one to signed 16-bit PCM.
+pub fn to_pcm(samples: &[f32]) -> Vec<i16> {
+    samples.iter().map(|&s| (s.max(-1.0).min(1.0) * i16::max_value() as f32) as i16).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_tones() {
+        let mut track = AudioTrack::new(Waveform::Square, 0.5);
+        track.record(0, 1000.0);
+        track.record(1, 1000.0);
+        track.record(2, 0.0);
+        assert_eq!(track.changes(), &[(0, 1000.0), (2, 0.0)]);
+
+        // Ticks of a millisecond at 8 kHz, so each tick is a single cycle
+        let samples = track.render(0, 3, 1e-3, 8000);
+        assert_eq!(samples.len(), 24);
+        assert_eq!(&samples[..8], &[0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+        assert!(samples[16..].iter().all(|&s| s == 0.0));
+
+        // Rendering in pieces gives the same result
+        let mut pieces = track.render(0, 1, 1e-3, 8000);
+        pieces.extend(track.render(1, 3, 1e-3, 8000));
+        assert_eq!(pieces, samples);
+
+        assert_eq!(to_pcm(&mix(&[vec![0.75], vec![0.5, -0.5]])), vec![i16::max_value(), -16383]);
+    }
+}
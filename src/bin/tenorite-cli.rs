@@ -0,0 +1,243 @@
+//! Runs simulations without a UI, for grading and batch verification in CI.
+//!
+//! Loads a project in any of the supported formats, optionally checks its
+//! circuit against a table of test vectors, then simulates it for a number
+//! of ticks or until a breakpoint condition becomes true. The final values
+//! of the traced signals are printed, and the recorded waveform can be
+//! exported as VCD or CSV.
+//!
+//! The exit status is 0 on success, 1 if a test vector failed, the
+//! condition was never met or the simulation failed, and 2 if the project
+//! could not be loaded or the arguments were invalid.
+//!
+//! Build with `cargo build --features cli --bin tenorite-cli`.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use tenorite_ui::check::Severity;
+use tenorite_ui::circuit::Circuit;
+use tenorite_ui::libraries::default_library;
+use tenorite_ui::net::PinRef;
+use tenorite_ui::project::loader::ProjectLoader;
+use tenorite_ui::simulation::{SimulationError, Simulator};
+use tenorite_ui::testing::TestVectors;
+use tenorite_ui::trace;
+
+const USAGE: &str = "\
+Usage: tenorite-cli <project> [options]
+
+Options:
+    --circuit <name>       Simulate the named circuit instead of the main one
+    --ticks <n>            Run for n ticks, or at most n ticks with --until
+    --until <condition>    Run until the breakpoint condition becomes true
+    --test <file>          Check the circuit against a table of test vectors
+    --trace <signal>       Record a signal, named by its path (repeatable).
+                           Defaults to every input and output pin
+    --vcd <file>           Write the recorded waveform as a VCD file
+    --csv <file>           Write the recorded waveform as CSV
+    --timescale <unit>     The VCD timescale of one step [default: 1ns]
+    -q, --quiet            Only print failures
+    -h, --help             Print this message
+
+Files may be given as `-` for standard output.";
+
+const EXIT_FAILED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+// The most ticks to run while waiting for a condition, if no limit is given
+const DEFAULT_TICK_LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, Default)]
+struct Options {
+    path: String,
+    circuit: Option<String>,
+    ticks: Option<u64>,
+    until: Option<String>,
+    test: Option<String>,
+    traces: Vec<String>,
+    vcd: Option<String>,
+    csv: Option<String>,
+    timescale: Option<String>,
+    quiet: bool,
+}
+
+impl Options {
+    // Whether to simulate beyond the test vectors
+    fn simulate(&self) -> bool {
+        self.test.is_none()
+            || self.ticks.is_some()
+            || self.until.is_some()
+            || !self.traces.is_empty()
+            || self.vcd.is_some()
+            || self.csv.is_some()
+    }
+}
+
+// Returns `None` if help was requested.
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("Missing value for `{}`", name));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-q" | "--quiet" => options.quiet = true,
+            "--circuit" => options.circuit = Some(value(&arg)?),
+            "--ticks" => {
+                let ticks = value(&arg)?;
+                options.ticks = Some(ticks.parse().map_err(|_| format!("Invalid number of ticks `{}`", ticks))?);
+            },
+            "--until" => options.until = Some(value(&arg)?),
+            "--test" => options.test = Some(value(&arg)?),
+            "--trace" => options.traces.push(value(&arg)?),
+            "--vcd" => options.vcd = Some(value(&arg)?),
+            "--csv" => options.csv = Some(value(&arg)?),
+            "--timescale" => options.timescale = Some(value(&arg)?),
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("Unknown option `{}`", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unexpected argument `{}`", arg)),
+        }
+    }
+    options.path = path.ok_or("No project given")?;
+    Ok(Some(options))
+}
+
+fn write_output(path: &str, contents: &str) -> Result<(), String> {
+    let result = if path == "-" {
+        io::stdout().write_all(contents.as_bytes())
+    } else {
+        fs::write(path, contents)
+    };
+    result.map_err(|e| format!("Could not write `{}`: {}", path, e))
+}
+
+// Checks the circuit against the test vectors, returning whether they all
+// passed.
+fn run_tests(circuit: &Circuit, path: &str, quiet: bool) -> Result<bool, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read `{}`: {}", path, e))?;
+    let vectors = TestVectors::parse(&text, circuit).map_err(|e| format!("{}: {}", path, e))?;
+    let report = vectors.run(circuit).map_err(|e| format!("{}: {}", path, e))?;
+    if !quiet || !report.passed() {
+        print!("{}", report);
+    }
+    Ok(report.passed())
+}
+
+// Simulates the circuit, returning whether it ran to completion.
+fn run_simulation(circuit: &Circuit, options: &Options) -> Result<bool, String> {
+    let mut sim = Simulator::new(circuit.clone());
+    if options.traces.is_empty() {
+        let pins: Vec<_> = circuit.components()
+            .filter(|(_, info)| ["input_pin", "output_pin"].contains(&&*info.metadata().id))
+            .map(|(id, _)| PinRef::new(id, 0))
+            .collect();
+        for pin in pins {
+            sim.trace(pin);
+        }
+    }
+    for path in &options.traces {
+        let probe = trace::find_probe(circuit, path).ok_or_else(|| format!("Unknown signal `{}`", path))?;
+        sim.trace_probe(probe);
+    }
+    let breakpoint = match options.until {
+        Some(ref condition) => Some(sim.add_breakpoint(condition)
+            .map_err(|e| format!("Invalid condition `{}`: {}", condition, e))?),
+        None => None,
+    };
+    let limit = options.ticks.unwrap_or(if breakpoint.is_some() { DEFAULT_TICK_LIMIT } else { 0 });
+
+    let mut result = sim.run_until_stable();
+    while result.is_ok() && sim.ticks() < limit {
+        result = sim.tick();
+    }
+    let completed = match result {
+        Ok(()) if breakpoint.is_some() => {
+            println!("Condition was not met within {} ticks", limit);
+            false
+        },
+        Ok(()) => true,
+        Err(SimulationError::Breakpoint { id }) if Some(id) == breakpoint => {
+            if !options.quiet {
+                println!("Condition was met after {} ticks", sim.ticks());
+            }
+            true
+        },
+        Err(e) => {
+            println!("Simulation failed after {} ticks: {}", sim.ticks(), e);
+            false
+        },
+    };
+
+    if !options.quiet {
+        for signal in sim.waveform().signals() {
+            match sim.probe_value(&signal.probe) {
+                Some(value) => match value.to_u64() {
+                    Some(n) => println!("{} = {} ({})", signal.name, value, n),
+                    None => println!("{} = {}", signal.name, value),
+                },
+                None => println!("{} = ?", signal.name),
+            }
+        }
+    }
+    if let Some(ref path) = options.vcd {
+        write_output(path, &sim.waveform().to_vcd(options.timescale.as_deref().unwrap_or("1ns")))?;
+    }
+    if let Some(ref path) = options.csv {
+        write_output(path, &sim.waveform().to_csv())?;
+    }
+    Ok(completed)
+}
+
+fn run(options: &Options) -> Result<bool, String> {
+    let bytes = fs::read(&options.path).map_err(|e| format!("Could not read `{}`: {}", options.path, e))?;
+    let library = default_library();
+    let report = ProjectLoader::new(&library).load_bytes(&bytes)
+        .map_err(|e| format!("Could not load `{}`: {}", options.path, e))?;
+    for diagnostic in &report.diagnostics {
+        match diagnostic.severity() {
+            Severity::Error => eprintln!("error: {}", diagnostic),
+            Severity::Warning => eprintln!("warning: {}", diagnostic),
+            Severity::Info if !options.quiet => eprintln!("note: {}", diagnostic),
+            Severity::Info => {},
+        }
+    }
+
+    let project = &report.project;
+    let circuit = match options.circuit {
+        Some(ref name) => project.circuit(name).ok_or_else(|| format!("The project has no circuit named `{}`", name))?,
+        None => project.main_circuit().ok_or("The project has no main circuit, so one must be chosen with `--circuit`")?,
+    };
+    let mut passed = true;
+    if let Some(ref path) = options.test {
+        passed &= run_tests(circuit, path, options.quiet)?;
+    }
+    if options.simulate() {
+        passed &= run_simulation(circuit, options)?;
+    }
+    Ok(passed)
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(EXIT_ERROR);
+        },
+    };
+    match run(&options) {
+        Ok(true) => {},
+        Ok(false) => process::exit(EXIT_FAILED),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(EXIT_ERROR);
+        },
+    }
+}
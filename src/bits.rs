@@ -0,0 +1,340 @@
+//! Fixed width bit strings, for properties wider than an integer field, and
+//! the word contents of memories.
+
+use std::fmt;
+
+use serde_json;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::simulation::{Bit, Value};
+
+/// The widest address of a RAM or ROM.
+pub const MAX_ADDRESS_BITS: u32 = 24;
+
+/// A fixed width string of defined bits. Bit 0 is the least significant bit.
+///
+/// Bit strings can be parsed from and formatted as hexadecimal (`0x1f`),
+/// binary (`0b11111`) or decimal (`31`, `-1`) text. They display as
+/// hexadecimal, which `parse` accepts, so the displayed form can be used as a
+/// property value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BitString {
+    bits: Vec<bool>,
+}
+
+// Arbitrary precision arithmetic on little-endian 32-bit limbs, used for
+// decimal conversions.
+fn mul_add(limbs: &mut Vec<u32>, mul: u32, add: u32) {
+    let mut carry = add as u64;
+    for limb in limbs.iter_mut() {
+        let v = *limb as u64 * mul as u64 + carry;
+        *limb = v as u32;
+        carry = v >> 32;
+    }
+    if carry != 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+fn div_rem(limbs: &mut Vec<u32>, div: u32) -> u32 {
+    let mut rem = 0u64;
+    for limb in limbs.iter_mut().rev() {
+        let v = (rem << 32) | *limb as u64;
+        *limb = (v / div as u64) as u32;
+        rem = v % div as u64;
+    }
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    rem as u32
+}
+
+impl BitString {
+    pub fn zero(width: u32) -> Self {
+        BitString { bits: vec![false; width as usize] }
+    }
+    pub fn from_bits(bits: Vec<bool>) -> Self {
+        BitString { bits }
+    }
+    pub fn from_u64(v: u64, width: u32) -> Self {
+        BitString { bits: (0..width).map(|i| i < 64 && (v >> i) & 1 != 0).collect() }
+    }
+    /// Like `from_u64`, but bits beyond the 64th are copied from the sign bit.
+    pub fn from_i64(v: i64, width: u32) -> Self {
+        BitString { bits: (0..width).map(|i| (v >> i.min(63)) & 1 != 0).collect() }
+    }
+    /// Converts a simulation value, returning `None` if any bit is undefined.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let bits = value.bits().iter().map(|b| b.to_bool()).collect::<Option<_>>()?;
+        Some(BitString { bits })
+    }
+    pub fn to_value(&self) -> Value {
+        Value::new(self.bits.iter().map(|&b| Bit::from_bool(b)).collect())
+    }
+    pub fn width(&self) -> u32 {
+        self.bits.len() as u32
+    }
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+    pub fn bit(&self, index: u32) -> bool {
+        self.bits.get(index as usize).cloned().unwrap_or(false)
+    }
+    /// Returns the value, or `None` if it does not fit in 64 bits.
+    pub fn to_u64(&self) -> Option<u64> {
+        let mut result = 0;
+        for (i, &bit) in self.bits.iter().enumerate() {
+            if bit {
+                if i >= 64 {
+                    return None;
+                }
+                result |= 1 << i;
+            }
+        }
+        Some(result)
+    }
+    /// Truncates or zero extends the bit string to a new width.
+    pub fn resized(&self, width: u32) -> Self {
+        let mut bits = self.bits.clone();
+        bits.resize(width as usize, false);
+        BitString { bits }
+    }
+
+    /// Parses a value of the given width. Hexadecimal values start with `0x`
+    /// and binary values with `0b`. Values may be negated with a leading
+    /// `-`, in which case they are stored in two's complement. Underscores
+    /// may be used to separate digits. Returns an explanation if the text is
+    /// not a number, or the number does not fit in the width.
+    pub fn parse(text: &str, width: u32) -> Result<Self, String> {
+        let text: String = text.trim().chars().filter(|&c| c != '_').collect();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.as_str()),
+        };
+        let lower = text.to_ascii_lowercase();
+        let (radix, digits) = if let Some(digits) = lower.strip_prefix("0x") {
+            (16, digits)
+        } else if let Some(digits) = lower.strip_prefix("0b") {
+            (2, digits)
+        } else {
+            (10, lower.as_str())
+        };
+        if digits.is_empty() {
+            return Err("Expected a number".into());
+        }
+
+        let mut limbs = Vec::new();
+        for c in digits.chars() {
+            let digit = c.to_digit(radix).ok_or_else(|| format!("`{}` is not a valid digit", c))?;
+            mul_add(&mut limbs, radix, digit);
+        }
+        let mut magnitude: Vec<bool> = limbs.iter()
+            .flat_map(|&limb| (0..32).map(move |i| (limb >> i) & 1 != 0))
+            .collect();
+        while magnitude.last() == Some(&false) {
+            magnitude.pop();
+        }
+
+        let len = magnitude.len() as u32;
+        if negative {
+            // The most negative value has only its top bit set
+            let is_power_of_two = magnitude.iter().filter(|&&b| b).count() == 1;
+            if len > width || (len == width && !is_power_of_two) {
+                return Err(format!("Must fit in {} bits as a signed value", width));
+            }
+            Ok(BitString::from_bits(magnitude).resized(width).negated())
+        } else {
+            if len > width {
+                return Err(format!("Must fit in {} bits", width));
+            }
+            Ok(BitString::from_bits(magnitude).resized(width))
+        }
+    }
+    // The two's complement negation of the value
+    fn negated(&self) -> Self {
+        let mut carry = true;
+        let bits = self.bits.iter().map(|&b| {
+            let sum = !b ^ carry;
+            carry &= !b;
+            sum
+        }).collect();
+        BitString { bits }
+    }
+
+    /// Formats the value as binary digits, most significant first, with one
+    /// digit for each bit.
+    pub fn to_binary(&self) -> String {
+        self.bits.iter().rev().map(|&b| if b { '1' } else { '0' }).collect()
+    }
+    /// Formats the value as hexadecimal digits, most significant first, with
+    /// enough digits for the full width.
+    pub fn to_hex(&self) -> String {
+        self.bits.chunks(4).rev().map(|chunk| {
+            let nibble = chunk.iter().rev().fold(0, |acc, &b| acc << 1 | b as u32);
+            std::char::from_digit(nibble, 16).expect("Nibbles are valid digits")
+        }).collect()
+    }
+    /// Formats the value as an unsigned decimal number.
+    pub fn to_decimal(&self) -> String {
+        let mut limbs: Vec<u32> = self.bits.chunks(32)
+            .map(|chunk| chunk.iter().rev().fold(0, |acc, &b| acc << 1 | b as u32))
+            .collect();
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let mut digits = Vec::new();
+        while !limbs.is_empty() {
+            digits.push(std::char::from_digit(div_rem(&mut limbs, 10), 10).expect("Remainder is a digit"));
+        }
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+impl fmt::Display for BitString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", self.to_hex())
+    }
+}
+
+pub(crate) fn max_word(data_bits: u32) -> u64 {
+    if data_bits >= 64 { u64::max_value() } else { (1 << data_bits) - 1 }
+}
+
+/// The contents of a RAM or ROM, as used by its `contents` property. Words
+/// beyond the end of the contents read as zero.
+///
+/// The property holds the text form, which is whitespace separated
+/// hexadecimal words where a run of identical words may be written as
+/// `count*word`. Contents convert to a property value with `into`, and can
+/// be read back with `from_value`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(transparent)]
+pub struct MemoryContents {
+    words: Vec<u64>,
+}
+
+impl MemoryContents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn from_words(words: Vec<u64>) -> Self {
+        MemoryContents { words }
+    }
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+    pub fn read(&self, address: usize) -> u64 {
+        self.words.get(address).cloned().unwrap_or(0)
+    }
+    pub fn write(&mut self, address: usize, word: u64) {
+        if address >= self.words.len() {
+            self.words.resize(address + 1, 0);
+        }
+        self.words[address] = word;
+    }
+    /// Parses the text form of the contents.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut words = Vec::new();
+        for token in text.split_whitespace() {
+            let (count, word) = match token.find('*') {
+                Some(index) => {
+                    let count = token[..index].parse::<usize>()
+                        .map_err(|_| format!("Invalid repeat count in `{}`", token))?;
+                    (count, &token[index + 1..])
+                },
+                None => (1, token),
+            };
+            let word = u64::from_str_radix(word, 16)
+                .map_err(|_| format!("Invalid hexadecimal word `{}`", word))?;
+            // Checked before allocating, so that `99999999*0` fails cleanly
+            if words.len() + count > 1 << MAX_ADDRESS_BITS {
+                return Err("Contents are too long".into());
+            }
+            words.extend((0..count).map(|_| word));
+        }
+        Ok(MemoryContents { words })
+    }
+    /// Reads contents from a property value.
+    pub fn from_value(value: &serde_json::Value) -> Result<Self, String> {
+        Self::parse(value.as_str().ok_or("Expected a string")?)
+    }
+    /// Checks that the contents fit in a memory of the given size.
+    pub fn check(&self, address_bits: u32, data_bits: u32) -> Result<(), String> {
+        let max_len = 1usize << address_bits;
+        if self.words.len() > max_len {
+            return Err(format!("Contents exceed {} words", max_len));
+        }
+        match self.words.iter().find(|&&w| w > max_word(data_bits)) {
+            Some(word) => Err(format!("Word `{:x}` does not fit in {} bits", word, data_bits)),
+            None => Ok(()),
+        }
+    }
+    // Discards whatever does not fit in a memory of the given size
+    pub(crate) fn fit(&mut self, address_bits: u32, data_bits: u32) {
+        self.words.truncate(1 << address_bits);
+        for word in &mut self.words {
+            *word &= max_word(data_bits);
+        }
+    }
+}
+
+impl fmt::Display for MemoryContents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = &self.words;
+        let len = words.iter().rposition(|&w| w != 0).map(|i| i + 1).unwrap_or(0);
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < len {
+            let run = words[i..len].iter().take_while(|&&w| w == words[i]).count();
+            if run >= 4 {
+                result.push(format!("{}*{:x}", run, words[i]));
+            } else {
+                result.extend(words[i..i + run].iter().map(|w| format!("{:x}", w)));
+            }
+            i += run;
+        }
+        f.write_str(&result.join(" "))
+    }
+}
+
+impl From<MemoryContents> for serde_json::Value {
+    fn from(contents: MemoryContents) -> Self {
+        serde_json::Value::String(contents.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let v = BitString::parse("0x1F", 8).unwrap();
+        assert_eq!(v.to_u64(), Some(31));
+        assert_eq!((v.to_hex(), v.to_binary(), v.to_decimal()), ("1f".into(), "00011111".into(), "31".into()));
+        assert_eq!(BitString::parse("0b1_0000", 5).unwrap(), BitString::from_u64(16, 5));
+        assert_eq!(BitString::parse("-1", 4).unwrap().to_binary(), "1111");
+        assert_eq!(BitString::parse("-8", 4).unwrap().to_binary(), "1000");
+        assert!(BitString::parse("-9", 4).is_err());
+        assert!(BitString::parse("16", 4).is_err());
+        assert!(BitString::parse("0x", 4).is_err());
+        assert!(BitString::parse("12a", 8).is_err());
+
+        // Values wider than 64 bits
+        let wide = BitString::parse("340282366920938463463374607431768211455", 128).unwrap();
+        assert_eq!(wide.to_hex(), "f".repeat(32));
+        assert_eq!(wide.to_u64(), None);
+        assert_eq!(BitString::parse(&wide.to_string(), 128).unwrap().to_decimal(), "340282366920938463463374607431768211455");
+        assert_eq!(BitString::from_i64(-2, 70).to_value().width(), 70);
+        assert_eq!(BitString::zero(3).to_decimal(), "0");
+    }
+}
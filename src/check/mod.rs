@@ -0,0 +1,130 @@
+//! Static checks of a circuit which can be made without simulating it.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::circuit::Circuit;
+use crate::net::{Connectivity, DisjointSet, NetId, PinRef};
+
+mod rules;
+
+pub use crate::net::WidthConflict;
+pub use self::rules::{
+    Severity, Location, Diagnostic, RuleContext, Rule, DesignRuleChecker,
+    WidthConflicts, InvalidWidths, FloatingInputs, MultipleDrivers, UnconnectedOutputs, OverlappingComponents,
+};
+
+/// The result of inferring the width of every net in a circuit.
+#[derive(Debug, Clone)]
+pub struct WidthInference {
+    connectivity: Connectivity,
+    widths: HashMap<NetId, u32>,
+    conflicts: Vec<WidthConflict>,
+}
+
+impl WidthInference {
+    /// Infers the width of each net from the pins attached to it. Nets
+    /// joined by tunnels with the same label must share a width, so they are
+    /// inferred together.
+    pub fn run(circuit: &Circuit) -> Self {
+        let connectivity = Connectivity::build(circuit);
+        let mut set = DisjointSet::default();
+        for _ in connectivity.nets() {
+            set.add();
+        }
+        let mut tunnels: HashMap<String, NetId> = HashMap::new();
+        for (id, info) in circuit.components() {
+            match info.tunnel_label() {
+                Some(ref label) if !label.is_empty() => {
+                    for pin in 0..info.get_shape().pins.len() {
+                        if let Some(net) = connectivity.net_of_pin(PinRef::new(id, pin)) {
+                            let first = *tunnels.entry(label.clone()).or_insert(net);
+                            set.union(first.0, net.0);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // Collect the pins of each group, identified by its lowest net
+        let mut groups: BTreeMap<usize, (NetId, Vec<NetId>, Vec<(PinRef, u32)>)> = BTreeMap::new();
+        for net in connectivity.nets() {
+            let root = set.find(net.id.0);
+            let group = groups.entry(root).or_insert_with(|| (net.id, Vec::new(), Vec::new()));
+            group.1.push(net.id);
+            group.2.extend(net.pins.iter().cloned());
+        }
+        let mut widths = HashMap::new();
+        let mut conflicts = Vec::new();
+        for (_, (first, nets, pins)) in groups {
+            let width = pins.first().map(|&(_, bits)| bits);
+            match WidthConflict::find(first, pins) {
+                Some(conflict) => conflicts.push(conflict),
+                None => if let Some(width) = width {
+                    widths.extend(nets.into_iter().map(|net| (net, width)));
+                },
+            }
+        }
+        conflicts.sort_by_key(|c| c.net);
+        WidthInference { connectivity, widths, conflicts }
+    }
+    pub fn connectivity(&self) -> &Connectivity {
+        &self.connectivity
+    }
+    /// The width of a net, or `None` if it has no pins or its pins disagree.
+    pub fn width(&self, net: NetId) -> Option<u32> {
+        self.widths.get(&net).cloned()
+    }
+    pub fn conflicts(&self) -> &[WidthConflict] {
+        &self.conflicts
+    }
+}
+
+/// Finds the nets, including those joined by tunnels, which connect pins
+/// of different widths.
+pub fn width_conflicts(circuit: &Circuit) -> Vec<WidthConflict> {
+    WidthInference::run(circuit).conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::libraries::default_library;
+
+    #[test]
+    fn infer_widths() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let mut create = |id: &str, bits: u32, label: Option<&str>| {
+            let mut info = library.create(id).unwrap();
+            info.set_property("num_bits", bits.into()).unwrap();
+            if let Some(label) = label {
+                info.set_property("label", label.into()).unwrap();
+            }
+            info
+        };
+        let input = circuit.add_component(create("input_pin", 4, None), 0, 0);
+        circuit.add_component(create("tunnel", 4, Some("a")), 2, 0);
+        circuit.add_component(create("tunnel", 4, Some("a")), 20, 0);
+        let output = circuit.add_component(create("output_pin", 4, None), 23, 0);
+        circuit.add_wire(Wire::new((20, 1), (23, 1)));
+
+        let inference = WidthInference::run(&circuit);
+        assert!(inference.conflicts().is_empty());
+        let net = inference.connectivity().net_of_pin(PinRef::new(output, 0)).unwrap();
+        assert_eq!(inference.width(net), Some(4));
+
+        // An 8 bit output on the far side of the tunnel conflicts with the
+        // 4 bit input, even though their nets are not directly connected.
+        circuit.set_property(output, "num_bits", 8.into()).unwrap().unwrap();
+        let conflicts = width_conflicts(&circuit);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].widths, vec![4, 8]);
+        assert!(conflicts[0].pins.contains(&(PinRef::new(input, 0), 4)));
+        assert!(conflicts[0].pins.contains(&(PinRef::new(output, 0), 8)));
+        let inference = WidthInference::run(&circuit);
+        let net = inference.connectivity().net_of_pin(PinRef::new(output, 0)).unwrap();
+        assert_eq!(inference.width(net), None);
+    }
+}
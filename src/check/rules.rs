@@ -0,0 +1,356 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::circuit::{Circuit, ComponentId, WireId};
+use crate::component::{ComponentInfo, PinDirection};
+use crate::net::{Connectivity, NetId, PinRef};
+use crate::simulation::Netlist;
+use super::WidthInference;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Part of a circuit which a diagnostic refers to.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Location {
+    Component(ComponentId),
+    Pin(PinRef),
+    Wire(WireId),
+    Net(NetId),
+    Point((i32, i32)),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The name of the rule which produced the diagnostic.
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+    pub locations: Vec<Location>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} [{}]: {}", self.severity, self.rule, self.message)
+    }
+}
+
+/// The information about a circuit available to each rule.
+pub struct RuleContext<'a> {
+    circuit: &'a Circuit,
+    inference: WidthInference,
+}
+
+impl<'a> RuleContext<'a> {
+    pub fn circuit(&self) -> &'a Circuit {
+        self.circuit
+    }
+    pub fn connectivity(&self) -> &Connectivity {
+        self.inference.connectivity()
+    }
+    pub fn widths(&self) -> &WidthInference {
+        &self.inference
+    }
+}
+
+/// A check which can be run over a circuit.
+pub trait Rule: Send + Sync {
+    /// A short identifier for the rule, such as `floating_input`.
+    fn name(&self) -> &str;
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>);
+}
+
+/// Runs a set of rules over a circuit.
+pub struct DesignRuleChecker {
+    rules: Vec<Box<Rule>>,
+}
+
+impl Default for DesignRuleChecker {
+    fn default() -> Self {
+        let mut checker = Self::new();
+        checker.add_rule(WidthConflicts);
+        checker.add_rule(InvalidWidths);
+        checker.add_rule(FloatingInputs);
+        checker.add_rule(MultipleDrivers);
+        checker.add_rule(UnconnectedOutputs);
+        checker.add_rule(OverlappingComponents);
+        checker
+    }
+}
+
+impl DesignRuleChecker {
+    /// Creates a checker with no rules. Use `default` for the built in rules.
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+    pub fn add_rule<R: Rule + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+    /// Removes the rule with the given name, returning true if it existed.
+    pub fn remove_rule(&mut self, name: &str) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|rule| rule.name() != name);
+        self.rules.len() != len
+    }
+    pub fn rule_names(&self) -> impl Iterator<Item=&str> {
+        self.rules.iter().map(|rule| rule.name())
+    }
+    /// Runs every rule, returning the diagnostics with the most severe first.
+    pub fn check(&self, circuit: &Circuit) -> Vec<Diagnostic> {
+        let ctx = RuleContext { circuit, inference: WidthInference::run(circuit) };
+        let mut diagnostics = Vec::new();
+        for rule in &self.rules {
+            rule.check(&ctx, &mut diagnostics);
+        }
+        diagnostics.sort_by(|a, b| b.severity.cmp(&a.severity));
+        diagnostics
+    }
+}
+
+fn diagnostic(rule: &Rule, severity: Severity, message: String, locations: Vec<Location>) -> Diagnostic {
+    Diagnostic { rule: rule.name().into(), severity, message, locations }
+}
+
+// Components which only join nets together, rather than driving them
+fn is_structural(info: &ComponentInfo) -> bool {
+    !info.connections().is_empty() || info.tunnel_label().is_some() || info.pull().is_some()
+        || info.resolution().is_some()
+}
+
+/// Nets connecting pins of different widths.
+pub struct WidthConflicts;
+
+impl Rule for WidthConflicts {
+    fn name(&self) -> &str {
+        "width_conflict"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        for conflict in ctx.widths().conflicts() {
+            let mut locations = vec![Location::Net(conflict.net)];
+            locations.extend(conflict.pins.iter().map(|&(pin, _)| Location::Pin(pin)));
+            diagnostics.push(diagnostic(self, Severity::Error, conflict.to_string(), locations));
+        }
+    }
+}
+
+/// Components whose pin widths are inconsistent with each other, as reported
+/// by `Component::width_errors`.
+pub struct InvalidWidths;
+
+impl Rule for InvalidWidths {
+    fn name(&self) -> &str {
+        "invalid_width"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (id, info) in ctx.circuit().components() {
+            for error in info.width_errors() {
+                diagnostics.push(diagnostic(self, Severity::Error,
+                    format!("Component {}: {}", id, error),
+                    vec![Location::Component(id)]));
+            }
+        }
+    }
+}
+
+/// Input pins with a bit which nothing drives or pulls.
+pub struct FloatingInputs;
+
+impl Rule for FloatingInputs {
+    fn name(&self) -> &str {
+        "floating_input"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        let circuit = ctx.circuit();
+        let netlist = Netlist::build(circuit);
+        let structural: Vec<_> = circuit.components().map(|(_, info)| is_structural(info)).collect();
+        let driven = |net: usize| {
+            netlist.pulls[net].is_some() || netlist.drivers[net].iter().any(|&(c, _, _)| !structural[c])
+        };
+        for (&(id, ref pins), (_, info)) in netlist.components.iter().zip(circuit.components()) {
+            let shape = info.get_shape();
+            for (index, pin) in pins.iter().enumerate() {
+                if pin.direction == PinDirection::Input && !pin.nets.iter().all(|&net| driven(net)) {
+                    diagnostics.push(diagnostic(self, Severity::Warning,
+                        format!("Input `{}` of component {} is not driven", shape.pins[index].name, id),
+                        vec![Location::Pin(PinRef::new(id, index))]));
+                }
+            }
+        }
+    }
+}
+
+/// Nets with more than one component driving them, unless every driver is a
+/// tri-state output or the net resolves conflicts with wired logic.
+pub struct MultipleDrivers;
+
+impl Rule for MultipleDrivers {
+    fn name(&self) -> &str {
+        "multiple_drivers"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        let circuit = ctx.circuit();
+        let netlist = Netlist::build(circuit);
+        let structural: Vec<_> = circuit.components().map(|(_, info)| is_structural(info)).collect();
+        // Report each distinct set of conflicting pins once, rather than
+        // once per bit.
+        let mut seen = BTreeSet::new();
+        for (drivers, resolution) in netlist.drivers.iter().zip(&netlist.resolutions) {
+            if resolution.is_some() {
+                continue;
+            }
+            let drivers: Vec<_> = drivers.iter().filter(|&&(c, _, _)| !structural[c]).collect();
+            let tri_state = drivers.iter()
+                .all(|&&(c, p, _)| netlist.components[c].1[p].direction == PinDirection::TriState);
+            let pins: BTreeSet<_> = drivers.iter()
+                .map(|&&(c, p, _)| PinRef::new(netlist.components[c].0, p))
+                .collect();
+            if pins.len() > 1 && !tri_state && seen.insert(pins.clone()) {
+                let names: Vec<_> = pins.iter().map(|pin| format!("{}.{}", pin.component, pin.pin)).collect();
+                diagnostics.push(diagnostic(self, Severity::Warning,
+                    format!("A net is driven by several outputs: {}", names.join(", ")),
+                    pins.into_iter().map(Location::Pin).collect()));
+            }
+        }
+    }
+}
+
+/// Output pins which are not connected to anything.
+pub struct UnconnectedOutputs;
+
+impl Rule for UnconnectedOutputs {
+    fn name(&self) -> &str {
+        "unconnected_output"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        let connectivity = ctx.connectivity();
+        for (id, info) in ctx.circuit().components() {
+            for (index, pin) in info.get_shape().pins.iter().enumerate() {
+                if pin.direction != PinDirection::Output {
+                    continue;
+                }
+                let pin_ref = PinRef::new(id, index);
+                let isolated = connectivity.net_of_pin(pin_ref)
+                    .and_then(|net| connectivity.net(net))
+                    .map_or(true, |net| net.pins.len() == 1 && net.wires.is_empty());
+                if isolated {
+                    diagnostics.push(diagnostic(self, Severity::Info,
+                        format!("Output `{}` of component {} is not connected", pin.name, id),
+                        vec![Location::Pin(pin_ref)]));
+                }
+            }
+        }
+    }
+}
+
+/// Components which overlap each other. Components may touch, such as to
+/// connect pins directly.
+pub struct OverlappingComponents;
+
+impl Rule for OverlappingComponents {
+    fn name(&self) -> &str {
+        "overlapping_components"
+    }
+    fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+        // Annotations such as boxes are often drawn around other components
+        let boxes: Vec<_> = ctx.circuit().components()
+            .filter(|(_, info)| !info.is_annotation())
+            .map(|(id, info)| (id, info.bounding_box()))
+            .collect();
+        for (i, &(a, ra)) in boxes.iter().enumerate() {
+            for &(b, rb) in &boxes[i + 1..] {
+                let overlaps = ra.x < rb.x + rb.width && rb.x < ra.x + ra.width
+                    && ra.y < rb.y + rb.height && rb.y < ra.y + ra.height;
+                if overlaps {
+                    diagnostics.push(diagnostic(self, Severity::Warning,
+                        format!("Components {} and {} overlap", a, b),
+                        vec![Location::Component(a), Location::Component(b)]));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::libraries::default_library;
+
+    struct NoGates;
+
+    impl Rule for NoGates {
+        fn name(&self) -> &str {
+            "no_gates"
+        }
+        fn check(&self, ctx: &RuleContext, diagnostics: &mut Vec<Diagnostic>) {
+            for (id, info) in ctx.circuit().components() {
+                if info.metadata().id.ends_with("_gate") {
+                    diagnostics.push(diagnostic(self, Severity::Error, "Gates are not allowed".into(),
+                        vec![Location::Component(id)]));
+                }
+            }
+        }
+    }
+
+    fn find<'a>(diagnostics: &'a [Diagnostic], rule: &str) -> Vec<&'a Diagnostic> {
+        diagnostics.iter().filter(|d| d.rule == rule).collect()
+    }
+
+    #[test]
+    fn design_rules() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let gate = circuit.add_component(library.create("and_gate").unwrap(), 0, 0);
+        let (x, y) = circuit.get_component(gate).unwrap().pins()[0].world;
+        let a = circuit.add_component(library.create("input_pin").unwrap(), x - 2, y - 1);
+        let b = circuit.add_component(library.create("input_pin").unwrap(), x - 6, y - 1);
+        circuit.add_wire(Wire::new((x - 4, y), (x, y)));
+
+        let checker = DesignRuleChecker::default();
+        let diagnostics = checker.check(&circuit);
+        // The second input of the gate is floating, and its output unused
+        let floating = find(&diagnostics, "floating_input");
+        assert_eq!(floating.len(), 1);
+        assert_eq!(floating[0].locations, vec![Location::Pin(PinRef::new(gate, 1))]);
+        assert_eq!(find(&diagnostics, "unconnected_output").len(), 1);
+        let drivers = find(&diagnostics, "multiple_drivers");
+        assert_eq!(drivers.len(), 1);
+        assert!(drivers[0].locations.contains(&Location::Pin(PinRef::new(a, 0))));
+        assert!(drivers[0].locations.contains(&Location::Pin(PinRef::new(b, 0))));
+        assert!(find(&diagnostics, "overlapping_components").is_empty());
+        assert!(find(&diagnostics, "invalid_width").is_empty());
+
+        let other = circuit.add_component(library.create("or_gate").unwrap(), 1, 1);
+        let mut checker = DesignRuleChecker::default();
+        checker.add_rule(NoGates);
+        assert!(checker.remove_rule("floating_input"));
+        let diagnostics = checker.check(&circuit);
+        assert!(find(&diagnostics, "floating_input").is_empty());
+        let overlaps = find(&diagnostics, "overlapping_components");
+        assert_eq!(overlaps[0].locations, vec![Location::Component(gate), Location::Component(other)]);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(find(&diagnostics, "no_gates").len(), 2);
+    }
+
+    #[test]
+    fn invalid_widths() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let mut extender = library.create("bit_extender").unwrap();
+        extender.set_property("in_bits", 16.into()).unwrap();
+        let id = circuit.add_component(extender, 0, 0);
+        let diagnostics = DesignRuleChecker::default().check(&circuit);
+        let invalid = find(&diagnostics, "invalid_width");
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].locations, vec![Location::Component(id)]);
+
+        circuit.set_property(id, "out_bits", 16.into()).unwrap().unwrap();
+        assert!(find(&DesignRuleChecker::default().check(&circuit), "invalid_width").is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+use uuid::Uuid;
+
+use crate::component::{Component, ComponentInfo, PropertyChanged, PropertyError};
+use crate::symbol::SymbolAppearance;
+use crate::parameters::Parameters;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComponentId(pub u64);
+
+impl fmt::Display for ComponentId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "c{}", self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WireId(pub u64);
+
+impl fmt::Display for WireId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "w{}", self.0)
+    }
+}
+
+/// A straight wire segment between two grid points. Wires connect to each
+/// other and to component pins at any point along the segment where a pin or
+/// another wire's endpoint lies.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Wire {
+    pub start: (i32, i32),
+    pub end: (i32, i32),
+}
+
+impl Wire {
+    pub fn new(start: (i32, i32), end: (i32, i32)) -> Self {
+        Self { start, end }
+    }
+    pub fn is_horizontal(&self) -> bool {
+        self.start.1 == self.end.1
+    }
+    pub fn is_vertical(&self) -> bool {
+        self.start.0 == self.end.0
+    }
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        let (x0, y0) = self.start;
+        let (x1, y1) = self.end;
+        let cross = (x1 - x0) as i64 * (y - y0) as i64 - (y1 - y0) as i64 * (x - x0) as i64;
+        cross == 0
+            && x >= x0.min(x1) && x <= x0.max(x1)
+            && y >= y0.min(y1) && y <= y0.max(y1)
+    }
+}
+
+/// A change made to a circuit through its methods.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CircuitEvent {
+    ComponentAdded(ComponentId),
+    ComponentRemoved(ComponentId),
+    ComponentMoved(ComponentId),
+    PropertyChanged(ComponentId, PropertyChanged),
+    WireAdded(WireId),
+    WireRemoved(WireId),
+}
+
+/// Receives the events raised by a circuit.
+pub trait EventSink: Send + Sync {
+    fn handle(&mut self, event: &CircuitEvent);
+}
+
+impl<F: FnMut(&CircuitEvent) + Send + Sync> EventSink for F {
+    fn handle(&mut self, event: &CircuitEvent) {
+        self(event)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(pub u64);
+
+// Subscriptions belong to a particular circuit, so they are not copied when
+// the circuit is cloned.
+#[derive(Default)]
+struct Subscriptions {
+    sinks: Vec<(SubscriptionId, Box<dyn EventSink>)>,
+    next_id: u64,
+}
+
+impl Clone for Subscriptions {
+    fn clone(&self) -> Self {
+        Default::default()
+    }
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} subscriptions", self.sinks.len())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Circuit {
+    components: BTreeMap<ComponentId, ComponentInfo>,
+    wires: BTreeMap<WireId, Wire>,
+    next_id: u64,
+    // The uuid of each component and wire, which unlike its id is preserved
+    // when the circuit is saved, and the id of each uuid. Components and
+    // wires share the space of ids, and a removed item's uuid is forgotten,
+    // so restoring it under the same id needs its uuid to be passed back.
+    uuids: BTreeMap<u64, Uuid>,
+    ids: HashMap<Uuid, u64>,
+    subscriptions: Subscriptions,
+    appearance: Option<SymbolAppearance>,
+    parameters: Parameters,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+    /// Registers a sink to be notified of changes made through the methods
+    /// of the circuit. Changes made via `get_component_mut` are not observed.
+    pub fn subscribe<S: EventSink + 'static>(&mut self, sink: S) -> SubscriptionId {
+        let subscriptions = &mut self.subscriptions;
+        let id = SubscriptionId(subscriptions.next_id);
+        subscriptions.next_id += 1;
+        subscriptions.sinks.push((id, Box::new(sink)));
+        id
+    }
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let sinks = &mut self.subscriptions.sinks;
+        let len = sinks.len();
+        sinks.retain(|&(sink_id, _)| sink_id != id);
+        sinks.len() != len
+    }
+    fn notify(&mut self, event: CircuitEvent) {
+        for (_, sink) in &mut self.subscriptions.sinks {
+            sink.handle(&event);
+        }
+    }
+    fn assign_uuid(&mut self, id: u64, uuid: Option<Uuid>) {
+        let uuid = match (uuid, self.uuids.get(&id)) {
+            (Some(uuid), _) => uuid,
+            (None, Some(_)) => return,
+            (None, None) => Uuid::new_v4(),
+        };
+        self.forget_uuid(id);
+        self.uuids.insert(id, uuid);
+        self.ids.insert(uuid, id);
+    }
+    fn forget_uuid(&mut self, id: u64) {
+        if let Some(uuid) = self.uuids.remove(&id) {
+            if self.ids.get(&uuid) == Some(&id) {
+                self.ids.remove(&uuid);
+            }
+        }
+    }
+    pub fn add_component(&mut self, mut component: ComponentInfo, x: i32, y: i32) -> ComponentId {
+        let id = ComponentId(self.allocate_id());
+        self.assign_uuid(id.0, None);
+        component.set_position(x, y);
+        self.components.insert(id, component);
+        self.notify(CircuitEvent::ComponentAdded(id));
+        id
+    }
+    // Used when loading and undoing, to preserve the id and uuid of an
+    // existing component
+    pub(crate) fn insert_component_with_uuid(&mut self, id: ComponentId, component: ComponentInfo, uuid: Option<Uuid>) {
+        self.next_id = self.next_id.max(id.0 + 1);
+        self.assign_uuid(id.0, uuid);
+        self.components.insert(id, component);
+        self.notify(CircuitEvent::ComponentAdded(id));
+    }
+    pub fn remove_component(&mut self, id: ComponentId) -> Option<ComponentInfo> {
+        let result = self.components.remove(&id);
+        if result.is_some() {
+            self.forget_uuid(id.0);
+            self.notify(CircuitEvent::ComponentRemoved(id));
+        }
+        result
+    }
+    /// Moves a component, returning its previous position, or `None` if
+    /// there is no such component.
+    pub fn move_component(&mut self, id: ComponentId, x: i32, y: i32) -> Option<(i32, i32)> {
+        let info = self.components.get_mut(&id)?;
+        let old_position = info.position();
+        info.set_position(x, y);
+        self.notify(CircuitEvent::ComponentMoved(id));
+        Some(old_position)
+    }
+    /// Sets a property of a component, notifying subscribers of the change.
+    /// Returns `None` if there is no such component.
+    pub fn set_property(
+        &mut self,
+        id: ComponentId,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Option<Result<PropertyChanged, PropertyError>> {
+        let result = self.components.get_mut(&id)?.set_property(name, value);
+        if let Ok(ref change) = result {
+            self.notify(CircuitEvent::PropertyChanged(id, change.clone()));
+        }
+        Some(result)
+    }
+    pub fn get_component(&self, id: ComponentId) -> Option<&ComponentInfo> {
+        self.components.get(&id)
+    }
+    pub fn get_component_mut(&mut self, id: ComponentId) -> Option<&mut ComponentInfo> {
+        self.components.get_mut(&id)
+    }
+    pub fn components(&self) -> impl Iterator<Item=(ComponentId, &ComponentInfo)> {
+        self.components.iter().map(|(&id, c)| (id, c))
+    }
+    pub fn components_mut(&mut self) -> impl Iterator<Item=(ComponentId, &mut ComponentInfo)> {
+        self.components.iter_mut().map(|(&id, c)| (id, c))
+    }
+    /// The components which are of the concrete type `T`.
+    pub fn components_of_type<T: Component>(&self) -> impl Iterator<Item=(ComponentId, &T)> {
+        self.components().filter_map(|(id, c)| Some((id, c.downcast_ref()?)))
+    }
+    pub fn add_wire(&mut self, wire: Wire) -> WireId {
+        self.add_wire_with_uuid(wire, None)
+    }
+    // Used when loading, to preserve the uuids of existing wires
+    pub(crate) fn add_wire_with_uuid(&mut self, wire: Wire, uuid: Option<Uuid>) -> WireId {
+        let id = WireId(self.allocate_id());
+        self.assign_uuid(id.0, uuid);
+        self.wires.insert(id, wire);
+        self.notify(CircuitEvent::WireAdded(id));
+        id
+    }
+    // Used by the journal, so that ids allocated after recovery match those
+    // which would have been allocated had the circuit never been saved
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id
+    }
+    pub(crate) fn reserve_ids(&mut self, next_id: u64) {
+        self.next_id = self.next_id.max(next_id);
+    }
+    // Used when undoing the removal of a wire, to preserve its id and uuid
+    pub(crate) fn insert_wire_with_uuid(&mut self, id: WireId, wire: Wire, uuid: Option<Uuid>) {
+        self.next_id = self.next_id.max(id.0 + 1);
+        self.assign_uuid(id.0, uuid);
+        self.wires.insert(id, wire);
+        self.notify(CircuitEvent::WireAdded(id));
+    }
+    pub fn remove_wire(&mut self, id: WireId) -> Option<Wire> {
+        let result = self.wires.remove(&id);
+        if result.is_some() {
+            self.forget_uuid(id.0);
+            self.notify(CircuitEvent::WireRemoved(id));
+        }
+        result
+    }
+    pub fn get_wire(&self, id: WireId) -> Option<&Wire> {
+        self.wires.get(&id)
+    }
+    pub fn wires(&self) -> impl Iterator<Item=(WireId, &Wire)> {
+        self.wires.iter().map(|(&id, w)| (id, w))
+    }
+    /// The identifier of a component which is preserved when the circuit is
+    /// saved and loaded, unlike its id.
+    pub fn component_uuid(&self, id: ComponentId) -> Option<Uuid> {
+        self.components.get(&id).and(self.uuids.get(&id.0).cloned())
+    }
+    pub fn wire_uuid(&self, id: WireId) -> Option<Uuid> {
+        self.wires.get(&id).and(self.uuids.get(&id.0).cloned())
+    }
+    pub fn find_component(&self, uuid: Uuid) -> Option<ComponentId> {
+        self.ids.get(&uuid).map(|&id| ComponentId(id)).filter(|id| self.components.contains_key(id))
+    }
+    pub fn find_wire(&self, uuid: Uuid) -> Option<WireId> {
+        self.ids.get(&uuid).map(|&id| WireId(id)).filter(|id| self.wires.contains_key(id))
+    }
+    pub fn num_components(&self) -> usize {
+        self.components.len()
+    }
+    pub fn num_wires(&self) -> usize {
+        self.wires.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty() && self.wires.is_empty()
+    }
+    /// How instances of this circuit are drawn when used as a subcircuit,
+    /// if not as the default box.
+    pub fn appearance(&self) -> Option<&SymbolAppearance> {
+        self.appearance.as_ref()
+    }
+    pub fn set_appearance(&mut self, appearance: Option<SymbolAppearance>) {
+        self.appearance = appearance;
+    }
+    /// The parameters instances of this circuit can be given, and the
+    /// component properties which depend on them.
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+    pub fn parameters_mut(&mut self) -> &mut Parameters {
+        &mut self.parameters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn add_and_remove() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("or_gate").unwrap(), 2, 3);
+        let b = circuit.add_component(lib.create("or_gate").unwrap(), 10, 3);
+        let w = circuit.add_wire(Wire::new((6, 5), (10, 5)));
+        assert_ne!(a, b);
+        assert_eq!(circuit.get_component(b).unwrap().position(), (10, 3));
+        assert_eq!(circuit.components().map(|(id, _)| id).collect::<Vec<_>>(), vec![a, b]);
+        let (uuid_a, uuid_w) = (circuit.component_uuid(a).unwrap(), circuit.wire_uuid(w).unwrap());
+        assert_eq!(circuit.find_component(uuid_a), Some(a));
+        assert_eq!(circuit.find_wire(uuid_w), Some(w));
+        assert_eq!(circuit.find_wire(uuid_a), None);
+
+        assert!(circuit.remove_component(a).is_some());
+        assert!(circuit.remove_component(a).is_none());
+        assert!(circuit.get_component(a).is_none());
+        assert_eq!(circuit.find_component(uuid_a), None);
+        assert_eq!(circuit.num_components(), 1);
+        assert_eq!(circuit.remove_wire(w), Some(Wire::new((6, 5), (10, 5))));
+        assert_eq!(circuit.find_wire(uuid_w), None);
+        assert!(circuit.uuids.len() == 1 && circuit.ids.len() == 1);
+    }
+
+    #[test]
+    fn components_of_type() {
+        use crate::subcircuit::{CircuitRegistry, SubcircuitComponent};
+
+        let mut lib = gates::library();
+        let mut registry = CircuitRegistry::new();
+        registry.insert("inner", Circuit::new()).unwrap();
+        registry.register(&mut lib).unwrap();
+        let mut circuit = Circuit::new();
+        circuit.add_component(lib.create("or_gate").unwrap(), 0, 0);
+        let instance = circuit.add_component(lib.create("inner").unwrap(), 10, 0);
+        let found: Vec<_> = circuit.components_of_type::<SubcircuitComponent>()
+            .map(|(id, component)| (id, component.name()))
+            .collect();
+        assert_eq!(found, vec![(instance, "inner")]);
+    }
+
+    #[test]
+    fn wire_contains() {
+        let wire = Wire::new((0, 2), (4, 2));
+        assert!(wire.contains((0, 2)));
+        assert!(wire.contains((3, 2)));
+        assert!(!wire.contains((5, 2)));
+        assert!(!wire.contains((2, 3)));
+    }
+
+    #[test]
+    fn events() {
+        use std::sync::{Arc, Mutex};
+
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let subscription = circuit.subscribe(move |e: &CircuitEvent| sink.lock().unwrap().push(e.clone()));
+
+        let a = circuit.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        let change = circuit.set_property(a, "num_inputs", 3.into()).unwrap().unwrap();
+        assert!(change.schema_changed);
+        assert_eq!(change.old_value, Some(2.into()));
+        assert!(circuit.set_property(a, "num_inputs", 1.into()).unwrap().is_err());
+        assert!(circuit.set_property(ComponentId(99), "num_inputs", 3.into()).is_none());
+        assert!(circuit.clone().remove_component(a).is_some());
+        assert!(circuit.unsubscribe(subscription));
+        circuit.remove_component(a);
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            CircuitEvent::ComponentAdded(a),
+            CircuitEvent::PropertyChanged(a, change),
+        ]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+
+use crate::circuit::{Circuit, Wire};
+use crate::library::Library;
+use crate::persist::{ComponentRecord, LoadError, save_properties, load_component};
+use crate::selection::{Item, Selection};
+use crate::undo::Command;
+
+/// A copied part of a circuit. Positions are relative to the top-left corner
+/// of the copied items. The clip can be serialized, so that it can be placed
+/// on the system clipboard and pasted into another process.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Clip {
+    /// The components, recorded under their ids in the source circuit.
+    pub components: Vec<ComponentRecord>,
+    pub wires: Vec<Wire>,
+}
+
+impl Clip {
+    /// Copies the selected items. Wires are included if they were selected,
+    /// or if both ends lie on pins of selected components.
+    pub fn copy(circuit: &Circuit, selection: &Selection) -> Clip {
+        let origin = match selection.bounding_box(circuit) {
+            Some(b) => (b.x, b.y),
+            None => return Clip::default(),
+        };
+        let relative = |(x, y): (i32, i32)| (x - origin.0, y - origin.1);
+
+        let mut clip = Clip::default();
+        let mut pins = HashSet::new();
+        let mut wires = Vec::new();
+        for item in selection.items() {
+            match item {
+                Item::Component(id) => if let Some(info) = circuit.get_component(id) {
+                    let position = info.position();
+                    pins.extend(info.get_shape().pins.iter().map(|pin| (position.0 + pin.x, position.1 + pin.y)));
+                    let (x, y) = relative(position);
+                    clip.components.push(ComponentRecord {
+                        id,
+                        component: info.metadata().id.clone().into_owned(),
+                        x,
+                        y,
+                        schema_version: info.metadata().schema_version,
+                        properties: save_properties(info),
+                        // Pasted components are new, so are given new uuids
+                        uuid: None,
+                    });
+                },
+                Item::Wire(id) => if let Some(&wire) = circuit.get_wire(id) {
+                    wires.push(wire);
+                },
+            }
+        }
+        for (id, &wire) in circuit.wires() {
+            if !selection.contains(Item::Wire(id)) && pins.contains(&wire.start) && pins.contains(&wire.end) {
+                wires.push(wire);
+            }
+        }
+        clip.wires = wires.into_iter()
+            .map(|wire| Wire::new(relative(wire.start), relative(wire.end)))
+            .collect();
+        clip
+    }
+    /// Copies the selected items, also returning the command which removes
+    /// them from the circuit.
+    pub fn cut(circuit: &Circuit, selection: &Selection) -> (Clip, Command) {
+        (Clip::copy(circuit, selection), selection.delete(circuit))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty() && self.wires.is_empty()
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Clip should always be serializable")
+    }
+    pub fn from_json(json: &str) -> Result<Clip, LoadError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+impl Circuit {
+    /// Adds the contents of a clip with its top-left corner at `offset`.
+    /// Components are created from the library and given fresh ids. Returns
+    /// the pasted items, so that they can be selected. Nothing is added if an
+    /// error is returned.
+    pub fn paste(&mut self, clip: &Clip, offset: (i32, i32), library: &Library) -> Result<Vec<Item>, LoadError> {
+        let mut components = Vec::with_capacity(clip.components.len());
+        for record in &clip.components {
+            let (info, _) = load_component(record, library)?;
+            components.push((info, record.x + offset.0, record.y + offset.1));
+        }
+        let mut items: Vec<_> = components.into_iter()
+            .map(|(info, x, y)| Item::Component(self.add_component(info, x, y)))
+            .collect();
+        let translate = |(x, y): (i32, i32)| (x + offset.0, y + offset.1);
+        items.extend(clip.wires.iter().map(|wire| {
+            Item::Wire(self.add_wire(Wire::new(translate(wire.start), translate(wire.end))))
+        }));
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn copy_and_paste() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let mut gate = lib.create("or_gate").unwrap();
+        gate.set_property("num_inputs", serde_json::json!(3)).unwrap();
+        let a = circuit.add_component(gate, 4, 4);
+        let b = circuit.add_component(lib.create("not_gate").unwrap(), 20, 4);
+        let pin_a = circuit.get_component(a).unwrap().get_shape().pins.last().map(|p| (p.x + 4, p.y + 4)).unwrap();
+        let pin_b = circuit.get_component(b).unwrap().get_shape().pins[0].clone();
+        circuit.add_wire(Wire::new(pin_a, (pin_b.x + 20, pin_b.y + 4)));
+        circuit.add_wire(Wire::new((0, 0), (0, 4)));
+
+        let mut selection = Selection::new();
+        selection.toggle(Item::Component(a));
+        selection.toggle(Item::Component(b));
+        let clip = Clip::from_json(&Clip::copy(&circuit, &selection).to_json()).unwrap();
+        assert_eq!(clip.components.len(), 2);
+        assert_eq!((clip.components[0].x, clip.components[0].y), (0, 0));
+        assert_eq!(clip.wires.len(), 1);
+
+        let items = circuit.paste(&clip, (4, 20), &lib).unwrap();
+        assert_eq!(items.len(), 3);
+        let pasted = match items[0] {
+            Item::Component(id) => circuit.get_component(id).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(pasted.position(), (4, 20));
+        assert_eq!(pasted.get_property("num_inputs"), Some(serde_json::json!(3)));
+        assert!(!items.contains(&Item::Component(a)));
+
+        let (clip, command) = Clip::cut(&circuit, &selection);
+        assert!(!clip.is_empty());
+        command.apply(&mut circuit).unwrap();
+        assert_eq!(circuit.num_components(), 2);
+    }
+}
@@ -0,0 +1,563 @@
+//! Collaborative editing of a circuit by several users at once.
+//!
+//! Each user's copy of the circuit, or replica, has a `Document` alongside
+//! it, which turns their edits into `Operation`s to be sent to the other
+//! replicas, and merges in the operations it receives. How operations are
+//! sent is up to the application.
+//!
+//! The document keeps the components and wires of the circuit as a map from
+//! uuid to last-writer-wins registers, ordered by Lamport timestamps. This
+//! makes operations commute, so every replica which has applied the same
+//! operations has the same circuit, whatever order they arrived in. Every
+//! replica must start from the same circuit, such as one loaded from the same
+//! file, so that they agree on the uuids of its components and wires.
+//!
+//! Local edits are captured by enabling the journal of the `CommandStack`
+//! used to edit the circuit, and passing what it records to
+//! `Document::record`.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fmt;
+use std::error::Error;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use maplit::btreemap;
+use uuid::Uuid;
+
+use crate::circuit::{Circuit, ComponentId, WireId, Wire};
+use crate::component::PropertyError;
+use crate::journal::JournalEntry;
+use crate::library::Library;
+use crate::persist::{self, ComponentRecord, LoadError};
+
+/// Identifies a replica, which should be unique among those collaborating.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct ReplicaId(pub Uuid);
+
+impl ReplicaId {
+    pub fn random() -> Self {
+        ReplicaId(Uuid::new_v4())
+    }
+}
+
+/// A Lamport timestamp. Timestamps are totally ordered, with ties between
+/// replicas broken by their ids, and the default timestamp comes before
+/// that of every operation.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Timestamp {
+    pub counter: u64,
+    pub replica: ReplicaId,
+}
+
+/// The number of operations from each replica which have been applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct VectorClock(BTreeMap<ReplicaId, u64>);
+
+impl VectorClock {
+    pub fn get(&self, replica: ReplicaId) -> u64 {
+        self.0.get(&replica).cloned().unwrap_or(0)
+    }
+    pub fn contains(&self, operation: &Operation) -> bool {
+        operation.seq <= self.get(operation.replica())
+    }
+    fn advance(&mut self, replica: ReplicaId) -> u64 {
+        let seq = self.0.entry(replica).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum OperationKind {
+    AddComponent {
+        uuid: Uuid,
+        component: String,
+        schema_version: u32,
+        x: i32,
+        y: i32,
+        properties: BTreeMap<String, Value>,
+    },
+    RemoveComponent {
+        uuid: Uuid,
+    },
+    MoveComponent {
+        uuid: Uuid,
+        x: i32,
+        y: i32,
+    },
+    /// Sets properties of a component, which are sent together as setting
+    /// one may depend on the others.
+    SetProperties {
+        uuid: Uuid,
+        properties: BTreeMap<String, Value>,
+    },
+    /// Adds a wire, or moves it if it already exists.
+    AddWire {
+        uuid: Uuid,
+        wire: Wire,
+    },
+    RemoveWire {
+        uuid: Uuid,
+    },
+}
+
+/// An edit made by one replica, to be applied by the others.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Operation {
+    /// The position of the operation among those of its replica, counting
+    /// from one.
+    pub seq: u64,
+    pub timestamp: Timestamp,
+    pub kind: OperationKind,
+}
+
+impl Operation {
+    pub fn replica(&self) -> ReplicaId {
+        self.timestamp.replica
+    }
+}
+
+#[derive(Debug)]
+pub enum CollabError {
+    Component {
+        uuid: Uuid,
+        error: LoadError,
+    },
+    Property {
+        uuid: Uuid,
+        error: PropertyError,
+    },
+}
+
+impl fmt::Display for CollabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollabError::Component { uuid, error } => write!(f, "Component {}: {}", uuid, error),
+            CollabError::Property { uuid, error } => write!(f, "Component {}: {}", uuid, error),
+        }
+    }
+}
+
+impl Error for CollabError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            CollabError::Component { error, .. } => Some(error),
+            CollabError::Property { error, .. } => Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Register<T> {
+    value: T,
+    timestamp: Timestamp,
+}
+
+impl<T> Register<T> {
+    fn new(value: T) -> Self {
+        Register { value, timestamp: Timestamp::default() }
+    }
+    // Keeps whichever value was written last
+    fn set(&mut self, value: T, timestamp: Timestamp) {
+        if timestamp > self.timestamp {
+            self.value = value;
+            self.timestamp = timestamp;
+        }
+    }
+}
+
+fn set_property(properties: &mut BTreeMap<String, Register<Value>>, name: String, value: Value, timestamp: Timestamp) {
+    match properties.entry(name) {
+        Entry::Vacant(entry) => { entry.insert(Register { value, timestamp }); },
+        Entry::Occupied(mut entry) => entry.get_mut().set(value, timestamp),
+    }
+}
+
+// Operations may refer to a component before the operation which added it
+// has arrived, so every part of the state is optional
+#[derive(Debug, Clone, Default)]
+struct ComponentState {
+    present: Register<bool>,
+    kind: Option<(String, u32)>,
+    position: Register<(i32, i32)>,
+    properties: BTreeMap<String, Register<Value>>,
+    // The id of the component in the local circuit, if it is there
+    local: Option<ComponentId>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct WireState {
+    present: Register<bool>,
+    wire: Register<Option<Wire>>,
+    local: Option<WireId>,
+}
+
+/// The shared state of a circuit being edited collaboratively.
+#[derive(Debug, Clone)]
+pub struct Document {
+    replica: ReplicaId,
+    counter: u64,
+    version: VectorClock,
+    log: Vec<Operation>,
+    // Operations which arrived before earlier operations of their replica
+    pending: Vec<Operation>,
+    components: BTreeMap<Uuid, ComponentState>,
+    wires: BTreeMap<Uuid, WireState>,
+}
+
+impl Document {
+    /// Starts collaborating on a circuit, which must be the same for every
+    /// replica.
+    pub fn new(replica: ReplicaId, circuit: &Circuit) -> Self {
+        let components = circuit.components().filter_map(|(id, info)| {
+            let state = ComponentState {
+                present: Register::new(true),
+                kind: Some((info.metadata().id.clone().into_owned(), info.metadata().schema_version)),
+                position: Register::new(info.position()),
+                properties: persist::save_properties(info).into_iter()
+                    .map(|(name, value)| (name, Register::new(value)))
+                    .collect(),
+                local: Some(id),
+            };
+            Some((circuit.component_uuid(id)?, state))
+        }).collect();
+        let wires = circuit.wires().filter_map(|(id, &wire)| {
+            let state = WireState {
+                present: Register::new(true),
+                wire: Register::new(Some(wire)),
+                local: Some(id),
+            };
+            Some((circuit.wire_uuid(id)?, state))
+        }).collect();
+        Document {
+            replica,
+            counter: 0,
+            version: VectorClock::default(),
+            log: Vec::new(),
+            pending: Vec::new(),
+            components,
+            wires,
+        }
+    }
+    pub fn replica(&self) -> ReplicaId {
+        self.replica
+    }
+    /// The operations which have been applied, from every replica.
+    pub fn version(&self) -> &VectorClock {
+        &self.version
+    }
+    /// The operations applied here which a replica at the given version has
+    /// not yet applied, in the order they were applied.
+    pub fn operations_since(&self, version: &VectorClock) -> Vec<Operation> {
+        self.log.iter().filter(|op| !version.contains(op)).cloned().collect()
+    }
+
+    fn find_component(&self, id: ComponentId) -> Option<Uuid> {
+        self.components.iter().find(|(_, state)| state.local == Some(id)).map(|(&uuid, _)| uuid)
+    }
+
+    /// Turns edits made to the local circuit, as journaled by a
+    /// `CommandStack`, into operations to send to the other replicas. The
+    /// circuit should be as it was after the edits.
+    pub fn record(&mut self, circuit: &Circuit, entries: &[JournalEntry]) -> Vec<Operation> {
+        let mut result = Vec::new();
+        for entry in entries {
+            let kinds = match *entry {
+                JournalEntry::Checkpoint { .. } => Vec::new(),
+                JournalEntry::InsertComponent(ref record) => {
+                    match record.uuid.or_else(|| circuit.component_uuid(record.id)) {
+                        Some(uuid) => {
+                            self.components.entry(uuid).or_default().local = Some(record.id);
+                            vec![OperationKind::AddComponent {
+                                uuid,
+                                component: record.component.clone(),
+                                schema_version: record.schema_version,
+                                x: record.x,
+                                y: record.y,
+                                properties: record.properties.clone(),
+                            }]
+                        },
+                        None => Vec::new(),
+                    }
+                },
+                JournalEntry::RemoveComponent { id } => match self.find_component(id) {
+                    Some(uuid) => {
+                        self.components.get_mut(&uuid).expect("Component exists").local = None;
+                        vec![OperationKind::RemoveComponent { uuid }]
+                    },
+                    None => Vec::new(),
+                },
+                JournalEntry::MoveComponent { id, x, y } => match self.find_component(id) {
+                    Some(uuid) => vec![OperationKind::MoveComponent { uuid, x, y }],
+                    None => Vec::new(),
+                },
+                JournalEntry::SetProperty { id, ref name, ref value } => match (self.find_component(id), circuit.get_component(id)) {
+                    // Setting one property may change others, such as the
+                    // inverted inputs of a gate when its number of inputs
+                    // changes, so every property which now differs is sent
+                    (Some(uuid), Some(info)) => {
+                        let state = &self.components[&uuid];
+                        let properties: BTreeMap<_, _> = persist::save_properties(info).into_iter()
+                            .filter(|(name, value)| state.properties.get(name).map(|r| &r.value) != Some(value))
+                            .collect();
+                        if properties.is_empty() {
+                            Vec::new()
+                        } else {
+                            vec![OperationKind::SetProperties { uuid, properties }]
+                        }
+                    },
+                    (Some(uuid), None) => vec![OperationKind::SetProperties {
+                        uuid,
+                        properties: btreemap!{ name.clone() => value.clone() },
+                    }],
+                    (None, _) => Vec::new(),
+                },
+                JournalEntry::AddWire { wire, uuid } => {
+                    self.wires.entry(uuid).or_default().local = circuit.find_wire(uuid);
+                    vec![OperationKind::AddWire { uuid, wire }]
+                },
+                JournalEntry::RemoveWire { wire } => {
+                    let found = self.wires.iter_mut()
+                        .find(|(_, state)| state.local.is_some() && state.wire.value == Some(wire));
+                    match found {
+                        Some((&uuid, state)) => {
+                            state.local = None;
+                            vec![OperationKind::RemoveWire { uuid }]
+                        },
+                        None => Vec::new(),
+                    }
+                },
+            };
+            for kind in kinds {
+                self.counter += 1;
+                let operation = Operation {
+                    seq: self.version.advance(self.replica),
+                    timestamp: Timestamp { counter: self.counter, replica: self.replica },
+                    kind,
+                };
+                self.update(&operation);
+                self.log.push(operation.clone());
+                result.push(operation);
+            }
+        }
+        result
+    }
+
+    /// Applies operations from other replicas to the local circuit.
+    /// Operations which have already been applied are ignored, and those
+    /// which arrive before earlier operations of the same replica are held
+    /// until those arrive. Returns the number of operations applied.
+    ///
+    /// If an error is returned, the operation which caused it has still been
+    /// applied to the document, but the local circuit may be missing the
+    /// change, such as when a component is not in the local library.
+    pub fn merge<I: IntoIterator<Item=Operation>>(
+        &mut self,
+        circuit: &mut Circuit,
+        operations: I,
+        library: &Library,
+    ) -> Result<usize, CollabError> {
+        self.pending.extend(operations);
+        let mut applied = 0;
+        loop {
+            let version = &self.version;
+            self.pending.retain(|op| !version.contains(op));
+            let next = self.pending.iter()
+                .position(|op| op.seq == version.get(op.replica()) + 1);
+            let operation = match next {
+                Some(index) => self.pending.swap_remove(index),
+                None => return Ok(applied),
+            };
+            self.counter = self.counter.max(operation.timestamp.counter);
+            self.version.advance(operation.replica());
+            self.update(&operation);
+            self.log.push(operation.clone());
+            applied += 1;
+            match operation.kind {
+                OperationKind::AddComponent { uuid, .. }
+                | OperationKind::RemoveComponent { uuid }
+                | OperationKind::MoveComponent { uuid, .. }
+                | OperationKind::SetProperties { uuid, .. } => self.materialize_component(uuid, circuit, library)?,
+                OperationKind::AddWire { uuid, .. }
+                | OperationKind::RemoveWire { uuid } => self.materialize_wire(uuid, circuit),
+            }
+        }
+    }
+
+    // Updates the registers changed by an operation
+    fn update(&mut self, operation: &Operation) {
+        let timestamp = operation.timestamp;
+        match operation.kind.clone() {
+            OperationKind::AddComponent { uuid, component, schema_version, x, y, properties } => {
+                let state = self.components.entry(uuid).or_default();
+                state.kind.get_or_insert((component, schema_version));
+                state.present.set(true, timestamp);
+                state.position.set((x, y), timestamp);
+                for (name, value) in properties {
+                    set_property(&mut state.properties, name, value, timestamp);
+                }
+            },
+            OperationKind::RemoveComponent { uuid } =>
+                self.components.entry(uuid).or_default().present.set(false, timestamp),
+            OperationKind::MoveComponent { uuid, x, y } =>
+                self.components.entry(uuid).or_default().position.set((x, y), timestamp),
+            OperationKind::SetProperties { uuid, properties } => {
+                let state = self.components.entry(uuid).or_default();
+                for (name, value) in properties {
+                    set_property(&mut state.properties, name, value, timestamp);
+                }
+            },
+            OperationKind::AddWire { uuid, wire } => {
+                let state = self.wires.entry(uuid).or_default();
+                state.present.set(true, timestamp);
+                state.wire.set(Some(wire), timestamp);
+            },
+            OperationKind::RemoveWire { uuid } =>
+                self.wires.entry(uuid).or_default().present.set(false, timestamp),
+        }
+    }
+
+    // Brings a component of the local circuit into line with the document
+    fn materialize_component(&mut self, uuid: Uuid, circuit: &mut Circuit, library: &Library) -> Result<(), CollabError> {
+        let state = self.components.get_mut(&uuid).expect("Component state exists");
+        let local = state.local.filter(|&id| circuit.get_component(id).is_some());
+        let properties = state.properties.iter().map(|(name, register)| (name.clone(), register.value.clone()));
+        match (state.present.value, &state.kind, local) {
+            (true, Some((component, schema_version)), None) => {
+                let (x, y) = state.position.value;
+                let record = ComponentRecord {
+                    id: ComponentId(circuit.next_id()),
+                    component: component.clone(),
+                    x,
+                    y,
+                    schema_version: *schema_version,
+                    properties: properties.collect(),
+                    uuid: Some(uuid),
+                };
+                let (info, _) = persist::load_component(&record, library)
+                    .map_err(|error| CollabError::Component { uuid, error })?;
+                circuit.insert_component_with_uuid(record.id, info, record.uuid);
+                state.local = Some(record.id);
+            },
+            (true, Some(_), Some(id)) => {
+                let info = circuit.get_component(id).expect("Component exists");
+                let position = state.position.value;
+                let mut changed: BTreeMap<_, _> = properties
+                    .filter(|(name, value)| info.get_property(name).as_ref() != Some(value))
+                    .collect();
+                if info.position() != position {
+                    circuit.move_component(id, position.0, position.1);
+                }
+                // As in `persist::load_properties`, properties which fail are
+                // retried in case they depend on others
+                while !changed.is_empty() {
+                    let remaining = changed.len();
+                    let mut error = None;
+                    let names: Vec<_> = changed.keys().cloned().collect();
+                    for name in names {
+                        match circuit.set_property(id, &name, changed[&name].clone()).expect("Component exists") {
+                            Ok(_) => { changed.remove(&name); },
+                            Err(e) => error = Some(e),
+                        }
+                    }
+                    match error {
+                        Some(error) if changed.len() == remaining => return Err(CollabError::Property { uuid, error }),
+                        _ => {},
+                    }
+                }
+            },
+            (false, _, Some(id)) => {
+                circuit.remove_component(id);
+                state.local = None;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Brings a wire of the local circuit into line with the document
+    fn materialize_wire(&mut self, uuid: Uuid, circuit: &mut Circuit) {
+        let state = self.wires.get_mut(&uuid).expect("Wire state exists");
+        let local = state.local.filter(|&id| circuit.get_wire(id).is_some());
+        match (state.present.value, state.wire.value, local) {
+            (true, Some(wire), None) => state.local = Some(circuit.add_wire_with_uuid(wire, Some(uuid))),
+            (true, Some(wire), Some(id)) if circuit.get_wire(id) != Some(&wire) => {
+                circuit.remove_wire(id);
+                circuit.insert_wire_with_uuid(id, wire, Some(uuid));
+            },
+            (false, _, Some(id)) => {
+                circuit.remove_wire(id);
+                state.local = None;
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::diff_circuits;
+    use crate::libraries::gates;
+    use crate::undo::{Command, CommandStack};
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let lib = gates::library();
+        let mut original = Circuit::new();
+        let gate = original.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        let wire = original.add_wire(Wire::new((0, 0), (0, 4)));
+        let mut a = Circuit::from_json(&original.to_json(), &lib).unwrap();
+        let mut b = Circuit::from_json(&original.to_json(), &lib).unwrap();
+        let mut doc_a = Document::new(ReplicaId::random(), &a);
+        let mut doc_b = Document::new(ReplicaId::random(), &b);
+        let mut stack_a = CommandStack::new();
+        let mut stack_b = CommandStack::new();
+        stack_a.enable_journal();
+        stack_b.enable_journal();
+
+        stack_a.execute(&mut a, Command::MoveComponent { id: gate, x: 4, y: 4 }).unwrap();
+        stack_a.execute(&mut a, Command::SetProperty { id: gate, name: "label".into(), value: "A".into() }).unwrap();
+        stack_a.add_component(&mut a, lib.create("or_gate").unwrap(), 8, 8);
+        let ops_a = doc_a.record(&a, &stack_a.take_journal());
+
+        stack_b.execute(&mut b, Command::SetProperty { id: gate, name: "label".into(), value: "B".into() }).unwrap();
+        stack_b.execute(&mut b, Command::SetProperty { id: gate, name: "num_inputs".into(), value: 3.into() }).unwrap();
+        let wire_b = b.find_wire(original.wire_uuid(wire).unwrap()).unwrap();
+        stack_b.execute(&mut b, Command::RemoveWire { id: wire_b }).unwrap();
+        stack_b.add_wire(&mut b, Wire::new((2, 2), (2, 6)));
+        let ops_b = doc_b.record(&b, &stack_b.take_journal());
+        // Both properties were changed by the time the edits were recorded,
+        // so are sent together
+        assert_eq!(ops_b.len(), 3);
+
+        // Deliver out of order, and more than once
+        let mut reversed = ops_a.clone();
+        reversed.reverse();
+        assert_eq!(doc_b.merge(&mut b, reversed, &lib).unwrap(), 3);
+        assert_eq!(doc_b.merge(&mut b, ops_a.clone(), &lib).unwrap(), 0);
+        assert_eq!(doc_a.merge(&mut a, doc_b.operations_since(doc_a.version()), &lib).unwrap(), 3);
+        assert_eq!(doc_a.version(), doc_b.version());
+
+        assert!(diff_circuits(&a, &b).is_empty());
+        assert_eq!(a.num_components(), 2);
+        assert_eq!(a.wires().map(|(_, &w)| w).collect::<Vec<_>>(), vec![Wire::new((2, 2), (2, 6))]);
+        let info = a.get_component(gate).unwrap();
+        assert_eq!(info.position(), (4, 4));
+        assert_eq!(info.get_property("num_inputs"), Some(3.into()));
+        // Both replicas pick the same winner of the conflicting labels
+        assert_eq!(info.get_property("label"), b.get_component(gate).unwrap().get_property("label"));
+
+        // Removing a component wins over concurrent edits to it
+        stack_a.execute(&mut a, Command::RemoveComponent { id: gate }).unwrap();
+        stack_b.execute(&mut b, Command::MoveComponent { id: gate, x: 0, y: 0 }).unwrap();
+        let ops_a = doc_a.record(&a, &stack_a.take_journal());
+        let ops_b = doc_b.record(&b, &stack_b.take_journal());
+        doc_a.merge(&mut a, ops_b, &lib).unwrap();
+        doc_b.merge(&mut b, ops_a, &lib).unwrap();
+        assert!(a.get_component(gate).is_none());
+        assert!(diff_circuits(&a, &b).is_empty());
+    }
+}
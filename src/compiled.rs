@@ -0,0 +1,502 @@
+//! A faster way to evaluate combinational circuits, for analysis which
+//! needs the outputs for a great many different inputs.
+//!
+//! Subcircuits are inlined, and every component is placed in an order where
+//! it comes after all of the components driving its inputs. Settling the
+//! circuit is then a single pass over that order, reading and writing bits
+//! in preallocated arrays. Simple components such as gates describe their
+//! function with a `Kernel`, which is evaluated without calling into the
+//! component at all; other components are evaluated as usual.
+//!
+//! Circuits with a feedback loop have no such order, and can only be
+//! simulated with the event-driven `Simulator`.
+//!
+//! Nets with more than one driver use the default `Resolution` unless a
+//! component attached to them overrides it. Propagation delays are ignored,
+//! since only the settled values are computed.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::error::Error;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::{ComponentInfo, Interaction, PinDirection};
+use crate::net::PinRef;
+use crate::simulation::{Bit, Value, Netlist, Resolution};
+use crate::subcircuit::{ports, INPUT_PIN, OUTPUT_PIN};
+
+/// A boolean function combining a bit from each input of a gate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GateOp {
+    And,
+    Or,
+    /// True when exactly one input is true.
+    Xor,
+    /// True when an odd number of inputs are true.
+    Parity,
+}
+
+impl GateOp {
+    pub fn apply<I: IntoIterator<Item=Bit>>(self, inputs: I) -> Bit {
+        // Floating inputs are ignored, as if they were not connected
+        let (mut zeros, mut ones, mut unknowns, mut errors) = (0, 0, 0, 0);
+        for bit in inputs {
+            match bit {
+                Bit::Zero => zeros += 1,
+                Bit::One => ones += 1,
+                Bit::HighZ => {},
+                Bit::Unknown => unknowns += 1,
+                Bit::Error => errors += 1,
+            }
+        }
+        if zeros + ones + unknowns + errors == 0 {
+            return Bit::Error;
+        }
+        match self {
+            GateOp::And if zeros > 0 => Bit::Zero,
+            GateOp::Or if ones > 0 => Bit::One,
+            _ if errors > 0 => Bit::Error,
+            _ if unknowns > 0 => Bit::Unknown,
+            GateOp::And => Bit::One,
+            GateOp::Or => Bit::Zero,
+            GateOp::Xor => Bit::from_bool(ones == 1),
+            GateOp::Parity => Bit::from_bool(ones % 2 == 1),
+        }
+    }
+}
+
+/// The function of a component, as evaluated by the compiled simulator.
+/// Pins are numbered as in the component's shape, and a kernel must give the
+/// same results as the component's `evaluate` method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kernel {
+    /// Combines the same bit of each input pin, optionally inverting some of
+    /// them, and drives the result onto the last pin.
+    Gate { op: GateOp, invert_inputs: Vec<bool>, invert_output: bool },
+    /// Drives its first pin onto its second, optionally inverted. A floating
+    /// input is an error.
+    Buffer { invert: bool },
+    /// As for `Buffer`, but the output floats unless the third pin is one.
+    ControlledBuffer { invert: bool },
+}
+
+impl Kernel {
+    fn run(&self, pins: &[Vec<usize>], slots: &[Vec<usize>], values: &[Bit], out: &mut [Bit]) {
+        let input = |pin: usize, bit: usize| pins[pin].get(bit).map_or(Bit::HighZ, |&net| values[net]);
+        let buffer = |bit: Bit, invert: bool| match bit {
+            Bit::HighZ => Bit::Error,
+            _ if invert => !bit,
+            _ => bit,
+        };
+        match *self {
+            Kernel::Gate { op, ref invert_inputs, invert_output } => {
+                let n = invert_inputs.len();
+                for (b, &slot) in slots[n].iter().enumerate() {
+                    let result = op.apply((0..n).map(|i| {
+                        let bit = input(i, b);
+                        if invert_inputs[i] && bit != Bit::HighZ { !bit } else { bit }
+                    }));
+                    out[slot] = if invert_output { !result } else { result };
+                }
+            },
+            Kernel::Buffer { invert } => {
+                for (b, &slot) in slots[1].iter().enumerate() {
+                    out[slot] = buffer(input(0, b), invert);
+                }
+            },
+            Kernel::ControlledBuffer { invert } => {
+                let enable = input(2, 0);
+                for (b, &slot) in slots[1].iter().enumerate() {
+                    out[slot] = match enable {
+                        Bit::Zero => Bit::HighZ,
+                        Bit::One => buffer(input(0, b), invert),
+                        Bit::Error => Bit::Error,
+                        _ => Bit::Unknown,
+                    };
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// The outputs of these components feed back into their own inputs,
+    /// possibly through other components. Components within a subcircuit
+    /// are reported as the subcircuit itself.
+    CombinationalLoop { components: Vec<ComponentId> },
+    /// These components, such as transistors, switch nets together while
+    /// simulating, so the nets cannot be resolved in a fixed order.
+    Switches { components: Vec<ComponentId> },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::CombinationalLoop { components } =>
+                write!(f, "The circuit has a feedback loop through {} components", components.len()),
+            CompileError::Switches { components } =>
+                write!(f, "The circuit has {} switching components such as transistors", components.len()),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Kernel(Kernel),
+    // A component evaluated by calling `clock_edge` and `evaluate`
+    Component(usize),
+    // Drives the nets of the second pin with the values of the first, to
+    // join the ports of an inlined subcircuit to the nets outside it.
+    Copy,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    op: Op,
+    // The top-level component this node was compiled from
+    owner: ComponentId,
+    // The net attached to each bit of each pin
+    pins: Vec<Vec<usize>>,
+    // The slot written by each bit of each pin, for pins driven by the node
+    slots: Vec<Vec<usize>>,
+    // The nets which must be resolved again after the node is evaluated
+    driven: Vec<usize>,
+    reads: Vec<usize>,
+    // Nets attached to a bidirectional pin, which the node may both read
+    // and drive without forming a loop
+    shared: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<Node>,
+    components: Vec<ComponentInfo>,
+    top: HashMap<ComponentId, usize>,
+    // The slots driving each net
+    drivers: Vec<Vec<usize>>,
+    pulls: Vec<Option<Bit>>,
+    resolutions: Vec<Resolution>,
+    // The node writing each slot
+    slot_nodes: Vec<usize>,
+    // Components which cannot be compiled because they switch nets together
+    switches: BTreeSet<ComponentId>,
+}
+
+impl Builder {
+    // Adds the components of a circuit, returning the nets attached to the
+    // pins of each one. An inlined subcircuit is given the nets outside it
+    // attached to each of its input pins and then each of its output pins.
+    fn add_circuit(
+        &mut self,
+        circuit: &Circuit,
+        outer: Option<(ComponentId, &[Vec<usize>])>,
+    ) -> HashMap<ComponentId, Vec<Vec<usize>>> {
+        let netlist = Netlist::build(circuit);
+        let base = self.drivers.len();
+        self.drivers.resize(base + netlist.pulls.len(), Vec::new());
+        self.pulls.extend(netlist.pulls.iter().cloned());
+        self.resolutions.extend(netlist.resolutions.iter().map(|r| r.unwrap_or_default()));
+        let (inputs, outputs) = (ports(circuit, INPUT_PIN), ports(circuit, OUTPUT_PIN));
+
+        let mut result = HashMap::new();
+        for ((id, pins), (_, info)) in netlist.components.iter().zip(circuit.components()) {
+            let id = *id;
+            let nets: Vec<Vec<usize>> = pins.iter()
+                .map(|pin| pin.nets.iter().map(|&net| base + net).collect())
+                .collect();
+            let directions: Vec<_> = pins.iter().map(|pin| pin.direction).collect();
+            let owner = outer.map_or(id, |(owner, _)| owner);
+            let input = inputs.iter().position(|&(c, _)| c == id);
+            let output = outputs.iter().position(|&(c, _)| c == id);
+
+            // The ports of an inlined subcircuit are replaced by copies to
+            // and from the nets outside it
+            let copy = [PinDirection::Input, PinDirection::Output];
+            match (outer, input, output) {
+                (Some((_, ports)), Some(i), _) => {
+                    self.add_node(Op::Copy, owner, vec![ports[i].clone(), nets[0].clone()], &copy);
+                },
+                (Some((_, ports)), _, Some(i)) => {
+                    self.add_node(Op::Copy, owner, vec![nets[0].clone(), ports[inputs.len() + i].clone()], &copy);
+                },
+                _ => if let Some(inner) = info.subcircuit() {
+                    self.add_circuit(inner, Some((owner, &nets)));
+                } else if let Some(kernel) = info.kernel() {
+                    self.add_node(Op::Kernel(kernel), owner, nets.clone(), &directions);
+                } else if !info.switches().is_empty() {
+                    self.switches.insert(owner);
+                } else if !info.is_annotation() {
+                    let index = self.components.len();
+                    self.components.push(info.clone());
+                    if outer.is_none() {
+                        self.top.insert(id, index);
+                    }
+                    self.add_node(Op::Component(index), owner, nets.clone(), &directions);
+                },
+            }
+            result.insert(id, nets);
+        }
+        result
+    }
+    fn add_node(&mut self, op: Op, owner: ComponentId, pins: Vec<Vec<usize>>, directions: &[PinDirection]) {
+        let index = self.nodes.len();
+        let mut reads = BTreeSet::new();
+        let mut driven = BTreeSet::new();
+        let mut shared = BTreeSet::new();
+        let slots = pins.iter().zip(directions).map(|(nets, &direction)| {
+            if direction == PinDirection::Bidirectional {
+                shared.extend(nets.iter().cloned());
+            }
+            if direction.is_input() {
+                reads.extend(nets.iter().cloned());
+            }
+            if !direction.is_output() {
+                return Vec::new();
+            }
+            driven.extend(nets.iter().cloned());
+            nets.iter().map(|&net| {
+                let slot = self.slot_nodes.len();
+                self.slot_nodes.push(index);
+                self.drivers[net].push(slot);
+                slot
+            }).collect()
+        }).collect();
+        self.nodes.push(Node {
+            op,
+            owner,
+            pins,
+            slots,
+            driven: driven.into_iter().collect(),
+            reads: reads.into_iter().collect(),
+            shared: shared.into_iter().collect(),
+        });
+    }
+    // Orders the nodes so that each comes after every node driving a net it
+    // reads from.
+    fn sort(&mut self) -> Result<(), CompileError> {
+        let n = self.nodes.len();
+        let mut dependents = vec![Vec::new(); n];
+        let mut pending = vec![0; n];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let sources: BTreeSet<_> = node.reads.iter()
+                .filter(|net| !node.shared.contains(net))
+                .flat_map(|&net| &self.drivers[net])
+                .map(|&slot| self.slot_nodes[slot])
+                .chain(node.shared.iter()
+                    .flat_map(|&net| &self.drivers[net])
+                    .map(|&slot| self.slot_nodes[slot])
+                    .filter(|&source| source != i))
+                .collect();
+            pending[i] = sources.len();
+            for source in sources {
+                dependents[source].push(i);
+            }
+        }
+
+        let mut ready: Vec<_> = (0..n).filter(|&i| pending[i] == 0).rev().collect();
+        let mut order = Vec::with_capacity(n);
+        while let Some(i) = ready.pop() {
+            order.push(i);
+            for &j in &dependents[i] {
+                pending[j] -= 1;
+                if pending[j] == 0 {
+                    ready.push(j);
+                }
+            }
+        }
+        if order.len() < n {
+            let components: BTreeSet<_> = (0..n)
+                .filter(|&i| pending[i] > 0)
+                .map(|i| self.nodes[i].owner)
+                .collect();
+            return Err(CompileError::CombinationalLoop { components: components.into_iter().collect() });
+        }
+
+        let mut nodes: Vec<_> = self.nodes.drain(..).map(Some).collect();
+        self.nodes = order.into_iter().map(|i| nodes[i].take().expect("Each node is ordered once")).collect();
+        Ok(())
+    }
+}
+
+/// A circuit compiled for fast evaluation. Unlike a `Simulator`, there is
+/// no notion of time: each call to `run` settles the whole circuit at once.
+#[derive(Debug, Clone)]
+pub struct CompiledCircuit {
+    nodes: Vec<Node>,
+    components: Vec<ComponentInfo>,
+    // The components of the top-level circuit which are not inlined, and may
+    // be interacted with
+    top: HashMap<ComponentId, usize>,
+    pins: HashMap<ComponentId, Vec<Vec<usize>>>,
+    drivers: Vec<Vec<usize>>,
+    pulls: Vec<Option<Bit>>,
+    resolutions: Vec<Resolution>,
+    // The value driven by each output bit of each node
+    slots: Vec<Bit>,
+    // The value of each net
+    values: Vec<Bit>,
+}
+
+impl CompiledCircuit {
+    /// Compiles a circuit, and settles it with its initial inputs.
+    pub fn compile(circuit: &Circuit) -> Result<Self, CompileError> {
+        let mut builder = Builder::default();
+        let pins = builder.add_circuit(circuit, None);
+        if !builder.switches.is_empty() {
+            return Err(CompileError::Switches { components: builder.switches.into_iter().collect() });
+        }
+        builder.sort()?;
+
+        let values = builder.pulls.iter().map(|pull| pull.unwrap_or(Bit::HighZ)).collect();
+        let mut result = CompiledCircuit {
+            nodes: builder.nodes,
+            components: builder.components,
+            top: builder.top,
+            pins,
+            drivers: builder.drivers,
+            pulls: builder.pulls,
+            resolutions: builder.resolutions,
+            slots: vec![Bit::HighZ; builder.slot_nodes.len()],
+            values,
+        };
+        result.run();
+        Ok(result)
+    }
+    /// The number of components and kernels evaluated by each call to `run`.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// Evaluates every component once, in order.
+    pub fn run(&mut self) {
+        let CompiledCircuit { nodes, components, drivers, pulls, resolutions, slots, values, .. } = self;
+        for node in nodes.iter() {
+            match node.op {
+                Op::Kernel(ref kernel) => kernel.run(&node.pins, &node.slots, values, slots),
+                Op::Component(index) => {
+                    let inputs: Vec<_> = node.pins.iter()
+                        .map(|nets| Value::new(nets.iter().map(|&net| values[net]).collect()))
+                        .collect();
+                    let info = &mut components[index];
+                    info.clock_edge(&inputs);
+                    let result = info.evaluate(&inputs);
+                    for (pin, pin_slots) in node.slots.iter().enumerate() {
+                        for (bit, &slot) in pin_slots.iter().enumerate() {
+                            slots[slot] = result.get(pin).map_or(Bit::HighZ, |v| v.bit(bit as u32));
+                        }
+                    }
+                },
+                Op::Copy => {
+                    for (&net, &slot) in node.pins[0].iter().zip(&node.slots[1]) {
+                        slots[slot] = values[net];
+                    }
+                },
+            }
+            for &net in &node.driven {
+                let bit = drivers[net].iter().fold(Bit::HighZ, |acc, &slot| resolutions[net].resolve(acc, slots[slot]));
+                values[net] = if bit == Bit::HighZ { pulls[net].unwrap_or(Bit::HighZ) } else { bit };
+            }
+        }
+    }
+    /// As for `Simulator::interact`. Only components of the top-level
+    /// circuit can be interacted with, and the circuit is not settled until
+    /// the next call to `run`.
+    pub fn interact(&mut self, id: ComponentId, interaction: Interaction) -> bool {
+        match self.top.get(&id) {
+            Some(&index) => self.components[index].interact(interaction),
+            None => false,
+        }
+    }
+    /// As for `Simulator::display_value`, for components of the top-level
+    /// circuit.
+    pub fn display_value(&self, id: ComponentId) -> Option<Value> {
+        self.components[*self.top.get(&id)?].display_value()
+    }
+    /// The value on a pin of a component in the top-level circuit.
+    pub fn pin_value(&self, pin: PinRef) -> Option<Value> {
+        let nets = self.pins.get(&pin.component)?.get(pin.pin)?;
+        Some(Value::new(nets.iter().map(|&net| self.values[net]).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::libraries::default_library;
+    use crate::simulation::Simulator;
+    use crate::subcircuit::CircuitRegistry;
+
+    // A half adder, with its sum on the upper output and carry on the lower
+    fn half_adder(library: &crate::library::Library) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit.add_component(library.create("input_pin").unwrap(), -2, 0);
+        circuit.add_component(library.create("input_pin").unwrap(), -2, 2);
+        circuit.add_component(library.create("xor_gate").unwrap(), 0, 0);
+        circuit.add_component(library.create("and_gate").unwrap(), 0, 6);
+        circuit.add_wire(Wire::new((0, 1), (-1, 1)));
+        circuit.add_wire(Wire::new((-1, 1), (-1, 7)));
+        circuit.add_wire(Wire::new((-1, 7), (0, 7)));
+        circuit.add_wire(Wire::new((0, 3), (1, 3)));
+        circuit.add_wire(Wire::new((1, 3), (1, 9)));
+        circuit.add_wire(Wire::new((1, 9), (0, 9)));
+        circuit.add_component(library.create("output_pin").unwrap(), 4, 1);
+        circuit.add_component(library.create("output_pin").unwrap(), 4, 7);
+        circuit
+    }
+
+    #[test]
+    fn matches_simulator() {
+        let mut library = default_library();
+        let mut registry = CircuitRegistry::new();
+        registry.insert("half_adder", half_adder(&library)).unwrap();
+        registry.register(&mut library).unwrap();
+
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(library.create("input_pin").unwrap(), -2, 0);
+        let b = circuit.add_component(library.create("input_pin").unwrap(), -2, 2);
+        circuit.add_component(library.create("half_adder").unwrap(), 0, 0);
+        let not = circuit.add_component(library.create("not_gate").unwrap(), 4, 2);
+        let leds = [
+            circuit.add_component(library.create("led").unwrap(), 4, 0),
+            circuit.add_component(library.create("led").unwrap(), 7, 2),
+        ];
+
+        let mut compiled = CompiledCircuit::compile(&circuit).unwrap();
+        // The inlined gates, along with four copies joining them to the
+        // ports of the subcircuit
+        assert_eq!(compiled.len(), 11);
+        let mut sim = Simulator::new(circuit);
+        for row in 0..4 {
+            for &(id, value) in &[(a, row & 1), (b, row >> 1)] {
+                assert!(compiled.interact(id, Interaction::Set(Value::from_u64(value, 1))));
+                sim.interact(id, Interaction::Set(Value::from_u64(value, 1)));
+            }
+            compiled.run();
+            sim.run_until_stable().unwrap();
+            for &led in &leds {
+                assert_eq!(compiled.display_value(led), sim.display_value(led), "row {}", row);
+            }
+            let out = PinRef { component: not, pin: 1 };
+            assert_eq!(compiled.pin_value(out), sim.pin_value(out));
+        }
+        assert!(!compiled.interact(not, Interaction::Press));
+    }
+
+    #[test]
+    fn rejects_loops() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let not = circuit.add_component(library.create("not_gate").unwrap(), 0, 0);
+        circuit.add_wire(Wire::new((3, 1), (3, 3)));
+        circuit.add_wire(Wire::new((3, 3), (0, 3)));
+        circuit.add_wire(Wire::new((0, 3), (0, 1)));
+        let error = CompiledCircuit::compile(&circuit).unwrap_err();
+        assert_eq!(error, CompileError::CombinationalLoop { components: vec![not] });
+    }
+}
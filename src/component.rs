@@ -1,32 +1,131 @@
 use std::collections::BTreeMap;
+use std::cell::RefCell;
 use std::fmt;
 use std::any::Any;
 use std::error::Error;
 use std::mem;
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use serde_derive::{Serialize, Deserialize};
 use serde_json;
 use serde::Serialize;
+use serde::de::DeserializeOwned;
+use regex::Regex;
 
 use crate::library::ComponentMetadata;
+use crate::bits::{BitString, MemoryContents};
 
-#[derive(Serialize, Debug, Clone)]
+pub use tenorite_ui_derive::Component;
+use crate::simulation::{Bit, Value, Resolution, Simulator};
+use crate::export::verilog::HdlEmit;
+use crate::render::{DrawContext, Framebuffer};
+use crate::trace::Capture;
+use crate::audio::AudioTrack;
+use crate::compiled::Kernel;
+use crate::circuit::Circuit;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum FieldType {
+    Boolean,
     Text {
         min_len: u32,
         max_len: u32,
     },
+    /// Free-form text. If a pattern is given, the whole value must match it.
+    String {
+        pattern: Option<String>,
+        placeholder: Option<String>,
+        multiline: bool,
+    },
+    /// A whole number. If a unit is given, editors should display the value
+    /// in that unit.
     Integer {
-        min: u32,
-        max: u32,
+        min: i64,
+        max: i64,
+        unit: Option<Unit>,
+    },
+    /// A finite number. If a step is given, the value must be a whole number
+    /// of steps above `min`.
+    Float {
+        min: f64,
+        max: f64,
+        step: Option<f64>,
+        unit: Option<Unit>,
     },
     Enum {
         options: Vec<String>
+    },
+    /// Bulk memory contents, as whitespace separated hexadecimal words.
+    /// A run of identical words may be written as `count*word`. See
+    /// `MemoryContents`.
+    HexContents {
+        address_bits: u32,
+        data_bits: u32,
+    },
+    /// A `BitString` of the given width, edited as text which
+    /// `BitString::parse` accepts.
+    BitVector {
+        bits: u32,
+    },
+    /// A list of values of the same type. The whole list is set at once, or
+    /// single elements with `ComponentInfo::set_list_element`.
+    List {
+        element: Box<FieldType>,
+        min_len: u32,
+        max_len: u32,
+    },
+    /// A `Color`. If `alpha` is false, the color must be fully opaque.
+    Color {
+        alpha: bool,
+    },
+    /// An object with a fixed set of fields. A list of records is edited as
+    /// a table, with a column for each field.
+    Record {
+        fields: Vec<RecordField>,
+    },
+}
+
+/// A field of a `FieldType::Record`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordField {
+    pub id: Cow<'static, str>,
+    pub name: Cow<'static, str>,
+    pub type_: FieldType,
+}
+
+impl RecordField {
+    pub fn new<I, S>(id: I, name: S, type_: FieldType) -> Self
+    where
+        I: Into<Cow<'static, str>>,
+        S: Into<Cow<'static, str>>,
+    {
+        RecordField { id: id.into(), name: name.into(), type_ }
     }
 }
 
+thread_local! {
+    // The compiled patterns of string fields. Fields come from a fixed set
+    // of schemas, so this only grows as far as the patterns they use.
+    static PATTERNS: RefCell<BTreeMap<String, Regex>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Whether the whole of `text` matches a pattern, compiling the pattern the
+// first time it is used
+fn matches_pattern(pattern: &str, text: &str) -> Result<bool, String> {
+    PATTERNS.with(|patterns| {
+        let mut patterns = patterns.borrow_mut();
+        if !patterns.contains_key(pattern) {
+            // Anchor the pattern so that it must match the whole value
+            let regex = Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| format!("Invalid pattern: {}", e))?;
+            patterns.insert(pattern.into(), regex);
+        }
+        Ok(patterns[pattern].is_match(text))
+    })
+}
+
 impl FieldType {
     pub fn for_enum<T: Serialize>(variants: &[T]) -> Self {
         let options = variants
@@ -38,22 +137,363 @@ impl FieldType {
             .collect();
         FieldType::Enum { options }
     }
+    /// Narrows the bounds of a numeric field.
+    pub fn with_range(self, min: Option<i64>, max: Option<i64>) -> Self {
+        match self {
+            FieldType::Integer { min: old_min, max: old_max, unit } => FieldType::Integer {
+                min: min.unwrap_or(old_min),
+                max: max.unwrap_or(old_max),
+                unit,
+            },
+            FieldType::Float { min: old_min, max: old_max, step, unit } => FieldType::Float {
+                min: min.map_or(old_min, |v| v as f64),
+                max: max.map_or(old_max, |v| v as f64),
+                step,
+                unit,
+            },
+            _ => panic!("Only numeric fields have a range"),
+        }
+    }
+    /// An integer field with the given bounds.
+    pub fn integer(min: i64, max: i64) -> Self {
+        FieldType::Integer { min, max, unit: None }
+    }
+    /// A float field with the given bounds.
+    pub fn float(min: f64, max: f64) -> Self {
+        FieldType::Float { min, max, step: None, unit: None }
+    }
+    /// Restricts a float field to multiples of `step` above its minimum.
+    pub fn with_step(self, step: f64) -> Self {
+        match self {
+            FieldType::Float { min, max, unit, .. } => FieldType::Float { min, max, step: Some(step), unit },
+            _ => panic!("Only float fields have a step"),
+        }
+    }
+    /// Sets the unit a numeric field is displayed in.
+    pub fn with_unit(self, unit: Unit) -> Self {
+        match self {
+            FieldType::Integer { min, max, .. } => FieldType::Integer { min, max, unit: Some(unit) },
+            FieldType::Float { min, max, step, .. } => FieldType::Float { min, max, step, unit: Some(unit) },
+            _ => panic!("Only numeric fields have a unit"),
+        }
+    }
+    pub fn string() -> Self {
+        FieldType::String {
+            pattern: None,
+            placeholder: None,
+            multiline: false,
+        }
+    }
+    pub fn with_pattern<S: Into<String>>(self, pattern: S) -> Self {
+        match self {
+            FieldType::String { placeholder, multiline, .. } => FieldType::String {
+                pattern: Some(pattern.into()),
+                placeholder,
+                multiline,
+            },
+            _ => panic!("Only string fields have a pattern"),
+        }
+    }
+    pub fn with_placeholder<S: Into<String>>(self, placeholder: S) -> Self {
+        match self {
+            FieldType::String { pattern, multiline, .. } => FieldType::String {
+                pattern,
+                placeholder: Some(placeholder.into()),
+                multiline,
+            },
+            _ => panic!("Only string fields have a placeholder"),
+        }
+    }
+    pub fn multiline(self) -> Self {
+        match self {
+            FieldType::String { pattern, placeholder, .. } => FieldType::String {
+                pattern,
+                placeholder,
+                multiline: true,
+            },
+            _ => panic!("Only string fields can be multiline"),
+        }
+    }
+    pub fn list(element: FieldType, min_len: u32, max_len: u32) -> Self {
+        FieldType::List { element: Box::new(element), min_len, max_len }
+    }
+    pub fn record(fields: Vec<RecordField>) -> Self {
+        FieldType::Record { fields }
+    }
+    /// A list of records, edited as a table with a row for each element.
+    pub fn table(columns: Vec<RecordField>, min_rows: u32, max_rows: u32) -> Self {
+        Self::list(Self::record(columns), min_rows, max_rows)
+    }
+    /// An opaque color field.
+    pub fn color() -> Self {
+        FieldType::Color { alpha: false }
+    }
+    /// Allows a color field to be translucent.
+    pub fn with_alpha(self) -> Self {
+        match self {
+            FieldType::Color { .. } => FieldType::Color { alpha: true },
+            _ => panic!("Only color fields have an alpha channel"),
+        }
+    }
+    /// Checks the constraints of the field type which can be verified without
+    /// knowing anything about the component. Returns an explanation if the
+    /// value is invalid.
+    pub fn validate(&self, value: &serde_json::Value) -> Result<(), String> {
+        match self {
+            FieldType::Boolean => {
+                value.as_bool().ok_or("Expected a boolean")?;
+            },
+            FieldType::Integer { min, max, .. } => {
+                let v = value.as_i64().ok_or("Expected an integer")?;
+                if v < *min || v > *max {
+                    return Err(format!("Must be between {} and {}", min, max));
+                }
+            },
+            FieldType::Float { min, max, step, .. } => {
+                let v = value.as_f64().filter(|v| v.is_finite()).ok_or("Expected a number")?;
+                if v < *min || v > *max {
+                    return Err(format!("Must be between {} and {}", min, max));
+                }
+                if let Some(step) = step {
+                    // Allow for rounding errors in values such as 0.1 + 0.2
+                    let steps = (v - min) / step;
+                    if (steps - steps.round()).abs() > 1e-6 {
+                        return Err(format!("Must be a multiple of {} above {}", step, min));
+                    }
+                }
+            },
+            FieldType::Enum { options } => {
+                let option = value.as_str().ok_or("Expected a string")?;
+                if !options.iter().any(|o| o == option) {
+                    return Err(format!("Must be one of: {}", options.join(", ")));
+                }
+            },
+            FieldType::HexContents { address_bits, data_bits } => {
+                MemoryContents::from_value(value)?.check(*address_bits, *data_bits)?;
+            },
+            FieldType::BitVector { bits } => {
+                let text = value.as_str().ok_or("Expected a string")?;
+                BitString::parse(text, *bits)?;
+            },
+            FieldType::List { element, min_len, max_len } => {
+                let items = value.as_array().ok_or("Expected a list")?;
+                let len = items.len() as u32;
+                if len < *min_len || len > *max_len {
+                    return Err(if min_len == max_len {
+                        format!("Must have {} elements", min_len)
+                    } else {
+                        format!("Must have between {} and {} elements", min_len, max_len)
+                    });
+                }
+                for (i, item) in items.iter().enumerate() {
+                    element.validate(item).map_err(|e| format!("Element {}: {}", i, e))?;
+                }
+            },
+            FieldType::Color { alpha } => {
+                let color: Color = serde_json::from_value(value.clone())
+                    .map_err(|_| "Expected a color with `r`, `g`, `b` and optionally `a` components")?;
+                if !alpha && !color.is_opaque() {
+                    return Err("Must be fully opaque".into());
+                }
+            },
+            FieldType::Record { fields } => {
+                let object = value.as_object().ok_or("Expected an object")?;
+                if let Some(key) = object.keys().find(|&key| !fields.iter().any(|f| f.id == *key)) {
+                    return Err(format!("Unknown field `{}`", key));
+                }
+                for field in fields {
+                    let item = object.get(&*field.id).ok_or_else(|| format!("Missing field `{}`", field.id))?;
+                    field.type_.validate(item).map_err(|e| format!("{}: {}", field.name, e))?;
+                }
+            },
+            FieldType::Text { min_len, max_len } => {
+                let text = value.as_str().ok_or("Expected a string")?;
+                let len = text.chars().count() as u32;
+                if len < *min_len || len > *max_len {
+                    return Err(format!("Length must be between {} and {}", min_len, max_len));
+                }
+            },
+            FieldType::String { pattern, multiline, .. } => {
+                let text = value.as_str().ok_or("Expected a string")?;
+                if !multiline && text.contains('\n') {
+                    return Err("Must be a single line".into());
+                }
+                if let Some(pattern) = pattern {
+                    if !matches_pattern(pattern, text)? {
+                        return Err(format!("Must match the pattern `{}`", pattern));
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
 }
 
 pub trait ReflectType {
     fn field_type() -> FieldType;
 }
 
-#[derive(Serialize, Debug, Clone)]
+impl ReflectType for bool {
+    fn field_type() -> FieldType {
+        FieldType::Boolean
+    }
+}
+
+macro_rules! reflect_integer {
+    ($($t:ty),*) => {
+        $(
+            impl ReflectType for $t {
+                fn field_type() -> FieldType {
+                    // Unsigned 64-bit types are limited to the positive range of an `i64`
+                    FieldType::integer(
+                        <$t>::min_value() as i64,
+                        (<$t>::max_value() as u64).min(i64::max_value() as u64) as i64,
+                    )
+                }
+            }
+        )*
+    };
+}
+
+reflect_integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl ReflectType for f32 {
+    fn field_type() -> FieldType {
+        FieldType::float(std::f32::MIN as f64, std::f32::MAX as f64)
+    }
+}
+
+impl ReflectType for f64 {
+    fn field_type() -> FieldType {
+        FieldType::float(std::f64::MIN, std::f64::MAX)
+    }
+}
+
+impl ReflectType for String {
+    fn field_type() -> FieldType {
+        FieldType::string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FieldSchema {
+    #[serde(default)]
     pub read_only: bool,
     pub type_: FieldType,
     pub name: Cow<'static, str>,
     pub description: Option<Cow<'static, str>>,
+    /// The heading the field is shown under in a property panel. Ungrouped
+    /// fields are shown first.
+    pub group: Option<Cow<'static, str>>,
+    /// Fields are shown in ascending order, and then by id.
+    #[serde(default)]
+    pub order: i32,
+    /// Other properties whose values affect this field, such as the number
+    /// of inputs determining the length of a list. Panels should refresh the
+    /// field when any of them change.
+    #[serde(default)]
+    pub depends_on: Vec<Cow<'static, str>>,
+    /// The field is only shown when this condition holds.
+    pub visible_if: Option<Condition>,
+    /// The value the field is reset to. For components created from a
+    /// library, `ComponentInfo::schema` fills this in from the initial value
+    /// of the property where one is not given.
+    pub default: Option<serde_json::Value>,
+    /// The key of the messages which translate the name, description and
+    /// options. Defaults to `property-` followed by the name of the
+    /// property. See `i18n`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_key: Option<Cow<'static, str>>,
+}
+
+/// A condition on the value of another property.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub property: Cow<'static, str>,
+    pub equals: serde_json::Value,
+}
+
+impl FieldSchema {
+    pub fn new<S: Into<Cow<'static, str>>>(name: S, type_: FieldType) -> Self {
+        FieldSchema {
+            read_only: false,
+            type_,
+            name: name.into(),
+            description: None,
+            group: None,
+            order: 0,
+            depends_on: Vec::new(),
+            visible_if: None,
+            default: None,
+            message_key: None,
+        }
+    }
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+    pub fn with_description<S: Into<Cow<'static, str>>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+    pub fn with_group<S: Into<Cow<'static, str>>>(mut self, group: S) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+    pub fn with_message_key<S: Into<Cow<'static, str>>>(mut self, key: S) -> Self {
+        self.message_key = Some(key.into());
+        self
+    }
+    pub fn with_order(mut self, order: i32) -> Self {
+        self.order = order;
+        self
+    }
+    pub fn with_default<V: Into<serde_json::Value>>(mut self, default: V) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+    pub fn depends_on<S: Into<Cow<'static, str>>>(mut self, property: S) -> Self {
+        self.depends_on.push(property.into());
+        self
+    }
+    /// Only shows the field when another property has the given value. The
+    /// field also depends on that property.
+    pub fn visible_if<S: Into<Cow<'static, str>>>(mut self, property: S, equals: serde_json::Value) -> Self {
+        let property = property.into();
+        if !self.depends_on.contains(&property) {
+            self.depends_on.push(property.clone());
+        }
+        self.visible_if = Some(Condition { property, equals });
+        self
+    }
 }
 
 pub type Schema = BTreeMap<Cow<'static, str>, FieldSchema>;
 
+/// The fields of a schema with the same group, in display order.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldGroup<'a> {
+    pub name: Option<&'a str>,
+    pub fields: Vec<(&'a str, &'a FieldSchema)>,
+}
+
+/// Arranges the fields of a schema for display in a property panel. The
+/// ungrouped fields come first, followed by each group in the order of its
+/// first field.
+pub fn field_groups(schema: &Schema) -> Vec<FieldGroup> {
+    let mut fields: Vec<_> = schema.iter().map(|(id, field)| (&**id, field)).collect();
+    fields.sort_by_key(|&(id, field)| (field.group.is_some(), field.order, id));
+    let mut groups: Vec<FieldGroup> = Vec::new();
+    for (id, field) in fields {
+        let name = field.group.as_ref().map(|g| &**g);
+        match groups.iter_mut().find(|g| g.name == name) {
+            Some(group) => group.fields.push((id, field)),
+            None => groups.push(FieldGroup { name, fields: vec![(id, field)] }),
+        }
+    }
+    groups
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub enum PropertyErrorReason {
     UnknownProperty,
@@ -63,6 +503,20 @@ pub enum PropertyErrorReason {
     },
 }
 
+/// Describes a successful change to a property, so that views of the
+/// component can be updated.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PropertyChanged {
+    pub name: String,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: serde_json::Value,
+    /// Some properties affect which other properties are available, such as
+    /// the number of inputs of a gate.
+    pub schema_changed: bool,
+    /// The properties which declare that they depend on this one.
+    pub dependents: Vec<String>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct PropertyError {
     pub name: String,
@@ -93,6 +547,14 @@ impl PropertyError {
             }
         }
     }
+    pub fn invalid<S: Into<String>>(name: &str, explanation: S) -> PropertyError {
+        PropertyError {
+            name: name.into(),
+            reason: PropertyErrorReason::InvalidValue {
+                explanation: explanation.into()
+            }
+        }
+    }
     pub fn unknown(name: &str) -> PropertyError {
         PropertyError {
             name: name.into(),
@@ -107,15 +569,49 @@ impl PropertyError {
     }
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PinDirection {
+    Input,
+    Output,
+    Bidirectional,
+    /// An output which can release the net by driving high-impedance, so
+    /// that several may share a net as long as only one is enabled.
+    TriState,
+}
+
+impl PinDirection {
+    pub fn is_input(self) -> bool {
+        self == PinDirection::Input || self == PinDirection::Bidirectional
+    }
+    pub fn is_output(self) -> bool {
+        self != PinDirection::Input
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Pin {
     pub x: i32,
     pub y: i32,
     pub name: String,
     pub bits: u32,
+    pub direction: PinDirection,
 }
 
-#[derive(Serialize, Debug, Clone)]
+/// Identifies a single bit of one of a component's pins, by the index of the
+/// pin within its shape.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PinBit {
+    pub pin: usize,
+    pub bit: u32,
+}
+
+impl PinBit {
+    pub fn new(pin: usize, bit: u32) -> Self {
+        Self { pin, bit }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Shape {
     pub width: i32,
     pub height: i32,
@@ -123,12 +619,90 @@ pub struct Shape {
     pub image_name: Cow<'static, str>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+/// A side of a component's bounding box.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Left, Right, Top, Bottom
+}
+
+impl Shape {
+    /// The edge of the shape nearest to a point, such as the position of one
+    /// of its pins. Points at a corner are considered to be on the left or
+    /// right edge.
+    pub fn edge_at(&self, (x, y): (i32, i32)) -> Edge {
+        let distances = [
+            (x, Edge::Left),
+            (self.width - x, Edge::Right),
+            (y, Edge::Top),
+            (self.height - y, Edge::Bottom),
+        ];
+        distances.iter().min_by_key(|&&(d, _)| d.abs()).map(|&(_, edge)| edge).expect("Distances are non-empty")
+    }
+}
+
+/// A pin of a component placed in a circuit.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlacedPin {
+    /// The index of the pin within the component's shape.
+    pub index: usize,
+    pub name: String,
+    pub bits: u32,
+    pub direction: PinDirection,
+    /// The position of the pin relative to the component, before it was
+    /// flipped or rotated.
+    pub local: (i32, i32),
+    /// The position of the pin in the circuit.
+    pub world: (i32, i32),
+    /// The side of the component the pin faces, after it was flipped and
+    /// rotated.
+    pub edge: Edge,
+}
+
+/// An axis aligned rectangle in grid units.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    /// Returns true if the point lies within the rectangle or on its edge.
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.x && x <= self.x + self.width
+            && y >= self.y && y <= self.y + self.height
+    }
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x <= other.x + other.width && other.x <= self.x + self.width
+            && self.y <= other.y + other.height && other.y <= self.y + self.height
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Orientation {
     North, East, South, West
 }
 
 impl Orientation {
+    /// The orientation after a quarter turn clockwise.
+    pub fn rotate_clockwise(self) -> Self {
+        match self {
+            Orientation::North => Orientation::East,
+            Orientation::East => Orientation::South,
+            Orientation::South => Orientation::West,
+            Orientation::West => Orientation::North,
+        }
+    }
+    /// The orientation which, combined with toggling whether a component is
+    /// flipped, mirrors the component from left to right.
+    pub fn mirrored(self) -> Self {
+        match self {
+            Orientation::East => Orientation::West,
+            Orientation::West => Orientation::East,
+            other => other,
+        }
+    }
     fn map_point(&self, x: i32, y: i32, w: i32, h: i32) -> (i32, i32) {
         match self {
             Orientation::North => (x, y),
@@ -137,7 +711,13 @@ impl Orientation {
             Orientation::West => (y, h - x),
         }
     }
-    fn map_shape(&self, mut shape: Shape) -> Shape {
+    // Components are mirrored horizontally, if flipped, before being rotated
+    fn map_shape(&self, mut shape: Shape, flipped: bool) -> Shape {
+        if flipped {
+            for pin in &mut shape.pins {
+                pin.x = shape.width - pin.x;
+            }
+        }
         match self {
             Orientation::North | Orientation::South => {},
             Orientation::East | Orientation::West => mem::swap(&mut shape.width, &mut shape.height),
@@ -160,11 +740,341 @@ impl ReflectType for Orientation {
     }
 }
 
-pub trait Component: Any + fmt::Debug + CloneComponent {
+/// Where a component's label is drawn, relative to its bounding box.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LabelPosition {
+    Above, Below, Left, Right
+}
+
+impl ReflectType for LabelPosition {
+    fn field_type() -> FieldType {
+        use self::LabelPosition::*;
+        FieldType::for_enum(&[Above, Below, Left, Right])
+    }
+}
+
+const SI_PREFIXES: &[(i32, &str)] = &[
+    (-12, "p"), (-9, "n"), (-6, "\u{b5}"), (-3, "m"), (0, ""), (3, "k"), (6, "M"), (9, "G"), (12, "T"),
+];
+
+// Formats a number with at most three decimal places and no trailing zeros
+fn format_number(v: f64) -> String {
+    let rounded = (v * 1000.0).round() / 1000.0;
+    if rounded == 0.0 { "0".into() } else { rounded.to_string() }
+}
+
+/// The unit of a numeric field, so that editors can display `100 MHz`
+/// rather than a bare number.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub symbol: Cow<'static, str>,
+    /// The size of one stored unit in terms of `symbol`. A delay stored in
+    /// nanoseconds has the symbol `s` and a scale of `1e-9`.
+    pub scale: f64,
+    /// Whether SI prefixes such as `k` and `M` are used for large and small
+    /// values.
+    pub prefixed: bool,
+}
+
+impl Unit {
+    /// A unit displayed without prefixes, such as `ticks`.
+    pub fn new(symbol: impl Into<Cow<'static, str>>) -> Self {
+        Unit { symbol: symbol.into(), scale: 1.0, prefixed: false }
+    }
+    /// A unit displayed with SI prefixes, such as `Hz`.
+    pub fn si(symbol: impl Into<Cow<'static, str>>) -> Self {
+        Unit { symbol: symbol.into(), scale: 1.0, prefixed: true }
+    }
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+    /// Formats a stored value for display, such as `2.5 kHz`.
+    pub fn format(&self, value: f64) -> String {
+        let v = value * self.scale;
+        let (exponent, prefix) = if self.prefixed && v != 0.0 {
+            let exponent = ((v.abs().log10() / 3.0).floor() as i32 * 3).max(-12).min(12);
+            SI_PREFIXES.iter().cloned().find(|&(e, _)| e == exponent).unwrap_or((0, ""))
+        } else {
+            (0, "")
+        };
+        format!("{} {}{}", format_number(v / 10f64.powi(exponent)), prefix, self.symbol)
+    }
+    /// Parses text such as `100 MHz` or `100M` back into a stored value. The
+    /// symbol may be omitted.
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        let text = text.trim();
+        let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(text.len());
+        let v: f64 = text[..split].trim().parse().ok()?;
+        let suffix = text[split..].trim();
+        let prefix = suffix.strip_suffix(&*self.symbol).unwrap_or(suffix).trim_end();
+        let exponent = if prefix.is_empty() {
+            0
+        } else if self.prefixed {
+            // `u` is accepted for micro, as it is easier to type
+            let prefix = if prefix == "u" { "\u{b5}" } else { prefix };
+            SI_PREFIXES.iter().find(|&&(_, p)| p == prefix)?.0
+        } else {
+            return None;
+        };
+        Some(v * 10f64.powi(exponent) / self.scale)
+    }
+}
+
+fn opaque() -> u8 {
+    255
+}
+
+/// An RGBA color, such as the color of an LED. The alpha component may be
+/// omitted when deserializing, in which case the color is opaque.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    #[serde(default = "opaque")]
+    pub a: u8,
+}
+
+impl Color {
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+    pub fn is_opaque(self) -> bool {
+        self.a == 255
+    }
+    /// Parses a CSS style hex color: `#rgb`, `#rrggbb` or `#rrggbbaa`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let digits = s.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let byte = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).ok();
+        match digits.len() {
+            3 => {
+                let nibble = |i: usize| u8::from_str_radix(&digits[i..i + 1], 16).ok().map(|v| v * 17);
+                Some(Color::rgb(nibble(0)?, nibble(1)?, nibble(2)?))
+            },
+            6 => Some(Color::rgb(byte(0)?, byte(2)?, byte(4)?)),
+            8 => Some(Color::rgba(byte(0)?, byte(2)?, byte(4)?, byte(6)?)),
+            _ => None,
+        }
+    }
+    /// Formats the color as `#rrggbb`, or `#rrggbbaa` if it is translucent.
+    pub fn to_hex(self) -> String {
+        if self.is_opaque() {
+            format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+        }
+    }
+    /// Scales the brightness of the color, such as to draw an unlit LED.
+    pub fn scaled(self, factor: f64) -> Self {
+        let scale = |c: u8| (c as f64 * factor).round().max(0.0).min(255.0) as u8;
+        Color { r: scale(self.r), g: scale(self.g), b: scale(self.b), a: self.a }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl ReflectType for Color {
+    fn field_type() -> FieldType {
+        FieldType::color().with_alpha()
+    }
+}
+
+/// A user interaction with a component in a running simulation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Interaction {
+    /// The component was pressed, such as by a mouse click.
+    Press,
+    /// The component was released after being pressed.
+    Release,
+    /// A new value was entered directly.
+    Set(Value),
+    /// A key was typed while the component had focus.
+    Key(char),
+    /// One part of the component, such as a switch in a bank of switches,
+    /// was toggled.
+    Toggle(u32),
+    /// A control such as a joystick was moved to a position, given as
+    /// offsets from its centre.
+    Move { x: i64, y: i64 },
+}
+
+/// The configurable properties of a component. This can be derived from the
+/// fields of a struct with `#[derive(Component)]`.
+pub trait Properties {
     fn schema(&self) -> Schema;
     fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError>;
     fn get_property(&self, name: &str) -> Option<serde_json::Value>;
+}
+
+/// Helper for implementing `Properties::set_property` for a single field.
+pub fn set_field<T: DeserializeOwned>(field: &mut T, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+    *field = serde_json::from_value(value)
+        .map_err(|e| PropertyError::from_serde(e, name))?;
+    Ok(())
+}
+
+/// Helper for implementing `Properties::get_property` for a single field.
+pub fn get_field<T: Serialize>(field: &T) -> Option<serde_json::Value> {
+    serde_json::to_value(field).ok()
+}
+
+/// The value on each pin of a component, in the same order as the pins
+/// returned by `get_shape`.
+pub type PinValues = Vec<Value>;
+
+pub trait Component: Properties + Any + Send + Sync + fmt::Debug + CloneComponent {
     fn get_shape(&self) -> Shape;
+    /// The combinational half of a component. Computes the value driven onto
+    /// each pin given the current value on each pin, without changing any
+    /// state. Pins which the component does not drive should be left
+    /// floating.
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        Vec::new()
+    }
+    /// The sequential half of a component, called by the simulator before
+    /// each evaluation with the current value on each pin. Components with
+    /// stored state detect a triggering edge on their clock input here and
+    /// update the state which `evaluate` reads. Inputs which act without
+    /// waiting for an edge, such as an asynchronous reset, are handled here
+    /// too, as is recording the inputs of a display.
+    fn clock_edge(&mut self, _inputs: &PinValues) {}
+    /// The propagation delay of the component: how long a change to its
+    /// inputs takes to reach its outputs, in the simulator's units of time.
+    /// Giving components on different paths different delays lets the
+    /// simulation show the glitches this causes. Delays of zero are treated
+    /// as one.
+    fn delay(&self) -> u64 {
+        1
+    }
+    /// Handles a user interaction. Returns true if the interaction was
+    /// handled, in which case the simulation should be stepped again.
+    fn interact(&mut self, _interaction: Interaction) -> bool {
+        false
+    }
+    /// The value currently shown by an output component such as an LED or
+    /// display, as of its last update.
+    fn display_value(&self) -> Option<Value> {
+        None
+    }
+    /// The samples recorded by a logic analyzer, for the UI to plot.
+    fn capture(&self) -> Option<&Capture> {
+        None
+    }
+    /// The tones played by a component such as a buzzer.
+    fn audio(&self) -> Option<&AudioTrack> {
+        None
+    }
+    /// The pixels shown by a display such as an LED matrix, as of its last
+    /// update.
+    fn framebuffer(&self) -> Option<Framebuffer> {
+        None
+    }
+    /// The text shown by a terminal, as of its last update, with lines
+    /// separated by `\n`.
+    fn terminal_text(&self) -> Option<String> {
+        None
+    }
+    /// Called by the simulator each time the simulation clock advances by one
+    /// tick. Returns true if the component's outputs may have changed.
+    fn tick(&mut self) -> bool {
+        false
+    }
+    /// The runtime state of a sequential component, such as the value held
+    /// by a register, which is not captured by its properties. Components
+    /// with no such state return `None`.
+    fn state(&self) -> Option<serde_json::Value> {
+        None
+    }
+    /// Restores runtime state previously returned by `state`.
+    fn set_state(&mut self, _state: serde_json::Value) -> Result<(), Box<Error>> {
+        Ok(())
+    }
+
+    // The following hooks describe components which affect how nets are
+    // formed rather than driving values onto them.
+
+    /// Pairs of pin bits which are directly connected through this component,
+    /// as with a splitter.
+    fn connections(&self) -> Vec<(PinBit, PinBit)> {
+        Vec::new()
+    }
+    /// Pairs of pin bits which the component connects only while its inputs
+    /// allow, as with a transistor. Nets joined by a conducting switch share
+    /// a single value, whichever side drives it.
+    fn switches(&self) -> Vec<(PinBit, PinBit)> {
+        Vec::new()
+    }
+    /// Whether each of the `switches` currently conducts, given the value on
+    /// each pin.
+    fn conducting(&self, _inputs: &[Value]) -> Vec<bool> {
+        Vec::new()
+    }
+    /// All pins of components sharing the same tunnel label are connected.
+    fn tunnel_label(&self) -> Option<String> {
+        None
+    }
+    /// Floating bits on nets attached to this component are pulled to the
+    /// returned value.
+    fn pull(&self) -> Option<Bit> {
+        None
+    }
+    /// Nets attached to this component resolve conflicting drivers with the
+    /// returned policy, rather than the simulator's default.
+    fn resolution(&self) -> Option<Resolution> {
+        None
+    }
+    /// Problems with the pin widths the component has been configured with,
+    /// such as an extender which is narrower than its input. These are
+    /// reported by the design rule checker rather than rejected by
+    /// `set_property`, since related properties may be set in any order.
+    fn width_errors(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// The Verilog implementation of the component, if it has one.
+    fn hdl(&self) -> Option<&HdlEmit> {
+        None
+    }
+    /// A simple combinational function which the compiled simulator can
+    /// evaluate directly, in place of calling `evaluate`.
+    fn kernel(&self) -> Option<Kernel> {
+        None
+    }
+    /// The circuit a subcircuit component simulates, which the compiled
+    /// simulator inlines into the outer circuit.
+    fn subcircuit(&self) -> Option<&Circuit> {
+        None
+    }
+    /// The simulation of the inner circuit of a subcircuit instance, through
+    /// which signals inside the instance are probed.
+    fn simulator(&self) -> Option<&Simulator> {
+        None
+    }
+    /// Annotations such as text labels have no electrical function. Any pins
+    /// they declare are ignored when building nets, and they are never ticked
+    /// by the simulator.
+    fn is_annotation(&self) -> bool {
+        false
+    }
+    /// Draws the component in its own coordinate space, before rotation,
+    /// with the origin at its top-left corner. Components which draw nothing
+    /// are shown using their image instead.
+    fn draw(&self, _ctx: &mut DrawContext) {}
+    /// Draws a diagram of the component's behaviour to be shown separately
+    /// from the circuit, such as the states of a state machine. Components
+    /// which draw nothing have no diagram.
+    fn draw_diagram(&self, _ctx: &mut DrawContext) {}
 }
 
 pub trait AnyComponent: Component {
@@ -187,52 +1097,733 @@ impl<T: Clone + Component> CloneComponent for T {
     }
 }
 
+/// Identifies a component instance for the lifetime of the process. Unlike a
+/// `ComponentId`, this does not depend on which circuit the component is in.
+/// Clones share the id of the original.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstanceId(pub u64);
+
+impl InstanceId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        InstanceId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "i{}", self.0)
+    }
+}
+
+#[derive(Debug)]
 pub struct ComponentInfo {
+    instance_id: InstanceId,
     component: Box<AnyComponent>,
     orientation: Orientation,
+    flipped: bool,
+    label: String,
+    label_position: LabelPosition,
     x: i32,
     y: i32,
     metadata: Arc<ComponentMetadata>,
+    schema_revision: u64,
+    defaults: Arc<BTreeMap<String, serde_json::Value>>,
+}
+
+impl Clone for ComponentInfo {
+    fn clone(&self) -> Self {
+        Self {
+            instance_id: self.instance_id,
+            component: self.component.clone_component(),
+            orientation: self.orientation,
+            flipped: self.flipped,
+            label: self.label.clone(),
+            label_position: self.label_position,
+            x: self.x,
+            y: self.y,
+            metadata: self.metadata.clone(),
+            schema_revision: self.schema_revision,
+            defaults: self.defaults.clone(),
+        }
+    }
 }
 
 impl ComponentInfo {
     const ORIENTATION: &'static str = "orientation";
+    const FLIPPED: &'static str = "flipped";
+    const LABEL: &'static str = "label";
+    const LABEL_POSITION: &'static str = "label_position";
+    /// The group of the properties shared by every component.
+    pub const PLACEMENT_GROUP: &'static str = "Placement";
+    /// The group of the label properties shared by every component.
+    pub const LABEL_GROUP: &'static str = "Label";
     pub(crate) fn new(component: Box<AnyComponent>, metadata: Arc<ComponentMetadata>) -> Self {
         Self {
+            instance_id: InstanceId::next(),
             component,
             orientation: Orientation::North,
+            flipped: false,
+            label: String::new(),
+            label_position: LabelPosition::Above,
             x: 0,
             y: 0,
-            metadata
+            metadata,
+            schema_revision: 0,
+            defaults: Arc::default(),
         }
     }
+    // The current value of each writable property of the component itself
+    pub(crate) fn initial_properties(&self) -> BTreeMap<String, serde_json::Value> {
+        self.component.schema().into_iter()
+            .filter(|(_, field)| !field.read_only)
+            .filter_map(|(name, _)| Some((name.to_string(), self.component.get_property(&name)?)))
+            .collect()
+    }
+    pub(crate) fn set_defaults(&mut self, defaults: Arc<BTreeMap<String, serde_json::Value>>) {
+        self.defaults = defaults;
+    }
+    /// Increases whenever a property change alters the schema, so that
+    /// panels only need to rebuild themselves when this changes.
+    pub fn schema_revision(&self) -> u64 {
+        self.schema_revision
+    }
+    /// Whether a property should currently be shown, according to its
+    /// `visible_if` condition.
+    pub fn is_visible(&self, name: &str) -> bool {
+        match self.schema().get(name) {
+            Some(field) => field.visible_if.as_ref().map_or(true, |condition| {
+                self.get_property(&condition.property).as_ref() == Some(&condition.equals)
+            }),
+            None => false,
+        }
+    }
+    /// The library entry this component was created from.
+    pub fn metadata(&self) -> &ComponentMetadata {
+        &self.metadata
+    }
+    /// The component as its concrete type, if it is a `T`.
+    pub fn downcast_ref<T: Component>(&self) -> Option<&T> {
+        self.component.as_any_ref().downcast_ref()
+    }
+    /// The component as its concrete type, if it is a `T`. Changes made
+    /// through the result bypass `set_property`, so they are neither
+    /// validated against the schema nor counted by `schema_revision`.
+    pub fn downcast_mut<T: Component>(&mut self) -> Option<&mut T> {
+        self.component.as_any_mut().downcast_mut()
+    }
+    pub fn instance_id(&self) -> InstanceId {
+        self.instance_id
+    }
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+    /// Whether the component is mirrored horizontally before being rotated.
+    pub fn flipped(&self) -> bool {
+        self.flipped
+    }
+    // Components such as tunnels have a `label` property of their own, which
+    // then doubles as the name of the instance.
+    fn owns_label(&self) -> bool {
+        self.component.schema().contains_key(Self::LABEL)
+    }
+    /// The name given to this instance by the user, such as "PC register".
+    pub fn label(&self) -> Option<String> {
+        match self.get_property(Self::LABEL) {
+            Some(serde_json::Value::String(label)) if !label.is_empty() => Some(label),
+            _ => None,
+        }
+    }
+    pub fn label_position(&self) -> LabelPosition {
+        self.label_position
+    }
+    /// The position of the top-left corner of the component.
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+    pub fn translate(&mut self, dx: i32, dy: i32) {
+        self.x += dx;
+        self.y += dy;
+    }
+    /// Rounds the position to the nearest multiple of `grid`.
+    pub fn snap_to_grid(&mut self, grid: i32) {
+        assert!(grid > 0, "Grid size must be positive");
+        let snap = |v: i32| (v as f64 / grid as f64).round() as i32 * grid;
+        self.x = snap(self.x);
+        self.y = snap(self.y);
+    }
+    /// The area occupied by the component, after taking its orientation into
+    /// account.
+    pub fn bounding_box(&self) -> Rect {
+        let shape = self.get_shape();
+        Rect { x: self.x, y: self.y, width: shape.width, height: shape.height }
+    }
     pub fn schema(&self) -> Schema {
         let mut s = self.component.schema();
-        s.insert(Self::ORIENTATION.into(), FieldSchema {
-            type_: Orientation::field_type(),
-            read_only: false,
-            name: "Orientation".into(),
-            description: None,
-        });
+        for (name, field) in &mut s {
+            if field.default.is_none() {
+                field.default = self.defaults.get(&**name).cloned();
+            }
+        }
+        // Shown after the component's own properties
+        if !s.contains_key(Self::LABEL) {
+            s.insert(Self::LABEL.into(), FieldSchema::new("Label", FieldType::Text { min_len: 0, max_len: 64 })
+                .with_group(Self::LABEL_GROUP)
+                .with_order(900)
+                .with_default(""));
+        }
+        s.insert(Self::LABEL_POSITION.into(), FieldSchema::new("Label position", LabelPosition::field_type())
+            .with_group(Self::LABEL_GROUP)
+            .with_order(901)
+            .with_default(serde_json::to_value(LabelPosition::Above).expect("Label positions are serializable")));
+        s.insert(Self::ORIENTATION.into(), FieldSchema::new("Orientation", Orientation::field_type())
+            .with_group(Self::PLACEMENT_GROUP)
+            .with_order(1000)
+            .with_default(serde_json::to_value(Orientation::North).expect("Orientations are serializable")));
+        s.insert(Self::FLIPPED.into(), FieldSchema::new("Flipped", bool::field_type())
+            .with_group(Self::PLACEMENT_GROUP)
+            .with_order(1001)
+            .with_default(false));
         s
     }
-    pub fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+    /// Restores a property to its default value.
+    pub fn reset_property(&mut self, name: &str) -> Result<PropertyChanged, PropertyError> {
+        let default = match self.schema().get(name) {
+            Some(field) => field.default.clone()
+                .ok_or_else(|| PropertyError::invalid(name, "The property has no default"))?,
+            None => return Err(PropertyError::unknown(name)),
+        };
+        self.set_property(name, default)
+    }
+    /// Restores every property except for the placement and label of the
+    /// component to its default value. Either every property is reset, or the component is
+    /// left unchanged and the errors are returned.
+    pub fn reset_all(&mut self) -> Result<(), Vec<PropertyError>> {
+        let mut copy = self.clone();
+        let pending = |copy: &ComponentInfo| -> Vec<_> {
+            copy.schema().into_iter()
+                .filter(|(_, field)| !field.read_only && field.group.as_ref()
+                    .map_or(true, |g| g != Self::PLACEMENT_GROUP && g != Self::LABEL_GROUP))
+                .filter_map(|(name, field)| Some((name, field.default?)))
+                .filter(|(name, default)| copy.get_property(name).as_ref() != Some(default))
+                .collect()
+        };
+        // Defaults may depend on other properties, such as the length of a
+        // list, so keep going while progress is being made.
+        let mut remaining = pending(&copy);
+        for _ in 0..=self.schema().len() {
+            if remaining.is_empty() {
+                break;
+            }
+            let count = remaining.len();
+            let errors: Vec<_> = remaining.into_iter()
+                .filter_map(|(name, default)| copy.set_property(&name, default).err())
+                .collect();
+            if errors.len() == count {
+                return Err(errors);
+            }
+            remaining = pending(&copy);
+        }
+        if !remaining.is_empty() {
+            return Err(remaining.into_iter()
+                .map(|(name, _)| PropertyError::invalid(&name, "Did not settle on its default"))
+                .collect());
+        }
+        *self = copy;
+        Ok(())
+    }
+    pub fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<PropertyChanged, PropertyError> {
+        let schema = self.schema();
+        // Check the value against the schema, so that components only need
+        // to handle values which are valid for the declared field type.
+        match schema.get(name) {
+            Some(field) if field.read_only => return Err(PropertyError::read_only(name)),
+            Some(field) => field.type_.validate(&value)
+                .map_err(|e| PropertyError::invalid(name, e))?,
+            None => return Err(PropertyError::unknown(name)),
+        }
+        let old_value = self.get_property(name);
         match name {
             Self::ORIENTATION => {
-                self.orientation = serde_json::from_value(value)
+                self.orientation = serde_json::from_value(value.clone())
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+            },
+            Self::FLIPPED => {
+                self.flipped = serde_json::from_value(value.clone())
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+            },
+            Self::LABEL if !self.owns_label() => {
+                self.label = serde_json::from_value(value.clone())
                     .map_err(|e| PropertyError::from_serde(e, name))?;
             },
-            _ => self.component.set_property(name, value)?,
+            Self::LABEL_POSITION => {
+                self.label_position = serde_json::from_value(value.clone())
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+            },
+            _ => self.component.set_property(name, value.clone())?,
+        }
+        let new_schema = self.schema();
+        let schema_changed = new_schema != schema;
+        if schema_changed {
+            self.schema_revision += 1;
+        }
+        Ok(PropertyChanged {
+            name: name.into(),
+            old_value,
+            new_value: value,
+            schema_changed,
+            dependents: new_schema.iter()
+                .filter(|(_, field)| field.depends_on.iter().any(|d| d == name))
+                .map(|(id, _)| id.clone().into_owned())
+                .collect(),
+        })
+    }
+    /// Sets several properties at once. Either every value is applied, or
+    /// the component is left unchanged and all of the errors are returned.
+    pub fn set_properties<I>(&mut self, values: I) -> Result<(), Vec<PropertyError>>
+    where
+        I: IntoIterator<Item=(String, serde_json::Value)>
+    {
+        // Apply the changes to a copy, so that a failure part way through
+        // does not leave the component in a mixed state.
+        let mut copy = self.clone();
+        let errors: Vec<_> = values.into_iter()
+            .filter_map(|(name, value)| copy.set_property(&name, value).err())
+            .collect();
+        if errors.is_empty() {
+            *self = copy;
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    /// Replaces a single element of a list property.
+    pub fn set_list_element(&mut self, name: &str, index: usize, value: serde_json::Value) -> Result<PropertyChanged, PropertyError> {
+        let mut list = match self.get_property(name) {
+            Some(serde_json::Value::Array(list)) => list,
+            Some(_) => return Err(PropertyError::invalid(name, "Not a list")),
+            None => return Err(PropertyError::unknown(name)),
+        };
+        match list.get_mut(index) {
+            Some(item) => *item = value,
+            None => return Err(PropertyError::invalid(name, format!("No element {}", index))),
+        }
+        self.set_property(name, list.into())
+    }
+    pub fn get_list_element(&self, name: &str, index: usize) -> Option<serde_json::Value> {
+        match self.get_property(name)? {
+            serde_json::Value::Array(mut list) if index < list.len() => Some(list.swap_remove(index)),
+            _ => None,
         }
-        Ok(())
     }
     pub fn get_property(&self, name: &str) -> Option<serde_json::Value> {
         match name {
             Self::ORIENTATION => serde_json::to_value(self.orientation).ok(),
+            Self::FLIPPED => serde_json::to_value(self.flipped).ok(),
+            Self::LABEL if !self.owns_label() => serde_json::to_value(&self.label).ok(),
+            Self::LABEL_POSITION => serde_json::to_value(self.label_position).ok(),
             _ => self.component.get_property(name),
         }
     }
     pub fn get_shape(&self) -> Shape {
-        self.orientation.map_shape(self.component.get_shape())
+        self.orientation.map_shape(self.component.get_shape(), self.flipped)
+    }
+    /// The pins of the component, in both its own coordinates and those of
+    /// the circuit.
+    pub fn pins(&self) -> Vec<PlacedPin> {
+        let local = self.component.get_shape();
+        let shape = self.orientation.map_shape(local.clone(), self.flipped);
+        local.pins.into_iter().zip(&shape.pins).enumerate().map(|(index, (pin, placed))| PlacedPin {
+            index,
+            local: (pin.x, pin.y),
+            world: (self.x + placed.x, self.y + placed.y),
+            edge: shape.edge_at((placed.x, placed.y)),
+            name: pin.name,
+            bits: pin.bits,
+            direction: pin.direction,
+        }).collect()
+    }
+    pub fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        self.component.evaluate(inputs)
+    }
+    pub fn clock_edge(&mut self, inputs: &PinValues) {
+        self.component.clock_edge(inputs)
+    }
+    pub fn delay(&self) -> u64 {
+        self.component.delay()
+    }
+    pub fn interact(&mut self, interaction: Interaction) -> bool {
+        self.component.interact(interaction)
+    }
+    pub fn display_value(&self) -> Option<Value> {
+        self.component.display_value()
+    }
+    pub fn capture(&self) -> Option<&Capture> {
+        self.component.capture()
+    }
+    pub fn audio(&self) -> Option<&AudioTrack> {
+        self.component.audio()
+    }
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        self.component.framebuffer()
+    }
+    pub fn terminal_text(&self) -> Option<String> {
+        self.component.terminal_text()
+    }
+    pub fn tick(&mut self) -> bool {
+        self.component.tick()
+    }
+    pub fn state(&self) -> Option<serde_json::Value> {
+        self.component.state()
+    }
+    pub fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.component.set_state(state)
+    }
+    pub fn connections(&self) -> Vec<(PinBit, PinBit)> {
+        self.component.connections()
+    }
+    pub fn switches(&self) -> Vec<(PinBit, PinBit)> {
+        self.component.switches()
+    }
+    pub fn conducting(&self, inputs: &[Value]) -> Vec<bool> {
+        self.component.conducting(inputs)
+    }
+    pub fn tunnel_label(&self) -> Option<String> {
+        self.component.tunnel_label()
+    }
+    pub fn pull(&self) -> Option<Bit> {
+        self.component.pull()
+    }
+    pub fn resolution(&self) -> Option<Resolution> {
+        self.component.resolution()
+    }
+    pub fn width_errors(&self) -> Vec<String> {
+        self.component.width_errors()
+    }
+    pub fn is_annotation(&self) -> bool {
+        self.component.is_annotation()
+    }
+    pub fn kernel(&self) -> Option<Kernel> {
+        self.component.kernel()
+    }
+    pub fn subcircuit(&self) -> Option<&Circuit> {
+        self.component.subcircuit()
+    }
+    pub fn simulator(&self) -> Option<&Simulator> {
+        self.component.simulator()
+    }
+    pub fn hdl(&self) -> Option<&HdlEmit> {
+        self.component.hdl()
+    }
+    pub fn draw(&self, ctx: &mut DrawContext) {
+        self.component.draw(ctx)
+    }
+    pub fn draw_diagram(&self, ctx: &mut DrawContext) {
+        self.component.draw_diagram(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::{gates, wiring};
+
+    #[test]
+    fn validate_strings() {
+        let identifier = FieldType::string().with_pattern("[A-Za-z_][A-Za-z0-9_]*");
+        assert!(identifier.validate(&"clk_2".into()).is_ok());
+        assert!(identifier.validate(&"2clk".into()).is_err());
+        assert!(identifier.validate(&"clk!".into()).is_err());
+        assert!(identifier.validate(&true.into()).is_err());
+        assert!(PATTERNS.with(|patterns| patterns.borrow().contains_key("[A-Za-z_][A-Za-z0-9_]*")));
+
+        assert!(FieldType::string().validate(&"a\nb".into()).is_err());
+        assert!(FieldType::string().multiline().validate(&"a\nb".into()).is_ok());
+        assert!(FieldType::string().with_pattern("[").validate(&"".into()).is_err());
+
+        let text = FieldType::Text { min_len: 1, max_len: 3 };
+        assert!(text.validate(&"".into()).is_err());
+        assert!(text.validate(&"abc".into()).is_ok());
+    }
+
+    #[test]
+    fn validate_floats() {
+        let duty = FieldType::float(0.0, 1.0).with_step(0.1);
+        assert!(duty.validate(&0.3.into()).is_ok());
+        assert!(duty.validate(&1.into()).is_ok());
+        assert!(duty.validate(&0.35.into()).is_err());
+        assert!(duty.validate(&1.5.into()).is_err());
+        assert!(duty.validate(&"0.5".into()).is_err());
+        assert_eq!(<f32 as ReflectType>::field_type().with_range(Some(-1), None),
+            FieldType::float(-1.0, std::f32::MAX as f64));
+
+        #[derive(Debug, Clone, Component)]
+        struct Knob {
+            #[property(name = "Level", min = 0, max = 1, step = 0.25)]
+            level: f64,
+        }
+        let knob = Knob { level: 0.5 };
+        assert_eq!(knob.schema()["level"].type_, FieldType::float(0.0, 1.0).with_step(0.25));
+    }
+
+    #[test]
+    fn validate_tables() {
+        let table = FieldType::table(vec![
+            RecordField::new("name", "Name", FieldType::string()),
+            RecordField::new("value", "Value", FieldType::integer(0, 9)),
+        ], 1, 4);
+        assert!(table.validate(&serde_json::json!([{ "name": "a", "value": 1 }])).is_ok());
+        assert!(table.validate(&serde_json::json!([])).is_err());
+        assert_eq!(table.validate(&serde_json::json!([{ "name": "a" }])).unwrap_err(),
+            "Element 0: Missing field `value`");
+        assert_eq!(table.validate(&serde_json::json!([{ "name": "a", "value": 10 }])).unwrap_err(),
+            "Element 0: Value: Must be between 0 and 9");
+        assert!(table.validate(&serde_json::json!([{ "name": "a", "value": 1, "extra": 2 }])).is_err());
+    }
+
+    #[test]
+    fn units() {
+        let hz = Unit::si("Hz");
+        assert_eq!(hz.format(100e6), "100 MHz");
+        assert_eq!(hz.format(2500.0), "2.5 kHz");
+        assert_eq!(hz.format(0.0), "0 Hz");
+        assert_eq!(hz.parse("100 MHz"), Some(100e6));
+        assert_eq!(hz.parse("3k"), Some(3000.0));
+        assert_eq!(hz.parse("12"), Some(12.0));
+        assert_eq!(hz.parse("5 Q"), None);
+
+        let ns = Unit::si("s").with_scale(1e-9);
+        assert_eq!(ns.format(1500.0), "1.5 \u{b5}s");
+        assert_eq!(ns.parse("2 us").map(f64::round), Some(2000.0));
+        assert_eq!(Unit::new("ticks").format(4000.0), "4000 ticks");
+        assert_eq!(Unit::new("ticks").parse("4k"), None);
+
+        let field = FieldType::integer(1, 1024).with_unit(Unit::new("ticks")).with_range(Some(2), None);
+        assert_eq!(field, FieldType::Integer { min: 2, max: 1024, unit: Some(Unit::new("ticks")) });
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(Color::from_hex("#f22"), Some(Color::rgb(255, 34, 34)));
+        assert_eq!(Color::from_hex("#00ff0080"), Some(Color::rgba(0, 255, 0, 128)));
+        assert_eq!(Color::from_hex("f22"), None);
+        assert_eq!(Color::from_hex("#ff22"), None);
+        assert_eq!(Color::rgba(0, 255, 0, 128).to_hex(), "#00ff0080");
+        assert_eq!(Color::rgb(255, 34, 34).scaled(0.25).to_string(), "#400909");
+
+        let value = serde_json::json!({ "r": 1, "g": 2, "b": 3 });
+        assert_eq!(serde_json::from_value::<Color>(value.clone()).unwrap(), Color::rgb(1, 2, 3));
+        let translucent = serde_json::to_value(Color::rgba(1, 2, 3, 4)).unwrap();
+        assert!(FieldType::color().validate(&value).is_ok());
+        assert!(FieldType::color().validate(&translucent).is_err());
+        assert!(FieldType::color().with_alpha().validate(&translucent).is_ok());
+        assert!(FieldType::color().validate(&serde_json::json!({ "r": 300, "g": 0, "b": 0 })).is_err());
+        assert!(FieldType::color().validate(&"#fff".into()).is_err());
+    }
+
+    #[test]
+    fn validate_against_schema() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        let error = gate.set_property("num_inputs", 40.into()).unwrap_err();
+        assert!(matches!(error.reason, PropertyErrorReason::InvalidValue { .. }));
+        assert!(gate.set_property("num_inputs", "3".into()).is_err());
+        assert!(gate.set_property("orientation", "Up".into()).is_err());
+        let error = gate.set_property("invert_input_2", true.into()).unwrap_err();
+        assert!(matches!(error.reason, PropertyErrorReason::UnknownProperty));
+        assert!(gate.set_property("invert_inputs", serde_json::json!([true, false, true])).is_err());
+        assert!(gate.set_property("invert_inputs", serde_json::json!([true, 1])).is_err());
+        gate.set_list_element("invert_inputs", 1, true.into()).unwrap();
+        assert_eq!(gate.get_property("invert_inputs"), Some(serde_json::json!([false, true])));
+        assert_eq!(gate.get_list_element("invert_inputs", 1), Some(true.into()));
+        assert!(gate.set_list_element("invert_inputs", 2, true.into()).is_err());
+        assert!(gate.set_list_element("num_inputs", 0, true.into()).is_err());
+
+        let range = FieldType::integer(-4, 4);
+        assert!(range.validate(&(-4).into()).is_ok());
+        assert!(range.validate(&5.into()).is_err());
+        assert!(range.validate(&1.5.into()).is_err());
+    }
+
+    #[derive(Debug, Clone, Component)]
+    struct Lamp {
+        #[property(name = "Dimmable")]
+        dimmable: bool,
+        #[property(name = "Brightness", max = 100, depends_on = "dimmable")]
+        brightness: u32,
+    }
+
+    impl Component for Lamp {
+        fn get_shape(&self) -> Shape {
+            Shape { width: 2, height: 2, pins: Vec::new(), image_name: "lamp".into() }
+        }
+    }
+
+    #[test]
+    fn dependent_properties() {
+        let metadata = Arc::new(ComponentMetadata::new("lamp", "Lamp", "Test", ""));
+        let mut lamp = ComponentInfo::new(Box::new(Lamp { dimmable: false, brightness: 100 }), metadata);
+        assert!(lamp.is_visible("brightness"));
+        let change = lamp.set_property("dimmable", true.into()).unwrap();
+        assert_eq!(change.dependents, vec!["brightness".to_string()]);
+        assert!(!change.schema_changed);
+
+        let field = FieldSchema::new("Brightness", FieldType::Boolean).visible_if("dimmable", true.into());
+        assert_eq!(field.depends_on, vec![Cow::from("dimmable")]);
+
+        let mut gate = gates::library().create("and_gate").unwrap();
+        let revision = gate.schema_revision();
+        gate.set_property("num_bits", 4.into()).unwrap();
+        assert_eq!(gate.schema_revision(), revision);
+        let change = gate.set_property("num_inputs", 3.into()).unwrap();
+        assert!(change.schema_changed);
+        assert_eq!(change.dependents, vec!["invert_inputs".to_string()]);
+        assert_eq!(gate.schema_revision(), revision + 1);
+    }
+
+    #[test]
+    fn downcasting() {
+        let metadata = Arc::new(ComponentMetadata::new("lamp", "Lamp", "Test", ""));
+        let mut lamp = ComponentInfo::new(Box::new(Lamp { dimmable: true, brightness: 100 }), metadata);
+        assert_eq!(lamp.downcast_ref::<Lamp>().unwrap().brightness, 100);
+        lamp.downcast_mut::<Lamp>().unwrap().brightness = 40;
+        assert_eq!(lamp.get_property("brightness"), Some(40.into()));
+        let gate = gates::library().create("and_gate").unwrap();
+        assert!(gate.downcast_ref::<Lamp>().is_none());
+    }
+
+    #[test]
+    fn reset_to_defaults() {
+        let mut gate = gates::library().create("or_gate").unwrap();
+        gate.set_property("orientation", "East".into()).unwrap();
+        gate.set_property("num_inputs", 3.into()).unwrap();
+        gate.set_property("num_bits", 8.into()).unwrap();
+        gate.set_list_element("invert_inputs", 2, true.into()).unwrap();
+        assert_eq!(gate.schema()["num_bits"].default, Some(1.into()));
+
+        gate.reset_property("invert_inputs").unwrap();
+        assert_eq!(gate.get_property("invert_inputs"), Some(serde_json::json!([false, false, false])));
+        gate.set_list_element("invert_inputs", 2, true.into()).unwrap();
+        gate.reset_all().unwrap();
+        assert_eq!(gate.get_property("num_inputs"), Some(2.into()));
+        assert_eq!(gate.get_property("num_bits"), Some(1.into()));
+        assert_eq!(gate.get_property("invert_inputs"), Some(serde_json::json!([false, false])));
+        assert_eq!(gate.orientation(), Orientation::East);
+        gate.reset_property("orientation").unwrap();
+        assert_eq!(gate.orientation(), Orientation::North);
+        assert!(gate.reset_property("missing").is_err());
+    }
+
+    #[test]
+    fn instance_labels() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        assert_eq!(gate.label(), None);
+        gate.set_property("label", "Carry".into()).unwrap();
+        gate.set_property("label_position", "Left".into()).unwrap();
+        assert_eq!(gate.label(), Some("Carry".into()));
+        assert_eq!(gate.label_position(), LabelPosition::Left);
+        gate.reset_all().unwrap();
+        assert_eq!(gate.label(), Some("Carry".into()));
+        assert!(gate.set_property("label_position", "Inside".into()).is_err());
+
+        // Tunnels keep their own label, which also names the instance
+        let mut tunnel = wiring::library().create("tunnel").unwrap();
+        assert!(tunnel.schema()["label"].group.is_none());
+        tunnel.set_property("label", "bus".into()).unwrap();
+        assert_eq!(tunnel.tunnel_label(), Some("bus".into()));
+        assert_eq!(tunnel.label(), Some("bus".into()));
+    }
+
+    #[test]
+    fn set_properties_is_atomic() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        let errors = gate.set_properties(vec![
+            ("num_inputs".to_string(), 4.into()),
+            ("num_bits".to_string(), 0.into()),
+            ("missing".to_string(), 1.into()),
+        ]).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(gate.get_property("num_inputs"), Some(2.into()));
+
+        gate.set_properties(vec![
+            ("num_inputs".to_string(), 4.into()),
+            ("num_bits".to_string(), 8.into()),
+        ]).unwrap();
+        assert_eq!(gate.get_property("num_inputs"), Some(4.into()));
+        assert_eq!(gate.get_property("num_bits"), Some(8.into()));
+    }
+
+    #[test]
+    fn position_and_bounds() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        gate.set_position(3, 4);
+        gate.translate(-4, 1);
+        assert_eq!(gate.position(), (-1, 5));
+        gate.snap_to_grid(4);
+        assert_eq!(gate.position(), (0, 4));
+
+        let shape = gate.get_shape();
+        assert_eq!(gate.bounding_box(), Rect { x: 0, y: 4, width: shape.width, height: shape.height });
+        gate.set_property("orientation", "East".into()).unwrap();
+        let bounds = gate.bounding_box();
+        assert_eq!((bounds.width, bounds.height), (shape.height, shape.width));
+        assert!(bounds.contains((0, 4)));
+        assert!(!bounds.contains((-1, 4)));
+    }
+
+    #[test]
+    fn pin_edges() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        gate.set_position(10, 20);
+        let local = gate.get_shape();
+        let pins = gate.pins();
+        assert_eq!(pins.len(), local.pins.len());
+        let output = pins.last().unwrap();
+        assert_eq!(output.direction, PinDirection::Output);
+        assert_eq!(output.edge, Edge::Right);
+        assert_eq!(output.world, (10 + output.local.0, 20 + output.local.1));
+        assert!(pins.iter().filter(|p| p.direction.is_input()).all(|p| p.edge == Edge::Left));
+
+        gate.set_property("orientation", "East".into()).unwrap();
+        let pins = gate.pins();
+        assert_eq!(pins.last().unwrap().edge, Edge::Bottom);
+        assert_eq!(pins.last().unwrap().local, output.local);
+        assert!(pins.iter().filter(|p| p.direction.is_input()).all(|p| p.edge == Edge::Top));
+        gate.set_property("flipped", true.into()).unwrap();
+        assert_eq!(gate.pins().last().unwrap().edge, Edge::Top);
+    }
+
+    #[test]
+    fn flipping() {
+        let mut gate = gates::library().create("and_gate").unwrap();
+        let shape = gate.get_shape();
+        gate.set_property("flipped", true.into()).unwrap();
+        let flipped = gate.get_shape();
+        for (pin, mirrored) in shape.pins.iter().zip(&flipped.pins) {
+            assert_eq!((mirrored.x, mirrored.y), (shape.width - pin.x, pin.y));
+        }
+        assert_eq!(gate.get_property("flipped"), Some(true.into()));
+
+        // Flipping a rotated component mirrors it vertically
+        gate.set_property("orientation", "East".into()).unwrap();
+        let rotated = gate.get_shape();
+        gate.set_property("flipped", false.into()).unwrap();
+        let unflipped = gate.get_shape();
+        for (a, b) in rotated.pins.iter().zip(&unflipped.pins) {
+            assert_eq!((a.x, a.y), (b.x, rotated.height - b.y));
+        }
+    }
+
+    #[test]
+    fn identity() {
+        let lib = gates::library();
+        let a = lib.create("and_gate").unwrap();
+        let b = lib.create("and_gate").unwrap();
+        assert_eq!(a.metadata().id, "and_gate");
+        assert_ne!(a.instance_id(), b.instance_id());
+        assert_eq!(a.clone().instance_id(), a.instance_id());
     }
 }
@@ -0,0 +1,463 @@
+//! Breakpoints, which pause the simulation when signals reach a given state,
+//! and watches, which report each change to a signal.
+//!
+//! Signals are referred to by the names used for waveforms: the label of a
+//! component, or a component and one of its pins, such as `c4.out`.
+//! Conditions are written like:
+//!
+//! ```text
+//! net data == 0xff
+//! rising edge of clk && addr == 0x10
+//! !(count < 4) || falling edge of c3.out
+//! ```
+//!
+//! A signal on its own is true when it is non-zero. Comparisons involving a
+//! signal which is floating or unknown are false.
+
+use std::fmt;
+use std::error::Error;
+
+use crate::circuit::Circuit;
+use crate::net::PinRef;
+use crate::simulation::{Bit, Value};
+use crate::trace;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BreakpointId(pub u64);
+
+impl fmt::Display for BreakpointId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    /// The offset in characters of the problem within the condition.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ConditionError {
+    fn new<S: Into<String>>(position: usize, message: S) -> Self {
+        ConditionError { position, message: message.into() }
+    }
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid condition at character {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for ConditionError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Number(u64),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let word_len = chars[i..].iter().take_while(|&&c| c.is_alphanumeric() || c == '_' || c == '.').count();
+        let word: String = chars[i..i + word_len].iter().collect();
+        if c.is_ascii_digit() {
+            let (digits, radix) = if word.starts_with("0x") || word.starts_with("0X") {
+                (&word[2..], 16)
+            } else if word.starts_with("0b") || word.starts_with("0B") {
+                (&word[2..], 2)
+            } else {
+                (&word[..], 10)
+            };
+            let value = u64::from_str_radix(digits, radix)
+                .map_err(|_| ConditionError::new(start, format!("Invalid number `{}`", word)))?;
+            result.push((start, Token::Number(value)));
+            i += word_len;
+        } else if word_len > 0 {
+            result.push((start, Token::Name(word)));
+            i += word_len;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS.iter().find(|s| rest.starts_with(*s))
+                .ok_or_else(|| ConditionError::new(start, format!("Unexpected `{}`", c)))?;
+            result.push((start, Token::Symbol(symbol)));
+            i += symbol.len();
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Edge {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Operand {
+    // An index into the signals of the condition
+    Signal(usize),
+    Constant(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Edge(Edge, usize),
+    Compare(Operand, &'static str, Operand),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+    signals: &'a mut Vec<(String, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, t)| t)
+    }
+    fn peek_name(&self, offset: usize, name: &str) -> bool {
+        match self.tokens.get(self.index + offset) {
+            Some((_, Token::Name(n))) => n == name,
+            _ => false,
+        }
+    }
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |&(p, _)| p)
+    }
+    fn error(&self, message: &str) -> ConditionError {
+        ConditionError::new(self.position(), message)
+    }
+    fn eat(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.index += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        let mut result = self.and()?;
+        while self.eat("||") {
+            result = Expr::Or(Box::new(result), Box::new(self.and()?));
+        }
+        Ok(result)
+    }
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        let mut result = self.unary()?;
+        while self.eat("&&") {
+            result = Expr::And(Box::new(result), Box::new(self.unary()?));
+        }
+        Ok(result)
+    }
+    fn unary(&mut self) -> Result<Expr, ConditionError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let result = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("Expected `)`"));
+            }
+            return Ok(result);
+        }
+        let edge = if self.peek_name(0, "rising") { Some(Edge::Rising) } else if self.peek_name(0, "falling") { Some(Edge::Falling) } else { None };
+        if let Some(edge) = edge.filter(|_| self.peek_name(1, "edge")) {
+            self.index += 2;
+            if self.peek_name(0, "of") {
+                self.index += 1;
+            }
+            return Ok(Expr::Edge(edge, self.signal()?));
+        }
+        let left = self.operand()?;
+        let op = match self.peek() {
+            Some(&Token::Symbol(op)) if ["==", "!=", "<", "<=", ">", ">="].contains(&op) => op,
+            _ => return Ok(Expr::Compare(left, "!=", Operand::Constant(0))),
+        };
+        self.index += 1;
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+    fn operand(&mut self) -> Result<Operand, ConditionError> {
+        if let Some(&Token::Number(value)) = self.peek() {
+            self.index += 1;
+            return Ok(Operand::Constant(value));
+        }
+        Ok(Operand::Signal(self.signal()?))
+    }
+    fn signal(&mut self) -> Result<usize, ConditionError> {
+        // The word `net` may be used to introduce a signal name
+        if self.peek_name(0, "net") {
+            if let Some((_, Token::Name(_))) = self.tokens.get(self.index + 1) {
+                self.index += 1;
+            }
+        }
+        let position = self.position();
+        let name = match self.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return Err(self.error("Expected a signal name")),
+        };
+        self.index += 1;
+        Ok(match self.signals.iter().position(|(n, _)| *n == name) {
+            Some(index) => index,
+            None => {
+                self.signals.push((name, position));
+                self.signals.len() - 1
+            },
+        })
+    }
+}
+
+/// A parsed breakpoint condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+    // The name of each signal, and where it first appears in the text
+    signals: Vec<(String, usize)>,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, ConditionError> {
+        let mut signals = Vec::new();
+        let mut parser = Parser { tokens: tokenize(text)?, index: 0, end: text.chars().count(), signals: &mut signals };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("Unexpected input after the end of the condition"));
+        }
+        Ok(Condition { text: text.into(), expr, signals })
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// The names of the signals the condition refers to.
+    pub fn signals(&self) -> impl Iterator<Item=&str> {
+        self.signals.iter().map(|(name, _)| name.as_str())
+    }
+    /// Evaluates the condition given the value of each signal in the order of
+    /// `signals`, now and as of the previous evaluation.
+    pub fn evaluate(&self, current: &[Option<Value>], previous: &[Option<Value>]) -> bool {
+        Self::evaluate_expr(&self.expr, current, previous)
+    }
+    fn evaluate_expr(expr: &Expr, current: &[Option<Value>], previous: &[Option<Value>]) -> bool {
+        let bit = |values: &[Option<Value>], i: usize| values.get(i).and_then(|v| v.as_ref()).map(|v| v.bit(0));
+        let number = |operand: Operand| match operand {
+            Operand::Constant(value) => Some(value),
+            Operand::Signal(i) => current.get(i).and_then(|v| v.as_ref()).and_then(|v| v.to_u64()),
+        };
+        match *expr {
+            Expr::Or(ref a, ref b) => Self::evaluate_expr(a, current, previous) || Self::evaluate_expr(b, current, previous),
+            Expr::And(ref a, ref b) => Self::evaluate_expr(a, current, previous) && Self::evaluate_expr(b, current, previous),
+            Expr::Not(ref a) => !Self::evaluate_expr(a, current, previous),
+            Expr::Edge(edge, i) => match (edge, bit(previous, i), bit(current, i)) {
+                (Edge::Rising, Some(Bit::Zero), Some(Bit::One)) => true,
+                (Edge::Falling, Some(Bit::One), Some(Bit::Zero)) => true,
+                _ => false,
+            },
+            Expr::Compare(left, op, right) => match (number(left), number(right)) {
+                (Some(a), Some(b)) => match op {
+                    "==" => a == b,
+                    "!=" => a != b,
+                    "<" => a < b,
+                    "<=" => a <= b,
+                    ">" => a > b,
+                    _ => a >= b,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+fn find_signal(circuit: &Circuit, name: &str, position: usize) -> Result<PinRef, ConditionError> {
+    trace::find_signal(circuit, name)
+        .ok_or_else(|| ConditionError::new(position, format!("Unknown signal `{}`", name)))
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub condition: Condition,
+    pins: Vec<PinRef>,
+    previous: Vec<Option<Value>>,
+    // Whether the condition held as of the previous evaluation
+    held: bool,
+}
+
+/// A change in the value of a watched signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchEvent {
+    pub name: String,
+    pub time: u64,
+    pub previous: Option<Value>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone)]
+struct Watch {
+    name: String,
+    pin: PinRef,
+    last: Option<Value>,
+}
+
+/// The breakpoints and watches of a simulation.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: u64,
+    watches: Vec<Watch>,
+    events: Vec<WatchEvent>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty() && self.watches.is_empty()
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    pub fn add_breakpoint(&mut self, circuit: &Circuit, condition: &str) -> Result<BreakpointId, ConditionError> {
+        let condition = Condition::parse(condition)?;
+        let pins = condition.signals.iter()
+            .map(|(name, position)| find_signal(circuit, name, *position))
+            .collect::<Result<Vec<_>, _>>()?;
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, previous: vec![None; pins.len()], condition, pins, held: false });
+        Ok(id)
+    }
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+    pub fn add_watch(&mut self, circuit: &Circuit, name: &str) -> Result<(), ConditionError> {
+        let pin = find_signal(circuit, name, 0)?;
+        if !self.watches.iter().any(|w| w.name == name) {
+            self.watches.push(Watch { name: name.into(), pin, last: None });
+        }
+        Ok(())
+    }
+    pub fn remove_watch(&mut self, name: &str) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w.name != name);
+        self.watches.len() != len
+    }
+    /// Takes the changes to watched signals since this was last called.
+    pub fn take_events(&mut self) -> Vec<WatchEvent> {
+        std::mem::take(&mut self.events)
+    }
+    /// Forgets the previous values of every signal, as when the simulation
+    /// is reset.
+    pub fn reset(&mut self) {
+        for breakpoint in &mut self.breakpoints {
+            breakpoint.previous.iter_mut().for_each(|v| *v = None);
+            breakpoint.held = false;
+        }
+        for watch in &mut self.watches {
+            watch.last = None;
+        }
+        self.events.clear();
+    }
+    /// Looks at the signals after a step of the simulation, recording changes
+    /// to watched signals. Returns the first breakpoint whose condition has
+    /// become true. A condition which stays true does not hit again until it
+    /// has been false.
+    pub fn observe<F: Fn(PinRef) -> Option<Value>>(&mut self, time: u64, value_of: F) -> Option<BreakpointId> {
+        for watch in &mut self.watches {
+            let value = match value_of(watch.pin) {
+                Some(value) => value,
+                None => continue,
+            };
+            if watch.last.as_ref() != Some(&value) {
+                let previous = watch.last.replace(value.clone());
+                self.events.push(WatchEvent { name: watch.name.clone(), time, previous, value });
+            }
+        }
+        let mut hit = None;
+        for breakpoint in &mut self.breakpoints {
+            let current: Vec<_> = breakpoint.pins.iter().map(|&pin| value_of(pin)).collect();
+            let holds = breakpoint.condition.evaluate(&current, &breakpoint.previous);
+            if holds && !breakpoint.held && hit.is_none() {
+                hit = Some(breakpoint.id);
+            }
+            breakpoint.held = holds;
+            breakpoint.previous = current;
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::Interaction;
+    use crate::simulation::{Simulator, SimulationError};
+
+    #[test]
+    fn parse_conditions() {
+        let condition = Condition::parse("rising edge of clk && net addr == 0x10 || !(b.out < 0b11)").unwrap();
+        assert_eq!(condition.signals().collect::<Vec<_>>(), vec!["clk", "addr", "b.out"]);
+        let values = |clk, addr, b| vec![Some(Value::from_u64(clk, 1)), Some(Value::from_u64(addr, 8)), Some(Value::from_u64(b, 2))];
+        assert!(condition.evaluate(&values(1, 0x10, 0), &values(0, 0, 0)));
+        assert!(!condition.evaluate(&values(1, 0x10, 0), &values(1, 0, 0)));
+        assert!(condition.evaluate(&values(0, 0, 3), &values(0, 0, 0)));
+        assert!(!Condition::parse("x == 1").unwrap().evaluate(&[None], &[None]));
+
+        assert_eq!(Condition::parse("a == ").unwrap_err().position, 5);
+        assert_eq!(Condition::parse("(a").unwrap_err().message, "Expected `)`");
+        assert_eq!(Condition::parse("a $ b").unwrap_err().position, 2);
+        assert!(Condition::parse("0xfg").is_err());
+    }
+
+    #[test]
+    fn breakpoints_pause_simulation() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let mut input = library.create("input_pin").unwrap();
+        input.set_property("label", "a".into()).unwrap();
+        let input = circuit.add_component(input, -2, 0);
+        let not = circuit.add_component(library.create("not_gate").unwrap(), 0, 0);
+
+        let mut sim = Simulator::new(circuit);
+        let name = format!("{}.out", not);
+        assert!(sim.add_breakpoint("rising edge of nope").is_err());
+        let id = sim.add_breakpoint(&format!("a && {} == 0", name)).unwrap();
+        sim.add_watch(&name).unwrap();
+        sim.run_until_stable().unwrap();
+        let events = sim.take_watch_events();
+        assert_eq!(events.last().unwrap().value.to_u64(), Some(1));
+
+        sim.interact(input, Interaction::Press);
+        assert_eq!(sim.run_until_stable(), Err(SimulationError::Breakpoint { id }));
+        let events = sim.take_watch_events();
+        assert_eq!((events.len(), events[0].value.to_u64()), (1, Some(0)));
+        sim.run_until_stable().unwrap();
+
+        // The condition must become false before it hits again
+        sim.interact(input, Interaction::Press);
+        sim.run_until_stable().unwrap();
+        assert!(sim.remove_breakpoint(id));
+        assert!(sim.remove_watch(&name));
+    }
+}
@@ -0,0 +1,274 @@
+//! Comparing two versions of a circuit or project, such as for version
+//! control or merging.
+//!
+//! Components and wires are matched up by their uuids, so a component which
+//! has been moved or had its properties changed is reported as modified,
+//! rather than as removed and added again.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::circuit::{Circuit, ComponentId, Wire};
+use crate::persist;
+use crate::project::Project;
+
+/// A property whose value differs, where `None` means the property did not
+/// exist in that version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    pub name: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ComponentChange {
+    Added {
+        uuid: Uuid,
+        id: ComponentId,
+        component: String,
+    },
+    Removed {
+        uuid: Uuid,
+        id: ComponentId,
+        component: String,
+    },
+    /// The id is that of the component in the new version.
+    Modified {
+        uuid: Uuid,
+        id: ComponentId,
+        /// The old and new positions, if the component was moved.
+        moved: Option<((i32, i32), (i32, i32))>,
+        properties: Vec<PropertyChange>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WireChange {
+    Added {
+        uuid: Uuid,
+        wire: Wire,
+    },
+    Removed {
+        uuid: Uuid,
+        wire: Wire,
+    },
+    Moved {
+        uuid: Uuid,
+        old: Wire,
+        new: Wire,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CircuitDiff {
+    pub components: Vec<ComponentChange>,
+    pub wires: Vec<WireChange>,
+    pub appearance_changed: bool,
+    pub parameters_changed: bool,
+}
+
+impl CircuitDiff {
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+            && self.wires.is_empty()
+            && !self.appearance_changed
+            && !self.parameters_changed
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ProjectDiff {
+    pub metadata_changed: bool,
+    pub libraries_changed: bool,
+    pub main_changed: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// The changes to each circuit in both versions which has changed.
+    pub modified: BTreeMap<String, CircuitDiff>,
+}
+
+impl ProjectDiff {
+    pub fn is_empty(&self) -> bool {
+        !self.metadata_changed
+            && !self.libraries_changed
+            && !self.main_changed
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+fn property_changes(old: BTreeMap<String, Value>, mut new: BTreeMap<String, Value>) -> Vec<PropertyChange> {
+    let mut result = Vec::new();
+    for (name, old) in old {
+        let new = new.remove(&name);
+        if new.as_ref() != Some(&old) {
+            result.push(PropertyChange { name, old: Some(old), new });
+        }
+    }
+    result.extend(new.into_iter().map(|(name, new)| PropertyChange { name, old: None, new: Some(new) }));
+    result
+}
+
+/// Compares two versions of a circuit. Removals are listed before additions
+/// and modifications.
+pub fn diff_circuits(old: &Circuit, new: &Circuit) -> CircuitDiff {
+    let mut result = CircuitDiff {
+        appearance_changed: old.appearance() != new.appearance(),
+        parameters_changed: old.parameters() != new.parameters(),
+        ..CircuitDiff::default()
+    };
+
+    let mut added = Vec::new();
+    let mut remaining: BTreeSet<_> = old.components().map(|(id, _)| id).collect();
+    let old_ids: HashMap<_, _> = remaining.iter().filter_map(|&id| Some((old.component_uuid(id)?, id))).collect();
+    for (id, info) in new.components() {
+        let uuid = new.component_uuid(id).expect("Every component has a uuid");
+        let kind = info.metadata().id.clone().into_owned();
+        let old_id = old_ids.get(&uuid).cloned();
+        let old_info = old_id.and_then(|old_id| old.get_component(old_id))
+            .filter(|old_info| old_info.metadata().id == info.metadata().id);
+        match (old_id, old_info) {
+            (Some(old_id), Some(old_info)) => {
+                remaining.remove(&old_id);
+                let moved = Some((old_info.position(), info.position())).filter(|(a, b)| a != b);
+                let properties = property_changes(persist::save_properties(old_info), persist::save_properties(info));
+                if moved.is_some() || !properties.is_empty() {
+                    added.push(ComponentChange::Modified { uuid, id, moved, properties });
+                }
+            },
+            // A different kind of component with the same uuid replaces it
+            _ => added.push(ComponentChange::Added { uuid, id, component: kind }),
+        }
+    }
+    for id in remaining {
+        let info = old.get_component(id).expect("Component exists");
+        result.components.push(ComponentChange::Removed {
+            uuid: old.component_uuid(id).expect("Every component has a uuid"),
+            id,
+            component: info.metadata().id.clone().into_owned(),
+        });
+    }
+    result.components.extend(added);
+
+    let mut added = Vec::new();
+    let mut remaining: BTreeSet<_> = old.wires().map(|(id, _)| id).collect();
+    let old_ids: HashMap<_, _> = remaining.iter().filter_map(|&id| Some((old.wire_uuid(id)?, id))).collect();
+    for (id, &wire) in new.wires() {
+        let uuid = new.wire_uuid(id).expect("Every wire has a uuid");
+        match old_ids.get(&uuid).cloned() {
+            Some(old_id) => {
+                remaining.remove(&old_id);
+                let old_wire = *old.get_wire(old_id).expect("Wire exists");
+                if old_wire != wire {
+                    added.push(WireChange::Moved { uuid, old: old_wire, new: wire });
+                }
+            },
+            None => added.push(WireChange::Added { uuid, wire }),
+        }
+    }
+    for id in remaining {
+        result.wires.push(WireChange::Removed {
+            uuid: old.wire_uuid(id).expect("Every wire has a uuid"),
+            wire: *old.get_wire(id).expect("Wire exists"),
+        });
+    }
+    result.wires.extend(added);
+    result
+}
+
+/// Compares two versions of a project. Circuits are matched up by name.
+pub fn diff_projects(old: &Project, new: &Project) -> ProjectDiff {
+    let mut result = ProjectDiff {
+        metadata_changed: old.metadata != new.metadata,
+        libraries_changed: old.libraries != new.libraries,
+        main_changed: old.main() != new.main(),
+        ..ProjectDiff::default()
+    };
+    for name in old.circuits().names() {
+        if new.circuit(name).is_none() {
+            result.removed.push(name.into());
+        }
+    }
+    for name in new.circuits().names() {
+        let circuit = new.circuit(name).expect("Name is registered");
+        match old.circuit(name) {
+            Some(old_circuit) => {
+                let diff = diff_circuits(old_circuit, circuit);
+                if !diff.is_empty() {
+                    result.modified.insert(name.into(), diff);
+                }
+            },
+            None => result.added.push(name.into()),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn diff_after_save_and_load() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        let b = circuit.add_component(lib.create("or_gate").unwrap(), 8, 0);
+        let c = circuit.add_component(lib.create("not_gate").unwrap(), 16, 0);
+        let w = circuit.add_wire(Wire::new((0, 0), (0, 4)));
+        circuit.add_wire(Wire::new((4, 0), (8, 0)));
+        let mut project = Project::new("test");
+        project.insert_circuit("top", circuit).unwrap();
+
+        let json = project.to_json();
+        let loaded = Project::from_json(&json, &lib).unwrap();
+        assert!(diff_projects(&project, &loaded).is_empty());
+        let binary = Circuit::from_bytes(&project.circuit("top").unwrap().to_bytes(), &lib).unwrap();
+        assert!(diff_circuits(project.circuit("top").unwrap(), &binary).is_empty());
+
+        let mut edited = project.circuit("top").unwrap().clone();
+        edited.move_component(a, 2, 2);
+        edited.set_property(b, "label", "Carry".into()).unwrap().unwrap();
+        edited.remove_component(c);
+        let d = edited.add_component(lib.create("xor_gate").unwrap(), 0, 8);
+        let uuid = edited.wire_uuid(w);
+        let wire = edited.remove_wire(w).unwrap();
+        edited.insert_wire_with_uuid(w, Wire::new(wire.start, (0, 6)), uuid);
+        let mut new_project = loaded.clone();
+        new_project.insert_circuit("top", edited.clone()).unwrap();
+        new_project.insert_circuit("other", Circuit::new()).unwrap();
+
+        let diff = diff_projects(&project, &new_project);
+        assert_eq!(diff.added, vec!["other".to_string()]);
+        assert!(!diff.metadata_changed);
+        let top = &diff.modified["top"];
+        let original = project.circuit("top").unwrap();
+        assert_eq!(top.components, vec![
+            ComponentChange::Removed { uuid: original.component_uuid(c).unwrap(), id: c, component: "not_gate".into() },
+            ComponentChange::Modified {
+                uuid: original.component_uuid(a).unwrap(),
+                id: a,
+                moved: Some(((0, 0), (2, 2))),
+                properties: vec![],
+            },
+            ComponentChange::Modified {
+                uuid: original.component_uuid(b).unwrap(),
+                id: b,
+                moved: None,
+                properties: vec![PropertyChange { name: "label".into(), old: Some("".into()), new: Some("Carry".into()) }],
+            },
+            ComponentChange::Added { uuid: edited.component_uuid(d).unwrap(), id: d, component: "xor_gate".into() },
+        ]);
+        assert_eq!(top.wires, vec![WireChange::Moved {
+            uuid: original.wire_uuid(w).unwrap(),
+            old: wire,
+            new: Wire::new((0, 0), (0, 6)),
+        }]);
+    }
+}
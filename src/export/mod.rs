@@ -0,0 +1,3 @@
+//! Conversion of circuits to formats used by other tools.
+
+pub mod verilog;
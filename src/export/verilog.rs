@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::error::Error;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::simulation::{Bit, Netlist, Resolution};
+use crate::subcircuit::{ports, port_names, INPUT_PIN, OUTPUT_PIN};
+
+/// Implemented by components which can be expressed in Verilog.
+pub trait HdlEmit {
+    /// Writes the statements implementing the component. `pins` holds an
+    /// expression for the signal attached to each pin, in the order returned
+    /// by `get_shape`.
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError>;
+}
+
+#[derive(Debug, Clone)]
+pub enum ExportError {
+    /// The component does not implement `HdlEmit`.
+    Unsupported { component: ComponentId, id: String },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Unsupported { component, id } =>
+                write!(f, "Component `{}` of type `{}` cannot be exported to Verilog", component, id),
+        }
+    }
+}
+
+impl Error for ExportError {}
+
+// Keywords which are likely to be used as circuit names
+const KEYWORDS: &[&str] = &[
+    "always", "and", "assign", "begin", "buf", "case", "else", "end", "if", "initial", "input",
+    "module", "nand", "nor", "not", "or", "output", "reg", "wire", "xnor", "xor",
+];
+
+/// Converts a name into a valid Verilog identifier.
+pub fn identifier(name: &str) -> String {
+    let mut result: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if !result.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        result.insert(0, '_');
+    }
+    if KEYWORDS.contains(&&*result) {
+        result.push('_');
+    }
+    result
+}
+
+/// A sized decimal literal for a value of the given width.
+pub fn literal(value: u64, width: u32) -> String {
+    format!("{}'d{}", width, value)
+}
+
+// The signal attached to a pin, given the net of each bit
+fn signal(nets: &[usize]) -> String {
+    match nets {
+        [net] => format!("n{}", net),
+        _ => {
+            let bits: Vec<_> = nets.iter().rev().map(|net| format!("n{}", net)).collect();
+            format!("{{{}}}", bits.join(", "))
+        },
+    }
+}
+
+fn range(bits: u32) -> String {
+    if bits == 1 { String::new() } else { format!("[{}:0] ", bits - 1) }
+}
+
+/// Collects the statements making up the body of a Verilog module. Modules
+/// for any subcircuits are written to the same output.
+pub struct ModuleWriter<'a> {
+    modules: &'a mut Modules,
+    declarations: Vec<String>,
+    statements: Vec<String>,
+    next_id: usize,
+}
+
+impl<'a> ModuleWriter<'a> {
+    /// Returns a new identifier within the module, which will not clash with
+    /// the names of nets or ports.
+    pub fn fresh(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}_{}", prefix, self.next_id)
+    }
+    /// Declares a register of the given width, returning its name.
+    pub fn reg(&mut self, prefix: &str, bits: u32) -> String {
+        let name = self.fresh(prefix);
+        self.declarations.push(format!("reg {}{} = {};", range(bits), name, literal(0, bits)));
+        name
+    }
+    pub fn assign(&mut self, target: &str, expr: &str) {
+        self.statements.push(format!("assign {} = {};", target, expr));
+    }
+    /// Adds an arbitrary statement, such as an `always` block.
+    pub fn statement<S: Into<String>>(&mut self, statement: S) {
+        self.statements.push(statement.into());
+    }
+    /// Ensures that a module is defined for a circuit, returning the name of
+    /// the module. Circuits are assumed to be uniquely named.
+    pub fn module(&mut self, name: &str, circuit: &Circuit) -> Result<String, ExportError> {
+        let name = identifier(name);
+        if self.modules.names.insert(name.clone()) {
+            write_module(self.modules, &name, circuit)?;
+        }
+        Ok(name)
+    }
+}
+
+#[derive(Default)]
+struct Modules {
+    names: BTreeSet<String>,
+    output: String,
+}
+
+fn write_module(modules: &mut Modules, name: &str, circuit: &Circuit) -> Result<(), ExportError> {
+    let netlist = Netlist::build(circuit);
+    let (inputs, outputs) = (ports(circuit, INPUT_PIN), ports(circuit, OUTPUT_PIN));
+    let mut writer = ModuleWriter {
+        modules,
+        declarations: Vec::new(),
+        statements: Vec::new(),
+        next_id: 0,
+    };
+
+    let (input_names, output_names) = port_names(circuit);
+    let port_names: Vec<_> = inputs.iter().zip(&input_names)
+        .chain(outputs.iter().zip(&output_names))
+        .map(|(&(id, _), name)| (id, name.clone()))
+        .collect();
+
+    for (&(id, ref pins), (_, info)) in netlist.components.iter().zip(circuit.components()) {
+        let signals: Vec<_> = pins.iter().map(|pin| signal(&pin.nets)).collect();
+        if let Some((_, port)) = port_names.iter().find(|&&(p, _)| p == id) {
+            match &*info.metadata().id {
+                INPUT_PIN => writer.assign(&signals[0], port),
+                _ => writer.assign(port, &signals[0]),
+            }
+            continue;
+        }
+        // Components which only join nets together are already accounted
+        // for by the netlist, and those without outputs have no effect.
+        let structural = !info.connections().is_empty() || info.tunnel_label().is_some()
+            || info.pull().is_some() || info.resolution().is_some();
+        if structural || pins.iter().all(|pin| !pin.direction.is_output()) {
+            continue;
+        }
+        match info.hdl() {
+            Some(hdl) => hdl.emit_verilog(&signals, &mut writer)?,
+            None => return Err(ExportError::Unsupported { component: id, id: info.metadata().id.to_string() }),
+        }
+    }
+    // Pulled nets are tied off if nothing else drives them
+    for (net, pull) in netlist.pulls.iter().enumerate() {
+        match pull {
+            Some(Bit::Zero) | Some(Bit::One) if netlist.drivers[net].is_empty() => {
+                let value = (*pull == Some(Bit::One)) as u64;
+                writer.assign(&format!("n{}", net), &literal(value, 1));
+            },
+            _ => {},
+        }
+    }
+
+    let ModuleWriter { modules, declarations, statements, .. } = writer;
+    let out = &mut modules.output;
+    let mut header: Vec<_> = inputs.iter().zip(&input_names)
+        .map(|(&(_, bits), name)| format!("input wire {}{}", range(bits), name))
+        .collect();
+    header.extend(outputs.iter().zip(&output_names).map(|(&(_, bits), name)| format!("output wire {}{}", range(bits), name)));
+    out.push_str(&format!("module {} (\n", name));
+    for (i, port) in header.iter().enumerate() {
+        let separator = if i + 1 < header.len() { "," } else { "" };
+        out.push_str(&format!("    {}{}\n", port, separator));
+    }
+    out.push_str(");\n");
+    for (net, resolution) in netlist.resolutions.iter().enumerate() {
+        let kind = match resolution {
+            Some(Resolution::WiredAnd) => "wand",
+            Some(Resolution::WiredOr) => "wor",
+            _ => "wire",
+        };
+        out.push_str(&format!("    {} n{};\n", kind, net));
+    }
+    for line in declarations.iter().chain(&statements) {
+        out.push_str(&format!("    {}\n", line));
+    }
+    out.push_str("endmodule\n\n");
+    Ok(())
+}
+
+/// Exports a circuit as a Verilog module with the given name, preceded by a
+/// module for each distinct subcircuit it uses. The module has a port for
+/// each input and output pin, named after the pin's label, or otherwise
+/// `in0`, `in1` and so on in the same order as the pins of a subcircuit.
+pub fn export(circuit: &Circuit, name: &str) -> Result<String, ExportError> {
+    let mut modules = Modules::default();
+    let name = identifier(name);
+    modules.names.insert(name.clone());
+    write_module(&mut modules, &name, circuit)?;
+    Ok(modules.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Library;
+    use crate::libraries::default_library;
+    use crate::subcircuit::CircuitRegistry;
+
+    fn half_adder(library: &Library) -> Circuit {
+        let mut circuit = Circuit::new();
+        let xor = circuit.add_component(library.create("xor_gate").unwrap(), 0, 0);
+        let pins: Vec<_> = circuit.get_component(xor).unwrap().get_shape().pins
+            .iter().map(|p| (p.x, p.y)).collect();
+        circuit.add_component(library.create("input_pin").unwrap(), pins[0].0 - 2, pins[0].1 - 1);
+        circuit.add_component(library.create("input_pin").unwrap(), pins[1].0 - 2, pins[1].1 - 1);
+        circuit.add_component(library.create("output_pin").unwrap(), pins[2].0, pins[2].1 - 1);
+        circuit
+    }
+
+    #[test]
+    fn export_modules() {
+        let mut library = default_library();
+        let circuit = half_adder(&library);
+        let verilog = export(&circuit, "half adder").unwrap();
+        assert!(verilog.starts_with("module half_adder (\n    input wire in0,\n    input wire in1,\n    output wire out0\n);\n"));
+        assert!(verilog.contains("assign n2 = n0 ^ n1;"), "{}", verilog);
+
+        let mut registry = CircuitRegistry::new();
+        registry.insert("xor", circuit).unwrap();
+        registry.register(&mut library).unwrap();
+        let mut top = Circuit::new();
+        top.add_component(library.create("xor").unwrap(), 0, 0);
+        top.add_component(library.create("xor").unwrap(), 0, 10);
+        let verilog = export(&top, "top").unwrap();
+        assert_eq!(verilog.matches("module xor_ (").count(), 1);
+        assert!(verilog.contains("xor_ u_1 (.in0(n0), .in1(n1), .out0(n2));"), "{}", verilog);
+
+        // Labelled pins name their ports
+        let mut labelled = half_adder(&library);
+        let pins: Vec<_> = ports(&labelled, INPUT_PIN).into_iter().chain(ports(&labelled, OUTPUT_PIN)).collect();
+        for (&(id, _), label) in pins.iter().zip(&["a", "a", "sum"]) {
+            labelled.get_component_mut(id).unwrap().set_property("label", (*label).into()).unwrap();
+        }
+        let verilog = export(&labelled, "half_adder").unwrap();
+        assert!(verilog.contains("input wire in0,\n    input wire in1,\n    output wire sum\n"), "{}", verilog);
+
+        top.add_component(library.create("button").unwrap(), 20, 0);
+        assert!(matches!(export(&top, "top"), Err(ExportError::Unsupported { .. })));
+    }
+}
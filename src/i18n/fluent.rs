@@ -0,0 +1,113 @@
+use std::fmt;
+use std::error::Error;
+
+use fluent_bundle::{FluentBundle, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+use super::Localizer;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FluentLocalizerError {
+    InvalidLocale(String),
+    /// Messages which could not be parsed, or which were already defined,
+    /// each with a description of the problem.
+    InvalidMessages(Vec<String>),
+}
+
+impl fmt::Display for FluentLocalizerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FluentLocalizerError::InvalidLocale(locale) => write!(f, "Invalid locale `{}`", locale),
+            FluentLocalizerError::InvalidMessages(errors) => write!(f, "Invalid messages: {}", errors.join("; ")),
+        }
+    }
+}
+
+impl Error for FluentLocalizerError {}
+
+/// Translates messages from Fluent (`.ftl`) files.
+pub struct FluentLocalizer {
+    bundle: FluentBundle<FluentResource>,
+}
+
+impl fmt::Debug for FluentLocalizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FluentLocalizer").field("locales", &self.bundle.locales).finish()
+    }
+}
+
+impl FluentLocalizer {
+    /// A localizer with no messages, for a locale such as `fr` or `pt-BR`.
+    pub fn new(locale: &str) -> Result<Self, FluentLocalizerError> {
+        let locale: LanguageIdentifier = locale.parse()
+            .map_err(|_| FluentLocalizerError::InvalidLocale(locale.into()))?;
+        let mut bundle = FluentBundle::new(vec![locale]);
+        // Translations are shown as plain text, not embedded in other text
+        bundle.set_use_isolating(false);
+        Ok(FluentLocalizer { bundle })
+    }
+    /// Adds the messages of a Fluent file. If some of them are invalid, the
+    /// rest are still added.
+    pub fn add_messages(&mut self, source: &str) -> Result<(), FluentLocalizerError> {
+        let (resource, mut errors) = match FluentResource::try_new(source.into()) {
+            Ok(resource) => (resource, Vec::new()),
+            Err((resource, errors)) => (resource, errors.iter().map(|e| e.to_string()).collect()),
+        };
+        if let Err(added) = self.bundle.add_resource(resource) {
+            errors.extend(added.iter().map(|e| e.to_string()));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FluentLocalizerError::InvalidMessages(errors))
+        }
+    }
+}
+
+impl Localizer for FluentLocalizer {
+    fn message(&self, key: &str) -> Option<String> {
+        let (id, attribute) = match key.find('.') {
+            Some(index) => (&key[..index], Some(&key[index + 1..])),
+            None => (key, None),
+        };
+        let message = self.bundle.get_message(id)?;
+        let pattern = match attribute {
+            Some(attribute) => message.get_attribute(attribute)?.value(),
+            None => message.value()?,
+        };
+        let mut errors = Vec::new();
+        let text = self.bundle.format_pattern(pattern, None, &mut errors);
+        if errors.is_empty() { Some(text.into_owned()) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::{localize_metadata, template};
+    use crate::libraries::gates;
+
+    #[test]
+    fn translates_from_fluent() {
+        let library = gates::library();
+        assert!(FluentLocalizer::new("not a locale").is_err());
+        let mut localizer = FluentLocalizer::new("fr").unwrap();
+        localizer.add_messages("component-and_gate = Porte ET\n    .description = Une porte ET.\n").unwrap();
+        assert!(localizer.add_messages("component-and_gate = Encore\n").is_err());
+
+        let and_gate = library.get_metadata("and_gate").unwrap();
+        let localized = localize_metadata(&and_gate, &localizer);
+        assert_eq!(localized.name, "Porte ET");
+        assert_eq!(localized.description, "Une porte ET.");
+        assert_eq!(localized.category, and_gate.category);
+
+        // The template translates everything to the defaults
+        let mut english = FluentLocalizer::new("en").unwrap();
+        english.add_messages(&template(&library)).unwrap();
+        for metadata in library.list() {
+            let localized = localize_metadata(&metadata, &english);
+            assert_eq!(localized.name, metadata.name);
+            assert_eq!(localized.category, metadata.category);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Translating the names and descriptions of components and their
+//! properties.
+//!
+//! The text in `ComponentMetadata` and `FieldSchema` is the English default.
+//! Each piece of text also has a message key, which a `Localizer` looks up
+//! to find its translation. Keys follow the conventions of Fluent, where a
+//! message has a value and may have attributes:
+//!
+//! ```text
+//! component-and_gate = AND gate
+//!     .description = Outputs 1 when all of its inputs are 1.
+//!     .preset-4-inputs = 4 inputs
+//! category-gates = Gates
+//! property-num_inputs = Number of inputs
+//! property-orientation = Orientation
+//!     .option-North = North
+//! group-placement = Placement
+//! ```
+//!
+//! Components and properties may choose their own keys, but otherwise are
+//! keyed by their ids. Categories and groups are keyed by their English
+//! names. Text without a translation falls back to the default, and
+//! `template` lists every message of a library for translators to start
+//! from.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::component::{FieldSchema, FieldType, Schema};
+use crate::library::{ComponentMetadata, Library};
+
+#[cfg(feature = "fluent")]
+mod fluent;
+#[cfg(feature = "fluent")]
+pub use self::fluent::{FluentLocalizer, FluentLocalizerError};
+
+/// Finds the translations of messages for a single language.
+pub trait Localizer {
+    /// The translation of a message, or `None` if it has not been
+    /// translated. A key of the form `message.attribute` refers to an
+    /// attribute of the message.
+    fn message(&self, key: &str) -> Option<String>;
+}
+
+impl Localizer for HashMap<String, String> {
+    fn message(&self, key: &str) -> Option<String> {
+        self.get(key).cloned()
+    }
+}
+
+impl Localizer for BTreeMap<String, String> {
+    fn message(&self, key: &str) -> Option<String> {
+        self.get(key).cloned()
+    }
+}
+
+fn localize(localizer: &Localizer, key: &str, default: &str) -> String {
+    localizer.message(key).unwrap_or_else(|| default.into())
+}
+
+// Makes text usable as part of a message key, which may only contain ASCII
+// letters, digits, `_` and `-`.
+fn slug(text: &str) -> String {
+    text.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '-' }).collect()
+}
+
+/// The key of the messages for a component.
+pub fn component_key(metadata: &ComponentMetadata) -> Cow<'_, str> {
+    match metadata.message_key {
+        Some(ref key) => Cow::Borrowed(key),
+        None => Cow::Owned(format!("component-{}", slug(&metadata.id))),
+    }
+}
+
+/// The key of the message for a property, given its name within the
+/// schema.
+pub fn field_key<'a>(property: &str, field: &'a FieldSchema) -> Cow<'a, str> {
+    match field.message_key {
+        Some(ref key) => Cow::Borrowed(key),
+        None => Cow::Owned(format!("property-{}", slug(property))),
+    }
+}
+
+pub fn category_key(category: &str) -> String {
+    format!("category-{}", slug(&category.to_lowercase()))
+}
+
+pub fn group_key(group: &str) -> String {
+    format!("group-{}", slug(&group.to_lowercase()))
+}
+
+// The attribute of a field's message which translates an option of an enum
+fn option_attribute(option: &str) -> String {
+    format!("option-{}", slug(option))
+}
+
+// The attribute of a component's message which translates the name of a
+// preset
+fn preset_attribute(preset: &str) -> String {
+    format!("preset-{}", slug(&preset.to_lowercase()))
+}
+
+/// The text of `ComponentMetadata`, translated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalizedMetadata {
+    pub name: String,
+    pub category: String,
+    pub description: String,
+    /// The names of the presets, in the same order.
+    pub presets: Vec<String>,
+}
+
+pub fn localize_metadata(metadata: &ComponentMetadata, localizer: &Localizer) -> LocalizedMetadata {
+    let key = component_key(metadata);
+    LocalizedMetadata {
+        name: localize(localizer, &key, &metadata.name),
+        category: localize_category(&metadata.category, localizer),
+        description: localize(localizer, &format!("{}.description", key), &metadata.description),
+        presets: metadata.presets.iter()
+            .map(|preset| localize(localizer, &format!("{}.{}", key, preset_attribute(&preset.name)), &preset.name))
+            .collect(),
+    }
+}
+
+pub fn localize_category(category: &str, localizer: &Localizer) -> String {
+    localize(localizer, &category_key(category), category)
+}
+
+pub fn localize_group(group: &str, localizer: &Localizer) -> String {
+    localize(localizer, &group_key(group), group)
+}
+
+/// The text of a `FieldSchema`, translated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalizedField {
+    pub name: String,
+    pub description: Option<String>,
+    pub group: Option<String>,
+    /// Labels for the options of an enum field, in the same order. The
+    /// values of the property are still the untranslated options.
+    pub options: Vec<String>,
+}
+
+pub fn localize_field(property: &str, field: &FieldSchema, localizer: &Localizer) -> LocalizedField {
+    let key = field_key(property, field);
+    let options = match field.type_ {
+        FieldType::Enum { ref options } => options.iter()
+            .map(|option| localize(localizer, &format!("{}.{}", key, option_attribute(option)), option))
+            .collect(),
+        _ => Vec::new(),
+    };
+    LocalizedField {
+        name: localize(localizer, &key, &field.name),
+        description: field.description.as_ref()
+            .map(|description| localize(localizer, &format!("{}.description", key), description)),
+        group: field.group.as_ref().map(|group| localize_group(group, localizer)),
+        options,
+    }
+}
+
+/// Translates every field of a schema, by the name of its property.
+pub fn localize_schema(schema: &Schema, localizer: &Localizer) -> BTreeMap<String, LocalizedField> {
+    schema.iter()
+        .map(|(property, field)| (property.to_string(), localize_field(property, field, localizer)))
+        .collect()
+}
+
+// Writes text as the value of a Fluent message, escaping braces and
+// indenting any further lines.
+fn write_text(out: &mut String, text: &str) {
+    let escaped = text.replace('{', "{\"{\"}").replace('}', "{\"}\"}");
+    let _ = writeln!(out, "{}", escaped.replace('\n', "\n    "));
+}
+
+/// A Fluent file containing the default text of every message used by a
+/// library, for translators to start from. Properties which share a key are
+/// only listed once.
+pub fn template(library: &Library) -> String {
+    let mut out = String::new();
+    let mut categories = BTreeSet::new();
+    let mut groups = BTreeSet::new();
+    let mut fields = BTreeMap::new();
+    let _ = writeln!(out, "## Components\n");
+    for metadata in library.list() {
+        let _ = write!(out, "{} = ", component_key(&metadata));
+        write_text(&mut out, &metadata.name);
+        if !metadata.description.is_empty() {
+            let _ = write!(out, "    .description = ");
+            write_text(&mut out, &metadata.description);
+        }
+        for preset in &metadata.presets {
+            let _ = write!(out, "    .{} = ", preset_attribute(&preset.name));
+            write_text(&mut out, &preset.name);
+        }
+        categories.insert(metadata.category.clone());
+        if let Ok(info) = library.create(&metadata.id) {
+            for (property, field) in info.schema() {
+                groups.extend(field.group.clone());
+                fields.entry(field_key(&property, &field).into_owned()).or_insert(field);
+            }
+        }
+    }
+
+    let _ = writeln!(out, "\n## Properties\n");
+    for (key, field) in fields {
+        let _ = write!(out, "{} = ", key);
+        write_text(&mut out, &field.name);
+        if let Some(ref description) = field.description {
+            let _ = write!(out, "    .description = ");
+            write_text(&mut out, description);
+        }
+        if let FieldType::Enum { ref options } = field.type_ {
+            for option in options {
+                let _ = write!(out, "    .{} = ", option_attribute(option));
+                write_text(&mut out, option);
+            }
+        }
+    }
+
+    let _ = writeln!(out, "\n## Categories\n");
+    for category in categories {
+        let _ = write!(out, "{} = ", category_key(&category));
+        write_text(&mut out, &category);
+    }
+    let _ = writeln!(out, "\n## Groups\n");
+    for group in groups {
+        let _ = write!(out, "{} = ", group_key(&group));
+        write_text(&mut out, &group);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn translates_with_fallback() {
+        let library = gates::library();
+        let localizer: BTreeMap<String, String> = vec![
+            ("component-and_gate", "Porte ET"),
+            ("category-gates", "Portes"),
+            ("property-num_inputs", "Nombre d'entrées"),
+            ("group-placement", "Placement"),
+            ("property-orientation.option-North", "Nord"),
+            ("component-and_gate.preset-4-inputs", "4 entrées"),
+        ].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        let and_gate = library.get_metadata("and_gate").unwrap();
+        let localized = localize_metadata(&and_gate, &localizer);
+        assert_eq!(localized.name, "Porte ET");
+        assert_eq!(localized.category, "Portes");
+        assert_eq!(localized.description, and_gate.description);
+        assert_eq!(localized.presets, vec!["4 entrées", "8 bits wide"]);
+        let or_gate = library.get_metadata("or_gate").unwrap();
+        assert_eq!(localize_metadata(&or_gate, &localizer).name, or_gate.name);
+        let renamed = (*or_gate).clone().with_message_key("component-and_gate");
+        assert_eq!(localize_metadata(&renamed, &localizer).name, "Porte ET");
+
+        let schema = library.create("and_gate").unwrap().schema();
+        let fields = localize_schema(&schema, &localizer);
+        assert_eq!(fields["num_inputs"].name, "Nombre d'entrées");
+        let orientation = &fields["orientation"];
+        assert_eq!(orientation.name, "Orientation");
+        assert_eq!(orientation.group.as_deref(), Some("Placement"));
+        assert_eq!(orientation.options[0], "Nord");
+        assert_eq!(orientation.options.len(), 4);
+
+        let template = template(&library);
+        assert!(template.contains(&format!("component-and_gate = {}\n", and_gate.name)), "{}", template);
+        assert!(template.contains("\nproperty-orientation = Orientation\n"), "{}", template);
+        assert!(template.contains("    .option-North = North\n"), "{}", template);
+        assert!(template.contains("    .preset-8-bits-wide = 8 bits wide\n"), "{}", template);
+        assert!(template.contains("\ngroup-placement = Placement\n"), "{}", template);
+        assert_eq!(template.matches("\nproperty-orientation = ").count(), 1);
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::error::Error;
+
+use serde_json::{self, json};
+
+use crate::circuit::{Circuit, Wire};
+use crate::component::ComponentInfo;
+use crate::library::Library;
+use crate::subcircuit::CircuitRegistry;
+use super::memimage;
+use super::xml::{self, Element, XmlError};
+
+// Logisim positions are in pixels, on a ten pixel grid
+const GRID: f64 = 10.0;
+
+// Directions in clockwise order, matching the order of `Orientation`
+const DIRECTIONS: [&str; 4] = ["east", "south", "west", "north"];
+
+/// How a Logisim component corresponds to a component in the library.
+struct Mapping {
+    id: &'static str,
+    // The pin located at the component's position in Logisim, or `None` if
+    // the position is the top-left corner.
+    anchor: Option<&'static str>,
+    // The direction the component faces when it is not rotated
+    facing: &'static str,
+    // The direction Logisim assumes when the attribute is omitted
+    default_facing: &'static str,
+}
+
+fn mapping(id: &'static str, anchor: Option<&'static str>) -> Mapping {
+    Mapping { id, anchor, facing: "east", default_facing: "east" }
+}
+
+fn facing(mapping: Mapping, facing: &'static str, default_facing: &'static str) -> Mapping {
+    Mapping { facing, default_facing, ..mapping }
+}
+
+fn find_mapping(name: &str, element: &Element) -> Option<Mapping> {
+    Some(match name {
+        "Pin" if element_attribute(element, "output") == Some("true") =>
+            facing(mapping("output_pin", Some("in")), "west", "east"),
+        "Pin" => mapping("input_pin", Some("out")),
+        "Probe" => facing(mapping("probe", Some("in")), "west", "east"),
+        "LED" => facing(mapping("led", Some("in")), "west", "west"),
+        "Hex Digit Display" => mapping("hex_display", Some("in")),
+        "Button" => mapping("button", Some("out")),
+        "Splitter" => mapping("splitter", Some("combined")),
+        "Tunnel" => facing(mapping("tunnel", Some("io")), "west", "west"),
+        "Pull Resistor" => mapping("pull_resistor", Some("io")),
+        "Constant" => mapping("constant", Some("out")),
+        "Power" => facing(mapping("power", Some("out")), "north", "north"),
+        "Ground" => facing(mapping("ground", Some("out")), "south", "south"),
+        "Clock" => mapping("clock", Some("out")),
+        "AND Gate" => mapping("and_gate", Some("out")),
+        "OR Gate" => mapping("or_gate", Some("out")),
+        "XOR Gate" => mapping("xor_gate", Some("out")),
+        "NAND Gate" => mapping("nand_gate", Some("out")),
+        "NOR Gate" => mapping("nor_gate", Some("out")),
+        "XNOR Gate" => mapping("xnor_gate", Some("out")),
+        "Odd Parity" => mapping("odd_parity", Some("out")),
+        "Even Parity" => mapping("even_parity", Some("out")),
+        "NOT Gate" => mapping("not_gate", Some("out")),
+        "Buffer" => mapping("buffer", Some("out")),
+        "Controlled Buffer" => mapping("controlled_buffer", Some("out")),
+        "Controlled Inverter" => mapping("controlled_inverter", Some("out")),
+        "Multiplexer" => mapping("multiplexer", Some("out")),
+        "Demultiplexer" => mapping("demultiplexer", Some("in")),
+        "Decoder" => mapping("decoder", Some("sel")),
+        "Priority Encoder" => mapping("priority_encoder", Some("out")),
+        "Adder" => mapping("adder", Some("out")),
+        "Subtractor" => mapping("subtractor", Some("out")),
+        "Multiplier" => mapping("multiplier", Some("out")),
+        "Negator" => mapping("negator", Some("out")),
+        "Divider" => mapping("divider", Some("out")),
+        "Comparator" => mapping("comparator", Some("eq")),
+        "Shifter" => mapping("shifter", Some("out")),
+        "D Flip-Flop" => mapping("d_flip_flop", None),
+        "J-K Flip-Flop" => mapping("jk_flip_flop", None),
+        "S-R Flip-Flop" => mapping("sr_flip_flop", None),
+        "T Flip-Flop" => mapping("t_flip_flop", None),
+        "Register" => mapping("register", None),
+        "Counter" => mapping("counter", None),
+        "Random" => mapping("random", None),
+        "RAM" => mapping("ram", None),
+        "ROM" => mapping("rom", None),
+        _ => return None,
+    })
+}
+
+fn element_attribute<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+    element.children_named("a").find(|a| a.attribute("name") == Some(name)).and_then(|a| a.attribute("val"))
+}
+
+/// Converts a Logisim attribute to the name and value of a property. An
+/// element of a list property is named `list.index`. Returns `None` if the attribute has no equivalent, or an error message if
+/// the value could not be converted.
+fn convert_attribute(name: &str, value: &str) -> Option<Result<(String, serde_json::Value), String>> {
+    let int = |property: &str| Some(value.parse::<i64>()
+        .map(|v| (property.to_string(), json!(v)))
+        .map_err(|_| format!("`{}` is not an integer", value)));
+    let choice = |property: &str, options: &[(&str, &str)]| Some(options.iter()
+        .find(|(o, _)| *o == value)
+        .map(|(_, v)| (property.to_string(), json!(v)))
+        .ok_or_else(|| format!("`{}` is not supported", value)));
+    let indexed = |prefix: &str| name.get(prefix.len()..).filter(|_| name.starts_with(prefix));
+    match name {
+        "width" => int("num_bits"),
+        "incoming" => int("num_bits"),
+        "fanout" => int("fan_out"),
+        "inputs" => int("num_inputs"),
+        "select" => int("select_bits"),
+        "addrWidth" => int("address_bits"),
+        "dataWidth" => int("data_bits"),
+        "highDuration" => int("high_ticks"),
+        "lowDuration" => int("low_ticks"),
+        "label" => Some(Ok(("label".into(), json!(value)))),
+        "contents" => Some(memimage::parse_hex_text(value)
+            .map(|contents| ("contents".into(), contents.into()))
+            .map_err(|e| e.to_string())),
+        // Constant values are parsed by the component, as they depend on its width
+        "value" => Some(Ok(("value".into(), json!(value)))),
+        "facing" => None,
+        "trigger" => choice("trigger", &[("rising", "RisingEdge"), ("falling", "FallingEdge")]),
+        "pull" => choice("direction", &[("0", "Down"), ("1", "Up")]),
+        "mode" => choice("signed", &[("twosComplement", "true"), ("unsigned", "false")])
+            .map(|r| r.map(|(p, v)| (p, json!(v == "true")))),
+        "shift" => choice("shift_type", &[
+            ("ll", "LogicalLeft"), ("lr", "LogicalRight"), ("ar", "ArithmeticRight"),
+            ("rl", "RotateLeft"), ("rr", "RotateRight"),
+        ]),
+        _ => {
+            if let Some(i) = indexed("negate") {
+                Some(match value {
+                    "true" | "false" => Ok((format!("invert_inputs.{}", i), json!(value == "true"))),
+                    _ => Err(format!("`{}` is not a boolean", value)),
+                })
+            } else if let Some(i) = indexed("bit") {
+                int(&format!("bit_{}", i))
+            } else {
+                None
+            }
+        },
+    }
+}
+
+// Properties which others depend on are set first, such as the number of
+// inputs of a gate before whether each input is inverted.
+fn attribute_order(name: &str) -> u32 {
+    if name.starts_with("negate") || name.starts_with("bit") { 1 } else { 0 }
+}
+
+fn parse_point(value: &str) -> Option<(i32, i32)> {
+    let inner = value.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut parts = inner.split(',').map(|p| p.trim().parse::<f64>().ok());
+    let (x, y) = (parts.next()??, parts.next()??);
+    Some(((x / GRID).round() as i32, (y / GRID).round() as i32))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// The component has no equivalent in the library, and was skipped.
+    UnmappedComponent { library: Option<String>, name: String },
+    /// The attribute is meaningful but cannot be represented.
+    UnsupportedAttribute { component: String, name: String },
+    /// The attribute has an equivalent property, but its value was rejected.
+    InvalidAttribute { component: String, name: String, value: String, error: String },
+    /// The element is malformed, such as a component or wire without a valid
+    /// location.
+    InvalidElement { element: String },
+}
+
+/// A problem with part of a file which did not prevent the rest of it from
+/// being imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportWarning {
+    pub circuit: String,
+    /// The location of the affected component, in grid units.
+    pub location: Option<(i32, i32)>,
+    pub kind: WarningKind,
+}
+
+impl fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "In circuit `{}`", self.circuit)?;
+        if let Some((x, y)) = self.location {
+            write!(f, " at ({}, {})", x, y)?;
+        }
+        match &self.kind {
+            WarningKind::UnmappedComponent { library: Some(library), name } =>
+                write!(f, ": component `{}` from library `{}` is not supported", name, library),
+            WarningKind::UnmappedComponent { library: None, name } =>
+                write!(f, ": component `{}` is not supported", name),
+            WarningKind::UnsupportedAttribute { component, name } =>
+                write!(f, ": attribute `{}` of `{}` is not supported", name, component),
+            WarningKind::InvalidAttribute { component, name, value, error } =>
+                write!(f, ": attribute `{}` of `{}` has invalid value `{}`: {}", name, component, value, error),
+            WarningKind::InvalidElement { element } =>
+                write!(f, ": invalid `{}` element", element),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportError {
+    Xml(XmlError),
+    /// The document is not a Logisim project.
+    NotAProject,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Xml(e) => e.fmt(f),
+            ImportError::NotAProject => write!(f, "The file is not a Logisim project"),
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            ImportError::Xml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<XmlError> for ImportError {
+    fn from(e: XmlError) -> Self {
+        ImportError::Xml(e)
+    }
+}
+
+/// The result of importing a Logisim project.
+#[derive(Debug, Clone)]
+pub struct LogisimProject {
+    /// Each circuit in the project, which may be used as subcircuits of the
+    /// others.
+    pub circuits: CircuitRegistry,
+    /// The name of the main circuit, if the project specifies one.
+    pub main: Option<String>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+struct Importer<'a> {
+    library: Library,
+    // The description of each Logisim library, by number
+    libraries: BTreeMap<&'a str, &'a str>,
+    warnings: Vec<ImportWarning>,
+}
+
+impl<'a> Importer<'a> {
+    fn warn(&mut self, circuit: &str, location: Option<(i32, i32)>, kind: WarningKind) {
+        self.warnings.push(ImportWarning { circuit: circuit.into(), location, kind });
+    }
+    fn component(&mut self, circuit: &str, element: &Element) -> Option<ComponentInfo> {
+        let name = element.attribute("name").unwrap_or("");
+        let location = element.attribute("loc").and_then(parse_point);
+        let location = match location {
+            Some(location) => location,
+            None => {
+                self.warn(circuit, None, WarningKind::InvalidElement { element: "comp".into() });
+                return None;
+            },
+        };
+        let library = element.attribute("lib");
+        let mapping = match library {
+            // Components without a library are subcircuits
+            None => Some(mapping("", None)).filter(|_| self.library.contains(name)),
+            Some(_) => find_mapping(name, element),
+        };
+        let (mut info, mapping) = match mapping {
+            Some(mapping) => {
+                let id = if mapping.id.is_empty() { name } else { mapping.id };
+                (self.library.create(id).ok()?, mapping)
+            },
+            None => {
+                let library = library.map(|l| self.libraries.get(l).cloned().unwrap_or(l).trim_start_matches('#').to_string());
+                self.warn(circuit, Some(location), WarningKind::UnmappedComponent { library, name: name.into() });
+                return None;
+            },
+        };
+
+        let mut attributes: Vec<_> = element.children_named("a")
+            .filter_map(|a| Some((a.attribute("name")?, a.attribute("val").unwrap_or(""))))
+            .collect();
+        attributes.sort_by_key(|&(n, _)| attribute_order(n));
+        for (attribute, value) in attributes {
+            let invalid = |error: String| WarningKind::InvalidAttribute {
+                component: name.into(),
+                name: attribute.into(),
+                value: value.into(),
+                error,
+            };
+            match convert_attribute(attribute, value) {
+                Some(Ok((property, value))) => {
+                    let (list, index) = match property.find('.') {
+                        Some(dot) => (&property[..dot], property[dot + 1..].parse().ok()),
+                        None => (&*property, None),
+                    };
+                    if info.schema().contains_key(list) {
+                        let result = match index {
+                            Some(index) => info.set_list_element(list, index, value),
+                            None => info.set_property(list, value),
+                        };
+                        if let Err(e) = result {
+                            self.warn(circuit, Some(location), invalid(e.to_string()));
+                        }
+                    }
+                },
+                Some(Err(error)) => self.warn(circuit, Some(location), invalid(error)),
+                None => {},
+            }
+        }
+
+        let facing = element_attribute(element, "facing").unwrap_or(mapping.default_facing);
+        let index = |d: &str| DIRECTIONS.iter().position(|&o| o == d);
+        if let (Some(facing), Some(base)) = (index(facing), index(mapping.facing)) {
+            let orientation = ["North", "East", "South", "West"][(facing + 4 - base) % 4];
+            info.set_property("orientation", json!(orientation)).expect("Every component has an orientation");
+        }
+
+        let shape = info.get_shape();
+        let offset = mapping.anchor
+            .and_then(|anchor| shape.pins.iter().find(|p| p.name == anchor))
+            .map(|pin| (pin.x, pin.y))
+            .unwrap_or((0, 0));
+        info.set_position(location.0 - offset.0, location.1 - offset.1);
+        Some(info)
+    }
+    fn circuit(&mut self, element: &Element) -> Circuit {
+        let name = element.attribute("name").unwrap_or("");
+        let mut circuit = Circuit::new();
+        for child in &element.children {
+            match &*child.name {
+                "wire" => {
+                    let from = child.attribute("from").and_then(parse_point);
+                    let to = child.attribute("to").and_then(parse_point);
+                    match (from, to) {
+                        (Some(from), Some(to)) => { circuit.add_wire(Wire::new(from, to)); },
+                        _ => self.warn(name, None, WarningKind::InvalidElement { element: "wire".into() }),
+                    }
+                },
+                "comp" => {
+                    if let Some(info) = self.component(name, child) {
+                        let (x, y) = info.position();
+                        circuit.add_component(info, x, y);
+                    }
+                },
+                _ => {},
+            }
+        }
+        circuit
+    }
+}
+
+/// Imports the circuits from the contents of a Logisim `.circ` file. Each
+/// component is mapped to the equivalent from `library`, and any which have
+/// no equivalent are reported as warnings and skipped.
+///
+/// Components are placed so that the pin at their Logisim location lines up
+/// with the original wires. Other pins may be positioned differently.
+pub fn import(source: &str, library: &Library) -> Result<LogisimProject, ImportError> {
+    let root = xml::parse(source)?;
+    if root.name != "project" {
+        return Err(ImportError::NotAProject);
+    }
+    let mut importer = Importer {
+        library: library.clone(),
+        libraries: root.children_named("lib")
+            .filter_map(|l| Some((l.attribute("name")?, l.attribute("desc")?)))
+            .collect(),
+        warnings: Vec::new(),
+    };
+
+    // Circuits are imported after those they use as subcircuits, so that
+    // they are available in the library.
+    let mut pending: Vec<_> = root.children_named("circuit").collect();
+    let names: BTreeSet<_> = pending.iter().filter_map(|c| c.attribute("name")).collect();
+    let mut registry = CircuitRegistry::new();
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|c| {
+            c.children_named("comp")
+                .filter(|comp| comp.attribute("lib").is_none())
+                .filter_map(|comp| comp.attribute("name"))
+                .all(|n| !names.contains(n) || registry.get(n).is_some())
+        });
+        // A cycle of circuits cannot be fully imported: the first is
+        // imported without its subcircuits, which are reported as unmapped.
+        let element = pending.remove(ready.unwrap_or(0));
+        let name = element.attribute("name").unwrap_or("");
+        let circuit = importer.circuit(element);
+        if registry.insert(name, circuit).is_ok() {
+            // Imported circuits have no parameters, so they always elaborate
+            registry.register(&mut importer.library).ok();
+        }
+    }
+
+    let main = root.children_named("main").next().and_then(|m| m.attribute("name")).map(String::from);
+    Ok(LogisimProject {
+        circuits: registry,
+        main,
+        warnings: importer.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::default_library;
+    use crate::analysis::TruthTable;
+
+    const PROJECT: &str = r##"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<project source="2.7.1" version="1.0">
+  <lib desc="#Wiring" name="0"/>
+  <lib desc="#Gates" name="1"/>
+  <lib desc="#Base" name="6"/>
+  <main name="main"/>
+  <circuit name="inverter">
+    <a name="circuit" val="inverter"/>
+    <comp lib="0" loc="(100,100)" name="Pin"/>
+    <comp lib="1" loc="(140,100)" name="NOT Gate">
+      <a name="size" val="30"/>
+    </comp>
+    <wire from="(100,100)" to="(110,100)"/>
+    <comp lib="0" loc="(160,100)" name="Pin">
+      <a name="output" val="true"/>
+      <a name="facing" val="west"/>
+    </comp>
+    <wire from="(140,100)" to="(160,100)"/>
+  </circuit>
+  <circuit name="main">
+    <comp loc="(200,200)" name="inverter"/>
+    <comp lib="1" loc="(300,200)" name="AND Gate">
+      <a name="inputs" val="3"/>
+      <a name="negate1" val="true"/>
+      <a name="width" val="x"/>
+    </comp>
+    <comp lib="6" loc="(0,0)" name="Text"/>
+  </circuit>
+</project>
+"##;
+
+    #[test]
+    fn import_project() {
+        let project = import(PROJECT, &default_library()).unwrap();
+        assert_eq!(project.main.as_ref().map(|m| &**m), Some("main"));
+        assert_eq!(project.circuits.names().collect::<Vec<_>>(), vec!["inverter", "main"]);
+
+        let main = project.circuits.get("main").unwrap();
+        assert_eq!(main.num_components(), 2);
+        let gate = main.components().map(|(_, info)| info).find(|info| info.metadata().id == "and_gate").unwrap();
+        assert_eq!(gate.get_property("num_inputs"), Some(json!(3)));
+        assert_eq!(gate.get_property("invert_inputs"), Some(json!([false, true, false])));
+        let out = gate.get_shape().pins.last().unwrap().clone();
+        assert_eq!((gate.position().0 + out.x, gate.position().1 + out.y), (30, 20));
+
+        assert_eq!(project.warnings.len(), 2);
+        assert!(matches!(&project.warnings[0].kind, WarningKind::InvalidAttribute { name, .. } if name == "width"));
+        assert_eq!(project.warnings[1], ImportWarning {
+            circuit: "main".into(),
+            location: Some((0, 0)),
+            kind: WarningKind::UnmappedComponent { library: Some("Base".into()), name: "Text".into() },
+        });
+
+        // The NOT gate's input is not at the Logisim location, but its
+        // output and the pins line up with the wires.
+        let inverter = project.circuits.get("inverter").unwrap();
+        let table = TruthTable::generate(inverter).unwrap();
+        assert_eq!(table.to_string(), "0 | 1\n1 | 0\n");
+
+        assert!(matches!(import("<circuit/>", &default_library()), Err(ImportError::NotAProject)));
+    }
+}
@@ -0,0 +1,223 @@
+//! Loading RAM and ROM contents from the image files produced by assemblers
+//! and other simulators.
+//!
+//! Intel HEX files and raw binary images are byte addressed. For memories
+//! wider than eight bits, each word is assembled from consecutive bytes,
+//! least significant byte first.
+
+use std::fmt;
+use std::error::Error;
+
+use crate::component::ComponentInfo;
+use crate::bits::{MemoryContents, MAX_ADDRESS_BITS};
+
+// The largest memory holds this many words of up to eight bytes each
+const MAX_BYTES: usize = 8 << MAX_ADDRESS_BITS;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Records of the form `:LLAAAATT...CC`.
+    IntelHex,
+    /// Whitespace separated hexadecimal words, as accepted by the `contents`
+    /// property. Lines may start with an address such as `1f:`, and Logisim
+    /// headers such as `v2.0 raw` are skipped.
+    HexText,
+    /// The bytes of the image, with no framing.
+    Binary,
+}
+
+impl ImageFormat {
+    /// Guesses the format of an image from its contents.
+    pub fn detect(bytes: &[u8]) -> Self {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) if text.chars().all(|c| !c.is_control() || c.is_whitespace()) => text,
+            _ => return ImageFormat::Binary,
+        };
+        if text.trim_start().starts_with(':') {
+            ImageFormat::IntelHex
+        } else {
+            ImageFormat::HexText
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageError {
+    /// The line of a text image on which the error occurred.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ImageError {
+    fn new<S: Into<String>>(line: Option<usize>, message: S) -> Self {
+        ImageError { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Invalid memory image on line {}: {}", line, self.message),
+            None => write!(f, "Invalid memory image: {}", self.message),
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+fn hex_byte(text: &str, index: usize) -> Option<u8> {
+    text.get(index * 2..index * 2 + 2).and_then(|s| u8::from_str_radix(s, 16).ok())
+}
+
+/// Parses an Intel HEX file into the bytes it describes. Gaps between
+/// records are filled with zeros.
+pub fn parse_intel_hex(text: &str) -> Result<Vec<u8>, ImageError> {
+    let mut result = Vec::new();
+    let mut base = 0usize;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = Some(index + 1);
+        let error = |message: &str| ImageError::new(line_number, message);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line.strip_prefix(':').ok_or_else(|| error("Expected a record starting with `:`"))?;
+        if record.len() % 2 != 0 || !record.chars().all(|c| c.is_ascii_hexdigit()) || record.len() < 10 {
+            return Err(error("Malformed record"));
+        }
+        let bytes: Vec<u8> = (0..record.len() / 2).map(|i| hex_byte(record, i).expect("Digits were checked")).collect();
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(error("Record length does not match its data"));
+        }
+        if bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) != 0 {
+            return Err(error("Checksum mismatch"));
+        }
+        let offset = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let data = &bytes[4..4 + len];
+        let value = || data.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+        match bytes[3] {
+            0x00 => {
+                let start = base + offset;
+                if start + len > MAX_BYTES {
+                    return Err(error(&format!("Address {:x} is beyond the largest memory", start + len - 1)));
+                }
+                if result.len() < start + len {
+                    result.resize(start + len, 0);
+                }
+                result[start..start + len].copy_from_slice(data);
+            },
+            0x01 => break,
+            0x02 if len == 2 => base = value() << 4,
+            0x04 if len == 2 => base = value() << 16,
+            // Start addresses have no meaning for a memory
+            0x03 | 0x05 => {},
+            t => return Err(error(&format!("Unsupported record type {:02x}", t))),
+        }
+    }
+    Ok(result)
+}
+
+/// Parses words written as hexadecimal text.
+pub fn parse_hex_text(text: &str) -> Result<MemoryContents, ImageError> {
+    let mut contents = MemoryContents::new();
+    let mut address = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = Some(index + 1);
+        let mut line = line.split('#').next().unwrap_or("").trim();
+        if line.starts_with("v2.0 raw") || line.starts_with("v3.0 hex") || line.starts_with("addr/data:") {
+            continue;
+        }
+        if let Some(colon) = line.find(':') {
+            address = usize::from_str_radix(line[..colon].trim(), 16)
+                .map_err(|_| ImageError::new(line_number, format!("Invalid address `{}`", &line[..colon])))?;
+            line = &line[colon + 1..];
+        }
+        let words = MemoryContents::parse(line).map_err(|e| ImageError::new(line_number, e))?;
+        if address.checked_add(words.len()).map_or(true, |end| end > 1 << MAX_ADDRESS_BITS) {
+            return Err(ImageError::new(line_number, "Contents are too long"));
+        }
+        for &word in words.words() {
+            contents.write(address, word);
+            address += 1;
+        }
+    }
+    Ok(contents)
+}
+
+/// Assembles bytes into words of the given width, least significant byte
+/// first.
+pub fn words_from_bytes(bytes: &[u8], data_bits: u32) -> MemoryContents {
+    let bytes_per_word = ((data_bits + 7) / 8).max(1) as usize;
+    let words = bytes.chunks(bytes_per_word)
+        .map(|chunk| chunk.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64))
+        .collect();
+    MemoryContents::from_words(words)
+}
+
+/// Loads an image for a memory with words of the given width.
+pub fn load(bytes: &[u8], format: ImageFormat, data_bits: u32) -> Result<MemoryContents, ImageError> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| ImageError::new(None, "The image is not text"));
+    let contents = match format {
+        ImageFormat::IntelHex => words_from_bytes(&parse_intel_hex(text()?)?, data_bits),
+        ImageFormat::HexText => parse_hex_text(text()?)?,
+        ImageFormat::Binary => {
+            if bytes.len() > MAX_BYTES {
+                return Err(ImageError::new(None, "The image is larger than the largest memory"));
+            }
+            words_from_bytes(bytes, data_bits)
+        },
+    };
+    contents.check(MAX_ADDRESS_BITS, data_bits).map_err(|e| ImageError::new(None, e))?;
+    Ok(contents)
+}
+
+/// Loads an image into the `contents` property of a RAM or ROM.
+pub fn load_into(info: &mut ComponentInfo, bytes: &[u8], format: ImageFormat) -> Result<(), ImageError> {
+    let data_bits = info.get_property("data_bits")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ImageError::new(None, "The component is not a memory"))?;
+    let contents = load(bytes, format, data_bits as u32)?;
+    info.set_property("contents", contents.into())
+        .map_err(|e| ImageError::new(None, e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::memory;
+
+    const INTEL_HEX: &str = ":0400000001020304F2\n:02000004000FEB\n:0100000005FA\n:00000001FF\n";
+
+    #[test]
+    fn load_images() {
+        assert_eq!(ImageFormat::detect(INTEL_HEX.as_bytes()), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::detect(b"v2.0 raw\n0 1 2"), ImageFormat::HexText);
+        assert_eq!(ImageFormat::detect(&[0, 1, 2, 0xff]), ImageFormat::Binary);
+
+        // Extended linear address records move later data
+        let bytes = parse_intel_hex(INTEL_HEX).unwrap();
+        assert_eq!(&bytes[..4], &[1, 2, 3, 4]);
+        assert_eq!(bytes.len(), 0xf0001);
+        assert_eq!(bytes[0xf0000], 5);
+        let bad = INTEL_HEX.replacen("F2", "F3", 1);
+        assert_eq!(parse_intel_hex(&bad).unwrap_err().line, Some(1));
+
+        let words = load(&[0x34, 0x12, 0x78], ImageFormat::Binary, 16).unwrap();
+        assert_eq!(words.words(), &[0x1234, 0x78]);
+        assert!(load(&[0x10], ImageFormat::Binary, 4).is_err());
+
+        let text = "v3.0 hex words addressed\n00: 1 2 3\n10: 4*ff # padding\n";
+        let words = load(text.as_bytes(), ImageFormat::HexText, 8).unwrap();
+        assert_eq!(words.read(2), 3);
+        assert_eq!(words.read(0x13), 0xff);
+        assert_eq!(parse_hex_text("0 1\nzz").unwrap_err().line, Some(2));
+        assert_eq!(parse_hex_text("0 1\nffffffffffffffff: 1").unwrap_err().line, Some(2));
+
+        let mut rom = memory::library().create("rom").unwrap();
+        load_into(&mut rom, INTEL_HEX.as_bytes(), ImageFormat::IntelHex).unwrap_err();
+        load_into(&mut rom, &INTEL_HEX.as_bytes()[..20], ImageFormat::IntelHex).unwrap();
+        assert_eq!(rom.get_property("contents"), Some("1 2 3 4".into()));
+    }
+}
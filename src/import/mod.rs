@@ -0,0 +1,7 @@
+//! Conversion of circuits from formats used by other tools.
+
+pub mod logisim;
+pub mod memimage;
+mod xml;
+
+pub use self::xml::XmlError;
@@ -0,0 +1,214 @@
+//! A minimal XML parser, sufficient for the files produced by other circuit
+//! editors. Namespaces, DTDs and processing instructions are ignored.
+
+use std::fmt;
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlError {
+    /// The byte offset in the input at which the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for XmlError {}
+
+/// Parses a document, returning its root element.
+pub fn parse(input: &str) -> Result<Element, XmlError> {
+    let mut parser = Parser { input, position: 0 };
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+    if parser.position < input.len() {
+        return Err(parser.error("Unexpected content after the root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> XmlError {
+        XmlError { position: self.position, message: message.into() }
+    }
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.position += s.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, s: &str) -> Result<(), XmlError> {
+        if self.eat(s) { Ok(()) } else { Err(self.error(&format!("Expected `{}`", s))) }
+    }
+    // Advances past the next occurrence of `end`
+    fn skip_until(&mut self, end: &str) -> Result<&'a str, XmlError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                let skipped = &self.rest()[..i];
+                self.position += i + end.len();
+                Ok(skipped)
+            },
+            None => Err(self.error(&format!("Expected `{}`", end))),
+        }
+    }
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.position = self.input.len() - trimmed.len();
+    }
+    // Skips whitespace, comments, and declarations outside the root element
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<?") {
+                self.skip_until("?>")?;
+            } else if self.eat("<!--") {
+                self.skip_until("-->")?;
+            } else if self.eat("<!") {
+                self.skip_until(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+    fn name(&mut self) -> Result<String, XmlError> {
+        let len = self.rest()
+            .find(|c: char| c.is_whitespace() || "=/>\"'".contains(c))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error("Expected a name"));
+        }
+        let name = &self.rest()[..len];
+        self.position += len;
+        Ok(name.into())
+    }
+    fn unescape(&self, text: &str) -> Result<String, XmlError> {
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find('&') {
+            result.push_str(&rest[..i]);
+            let end = rest[i..].find(';').ok_or_else(|| self.error("Unterminated entity"))?;
+            let entity = &rest[i + 1..i + end];
+            let c = match entity {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| self.error("Invalid character reference"))?,
+                _ if entity.starts_with('#') => entity[1..].parse().ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| self.error("Invalid character reference"))?,
+                _ => return Err(self.error(&format!("Unknown entity `{}`", entity))),
+            };
+            result.push(c);
+            rest = &rest[i + end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+    fn element(&mut self) -> Result<Element, XmlError> {
+        self.expect("<")?;
+        let mut element = Element { name: self.name()?, ..Default::default() };
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.eat("\"") { "\"" } else { self.expect("'")?; "'" };
+            let value = self.skip_until(quote)?;
+            element.attributes.push((name, self.unescape(value)?));
+        }
+        loop {
+            if self.eat("</") {
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(self.error(&format!("Expected `</{}>`", element.name)));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            } else if self.eat("<!--") {
+                self.skip_until("-->")?;
+            } else if self.eat("<![CDATA[") {
+                let text = self.skip_until("]]>")?;
+                element.text.push_str(text);
+            } else if self.rest().starts_with('<') {
+                element.children.push(self.element()?);
+            } else if self.rest().is_empty() {
+                return Err(self.error(&format!("Expected `</{}>`", element.name)));
+            } else {
+                let len = self.rest().find('<').unwrap_or(self.rest().len());
+                let text = &self.rest()[..len];
+                element.text.push_str(&self.unescape(text)?);
+                self.position += len;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_document() {
+        let root = parse(r#"<?xml version="1.0"?>
+            <!-- comment -->
+            <a x="1 &lt; 2" y='&#65;'>
+                text &amp; more<b/><![CDATA[<raw>]]>
+                <c z="3"></c>
+            </a>
+        "#).unwrap();
+        assert_eq!(root.name, "a");
+        assert_eq!(root.attribute("x"), Some("1 < 2"));
+        assert_eq!(root.attribute("y"), Some("A"));
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.children_named("c").next().unwrap().attribute("z"), Some("3"));
+        assert!(root.text.contains("text & more"));
+        assert!(root.text.contains("<raw>"));
+
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a x=1/>").is_err());
+        assert_eq!(parse("<a/><b/>").unwrap_err().position, 4);
+    }
+}
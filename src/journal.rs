@@ -0,0 +1,320 @@
+//! An append-only journal of edits, for recovering work after a crash.
+//!
+//! Saving a whole circuit after every edit is expensive, so instead an
+//! application saves it occasionally and, in between, appends each edit to a
+//! journal as it is applied. After a crash, `recover` replays the journal
+//! over the last save.
+//!
+//! A typical application enables the journal on its `CommandStack`, writes
+//! out `take_journal()` after each edit, and calls `Journal::checkpoint`
+//! whenever it saves the circuit in full.
+//!
+//! Each entry is written as a line of JSON. Wires are identified by their
+//! endpoints rather than their ids, since wire ids are not preserved when a
+//! circuit is saved and loaded.
+
+use std::fmt;
+use std::error::Error;
+use std::io::{self, Write};
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+use uuid::Uuid;
+
+use crate::circuit::{Circuit, ComponentId, Wire};
+use crate::library::Library;
+use crate::persist::{self, CircuitFile, ComponentRecord, LoadError};
+use crate::undo::{Command, EditError};
+
+/// A single edit, in the form it was applied to the circuit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    /// The circuit was saved in full. Only the entries after the last
+    /// checkpoint are replayed.
+    Checkpoint {
+        next_id: u64,
+    },
+    InsertComponent(ComponentRecord),
+    RemoveComponent {
+        id: ComponentId,
+    },
+    MoveComponent {
+        id: ComponentId,
+        x: i32,
+        y: i32,
+    },
+    SetProperty {
+        id: ComponentId,
+        name: String,
+        value: serde_json::Value,
+    },
+    AddWire {
+        wire: Wire,
+        uuid: Uuid,
+    },
+    RemoveWire {
+        wire: Wire,
+    },
+}
+
+impl JournalEntry {
+    /// The entry for an edit which has just been applied, given the command
+    /// which reverses it. Returns `None` for groups, whose commands are
+    /// journaled individually.
+    pub(crate) fn from_inverse(inverse: &Command, circuit: &Circuit) -> Option<JournalEntry> {
+        Some(match *inverse {
+            Command::RemoveComponent { id } => JournalEntry::InsertComponent(
+                persist::save_component(id, circuit.get_component(id)?, circuit.component_uuid(id))
+            ),
+            Command::InsertComponent { id, .. } => JournalEntry::RemoveComponent { id },
+            Command::MoveComponent { id, .. } => {
+                let (x, y) = circuit.get_component(id)?.position();
+                JournalEntry::MoveComponent { id, x, y }
+            },
+            Command::SetProperty { id, ref name, .. } => JournalEntry::SetProperty {
+                id,
+                name: name.clone(),
+                value: circuit.get_component(id)?.get_property(name)?,
+            },
+            Command::RemoveWire { id } => JournalEntry::AddWire {
+                wire: *circuit.get_wire(id)?,
+                uuid: circuit.wire_uuid(id)?,
+            },
+            Command::InsertWire { wire, .. } => JournalEntry::RemoveWire { wire },
+            Command::AddComponent { .. } | Command::AddWire { .. } | Command::Group(_) => return None,
+        })
+    }
+
+    fn replay(self, circuit: &mut Circuit, library: &Library) -> Result<(), EntryError> {
+        let command = match self {
+            JournalEntry::Checkpoint { next_id } => {
+                circuit.reserve_ids(next_id);
+                return Ok(());
+            },
+            JournalEntry::InsertComponent(record) => {
+                let (component, _) = persist::load_component(&record, library)
+                    .map_err(EntryError::Component)?;
+                circuit.insert_component_with_uuid(record.id, component, record.uuid);
+                return Ok(());
+            },
+            JournalEntry::RemoveComponent { id } => Command::RemoveComponent { id },
+            JournalEntry::MoveComponent { id, x, y } => Command::MoveComponent { id, x, y },
+            JournalEntry::SetProperty { id, name, value } => Command::SetProperty { id, name, value },
+            JournalEntry::AddWire { wire, uuid } => {
+                circuit.add_wire_with_uuid(wire, Some(uuid));
+                return Ok(());
+            },
+            JournalEntry::RemoveWire { wire } => {
+                let id = circuit.wires()
+                    .find(|&(_, &w)| w == wire)
+                    .map(|(id, _)| id)
+                    .ok_or(EntryError::MissingWire(wire))?;
+                Command::RemoveWire { id }
+            },
+        };
+        command.apply(circuit).map_err(EntryError::Edit)?;
+        Ok(())
+    }
+}
+
+/// Writes journal entries to a file or other sink, one line per entry.
+#[derive(Debug)]
+pub struct Journal<W> {
+    writer: W,
+}
+
+impl<W: Write> Journal<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+    /// Writes the entries and flushes the sink, so that they survive if the
+    /// application then crashes.
+    pub fn append(&mut self, entries: &[JournalEntry]) -> io::Result<()> {
+        for entry in entries {
+            let line = serde_json::to_string(entry).expect("Journal entries are serializable");
+            writeln!(self.writer, "{}", line)?;
+        }
+        self.writer.flush()
+    }
+    /// Records that the circuit has just been saved in full. The journal may
+    /// then be truncated, since the entries before the checkpoint are no
+    /// longer needed.
+    pub fn checkpoint(&mut self, circuit: &Circuit) -> io::Result<()> {
+        self.append(&[JournalEntry::Checkpoint { next_id: circuit.next_id() }])
+    }
+}
+
+/// Why a single journal entry could not be replayed.
+#[derive(Debug)]
+pub enum EntryError {
+    Json(serde_json::Error),
+    Component(LoadError),
+    Edit(EditError),
+    MissingWire(Wire),
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryError::Json(e) => write!(f, "Invalid entry: {}", e),
+            EntryError::Component(e) => e.fmt(f),
+            EntryError::Edit(e) => e.fmt(f),
+            EntryError::MissingWire(wire) =>
+                write!(f, "No wire from {:?} to {:?}", wire.start, wire.end),
+        }
+    }
+}
+
+impl Error for EntryError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            EntryError::Json(e) => Some(e),
+            EntryError::Component(e) => Some(e),
+            EntryError::Edit(e) => Some(e),
+            EntryError::MissingWire(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecoveryError {
+    /// The save itself could not be loaded.
+    Load(LoadError),
+    Entry {
+        /// The line of the journal, counting from one.
+        line: usize,
+        error: EntryError,
+    },
+}
+
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecoveryError::Load(e) => e.fmt(f),
+            RecoveryError::Entry { line, error } => write!(f, "Journal line {}: {}", line, error),
+        }
+    }
+}
+
+impl Error for RecoveryError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            RecoveryError::Load(e) => Some(e),
+            RecoveryError::Entry { error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<LoadError> for RecoveryError {
+    fn from(e: LoadError) -> Self {
+        RecoveryError::Load(e)
+    }
+}
+
+/// A circuit recovered from a save and a journal.
+#[derive(Debug)]
+pub struct Recovery {
+    pub circuit: Circuit,
+    /// The number of edits which were replayed over the save.
+    pub replayed: usize,
+}
+
+/// Parses the complete lines of a journal, along with their line numbers. A
+/// final line without a newline was cut short by a crash while it was being
+/// written, and is ignored.
+fn parse(journal: &[u8]) -> Result<Vec<(usize, JournalEntry)>, RecoveryError> {
+    let mut lines: Vec<_> = journal.split(|&b| b == b'\n').collect();
+    // The last element follows the final newline, so is empty or incomplete
+    lines.pop();
+    lines.into_iter()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| match serde_json::from_slice(line) {
+            Ok(entry) => Ok((index + 1, entry)),
+            Err(e) => Err(RecoveryError::Entry { line: index + 1, error: EntryError::Json(e) }),
+        })
+        .collect()
+}
+
+/// Loads the last full save of a circuit, and replays the edits journaled
+/// since it was made.
+pub fn recover(save: CircuitFile, journal: &[u8], library: &Library) -> Result<Recovery, RecoveryError> {
+    let mut circuit = persist::load(save, library)?;
+    let entries = parse(journal)?;
+    let start = entries.iter()
+        .rposition(|(_, entry)| matches!(entry, JournalEntry::Checkpoint { .. }))
+        .unwrap_or(0);
+    let mut replayed = 0;
+    for (line, entry) in entries.into_iter().skip(start) {
+        if !matches!(entry, JournalEntry::Checkpoint { .. }) {
+            replayed += 1;
+        }
+        entry.replay(&mut circuit, library)
+            .map_err(|error| RecoveryError::Entry { line, error })?;
+    }
+    Ok(Recovery { circuit, replayed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+    use crate::undo::CommandStack;
+
+    #[test]
+    fn replays_edits_over_save() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let mut stack = CommandStack::new();
+        let first = circuit.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        circuit.add_wire(Wire::new((0, 0), (0, 4)));
+        circuit.add_wire(Wire::new((4, 0), (8, 0)));
+
+        let save = persist::save(&circuit);
+        let mut journal = Journal::new(Vec::new());
+        journal.checkpoint(&circuit).unwrap();
+        stack.enable_journal();
+
+        let second = stack.add_component(&mut circuit, lib.create("or_gate").unwrap(), 3, 3);
+        stack.execute(&mut circuit, Command::Group(vec![
+            Command::MoveComponent { id: first, x: 2, y: 1 },
+            Command::SetProperty { id: second, name: "num_inputs".into(), value: 3.into() },
+        ])).unwrap();
+        let wire = circuit.wires().find(|(_, w)| w.start == (4, 0)).unwrap().0;
+        stack.execute(&mut circuit, Command::RemoveWire { id: wire }).unwrap();
+        stack.add_wire(&mut circuit, Wire::new((1, 1), (1, 5)));
+        stack.undo(&mut circuit).unwrap();
+        assert!(stack.execute(&mut circuit, Command::Group(vec![
+            Command::MoveComponent { id: first, x: 9, y: 9 },
+            Command::RemoveComponent { id: ComponentId(100) },
+        ])).is_err());
+        journal.append(&stack.take_journal()).unwrap();
+        assert!(stack.take_journal().is_empty());
+
+        let mut bytes = journal.into_inner();
+        // An entry cut short by a crash
+        bytes.extend_from_slice(br#"{"MoveComponent":{"id":0,"#);
+
+        let recovery = recover(save, &bytes, &lib).unwrap();
+        assert_eq!(recovery.replayed, 6);
+        let recovered = recovery.circuit;
+        assert_eq!(recovered.get_component(first).unwrap().position(), (2, 1));
+        let or_gate = recovered.get_component(second).unwrap();
+        assert_eq!(or_gate.position(), (3, 3));
+        assert_eq!(or_gate.get_property("num_inputs"), Some(3.into()));
+        let wires: Vec<_> = recovered.wires().map(|(_, &w)| w).collect();
+        assert_eq!(wires, vec![Wire::new((0, 0), (0, 4))]);
+        assert_eq!(recovered.next_id(), circuit.next_id());
+        let or_uuid = circuit.component_uuid(second).unwrap();
+        assert_eq!(recovered.component_uuid(second), Some(or_uuid));
+
+        let error = recover(persist::save(&Circuit::new()), b"\n{\"RemoveComponent\":{\"id\":5}}\n", &lib);
+        match error {
+            Err(RecoveryError::Entry { line: 2, error: EntryError::Edit(_) }) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,10 +1,46 @@
+// Allows code generated by `tenorite-ui-derive` to refer to this crate by name
+extern crate self as tenorite_ui;
+
 use std::collections::BTreeMap;
 use std::fmt;
 use std::any::Any;
 
+pub mod analysis;
+pub mod audio;
+pub mod bits;
+pub mod check;
+pub mod circuit;
+pub mod clipboard;
+pub mod collab;
+pub mod compiled;
 pub mod component;
+pub mod debugger;
+pub mod diff;
+pub mod export;
+pub mod i18n;
+pub mod import;
+pub mod journal;
 pub mod library;
 pub mod libraries;
+pub mod migration;
+pub mod net;
+pub mod palette;
+pub mod parameters;
+pub mod persist;
+pub mod plugin;
+pub mod project;
+pub mod protocol;
+pub mod render;
+pub mod routing;
+pub mod selection;
+pub mod simulation;
+pub mod subcircuit;
+pub mod symbol;
+pub mod testing;
+pub mod trace;
+pub mod undo;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 struct Plan {
 }
@@ -0,0 +1,168 @@
+//! Components which document a circuit without affecting it, such as text
+//! labels and boxes around related parts. They are placed, selected and
+//! saved like any other component, but have no pins and are skipped when
+//! building nets.
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Shape, Color};
+use crate::render::DrawContext;
+
+pub const CATEGORY: &'static str = "Annotations";
+
+// Font sizes are given in points, with ten points to a grid unit
+const POINTS_PER_UNIT: f64 = 10.0;
+// The width of a character relative to the font size, for sizing labels
+const CHAR_WIDTH: f64 = 0.6;
+
+fn default_color() -> Color {
+    Color::rgb(0x22, 0x22, 0x22)
+}
+
+/// A line of free text.
+#[derive(Debug, Clone, Component)]
+struct Label {
+    #[property(name = "Text")]
+    text: String,
+    #[property(name = "Font size", min = 4, max = 96, unit = "pt")]
+    font_size: u32,
+    #[property(name = "Color")]
+    color: Color,
+}
+
+impl Label {
+    fn text_height(&self) -> f64 {
+        self.font_size as f64 / POINTS_PER_UNIT
+    }
+}
+
+impl Component for Label {
+    fn get_shape(&self) -> Shape {
+        let chars = self.text.chars().count().max(1) as f64;
+        Shape {
+            width: (chars * self.text_height() * CHAR_WIDTH).ceil().max(1.0) as i32,
+            height: self.text_height().ceil().max(1.0) as i32,
+            pins: Vec::new(),
+            image_name: "label".into(),
+        }
+    }
+    fn is_annotation(&self) -> bool {
+        true
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        let center = (shape.width as f64 / 2.0, shape.height as f64 / 2.0);
+        ctx.text(center, self.text_height(), &self.text, &self.color.to_hex());
+    }
+}
+
+/// A rectangle, usually drawn around a group of components.
+#[derive(Debug, Clone, Component)]
+struct Rectangle {
+    #[property(name = "Width", min = 1, max = 512)]
+    width: u32,
+    #[property(name = "Height", min = 1, max = 512)]
+    height: u32,
+    #[property(name = "Color")]
+    color: Color,
+    #[property(name = "Fill")]
+    fill: Color,
+}
+
+impl Component for Rectangle {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: self.width as i32,
+            height: self.height as i32,
+            pins: Vec::new(),
+            image_name: "rectangle".into(),
+        }
+    }
+    fn is_annotation(&self) -> bool {
+        true
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let size = (self.width as f64, self.height as f64);
+        let fill = if self.fill.a == 0 { None } else { Some(self.fill.to_hex()) };
+        ctx.rect((0.0, 0.0), size, fill.as_ref().map(|s| s.as_str()), Some(&self.color.to_hex()));
+    }
+}
+
+/// An arrow pointing east, which can be rotated to point elsewhere.
+#[derive(Debug, Clone, Component)]
+struct Arrow {
+    #[property(name = "Length", min = 1, max = 512)]
+    length: u32,
+    #[property(name = "Color")]
+    color: Color,
+}
+
+impl Component for Arrow {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: self.length as i32,
+            height: 1,
+            pins: Vec::new(),
+            image_name: "arrow".into(),
+        }
+    }
+    fn is_annotation(&self) -> bool {
+        true
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let color = self.color.to_hex();
+        let end = self.length as f64;
+        ctx.line((0.0, 0.5), (end - 0.4, 0.5), &color);
+        ctx.polygon(vec![(end, 0.5), (end - 0.5, 0.2), (end - 0.5, 0.8)], Some(&color), None);
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("label", "Label", CATEGORY, "Text describing part of the circuit"),
+        || Box::new(Label { text: "Label".into(), font_size: 10, color: default_color() })
+    );
+    result.add(
+        ComponentMetadata::new("rectangle", "Rectangle", CATEGORY, "A box drawn around part of the circuit"),
+        || Box::new(Rectangle { width: 8, height: 6, color: default_color(), fill: Color::rgba(0, 0, 0, 0) })
+    );
+    result.add(
+        ComponentMetadata::new("arrow", "Arrow", CATEGORY, "An arrow pointing at part of the circuit"),
+        || Box::new(Arrow { length: 4, color: default_color() })
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Circuit, Wire};
+    use crate::net::Connectivity;
+    use crate::persist;
+    use crate::simulation::Simulator;
+
+    #[test]
+    fn annotations_are_not_electrical() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let mut label = lib.create("label").unwrap();
+        label.set_property("text", "Adder".into()).unwrap();
+        label.set_property("font_size", 20.into()).unwrap();
+        assert_eq!((label.get_shape().width, label.get_shape().height), (6, 2));
+        let label = circuit.add_component(label, 0, 0);
+        circuit.add_component(lib.create("rectangle").unwrap(), -1, -1);
+        circuit.add_wire(Wire::new((0, 1), (6, 1)));
+        let before = Connectivity::build(&circuit).nets().len();
+        circuit.add_component(lib.create("arrow").unwrap(), 0, 1);
+        assert_eq!(Connectivity::build(&circuit).nets().len(), before);
+
+        let mut sim = Simulator::new(circuit);
+        sim.tick().unwrap();
+        assert_eq!(sim.display_value(label), None);
+
+        let saved = persist::save(sim.circuit());
+        let loaded = persist::load(saved, &lib).unwrap();
+        assert_eq!(loaded.get_component(label).unwrap().get_property("text"), Some("Adder".into()));
+        assert!(loaded.get_component(label).unwrap().is_annotation());
+    }
+}
@@ -0,0 +1,731 @@
+use std::cmp::Ordering;
+
+use serde_json;
+use maplit::btreemap;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, FieldSchema, FieldType, ReflectType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::export::verilog::{HdlEmit, ModuleWriter, ExportError, literal};
+use super::data_bits_field;
+
+pub const CATEGORY: &'static str = "Arithmetic";
+
+// Arithmetic is performed on little-endian bit vectors so that it works for
+// any data width. A `None` operand means at least one bit was undefined.
+
+fn to_bools(value: &Value) -> Option<Vec<bool>> {
+    value.bits().iter().map(|b| b.to_bool()).collect()
+}
+
+fn from_bools(bits: &[bool]) -> Value {
+    Value::new(bits.iter().map(|&b| Bit::from_bool(b)).collect())
+}
+
+fn operand(inputs: &[Value], index: usize, width: u32) -> Option<Vec<bool>> {
+    let mut result = to_bools(inputs.get(index)?)?;
+    result.resize(width as usize, false);
+    Some(result)
+}
+
+// Unconnected carry inputs are treated as zero
+fn carry_in(inputs: &[Value], index: usize) -> Option<bool> {
+    match inputs.get(index).map(|v| v.bit(0)) {
+        None | Some(Bit::HighZ) => Some(false),
+        Some(bit) => bit.to_bool(),
+    }
+}
+
+fn add(a: &[bool], b: &[bool], mut carry: bool) -> (Vec<bool>, bool) {
+    let sum = a.iter().zip(b).map(|(&x, &y)| {
+        let s = x ^ y ^ carry;
+        carry = (x & y) | (carry & (x ^ y));
+        s
+    }).collect();
+    (sum, carry)
+}
+
+fn invert(a: &[bool]) -> Vec<bool> {
+    a.iter().map(|&b| !b).collect()
+}
+
+fn negate(a: &[bool]) -> Vec<bool> {
+    add(&invert(a), &vec![false; a.len()], true).0
+}
+
+fn extend(a: &[bool], width: usize, signed: bool) -> Vec<bool> {
+    let fill = signed && a.last().cloned().unwrap_or(false);
+    let mut result = a.to_vec();
+    result.resize(width, fill);
+    result
+}
+
+// Returns the full double-width product
+fn multiply(a: &[bool], b: &[bool], signed: bool) -> Vec<bool> {
+    let width = a.len() * 2;
+    let a = extend(a, width, signed);
+    let b = extend(b, width, signed);
+    let mut result = vec![false; width];
+    for (shift, &bit) in b.iter().enumerate() {
+        if bit {
+            let mut shifted = vec![false; shift];
+            shifted.extend_from_slice(&a[..width - shift]);
+            result = add(&result, &shifted, false).0;
+        }
+    }
+    result
+}
+
+// Long division of unsigned values, returning the quotient and remainder.
+// The divisor must not be zero.
+fn divide_unsigned(a: &[bool], b: &[bool]) -> (Vec<bool>, Vec<bool>) {
+    let width = a.len();
+    // One extra bit so that shifting the partial remainder cannot overflow
+    let b = extend(b, width + 1, false);
+    let mut quotient = vec![false; width];
+    let mut remainder = vec![false; width + 1];
+    for i in (0..width).rev() {
+        remainder.pop();
+        remainder.insert(0, a[i]);
+        if compare(&remainder, &b, false) != Ordering::Less {
+            remainder = add(&remainder, &invert(&b), true).0;
+            quotient[i] = true;
+        }
+    }
+    remainder.truncate(width);
+    (quotient, remainder)
+}
+
+// Signed division truncates towards zero, and the remainder takes the sign
+// of the dividend
+fn divide(a: &[bool], b: &[bool], signed: bool) -> (Vec<bool>, Vec<bool>) {
+    let negative = |x: &[bool]| signed && x.last().cloned().unwrap_or(false);
+    let magnitude = |x: &[bool]| if negative(x) { negate(x) } else { x.to_vec() };
+    let (quotient, remainder) = divide_unsigned(&magnitude(a), &magnitude(b));
+    (
+        if negative(a) != negative(b) { negate(&quotient) } else { quotient },
+        if negative(a) { negate(&remainder) } else { remainder },
+    )
+}
+
+fn compare(a: &[bool], b: &[bool], signed: bool) -> Ordering {
+    let msb = a.len().saturating_sub(1);
+    a.iter().zip(b).enumerate().rev()
+        .map(|(i, (&x, &y))| if signed && i == msb { y.cmp(&x) } else { x.cmp(&y) })
+        .find(|&o| o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ArithmeticType {
+    Adder,
+    Subtractor,
+    Multiplier,
+    Comparator,
+    Negator,
+    Divider,
+    Remainder,
+}
+
+impl ArithmeticType {
+    fn image_name(self) -> &'static str {
+        match self {
+            ArithmeticType::Adder => "adder",
+            ArithmeticType::Subtractor => "subtractor",
+            ArithmeticType::Multiplier => "multiplier",
+            ArithmeticType::Comparator => "comparator",
+            ArithmeticType::Negator => "negator",
+            ArithmeticType::Divider => "divider",
+            ArithmeticType::Remainder => "remainder",
+        }
+    }
+    fn has_signedness(self) -> bool {
+        match self {
+            ArithmeticType::Multiplier | ArithmeticType::Comparator |
+            ArithmeticType::Divider | ArithmeticType::Remainder => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ArithmeticUnit {
+    type_: ArithmeticType,
+    num_bits: u32,
+    signed: bool,
+}
+
+impl ArithmeticUnit {
+    fn new(type_: ArithmeticType) -> Self {
+        Self {
+            type_,
+            num_bits: 8,
+            signed: false,
+        }
+    }
+    fn compute(&self, inputs: &[Value]) -> Option<Vec<Vec<bool>>> {
+        let w = self.num_bits;
+        Some(match self.type_ {
+            ArithmeticType::Adder => {
+                let (sum, carry) = add(&operand(inputs, 0, w)?, &operand(inputs, 1, w)?, carry_in(inputs, 2)?);
+                vec![sum, vec![carry]]
+            },
+            ArithmeticType::Subtractor => {
+                // a - b - borrow == a + !b + !borrow
+                let b = invert(&operand(inputs, 1, w)?);
+                let (diff, carry) = add(&operand(inputs, 0, w)?, &b, !carry_in(inputs, 2)?);
+                vec![diff, vec![!carry]]
+            },
+            ArithmeticType::Multiplier => {
+                let product = multiply(&operand(inputs, 0, w)?, &operand(inputs, 1, w)?, self.signed);
+                let carry = extend(&operand(inputs, 2, w).unwrap_or_else(|| vec![false; w as usize]), product.len(), self.signed);
+                let (mut product, _) = add(&product, &carry, false);
+                let high = product.split_off(w as usize);
+                vec![product, high]
+            },
+            ArithmeticType::Comparator => {
+                let order = compare(&operand(inputs, 0, w)?, &operand(inputs, 1, w)?, self.signed);
+                vec![
+                    vec![order == Ordering::Greater],
+                    vec![order == Ordering::Equal],
+                    vec![order == Ordering::Less],
+                ]
+            },
+            ArithmeticType::Negator => vec![negate(&operand(inputs, 0, w)?)],
+            ArithmeticType::Divider | ArithmeticType::Remainder => {
+                // Dividing by zero gives a quotient of all ones and leaves
+                // the dividend as the remainder, as RISC-V does
+                let (a, b) = (operand(inputs, 0, w)?, operand(inputs, 1, w)?);
+                let by_zero = b.iter().all(|&bit| !bit);
+                let (quotient, remainder) = if by_zero {
+                    (vec![true; w as usize], a)
+                } else {
+                    divide(&a, &b, self.signed)
+                };
+                if self.type_ == ArithmeticType::Divider {
+                    vec![quotient, remainder, vec![by_zero]]
+                } else {
+                    vec![remainder, vec![by_zero]]
+                }
+            },
+        })
+    }
+    fn outputs(&self) -> Vec<(&'static str, u32)> {
+        match self.type_ {
+            ArithmeticType::Adder | ArithmeticType::Subtractor => vec![("out", self.num_bits), ("carry_out", 1)],
+            ArithmeticType::Multiplier => vec![("out", self.num_bits), ("carry_out", self.num_bits)],
+            ArithmeticType::Comparator => vec![("gt", 1), ("eq", 1), ("lt", 1)],
+            ArithmeticType::Negator => vec![("out", self.num_bits)],
+            ArithmeticType::Divider => vec![("out", self.num_bits), ("remainder", self.num_bits), ("div_zero", 1)],
+            ArithmeticType::Remainder => vec![("out", self.num_bits), ("div_zero", 1)],
+        }
+    }
+    fn inputs(&self) -> Vec<(&'static str, u32)> {
+        match self.type_ {
+            ArithmeticType::Adder | ArithmeticType::Subtractor => vec![("a", self.num_bits), ("b", self.num_bits), ("carry_in", 1)],
+            ArithmeticType::Multiplier => vec![("a", self.num_bits), ("b", self.num_bits), ("carry_in", self.num_bits)],
+            ArithmeticType::Comparator | ArithmeticType::Divider | ArithmeticType::Remainder =>
+                vec![("a", self.num_bits), ("b", self.num_bits)],
+            ArithmeticType::Negator => vec![("in", self.num_bits)],
+        }
+    }
+}
+
+impl Properties for ArithmeticUnit {
+    fn schema(&self) -> Schema {
+        let mut result = btreemap!{
+            "num_bits".into() => data_bits_field(),
+        };
+        if self.type_.has_signedness() {
+            result.insert("signed".into(), FieldSchema::new("Signed", bool::field_type())
+                .with_description("Treat values as two's complement"));
+        }
+        result
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "signed" if self.type_.has_signedness() => {
+                self.signed = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "signed" if self.type_.has_signedness() => serde_json::to_value(self.signed).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for ArithmeticUnit {
+    fn get_shape(&self) -> Shape {
+        // Operands on the left, results on the right, and carries on the
+        // top and bottom edges.
+        let positions = |names: Vec<(&'static str, u32)>, x: i32, direction| {
+            names.into_iter().enumerate().map(move |(i, (name, bits))| {
+                let (x, y) = match name {
+                    "carry_in" => (2, 0),
+                    "carry_out" => (2, 4),
+                    _ => (x, i as i32 + 1),
+                };
+                Pin { x, y, name: name.into(), bits, direction }
+            })
+        };
+        let mut pins: Vec<_> = positions(self.inputs(), 0, PinDirection::Input).collect();
+        pins.extend(positions(self.outputs(), 4, PinDirection::Output));
+        Shape {
+            width: 4,
+            height: 4,
+            pins,
+            image_name: self.type_.image_name().into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let mut result: Vec<_> = self.inputs().into_iter().map(|(_, bits)| Value::floating(bits)).collect();
+        match self.compute(inputs) {
+            Some(outputs) => result.extend(outputs.iter().map(|o| from_bools(o))),
+            None => result.extend(self.outputs().into_iter().map(|(_, bits)| Value::error(bits))),
+        }
+        result
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for ArithmeticUnit {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let signed = |expr: &str| if self.signed { format!("$signed({})", expr) } else { expr.into() };
+        let operand = |i: usize| signed(&pins[i]);
+        match self.type_ {
+            ArithmeticType::Adder => {
+                let target = format!("{{{}, {}}}", pins[4], pins[3]);
+                module.assign(&target, &format!("{} + {} + {}", pins[0], pins[1], pins[2]));
+            },
+            ArithmeticType::Subtractor => {
+                let target = format!("{{{}, {}}}", pins[4], pins[3]);
+                module.assign(&target, &format!("{} - {} - {}", pins[0], pins[1], pins[2]));
+            },
+            ArithmeticType::Multiplier => {
+                let target = format!("{{{}, {}}}", pins[4], pins[3]);
+                // Every operand, including the carry, must be signed for the
+                // product to be
+                module.assign(&target, &format!("{} * {} + {}", operand(0), operand(1), operand(2)));
+            },
+            ArithmeticType::Comparator => {
+                for (i, op) in [">", "==", "<"].iter().enumerate() {
+                    module.assign(&pins[i + 2], &format!("{} {} {}", operand(0), op, operand(1)));
+                }
+            },
+            ArithmeticType::Negator => {
+                module.assign(&pins[1], &format!("-{}", pins[0]));
+            },
+            ArithmeticType::Divider | ArithmeticType::Remainder => {
+                // Both arms of a conditional must be signed for it to be
+                let by_zero = format!("{} == 0", pins[1]);
+                let mut outputs = pins[2..].iter();
+                if self.type_ == ArithmeticType::Divider {
+                    let all_ones = signed(&format!("~{}", literal(0, self.num_bits)));
+                    let quotient = format!("{} ? {} : {} / {}", by_zero, all_ones, operand(0), operand(1));
+                    module.assign(outputs.next().unwrap(), &quotient);
+                }
+                let remainder = format!("{} ? {} : {} % {}", by_zero, operand(0), operand(0), operand(1));
+                module.assign(outputs.next().unwrap(), &remainder);
+                module.assign(outputs.next().unwrap(), &by_zero);
+            },
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum ShiftType {
+    LogicalLeft,
+    LogicalRight,
+    ArithmeticRight,
+    RotateLeft,
+    RotateRight,
+}
+
+impl ReflectType for ShiftType {
+    fn field_type() -> FieldType {
+        use self::ShiftType::*;
+        FieldType::for_enum(&[LogicalLeft, LogicalRight, ArithmeticRight, RotateLeft, RotateRight])
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Shifter {
+    num_bits: u32,
+    shift_type: ShiftType,
+}
+
+impl Shifter {
+    // Enough bits to express any distance up to `num_bits - 1`
+    fn distance_bits(&self) -> u32 {
+        let mut result = 1;
+        while (1u64 << result) < self.num_bits as u64 {
+            result += 1;
+        }
+        result
+    }
+    fn shift(&self, data: &[bool], distance: usize) -> Vec<bool> {
+        let w = data.len();
+        // Rotates wrap around, while shifting by the width or more shifts
+        // out every bit
+        let distance = match self.shift_type {
+            ShiftType::RotateLeft | ShiftType::RotateRight => distance % w.max(1),
+            _ => distance.min(w),
+        };
+        let msb = data.last().cloned().unwrap_or(false);
+        (0..w).map(|i| match self.shift_type {
+            ShiftType::LogicalLeft => i >= distance && data[i - distance],
+            ShiftType::LogicalRight => i + distance < w && data[i + distance],
+            ShiftType::ArithmeticRight => if i + distance < w { data[i + distance] } else { msb },
+            ShiftType::RotateLeft => data[(i + w - distance) % w],
+            ShiftType::RotateRight => data[(i + distance) % w],
+        }).collect()
+    }
+}
+
+impl Properties for Shifter {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => data_bits_field(),
+            "shift_type".into() => FieldSchema::new("Shift type", ShiftType::field_type()),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "shift_type" => {
+                self.shift_type = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "shift_type" => serde_json::to_value(self.shift_type).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Shifter {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 4,
+            height: 4,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "in".into(), bits: self.num_bits, direction: PinDirection::Input },
+                Pin { x: 0, y: 3, name: "distance".into(), bits: self.distance_bits(), direction: PinDirection::Input },
+                Pin { x: 4, y: 2, name: "out".into(), bits: self.num_bits, direction: PinDirection::Output },
+            ],
+            image_name: "shifter".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let out = operand(inputs, 0, self.num_bits)
+            .and_then(|data| {
+                let distance = inputs.get(1)?.to_u64()?;
+                Some(from_bools(&self.shift(&data, distance as usize)))
+            })
+            .unwrap_or_else(|| Value::error(self.num_bits));
+        vec![Value::floating(self.num_bits), Value::floating(self.distance_bits()), out]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Shifter {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let (data, distance) = (&pins[0], &pins[1]);
+        let expr = match self.shift_type {
+            ShiftType::LogicalLeft => format!("{} << {}", data, distance),
+            ShiftType::LogicalRight => format!("{} >> {}", data, distance),
+            ShiftType::ArithmeticRight => format!("$signed({}) >>> {}", data, distance),
+            // Rotates wrap the distance, as in `shift`
+            ShiftType::RotateLeft =>
+                format!("({0} << ({1} % {2})) | ({0} >> ({2} - {1} % {2}))", data, distance, self.num_bits),
+            ShiftType::RotateRight =>
+                format!("({0} >> ({1} % {2})) | ({0} << ({2} - {1} % {2}))", data, distance, self.num_bits),
+        };
+        module.assign(&pins[2], &expr);
+        Ok(())
+    }
+}
+
+// The number of decimal digits needed for any value of `num_bits` bits
+fn decimal_digits(num_bits: u32) -> u32 {
+    let max = if num_bits >= 64 { u64::max_value() } else { (1 << num_bits) - 1 };
+    max.to_string().len() as u32
+}
+
+/// Converts between binary and binary-coded decimal, with one four-bit pin
+/// per decimal digit, `d0` being the units. Converting from a digit above
+/// nine, or a number which does not fit in the binary output, is an error.
+#[derive(Debug, Clone)]
+struct BcdConverter {
+    num_bits: u32,
+    to_bcd: bool,
+}
+
+impl BcdConverter {
+    fn new(to_bcd: bool) -> Self {
+        Self { num_bits: 8, to_bcd }
+    }
+    fn split_digits(&self, value: u64) -> Vec<Value> {
+        let digits = decimal_digits(self.num_bits);
+        (0..digits).scan(value, |rest, _| {
+            let digit = *rest % 10;
+            *rest /= 10;
+            Some(Value::from_u64(digit, 4))
+        }).collect()
+    }
+    fn combine_digits(&self, digits: &[Value]) -> Option<u64> {
+        let mut result: u128 = 0;
+        for digit in digits.iter().rev() {
+            let digit = digit.to_u64().filter(|&d| d <= 9)?;
+            result = result * 10 + digit as u128;
+        }
+        let max = if self.num_bits >= 64 { u64::max_value() } else { (1 << self.num_bits) - 1 };
+        if result > max as u128 { None } else { Some(result as u64) }
+    }
+}
+
+impl Properties for BcdConverter {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for BcdConverter {
+    fn get_shape(&self) -> Shape {
+        let digits = decimal_digits(self.num_bits);
+        let (binary_x, digit_x, binary_direction, digit_direction) = if self.to_bcd {
+            (0, 4, PinDirection::Input, PinDirection::Output)
+        } else {
+            (4, 0, PinDirection::Output, PinDirection::Input)
+        };
+        // The most significant digit is at the top
+        let mut pins: Vec<_> = (0..digits).map(|i| Pin {
+            x: digit_x,
+            y: (digits - i) as i32,
+            name: format!("d{}", i),
+            bits: 4,
+            direction: digit_direction,
+        }).collect();
+        pins.push(Pin { x: binary_x, y: 1, name: "binary".into(), bits: self.num_bits, direction: binary_direction });
+        if self.to_bcd {
+            pins.rotate_right(1);
+        }
+        Shape {
+            width: 4,
+            height: digits as i32 + 1,
+            pins,
+            image_name: if self.to_bcd { "binary_to_bcd" } else { "bcd_to_binary" }.into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let digits = decimal_digits(self.num_bits) as usize;
+        if self.to_bcd {
+            let mut result = vec![Value::floating(self.num_bits)];
+            match inputs.get(0).and_then(|v| v.to_u64()) {
+                Some(value) => result.extend(self.split_digits(value)),
+                None => result.extend((0..digits).map(|_| Value::error(4))),
+            }
+            result
+        } else {
+            let mut result: Vec<_> = (0..digits).map(|_| Value::floating(4)).collect();
+            result.push(match inputs.get(..digits).and_then(|d| self.combine_digits(d)) {
+                Some(value) => Value::from_u64(value, self.num_bits),
+                None => Value::error(self.num_bits),
+            });
+            result
+        }
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    let units = [
+        ("adder", "Adder", "Adds two values with carry", ArithmeticType::Adder),
+        ("subtractor", "Subtractor", "Subtracts two values with borrow", ArithmeticType::Subtractor),
+        ("multiplier", "Multiplier", "Multiplies two values, outputting the upper half of the product as the carry", ArithmeticType::Multiplier),
+        ("comparator", "Comparator", "Compares two values", ArithmeticType::Comparator),
+        ("negator", "Negator", "Computes the two's complement negation of a value", ArithmeticType::Negator),
+        ("divider", "Divider", "Divides two values, outputting the quotient and remainder", ArithmeticType::Divider),
+        ("remainder", "Remainder", "Computes the remainder of dividing two values", ArithmeticType::Remainder),
+    ];
+    for &(id, name, description, type_) in &units {
+        result.add(
+            ComponentMetadata::new(id, name, CATEGORY, description),
+            move || Box::new(ArithmeticUnit::new(type_))
+        );
+    }
+    result.add(
+        ComponentMetadata::new("shifter", "Barrel Shifter", CATEGORY, "Shifts or rotates a value by a variable distance"),
+        || Box::new(Shifter { num_bits: 8, shift_type: ShiftType::LogicalLeft })
+    );
+    result.add(
+        ComponentMetadata::new("binary_to_bcd", "Binary to BCD", CATEGORY, "Splits a value into decimal digits"),
+        || Box::new(BcdConverter::new(true))
+    );
+    result.add(
+        ComponentMetadata::new("bcd_to_binary", "BCD to Binary", CATEGORY, "Combines decimal digits into a value"),
+        || Box::new(BcdConverter::new(false))
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Circuit;
+    use crate::export::verilog;
+
+    // The Verilog of a circuit containing a single component
+    fn export_single(id: &str, properties: &[(&str, serde_json::Value)]) -> String {
+        let mut component = library().create(id).unwrap();
+        for (name, value) in properties {
+            component.set_property(name, value.clone()).unwrap();
+        }
+        let mut circuit = Circuit::new();
+        circuit.add_component(component, 0, 0);
+        verilog::export(&circuit, id).unwrap()
+    }
+
+    fn eval(type_: ArithmeticType, signed: bool, inputs: &[u64]) -> Vec<Option<u64>> {
+        let unit = ArithmeticUnit { type_, num_bits: 8, signed };
+        let inputs: Vec<_> = inputs.iter().map(|&v| Value::from_u64(v, 8)).collect();
+        unit.evaluate(&inputs)[unit.inputs().len()..].iter().map(|v| v.to_u64()).collect()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval(ArithmeticType::Adder, false, &[200, 100]), vec![Some(44), Some(1)]);
+        assert_eq!(eval(ArithmeticType::Subtractor, false, &[5, 7]), vec![Some(254), Some(1)]);
+        assert_eq!(eval(ArithmeticType::Multiplier, false, &[200, 3]), vec![Some(88), Some(2)]);
+        assert_eq!(eval(ArithmeticType::Multiplier, true, &[0xff, 3]), vec![Some(0xfd), Some(0xff)]);
+        // The carry of a signed multiplier is signed too
+        assert_eq!(eval(ArithmeticType::Multiplier, false, &[3, 2, 0xff]), vec![Some(5), Some(1)]);
+        assert_eq!(eval(ArithmeticType::Multiplier, true, &[3, 2, 0xff]), vec![Some(5), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Negator, false, &[1]), vec![Some(0xff)]);
+        assert_eq!(eval(ArithmeticType::Comparator, false, &[0xff, 1]), vec![Some(1), Some(0), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Comparator, true, &[0xff, 1]), vec![Some(0), Some(0), Some(1)]);
+        assert_eq!(eval(ArithmeticType::Adder, false, &[1]), vec![None, None]);
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(eval(ArithmeticType::Divider, false, &[200, 7]), vec![Some(28), Some(4), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Remainder, false, &[200, 7]), vec![Some(4), Some(0)]);
+        // -7 / 2 == -3 remainder -1
+        assert_eq!(eval(ArithmeticType::Divider, true, &[0xf9, 2]), vec![Some(0xfd), Some(0xff), Some(0)]);
+        // 7 / -2 == -3 remainder 1
+        assert_eq!(eval(ArithmeticType::Divider, true, &[7, 0xfe]), vec![Some(0xfd), Some(1), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Divider, true, &[0x80, 0xff]), vec![Some(0x80), Some(0), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Divider, false, &[0xff, 0xff]), vec![Some(1), Some(0), Some(0)]);
+        assert_eq!(eval(ArithmeticType::Divider, false, &[9, 0]), vec![Some(0xff), Some(9), Some(1)]);
+        assert_eq!(eval(ArithmeticType::Remainder, true, &[9, 0]), vec![Some(9), Some(1)]);
+    }
+
+    #[test]
+    fn bcd() {
+        let to_bcd = library().create("binary_to_bcd").unwrap();
+        let pins = to_bcd.get_shape().pins;
+        assert_eq!(pins.iter().map(|p| &p.name[..]).collect::<Vec<_>>(), vec!["binary", "d0", "d1", "d2"]);
+        let digits = to_bcd.evaluate(&vec![Value::from_u64(209, 8)]);
+        assert_eq!(digits[1..].iter().map(|d| d.to_u64()).collect::<Vec<_>>(), vec![Some(9), Some(0), Some(2)]);
+
+        let from_bcd = library().create("bcd_to_binary").unwrap();
+        let eval = |digits: &[u64]| {
+            let inputs: Vec<_> = digits.iter().map(|&d| Value::from_u64(d, 4)).collect();
+            from_bcd.evaluate(&inputs)[3].to_u64()
+        };
+        assert_eq!(eval(&[9, 0, 2]), Some(209));
+        assert_eq!(eval(&[6, 5, 2]), None);
+        assert_eq!(eval(&[10, 0, 0]), None);
+
+        let mut wide = BcdConverter::new(true);
+        wide.num_bits = 64;
+        assert_eq!(wide.get_shape().pins.len(), 21);
+    }
+
+    #[test]
+    fn signed_verilog() {
+        // Unconnected pins of one bit each have a single net
+        let verilog = export_single("multiplier", &[("num_bits", 1.into()), ("signed", true.into())]);
+        assert!(verilog.contains("$signed(n0) * $signed(n1) + $signed(n2);"), "{}", verilog);
+        let verilog = export_single("multiplier", &[("num_bits", 1.into())]);
+        assert!(verilog.contains("n0 * n1 + n2;"), "{}", verilog);
+
+        let verilog = export_single("divider", &[("num_bits", 1.into()), ("signed", true.into())]);
+        assert!(verilog.contains("n1 == 0 ? $signed(~1'd0) : $signed(n0) / $signed(n1);"), "{}", verilog);
+        assert!(verilog.contains("n1 == 0 ? $signed(n0) : $signed(n0) % $signed(n1);"), "{}", verilog);
+        let verilog = export_single("remainder", &[("num_bits", 1.into())]);
+        assert!(verilog.contains("n1 == 0 ? n0 : n0 % n1;"), "{}", verilog);
+    }
+
+    #[test]
+    fn shifts() {
+        let data = [true, false, false, true];
+        let shifter = |shift_type| Shifter { num_bits: 4, shift_type };
+        assert_eq!(shifter(ShiftType::LogicalLeft).shift(&data, 1), vec![false, true, false, false]);
+        assert_eq!(shifter(ShiftType::LogicalRight).shift(&data, 1), vec![false, false, true, false]);
+        assert_eq!(shifter(ShiftType::ArithmeticRight).shift(&data, 2), vec![false, true, true, true]);
+        assert_eq!(shifter(ShiftType::RotateLeft).shift(&data, 1), vec![true, true, false, false]);
+        assert_eq!(shifter(ShiftType::RotateRight).shift(&data, 1), vec![false, false, true, true]);
+        assert_eq!(Shifter { num_bits: 8, shift_type: ShiftType::LogicalLeft }.distance_bits(), 3);
+
+        // A 5-bit shifter has a 3-bit distance, which can exceed its width
+        let data = [true, false, true, false, true];
+        let shifter = |shift_type| Shifter { num_bits: 5, shift_type };
+        assert_eq!(shifter(ShiftType::LogicalLeft).distance_bits(), 3);
+        assert_eq!(shifter(ShiftType::LogicalLeft).shift(&data, 7), vec![false; 5]);
+        assert_eq!(shifter(ShiftType::LogicalRight).shift(&data, 5), vec![false; 5]);
+        assert_eq!(shifter(ShiftType::ArithmeticRight).shift(&data, 7), vec![true; 5]);
+        assert_eq!(shifter(ShiftType::RotateLeft).shift(&data, 7), shifter(ShiftType::RotateLeft).shift(&data, 2));
+        assert_eq!(shifter(ShiftType::RotateRight).shift(&data, 6), vec![false, true, false, true, true]);
+
+        let verilog = export_single("shifter", &[("num_bits", 2.into()), ("shift_type", "RotateLeft".into())]);
+        assert!(verilog.contains("({n1, n0} << (n2 % 2)) | ({n1, n0} >> (2 - n2 % 2));"), "{}", verilog);
+    }
+}
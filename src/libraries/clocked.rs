@@ -0,0 +1,118 @@
+use std::error::Error;
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Shape, Pin, PinDirection, PinValues};
+use crate::simulation::Value;
+
+pub const CATEGORY: &'static str = "Clocks";
+
+/// A clock signal driven by the simulator's ticks. Each period consists of
+/// `high_ticks` ticks at one followed by `low_ticks` ticks at zero, and the
+/// phase shifts the signal earlier by that many ticks, so that several
+/// clocks can be offset from one another.
+#[derive(Debug, Clone, Component)]
+struct Clock {
+    #[property(name = "High duration", description = "Ticks spent at one in each period", min = 1, max = 1024, unit = "ticks")]
+    high_ticks: u32,
+    #[property(name = "Low duration", description = "Ticks spent at zero in each period", min = 1, max = 1024, unit = "ticks")]
+    low_ticks: u32,
+    #[property(name = "Phase", description = "Number of ticks to advance the clock by", max = 2048, unit = "ticks")]
+    phase: u32,
+    ticks: u64,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            high_ticks: 1,
+            low_ticks: 1,
+            phase: 0,
+            ticks: 0,
+        }
+    }
+    fn is_high(&self) -> bool {
+        let period = self.high_ticks as u64 + self.low_ticks as u64;
+        (self.ticks + self.phase as u64) % period < self.high_ticks as u64
+    }
+}
+
+impl Component for Clock {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 2,
+            height: 2,
+            pins: vec![Pin {
+                x: 2,
+                y: 1,
+                name: "out".into(),
+                bits: 1,
+                direction: PinDirection::Output,
+            }],
+            image_name: "clock".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::from_u64(self.is_high() as u64, 1)]
+    }
+    fn tick(&mut self) -> bool {
+        let was_high = self.is_high();
+        self.ticks += 1;
+        self.is_high() != was_high
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(Value::from_u64(self.is_high() as u64, 1))
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.ticks).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.ticks = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("clock", "Clock", CATEGORY, "Alternates between one and zero as the simulation ticks"),
+        || Box::new(Clock::new())
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Circuit;
+    use crate::simulation::Simulator;
+
+    #[test]
+    fn clocks_with_phase() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let mut slow = lib.create("clock").unwrap();
+        slow.set_property("high_ticks", 2.into()).unwrap();
+        slow.set_property("low_ticks", 2.into()).unwrap();
+        let mut shifted = slow.clone();
+        shifted.set_property("phase", 1.into()).unwrap();
+        let fast = circuit.add_component(lib.create("clock").unwrap(), 0, 0);
+        let slow = circuit.add_component(slow, 0, 4);
+        let shifted = circuit.add_component(shifted, 0, 8);
+
+        let mut sim = Simulator::new(circuit);
+        let mut history = Vec::new();
+        for _ in 0..4 {
+            history.push([fast, slow, shifted].iter()
+                .map(|&id| sim.display_value(id).unwrap().to_u64().unwrap())
+                .collect::<Vec<_>>());
+            sim.tick().unwrap();
+        }
+        assert_eq!(history, vec![
+            vec![1, 1, 1],
+            vec![0, 1, 0],
+            vec![1, 0, 0],
+            vec![0, 0, 1],
+        ]);
+        assert_eq!(sim.ticks(), 4);
+    }
+}
@@ -0,0 +1,535 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use serde_json;
+use maplit::btreemap;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, PinDirection, FieldSchema, FieldType, RecordField, ReflectType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::render::DrawContext;
+use super::memory::{Clock, trigger_field, input_bit, pin};
+
+pub const CATEGORY: &'static str = super::memory::CATEGORY;
+
+/// The most states a state machine may have.
+pub const MAX_STATES: u32 = 64;
+/// The most transitions a state machine may have.
+pub const MAX_TRANSITIONS: u32 = 256;
+
+/// Whether the outputs of a state machine depend only on its state, or also
+/// on its inputs.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum MachineType {
+    Moore,
+    Mealy,
+}
+
+impl ReflectType for MachineType {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[MachineType::Moore, MachineType::Mealy])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct State {
+    name: String,
+    /// The output while in this state. A Mealy machine uses it when no
+    /// transition matches the inputs.
+    #[serde(default)]
+    output: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Transition {
+    from: String,
+    /// One character per input bit, most significant first: `0` or `1` to
+    /// match that value, or `-` to match either.
+    input: String,
+    to: String,
+    /// The output while the transition is enabled, for Mealy machines.
+    #[serde(default)]
+    output: u64,
+}
+
+impl Transition {
+    // Returns `None` if the inputs are not known well enough to tell
+    fn matches(&self, input: &Value, input_bits: u32) -> Option<bool> {
+        let mut known = true;
+        for (i, c) in self.input.chars().rev().enumerate().take(input_bits as usize) {
+            let expected = match c {
+                '0' => Bit::Zero,
+                '1' => Bit::One,
+                _ => continue,
+            };
+            match input.bit(i as u32) {
+                Bit::Zero | Bit::One if input.bit(i as u32) == expected => {},
+                Bit::Zero | Bit::One => return Some(false),
+                _ => known = false,
+            }
+        }
+        if known { Some(true) } else { None }
+    }
+}
+
+// Fits an input pattern to a new number of bits, keeping the low bits and
+// matching anything in new high bits.
+fn resize_pattern(pattern: &str, bits: u32) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let bits = bits as usize;
+    if chars.len() >= bits {
+        chars[chars.len() - bits..].iter().collect()
+    } else {
+        std::iter::repeat('-').take(bits - chars.len()).chain(chars).collect()
+    }
+}
+
+fn output_mask(bits: u32) -> u64 {
+    if bits >= 64 { !0 } else { (1 << bits) - 1 }
+}
+
+/// A finite state machine, defined by a table of states and a table of
+/// transitions between them. On each clock edge the first transition from
+/// the current state whose input pattern matches is taken, and if none
+/// match the state is unchanged. The first state is the initial state,
+/// which the machine returns to while `reset` is high.
+#[derive(Debug, Clone)]
+struct StateMachine {
+    type_: MachineType,
+    input_bits: u32,
+    output_bits: u32,
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+    clock: Clock,
+    // `None` if the state is unknown, such as after a clock edge with
+    // unknown inputs
+    current: Option<usize>,
+}
+
+impl StateMachine {
+    fn new() -> Self {
+        let state = |name: &str, output| State { name: name.into(), output };
+        let transition = |from: &str, input: &str, to: &str| Transition {
+            from: from.into(),
+            input: input.into(),
+            to: to.into(),
+            output: 0,
+        };
+        Self {
+            type_: MachineType::Moore,
+            input_bits: 1,
+            output_bits: 1,
+            states: vec![state("idle", 0), state("active", 1)],
+            transitions: vec![transition("idle", "1", "active"), transition("active", "0", "idle")],
+            clock: Clock::new(),
+            current: Some(0),
+        }
+    }
+    fn state_index(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+    // The transition enabled by the inputs, `Ok(None)` if there is none, or
+    // `Err` if it cannot be determined.
+    fn enabled(&self, state: usize, input: &Value) -> Result<Option<&Transition>, ()> {
+        let name = &self.states[state].name;
+        let transitions = self.transitions.iter()
+            .filter(|t| t.from == *name && self.state_index(&t.to).is_some());
+        for transition in transitions {
+            match transition.matches(input, self.input_bits) {
+                Some(true) => return Ok(Some(transition)),
+                Some(false) => {},
+                None => return Err(()),
+            }
+        }
+        Ok(None)
+    }
+    fn output(&self, input: &Value) -> Value {
+        let state = match self.current {
+            Some(state) => state,
+            None => return Value::unknown(self.output_bits),
+        };
+        let output = match self.type_ {
+            MachineType::Moore => self.states[state].output,
+            MachineType::Mealy => match self.enabled(state, input) {
+                Ok(Some(transition)) => transition.output,
+                Ok(None) => self.states[state].output,
+                Err(()) => return Value::unknown(self.output_bits),
+            },
+        };
+        Value::from_u64(output, self.output_bits)
+    }
+    // Outputs of 64 bits are limited to the positive range of an `i64`
+    fn output_type(&self) -> FieldType {
+        FieldType::integer(0, output_mask(self.output_bits).min(i64::max_value() as u64) as i64)
+    }
+    fn state_columns(&self) -> Vec<RecordField> {
+        vec![
+            RecordField::new("name", "Name", FieldType::string().with_pattern("[A-Za-z0-9_]+")),
+            RecordField::new("output", "Output", self.output_type()),
+        ]
+    }
+    fn transition_columns(&self) -> Vec<RecordField> {
+        vec![
+            RecordField::new("from", "From", FieldType::string()),
+            RecordField::new("input", "Input", FieldType::string()
+                .with_pattern(format!("[01-]{{{}}}", self.input_bits))
+                .with_placeholder("-".repeat(self.input_bits as usize))),
+            RecordField::new("to", "To", FieldType::string()),
+            RecordField::new("output", "Output", self.output_type()),
+        ]
+    }
+}
+
+impl Properties for StateMachine {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "type_".into() => FieldSchema::new("Type", MachineType::field_type())
+                .with_description("Moore machine outputs depend only on the state, and Mealy machine outputs also depend on the inputs"),
+            "trigger".into() => trigger_field().with_order(1),
+            "input_bits".into() => FieldSchema::new("Input bits", FieldType::integer(1, 16)).with_order(2),
+            "output_bits".into() => FieldSchema::new("Output bits", FieldType::integer(1, 64)).with_order(3),
+            "states".into() => FieldSchema::new("States", FieldType::table(self.state_columns(), 1, MAX_STATES))
+                .with_description("The first state is the initial state")
+                .with_order(4)
+                .depends_on("output_bits"),
+            "transitions".into() => FieldSchema::new("Transitions", FieldType::table(self.transition_columns(), 0, MAX_TRANSITIONS))
+                .with_description("The first matching transition from the current state is taken. Inputs are written most significant bit first, with `-` matching either value. The output is only used by Mealy machines.")
+                .with_order(5)
+                .depends_on("input_bits")
+                .depends_on("output_bits"),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "type_" => {
+                self.type_ = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "input_bits" => {
+                self.input_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                for transition in &mut self.transitions {
+                    transition.input = resize_pattern(&transition.input, self.input_bits);
+                }
+                Ok(())
+            },
+            "output_bits" => {
+                self.output_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                let mask = output_mask(self.output_bits);
+                for state in &mut self.states {
+                    state.output &= mask;
+                }
+                for transition in &mut self.transitions {
+                    transition.output &= mask;
+                }
+                Ok(())
+            },
+            "states" => {
+                let states: Vec<State> = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                if states.is_empty() {
+                    return Err(PropertyError::invalid(name, "There must be at least one state"));
+                }
+                for (i, state) in states.iter().enumerate() {
+                    if states[..i].iter().any(|s| s.name == state.name) {
+                        return Err(PropertyError::invalid(name, format!("There is more than one state named `{}`", state.name)));
+                    }
+                }
+                // Stay in the same state if it still exists
+                let current = self.current.map(|i| self.states[i].name.clone());
+                self.states = states;
+                self.current = match current {
+                    Some(current) => self.state_index(&current).or(Some(0)),
+                    None => None,
+                };
+                Ok(())
+            },
+            // Transitions naming states which do not exist are allowed, so
+            // that states can be renamed, but such transitions are never
+            // taken.
+            "transitions" => {
+                self.transitions = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "type_" => serde_json::to_value(self.type_).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            "input_bits" => serde_json::to_value(self.input_bits).ok(),
+            "output_bits" => serde_json::to_value(self.output_bits).ok(),
+            "states" => serde_json::to_value(&self.states).ok(),
+            "transitions" => serde_json::to_value(&self.transitions).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for StateMachine {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 4,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "in", self.input_bits, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(2, 4, "reset", 1, PinDirection::Input),
+                pin(4, 2, "out", self.output_bits, PinDirection::Output),
+            ],
+            image_name: "state_machine".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, self.current)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, current): (Bit, Option<usize>) = serde_json::from_value(state)?;
+        if current.map_or(false, |i| i >= self.states.len()) {
+            return Err("State does not exist".into());
+        }
+        self.clock.last = last;
+        self.current = current;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 1));
+        if input_bit(inputs, 2) == Bit::One {
+            self.current = Some(0);
+        } else if fired {
+            if let Some(state) = self.current {
+                let input = inputs.get(0).cloned().unwrap_or_else(|| Value::floating(self.input_bits));
+                self.current = match self.enabled(state, &input) {
+                    Ok(Some(transition)) => self.state_index(&transition.to),
+                    Ok(None) => Some(state),
+                    Err(()) => None,
+                };
+            }
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let input = inputs.get(0).cloned().unwrap_or_else(|| Value::floating(self.input_bits));
+        vec![
+            Value::floating(self.input_bits),
+            Value::floating(1),
+            Value::floating(1),
+            self.output(&input),
+        ]
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        ctx.rect((0.0, 0.0), (4.0, 4.0), Some("#fff"), Some("#222"));
+        // The clock input
+        ctx.polygon(vec![(0.0, 2.6), (0.4, 3.0), (0.0, 3.4)], None, Some("#222"));
+        let name = match self.current {
+            Some(state) => &*self.states[state].name,
+            None => "?",
+        };
+        ctx.text((2.0, 1.5), 0.8, name, "#222");
+    }
+    fn draw_diagram(&self, ctx: &mut DrawContext) {
+        const STATE_RADIUS: f64 = 1.5;
+        let n = self.states.len();
+        // Place the states evenly around a circle, starting at the top
+        let radius = (n as f64 * 1.2).max(4.0);
+        let center = (radius + 4.0, radius + 4.0);
+        let positions: Vec<(f64, f64)> = (0..n).map(|i| {
+            let angle = 2.0 * PI * i as f64 / n as f64 - PI / 2.0;
+            (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+        }).collect();
+
+        // Transitions between the same pair of states share an arrow
+        let mut edges: Vec<((usize, usize), Vec<String>)> = Vec::new();
+        for transition in &self.transitions {
+            let (from, to) = match (self.state_index(&transition.from), self.state_index(&transition.to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => continue,
+            };
+            let label = match self.type_ {
+                MachineType::Moore => transition.input.clone(),
+                MachineType::Mealy => format!("{}/{:X}", transition.input, transition.output),
+            };
+            match edges.iter_mut().find(|(edge, _)| *edge == (from, to)) {
+                Some((_, labels)) => labels.push(label),
+                None => edges.push(((from, to), vec![label])),
+            }
+        }
+        for ((from, to), labels) in edges {
+            let (a, b) = (positions[from], positions[to]);
+            let label = labels.join(", ");
+            if from == to {
+                // A loop on the far side of the state from the centre
+                let (dx, dy) = if n == 1 { (0.0, -1.0) } else {
+                    let (dx, dy) = (a.0 - center.0, a.1 - center.1);
+                    let len = (dx * dx + dy * dy).sqrt();
+                    (dx / len, dy / len)
+                };
+                let loop_center = (a.0 + dx * STATE_RADIUS * 1.4, a.1 + dy * STATE_RADIUS * 1.4);
+                ctx.circle(loop_center, STATE_RADIUS * 0.6, None, Some("#222"));
+                ctx.text((a.0 + dx * STATE_RADIUS * 2.6, a.1 + dy * STATE_RADIUS * 2.6), 0.6, &label, "#222");
+                continue;
+            }
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let len = (dx * dx + dy * dy).sqrt();
+            let (ux, uy) = (dx / len, dy / len);
+            // Offset to the side, so that arrows in opposite directions
+            // between the same states do not overlap
+            let (px, py) = (-uy * 0.4, ux * 0.4);
+            let start = (a.0 + ux * STATE_RADIUS + px, a.1 + uy * STATE_RADIUS + py);
+            let tip = (b.0 - ux * STATE_RADIUS + px, b.1 - uy * STATE_RADIUS + py);
+            ctx.line(start, tip, "#222");
+            ctx.polygon(vec![
+                tip,
+                (tip.0 - ux * 0.6 - uy * 0.3, tip.1 - uy * 0.6 + ux * 0.3),
+                (tip.0 - ux * 0.6 + uy * 0.3, tip.1 - uy * 0.6 - ux * 0.3),
+            ], Some("#222"), None);
+            let middle = ((start.0 + tip.0) / 2.0 + px * 1.5, (start.1 + tip.1) / 2.0 + py * 1.5);
+            ctx.text(middle, 0.6, &label, "#222");
+        }
+
+        for (i, (state, &position)) in self.states.iter().zip(&positions).enumerate() {
+            let fill = if self.current == Some(i) { "#cfc" } else { "#fff" };
+            ctx.circle(position, STATE_RADIUS, Some(fill), Some("#222"));
+            // The initial state is drawn with a double outline
+            if i == 0 {
+                ctx.circle(position, STATE_RADIUS - 0.2, None, Some("#222"));
+            }
+            match self.type_ {
+                MachineType::Moore => {
+                    ctx.text((position.0, position.1 - 0.35), 0.6, &state.name, "#222");
+                    ctx.text((position.0, position.1 + 0.45), 0.5, &format!("{:X}", state.output), "#222");
+                },
+                MachineType::Mealy => ctx.text(position, 0.6, &state.name, "#222"),
+            }
+        }
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("state_machine", "State Machine", CATEGORY, "A finite state machine defined by tables of states and transitions"),
+        || Box::new(StateMachine::new())
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::render::DrawCommand;
+
+    fn clock(machine: &mut crate::component::ComponentInfo, input: u64) -> Value {
+        for &level in &[0, 1] {
+            let inputs = vec![Value::from_u64(input, 2), Value::from_u64(level, 1), Value::from_u64(0, 1)];
+            machine.clock_edge(&inputs);
+        }
+        machine.evaluate(&vec![Value::from_u64(input, 2)])[3].clone()
+    }
+
+    // Detects the sequence `1, 1` on its low input bit
+    fn detector(type_: &str) -> crate::component::ComponentInfo {
+        let mut machine = library().create("state_machine").unwrap();
+        machine.set_property("type_", json!(type_)).unwrap();
+        machine.set_property("input_bits", json!(2)).unwrap();
+        machine.set_property("states", json!([
+            { "name": "zero", "output": 0 },
+            { "name": "one", "output": 0 },
+            { "name": "two", "output": 1 },
+        ])).unwrap();
+        machine.set_property("transitions", json!([
+            { "from": "zero", "input": "-1", "to": "one", "output": 0 },
+            { "from": "one", "input": "-1", "to": "two", "output": 1 },
+            { "from": "two", "input": "-1", "to": "two", "output": 1 },
+            { "from": "one", "input": "-0", "to": "zero", "output": 0 },
+            { "from": "two", "input": "-0", "to": "zero", "output": 0 },
+        ])).unwrap();
+        machine
+    }
+
+    #[test]
+    fn moore_machine() {
+        let mut machine = detector("Moore");
+        let outputs: Vec<_> = [1, 3, 0, 1, 1, 1].iter()
+            .map(|&input| clock(&mut machine, input).to_u64().unwrap())
+            .collect();
+        assert_eq!(outputs, vec![0, 1, 0, 0, 1, 1]);
+
+        // Reset returns to the initial state
+        machine.clock_edge(&vec![Value::from_u64(0, 2), Value::from_u64(1, 1), Value::from_u64(1, 1)]);
+        assert_eq!(machine.evaluate(&vec![Value::from_u64(0, 2)])[3].to_u64(), Some(0));
+
+        // Unknown inputs leave the machine in an unknown state
+        let inputs = vec![Value::unknown(2), Value::from_u64(1, 1), Value::from_u64(0, 1)];
+        machine.clock_edge(&vec![Value::unknown(2), Value::from_u64(0, 1), Value::from_u64(0, 1)]);
+        machine.clock_edge(&inputs);
+        assert_eq!(machine.evaluate(&inputs)[3].bits(), &[Bit::Unknown]);
+    }
+
+    #[test]
+    fn mealy_machine() {
+        let mut machine = detector("Mealy");
+        clock(&mut machine, 1);
+        // The output follows the inputs without waiting for a clock edge
+        assert_eq!(machine.evaluate(&vec![Value::from_u64(1, 2)])[3].to_u64(), Some(1));
+        assert_eq!(machine.evaluate(&vec![Value::from_u64(2, 2)])[3].to_u64(), Some(0));
+    }
+
+    #[test]
+    fn editing_tables() {
+        let mut machine = detector("Moore");
+        assert!(machine.set_property("states", json!([{ "name": "a" }, { "name": "a" }])).is_err());
+        assert!(machine.set_property("states", json!([])).is_err());
+        assert!(machine.set_property("transitions", json!([{ "from": "zero", "input": "2", "to": "one" }])).is_err());
+
+        machine.set_property("input_bits", json!(3)).unwrap();
+        assert_eq!(machine.get_list_element("transitions", 0).unwrap()["input"], json!("--1"));
+        machine.set_property("output_bits", json!(1)).unwrap();
+        assert!(machine.set_list_element("states", 0, json!({ "name": "zero", "output": 2 })).is_err());
+        machine.set_property("output_bits", json!(64)).unwrap();
+        machine.set_list_element("states", 0, json!({ "name": "zero", "output": 2 })).unwrap();
+        machine.set_list_element("transitions", 0, json!({ "from": "zero", "input": "--1", "to": "one", "output": 3 })).unwrap();
+    }
+
+    #[test]
+    fn dangling_transitions() {
+        let mut machine = detector("Moore");
+        // Renaming a state leaves the transitions into it dangling, so they
+        // are skipped and the machine stays where it is
+        machine.set_list_element("states", 2, json!({ "name": "done", "output": 1 })).unwrap();
+        assert_eq!(clock(&mut machine, 1).to_u64(), Some(0));
+        assert_eq!(clock(&mut machine, 1).to_u64(), Some(0));
+        assert_eq!(clock(&mut machine, 0).to_u64(), Some(0));
+        assert_eq!(clock(&mut machine, 1).to_u64(), Some(0));
+    }
+
+    #[test]
+    fn state_diagram() {
+        let machine = detector("Mealy");
+        let mut ctx = DrawContext::new();
+        machine.draw_diagram(&mut ctx);
+        let texts: Vec<_> = ctx.commands().iter().filter_map(|c| match c {
+            DrawCommand::Text { text, .. } => Some(&**text),
+            _ => None,
+        }).collect();
+        assert!(texts.contains(&"zero"));
+        assert!(texts.contains(&"-1/0"));
+        // A loop on `two`
+        assert!(texts.contains(&"-1/1"));
+        let circles = ctx.commands().iter().filter(|c| match c {
+            DrawCommand::Circle { .. } => true,
+            _ => false,
+        }).count();
+        assert_eq!(circles, 5);
+    }
+}
@@ -1,13 +1,27 @@
 use smallbitvec::{SmallBitVec, sbvec};
 use serde_json;
 use maplit::btreemap;
-use serde_derive::{Serialize, Deserialize};
 
-use crate::library::{Library, ComponentMetadata};
-use crate::component::{Component, Schema, PropertyError, Shape, FieldSchema, FieldType};
+use crate::library::{Library, ComponentMetadata, Preset};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, FieldSchema, FieldType, ReflectType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::compiled::{GateOp, Kernel};
+use crate::migration::SavedProperties;
+use crate::bits::BitString;
+use crate::export::verilog::{HdlEmit, ModuleWriter, ExportError};
+use crate::analysis::BooleanExpr;
+use super::{data_bits_field, spaced_height, spaced_y};
 
 pub const CATEGORY: &'static str = "Gates";
 
+const INVERSION_GROUP: &'static str = "Inversion";
+
+fn delay_field() -> FieldSchema {
+    FieldSchema::new("Delay", FieldType::integer(1, 1000))
+        .with_description("Time taken for a change to the inputs to reach the output")
+        .with_order(2)
+}
+
 #[derive(Debug, Copy, Clone)]
 enum NaryGateType {
     And,
@@ -29,6 +43,14 @@ impl NaryGateType {
             (NaryGateType::Parity, true) => "even_parity",
         }
     }
+    fn op(self) -> GateOp {
+        match self {
+            NaryGateType::And => GateOp::And,
+            NaryGateType::Or => GateOp::Or,
+            NaryGateType::Xor => GateOp::Xor,
+            NaryGateType::Parity => GateOp::Parity,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,85 +60,43 @@ struct NaryGate {
     num_inputs: u32,
     num_bits: u32,
     invert_inputs: SmallBitVec,
+    delay: u64,
 }
 
 impl NaryGate {
-    fn new(type_: NaryGateType) -> Self {
+    fn new(type_: NaryGateType, invert_output: bool) -> Self {
         Self {
             type_,
-            invert_output: false,
+            invert_output,
             num_inputs: 2,
             num_bits: 1,
             invert_inputs: sbvec![false; 2],
+            delay: 1,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-enum YesNo {
-    Yes,
-    No
-}
-
-impl From<YesNo> for bool {
-    fn from(v: YesNo) -> bool {
-        match v {
-            YesNo::Yes => true,
-            YesNo::No => false,
-        }
-    }
-}
-
-impl From<bool> for YesNo {
-    fn from(v: bool) -> YesNo {
-        match v {
-            true => YesNo::Yes,
-            false => YesNo::No,
-        }
-    }
-}
-
-impl Component for NaryGate {
+impl Properties for NaryGate {
     fn schema(&self) -> Schema {
-        let mut result = btreemap!{
-            "invert_output".into() => FieldSchema {
-                read_only: false,
-                type_: FieldType::for_enum(&[YesNo::No, YesNo::Yes]),
-                name: "Invert output".into(),
-                description: None,
-            },
-            "num_inputs".into() => FieldSchema {
-                read_only: false,
-                type_: FieldType::Integer { min: 2, max: 32 },
-                name: "Number of inputs".into(),
-                description: None,
-            },
-            "num_bits".into() => FieldSchema {
-                read_only: false,
-                type_: FieldType::Integer { min: 1, max: 256 },
-                name: "Data bits".into(),
-                description: None,
-            },
-        };
-
-        for i in 0..self.num_inputs {
-            let id = format!("invert_input_{}", i);
-            result.insert(id.into(), FieldSchema {
-                read_only: false,
-                type_: FieldType::for_enum(&[YesNo::No, YesNo::Yes]),
-                name: format!("Invert input {}", i).into(),
-                description: None,
-            });
+        btreemap!{
+            "num_inputs".into() => FieldSchema::new("Number of inputs", FieldType::integer(2, 32)),
+            "num_bits".into() => data_bits_field().with_order(1),
+            "invert_inputs".into() => FieldSchema::new("Invert inputs",
+                FieldType::list(bool::field_type(), self.num_inputs, self.num_inputs))
+                .with_group(INVERSION_GROUP)
+                .with_default(vec![false; self.num_inputs as usize])
+                .depends_on("num_inputs"),
+            "invert_output".into() => FieldSchema::new("Invert output", bool::field_type())
+                .with_group(INVERSION_GROUP)
+                .with_order(1),
+            "delay".into() => delay_field(),
         }
-
-        result
     }
     fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
         match name {
             "invert_output" => {
-                self.invert_output = serde_json::from_value::<YesNo>(value)
-                    .map_err(|e| PropertyError::from_serde(e, name))?
-                    .into();
+                self.invert_output = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
                 Ok(())
             },
             "num_inputs" => {
@@ -126,22 +106,20 @@ impl Component for NaryGate {
                 Ok(())
             },
             "num_bits" => {
-                self.num_inputs = serde_json::from_value(value)
+                self.num_bits = serde_json::from_value(value)
                     .map_err(|e| PropertyError::from_serde(e, name))?;
                 Ok(())
             },
-            _ if name.starts_with("invert_input_") => {
-                let v = serde_json::from_value::<YesNo>(value)
-                    .map_err(|e| PropertyError::from_serde(e, name))?
-                    .into();
-                for i in 0..self.num_inputs {
-                    let id = format!("invert_input_{}", i);
-                    if id == name {
-                        self.invert_inputs.set(i as usize, v);
-                        return Ok(());
-                    }
-                }
-                Err(PropertyError::unknown(name))
+            "invert_inputs" => {
+                let inverted: Vec<bool> = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.invert_inputs = inverted.into_iter().collect();
+                Ok(())
+            },
+            "delay" => {
+                self.delay = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
             },
             _ => Err(PropertyError::unknown(name))
         }
@@ -149,7 +127,7 @@ impl Component for NaryGate {
     fn get_property(&self, name: &str) -> Option<serde_json::Value> {
         match name {
             "invert_output" => {
-                serde_json::to_value::<YesNo>(self.invert_output.into()).ok()
+                serde_json::to_value(self.invert_output).ok()
             },
             "num_inputs" => {
                 serde_json::to_value(self.num_inputs).ok()
@@ -157,34 +135,729 @@ impl Component for NaryGate {
             "num_bits" => {
                 serde_json::to_value(self.num_bits).ok()
             },
-            _ if name.starts_with("invert_input_") => {
-                for i in 0..self.num_inputs {
-                    let id = format!("invert_input_{}", i);
-                    if id == name {
-                        let v = self.invert_inputs[i as usize];
-                        return serde_json::to_value::<YesNo>(v.into()).ok();
-                    }
-                }
-                None
+            "invert_inputs" => {
+                serde_json::to_value(self.invert_inputs.iter().collect::<Vec<_>>()).ok()
+            },
+            "delay" => {
+                serde_json::to_value(self.delay).ok()
             },
             _ => None
         }
     }
+}
+
+// Version 1 had a separate `invert_input_N` property for each input
+fn migrate_invert_inputs(properties: &mut SavedProperties) -> Result<(), String> {
+    let num_inputs = properties.get("num_inputs").and_then(|v| v.as_u64()).unwrap_or(2);
+    let inverted: Vec<_> = (0..num_inputs)
+        .map(|i| properties.remove(&format!("invert_input_{}", i)).unwrap_or(false.into()))
+        .collect();
+    properties.insert("invert_inputs".into(), inverted.into());
+    Ok(())
+}
+
+impl Component for NaryGate {
     fn get_shape(&self) -> Shape {
+        let width = 4;
+        let height = spaced_height(self.num_inputs);
+        let mut pins: Vec<_> = (0..self.num_inputs).map(|i| Pin {
+            x: 0,
+            y: spaced_y(i, self.num_inputs),
+            name: format!("in{}", i),
+            bits: self.num_bits,
+            direction: PinDirection::Input,
+        }).collect();
+        pins.push(Pin {
+            x: width,
+            y: height / 2,
+            name: "out".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Output,
+        });
         Shape {
-            width: 3,
-            height: 3,
-            pins: vec![],
+            width,
+            height,
+            pins,
             image_name: self.type_.image_name(self.invert_output).into(),
         }
     }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let n = self.num_inputs as usize;
+        let bits = (0..self.num_bits).map(|b| {
+            let result = self.type_.op().apply(inputs.iter().take(n).enumerate().map(|(i, v)| {
+                let bit = v.bit(b);
+                if self.invert_inputs[i] && bit != Bit::HighZ { !bit } else { bit }
+            }));
+            if self.invert_output { !result } else { result }
+        }).collect();
+
+        let mut result = vec![Value::floating(self.num_bits); n];
+        result.push(Value::new(bits));
+        result
+    }
+    fn delay(&self) -> u64 {
+        self.delay
+    }
+    fn kernel(&self) -> Option<Kernel> {
+        Some(Kernel::Gate {
+            op: self.type_.op(),
+            invert_inputs: self.invert_inputs.iter().collect(),
+            invert_output: self.invert_output,
+        })
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for NaryGate {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let n = self.num_inputs as usize;
+        let inputs: Vec<_> = pins[..n].iter().enumerate()
+            .map(|(i, pin)| if self.invert_inputs[i] { format!("~{}", pin) } else { pin.clone() })
+            .collect();
+        let expr = match self.type_ {
+            NaryGateType::And => inputs.join(" & "),
+            NaryGateType::Or => inputs.join(" | "),
+            NaryGateType::Parity => inputs.join(" ^ "),
+            NaryGateType::Xor if n == 2 => inputs.join(" ^ "),
+            // Exactly one input is set
+            NaryGateType::Xor => (0..n).map(|i| {
+                let terms: Vec<_> = inputs.iter().enumerate()
+                    .map(|(j, input)| if i == j { input.clone() } else { format!("~{}", input) })
+                    .collect();
+                format!("({})", terms.join(" & "))
+            }).collect::<Vec<_>>().join(" | "),
+        };
+        let expr = if self.invert_output { format!("~({})", expr) } else { expr };
+        module.assign(&pins[n], &expr);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+struct UnaryGate {
+    invert: bool,
+    #[property(name = "Data bits", min = 1, max = 256, order = 1)]
+    num_bits: u32,
+    #[property(name = "Delay", description = "Time taken for a change to the input to reach the output", min = 1, max = 1000, order = 2)]
+    delay: u64,
+}
+
+impl UnaryGate {
+    fn new(invert: bool) -> Self {
+        Self {
+            invert,
+            num_bits: 1,
+            delay: 1,
+        }
+    }
+}
+
+impl Component for UnaryGate {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                Pin {
+                    x: 0,
+                    y: 1,
+                    name: "in".into(),
+                    bits: self.num_bits,
+                    direction: PinDirection::Input,
+                },
+                Pin {
+                    x: 3,
+                    y: 1,
+                    name: "out".into(),
+                    bits: self.num_bits,
+                    direction: PinDirection::Output,
+                },
+            ],
+            image_name: if self.invert { "not_gate" } else { "buffer" }.into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let bits = (0..self.num_bits).map(|b| {
+            let bit = inputs.get(0).map(|v| v.bit(b)).unwrap_or(Bit::HighZ);
+            match bit {
+                Bit::HighZ => Bit::Error,
+                _ if self.invert => !bit,
+                _ => bit,
+            }
+        }).collect();
+        vec![Value::floating(self.num_bits), Value::new(bits)]
+    }
+    fn delay(&self) -> u64 {
+        self.delay
+    }
+    fn kernel(&self) -> Option<Kernel> {
+        Some(Kernel::Buffer { invert: self.invert })
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for UnaryGate {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let expr = if self.invert { format!("~{}", pins[0]) } else { pins[0].clone() };
+        module.assign(&pins[1], &expr);
+        Ok(())
+    }
+}
+
+/// A buffer or inverter with an enable input. While disabled, its output is
+/// high-impedance so that another component may drive the net.
+#[derive(Debug, Clone, Component)]
+struct ControlledGate {
+    invert: bool,
+    #[property(name = "Data bits", min = 1, max = 256, order = 1)]
+    num_bits: u32,
+}
+
+impl Component for ControlledGate {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "in".into(), bits: self.num_bits, direction: PinDirection::Input },
+                Pin { x: 3, y: 1, name: "out".into(), bits: self.num_bits, direction: PinDirection::TriState },
+                Pin { x: 1, y: 2, name: "enable".into(), bits: 1, direction: PinDirection::Input },
+            ],
+            image_name: if self.invert { "controlled_inverter" } else { "controlled_buffer" }.into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let enable = inputs.get(2).map(|v| v.bit(0)).unwrap_or(Bit::HighZ);
+        let bits = (0..self.num_bits).map(|b| {
+            let bit = inputs.get(0).map(|v| v.bit(b)).unwrap_or(Bit::HighZ);
+            match enable {
+                Bit::Zero => Bit::HighZ,
+                Bit::One => match bit {
+                    Bit::HighZ => Bit::Error,
+                    _ if self.invert => !bit,
+                    _ => bit,
+                },
+                // The output may or may not be driven
+                Bit::Error => Bit::Error,
+                _ => Bit::Unknown,
+            }
+        }).collect();
+        vec![Value::floating(self.num_bits), Value::new(bits), Value::floating(1)]
+    }
+    fn kernel(&self) -> Option<Kernel> {
+        Some(Kernel::ControlledBuffer { invert: self.invert })
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for ControlledGate {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let expr = if self.invert { format!("~{}", pins[0]) } else { pins[0].clone() };
+        module.assign(&pins[1], &format!("{} ? {} : {}'bz", pins[2], expr, self.num_bits));
+        Ok(())
+    }
+}
+
+const DEFAULT_EXPRESSION: &'static str = "(a & b) | !c";
+
+/// Evaluates a boolean expression, with an input pin for each variable in
+/// the expression. Multi-bit inputs are combined bitwise.
+#[derive(Debug, Clone)]
+struct ExpressionGate {
+    expression: BooleanExpr,
+    num_bits: u32,
+}
+
+impl ExpressionGate {
+    fn new() -> Self {
+        Self {
+            expression: BooleanExpr::parse(DEFAULT_EXPRESSION).expect("Default expression is valid"),
+            num_bits: 1,
+        }
+    }
+}
+
+impl Properties for ExpressionGate {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "expression".into() => FieldSchema::new("Expression",
+                FieldType::string().with_placeholder(DEFAULT_EXPRESSION))
+                .with_description("Combine inputs with `&`, `|`, `^` and `!`. Each variable becomes an input pin.")
+                .with_default(DEFAULT_EXPRESSION),
+            "num_bits".into() => data_bits_field().with_order(1),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "expression" => {
+                let expression: String = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.expression = BooleanExpr::parse(&expression)
+                    .map_err(|e| PropertyError::invalid(name, e.to_string()))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "expression" => {
+                serde_json::to_value(self.expression.to_string()).ok()
+            },
+            "num_bits" => {
+                serde_json::to_value(self.num_bits).ok()
+            },
+            _ => None
+        }
+    }
+}
+
+impl Component for ExpressionGate {
+    fn get_shape(&self) -> Shape {
+        let variables = self.expression.variables();
+        let n = variables.len() as u32;
+        let width = 4;
+        let height = spaced_height(n);
+        let mut pins: Vec<_> = variables.into_iter().enumerate().map(|(i, name)| Pin {
+            x: 0,
+            y: spaced_y(i as u32, n),
+            name,
+            bits: self.num_bits,
+            direction: PinDirection::Input,
+        }).collect();
+        pins.push(Pin {
+            x: width,
+            y: height / 2,
+            name: "out".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Output,
+        });
+        Shape {
+            width,
+            height,
+            pins,
+            image_name: "expression".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let variables = self.expression.variables();
+        let bits = (0..self.num_bits).map(|b| {
+            self.expression.evaluate_bits(&|name| {
+                variables.iter().position(|v| v == name)
+                    .and_then(|i| inputs.get(i))
+                    .map(|v| v.bit(b))
+                    .unwrap_or(Bit::HighZ)
+            })
+        }).collect();
+
+        let mut result = vec![Value::floating(self.num_bits); variables.len()];
+        result.push(Value::new(bits));
+        result
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+fn expression_verilog(expr: &BooleanExpr, variables: &[String], pins: &[String], num_bits: u32) -> String {
+    let join = |exprs: &[BooleanExpr], op: &str| {
+        let terms: Vec<_> = exprs.iter().map(|e| expression_verilog(e, variables, pins, num_bits)).collect();
+        format!("({})", terms.join(op))
+    };
+    match expr {
+        BooleanExpr::Const(value) => format!("{{{}{{1'b{}}}}}", num_bits, *value as u8),
+        BooleanExpr::Var(name) => {
+            let i = variables.iter().position(|v| v == name).expect("Variable has a pin");
+            pins[i].clone()
+        },
+        BooleanExpr::Not(expr) => format!("~{}", expression_verilog(expr, variables, pins, num_bits)),
+        BooleanExpr::And(exprs) => join(exprs, " & "),
+        BooleanExpr::Or(exprs) => join(exprs, " | "),
+        BooleanExpr::Xor(exprs) => join(exprs, " ^ "),
+    }
+}
+
+impl HdlEmit for ExpressionGate {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let variables = self.expression.variables();
+        let expr = expression_verilog(&self.expression, &variables, pins, self.num_bits);
+        module.assign(&pins[variables.len()], &expr);
+        Ok(())
+    }
+}
+
+/// The widest input of a lookup table. Each additional bit doubles the
+/// number of rows.
+pub const MAX_LUT_INPUT_BITS: u32 = 10;
+
+/// Outputs the row of a table selected by its input, for logic which is
+/// easier to specify as a truth table than as gates.
+#[derive(Debug, Clone)]
+struct LookupTable {
+    in_bits: u32,
+    out_bits: u32,
+    rows: Vec<BitString>,
+}
+
+impl LookupTable {
+    fn new() -> Self {
+        let mut result = Self { in_bits: 2, out_bits: 1, rows: Vec::new() };
+        result.resize();
+        result
+    }
+    // Existing rows are kept where they still fit, so that the properties
+    // can be applied in any order
+    fn resize(&mut self) {
+        let out_bits = self.out_bits;
+        self.rows.resize(1 << self.in_bits, BitString::zero(out_bits));
+        for row in &mut self.rows {
+            *row = row.resized(out_bits);
+        }
+    }
+}
+
+impl Properties for LookupTable {
+    fn schema(&self) -> Schema {
+        let len = 1 << self.in_bits;
+        btreemap!{
+            "in_bits".into() => FieldSchema::new("Input bits", FieldType::integer(1, MAX_LUT_INPUT_BITS as i64)),
+            "out_bits".into() => data_bits_field().with_order(1),
+            "rows".into() => FieldSchema::new("Rows", FieldType::list(FieldType::BitVector { bits: self.out_bits }, len, len))
+                .with_description("The output for each input value, starting from zero")
+                .with_default(vec![BitString::zero(self.out_bits).to_string(); len as usize])
+                .with_order(2)
+                .depends_on("in_bits")
+                .depends_on("out_bits"),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "in_bits" => {
+                self.in_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.resize();
+                Ok(())
+            },
+            "out_bits" => {
+                self.out_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.resize();
+                Ok(())
+            },
+            "rows" => {
+                let rows: Vec<String> = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                if rows.len() != 1 << self.in_bits {
+                    return Err(PropertyError::invalid(name, format!("Expected {} rows", 1 << self.in_bits)));
+                }
+                self.rows = rows.iter()
+                    .map(|row| BitString::parse(row, self.out_bits))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| PropertyError::invalid(name, e))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "in_bits" => serde_json::to_value(self.in_bits).ok(),
+            "out_bits" => serde_json::to_value(self.out_bits).ok(),
+            "rows" => serde_json::to_value(self.rows.iter().map(|row| row.to_string()).collect::<Vec<_>>()).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for LookupTable {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 4,
+            height: 2,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "in".into(), bits: self.in_bits, direction: PinDirection::Input },
+                Pin { x: 4, y: 1, name: "out".into(), bits: self.out_bits, direction: PinDirection::Output },
+            ],
+            image_name: "lookup_table".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let out = match inputs.get(0).and_then(|v| v.to_u64()).and_then(|row| self.rows.get(row as usize)) {
+            Some(row) => row.to_value(),
+            None => Value::error(self.out_bits),
+        };
+        vec![Value::floating(self.in_bits), out]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for LookupTable {
+    // The rows are concatenated into a single constant, with the last row
+    // most significant, and shifted down to the selected row
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let rows: Vec<_> = self.rows.iter().rev()
+            .map(|row| format!("{}'b{}", self.out_bits, row.to_binary()))
+            .collect();
+        module.assign(&pins[1], &format!("{{{}}} >> ({} * {})", rows.join(", "), pins[0], self.out_bits));
+        Ok(())
+    }
 }
 
 pub fn library() -> Library {
     let mut result = Library::new();
+    let nary_gates = [
+        ("and_gate", "AND Gate", "Logical AND gate", NaryGateType::And, false),
+        ("or_gate", "OR Gate", "Logical OR gate", NaryGateType::Or, false),
+        ("xor_gate", "XOR Gate", "Logical XOR gate, true when exactly one input is true", NaryGateType::Xor, false),
+        ("nand_gate", "NAND Gate", "Logical NAND gate", NaryGateType::And, true),
+        ("nor_gate", "NOR Gate", "Logical NOR gate", NaryGateType::Or, true),
+        ("xnor_gate", "XNOR Gate", "Logical XNOR gate", NaryGateType::Xor, true),
+        ("odd_parity", "Odd Parity", "True when an odd number of inputs are true", NaryGateType::Parity, false),
+        ("even_parity", "Even Parity", "True when an even number of inputs are true", NaryGateType::Parity, true),
+    ];
+    for &(id, name, description, type_, invert_output) in &nary_gates {
+        result.add(
+            ComponentMetadata::new(id, name, CATEGORY, description)
+                .with_schema_version(2)
+                .with_preset(Preset::new("4 inputs").with_property("num_inputs", 4))
+                .with_preset(Preset::new("8 bits wide").with_property("num_bits", 8)),
+            move || Box::new(NaryGate::new(type_, invert_output))
+        );
+        result.add_migration(id, 1, migrate_invert_inputs)
+            .expect("Gate was just added");
+    }
+    result.add(
+        ComponentMetadata::new("not_gate", "NOT Gate", CATEGORY, "Logical NOT gate"),
+        || Box::new(UnaryGate::new(true))
+    );
     result.add(
-        ComponentMetadata::new("or_gate", "OR Gate", CATEGORY, "Logical OR gate"),
-        || Box::new(NaryGate::new(NaryGateType::Or))
+        ComponentMetadata::new("buffer", "Buffer", CATEGORY, "Passes its input through unchanged"),
+        || Box::new(UnaryGate::new(false))
+    );
+    result.add(
+        ComponentMetadata::new("controlled_buffer", "Controlled Buffer", CATEGORY, "Passes its input through while enabled, and is otherwise disconnected"),
+        || Box::new(ControlledGate { invert: false, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("controlled_inverter", "Controlled Inverter", CATEGORY, "Inverts its input while enabled, and is otherwise disconnected"),
+        || Box::new(ControlledGate { invert: true, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("expression", "Expression", CATEGORY, "Evaluates a boolean expression over its inputs")
+            .with_documentation("Each variable in the expression becomes an input pin, in the order \
+                the variables first appear. Variables are combined with `&` (AND), `|` (OR) and `^` \
+                (XOR), and `!` inverts a variable or a parenthesized expression. Multi-bit inputs are \
+                combined bitwise.")
+            .with_example("`(a & b) | (c & !sel)` selects between `a & b` and `c`.")
+            .with_tags(vec!["formula", "boolean"]),
+        || Box::new(ExpressionGate::new())
+    );
+    result.add(
+        ComponentMetadata::new("lookup_table", "Lookup Table", CATEGORY, "Outputs the row of a truth table selected by its input"),
+        || Box::new(LookupTable::new())
     );
     result
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{ComponentInfo, Interaction, field_groups};
+    use crate::circuit::{Circuit, Wire};
+    use crate::check::DesignRuleChecker;
+    use crate::net::PinRef;
+    use crate::simulation::Simulator;
+
+    fn eval(id: &str, inputs: &[u64]) -> Option<u64> {
+        let gate = library().create(id).unwrap();
+        let inputs: Vec<_> = inputs.iter().map(|&v| Value::from_u64(v, 1)).collect();
+        gate.evaluate(&inputs).last().unwrap().to_u64()
+    }
+
+    #[test]
+    fn truth_tables() {
+        let cases = [
+            ("and_gate", [0, 0, 0, 1]),
+            ("or_gate", [0, 1, 1, 1]),
+            ("xor_gate", [0, 1, 1, 0]),
+            ("nand_gate", [1, 1, 1, 0]),
+            ("nor_gate", [1, 0, 0, 0]),
+            ("xnor_gate", [1, 0, 0, 1]),
+            ("odd_parity", [0, 1, 1, 0]),
+            ("even_parity", [1, 0, 0, 1]),
+        ];
+        for &(id, expected) in &cases {
+            for i in 0..4 {
+                assert_eq!(eval(id, &[i & 1, i >> 1]), Some(expected[i as usize]), "{} {}", id, i);
+            }
+        }
+        assert_eq!(eval("not_gate", &[0]), Some(1));
+        assert_eq!(eval("not_gate", &[1]), Some(0));
+        assert_eq!(eval("buffer", &[1]), Some(1));
+        assert_eq!(eval("buffer", &[]), None);
+    }
+
+    #[test]
+    fn inversion() {
+        let mut gate = library().create("and_gate").unwrap();
+        gate.set_property("num_inputs", 3.into()).unwrap();
+        gate.set_property("invert_inputs", serde_json::json!([true, false, false])).unwrap();
+        let inputs = |a, b, c| -> PinValues { vec![Value::from_u64(a, 1), Value::from_u64(b, 1), Value::from_u64(c, 1)] };
+        assert_eq!(gate.evaluate(&inputs(0, 1, 1))[3].to_u64(), Some(1));
+        assert_eq!(gate.evaluate(&inputs(1, 1, 1))[3].to_u64(), Some(0));
+        gate.set_property("invert_output", true.into()).unwrap();
+        assert_eq!(gate.evaluate(&inputs(0, 1, 1))[3].to_u64(), Some(0));
+
+        // Parity counts every input, unlike XOR which is true for exactly one
+        let xor = library().create_with("xor_gate", serde_json::json!({ "num_inputs": 3 }).as_object().unwrap()).unwrap();
+        let parity = library().create_with("odd_parity", serde_json::json!({ "num_inputs": 3 }).as_object().unwrap()).unwrap();
+        assert_eq!(xor.evaluate(&inputs(1, 1, 1))[3].to_u64(), Some(0));
+        assert_eq!(parity.evaluate(&inputs(1, 1, 1))[3].to_u64(), Some(1));
+    }
+
+    #[test]
+    fn unknown_inputs() {
+        let gate = library().create("and_gate").unwrap();
+        let outputs = gate.evaluate(&vec![Value::from_u64(1, 1), Value::unknown(1)]);
+        assert_eq!(outputs[2].bits(), &[Bit::Unknown]);
+        let outputs = gate.evaluate(&vec![Value::from_u64(0, 1), Value::unknown(1)]);
+        assert_eq!(outputs[2].bits(), &[Bit::Zero]);
+        let not = library().create("not_gate").unwrap();
+        assert_eq!(not.evaluate(&vec![Value::unknown(1)])[1].bits(), &[Bit::Unknown]);
+    }
+
+    #[test]
+    fn tri_state_buffers() {
+        let lib = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("controlled_buffer").unwrap(), 4, 0);
+        let b = circuit.add_component(lib.create("controlled_inverter").unwrap(), 4, 10);
+        let mut pin = |x, y| circuit.add_component(lib.create("input_pin").unwrap(), x, y);
+        let (data_a, enable_a, data_b, enable_b) = (pin(2, 0), pin(3, 2), pin(2, 10), pin(3, 12));
+        circuit.add_wire(Wire::new((5, 3), (5, 2)));
+        circuit.add_wire(Wire::new((5, 13), (5, 12)));
+        circuit.add_wire(Wire::new((7, 1), (7, 11)));
+
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        let out = PinRef::new(a, 1);
+        assert_eq!(sim.pin_value(out).unwrap().bits(), &[Bit::HighZ]);
+        for &(id, v) in &[(enable_a, 1), (data_a, 1)] {
+            sim.interact(id, Interaction::Set(Value::from_u64(v, 1)));
+        }
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(out).unwrap().to_u64(), Some(1));
+        assert!(sim.contentions().is_empty());
+
+        // The inverter drives the opposite value once enabled
+        sim.interact(data_b, Interaction::Set(Value::from_u64(1, 1)));
+        sim.interact(enable_b, Interaction::Set(Value::from_u64(1, 1)));
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(out).unwrap().bits(), &[Bit::Error]);
+        let contentions = sim.contentions();
+        assert_eq!(contentions.len(), 1);
+        assert_eq!(contentions[0].drivers, vec![PinRef::new(a, 1), PinRef::new(b, 1)]);
+
+        sim.interact(enable_a, Interaction::Set(Value::from_u64(0, 1)));
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(out).unwrap().to_u64(), Some(0));
+        assert!(DesignRuleChecker::default().check(sim.circuit()).iter().all(|d| d.rule != "multiple_drivers"));
+    }
+
+    #[test]
+    fn floating_inputs_are_ignored() {
+        let gate = library().create("and_gate").unwrap();
+        let outputs = gate.evaluate(&vec![Value::from_u64(1, 1), Value::floating(1)]);
+        assert_eq!(outputs[2].to_u64(), Some(1));
+    }
+
+    #[test]
+    fn data_bits_property() {
+        let lib = library();
+        for &id in &["and_gate", "not_gate"] {
+            let mut gate = lib.create(id).unwrap();
+            assert_eq!(gate.schema()["num_bits"], data_bits_field().with_order(1).with_default(1), "{}", id);
+            gate.set_property("num_bits", serde_json::json!(8)).unwrap();
+            assert_eq!(gate.get_property("num_bits"), Some(serde_json::json!(8)));
+            assert_eq!(gate.get_shape().pins.last().unwrap().bits, 8);
+            assert!(gate.set_property("num_bits", serde_json::json!(0)).is_err());
+        }
+    }
+
+    #[test]
+    fn migrate_invert_inputs() {
+        let mut properties = SavedProperties::new();
+        properties.insert("num_inputs".into(), 3.into());
+        properties.insert("invert_input_1".into(), true.into());
+        library().migrate("nand_gate", 1, &mut properties).unwrap();
+        assert_eq!(properties.get("invert_input_1"), None);
+        assert_eq!(properties["invert_inputs"], serde_json::json!([false, true, false]));
+    }
+
+    #[test]
+    fn expression() {
+        let mut gate = library().create("expression").unwrap();
+        let names: Vec<_> = gate.get_shape().pins.into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["a", "b", "c", "out"]);
+        for i in 0..8 {
+            let inputs: Vec<_> = (0..3).map(|j| Value::from_u64((i >> j) & 1, 1)).collect();
+            let expected = (i & 1 == 1 && i & 2 == 2) || i & 4 == 0;
+            assert_eq!(gate.evaluate(&inputs)[3].to_u64(), Some(expected as u64), "{}", i);
+        }
+
+        gate.set_property("num_bits", serde_json::json!(4)).unwrap();
+        gate.set_property("expression", serde_json::json!("x ^ 1")).unwrap();
+        assert_eq!(gate.get_property("expression"), Some(serde_json::json!("(x ^ 1)")));
+        assert_eq!(gate.get_shape().pins.len(), 2);
+        assert_eq!(gate.evaluate(&vec![Value::from_u64(0b0110, 4)])[1].to_u64(), Some(0b1001));
+        assert!(gate.set_property("expression", serde_json::json!("x &")).is_err());
+    }
+
+    #[test]
+    fn lookup_table() {
+        let mut table = library().create("lookup_table").unwrap();
+        table.set_property("out_bits", 4.into()).unwrap();
+        table.set_property("rows", serde_json::json!(["0x3", "0b1010", "7", "0xf"])).unwrap();
+        let eval = |table: &ComponentInfo, value| table.evaluate(&vec![value])[1].to_u64();
+        assert_eq!(eval(&table, Value::from_u64(1, 2)), Some(0xa));
+        assert_eq!(eval(&table, Value::from_u64(3, 2)), Some(0xf));
+        assert_eq!(eval(&table, Value::floating(2)), None);
+        assert!(table.set_property("rows", serde_json::json!(["0", "0"])).is_err());
+        assert!(table.set_property("rows", serde_json::json!(["0x10", "0", "0", "0"])).is_err());
+
+        // Growing the table keeps the existing rows
+        table.set_property("in_bits", 3.into()).unwrap();
+        assert_eq!(eval(&table, Value::from_u64(2, 3)), Some(7));
+        assert_eq!(eval(&table, Value::from_u64(6, 3)), Some(0));
+        assert_eq!(table.get_property("rows").unwrap().as_array().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn property_layout() {
+        let gate = library().create("and_gate").unwrap();
+        let schema = gate.schema();
+        let groups = field_groups(&schema);
+        let layout: Vec<_> = groups.iter()
+            .map(|g| (g.name, g.fields.iter().map(|&(id, _)| id).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(layout, vec![
+            (None, vec!["num_inputs", "num_bits", "delay"]),
+            (Some(INVERSION_GROUP), vec!["invert_inputs", "invert_output"]),
+            (Some(ComponentInfo::LABEL_GROUP), vec!["label", "label_position"]),
+            (Some(ComponentInfo::PLACEMENT_GROUP), vec!["orientation", "flipped"]),
+        ]);
+    }
+}
@@ -0,0 +1,1455 @@
+use std::error::Error;
+
+use serde_json;
+use maplit::btreemap;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, FieldSchema, FieldType, PropertyError, Shape, Pin, PinDirection, Interaction, Color, ReflectType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::render::{DrawContext, Framebuffer};
+use crate::trace::Capture;
+use crate::audio::{AudioTrack, Waveform};
+use super::{data_bits_field, address_bits};
+use super::memory::{Clock, input_bit};
+
+pub const CATEGORY: &'static str = "Input/Output";
+
+fn num_bits_schema() -> Schema {
+    btreemap!{
+        "num_bits".into() => data_bits_field(),
+    }
+}
+
+fn set_num_bits(num_bits: &mut u32, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+    match name {
+        "num_bits" => {
+            *num_bits = serde_json::from_value(value)
+                .map_err(|e| PropertyError::from_serde(e, name))?;
+            Ok(())
+        },
+        _ => Err(PropertyError::unknown(name))
+    }
+}
+
+fn get_num_bits(num_bits: u32, name: &str) -> Option<serde_json::Value> {
+    match name {
+        "num_bits" => serde_json::to_value(num_bits).ok(),
+        _ => None
+    }
+}
+
+fn pin(x: i32, y: i32, name: &str, bits: u32, direction: PinDirection) -> Pin {
+    Pin { x, y, name: name.into(), bits, direction }
+}
+
+/// Drives a value chosen by the user. Pressing a single bit input toggles it.
+#[derive(Debug, Clone)]
+struct InputPin {
+    num_bits: u32,
+    value: Value,
+}
+
+impl Properties for InputPin {
+    fn schema(&self) -> Schema {
+        num_bits_schema()
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        set_num_bits(&mut self.num_bits, name, value)?;
+        self.value = Value::from_u64(0, self.num_bits);
+        Ok(())
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        get_num_bits(self.num_bits, name)
+    }
+}
+
+impl Component for InputPin {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 2,
+            height: 2,
+            pins: vec![pin(2, 1, "out", self.num_bits, PinDirection::Output)],
+            image_name: "input_pin".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![self.value.clone()]
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.value).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.value = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        match interaction {
+            Interaction::Press if self.num_bits == 1 => {
+                self.value = Value::new(vec![!self.value.bit(0)]);
+                true
+            },
+            Interaction::Set(ref value) if value.width() == self.num_bits => {
+                self.value = value.clone();
+                true
+            },
+            _ => false,
+        }
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(self.value.clone())
+    }
+}
+
+/// Drives a one while it is held down, and a zero otherwise.
+#[derive(Debug, Clone, Component)]
+struct Button {
+    pressed: bool,
+}
+
+impl Component for Button {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 2,
+            height: 2,
+            pins: vec![pin(2, 1, "out", 1, PinDirection::Output)],
+            image_name: "button".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::from_u64(self.pressed as u64, 1)]
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.pressed).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.pressed = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        let pressed = match interaction {
+            Interaction::Press => true,
+            Interaction::Release => false,
+            _ => return false,
+        };
+        self.pressed = pressed;
+        true
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(Value::from_u64(self.pressed as u64, 1))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DisplayType {
+    OutputPin,
+    Probe,
+    Led,
+    HexDisplay,
+}
+
+impl DisplayType {
+    fn image_name(self) -> &'static str {
+        match self {
+            DisplayType::OutputPin => "output_pin",
+            DisplayType::Probe => "probe",
+            DisplayType::Led => "led",
+            DisplayType::HexDisplay => "hex_display",
+        }
+    }
+    fn has_data_bits(self) -> bool {
+        match self {
+            DisplayType::OutputPin | DisplayType::Probe => true,
+            DisplayType::Led | DisplayType::HexDisplay => false,
+        }
+    }
+    fn default_color(self) -> Color {
+        match self {
+            DisplayType::OutputPin | DisplayType::Probe => Color::rgb(0x22, 0x22, 0x22),
+            DisplayType::Led | DisplayType::HexDisplay => Color::rgb(0xff, 0x22, 0x22),
+        }
+    }
+}
+
+// The brightness of an unlit segment, relative to a lit one
+const UNLIT: f64 = 0.25;
+
+/// Shows the value on its single input pin.
+#[derive(Debug, Clone)]
+struct Display {
+    type_: DisplayType,
+    num_bits: u32,
+    color: Color,
+    value: Value,
+}
+
+impl Display {
+    fn new(type_: DisplayType) -> Self {
+        let num_bits = if type_ == DisplayType::HexDisplay { 4 } else { 1 };
+        Self {
+            type_,
+            num_bits,
+            color: type_.default_color(),
+            value: Value::floating(num_bits),
+        }
+    }
+}
+
+impl Properties for Display {
+    fn schema(&self) -> Schema {
+        let mut schema = if self.type_.has_data_bits() { num_bits_schema() } else { Schema::new() };
+        schema.insert("color".into(), FieldSchema::new("Color", FieldType::color()));
+        schema
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        if name == "color" {
+            self.color = serde_json::from_value(value)
+                .map_err(|e| PropertyError::from_serde(e, name))?;
+            return Ok(());
+        }
+        if !self.type_.has_data_bits() {
+            return Err(PropertyError::unknown(name));
+        }
+        set_num_bits(&mut self.num_bits, name, value)?;
+        self.value = Value::floating(self.num_bits);
+        Ok(())
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "color" => serde_json::to_value(self.color).ok(),
+            _ if self.type_.has_data_bits() => get_num_bits(self.num_bits, name),
+            _ => None,
+        }
+    }
+}
+
+impl Component for Display {
+    fn get_shape(&self) -> Shape {
+        let (width, height, x, y) = match self.type_ {
+            DisplayType::OutputPin | DisplayType::Probe | DisplayType::Led => (2, 2, 0, 1),
+            DisplayType::HexDisplay => (3, 4, 1, 4),
+        };
+        Shape {
+            width,
+            height,
+            pins: vec![pin(x, y, "in", self.num_bits, PinDirection::Input)],
+            image_name: self.type_.image_name().into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.value).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.value = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        if let Some(value) = inputs.get(0) {
+            self.value = value.clone();
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(self.num_bits)]
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(self.value.clone())
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        let (width, height) = (shape.width as f64, shape.height as f64);
+        let center = (width / 2.0, height / 2.0);
+        let color = self.color.to_hex();
+        match self.type_ {
+            DisplayType::Led => {
+                let lit = self.value.bit(0) == Bit::One;
+                let fill = if lit { self.color } else { self.color.scaled(UNLIT) };
+                ctx.circle(center, width / 2.0, Some(&fill.to_hex()), None);
+            },
+            DisplayType::HexDisplay => {
+                ctx.rect((0.0, 0.0), (width, height), Some("#111"), None);
+                let text = match self.value.to_u64() {
+                    Some(v) => format!("{:X}", v),
+                    None => "-".into(),
+                };
+                ctx.text(center, height * 0.6, &text, &color);
+            },
+            DisplayType::OutputPin | DisplayType::Probe => {
+                ctx.rect((0.0, 0.0), (width, height), Some("#fff"), Some(&color));
+                ctx.text(center, 0.8, &self.value.to_string(), &color);
+            },
+        }
+    }
+}
+
+const SEGMENTS: [(&'static str, i32, i32); 8] = [
+    ("a", 2, 0), ("b", 3, 0), ("c", 2, 6), ("d", 1, 6),
+    ("e", 0, 6), ("f", 1, 0), ("g", 0, 0), ("dp", 3, 6),
+];
+
+/// A seven segment display with a decimal point. The displayed value has one
+/// bit per segment, in the order a-g followed by the decimal point.
+#[derive(Debug, Clone, Component)]
+struct SevenSegment {
+    #[property(name = "Color")]
+    color: Color,
+    segments: Value,
+}
+
+impl Component for SevenSegment {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 6,
+            pins: SEGMENTS.iter().map(|&(name, x, y)| pin(x, y, name, 1, PinDirection::Input)).collect(),
+            image_name: "seven_segment".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.segments).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.segments = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        self.segments = Value::new((0..SEGMENTS.len()).map(|i| {
+            inputs.get(i).map(|v| v.bit(0)).unwrap_or(Bit::HighZ)
+        }).collect());
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(1); SEGMENTS.len()]
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(self.segments.clone())
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        // The position and size of each segment, in the order of `SEGMENTS`
+        const RECTS: [((f64, f64), (f64, f64)); 7] = [
+            ((0.7, 0.5), (1.6, 0.3)), ((2.3, 0.7), (0.3, 2.1)), ((2.3, 3.2), (0.3, 2.1)), ((0.7, 5.2), (1.6, 0.3)),
+            ((0.4, 3.2), (0.3, 2.1)), ((0.4, 0.7), (0.3, 2.1)), ((0.7, 2.85), (1.6, 0.3)),
+        ];
+        let (lit, unlit) = (self.color.to_hex(), self.color.scaled(UNLIT).to_hex());
+        let color = |i| if self.segments.bit(i) == Bit::One { lit.as_str() } else { unlit.as_str() };
+        ctx.rect((0.0, 0.0), (3.0, 6.0), Some("#111"), None);
+        for (i, &(position, size)) in RECTS.iter().enumerate() {
+            ctx.rect(position, size, Some(color(i as u32)), None);
+        }
+        ctx.circle((2.75, 5.5), 0.15, Some(color(7)), None);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum TriggerMode {
+    /// Samples continuously, keeping the most recent.
+    Continuous,
+    RisingEdge,
+    FallingEdge,
+}
+
+impl ReflectType for TriggerMode {
+    fn field_type() -> FieldType {
+        use self::TriggerMode::*;
+        FieldType::for_enum(&[Continuous, RisingEdge, FallingEdge])
+    }
+}
+
+// The number of samples plotted across each grid unit of the display
+const SAMPLES_PER_UNIT: usize = 4;
+
+/// A logic analyzer which samples each of its probes once per tick.
+///
+/// When triggered by an edge, samples leading up to the trigger are kept, and
+/// sampling stops once the trigger is a quarter of the way through the
+/// buffer. Pressing the component clears the buffer and waits for the
+/// trigger again.
+#[derive(Debug, Clone, Component)]
+struct Oscilloscope {
+    #[property(name = "Probes", min = 1, max = 16, order = 0)]
+    num_probes: u32,
+    #[property(name = "Data bits", min = 1, max = 64, order = 1)]
+    num_bits: u32,
+    #[property(name = "Sample depth", min = 8, max = 65536, unit = "samples")]
+    depth: u32,
+    #[property(name = "Trigger")]
+    trigger: TriggerMode,
+    #[property(name = "Trigger probe", description = "The probe whose lowest bit is watched for the trigger edge", max = 15)]
+    trigger_probe: u32,
+    inputs: Vec<Value>,
+    last_trigger: Bit,
+    capture: Capture,
+}
+
+impl Oscilloscope {
+    fn new() -> Self {
+        Self {
+            num_probes: 4,
+            num_bits: 1,
+            depth: 256,
+            trigger: TriggerMode::Continuous,
+            trigger_probe: 0,
+            inputs: Vec::new(),
+            last_trigger: Bit::HighZ,
+            capture: Capture::new(),
+        }
+    }
+    fn is_finished(&self) -> bool {
+        let depth = self.depth as usize;
+        match self.capture.trigger_index() {
+            Some(index) => self.capture.len() >= depth && index <= depth / 4,
+            None => false,
+        }
+    }
+    fn sample(&mut self) {
+        let sample: Vec<_> = (0..self.num_probes as usize)
+            .map(|i| self.inputs.get(i).cloned().unwrap_or_else(|| Value::floating(self.num_bits)))
+            .collect();
+        let current = sample.get(self.trigger_probe as usize).map(|v| v.bit(0)).unwrap_or(Bit::HighZ);
+        let fired = match self.trigger {
+            TriggerMode::Continuous => false,
+            TriggerMode::RisingEdge => self.last_trigger == Bit::Zero && current == Bit::One,
+            TriggerMode::FallingEdge => self.last_trigger == Bit::One && current == Bit::Zero,
+        };
+        self.last_trigger = current;
+        self.capture.push(sample, self.depth as usize);
+        if fired && self.capture.trigger_index().is_none() {
+            self.capture.set_triggered();
+        }
+    }
+}
+
+impl Component for Oscilloscope {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 8,
+            height: self.num_probes as i32 * 2,
+            pins: (0..self.num_probes as i32)
+                .map(|i| pin(0, i * 2 + 1, &format!("probe{}", i), self.num_bits, PinDirection::Input))
+                .collect(),
+            image_name: "oscilloscope".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((&self.inputs, self.last_trigger, &self.capture)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (inputs, last_trigger, capture) = serde_json::from_value(state)?;
+        self.inputs = inputs;
+        self.last_trigger = last_trigger;
+        self.capture = capture;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        self.inputs = inputs.to_vec();
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(self.num_bits); self.num_probes as usize]
+    }
+    fn tick(&mut self) -> bool {
+        if !self.is_finished() {
+            self.sample();
+        }
+        false
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        match interaction {
+            Interaction::Press => {
+                self.capture.clear();
+                true
+            },
+            _ => false,
+        }
+    }
+    fn capture(&self) -> Option<&Capture> {
+        Some(&self.capture)
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        let (width, height) = (shape.width as f64, shape.height as f64);
+        ctx.rect((0.0, 0.0), (width, height), Some("#111"), None);
+        let shown = shape.width as usize * SAMPLES_PER_UNIT;
+        let start = self.capture.len().saturating_sub(shown);
+        let step = 1.0 / SAMPLES_PER_UNIT as f64;
+        for probe in 0..self.num_probes as usize {
+            let values = self.capture.probe(probe);
+            let middle = probe as f64 * 2.0 + 1.0;
+            if self.num_bits > 1 {
+                if let Some(value) = values.last() {
+                    ctx.text((width / 2.0, middle), 0.8, &value.to_string(), "#2f2");
+                }
+                continue;
+            }
+            let level = |v: &Value| match v.bit(0) {
+                Bit::One => middle - 0.6,
+                Bit::Zero => middle + 0.6,
+                _ => middle,
+            };
+            let mut last = None;
+            // A probe added since the last sample has a shorter history
+            for (i, value) in values.iter().skip(start).enumerate() {
+                let (x, y) = (i as f64 * step, level(value));
+                if let Some(previous) = last {
+                    if previous != y {
+                        ctx.line((x, previous), (x, y), "#2f2");
+                    }
+                }
+                ctx.line((x, y), (x + step, y), "#2f2");
+                last = Some(y);
+            }
+        }
+        if let Some(index) = self.capture.trigger_index().filter(|&i| i >= start) {
+            let x = (index - start) as f64 * step;
+            ctx.line((x, 0.0), (x, height), "#f22");
+        }
+    }
+}
+
+/// A grid of LEDs. An LED is lit when the bits of both the `row` and
+/// `column` inputs for its position are one, so that a circuit can scan
+/// across the rows or columns to show an image.
+#[derive(Debug, Clone, Component)]
+struct LedMatrix {
+    #[property(name = "Rows", min = 1, max = 64, order = 0)]
+    rows: u32,
+    #[property(name = "Columns", min = 1, max = 64, order = 1)]
+    columns: u32,
+    #[property(name = "Color")]
+    color: Color,
+    row_select: Value,
+    column_select: Value,
+}
+
+impl LedMatrix {
+    fn new() -> Self {
+        Self {
+            rows: 8,
+            columns: 8,
+            color: DisplayType::Led.default_color(),
+            row_select: Value::floating(8),
+            column_select: Value::floating(8),
+        }
+    }
+}
+
+impl Component for LedMatrix {
+    fn get_shape(&self) -> Shape {
+        let height = self.rows as i32 + 1;
+        Shape {
+            width: self.columns as i32 + 1,
+            height,
+            pins: vec![
+                pin(0, 1, "row", self.rows, PinDirection::Input),
+                pin(1, height, "column", self.columns, PinDirection::Input),
+            ],
+            image_name: "led_matrix".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((&self.row_select, &self.column_select)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (row_select, column_select) = serde_json::from_value(state)?;
+        self.row_select = row_select;
+        self.column_select = column_select;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        self.row_select = inputs.get(0).cloned().unwrap_or_else(|| Value::floating(self.rows));
+        self.column_select = inputs.get(1).cloned().unwrap_or_else(|| Value::floating(self.columns));
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(self.rows), Value::floating(self.columns)]
+    }
+    fn framebuffer(&self) -> Option<Framebuffer> {
+        let mut result = Framebuffer::new(self.columns, self.rows, self.color.scaled(UNLIT));
+        for y in (0..self.rows).filter(|&y| self.row_select.bit(y) == Bit::One) {
+            for x in (0..self.columns).filter(|&x| self.column_select.bit(x) == Bit::One) {
+                result.set(x, y, self.color);
+            }
+        }
+        Some(result)
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        ctx.rect((0.0, 0.0), (shape.width as f64, shape.height as f64), Some("#111"), None);
+        if let Some(framebuffer) = self.framebuffer() {
+            framebuffer.draw(ctx, (0.5, 0.5), (self.columns as f64, self.rows as f64));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum ColorFormat {
+    /// One bit per pixel, lit or unlit.
+    Monochrome,
+    /// Eight bits per pixel: three each of red and green, and two of blue.
+    Rgb332,
+    /// Twelve bits per pixel, four of each component.
+    Rgb444,
+    /// Twenty-four bits per pixel, eight of each component.
+    Rgb888,
+}
+
+impl ReflectType for ColorFormat {
+    fn field_type() -> FieldType {
+        use self::ColorFormat::*;
+        FieldType::for_enum(&[Monochrome, Rgb332, Rgb444, Rgb888])
+    }
+}
+
+impl ColorFormat {
+    fn bits(self) -> u32 {
+        match self {
+            ColorFormat::Monochrome => 1,
+            ColorFormat::Rgb332 => 8,
+            ColorFormat::Rgb444 => 12,
+            ColorFormat::Rgb888 => 24,
+        }
+    }
+    fn decode(self, value: u64) -> Color {
+        // Scales a component with the given number of bits to eight bits
+        let channel = |shift: u32, bits: u32| {
+            let max = (1 << bits) - 1;
+            (((value >> shift) & max) * 255 / max) as u8
+        };
+        match self {
+            ColorFormat::Monochrome if value & 1 == 1 => Color::rgb(255, 255, 255),
+            ColorFormat::Monochrome => Color::rgb(0, 0, 0),
+            ColorFormat::Rgb332 => Color::rgb(channel(5, 3), channel(2, 3), channel(0, 2)),
+            ColorFormat::Rgb444 => Color::rgb(channel(8, 4), channel(4, 4), channel(0, 4)),
+            ColorFormat::Rgb888 => Color::rgb(channel(16, 8), channel(8, 8), channel(0, 8)),
+        }
+    }
+}
+
+/// A bitmap display. On each rising clock edge while `write` is one, the
+/// pixel at `x` and `y` is set to the color on `data`. Pixels are cleared
+/// to black while `clear` is one.
+#[derive(Debug, Clone, Component)]
+struct VideoDisplay {
+    #[property(name = "Width", min = 1, max = 256, unit = "pixels", order = 0)]
+    width: u32,
+    #[property(name = "Height", min = 1, max = 256, unit = "pixels", order = 1)]
+    height: u32,
+    #[property(name = "Color format", order = 2)]
+    format: ColorFormat,
+    framebuffer: Framebuffer,
+    clock: Clock,
+}
+
+impl VideoDisplay {
+    fn new() -> Self {
+        Self {
+            width: 32,
+            height: 32,
+            format: ColorFormat::Rgb444,
+            framebuffer: Framebuffer::new(32, 32, Color::rgb(0, 0, 0)),
+            clock: Clock::new(),
+        }
+    }
+    fn is_resized(&self) -> bool {
+        (self.framebuffer.width(), self.framebuffer.height()) != (self.width, self.height)
+    }
+    // The pixels are cleared when the display is resized
+    fn resize(&mut self) {
+        if self.is_resized() {
+            self.framebuffer = Framebuffer::new(self.width, self.height, Color::rgb(0, 0, 0));
+        }
+    }
+}
+
+impl Component for VideoDisplay {
+    fn get_shape(&self) -> Shape {
+        // One grid unit per four pixels, leaving room for the pins
+        let width = (self.width as i32 + 3) / 4 + 1;
+        let height = ((self.height as i32 + 3) / 4).max(5) + 1;
+        Shape {
+            width,
+            height,
+            pins: vec![
+                pin(0, 1, "x", address_bits(self.width), PinDirection::Input),
+                pin(0, 2, "y", address_bits(self.height), PinDirection::Input),
+                pin(0, 3, "data", self.format.bits(), PinDirection::Input),
+                pin(0, 4, "write", 1, PinDirection::Input),
+                pin(0, 5, "clock", 1, PinDirection::Input),
+                pin(1, height, "clear", 1, PinDirection::Input),
+            ],
+            image_name: "video_display".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, &self.framebuffer)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, framebuffer): (Bit, Framebuffer) = serde_json::from_value(state)?;
+        if (framebuffer.width(), framebuffer.height()) != (self.width, self.height) {
+            return Err("The framebuffer does not match the size of the display".into());
+        }
+        self.clock.last = last;
+        self.framebuffer = framebuffer;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        self.resize();
+        let fired = self.clock.fired(input_bit(inputs, 4));
+        if input_bit(inputs, 5) == Bit::One {
+            self.framebuffer.fill(Color::rgb(0, 0, 0));
+        } else if fired && input_bit(inputs, 3) == Bit::One {
+            let input = |i: usize| inputs.get(i).and_then(|v| v.to_u64());
+            if let (Some(x), Some(y), Some(data)) = (input(0), input(1), input(2)) {
+                self.framebuffer.set(x as u32, y as u32, self.format.decode(data));
+            }
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        self.get_shape().pins.iter().map(|pin| Value::floating(pin.bits)).collect()
+    }
+    fn framebuffer(&self) -> Option<Framebuffer> {
+        let mut result = self.framebuffer.clone();
+        if self.is_resized() {
+            result = Framebuffer::new(self.width, self.height, Color::rgb(0, 0, 0));
+        }
+        Some(result)
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        ctx.rect((0.0, 0.0), (shape.width as f64, shape.height as f64), Some("#fff"), Some("#222"));
+        if let Some(framebuffer) = self.framebuffer() {
+            let size = (self.width as f64 / 4.0, self.height as f64 / 4.0);
+            framebuffer.draw(ctx, (0.5, 0.5), size);
+        }
+    }
+}
+
+/// Plays a tone at the frequency given by its input, in hertz, while
+/// `enable` is one. The tone can be heard with `Simulator::render_audio`.
+#[derive(Debug, Clone, Component)]
+struct Buzzer {
+    #[property(name = "Data bits", min = 1, max = 32, order = 0)]
+    num_bits: u32,
+    #[property(name = "Waveform")]
+    waveform: Waveform,
+    #[property(name = "Volume", min = 0, max = 1, step = 0.05)]
+    volume: f64,
+    frequency: f64,
+    ticks: u64,
+    track: AudioTrack,
+}
+
+impl Buzzer {
+    fn new() -> Self {
+        Self {
+            num_bits: 12,
+            waveform: Waveform::Square,
+            volume: 0.5,
+            frequency: 0.0,
+            ticks: 0,
+            track: AudioTrack::new(Waveform::Square, 0.5),
+        }
+    }
+}
+
+impl Component for Buzzer {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                pin(0, 1, "frequency", self.num_bits, PinDirection::Input),
+                pin(1, 2, "enable", 1, PinDirection::Input),
+            ],
+            image_name: "buzzer".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.frequency, self.ticks, &self.track)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (frequency, ticks, track) = serde_json::from_value(state)?;
+        self.frequency = frequency;
+        self.ticks = ticks;
+        self.track = track;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let enabled = input_bit(inputs, 1) == Bit::One;
+        self.frequency = match inputs.get(0).and_then(|v| v.to_u64()) {
+            Some(frequency) if enabled => frequency as f64,
+            _ => 0.0,
+        };
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(self.num_bits), Value::floating(1)]
+    }
+    fn tick(&mut self) -> bool {
+        self.track.waveform = self.waveform;
+        self.track.volume = self.volume;
+        self.track.record(self.ticks, self.frequency);
+        self.ticks += 1;
+        false
+    }
+    fn audio(&self) -> Option<&AudioTrack> {
+        Some(&self.track)
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(Value::from_u64((self.frequency > 0.0) as u64, 1))
+    }
+}
+
+/// Outputs a value chosen by dragging a handle along a track, from zero at
+/// the left to the largest value at the right.
+#[derive(Debug, Clone, Component)]
+struct Slider {
+    #[property(name = "Data bits", min = 1, max = 64)]
+    num_bits: u32,
+    position: u64,
+}
+
+impl Slider {
+    fn max(&self) -> u64 {
+        !0 >> (64 - self.num_bits)
+    }
+}
+
+impl Component for Slider {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 6,
+            height: 2,
+            pins: vec![pin(6, 1, "out", self.num_bits, PinDirection::Output)],
+            image_name: "slider".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::from_u64(self.position.min(self.max()), self.num_bits)]
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.position).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.position = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        match interaction {
+            Interaction::Set(ref value) if value.width() == self.num_bits => match value.to_u64() {
+                Some(position) => {
+                    self.position = position;
+                    true
+                },
+                None => false,
+            },
+            _ => false,
+        }
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(self.evaluate(&vec![]).remove(0))
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        ctx.rect((0.0, 0.0), (6.0, 2.0), Some("#fff"), Some("#222"));
+        ctx.line((0.5, 1.0), (5.5, 1.0), "#888");
+        let x = 0.5 + 5.0 * self.position.min(self.max()) as f64 / self.max() as f64;
+        ctx.rect((x - 0.2, 0.4), (0.4, 1.2), Some("#222"), None);
+    }
+}
+
+/// A bank of switches, each of which drives one bit of the output. The
+/// first switch drives the lowest bit.
+#[derive(Debug, Clone, Component)]
+struct DipSwitch {
+    #[property(name = "Switches", min = 1, max = 64)]
+    num_switches: u32,
+    switches: u64,
+}
+
+impl Component for DipSwitch {
+    fn get_shape(&self) -> Shape {
+        let width = self.num_switches as i32 + 1;
+        Shape {
+            width,
+            height: 3,
+            pins: vec![pin(width, 1, "out", self.num_switches, PinDirection::Output)],
+            image_name: "dip_switch".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::from_u64(self.switches, self.num_switches)]
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.switches).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        self.switches = serde_json::from_value(state)?;
+        Ok(())
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        match interaction {
+            Interaction::Toggle(index) if index < self.num_switches => {
+                self.switches ^= 1 << index;
+                true
+            },
+            Interaction::Set(ref value) if value.width() == self.num_switches => match value.to_u64() {
+                Some(switches) => {
+                    self.switches = switches;
+                    true
+                },
+                None => false,
+            },
+            _ => false,
+        }
+    }
+    fn display_value(&self) -> Option<Value> {
+        Some(Value::from_u64(self.switches, self.num_switches))
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let width = self.num_switches as f64 + 1.0;
+        ctx.rect((0.0, 0.0), (width, 3.0), Some("#c22"), None);
+        for i in 0..self.num_switches {
+            let x = i as f64 + 0.7;
+            let on = self.switches >> i & 1 == 1;
+            ctx.rect((x, 0.5), (0.6, 2.0), Some("#fff"), None);
+            ctx.rect((x + 0.1, if on { 0.6 } else { 1.5 }), (0.4, 0.9), Some("#222"), None);
+        }
+    }
+}
+
+/// A two axis joystick, which springs back to its centre when released.
+/// Each axis outputs an unsigned value, which is half of its range while
+/// centred, and increases to the right and downwards.
+#[derive(Debug, Clone, Component)]
+struct Joystick {
+    #[property(name = "Data bits", min = 2, max = 32)]
+    num_bits: u32,
+    x: i64,
+    y: i64,
+}
+
+impl Joystick {
+    // The furthest the stick can move from its centre along each axis
+    fn reach(&self) -> i64 {
+        (1 << (self.num_bits - 1)) - 1
+    }
+    fn axis(&self, offset: i64) -> Value {
+        let reach = self.reach();
+        Value::from_u64((offset.max(-reach).min(reach) + reach + 1) as u64, self.num_bits)
+    }
+}
+
+impl Component for Joystick {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 4,
+            height: 4,
+            pins: vec![
+                pin(4, 1, "x", self.num_bits, PinDirection::Output),
+                pin(4, 3, "y", self.num_bits, PinDirection::Output),
+            ],
+            image_name: "joystick".into(),
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![self.axis(self.x), self.axis(self.y)]
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.x, self.y)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (x, y) = serde_json::from_value(state)?;
+        self.x = x;
+        self.y = y;
+        Ok(())
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        let reach = self.reach();
+        let (x, y) = match interaction {
+            Interaction::Move { x, y } => (x.max(-reach).min(reach), y.max(-reach).min(reach)),
+            Interaction::Release => (0, 0),
+            _ => return false,
+        };
+        self.x = x;
+        self.y = y;
+        true
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        ctx.rect((0.0, 0.0), (4.0, 4.0), Some("#fff"), Some("#222"));
+        ctx.circle((2.0, 2.0), 1.6, None, Some("#888"));
+        let reach = self.reach() as f64;
+        let offset = |v: i64| 2.0 + 1.2 * v as f64 / reach;
+        ctx.line((2.0, 2.0), (offset(self.x), offset(self.y)), "#222");
+        ctx.circle((offset(self.x), offset(self.y)), 0.4, Some("#c22"), None);
+    }
+}
+
+// Characters are transferred as seven bit ASCII codes
+const CHAR_BITS: u32 = 7;
+
+/// Buffers the keys typed by the user, so that the circuit can read them
+/// one at a time. The oldest key is output while `available` is one, and is
+/// removed on a rising clock edge while `read` is one.
+#[derive(Debug, Clone, Component)]
+struct Keyboard {
+    #[property(name = "Buffer size", description = "Keys typed while the buffer is full are ignored", min = 1, max = 256)]
+    buffer_size: u32,
+    buffer: String,
+    clock: Clock,
+}
+
+impl Keyboard {
+    fn new() -> Self {
+        Self {
+            buffer_size: 32,
+            buffer: String::new(),
+            clock: Clock::new(),
+        }
+    }
+}
+
+impl Component for Keyboard {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 6,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "read", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(2, 4, "clear", 1, PinDirection::Input),
+                pin(6, 1, "data", CHAR_BITS, PinDirection::Output),
+                pin(6, 3, "available", 1, PinDirection::Output),
+            ],
+            image_name: "keyboard".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, &self.buffer)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, buffer) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.buffer = buffer;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 1));
+        if input_bit(inputs, 2) == Bit::One {
+            self.buffer.clear();
+        } else if fired && input_bit(inputs, 0) == Bit::One && !self.buffer.is_empty() {
+            self.buffer.remove(0);
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        let next = self.buffer.chars().next();
+        vec![
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::from_u64(next.map_or(0, |c| c as u64), CHAR_BITS),
+            Value::from_u64(next.is_some() as u64, 1),
+        ]
+    }
+    fn interact(&mut self, interaction: Interaction) -> bool {
+        match interaction {
+            Interaction::Key(c) if c.is_ascii() && self.buffer.len() < self.buffer_size as usize => {
+                self.buffer.push(c);
+                true
+            },
+            _ => false,
+        }
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        ctx.rect((0.0, 0.0), (6.0, 4.0), Some("#fff"), Some("#222"));
+        // The clock input
+        ctx.polygon(vec![(0.0, 2.6), (0.4, 3.0), (0.0, 3.4)], None, Some("#222"));
+        // The next keys to be read
+        let text: String = self.buffer.chars().filter(|c| !c.is_control()).take(8).collect();
+        ctx.text((3.0, 2.0), 0.8, &text, "#222");
+    }
+}
+
+/// Prints characters to a scrolling text display. On each rising clock
+/// edge while `write` is one, the character on `data` is printed: newline
+/// and carriage return start a new line, backspace removes the last
+/// character, and form feed clears the display.
+#[derive(Debug, Clone, Component)]
+struct Terminal {
+    #[property(name = "Rows", min = 1, max = 64)]
+    rows: u32,
+    #[property(name = "Columns", min = 1, max = 128)]
+    columns: u32,
+    lines: Vec<String>,
+    clock: Clock,
+}
+
+impl Terminal {
+    fn new() -> Self {
+        Self {
+            rows: 8,
+            columns: 32,
+            lines: vec![String::new()],
+            clock: Clock::new(),
+        }
+    }
+    fn new_line(&mut self) {
+        self.lines.push(String::new());
+        if self.lines.len() > self.rows as usize {
+            let excess = self.lines.len() - self.rows as usize;
+            self.lines.drain(..excess);
+        }
+    }
+    fn print(&mut self, c: char) {
+        match c {
+            '\n' | '\r' => self.new_line(),
+            '\u{8}' => {
+                self.lines.last_mut().expect("There is always a line").pop();
+            },
+            '\u{c}' => self.lines = vec![String::new()],
+            c if c.is_control() => {},
+            c => {
+                if self.lines.last().map_or(false, |line| line.len() >= self.columns as usize) {
+                    self.new_line();
+                }
+                self.lines.last_mut().expect("There is always a line").push(c);
+            },
+        }
+    }
+}
+
+impl Component for Terminal {
+    fn get_shape(&self) -> Shape {
+        let (width, height) = (self.columns as i32 / 2 + 2, self.rows as i32 + 2);
+        Shape {
+            width,
+            height,
+            pins: vec![
+                pin(0, 1, "data", CHAR_BITS, PinDirection::Input),
+                pin(0, 2, "write", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(2, height, "clear", 1, PinDirection::Input),
+            ],
+            image_name: "terminal".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, &self.lines)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, lines): (Bit, Vec<String>) = serde_json::from_value(state)?;
+        if lines.is_empty() {
+            return Err("A terminal has at least one line".into());
+        }
+        self.clock.last = last;
+        self.lines = lines;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 2));
+        if input_bit(inputs, 3) == Bit::One {
+            self.lines = vec![String::new()];
+        } else if fired && input_bit(inputs, 1) == Bit::One {
+            // Unknown characters are shown as `?`
+            let c = inputs.get(0).and_then(|v| v.to_u64()).map_or('?', |v| v as u8 as char);
+            self.print(c);
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::floating(CHAR_BITS), Value::floating(1), Value::floating(1), Value::floating(1)]
+    }
+    fn terminal_text(&self) -> Option<String> {
+        Some(self.lines.join("\n"))
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        let shape = self.get_shape();
+        ctx.rect((0.0, 0.0), (shape.width as f64, shape.height as f64), Some("#111"), None);
+        for (i, line) in self.lines.iter().enumerate() {
+            let half_width = line.chars().count() as f64 * 0.25;
+            ctx.text((1.0 + half_width, 1.5 + i as f64), 0.8, line, "#2f2");
+        }
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("input_pin", "Input Pin", CATEGORY, "A value which can be changed while the simulation runs"),
+        || Box::new(InputPin { num_bits: 1, value: Value::from_u64(0, 1) })
+    );
+    result.add(
+        ComponentMetadata::new("button", "Button", CATEGORY, "Outputs one while held down"),
+        || Box::new(Button { pressed: false })
+    );
+    result.add(
+        ComponentMetadata::new("slider", "Slider", CATEGORY, "Outputs a value chosen by dragging a handle"),
+        || Box::new(Slider { num_bits: 8, position: 0 })
+    );
+    result.add(
+        ComponentMetadata::new("dip_switch", "DIP Switch", CATEGORY, "A bank of switches, each driving one bit of the output"),
+        || Box::new(DipSwitch { num_switches: 8, switches: 0 })
+    );
+    result.add(
+        ComponentMetadata::new("joystick", "Joystick", CATEGORY, "Outputs the position of a stick along two axes"),
+        || Box::new(Joystick { num_bits: 4, x: 0, y: 0 })
+    );
+    let displays = [
+        ("output_pin", "Output Pin", "Shows the value of a circuit output", DisplayType::OutputPin),
+        ("probe", "Probe", "Shows the value on a wire", DisplayType::Probe),
+        ("led", "LED", "Lights up when its input is one", DisplayType::Led),
+        ("hex_display", "Hex Display", "Shows a four bit value as a hexadecimal digit", DisplayType::HexDisplay),
+    ];
+    for &(id, name, description, type_) in &displays {
+        result.add(
+            ComponentMetadata::new(id, name, CATEGORY, description),
+            move || Box::new(Display::new(type_))
+        );
+    }
+    result.add(
+        ComponentMetadata::new("seven_segment", "7-Segment Display", CATEGORY, "Lights each segment whose input is one"),
+        || Box::new(SevenSegment { color: Color::rgb(0xff, 0x22, 0x22), segments: Value::floating(SEGMENTS.len() as u32) })
+    );
+    result.add(
+        ComponentMetadata::new("oscilloscope", "Oscilloscope", CATEGORY, "Records the values on its probes over time"),
+        || Box::new(Oscilloscope::new())
+    );
+    result.add(
+        ComponentMetadata::new("buzzer", "Buzzer", CATEGORY, "Plays a tone at the frequency given by its input"),
+        || Box::new(Buzzer::new())
+    );
+    result.add(
+        ComponentMetadata::new("led_matrix", "LED Matrix", CATEGORY, "A grid of LEDs, lit by selecting their row and column"),
+        || Box::new(LedMatrix::new())
+    );
+    result.add(
+        ComponentMetadata::new("video_display", "Video Display", CATEGORY, "A bitmap display whose pixels are written one at a time"),
+        || Box::new(VideoDisplay::new())
+    );
+    result.add(
+        ComponentMetadata::new("keyboard", "Keyboard", CATEGORY, "Buffers keys typed by the user for the circuit to read"),
+        || Box::new(Keyboard::new())
+    );
+    result.add(
+        ComponentMetadata::new("terminal", "Terminal", CATEGORY, "Prints the characters written to it"),
+        || Box::new(Terminal::new())
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Circuit, Wire};
+    use crate::simulation::Simulator;
+
+    #[test]
+    fn input_pin_drives_led() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(lib.create("input_pin").unwrap(), 0, 0);
+        let led = circuit.add_component(lib.create("led").unwrap(), 2, 0);
+
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.display_value(led).unwrap().to_u64(), Some(0));
+
+        assert!(sim.interact(input, Interaction::Press));
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.display_value(led).unwrap().to_u64(), Some(1));
+        assert!(!sim.interact(led, Interaction::Press));
+    }
+
+    #[test]
+    fn oscilloscope_triggers() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(lib.create("input_pin").unwrap(), -2, 0);
+        let mut scope = lib.create("oscilloscope").unwrap();
+        scope.set_property("num_probes", 1.into()).unwrap();
+        scope.set_property("depth", 8.into()).unwrap();
+        scope.set_property("trigger", "RisingEdge".into()).unwrap();
+        let scope = circuit.add_component(scope, 0, 0);
+
+        let mut sim = Simulator::new(circuit);
+        for _ in 0..10 {
+            sim.tick().unwrap();
+        }
+        let capture = sim.capture(scope).unwrap();
+        assert_eq!((capture.len(), capture.trigger_index()), (8, None));
+
+        sim.interact(input, Interaction::Press);
+        for _ in 0..20 {
+            sim.tick().unwrap();
+        }
+        // Two samples before the trigger are kept, then sampling stops
+        let capture = sim.capture(scope).unwrap();
+        assert_eq!(capture.trigger_index(), Some(2));
+        let bits: Vec<_> = capture.probe(0).iter().map(|v| v.to_u64().unwrap()).collect();
+        assert_eq!(bits, vec![0, 0, 1, 1, 1, 1, 1, 1]);
+
+        assert!(sim.interact(scope, Interaction::Press));
+        assert!(sim.capture(scope).unwrap().is_empty());
+    }
+
+    #[test]
+    fn oscilloscope_adding_probes() {
+        let mut scope = library().create("oscilloscope").unwrap();
+        scope.set_property("num_probes", 1.into()).unwrap();
+        for _ in 0..100 {
+            scope.tick();
+        }
+        // The new probe has no samples until the next tick
+        scope.set_property("num_probes", 2.into()).unwrap();
+        scope.draw(&mut DrawContext::new());
+        scope.tick();
+        assert_eq!(scope.capture().unwrap().probe(1).len(), 1);
+        scope.draw(&mut DrawContext::new());
+    }
+
+    #[test]
+    fn buzzer_plays_tones() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let mut frequency = lib.create("input_pin").unwrap();
+        frequency.set_property("num_bits", 12.into()).unwrap();
+        let frequency = circuit.add_component(frequency, -2, 0);
+        let enable = circuit.add_component(lib.create("input_pin").unwrap(), -1, 1);
+        let buzzer = circuit.add_component(lib.create("buzzer").unwrap(), 0, 0);
+
+        let mut sim = Simulator::new(circuit);
+        sim.set_tick_frequency(1000.0);
+        sim.interact(frequency, Interaction::Set(Value::from_u64(500, 12)));
+        sim.tick().unwrap();
+        sim.interact(enable, Interaction::Press);
+        sim.run_until_stable().unwrap();
+        for _ in 0..2 {
+            sim.tick().unwrap();
+        }
+        assert_eq!(sim.circuit().get_component(buzzer).unwrap().audio().unwrap().changes(), &[(1, 500.0)]);
+
+        // Half a cycle of silence, then a whole cycle of the tone
+        let samples = sim.render_audio(0, 3, 4000);
+        assert_eq!(samples.len(), 12);
+        assert!(samples[..4].iter().all(|&s| s == 0));
+        assert!(samples[4..8].iter().all(|&s| s > 0));
+        assert!(samples[8..].iter().all(|&s| s < 0));
+    }
+
+    #[test]
+    fn led_matrix() {
+        let mut matrix = library().create("led_matrix").unwrap();
+        matrix.set_property("rows", 2.into()).unwrap();
+        matrix.set_property("columns", 3.into()).unwrap();
+        matrix.clock_edge(&vec![Value::from_u64(0b10, 2), Value::from_u64(0b101, 3)]);
+        let framebuffer = matrix.framebuffer().unwrap();
+        let lit: Vec<_> = (0..2).map(|y| (0..3).map(|x| {
+            framebuffer.get(x, y) == Some(Color::rgb(0xff, 0x22, 0x22))
+        }).collect::<Vec<_>>()).collect();
+        assert_eq!(lit, vec![vec![false, false, false], vec![true, false, true]]);
+    }
+
+    #[test]
+    fn video_display() {
+        let mut video = library().create("video_display").unwrap();
+        video.set_property("width", 4.into()).unwrap();
+        video.set_property("height", 2.into()).unwrap();
+        assert_eq!(video.framebuffer().unwrap().pixels().len(), 8);
+        let bits: Vec<_> = video.get_shape().pins.iter().map(|p| p.bits).collect();
+        assert_eq!(bits, vec![2, 1, 12, 1, 1, 1]);
+
+        let write = |video: &mut crate::component::ComponentInfo, x, y, data| {
+            for &clock in &[0, 1] {
+                video.clock_edge(&vec![
+                    Value::from_u64(x, 2), Value::from_u64(y, 1), Value::from_u64(data, 12),
+                    Value::from_u64(1, 1), Value::from_u64(clock, 1), Value::from_u64(0, 1),
+                ]);
+            }
+        };
+        write(&mut video, 3, 1, 0xf80);
+        assert_eq!(video.framebuffer().unwrap().get(3, 1), Some(Color::rgb(255, 136, 0)));
+        assert_eq!(video.framebuffer().unwrap().get(0, 0), Some(Color::rgb(0, 0, 0)));
+
+        // The pixels are kept when the simulation is saved and restored
+        let state = video.state().unwrap();
+        video.clock_edge(&vec![Value::floating(2), Value::floating(1), Value::floating(12),
+            Value::floating(1), Value::floating(1), Value::from_u64(1, 1)]);
+        assert_eq!(video.framebuffer().unwrap().get(3, 1), Some(Color::rgb(0, 0, 0)));
+        video.set_state(state).unwrap();
+        assert_eq!(video.framebuffer().unwrap().get(3, 1), Some(Color::rgb(255, 136, 0)));
+    }
+
+    #[test]
+    fn analog_inputs() {
+        let lib = library();
+        let mut slider = lib.create("slider").unwrap();
+        assert!(slider.interact(Interaction::Set(Value::from_u64(200, 8))));
+        assert!(!slider.interact(Interaction::Set(Value::from_u64(1, 4))));
+        assert_eq!(slider.evaluate(&vec![])[0].to_u64(), Some(200));
+        // Narrowing the slider keeps the handle at the end of the track
+        slider.set_property("num_bits", 4.into()).unwrap();
+        assert_eq!(slider.evaluate(&vec![])[0].to_u64(), Some(15));
+
+        let mut dip = lib.create("dip_switch").unwrap();
+        dip.set_property("num_switches", 4.into()).unwrap();
+        assert!(dip.interact(Interaction::Toggle(0)));
+        assert!(dip.interact(Interaction::Toggle(2)));
+        assert!(!dip.interact(Interaction::Toggle(4)));
+        assert_eq!(dip.evaluate(&vec![])[0].to_u64(), Some(0b0101));
+        assert!(dip.interact(Interaction::Toggle(0)));
+        assert_eq!(dip.display_value().unwrap().to_u64(), Some(0b0100));
+
+        let mut joystick = lib.create("joystick").unwrap();
+        let axes = |j: &crate::component::ComponentInfo| {
+            let outputs = j.evaluate(&vec![]);
+            (outputs[0].to_u64().unwrap(), outputs[1].to_u64().unwrap())
+        };
+        assert_eq!(axes(&joystick), (8, 8));
+        assert!(joystick.interact(Interaction::Move { x: -3, y: 100 }));
+        assert_eq!(axes(&joystick), (5, 15));
+        assert!(joystick.interact(Interaction::Release));
+        assert_eq!(axes(&joystick), (8, 8));
+    }
+
+    #[test]
+    fn keyboard_feeds_terminal() {
+        let lib = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let keyboard = circuit.add_component(lib.create("keyboard").unwrap(), 0, 0);
+        let mut terminal = lib.create("terminal").unwrap();
+        terminal.set_property("rows", 2.into()).unwrap();
+        terminal.set_property("columns", 4.into()).unwrap();
+        let terminal = circuit.add_component(terminal, 8, 0);
+        let read = circuit.add_component(lib.create("input_pin").unwrap(), -2, 0);
+        circuit.add_component(lib.create("clock").unwrap(), -4, 2);
+        // Each character available from the keyboard is written to the
+        // terminal on the same clock edge that reads it
+        for &(start, end) in &[
+            ((6, 1), (8, 1)),
+            ((6, 3), (7, 3)), ((7, 3), (7, 2)), ((7, 2), (8, 2)),
+            ((-2, 3), (0, 3)), ((0, 3), (0, 6)), ((0, 6), (8, 6)), ((8, 6), (8, 3)),
+        ] {
+            circuit.add_wire(Wire::new(start, end));
+        }
+
+        let mut sim = Simulator::new(circuit);
+        sim.interact(read, Interaction::Press);
+        for c in "Hi\nthere".chars() {
+            assert!(sim.interact(keyboard, Interaction::Key(c)));
+        }
+        assert!(!sim.interact(keyboard, Interaction::Key('é')));
+        for _ in 0..20 {
+            sim.tick().unwrap();
+        }
+        assert_eq!(sim.terminal_text(terminal).unwrap(), "ther\ne");
+
+        let mut keyboard = lib.create("keyboard").unwrap();
+        keyboard.set_property("buffer_size", 1.into()).unwrap();
+        assert!(keyboard.interact(Interaction::Key('a')));
+        assert!(!keyboard.interact(Interaction::Key('b')));
+        let outputs = keyboard.evaluate(&vec![]);
+        assert_eq!((outputs[3].to_u64(), outputs[4].to_u64()), (Some('a' as u64), Some(1)));
+    }
+
+    #[test]
+    fn button_is_momentary() {
+        let mut button = Button { pressed: false };
+        assert!(button.interact(Interaction::Press));
+        assert_eq!(button.evaluate(&vec![])[0].to_u64(), Some(1));
+        assert!(button.interact(Interaction::Release));
+        assert_eq!(button.evaluate(&vec![])[0].to_u64(), Some(0));
+    }
+}
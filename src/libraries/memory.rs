@@ -0,0 +1,1207 @@
+use std::error::Error;
+
+use serde_json;
+use maplit::btreemap;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, FieldSchema, FieldType, ReflectType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::bits::max_word;
+use crate::export::verilog::{HdlEmit, ModuleWriter, ExportError, literal};
+use crate::render::DrawContext;
+use super::{data_bits_field, address_bits};
+
+pub use crate::bits::{MemoryContents, MAX_ADDRESS_BITS};
+
+pub const CATEGORY: &'static str = "Memory";
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Trigger {
+    RisingEdge,
+    FallingEdge,
+}
+
+impl ReflectType for Trigger {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[Trigger::RisingEdge, Trigger::FallingEdge])
+    }
+}
+
+pub(crate) fn trigger_field() -> FieldSchema {
+    FieldSchema::new("Trigger", Trigger::field_type())
+}
+
+// Emits a register which is cleared asynchronously by `reset`, and
+// otherwise updated on each edge of `clock` to the expression returned by
+// `next` given the register's name.
+fn emit_register<F>(module: &mut ModuleWriter, trigger: Trigger, clock: &str, reset: &str, bits: u32, next: F) -> String
+where
+    F: FnOnce(&str) -> String
+{
+    let edge = match trigger {
+        Trigger::RisingEdge => "posedge",
+        Trigger::FallingEdge => "negedge",
+    };
+    let state = module.reg("state", bits);
+    module.statement(format!(
+        "always @({} {} or posedge {}) if ({}) {} <= {}; else {} <= {};",
+        edge, clock, reset, reset, state, literal(0, bits), state, next(&state)
+    ));
+    state
+}
+
+/// Remembers the previous value of a clock input in order to detect edges.
+#[derive(Debug, Clone)]
+pub(crate) struct Clock {
+    pub(crate) trigger: Trigger,
+    pub(crate) last: Bit,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Self {
+            trigger: Trigger::RisingEdge,
+            last: Bit::HighZ,
+        }
+    }
+    pub(crate) fn fired(&mut self, current: Bit) -> bool {
+        let result = match self.trigger {
+            Trigger::RisingEdge => self.last == Bit::Zero && current == Bit::One,
+            Trigger::FallingEdge => self.last == Bit::One && current == Bit::Zero,
+        };
+        self.last = current;
+        result
+    }
+}
+
+pub(crate) fn input_bit(inputs: &[Value], index: usize) -> Bit {
+    inputs.get(index).map(|v| v.bit(0)).unwrap_or(Bit::HighZ)
+}
+
+pub(crate) fn pin(x: i32, y: i32, name: &str, bits: u32, direction: PinDirection) -> Pin {
+    Pin { x, y, name: name.into(), bits, direction }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FlipFlopType {
+    D,
+    JK,
+    SR,
+    T,
+}
+
+impl FlipFlopType {
+    fn data_inputs(self) -> &'static [&'static str] {
+        match self {
+            FlipFlopType::D => &["d"],
+            FlipFlopType::JK => &["j", "k"],
+            FlipFlopType::SR => &["s", "r"],
+            FlipFlopType::T => &["t"],
+        }
+    }
+    fn image_name(self) -> &'static str {
+        match self {
+            FlipFlopType::D => "d_flip_flop",
+            FlipFlopType::JK => "jk_flip_flop",
+            FlipFlopType::SR => "sr_flip_flop",
+            FlipFlopType::T => "t_flip_flop",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FlipFlop {
+    type_: FlipFlopType,
+    clock: Clock,
+    state: bool,
+}
+
+impl FlipFlop {
+    fn new(type_: FlipFlopType) -> Self {
+        Self {
+            type_,
+            clock: Clock::new(),
+            state: false,
+        }
+    }
+    fn next_state(&self, inputs: &[Value]) -> Option<bool> {
+        let a = input_bit(inputs, 0).to_bool();
+        let b = input_bit(inputs, 1).to_bool();
+        Some(match self.type_ {
+            FlipFlopType::D => a?,
+            FlipFlopType::T => self.state ^ a?,
+            FlipFlopType::JK => match (a?, b?) {
+                (false, false) => self.state,
+                (true, false) => true,
+                (false, true) => false,
+                (true, true) => !self.state,
+            },
+            // Setting and resetting simultaneously is invalid, and leaves
+            // the state unchanged.
+            FlipFlopType::SR => match (a?, b?) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => self.state,
+            },
+        })
+    }
+}
+
+impl Properties for FlipFlop {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for FlipFlop {
+    fn get_shape(&self) -> Shape {
+        let mut pins: Vec<_> = self.type_.data_inputs().iter().enumerate()
+            .map(|(i, name)| pin(0, i as i32 + 1, name, 1, PinDirection::Input))
+            .collect();
+        pins.push(pin(0, 3, "clock", 1, PinDirection::Input));
+        pins.push(pin(1, 4, "reset", 1, PinDirection::Input));
+        pins.push(pin(3, 1, "q", 1, PinDirection::Output));
+        pins.push(pin(3, 3, "q_bar", 1, PinDirection::Output));
+        Shape {
+            width: 3,
+            height: 4,
+            pins,
+            image_name: self.type_.image_name().into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, self.state)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, state) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.state = state;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let n = self.type_.data_inputs().len();
+        let fired = self.clock.fired(input_bit(inputs, n));
+        if input_bit(inputs, n + 1) == Bit::One {
+            self.state = false;
+        } else if fired {
+            if let Some(state) = self.next_state(inputs) {
+                self.state = state;
+            }
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        let mut result = vec![Value::floating(1); self.type_.data_inputs().len() + 2];
+        result.push(Value::new(vec![Bit::from_bool(self.state)]));
+        result.push(Value::new(vec![Bit::from_bool(!self.state)]));
+        result
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for FlipFlop {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let n = self.type_.data_inputs().len();
+        let next = |state: &str| match self.type_ {
+            FlipFlopType::D => pins[0].clone(),
+            FlipFlopType::T => format!("{} ^ {}", state, pins[0]),
+            FlipFlopType::JK => format!("({0} & ~{2}) | (~{1} & {2})", pins[0], pins[1], state),
+            FlipFlopType::SR => format!("({0} & ~{1}) | ({2} & ~({0} ^ {1}))", pins[0], pins[1], state),
+        };
+        let state = emit_register(module, self.clock.trigger, &pins[n], &pins[n + 1], 1, next);
+        module.assign(&pins[n + 2], &state);
+        module.assign(&pins[n + 3], &format!("~{}", state));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Register {
+    num_bits: u32,
+    clock: Clock,
+    value: Value,
+}
+
+impl Properties for Register {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => data_bits_field(),
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.value = Value::from_u64(0, self.num_bits);
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Register {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "d", self.num_bits, PinDirection::Input),
+                pin(0, 2, "enable", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(1, 4, "reset", 1, PinDirection::Input),
+                pin(3, 2, "q", self.num_bits, PinDirection::Output),
+            ],
+            image_name: "register".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, &self.value)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, value) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.value = value;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 2));
+        if input_bit(inputs, 3) == Bit::One {
+            self.value = Value::from_u64(0, self.num_bits);
+        } else if fired && input_bit(inputs, 1) != Bit::Zero {
+            if let Some(d) = inputs.get(0) {
+                self.value = d.clone();
+            }
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![
+            Value::floating(self.num_bits),
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            self.value.clone(),
+        ]
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        ctx.rect((0.0, 0.0), (3.0, 4.0), Some("#fff"), Some("#222"));
+        // The clock input
+        ctx.polygon(vec![(0.0, 2.6), (0.4, 3.0), (0.0, 3.4)], None, Some("#222"));
+        let text = match self.value.to_u64() {
+            Some(v) => format!("{:X}", v),
+            None => self.value.to_string(),
+        };
+        ctx.text((1.5, 1.5), 0.8, &text, "#222");
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Register {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let next = |state: &str| format!("{} ? {} : {}", pins[1], pins[0], state);
+        let state = emit_register(module, self.clock.trigger, &pins[2], &pins[3], self.num_bits, next);
+        module.assign(&pins[4], &state);
+        Ok(())
+    }
+}
+
+/// Counts clock edges while enabled, upwards or downwards while `down` is
+/// one, wrapping around at either end. `carry` is one at the end of the
+/// range being counted towards. While `load` is one, each clock edge loads
+/// `data` instead, and `reset` clears the count immediately.
+#[derive(Debug, Clone)]
+struct Counter {
+    num_bits: u32,
+    clock: Clock,
+    value: u64,
+}
+
+impl Counter {
+    fn max_value(&self) -> u64 {
+        if self.num_bits >= 64 { u64::max_value() } else { (1 << self.num_bits) - 1 }
+    }
+    fn end(&self, down: bool) -> u64 {
+        if down { 0 } else { self.max_value() }
+    }
+}
+
+impl Properties for Counter {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.value &= self.max_value();
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Counter {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "enable", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(1, 4, "reset", 1, PinDirection::Input),
+                pin(3, 1, "q", self.num_bits, PinDirection::Output),
+                pin(3, 3, "carry", 1, PinDirection::Output),
+                pin(0, 2, "load", 1, PinDirection::Input),
+                pin(2, 4, "data", self.num_bits, PinDirection::Input),
+                pin(1, 0, "down", 1, PinDirection::Input),
+            ],
+            image_name: "counter".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, self.value)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, value): (_, u64) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.value = value & self.max_value();
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 1));
+        let down = input_bit(inputs, 7) == Bit::One;
+        if input_bit(inputs, 2) == Bit::One {
+            self.value = 0;
+        } else if fired && input_bit(inputs, 5) == Bit::One {
+            if let Some(data) = inputs.get(6).and_then(|v| v.to_u64()) {
+                self.value = data & self.max_value();
+            }
+        } else if fired && input_bit(inputs, 0) != Bit::Zero {
+            self.value = match (self.value == self.end(down), down) {
+                (true, _) => self.end(!down),
+                (false, true) => self.value - 1,
+                (false, false) => self.value + 1,
+            };
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let down = input_bit(inputs, 7) == Bit::One;
+        vec![
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::from_u64(self.value, self.num_bits),
+            Value::from_u64((self.value == self.end(down)) as u64, 1),
+            Value::floating(1),
+            Value::floating(self.num_bits),
+            Value::floating(1),
+        ]
+    }
+}
+
+/// Outputs a new pseudo-random value on each clock edge while enabled. The
+/// values come from a 64-bit xorshift register, a kind of linear feedback
+/// shift register with a period of 2^64 - 1, so the sequence is
+/// the same each time the generator is reset to the same seed.
+#[derive(Debug, Clone)]
+struct Random {
+    num_bits: u32,
+    seed: u64,
+    clock: Clock,
+    state: u64,
+}
+
+impl Random {
+    fn new() -> Self {
+        let mut result = Self { num_bits: 8, seed: 1, clock: Clock::new(), state: 0 };
+        result.reset();
+        result
+    }
+    // The register would stay at zero forever, so a zero seed is replaced
+    fn reset(&mut self) {
+        self.state = if self.seed == 0 { 1 } else { self.seed };
+    }
+    // The top bits are the most thoroughly mixed
+    fn value(&self) -> u64 {
+        self.state >> (64 - self.num_bits)
+    }
+    fn advance(&mut self) {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+    }
+}
+
+impl Properties for Random {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+            "seed".into() => FieldSchema::new("Seed", u64::field_type())
+                .with_description("The generator restarts from this value when reset. Zero is treated as one."),
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "seed" => {
+                self.seed = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.reset();
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "seed" => serde_json::to_value(self.seed).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Random {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "enable", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(1, 4, "reset", 1, PinDirection::Input),
+                pin(3, 2, "q", self.num_bits, PinDirection::Output),
+            ],
+            image_name: "random".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, self.state)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, state) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.state = state;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 1));
+        if input_bit(inputs, 2) == Bit::One {
+            self.reset();
+        } else if fired && input_bit(inputs, 0) != Bit::Zero {
+            self.advance();
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::from_u64(self.value(), self.num_bits),
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum ShiftDirection {
+    Left,
+    Right,
+}
+
+impl ReflectType for ShiftDirection {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[ShiftDirection::Left, ShiftDirection::Right])
+    }
+}
+
+/// Shifts its contents by one bit on each clock edge while enabled, taking
+/// the new bit from `in`. The bit about to be shifted out is on `out`, and
+/// the whole contents on `q`. While `load` is one, each clock edge loads
+/// `data` instead.
+#[derive(Debug, Clone)]
+struct ShiftRegister {
+    num_bits: u32,
+    direction: ShiftDirection,
+    clock: Clock,
+    value: u64,
+}
+
+impl ShiftRegister {
+    fn new() -> Self {
+        Self { num_bits: 8, direction: ShiftDirection::Left, clock: Clock::new(), value: 0 }
+    }
+    fn serial_out(&self) -> u64 {
+        match self.direction {
+            ShiftDirection::Left => self.value >> (self.num_bits - 1),
+            ShiftDirection::Right => self.value & 1,
+        }
+    }
+    fn shift(&mut self, bit: u64) {
+        self.value = match self.direction {
+            ShiftDirection::Left => (self.value << 1 | bit) & max_word(self.num_bits),
+            ShiftDirection::Right => self.value >> 1 | bit << (self.num_bits - 1),
+        };
+    }
+}
+
+impl Properties for ShiftRegister {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+            "direction".into() => FieldSchema::new("Direction", ShiftDirection::field_type())
+                .with_description("Left shifts new bits in at the least significant end."),
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.value &= max_word(self.num_bits);
+                Ok(())
+            },
+            "direction" => {
+                self.direction = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "direction" => serde_json::to_value(self.direction).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for ShiftRegister {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 4,
+            pins: vec![
+                pin(0, 1, "in", 1, PinDirection::Input),
+                pin(0, 2, "enable", 1, PinDirection::Input),
+                pin(0, 3, "clock", 1, PinDirection::Input),
+                pin(1, 4, "reset", 1, PinDirection::Input),
+                pin(2, 4, "load", 1, PinDirection::Input),
+                pin(1, 0, "data", self.num_bits, PinDirection::Input),
+                pin(3, 1, "q", self.num_bits, PinDirection::Output),
+                pin(3, 3, "out", 1, PinDirection::Output),
+            ],
+            image_name: "shift_register".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, self.value)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, value): (_, u64) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.value = value & max_word(self.num_bits);
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 2));
+        if input_bit(inputs, 3) == Bit::One {
+            self.value = 0;
+        } else if fired && input_bit(inputs, 4) == Bit::One {
+            if let Some(data) = inputs.get(5).and_then(|v| v.to_u64()) {
+                self.value = data & max_word(self.num_bits);
+            }
+        } else if fired && input_bit(inputs, 1) != Bit::Zero {
+            self.shift((input_bit(inputs, 0) == Bit::One) as u64);
+        }
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::floating(self.num_bits),
+            Value::from_u64(self.value, self.num_bits),
+            Value::from_u64(self.serial_out(), 1),
+        ]
+    }
+}
+
+/// The largest register file, which keeps its address pins to eight bits.
+pub const MAX_REGISTERS: u32 = 256;
+
+/// A bank of registers with two read ports and one write port. The
+/// registers addressed by `read_a` and `read_b` are output continuously,
+/// and on each clock edge while `write` is one, `data` is stored in the
+/// register addressed by `write_addr`.
+#[derive(Debug, Clone)]
+struct RegisterFile {
+    num_registers: u32,
+    num_bits: u32,
+    clock: Clock,
+    registers: Vec<u64>,
+}
+
+impl RegisterFile {
+    fn new() -> Self {
+        Self { num_registers: 8, num_bits: 8, clock: Clock::new(), registers: vec![0; 8] }
+    }
+    fn fit_registers(&mut self) {
+        let mask = max_word(self.num_bits);
+        self.registers.resize(self.num_registers as usize, 0);
+        for register in &mut self.registers {
+            *register &= mask;
+        }
+    }
+    fn read(&self, address: Option<&Value>) -> Value {
+        match address.and_then(|v| v.to_u64()).and_then(|a| self.registers.get(a as usize)) {
+            Some(&word) => Value::from_u64(word, self.num_bits),
+            None => Value::error(self.num_bits),
+        }
+    }
+}
+
+impl Properties for RegisterFile {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_registers".into() => FieldSchema::new("Registers", FieldType::integer(2, MAX_REGISTERS as i64)),
+            "num_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+            "trigger".into() => trigger_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_registers" => {
+                self.num_registers = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.fit_registers();
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.fit_registers();
+                Ok(())
+            },
+            "trigger" => {
+                self.clock.trigger = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_registers" => serde_json::to_value(self.num_registers).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "trigger" => serde_json::to_value(self.clock.trigger).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for RegisterFile {
+    fn get_shape(&self) -> Shape {
+        let address = address_bits(self.num_registers);
+        Shape {
+            width: 5,
+            height: 7,
+            pins: vec![
+                pin(0, 1, "read_a", address, PinDirection::Input),
+                pin(0, 2, "read_b", address, PinDirection::Input),
+                pin(0, 3, "write_addr", address, PinDirection::Input),
+                pin(0, 4, "data", self.num_bits, PinDirection::Input),
+                pin(0, 5, "write", 1, PinDirection::Input),
+                pin(0, 6, "clock", 1, PinDirection::Input),
+                pin(2, 7, "reset", 1, PinDirection::Input),
+                pin(5, 1, "out_a", self.num_bits, PinDirection::Output),
+                pin(5, 2, "out_b", self.num_bits, PinDirection::Output),
+            ],
+            image_name: "register_file".into(),
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.clock.last, &self.registers)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (last, registers) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.registers = registers;
+        self.fit_registers();
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let fired = self.clock.fired(input_bit(inputs, 5));
+        if input_bit(inputs, 6) == Bit::One {
+            self.registers = vec![0; self.num_registers as usize];
+        } else if fired && input_bit(inputs, 4) == Bit::One {
+            let address = inputs.get(2).and_then(|v| v.to_u64());
+            let data = inputs.get(3).and_then(|v| v.to_u64());
+            if let (Some(address), Some(data)) = (address, data) {
+                let mask = max_word(self.num_bits);
+                if let Some(register) = self.registers.get_mut(address as usize) {
+                    *register = data & mask;
+                }
+            }
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let mut result: Vec<_> = self.get_shape().pins.iter().map(|p| Value::floating(p.bits)).collect();
+        result[7] = self.read(inputs.get(0));
+        result[8] = self.read(inputs.get(1));
+        result
+    }
+}
+
+/// RAM and ROM. Contents are stored sparsely: addresses beyond the end of
+/// `contents` read as zero.
+#[derive(Debug, Clone)]
+struct Memory {
+    writable: bool,
+    address_bits: u32,
+    data_bits: u32,
+    contents: MemoryContents,
+    clock: Clock,
+}
+
+impl Memory {
+    fn new(writable: bool) -> Self {
+        Self {
+            writable,
+            address_bits: 8,
+            data_bits: 8,
+            contents: MemoryContents::new(),
+            clock: Clock::new(),
+        }
+    }
+    fn fit_contents(&mut self) {
+        self.contents.fit(self.address_bits, self.data_bits);
+    }
+    // The index of the data output pin
+    fn output_index(&self) -> usize {
+        if self.writable { 4 } else { 1 }
+    }
+}
+
+impl Properties for Memory {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "address_bits".into() => FieldSchema::new("Address bits", FieldType::integer(1, MAX_ADDRESS_BITS as i64)),
+            "data_bits".into() => FieldSchema::new("Data bits", FieldType::integer(1, 64)),
+            "contents".into() => FieldSchema::new("Contents", FieldType::HexContents {
+                address_bits: self.address_bits,
+                data_bits: self.data_bits,
+            }).depends_on("address_bits").depends_on("data_bits"),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "address_bits" => {
+                self.address_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.fit_contents();
+                Ok(())
+            },
+            "data_bits" => {
+                self.data_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.fit_contents();
+                Ok(())
+            },
+            "contents" => {
+                let contents = MemoryContents::from_value(&value)
+                    .map_err(|e| PropertyError::invalid(name, e))?;
+                contents.check(self.address_bits, self.data_bits)
+                    .map_err(|e| PropertyError::invalid(name, e))?;
+                self.contents = contents;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "address_bits" => serde_json::to_value(self.address_bits).ok(),
+            "data_bits" => serde_json::to_value(self.data_bits).ok(),
+            "contents" => Some(self.contents.clone().into()),
+            _ => None
+        }
+    }
+}
+
+impl Component for Memory {
+    fn get_shape(&self) -> Shape {
+        let mut pins = vec![pin(0, 1, "addr", self.address_bits, PinDirection::Input)];
+        if self.writable {
+            pins.push(pin(0, 2, "data_in", self.data_bits, PinDirection::Input));
+            pins.push(pin(0, 3, "write", 1, PinDirection::Input));
+            pins.push(pin(0, 4, "clock", 1, PinDirection::Input));
+        }
+        pins.push(pin(5, 2, "data", self.data_bits, PinDirection::Output));
+        Shape {
+            width: 5,
+            height: 5,
+            pins,
+            image_name: if self.writable { "ram" } else { "rom" }.into(),
+        }
+    }
+    // Only a RAM's contents change while simulating
+    fn state(&self) -> Option<serde_json::Value> {
+        if !self.writable {
+            return None;
+        }
+        serde_json::to_value((self.clock.last, &self.contents)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        if !self.writable {
+            return Ok(());
+        }
+        let (last, contents) = serde_json::from_value(state)?;
+        self.clock.last = last;
+        self.contents = contents;
+        Ok(())
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        if !self.writable {
+            return;
+        }
+        let fired = self.clock.fired(input_bit(inputs, 3));
+        if fired && input_bit(inputs, 2) == Bit::One {
+            let address = inputs.get(0).and_then(|v| v.to_u64());
+            let data = inputs.get(1).and_then(|v| v.to_u64());
+            if let (Some(address), Some(data)) = (address, data) {
+                self.contents.write(address as usize, data);
+            }
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let mut result: Vec<_> = self.get_shape().pins.iter().map(|p| Value::floating(p.bits)).collect();
+        let output = self.output_index();
+        result[output] = match inputs.get(0).and_then(|v| v.to_u64()) {
+            Some(address) => Value::from_u64(self.contents.read(address as usize), self.data_bits),
+            None => Value::error(self.data_bits),
+        };
+        result
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    let flip_flops = [
+        ("d_flip_flop", "D Flip-Flop", "Stores its input on each clock edge", FlipFlopType::D),
+        ("jk_flip_flop", "J-K Flip-Flop", "Sets, resets or toggles on each clock edge", FlipFlopType::JK),
+        ("sr_flip_flop", "S-R Flip-Flop", "Sets or resets on each clock edge", FlipFlopType::SR),
+        ("t_flip_flop", "T Flip-Flop", "Toggles on each clock edge when its input is set", FlipFlopType::T),
+    ];
+    for &(id, name, description, type_) in &flip_flops {
+        result.add(
+            ComponentMetadata::new(id, name, CATEGORY, description),
+            move || Box::new(FlipFlop::new(type_))
+        );
+    }
+    result.add(
+        ComponentMetadata::new("register", "Register", CATEGORY, "Stores a multi-bit value on each clock edge"),
+        || Box::new(Register { num_bits: 8, clock: Clock::new(), value: Value::from_u64(0, 8) })
+    );
+    result.add(
+        ComponentMetadata::new("counter", "Counter", CATEGORY, "Counts clock edges up or down, with load and clear inputs"),
+        || Box::new(Counter { num_bits: 8, clock: Clock::new(), value: 0 })
+    );
+    result.add(
+        ComponentMetadata::new("random", "Random Generator", CATEGORY, "Outputs a reproducible sequence of pseudo-random values"),
+        || Box::new(Random::new())
+    );
+    result.add(
+        ComponentMetadata::new("shift_register", "Shift Register", CATEGORY, "Shifts in one bit on each clock edge, with parallel load and output"),
+        || Box::new(ShiftRegister::new())
+    );
+    result.add(
+        ComponentMetadata::new("register_file", "Register File", CATEGORY, "A bank of registers with two read ports and one write port"),
+        || Box::new(RegisterFile::new())
+    );
+    result.add(
+        ComponentMetadata::new("ram", "RAM", CATEGORY, "Random access memory"),
+        || Box::new(Memory::new(true))
+    );
+    result.add(
+        ComponentMetadata::new("rom", "ROM", CATEGORY, "Read-only memory with editable contents"),
+        || Box::new(Memory::new(false))
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(values: &[u64]) -> Vec<Value> {
+        values.iter().map(|&v| Value::from_u64(v, 1)).collect()
+    }
+
+    #[test]
+    fn flip_flops_change_on_edges() {
+        let mut ff = FlipFlop::new(FlipFlopType::D);
+        ff.clock_edge(&bits(&[1, 0, 0]));
+        ff.clock_edge(&bits(&[1, 0, 0]));
+        assert!(!ff.state);
+        ff.clock_edge(&bits(&[1, 1, 0]));
+        assert!(ff.state);
+        ff.clock_edge(&bits(&[0, 1, 0]));
+        assert!(ff.state);
+        ff.clock_edge(&bits(&[0, 1, 1]));
+        assert!(!ff.state);
+
+        let mut ff = FlipFlop::new(FlipFlopType::JK);
+        for _ in 0..3 {
+            ff.clock_edge(&bits(&[1, 1, 0, 0]));
+            ff.clock_edge(&bits(&[1, 1, 1, 0]));
+        }
+        assert!(ff.state);
+    }
+
+    #[test]
+    fn counter_wraps() {
+        let mut counter = Counter { num_bits: 2, clock: Clock::new(), value: 0 };
+        for _ in 0..5 {
+            counter.clock_edge(&bits(&[1, 0, 0]));
+            counter.clock_edge(&bits(&[1, 1, 0]));
+        }
+        assert_eq!(counter.value, 1);
+    }
+
+    #[test]
+    fn restored_state_fits() {
+        let mut counter = Counter { num_bits: 2, clock: Clock::new(), value: 0 };
+        let mut state = counter.state().unwrap();
+        state[1] = u64::max_value().into();
+        counter.set_state(state.clone()).unwrap();
+        assert_eq!(counter.value, 3);
+        counter.clock_edge(&bits(&[1, 0, 0]));
+        counter.clock_edge(&bits(&[1, 1, 0]));
+        assert_eq!(counter.value, 0);
+
+        let mut register = ShiftRegister::new();
+        register.num_bits = 4;
+        register.set_state(state).unwrap();
+        assert_eq!(register.value, 0b1111);
+    }
+
+    #[test]
+    fn counter_loads_and_counts_down() {
+        let mut counter = Counter { num_bits: 4, clock: Clock::new(), value: 0 };
+        let inputs = |clock, load, data, down| vec![
+            Value::from_u64(1, 1),
+            Value::from_u64(clock, 1),
+            Value::from_u64(0, 1),
+            Value::floating(1),
+            Value::floating(1),
+            Value::from_u64(load, 1),
+            Value::from_u64(data, 4),
+            Value::from_u64(down, 1),
+        ];
+        counter.clock_edge(&inputs(0, 1, 2, 1));
+        counter.clock_edge(&inputs(1, 1, 2, 1));
+        assert_eq!(counter.value, 2);
+        let mut values = Vec::new();
+        for _ in 0..3 {
+            counter.clock_edge(&inputs(0, 0, 0, 1));
+            counter.clock_edge(&inputs(1, 0, 0, 1));
+            values.push(counter.value);
+        }
+        assert_eq!(values, vec![1, 0, 15]);
+        // Counting down carries at zero
+        assert_eq!(counter.evaluate(&inputs(1, 0, 0, 1))[4].to_u64(), Some(0));
+        counter.value = 0;
+        assert_eq!(counter.evaluate(&inputs(1, 0, 0, 1))[4].to_u64(), Some(1));
+    }
+
+    #[test]
+    fn shift_register() {
+        let mut register = ShiftRegister::new();
+        register.num_bits = 4;
+        let inputs = |bit, clock, load, data| vec![
+            Value::from_u64(bit, 1),
+            Value::from_u64(1, 1),
+            Value::from_u64(clock, 1),
+            Value::from_u64(0, 1),
+            Value::from_u64(load, 1),
+            Value::from_u64(data, 4),
+        ];
+        let mut shifted_out = Vec::new();
+        for &bit in &[1, 0, 1, 1, 0] {
+            register.clock_edge(&inputs(bit, 0, 0, 0));
+            register.clock_edge(&inputs(bit, 1, 0, 0));
+            shifted_out.push(register.evaluate(&vec![])[7].to_u64().unwrap());
+        }
+        assert_eq!(register.value, 0b0110);
+        assert_eq!(shifted_out, vec![0, 0, 0, 1, 0]);
+
+        register.set_property("direction", "Right".into()).unwrap();
+        register.clock_edge(&inputs(0, 0, 1, 0b1001));
+        register.clock_edge(&inputs(0, 1, 1, 0b1001));
+        assert_eq!(register.value, 0b1001);
+        assert_eq!(register.evaluate(&vec![])[7].to_u64(), Some(1));
+        register.clock_edge(&inputs(1, 0, 0, 0));
+        register.clock_edge(&inputs(1, 1, 0, 0));
+        assert_eq!(register.value, 0b1100);
+    }
+
+    #[test]
+    fn register_file() {
+        let mut file = library().create("register_file").unwrap();
+        file.set_property("num_registers", 4.into()).unwrap();
+        let pins = file.get_shape().pins;
+        assert_eq!(pins[0].bits, 2);
+        let inputs = |a, b, w, data, write, clock| vec![
+            Value::from_u64(a, 2),
+            Value::from_u64(b, 2),
+            Value::from_u64(w, 2),
+            Value::from_u64(data, 8),
+            Value::from_u64(write, 1),
+            Value::from_u64(clock, 1),
+            Value::from_u64(0, 1),
+        ];
+        for &(address, data) in &[(1, 0x12), (3, 0x34)] {
+            file.clock_edge(&inputs(0, 0, address, data, 1, 0));
+            file.clock_edge(&inputs(0, 0, address, data, 1, 1));
+        }
+        let outputs = file.evaluate(&inputs(1, 3, 0, 0, 0, 1));
+        assert_eq!(outputs[7].to_u64(), Some(0x12));
+        assert_eq!(outputs[8].to_u64(), Some(0x34));
+        assert!(file.evaluate(&vec![Value::floating(2)])[7].to_u64().is_none());
+
+        // Registers survive resizing where they still fit
+        file.set_property("num_registers", 2.into()).unwrap();
+        assert_eq!(file.state().unwrap()[1], serde_json::json!([0, 0x12]));
+    }
+
+    #[test]
+    fn random_is_reproducible() {
+        let mut random = library().create("random").unwrap();
+        random.set_property("seed", 1234.into()).unwrap();
+        let run = |random: &mut crate::component::ComponentInfo| {
+            random.clock_edge(&bits(&[1, 0, 1]));
+            (0..8).map(|_| {
+                random.clock_edge(&bits(&[1, 0, 0]));
+                random.clock_edge(&bits(&[1, 1, 0]));
+                random.evaluate(&vec![])[3].to_u64().unwrap()
+            }).collect::<Vec<_>>()
+        };
+        let first = run(&mut random);
+        assert_eq!(run(&mut random), first);
+        let mut distinct = first.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 4, "{:?}", first);
+        assert!(first.iter().all(|&v| v < 256));
+
+        random.set_property("seed", 0.into()).unwrap();
+        assert_ne!(run(&mut random), vec![0; 8]);
+    }
+
+    #[test]
+    fn ram_reads_and_writes() {
+        let mut ram = Memory::new(true);
+        let inputs = |clock| vec![
+            Value::from_u64(3, 8),
+            Value::from_u64(42, 8),
+            Value::from_u64(1, 1),
+            Value::from_u64(clock, 1),
+        ];
+        ram.clock_edge(&inputs(0));
+        ram.clock_edge(&inputs(1));
+        assert_eq!(ram.evaluate(&inputs(1))[4].to_u64(), Some(42));
+        assert_eq!(ram.contents.to_string(), "0 0 0 2a");
+    }
+
+    #[test]
+    fn contents_format() {
+        let contents = MemoryContents::parse("1 2 5*0 ff").unwrap();
+        assert_eq!(contents.words(), &[1, 2, 0, 0, 0, 0, 0, 0xff]);
+        assert_eq!(contents.to_string(), "1 2 5*0 ff");
+        assert!(contents.check(4, 8).is_ok());
+        assert!(MemoryContents::parse("100").unwrap().check(4, 8).is_err());
+        assert!(MemoryContents::parse("17*0").unwrap().check(4, 8).is_err());
+        assert!(MemoryContents::parse("xyz").is_err());
+
+        let mut rom = library().create("rom").unwrap();
+        rom.set_property("contents", MemoryContents::from_words(vec![7, 7, 7, 7, 1]).into()).unwrap();
+        let saved = MemoryContents::from_value(&rom.get_property("contents").unwrap()).unwrap();
+        assert_eq!(saved.words(), &[7, 7, 7, 7, 1]);
+    }
+}
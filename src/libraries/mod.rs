@@ -1 +1,80 @@
+use crate::component::{FieldSchema, FieldType};
+use crate::library::Library;
+
+pub mod annotations;
+pub mod arithmetic;
+pub mod clocked;
+pub mod fsm;
 pub mod gates;
+pub mod io;
+pub mod memory;
+pub mod plexers;
+#[cfg(feature = "scripting")]
+pub mod scripted;
+pub mod wiring;
+
+/// All of the standard components. The categories are listed in the order
+/// they are most commonly needed when building a circuit.
+pub fn default_library() -> Library {
+    #[allow(unused_mut)]
+    let mut libraries = vec![
+        io::library(),
+        gates::library(),
+        wiring::library(),
+        plexers::library(),
+        arithmetic::library(),
+        memory::library(),
+        fsm::library(),
+        clocked::library(),
+        annotations::library(),
+    ];
+    #[cfg(feature = "scripting")]
+    libraries.push(scripted::library());
+    let mut result = Library::new();
+    for (order, library) in libraries.into_iter().enumerate() {
+        result.extend_checked(library.with_order(order as i32))
+            .expect("Standard libraries should not overlap");
+    }
+    result
+}
+
+pub(crate) fn data_bits_field() -> FieldSchema {
+    FieldSchema::new("Data bits", FieldType::integer(1, 256))
+}
+
+// A column of `count` pins spaced one unit apart, skipping the centre line
+// when there is an even number of them so that a single pin can be placed
+// opposite at `spaced_height(count) / 2`.
+pub(crate) fn spaced_height(count: u32) -> i32 {
+    let n = count as i32;
+    if n % 2 == 0 { n + 2 } else { n + 1 }
+}
+
+// The number of bits needed to address `count` positions
+pub(crate) fn address_bits(count: u32) -> u32 {
+    (32 - (count - 1).leading_zeros()).max(1)
+}
+
+pub(crate) fn spaced_y(index: u32, count: u32) -> i32 {
+    let skip = if count % 2 == 0 && index >= count / 2 { 1 } else { 0 };
+    (index + 1 + skip) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_library_contents() {
+        let library = default_library();
+        assert_eq!(library.list_categories(), vec![
+            io::CATEGORY, gates::CATEGORY, wiring::CATEGORY,
+            plexers::CATEGORY, arithmetic::CATEGORY, memory::CATEGORY, clocked::CATEGORY,
+            annotations::CATEGORY,
+        ]);
+        for metadata in library.list() {
+            let component = library.create(&metadata.id).unwrap();
+            component.get_shape();
+        }
+    }
+}
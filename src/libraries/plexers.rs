@@ -0,0 +1,304 @@
+use serde_json;
+use maplit::btreemap;
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, FieldSchema, FieldType, PinValues};
+use crate::simulation::{Bit, Value};
+use crate::export::verilog::{HdlEmit, ModuleWriter, ExportError, literal};
+use super::{data_bits_field, spaced_height, spaced_y};
+
+pub const CATEGORY: &'static str = "Plexers";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PlexerType {
+    Multiplexer,
+    Demultiplexer,
+    Decoder,
+    PriorityEncoder,
+}
+
+impl PlexerType {
+    fn image_name(self) -> &'static str {
+        match self {
+            PlexerType::Multiplexer => "multiplexer",
+            PlexerType::Demultiplexer => "demultiplexer",
+            PlexerType::Decoder => "decoder",
+            PlexerType::PriorityEncoder => "priority_encoder",
+        }
+    }
+    // Decoders and encoders always operate on individual bits
+    fn has_data_bits(self) -> bool {
+        match self {
+            PlexerType::Multiplexer | PlexerType::Demultiplexer => true,
+            PlexerType::Decoder | PlexerType::PriorityEncoder => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Plexer {
+    type_: PlexerType,
+    select_bits: u32,
+    num_bits: u32,
+}
+
+impl Plexer {
+    fn new(type_: PlexerType) -> Self {
+        Self {
+            type_,
+            select_bits: 1,
+            num_bits: 1,
+        }
+    }
+    // The number of data inputs or outputs
+    fn ways(&self) -> u32 {
+        1 << self.select_bits
+    }
+    fn select(&self, value: Option<&Value>) -> Option<usize> {
+        value.and_then(|v| v.to_u64()).map(|s| s as usize)
+    }
+}
+
+impl Properties for Plexer {
+    fn schema(&self) -> Schema {
+        let mut result = btreemap!{
+            "select_bits".into() => FieldSchema::new("Select bits", FieldType::integer(1, 5)),
+        };
+        if self.type_.has_data_bits() {
+            result.insert("num_bits".into(), data_bits_field());
+        }
+        result
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "select_bits" => {
+                self.select_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "num_bits" if self.type_.has_data_bits() => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "select_bits" => serde_json::to_value(self.select_bits).ok(),
+            "num_bits" if self.type_.has_data_bits() => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Plexer {
+    fn get_shape(&self) -> Shape {
+        let ways = self.ways();
+        let width = 3;
+        let height = spaced_height(ways);
+        let pin = |x, y, name: String, bits, direction| Pin { x, y, name, bits, direction };
+        let column = |x, prefix: &str, bits, direction| (0..ways).map(|i| {
+            pin(x, spaced_y(i, ways), format!("{}{}", prefix, i), bits, direction)
+        }).collect::<Vec<_>>();
+        let select = pin(1, height, "sel".into(), self.select_bits, PinDirection::Input);
+
+        let pins = match self.type_ {
+            PlexerType::Multiplexer => {
+                let mut pins = column(0, "in", self.num_bits, PinDirection::Input);
+                pins.push(select);
+                pins.push(pin(width, height / 2, "out".into(), self.num_bits, PinDirection::Output));
+                pins
+            },
+            PlexerType::Demultiplexer => {
+                let mut pins = vec![
+                    pin(0, height / 2, "in".into(), self.num_bits, PinDirection::Input),
+                    select,
+                ];
+                pins.extend(column(width, "out", self.num_bits, PinDirection::Output));
+                pins
+            },
+            PlexerType::Decoder => {
+                let mut pins = vec![select];
+                pins.extend(column(width, "out", 1, PinDirection::Output));
+                pins
+            },
+            PlexerType::PriorityEncoder => {
+                let mut pins = column(0, "in", 1, PinDirection::Input);
+                pins.push(pin(width, height / 2, "out".into(), self.select_bits, PinDirection::Output));
+                pins.push(pin(1, height, "valid".into(), 1, PinDirection::Output));
+                pins
+            },
+        };
+        Shape {
+            width,
+            height,
+            pins,
+            image_name: self.type_.image_name().into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let ways = self.ways() as usize;
+        match self.type_ {
+            PlexerType::Multiplexer => {
+                let out = match self.select(inputs.get(ways)) {
+                    Some(s) => inputs.get(s).cloned().unwrap_or_else(|| Value::floating(self.num_bits)),
+                    None => Value::error(self.num_bits),
+                };
+                let mut result = vec![Value::floating(self.num_bits); ways];
+                result.push(Value::floating(self.select_bits));
+                result.push(out);
+                result
+            },
+            PlexerType::Demultiplexer => {
+                let select = self.select(inputs.get(1));
+                let mut result = vec![Value::floating(self.num_bits), Value::floating(self.select_bits)];
+                result.extend((0..ways).map(|i| match select {
+                    Some(s) if s == i => inputs.get(0).cloned().unwrap_or_else(|| Value::floating(self.num_bits)),
+                    Some(_) => Value::from_u64(0, self.num_bits),
+                    None => Value::error(self.num_bits),
+                }));
+                result
+            },
+            PlexerType::Decoder => {
+                let select = self.select(inputs.get(0));
+                let mut result = vec![Value::floating(self.select_bits)];
+                result.extend((0..ways).map(|i| match select {
+                    Some(s) => Value::from_u64((s == i) as u64, 1),
+                    None => Value::error(1),
+                }));
+                result
+            },
+            PlexerType::PriorityEncoder => {
+                let mut result = vec![Value::floating(1); ways];
+                let mut out = Value::floating(self.select_bits);
+                let mut valid = Value::from_u64(0, 1);
+                // The highest numbered input which is set takes priority
+                for i in (0..ways).rev() {
+                    match inputs.get(i).map(|v| v.bit(0)) {
+                        Some(Bit::One) => {
+                            out = Value::from_u64(i as u64, self.select_bits);
+                            valid = Value::from_u64(1, 1);
+                            break;
+                        },
+                        Some(Bit::Error) => {
+                            out = Value::error(self.select_bits);
+                            valid = Value::error(1);
+                            break;
+                        },
+                        Some(Bit::Unknown) => {
+                            out = Value::unknown(self.select_bits);
+                            valid = Value::unknown(1);
+                            break;
+                        },
+                        _ => {},
+                    }
+                }
+                result.push(out);
+                result.push(valid);
+                result
+            },
+        }
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Plexer {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let ways = self.ways() as usize;
+        let index = |i: usize| literal(i as u64, self.select_bits);
+        match self.type_ {
+            PlexerType::Multiplexer => {
+                let select = &pins[ways];
+                let mut expr = pins[ways - 1].clone();
+                for i in (0..ways - 1).rev() {
+                    expr = format!("{} == {} ? {} : {}", select, index(i), pins[i], expr);
+                }
+                module.assign(&pins[ways + 1], &expr);
+            },
+            PlexerType::Demultiplexer => {
+                for i in 0..ways {
+                    let expr = format!("{} == {} ? {} : {}", pins[1], index(i), pins[0], literal(0, self.num_bits));
+                    module.assign(&pins[i + 2], &expr);
+                }
+            },
+            PlexerType::Decoder => {
+                for i in 0..ways {
+                    module.assign(&pins[i + 1], &format!("{} == {}", pins[0], index(i)));
+                }
+            },
+            PlexerType::PriorityEncoder => {
+                let mut expr = index(0);
+                for i in 1..ways {
+                    expr = format!("{} ? {} : {}", pins[i], index(i), expr);
+                }
+                module.assign(&pins[ways], &expr);
+                module.assign(&pins[ways + 1], &pins[..ways].join(" | "));
+            },
+        }
+        Ok(())
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    let plexers = [
+        ("multiplexer", "Multiplexer", "Outputs the data input chosen by the select input", PlexerType::Multiplexer),
+        ("demultiplexer", "Demultiplexer", "Routes its input to the output chosen by the select input", PlexerType::Demultiplexer),
+        ("decoder", "Decoder", "Sets only the output chosen by the select input", PlexerType::Decoder),
+        ("priority_encoder", "Priority Encoder", "Outputs the index of the highest input which is set", PlexerType::PriorityEncoder),
+    ];
+    for &(id, name, description, type_) in &plexers {
+        result.add(
+            ComponentMetadata::new(id, name, CATEGORY, description),
+            move || Box::new(Plexer::new(type_))
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[u64], width: u32) -> Vec<Value> {
+        values.iter().map(|&v| Value::from_u64(v, width)).collect()
+    }
+
+    #[test]
+    fn pins_follow_select_width() {
+        let mut mux = Plexer::new(PlexerType::Multiplexer);
+        mux.select_bits = 2;
+        let shape = mux.get_shape();
+        assert_eq!(shape.pins.len(), 6);
+        assert_eq!(shape.height, 6);
+        assert_eq!(shape.pins[4].name, "sel");
+        assert_eq!((shape.pins[4].x, shape.pins[4].y), (1, 6));
+        assert_eq!((shape.pins[5].x, shape.pins[5].y), (3, 3));
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut mux = Plexer::new(PlexerType::Multiplexer);
+        mux.num_bits = 4;
+        let mut inputs = values(&[3, 9], 4);
+        inputs.push(Value::from_u64(1, 1));
+        assert_eq!(mux.evaluate(&inputs)[3].to_u64(), Some(9));
+
+        let decoder = Plexer::new(PlexerType::Decoder);
+        let outputs = decoder.evaluate(&values(&[1], 1));
+        assert_eq!(outputs[1..].iter().map(|v| v.to_u64()).collect::<Vec<_>>(), vec![Some(0), Some(1)]);
+
+        let mut encoder = Plexer::new(PlexerType::PriorityEncoder);
+        encoder.select_bits = 2;
+        let outputs = encoder.evaluate(&values(&[1, 0, 1, 0], 1));
+        assert_eq!(outputs[4].to_u64(), Some(2));
+        assert_eq!(outputs[5].to_u64(), Some(1));
+        let outputs = encoder.evaluate(&values(&[0, 0, 0, 0], 1));
+        assert!(outputs[4].is_floating());
+        assert_eq!(outputs[5].to_u64(), Some(0));
+    }
+}
@@ -0,0 +1,329 @@
+//! Components whose behaviour is written in Rhai, and edited as one of the
+//! component's properties, for prototyping logic without rebuilding the
+//! application. A script defines the component's pins, any properties of
+//! its own, and how its outputs are computed:
+//!
+//! ```text
+//! // The inputs and outputs, as names and widths in bits
+//! fn inputs(props) { [["a", props.bits], ["b", props.bits]] }
+//! fn outputs(props) { [["y", props.bits]] }
+//!
+//! // Optional: further properties, and their default values
+//! fn properties() { #{ bits: 8 } }
+//!
+//! // Given each input as a number, or () if any of its bits are not
+//! // driven, returns a number or () for each output
+//! fn evaluate(inputs, props) {
+//!     if inputs[0] == () || inputs[1] == () { return [()]; }
+//!     [inputs[0] + inputs[1]]
+//! }
+//! ```
+//!
+//! Outputs which are `()` are unknown, and outputs are errors if the script
+//! fails or runs for too long. Requires the `scripting` feature.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rhai::{Array, Dynamic, Engine, FuncArgs, Map, Scope, Variant, AST};
+use serde_json;
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, FieldSchema, FieldType, ReflectType, PinValues};
+use crate::simulation::Value;
+use super::{spaced_height, spaced_y};
+
+pub const CATEGORY: &'static str = "Scripted";
+
+/// The number of operations a script may perform in a single call before
+/// it is abandoned.
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+const SCRIPT_PROPERTY: &'static str = "script";
+
+const DEFAULT_SCRIPT: &'static str = r#"// The inputs and outputs, as names and widths in bits
+fn inputs(props) { [["a", 1], ["b", 1]] }
+fn outputs(props) { [["y", 1]] }
+
+// Further properties, and their default values
+fn properties() { #{ invert: false } }
+
+// Given each input as a number, or () if any of its bits are not driven,
+// returns a number or () for each output
+fn evaluate(inputs, props) {
+    if inputs[0] == () || inputs[1] == () { return [()]; }
+    let y = inputs[0] & inputs[1];
+    [if props.invert { 1 - y } else { y }]
+}
+"#;
+
+#[derive(Debug)]
+struct Script {
+    engine: Engine,
+    ast: AST,
+    // The properties the script defines, and their default values
+    defaults: BTreeMap<String, serde_json::Value>,
+}
+
+impl Script {
+    fn compile(source: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        for &(name, params) in &[("inputs", 1), ("outputs", 1), ("evaluate", 2)] {
+            if !ast.iter_functions().any(|f| f.name == name && f.params.len() == params) {
+                return Err(format!("The script must define `{}` with {} parameters", name, params));
+            }
+        }
+        let mut script = Script { engine, ast, defaults: BTreeMap::new() };
+        if script.ast.iter_functions().any(|f| f.name == "properties" && f.params.is_empty()) {
+            let defaults: Map = script.call("properties", ())?;
+            for (name, value) in defaults {
+                let name = name.to_string();
+                if name == SCRIPT_PROPERTY {
+                    return Err(format!("`{}` cannot be redefined", SCRIPT_PROPERTY));
+                }
+                let value = from_dynamic(value).ok_or_else(|| format!("`{}` must be a boolean, integer or string", name))?;
+                script.defaults.insert(name, value);
+            }
+        }
+        Ok(script)
+    }
+    fn call<T: Variant + Clone, A: FuncArgs>(&self, name: &str, args: A) -> Result<T, String> {
+        self.engine.call_fn(&mut Scope::new(), &self.ast, name, args).map_err(|e| e.to_string())
+    }
+    // Calls `inputs` or `outputs` to find the pins
+    fn pins(&self, function: &str, props: &Map) -> Result<Vec<(String, u32)>, String> {
+        let pins: Array = self.call(function, (props.clone(),))?;
+        pins.into_iter().map(|pin| {
+            let invalid = || format!("`{}` must return a list of names and widths, such as [[\"a\", 1]]", function);
+            let pin = pin.try_cast::<Array>().filter(|p| p.len() == 2).ok_or_else(invalid)?;
+            let name = pin[0].clone().into_string().map_err(|_| invalid())?;
+            let bits = pin[1].as_int().map_err(|_| invalid())?;
+            if !(1..=64).contains(&bits) {
+                return Err(format!("`{}` must be between 1 and 64 bits wide", name));
+            }
+            Ok((name, bits as u32))
+        }).collect()
+    }
+}
+
+fn to_dynamic(value: &serde_json::Value) -> Dynamic {
+    match value {
+        serde_json::Value::Bool(b) => Dynamic::from(*b),
+        serde_json::Value::Number(n) => n.as_i64().map_or(Dynamic::UNIT, Dynamic::from),
+        serde_json::Value::String(s) => Dynamic::from(s.clone()),
+        _ => Dynamic::UNIT,
+    }
+}
+
+fn from_dynamic(value: Dynamic) -> Option<serde_json::Value> {
+    if let Ok(b) = value.as_bool() {
+        Some(b.into())
+    } else if let Ok(i) = value.as_int() {
+        Some(i.into())
+    } else {
+        value.into_string().ok().map(Into::into)
+    }
+}
+
+fn to_map(properties: &BTreeMap<String, serde_json::Value>) -> Map {
+    properties.iter().map(|(k, v)| (k.as_str().into(), to_dynamic(v))).collect()
+}
+
+fn field_type(default: &serde_json::Value) -> FieldType {
+    match default {
+        serde_json::Value::Bool(_) => bool::field_type(),
+        serde_json::Value::Number(_) => i64::field_type(),
+        _ => FieldType::string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ScriptedComponent {
+    source: String,
+    script: Arc<Script>,
+    properties: BTreeMap<String, serde_json::Value>,
+    inputs: Vec<(String, u32)>,
+    outputs: Vec<(String, u32)>,
+}
+
+impl ScriptedComponent {
+    fn new(source: &str) -> Result<Self, String> {
+        let script = Script::compile(source)?;
+        let properties = script.defaults.clone();
+        Self::build(source.into(), Arc::new(script), properties)
+    }
+    fn build(
+        source: String,
+        script: Arc<Script>,
+        properties: BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, String> {
+        let props = to_map(&properties);
+        let inputs = script.pins("inputs", &props)?;
+        let outputs = script.pins("outputs", &props)?;
+        Ok(ScriptedComponent { source, script, properties, inputs, outputs })
+    }
+}
+
+impl Properties for ScriptedComponent {
+    fn schema(&self) -> Schema {
+        let mut schema = Schema::new();
+        schema.insert(SCRIPT_PROPERTY.into(), FieldSchema::new("Script", FieldType::string().multiline())
+            .with_description("Defines the pins and behaviour of the component, in Rhai")
+            .with_default(DEFAULT_SCRIPT)
+            .with_order(1));
+        for (name, default) in &self.script.defaults {
+            schema.insert(name.clone().into(), FieldSchema::new(name.clone(), field_type(default))
+                .with_default(default.clone())
+                .depends_on(SCRIPT_PROPERTY));
+        }
+        schema
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        if name == SCRIPT_PROPERTY {
+            let source: String = serde_json::from_value(value)
+                .map_err(|e| PropertyError::from_serde(e, name))?;
+            let script = Script::compile(&source).map_err(|e| PropertyError::invalid(name, e))?;
+            // Properties the new script still defines keep their values
+            let properties = script.defaults.iter().map(|(k, default)| {
+                let value = self.properties.get(k)
+                    .filter(|v| field_type(default).validate(v).is_ok())
+                    .unwrap_or(default);
+                (k.clone(), value.clone())
+            }).collect();
+            *self = Self::build(source, Arc::new(script), properties)
+                .map_err(|e| PropertyError::invalid(name, e))?;
+            return Ok(());
+        }
+        let default = self.script.defaults.get(name).ok_or_else(|| PropertyError::unknown(name))?;
+        field_type(default).validate(&value).map_err(|e| PropertyError::invalid(name, e))?;
+        let mut properties = self.properties.clone();
+        properties.insert(name.into(), value);
+        *self = Self::build(self.source.clone(), self.script.clone(), properties)
+            .map_err(|e| PropertyError::invalid(name, e))?;
+        Ok(())
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        if name == SCRIPT_PROPERTY {
+            Some(self.source.clone().into())
+        } else {
+            self.properties.get(name).cloned()
+        }
+    }
+}
+
+impl Component for ScriptedComponent {
+    fn get_shape(&self) -> Shape {
+        let width = 4;
+        let (num_inputs, num_outputs) = (self.inputs.len() as u32, self.outputs.len() as u32);
+        let mut pins: Vec<_> = self.inputs.iter().enumerate().map(|(i, (name, bits))| Pin {
+            x: 0,
+            y: spaced_y(i as u32, num_inputs),
+            name: name.clone(),
+            bits: *bits,
+            direction: PinDirection::Input,
+        }).collect();
+        pins.extend(self.outputs.iter().enumerate().map(|(i, (name, bits))| Pin {
+            x: width,
+            y: spaced_y(i as u32, num_outputs),
+            name: name.clone(),
+            bits: *bits,
+            direction: PinDirection::Output,
+        }));
+        Shape {
+            width,
+            height: spaced_height(num_inputs.max(num_outputs)),
+            pins,
+            image_name: "script".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let args: Array = self.inputs.iter().enumerate()
+            .map(|(i, _)| inputs.get(i).and_then(|v| v.to_u64()).map_or(Dynamic::UNIT, |v| Dynamic::from(v as i64)))
+            .collect();
+        let outputs: Option<Array> = self.script.call("evaluate", (args, to_map(&self.properties))).ok();
+        let mut result: Vec<_> = self.inputs.iter().map(|&(_, bits)| Value::floating(bits)).collect();
+        result.extend(self.outputs.iter().enumerate().map(|(i, &(_, bits))| {
+            match outputs.as_ref().and_then(|o| o.get(i)) {
+                Some(v) if v.is_unit() => Value::unknown(bits),
+                Some(v) => v.as_int().map_or_else(|_| Value::error(bits), |v| Value::from_i64(v, bits)),
+                None => Value::error(bits),
+            }
+        }));
+        result
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("script", "Script", CATEGORY, "A component whose behaviour is defined by a script"),
+        || Box::new(ScriptedComponent::new(DEFAULT_SCRIPT).expect("Default script is valid"))
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(component: &ScriptedComponent, inputs: &[u64]) -> Vec<Option<u64>> {
+        let inputs: Vec<_> = inputs.iter().map(|&v| Value::from_u64(v, 1)).collect();
+        component.evaluate(&inputs).iter().skip(inputs.len()).map(|v| v.to_u64()).collect()
+    }
+
+    #[test]
+    fn default_script() {
+        let mut component = ScriptedComponent::new(DEFAULT_SCRIPT).unwrap();
+        assert_eq!(component.get_shape().pins.len(), 3);
+        assert_eq!(eval(&component, &[1, 1]), vec![Some(1)]);
+        assert_eq!(eval(&component, &[0, 1]), vec![Some(0)]);
+        component.set_property("invert", true.into()).unwrap();
+        assert_eq!(eval(&component, &[1, 1]), vec![Some(0)]);
+        assert!(component.set_property("invert", 3.into()).is_err());
+        assert!(component.set_property("missing", 3.into()).is_err());
+    }
+
+    #[test]
+    fn editing_the_script() {
+        let mut component = ScriptedComponent::new(DEFAULT_SCRIPT).unwrap();
+        component.set_property("invert", true.into()).unwrap();
+        assert!(component.set_property(SCRIPT_PROPERTY, "fn evaluate(".into()).is_err());
+        assert!(component.set_property(SCRIPT_PROPERTY, "fn evaluate(inputs, props) { [] }".into()).is_err());
+        assert_eq!(component.get_property("invert"), Some(true.into()));
+
+        let script = r#"
+            fn inputs(props) { [["a", props.bits]] }
+            fn outputs(props) { [["y", props.bits], ["overflow", 1]] }
+            fn properties() { #{ bits: 4, invert: false } }
+            fn evaluate(inputs, props) {
+                if inputs[0] == () { return [(), ()]; }
+                let y = inputs[0] * 2;
+                [y, y >> props.bits]
+            }
+        "#;
+        component.set_property(SCRIPT_PROPERTY, script.into()).unwrap();
+        assert_eq!(component.get_property("invert"), Some(true.into()));
+        assert_eq!(component.get_shape().pins[0].bits, 4);
+        let outputs = component.evaluate(&vec![Value::from_u64(9, 4)]);
+        assert_eq!(outputs[1].to_u64(), Some(2));
+        assert_eq!(outputs[2].to_u64(), Some(1));
+        assert!(!component.evaluate(&vec![Value::floating(4)])[1].is_defined());
+
+        component.set_property("bits", 8.into()).unwrap();
+        assert_eq!(component.get_shape().pins[0].bits, 8);
+        assert!(component.schema().contains_key("bits"));
+    }
+
+    #[test]
+    fn runaway_scripts_fail() {
+        let script = r#"
+            fn inputs(props) { [] }
+            fn outputs(props) { [["y", 1]] }
+            fn evaluate(inputs, props) { loop {} }
+        "#;
+        let component = ScriptedComponent::new(script).unwrap();
+        assert_eq!(component.evaluate(&vec![]), vec![Value::error(1)]);
+    }
+}
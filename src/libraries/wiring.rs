@@ -0,0 +1,1161 @@
+use serde_json;
+use maplit::btreemap;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+use crate::component::{
+    Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, PinBit,
+    FieldSchema, FieldType, ReflectType, PinValues,
+};
+use crate::simulation::{Bit, Value, Resolution};
+use crate::bits::BitString;
+use crate::migration::SavedProperties;
+use crate::export::verilog::{HdlEmit, ModuleWriter, ExportError};
+use super::{data_bits_field, address_bits};
+
+pub const CATEGORY: &'static str = "Wiring";
+
+fn single_pin_shape(width: i32, height: i32, pin: Pin, image_name: &'static str) -> Shape {
+    Shape {
+        width,
+        height,
+        pins: vec![pin],
+        image_name: image_name.into(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Splitter {
+    num_bits: u32,
+    fan_out: u32,
+    // The end each bit of the combined bus is routed to
+    mapping: Vec<u32>,
+}
+
+impl Splitter {
+    fn new() -> Self {
+        let mut result = Self {
+            num_bits: 2,
+            fan_out: 2,
+            mapping: Vec::new(),
+        };
+        result.resize();
+        result
+    }
+    fn default_end(&self, bit: u32) -> u32 {
+        bit * self.fan_out / self.num_bits
+    }
+    // Existing assignments are preserved where possible, so that the
+    // properties can be applied in any order.
+    fn resize(&mut self) {
+        for end in &mut self.mapping {
+            *end = (*end).min(self.fan_out - 1);
+        }
+        for bit in self.mapping.len() as u32..self.num_bits {
+            let end = self.default_end(bit);
+            self.mapping.push(end);
+        }
+        self.mapping.truncate(self.num_bits as usize);
+    }
+    fn end_width(&self, end: u32) -> u32 {
+        self.mapping.iter().filter(|&&e| e == end).count() as u32
+    }
+    fn parse_bit(&self, name: &str) -> Option<u32> {
+        if !name.starts_with("bit_") {
+            return None;
+        }
+        name["bit_".len()..].parse().ok().filter(|&bit| bit < self.num_bits)
+    }
+}
+
+impl Properties for Splitter {
+    fn schema(&self) -> Schema {
+        let mut result = btreemap!{
+            "fan_out".into() => FieldSchema::new("Fan out", FieldType::integer(1, 32)),
+            "num_bits".into() => data_bits_field(),
+        };
+        for bit in 0..self.num_bits {
+            result.insert(
+                format!("bit_{}", bit).into(),
+                FieldSchema::new(format!("Bit {}", bit), FieldType::integer(0, self.fan_out as i64 - 1))
+                    .with_description("The end this bit of the combined bus is connected to")
+                    .depends_on("fan_out")
+            );
+        }
+        result
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "fan_out" => {
+                self.fan_out = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.resize();
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.resize();
+                Ok(())
+            },
+            _ => {
+                let bit = self.parse_bit(name).ok_or_else(|| PropertyError::unknown(name))?;
+                let end: u32 = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                if end >= self.fan_out {
+                    return Err(PropertyError::invalid(name, format!("Splitter only has {} ends", self.fan_out)));
+                }
+                self.mapping[bit as usize] = end;
+                Ok(())
+            }
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "fan_out" => serde_json::to_value(self.fan_out).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => {
+                let bit = self.parse_bit(name)?;
+                serde_json::to_value(self.mapping[bit as usize]).ok()
+            }
+        }
+    }
+}
+
+impl Component for Splitter {
+    fn get_shape(&self) -> Shape {
+        let mut pins = vec![Pin {
+            x: 0,
+            y: 0,
+            name: "combined".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Bidirectional,
+        }];
+        pins.extend((0..self.fan_out).map(|end| Pin {
+            x: 2,
+            y: end as i32 + 1,
+            name: format!("end{}", end),
+            bits: self.end_width(end),
+            direction: PinDirection::Bidirectional,
+        }));
+        Shape {
+            width: 2,
+            height: self.fan_out as i32 + 1,
+            pins,
+            image_name: "splitter".into(),
+        }
+    }
+    fn connections(&self) -> Vec<(PinBit, PinBit)> {
+        let mut next = vec![0; self.fan_out as usize];
+        self.mapping.iter().enumerate().map(|(bit, &end)| {
+            let index = next[end as usize];
+            next[end as usize] += 1;
+            (PinBit::new(0, bit as u32), PinBit::new(end as usize + 1, index))
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Tunnel {
+    label: String,
+    num_bits: u32,
+}
+
+impl Properties for Tunnel {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "label".into() => FieldSchema::new("Label", FieldType::Text { min_len: 0, max_len: 64 })
+                .with_description("Tunnels with the same label are connected"),
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "label" => {
+                self.label = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "label" => serde_json::to_value(&self.label).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Tunnel {
+    fn get_shape(&self) -> Shape {
+        single_pin_shape(3, 2, Pin {
+            x: 0,
+            y: 1,
+            name: "io".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Bidirectional,
+        }, "tunnel")
+    }
+    fn tunnel_label(&self) -> Option<String> {
+        Some(self.label.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum PullDirection {
+    Down,
+    Up,
+}
+
+impl ReflectType for PullDirection {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[PullDirection::Down, PullDirection::Up])
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PullResistor {
+    direction: PullDirection,
+    num_bits: u32,
+}
+
+impl Properties for PullResistor {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "direction".into() => FieldSchema::new("Pull direction", PullDirection::field_type()),
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "direction" => {
+                self.direction = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "direction" => serde_json::to_value(self.direction).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for PullResistor {
+    fn get_shape(&self) -> Shape {
+        let image_name = match self.direction {
+            PullDirection::Down => "pull_down",
+            PullDirection::Up => "pull_up",
+        };
+        single_pin_shape(2, 3, Pin {
+            x: 1,
+            y: 0,
+            name: "io".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Input,
+        }, image_name)
+    }
+    fn pull(&self) -> Option<Bit> {
+        Some(Bit::from_bool(self.direction == PullDirection::Up))
+    }
+}
+
+// Overrides how conflicting drivers are resolved on the nets it is
+// attached to, such as wired AND for open collector outputs
+#[derive(Debug, Clone)]
+struct WiredLogic {
+    resolution: Resolution,
+    num_bits: u32,
+}
+
+impl Properties for WiredLogic {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "resolution".into() => FieldSchema::new("Resolution", Resolution::field_type())
+                .with_description("How the net is resolved when several outputs drive it to different values"),
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "resolution" => {
+                self.resolution = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "resolution" => serde_json::to_value(self.resolution).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for WiredLogic {
+    fn get_shape(&self) -> Shape {
+        let image_name = match self.resolution {
+            Resolution::Error => "wired",
+            Resolution::WiredAnd => "wired_and",
+            Resolution::WiredOr => "wired_or",
+        };
+        single_pin_shape(2, 2, Pin {
+            x: 1,
+            y: 0,
+            name: "io".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Input,
+        }, image_name)
+    }
+    fn resolution(&self) -> Option<Resolution> {
+        Some(self.resolution)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Constant {
+    value: BitString,
+    num_bits: u32,
+}
+
+impl Properties for Constant {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "value".into() => FieldSchema::new("Value", FieldType::BitVector { bits: self.num_bits })
+                .with_description("Hexadecimal values start with `0x` and binary values with `0b`. Negative values are sign extended")
+                .depends_on("num_bits"),
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "value" => {
+                let text: String = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.value = BitString::parse(&text, self.num_bits)
+                    .map_err(|e| PropertyError::invalid(name, e))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.value = self.value.resized(self.num_bits);
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "value" => serde_json::to_value(self.value.to_string()).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+// Version 1 stored the value of a constant as a signed integer
+fn migrate_constant_value(properties: &mut SavedProperties) -> Result<(), String> {
+    let num_bits = properties.get("num_bits").and_then(|v| v.as_u64()).unwrap_or(1) as u32;
+    if let Some(value) = properties.get("value").and_then(|v| v.as_i64()) {
+        properties.insert("value".into(), BitString::from_i64(value, num_bits).to_string().into());
+    }
+    Ok(())
+}
+
+impl Component for Constant {
+    fn get_shape(&self) -> Shape {
+        single_pin_shape(2, 2, Pin {
+            x: 2,
+            y: 1,
+            name: "out".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Output,
+        }, "constant")
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![self.value.to_value()]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Constant {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let value = self.value.to_value();
+        module.assign(&pins[0], &format!("{}'b{}", self.num_bits, value));
+        Ok(())
+    }
+}
+
+/// Power and ground, which drive every bit high or low respectively.
+#[derive(Debug, Clone)]
+struct Rail {
+    high: bool,
+    num_bits: u32,
+}
+
+impl Properties for Rail {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Rail {
+    fn get_shape(&self) -> Shape {
+        let (y, image_name) = if self.high { (2, "power") } else { (0, "ground") };
+        single_pin_shape(2, 2, Pin {
+            x: 1,
+            y,
+            name: "out".into(),
+            bits: self.num_bits,
+            direction: PinDirection::Output,
+        }, image_name)
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        vec![Value::repeat(Bit::from_bool(self.high), self.num_bits)]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Rail {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        module.assign(&pins[0], &format!("{{{}{{1'b{}}}}}", self.num_bits, self.high as u8));
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum ExtendMode {
+    Zero,
+    One,
+    Sign,
+}
+
+impl ReflectType for ExtendMode {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[ExtendMode::Zero, ExtendMode::One, ExtendMode::Sign])
+    }
+}
+
+/// Widens a bus, filling the extra high bits with zeros, ones or copies of
+/// the input's most significant bit.
+#[derive(Debug, Clone)]
+struct BitExtender {
+    in_bits: u32,
+    out_bits: u32,
+    mode: ExtendMode,
+}
+
+impl Properties for BitExtender {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "in_bits".into() => FieldSchema::new("Input bits", FieldType::integer(1, 256)),
+            "out_bits".into() => FieldSchema::new("Output bits", FieldType::integer(1, 256)),
+            "mode".into() => FieldSchema::new("Mode", ExtendMode::field_type()),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "in_bits" => {
+                self.in_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "out_bits" => {
+                self.out_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "mode" => {
+                self.mode = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "in_bits" => serde_json::to_value(self.in_bits).ok(),
+            "out_bits" => serde_json::to_value(self.out_bits).ok(),
+            "mode" => serde_json::to_value(self.mode).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for BitExtender {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "in".into(), bits: self.in_bits, direction: PinDirection::Input },
+                Pin { x: 3, y: 1, name: "out".into(), bits: self.out_bits, direction: PinDirection::Output },
+            ],
+            image_name: "bit_extender".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let input = inputs.get(0).cloned().unwrap_or_else(|| Value::floating(self.in_bits));
+        let bits = input.bits();
+        let fill = match self.mode {
+            ExtendMode::Zero => Bit::Zero,
+            ExtendMode::One => Bit::One,
+            ExtendMode::Sign => bits.last().cloned().unwrap_or(Bit::Zero),
+        };
+        let out = (0..self.out_bits as usize).map(|i| bits.get(i).cloned().unwrap_or(fill)).collect();
+        vec![Value::floating(self.in_bits), Value::new(out)]
+    }
+    // Narrowing would silently discard bits, which a splitter makes explicit
+    fn width_errors(&self) -> Vec<String> {
+        if self.out_bits < self.in_bits {
+            vec![format!("Output of {} bits is narrower than the input of {} bits", self.out_bits, self.in_bits)]
+        } else {
+            Vec::new()
+        }
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for BitExtender {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        // Assigning to a wider net zero extends, or sign extends a signed
+        // value, so only extending with ones needs the extra bits spelled out
+        let extra = self.out_bits.saturating_sub(self.in_bits);
+        let expr = match self.mode {
+            ExtendMode::One if extra > 0 => format!("{{{{{}{{1'b1}}}}, {}}}", extra, pins[0]),
+            ExtendMode::Sign => format!("$signed({})", pins[0]),
+            _ => pins[0].clone(),
+        };
+        module.assign(&pins[1], &expr);
+        Ok(())
+    }
+}
+
+/// Outputs one slice of a bus, chosen by `sel`: slice zero is the least
+/// significant `out_bits` bits. Bits of the last slice beyond the end of the
+/// bus are zero.
+#[derive(Debug, Clone)]
+struct BitSelector {
+    num_bits: u32,
+    out_bits: u32,
+}
+
+impl BitSelector {
+    fn select_bits(&self) -> u32 {
+        let slices = (self.num_bits + self.out_bits - 1) / self.out_bits;
+        address_bits(slices)
+    }
+}
+
+impl Properties for BitSelector {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => data_bits_field(),
+            "out_bits".into() => FieldSchema::new("Output bits", FieldType::integer(1, 256)),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "out_bits" => {
+                self.out_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            "out_bits" => serde_json::to_value(self.out_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for BitSelector {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "in".into(), bits: self.num_bits, direction: PinDirection::Input },
+                Pin { x: 1, y: 2, name: "sel".into(), bits: self.select_bits(), direction: PinDirection::Input },
+                Pin { x: 3, y: 1, name: "out".into(), bits: self.out_bits, direction: PinDirection::Output },
+            ],
+            image_name: "bit_selector".into(),
+        }
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let out = match (inputs.get(0), inputs.get(1).and_then(|v| v.to_u64())) {
+            (Some(input), Some(sel)) => {
+                let start = sel as usize * self.out_bits as usize;
+                let bits = input.bits();
+                Value::new((start..start + self.out_bits as usize)
+                    .map(|i| bits.get(i).cloned().unwrap_or(Bit::Zero))
+                    .collect())
+            },
+            _ => Value::error(self.out_bits),
+        };
+        vec![Value::floating(self.num_bits), Value::floating(self.select_bits()), out]
+    }
+    fn width_errors(&self) -> Vec<String> {
+        if self.out_bits > self.num_bits {
+            vec![format!("Output of {} bits is wider than the input of {} bits", self.out_bits, self.num_bits)]
+        } else {
+            Vec::new()
+        }
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for BitSelector {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        module.assign(&pins[2], &format!("{} >> ({} * {})", pins[0], pins[1], self.out_bits));
+        Ok(())
+    }
+}
+
+/// Concatenates buses into one, with the first input as the least
+/// significant bits. Like a splitter, it joins nets rather than driving
+/// them.
+#[derive(Debug, Clone)]
+struct BusMerger {
+    widths: Vec<u32>,
+}
+
+impl Properties for BusMerger {
+    fn schema(&self) -> Schema {
+        let num_inputs = self.widths.len() as u32;
+        btreemap!{
+            "num_inputs".into() => FieldSchema::new("Number of inputs", FieldType::integer(2, 32)),
+            "widths".into() => FieldSchema::new("Input bits",
+                FieldType::list(FieldType::integer(1, 256), num_inputs, num_inputs))
+                .with_default(vec![1; num_inputs as usize])
+                .depends_on("num_inputs"),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_inputs" => {
+                let num_inputs: u32 = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                self.widths.resize(num_inputs as usize, 1);
+                Ok(())
+            },
+            "widths" => {
+                self.widths = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_inputs" => serde_json::to_value(self.widths.len()).ok(),
+            "widths" => serde_json::to_value(&self.widths).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for BusMerger {
+    fn get_shape(&self) -> Shape {
+        let mut pins: Vec<_> = self.widths.iter().enumerate().map(|(i, &bits)| Pin {
+            x: 0,
+            y: i as i32 + 1,
+            name: format!("in{}", i),
+            bits,
+            direction: PinDirection::Input,
+        }).collect();
+        pins.push(Pin {
+            x: 2,
+            y: 0,
+            name: "out".into(),
+            bits: self.widths.iter().sum(),
+            direction: PinDirection::Output,
+        });
+        Shape {
+            width: 2,
+            height: self.widths.len() as i32 + 1,
+            pins,
+            image_name: "bus_merger".into(),
+        }
+    }
+    fn connections(&self) -> Vec<(PinBit, PinBit)> {
+        let out = self.widths.len();
+        let mut offset = 0;
+        self.widths.iter().enumerate().flat_map(|(input, &bits)| {
+            let start = offset;
+            offset += bits;
+            (0..bits).map(move |bit| (PinBit::new(input, bit), PinBit::new(out, start + bit)))
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum TransistorType {
+    N,
+    P,
+}
+
+impl ReflectType for TransistorType {
+    fn field_type() -> FieldType {
+        FieldType::for_enum(&[TransistorType::N, TransistorType::P])
+    }
+}
+
+// Connects each bit of one pin to the same bit of another
+fn bitwise_switches(a: usize, b: usize, num_bits: u32) -> Vec<(PinBit, PinBit)> {
+    (0..num_bits).map(|bit| (PinBit::new(a, bit), PinBit::new(b, bit))).collect()
+}
+
+// An array of Verilog switch primitives, one for each bit
+fn emit_switch(module: &mut ModuleWriter, primitive: &str, a: &str, b: &str, control: &str, num_bits: u32) {
+    let name = module.fresh("switch");
+    if num_bits == 1 {
+        module.statement(format!("{} {} ({}, {}, {});", primitive, name, a, b, control));
+    } else {
+        module.statement(format!("{} {}[{}:0] ({}, {}, {{{}{{{}}}}});", primitive, name, num_bits - 1, a, b, num_bits, control));
+    }
+}
+
+/// Connects its source and drain while its gate is one, for an N-type
+/// transistor, or zero for a P-type. Values pass in either direction at full
+/// strength, so this models a transistor as an ideal switch.
+#[derive(Debug, Clone)]
+struct Transistor {
+    type_: TransistorType,
+    num_bits: u32,
+}
+
+impl Transistor {
+    fn on(&self) -> Bit {
+        match self.type_ {
+            TransistorType::N => Bit::One,
+            TransistorType::P => Bit::Zero,
+        }
+    }
+}
+
+impl Properties for Transistor {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "type".into() => FieldSchema::new("Type", TransistorType::field_type())
+                .with_description("N-type conducts while the gate is one, and P-type while it is zero"),
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "type" => {
+                self.type_ = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "type" => serde_json::to_value(self.type_).ok(),
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for Transistor {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 2,
+            height: 2,
+            pins: vec![
+                Pin { x: 0, y: 1, name: "gate".into(), bits: 1, direction: PinDirection::Input },
+                Pin { x: 1, y: 0, name: "source".into(), bits: self.num_bits, direction: PinDirection::Bidirectional },
+                Pin { x: 1, y: 2, name: "drain".into(), bits: self.num_bits, direction: PinDirection::Bidirectional },
+            ],
+            image_name: match self.type_ {
+                TransistorType::N => "n_transistor",
+                TransistorType::P => "p_transistor",
+            }.into(),
+        }
+    }
+    fn switches(&self) -> Vec<(PinBit, PinBit)> {
+        bitwise_switches(1, 2, self.num_bits)
+    }
+    fn conducting(&self, inputs: &[Value]) -> Vec<bool> {
+        // An undefined gate leaves the transistor off
+        let on = inputs.get(0).map_or(false, |v| v.bit(0) == self.on());
+        vec![on; self.num_bits as usize]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for Transistor {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let primitive = match self.type_ {
+            TransistorType::N => "tranif1",
+            TransistorType::P => "tranif0",
+        };
+        emit_switch(module, primitive, &pins[1], &pins[2], &pins[0], self.num_bits);
+        Ok(())
+    }
+}
+
+/// A CMOS transmission gate: an N-type and a P-type transistor in parallel,
+/// so that `a` and `b` are connected while `gate` is one or `gate_n` is
+/// zero.
+#[derive(Debug, Clone)]
+struct TransmissionGate {
+    num_bits: u32,
+}
+
+impl Properties for TransmissionGate {
+    fn schema(&self) -> Schema {
+        btreemap!{
+            "num_bits".into() => data_bits_field(),
+        }
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        match name {
+            "num_bits" => {
+                self.num_bits = serde_json::from_value(value)
+                    .map_err(|e| PropertyError::from_serde(e, name))?;
+                Ok(())
+            },
+            _ => Err(PropertyError::unknown(name))
+        }
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        match name {
+            "num_bits" => serde_json::to_value(self.num_bits).ok(),
+            _ => None
+        }
+    }
+}
+
+impl Component for TransmissionGate {
+    fn get_shape(&self) -> Shape {
+        Shape {
+            width: 3,
+            height: 2,
+            pins: vec![
+                Pin { x: 1, y: 0, name: "gate".into(), bits: 1, direction: PinDirection::Input },
+                Pin { x: 1, y: 2, name: "gate_n".into(), bits: 1, direction: PinDirection::Input },
+                Pin { x: 0, y: 1, name: "a".into(), bits: self.num_bits, direction: PinDirection::Bidirectional },
+                Pin { x: 3, y: 1, name: "b".into(), bits: self.num_bits, direction: PinDirection::Bidirectional },
+            ],
+            image_name: "transmission_gate".into(),
+        }
+    }
+    fn switches(&self) -> Vec<(PinBit, PinBit)> {
+        bitwise_switches(2, 3, self.num_bits)
+    }
+    fn conducting(&self, inputs: &[Value]) -> Vec<bool> {
+        let control = |index: usize, on| inputs.get(index).map_or(false, |v| v.bit(0) == on);
+        let on = control(0, Bit::One) || control(1, Bit::Zero);
+        vec![on; self.num_bits as usize]
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for TransmissionGate {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        emit_switch(module, "tranif1", &pins[2], &pins[3], &pins[0], self.num_bits);
+        emit_switch(module, "tranif0", &pins[2], &pins[3], &pins[1], self.num_bits);
+        Ok(())
+    }
+}
+
+pub fn library() -> Library {
+    let mut result = Library::new();
+    result.add(
+        ComponentMetadata::new("splitter", "Splitter", CATEGORY, "Splits a bus into smaller buses, or combines them"),
+        || Box::new(Splitter::new())
+    );
+    result.add(
+        ComponentMetadata::new("bit_extender", "Bit Extender", CATEGORY, "Widens a bus by zero or sign extension"),
+        || Box::new(BitExtender { in_bits: 8, out_bits: 8, mode: ExtendMode::Zero })
+    );
+    result.add(
+        ComponentMetadata::new("bit_selector", "Bit Selector", CATEGORY, "Selects a slice of a bus"),
+        || Box::new(BitSelector { num_bits: 8, out_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("bus_merger", "Bus Merger", CATEGORY, "Concatenates several buses into one"),
+        || Box::new(BusMerger { widths: vec![1, 1] })
+    );
+    result.add(
+        ComponentMetadata::new("transistor", "Transistor", CATEGORY, "Connects its source and drain depending on its gate"),
+        || Box::new(Transistor { type_: TransistorType::N, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("transmission_gate", "Transmission Gate", CATEGORY, "Connects two buses in either direction while enabled"),
+        || Box::new(TransmissionGate { num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("tunnel", "Tunnel", CATEGORY, "Connects to all other tunnels with the same label"),
+        || Box::new(Tunnel { label: String::new(), num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("pull_resistor", "Pull Resistor", CATEGORY, "Pulls floating bits to a fixed value"),
+        || Box::new(PullResistor { direction: PullDirection::Down, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("wired_logic", "Wired Logic", CATEGORY, "Resolves conflicting drivers of a net as wired AND or OR"),
+        || Box::new(WiredLogic { resolution: Resolution::WiredAnd, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("constant", "Constant", CATEGORY, "Outputs a fixed value").with_schema_version(2),
+        || Box::new(Constant { value: BitString::from_u64(1, 1), num_bits: 1 })
+    );
+    result.add_migration("constant", 1, migrate_constant_value)
+        .expect("Constant was just added");
+    result.add(
+        ComponentMetadata::new("power", "Power", CATEGORY, "Drives all bits high"),
+        || Box::new(Rail { high: true, num_bits: 1 })
+    );
+    result.add(
+        ComponentMetadata::new("ground", "Ground", CATEGORY, "Drives all bits low"),
+        || Box::new(Rail { high: false, num_bits: 1 })
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::circuit::Circuit;
+    use crate::component::ComponentInfo;
+    use crate::net::PinRef;
+    use crate::simulation::{Simulator, SimulatorConfig};
+
+    fn info<C: Component + Clone>(component: C) -> ComponentInfo {
+        let metadata = ComponentMetadata::new("test", "Test", CATEGORY, "");
+        ComponentInfo::new(Box::new(component), Arc::new(metadata))
+    }
+
+    #[test]
+    fn splitter_mapping() {
+        let mut splitter = Splitter::new();
+        splitter.num_bits = 5;
+        splitter.fan_out = 3;
+        splitter.mapping.clear();
+        splitter.resize();
+        assert_eq!(splitter.mapping, vec![0, 0, 1, 1, 2]);
+        splitter.mapping[1] = 2;
+        assert_eq!(splitter.connections()[4], (PinBit::new(0, 4), PinBit::new(3, 1)));
+        splitter.fan_out = 2;
+        splitter.resize();
+        assert_eq!(splitter.mapping, vec![0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn splitter_combines_ends() {
+        let lib = library();
+        let mut circuit = Circuit::new();
+        let splitter = circuit.add_component(lib.create("splitter").unwrap(), 10, 10);
+        circuit.add_component(lib.create("power").unwrap(), 11, 9);
+        circuit.add_component(lib.create("ground").unwrap(), 11, 12);
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(splitter, 0)).unwrap().to_u64(), Some(1));
+    }
+
+    #[test]
+    fn tunnels_and_pulls() {
+        let mut circuit = Circuit::new();
+        circuit.add_component(info(Rail { high: true, num_bits: 2 }), -1, -1);
+        circuit.add_component(info(Tunnel { label: "a".into(), num_bits: 2 }), 0, 0);
+        let far = circuit.add_component(info(Tunnel { label: "a".into(), num_bits: 2 }), 20, 0);
+        let other = circuit.add_component(info(Tunnel { label: "b".into(), num_bits: 2 }), 40, 0);
+        circuit.add_component(info(PullResistor { direction: PullDirection::Up, num_bits: 1 }), 39, 1);
+
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(far, 0)).unwrap().to_u64(), Some(3));
+        assert_eq!(sim.pin_value(PinRef::new(other, 0)).unwrap().bits(), &[Bit::One, Bit::HighZ]);
+    }
+
+    #[test]
+    fn wired_logic() {
+        let mut circuit = Circuit::new();
+        circuit.add_component(info(Rail { high: true, num_bits: 1 }), -1, -1);
+        circuit.add_component(info(Tunnel { label: "a".into(), num_bits: 1 }), 0, 0);
+        circuit.add_component(info(Rail { high: false, num_bits: 1 }), 19, 1);
+        let far = circuit.add_component(info(Tunnel { label: "a".into(), num_bits: 1 }), 20, 0);
+        let read = |circuit: &Circuit, config| {
+            let mut sim = Simulator::with_config(circuit.clone(), config);
+            sim.run_until_stable().unwrap();
+            sim.pin_value(PinRef::new(far, 0)).unwrap().bit(0)
+        };
+        let wired_or = SimulatorConfig { resolution: Resolution::WiredOr, ..SimulatorConfig::default() };
+        assert_eq!(read(&circuit, SimulatorConfig::default()), Bit::Error);
+        assert_eq!(read(&circuit, wired_or.clone()), Bit::One);
+
+        // The net's own policy takes precedence over the simulator's
+        circuit.add_component(info(WiredLogic { resolution: Resolution::WiredAnd, num_bits: 1 }), -1, 1);
+        assert_eq!(read(&circuit, wired_or), Bit::Zero);
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert!(sim.contentions().is_empty());
+
+        assert_eq!(Resolution::WiredAnd.resolve(Bit::One, Bit::Unknown), Bit::Unknown);
+        assert_eq!(Resolution::WiredOr.resolve(Bit::Error, Bit::One), Bit::One);
+    }
+
+    #[test]
+    fn extend_select_and_merge() {
+        let value = |bits: u64, width| Value::from_u64(bits, width);
+        let mut extender = BitExtender { in_bits: 4, out_bits: 8, mode: ExtendMode::Sign };
+        assert_eq!(extender.evaluate(&vec![value(0b1010, 4)])[1].to_u64(), Some(0xfa));
+        extender.mode = ExtendMode::Zero;
+        assert_eq!(extender.evaluate(&vec![value(0b1010, 4)])[1].to_u64(), Some(0x0a));
+        assert!(extender.width_errors().is_empty());
+        extender.out_bits = 2;
+        assert_eq!(extender.width_errors().len(), 1);
+
+        let selector = BitSelector { num_bits: 10, out_bits: 4 };
+        assert_eq!(selector.get_shape().pins[1].bits, 2);
+        assert_eq!(selector.evaluate(&vec![value(0x2a5, 10), value(1, 2)])[2].to_u64(), Some(0xa));
+        assert_eq!(selector.evaluate(&vec![value(0x2a5, 10), value(2, 2)])[2].to_u64(), Some(0x2));
+        assert_eq!(selector.evaluate(&vec![value(0x2a5, 10), Value::floating(2)])[2].to_u64(), None);
+
+        let mut merger = library().create("bus_merger").unwrap();
+        merger.set_property("widths", serde_json::json!([1, 2])).unwrap();
+        let mut circuit = Circuit::new();
+        let merger = circuit.add_component(merger, 0, 0);
+        circuit.add_component(info(Rail { high: false, num_bits: 1 }), -1, 1);
+        circuit.add_component(info(Rail { high: true, num_bits: 2 }), -1, 0);
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(merger, 2)).unwrap().to_u64(), Some(0b110));
+    }
+
+    // A CMOS inverter, with the drain of the P-type transistor joined
+    // directly to the source of the N-type transistor
+    fn cmos_inverter(input: Option<bool>) -> Option<Value> {
+        let mut circuit = Circuit::new();
+        let p = circuit.add_component(info(Transistor { type_: TransistorType::P, num_bits: 1 }), 0, 0);
+        circuit.add_component(info(Transistor { type_: TransistorType::N, num_bits: 1 }), 0, 2);
+        circuit.add_component(info(Rail { high: true, num_bits: 1 }), 0, -2);
+        circuit.add_component(info(Rail { high: false, num_bits: 1 }), 0, 4);
+        if let Some(input) = input {
+            circuit.add_component(info(Rail { high: input, num_bits: 1 }), -1, if input { -1 } else { 1 });
+            circuit.add_component(info(Rail { high: input, num_bits: 1 }), -1, if input { 1 } else { 3 });
+        }
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        sim.pin_value(PinRef::new(p, 2))
+    }
+
+    #[test]
+    fn transistors() {
+        assert_eq!(cmos_inverter(Some(false)).unwrap().to_u64(), Some(1));
+        assert_eq!(cmos_inverter(Some(true)).unwrap().to_u64(), Some(0));
+        assert!(cmos_inverter(None).unwrap().is_floating());
+
+        // A transmission gate passes values in either direction
+        let gate = TransmissionGate { num_bits: 2 };
+        let control = |gate, gate_n| vec![Value::from_u64(gate, 1), Value::from_u64(gate_n, 1)];
+        assert_eq!(gate.conducting(&control(1, 1)), vec![true, true]);
+        assert_eq!(gate.conducting(&control(0, 0)), vec![true, true]);
+        assert_eq!(gate.conducting(&control(0, 1)), vec![false, false]);
+        let mut circuit = Circuit::new();
+        let id = circuit.add_component(info(gate), 0, 0);
+        circuit.add_component(info(Rail { high: true, num_bits: 1 }), 0, -2);
+        circuit.add_component(info(Rail { high: true, num_bits: 2 }), 2, -1);
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(id, 2)).unwrap().to_u64(), Some(3));
+        assert!(crate::compiled::CompiledCircuit::compile(sim.circuit()).is_err());
+    }
+
+    #[test]
+    fn wide_constants() {
+        let lib = library();
+        let mut constant = lib.create("constant").unwrap();
+        constant.set_property("num_bits", 72.into()).unwrap();
+        constant.set_property("value", "0xff_0000_0000_0000_0001".into()).unwrap();
+        let output = &constant.evaluate(&vec![])[0];
+        assert_eq!((output.width(), output.bit(0), output.bit(71), output.bit(63)), (72, Bit::One, Bit::One, Bit::Zero));
+        assert!(constant.set_property("value", "0x1_0000_0000_0000_0000_00".into()).is_err());
+
+        // Older versions stored the value as a signed integer
+        let mut properties = SavedProperties::new();
+        properties.insert("num_bits".into(), 8.into());
+        properties.insert("value".into(), (-2).into());
+        lib.migrate("constant", 1, &mut properties).unwrap();
+        assert_eq!(properties["value"], "0xfe");
+    }
+}
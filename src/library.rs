@@ -5,8 +5,38 @@ use std::fmt;
 use std::error::Error;
 
 use serde_derive::{Serialize, Deserialize};
+use serde_json::{Map, Value};
 
-use crate::component::{AnyComponent, ComponentInfo};
+use crate::component::{AnyComponent, ComponentInfo, PinDirection, PropertyError};
+use crate::migration::{self, Migration, Migrations, MigrationError, SavedProperties};
+
+/// How a component is shown in a palette.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Icon {
+    /// The name of an icon provided by the front-end.
+    Named(Cow<'static, str>),
+    /// A standalone SVG document.
+    Svg(Cow<'static, str>),
+}
+
+/// A named set of property values for a component, such as an AND gate
+/// with four inputs, which palettes can offer alongside the component's
+/// defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: Cow<'static, str>,
+    pub properties: Map<String, Value>,
+}
+
+impl Preset {
+    pub fn new<S: Into<Cow<'static, str>>>(name: S) -> Self {
+        Preset { name: name.into(), properties: Map::new() }
+    }
+    pub fn with_property<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.properties.insert(name.into(), value.into());
+        self
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ComponentMetadata {
@@ -14,6 +44,44 @@ pub struct ComponentMetadata {
     pub name: Cow<'static, str>,
     pub category: Cow<'static, str>,
     pub description: Cow<'static, str>,
+    /// Components and categories with a lower order are listed first.
+    /// Categories are ordered by the lowest order of their components, and
+    /// ties are broken by name.
+    #[serde(default)]
+    pub order: i32,
+    /// Increased whenever the properties of the component change in a way
+    /// which requires saved components to be migrated.
+    #[serde(default = "migration::initial_schema_version")]
+    pub schema_version: u32,
+    /// The key of the messages which translate the name and description.
+    /// Defaults to `component-` followed by the id. See `i18n`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_key: Option<Cow<'static, str>>,
+    /// Long-form documentation, as markdown, to follow the description in a
+    /// help panel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Cow<'static, str>>,
+    /// Examples of using the component, each as markdown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<Cow<'static, str>>,
+    /// Keywords which searches should match, such as `nand` for a NOT AND
+    /// gate.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Cow<'static, str>>,
+    /// A link to further documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc_url: Option<Cow<'static, str>>,
+    /// Without an icon, palettes show a thumbnail of the component. See
+    /// `render::thumbnail`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Icon>,
+    /// The component is kept so that old files still load, but is left out
+    /// of palettes and searches.
+    #[serde(default)]
+    pub deprecated: bool,
+    /// Common configurations of the component. See `Library::create_preset`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub presets: Vec<Preset>,
 }
 
 impl ComponentMetadata {
@@ -28,14 +96,149 @@ impl ComponentMetadata {
             name: name.into(),
             category: category.into(),
             description: description.into(),
+            order: 0,
+            schema_version: migration::INITIAL_SCHEMA_VERSION,
+            message_key: None,
+            documentation: None,
+            examples: Vec::new(),
+            tags: Vec::new(),
+            doc_url: None,
+            icon: None,
+            deprecated: false,
+            presets: Vec::new(),
+        }
+    }
+    pub fn with_order(mut self, order: i32) -> Self {
+        self.order = order;
+        self
+    }
+    pub fn with_schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+    pub fn with_message_key<S: Into<Cow<'static, str>>>(mut self, key: S) -> Self {
+        self.message_key = Some(key.into());
+        self
+    }
+    pub fn with_documentation<S: Into<Cow<'static, str>>>(mut self, documentation: S) -> Self {
+        self.documentation = Some(documentation.into());
+        self
+    }
+    pub fn with_example<S: Into<Cow<'static, str>>>(mut self, example: S) -> Self {
+        self.examples.push(example.into());
+        self
+    }
+    pub fn with_tags<S: Into<Cow<'static, str>>, I: IntoIterator<Item=S>>(mut self, tags: I) -> Self {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+    pub fn with_doc_url<S: Into<Cow<'static, str>>>(mut self, url: S) -> Self {
+        self.doc_url = Some(url.into());
+        self
+    }
+    pub fn with_icon(mut self, icon: Icon) -> Self {
+        self.icon = Some(icon);
+        self
+    }
+    pub fn deprecated(mut self) -> Self {
+        self.deprecated = true;
+        self
+    }
+    pub fn with_preset(mut self, preset: Preset) -> Self {
+        self.presets.push(preset);
+        self
+    }
+    fn sort_key(&self) -> (i32, &str, &str) {
+        (self.order, &self.name, &self.id)
+    }
+    // Returns a score for how well the metadata matches a lowercase query,
+    // or `None` if it does not match at all. Higher scores are better.
+    fn match_score(&self, query: &str) -> Option<u32> {
+        let id = self.id.to_lowercase();
+        let name = self.name.to_lowercase();
+        let description = self.description.to_lowercase();
+        if id == query || name == query {
+            Some(5)
+        } else if id.starts_with(query) || name.starts_with(query) {
+            Some(4)
+        } else if id.contains(query) || name.contains(query) {
+            Some(3)
+        } else if description.contains(query) || self.tags.iter().any(|tag| tag.to_lowercase().starts_with(query)) {
+            Some(2)
+        } else if is_subsequence(query, &name) || is_subsequence(query, &id) {
+            Some(1)
+        } else {
+            None
         }
     }
 }
 
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+/// A property of a component, as listed in its documentation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PropertyDocumentation {
+    pub property: String,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A pin of a component, as listed in its documentation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PinDocumentation {
+    pub name: String,
+    pub bits: u32,
+    pub direction: PinDirection,
+}
+
+/// Everything a help panel shows about a component.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Documentation {
+    pub id: String,
+    pub name: String,
+    pub category: String,
+    pub description: String,
+    pub documentation: Option<String>,
+    pub examples: Vec<String>,
+    pub tags: Vec<String>,
+    pub doc_url: Option<String>,
+    /// The properties of a newly created component, in the order a
+    /// property panel shows them.
+    pub properties: Vec<PropertyDocumentation>,
+    /// The pins of a newly created component.
+    pub pins: Vec<PinDocumentation>,
+}
+
+/// A single page of results from a larger list.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub offset: usize,
+    /// The number of items across all pages.
+    pub total: usize,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, offset: usize, limit: usize) -> Self {
+        let total = items.len();
+        let items = items.into_iter().skip(offset).take(limit).collect();
+        Page { items, offset, total }
+    }
+    pub fn has_more(&self) -> bool {
+        self.offset + self.items.len() < self.total
+    }
+}
+
 #[derive(Clone)]
 struct ComponentEntry {
     metadata: Arc<ComponentMetadata>,
     factory: Arc<Fn() -> Box<AnyComponent> + Send + Sync + 'static>,
+    migrations: Migrations,
+    // The initial property values, captured when the component was added
+    defaults: Arc<SavedProperties>,
 }
 
 impl fmt::Debug for ComponentEntry {
@@ -46,7 +249,9 @@ impl fmt::Debug for ComponentEntry {
 
 #[derive(Clone, Default, Debug)]
 pub struct Library {
-    components: BTreeMap<String, ComponentEntry>
+    components: BTreeMap<String, ComponentEntry>,
+    // The old ids of renamed components, mapped to their current ids
+    aliases: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
@@ -62,26 +267,466 @@ impl fmt::Display for MissingComponentError {
 
 impl Error for MissingComponentError { }
 
+/// Why `Library::create_with` could not create a component.
+#[derive(Debug, Clone)]
+pub enum CreateError {
+    Missing(MissingComponentError),
+    MissingPreset { id: String, preset: String },
+    /// The properties which could not be set.
+    Properties(Vec<PropertyError>),
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateError::Missing(e) => e.fmt(f),
+            CreateError::MissingPreset { id, preset } =>
+                write!(f, "Component `{}` has no preset named `{}`", id, preset),
+            CreateError::Properties(errors) => {
+                let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("; "))
+            },
+        }
+    }
+}
+
+impl Error for CreateError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            CreateError::Missing(e) => Some(e),
+            CreateError::MissingPreset { .. } => None,
+            CreateError::Properties(errors) => errors.first().map(|e| e as &(Error + 'static)),
+        }
+    }
+}
+
+impl From<MissingComponentError> for CreateError {
+    fn from(e: MissingComponentError) -> Self {
+        CreateError::Missing(e)
+    }
+}
+
+/// Separates the namespace of a component from the rest of its id, as in
+/// `gates/or_gate`.
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+#[derive(Debug, Clone)]
+pub struct CollisionError {
+    pub ids: Vec<String>,
+}
+
+impl fmt::Display for CollisionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Components with ids `{}` are already present", self.ids.join("`, `"))
+    }
+}
+
+impl Error for CollisionError { }
+
 impl Library {
     pub fn new() -> Self {
         Default::default()
     }
+    /// Returns every component, including those which are deprecated.
     pub fn list(&self) -> Vec<Arc<ComponentMetadata>> {
         self.components.values().map(|c| c.metadata.clone()).collect()
     }
+    // The components which palettes and searches show
+    fn listed(&self) -> impl Iterator<Item=&ComponentEntry> {
+        self.components.values().filter(|c| !c.metadata.deprecated)
+    }
+    /// Returns the category names, in display order.
+    pub fn list_categories(&self) -> Vec<String> {
+        let mut categories = BTreeMap::new();
+        for entry in self.listed() {
+            let metadata = &entry.metadata;
+            let order = categories.entry(&*metadata.category).or_insert(metadata.order);
+            *order = (*order).min(metadata.order);
+        }
+        let mut result: Vec<_> = categories.into_iter().collect();
+        result.sort_by_key(|&(name, order)| (order, name));
+        result.into_iter().map(|(name, _)| name.into()).collect()
+    }
+    /// Returns the components in a category, in display order.
+    pub fn list_by_category(&self, category: &str) -> Vec<Arc<ComponentMetadata>> {
+        let mut result: Vec<_> = self.listed()
+            .filter(|c| c.metadata.category == category)
+            .map(|c| c.metadata.clone())
+            .collect();
+        result.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        result
+    }
+    /// Finds components whose id, name or description match the query. The
+    /// match is case-insensitive and the characters of the query need not be
+    /// adjacent, but closer matches are returned first.
+    pub fn search(&self, query: &str) -> Vec<Arc<ComponentMetadata>> {
+        let query = query.trim().to_lowercase();
+        let mut result: Vec<_> = self.listed()
+            .filter_map(|c| c.metadata.match_score(&query).map(|score| (score, c.metadata.clone())))
+            .collect();
+        result.sort_by(|(sa, a), (sb, b)| sb.cmp(sa).then_with(|| a.sort_key().cmp(&b.sort_key())));
+        result.into_iter().map(|(_, m)| m).collect()
+    }
+    pub fn search_page(&self, query: &str, offset: usize, limit: usize) -> Page<Arc<ComponentMetadata>> {
+        Page::new(self.search(query), offset, limit)
+    }
+    /// Makes `old_id` another name for the component `new_id`, so that
+    /// files saved before the component was renamed still load. A component
+    /// registered under `old_id` takes precedence over the alias.
+    pub fn add_alias(&mut self, old_id: &str, new_id: &str) -> Result<(), MissingComponentError> {
+        if !self.contains(new_id) {
+            return Err(MissingComponentError { id: new_id.into() });
+        }
+        self.aliases.insert(old_id.into(), new_id.into());
+        Ok(())
+    }
+    /// The id of the component an alias refers to, if `id` is an alias.
+    /// Aliases of aliases are followed, so that a component may be renamed
+    /// several times.
+    pub fn alias_of(&self, id: &str) -> Option<&str> {
+        let (mut current, mut target) = (id, None);
+        // Each alias is followed at most once, in case of cycles
+        for _ in 0..=self.aliases.len() {
+            if self.components.contains_key(current) {
+                return target;
+            }
+            let next = self.aliases.get(current)?;
+            current = next;
+            target = Some(&**next);
+        }
+        None
+    }
+    /// The id under which the component is registered, following any alias.
+    pub fn resolve_id<'a>(&'a self, id: &'a str) -> &'a str {
+        self.alias_of(id).unwrap_or(id)
+    }
+    fn entry(&self, id: &str) -> Option<&ComponentEntry> {
+        self.components.get(self.resolve_id(id))
+    }
+    /// Whether a component can be created with the id, which may be an
+    /// alias.
+    pub fn contains(&self, id: &str) -> bool {
+        self.entry(id).is_some()
+    }
+    pub fn get_metadata(&self, id: &str) -> Option<Arc<ComponentMetadata>> {
+        self.entry(id).map(|c| c.metadata.clone())
+    }
+    /// The documentation of a component, including the properties and pins
+    /// it has when created with its default properties.
+    pub fn documentation(&self, id: &str) -> Result<Documentation, MissingComponentError> {
+        let info = self.create(id)?;
+        let metadata = info.metadata();
+        let to_strings = |items: &[Cow<'static, str>]| items.iter().map(|s| s.to_string()).collect();
+        let mut fields: Vec<_> = info.schema().into_iter().collect();
+        fields.sort_by(|(a, fa), (b, fb)| (fa.order, a).cmp(&(fb.order, b)));
+        Ok(Documentation {
+            id: metadata.id.to_string(),
+            name: metadata.name.to_string(),
+            category: metadata.category.to_string(),
+            description: metadata.description.to_string(),
+            documentation: metadata.documentation.as_ref().map(|s| s.to_string()),
+            examples: to_strings(&metadata.examples),
+            tags: to_strings(&metadata.tags),
+            doc_url: metadata.doc_url.as_ref().map(|s| s.to_string()),
+            properties: fields.into_iter().map(|(property, field)| PropertyDocumentation {
+                property: property.into_owned(),
+                name: field.name.into_owned(),
+                description: field.description.map(Cow::into_owned),
+            }).collect(),
+            pins: info.get_shape().pins.iter().map(|pin| PinDocumentation {
+                name: pin.name.clone(),
+                bits: pin.bits,
+                direction: pin.direction,
+            }).collect(),
+        })
+    }
     pub fn create(&self, id: &str) -> Result<ComponentInfo, MissingComponentError> {
-        let entry = self.components.get(id)
+        let entry = self.entry(id)
             .ok_or_else(|| MissingComponentError { id: id.into() })?;
-        Ok(ComponentInfo::new((entry.factory)(), entry.metadata.clone()))
+        let mut info = ComponentInfo::new((entry.factory)(), entry.metadata.clone());
+        info.set_defaults(entry.defaults.clone());
+        Ok(info)
+    }
+    /// Creates a component with the given initial values for some of its
+    /// properties. Some properties are only valid once others have been set,
+    /// so the values are applied in whatever order succeeds. If any cannot
+    /// be set, no component is created and the error for each is returned.
+    pub fn create_with(&self, id: &str, properties: &Map<String, Value>) -> Result<ComponentInfo, CreateError> {
+        let mut info = self.create(id)?;
+        let mut remaining: Vec<_> = properties.iter().collect();
+        loop {
+            let count = remaining.len();
+            let mut errors = Vec::new();
+            remaining.retain(|&(name, value)| match info.set_property(name, value.clone()) {
+                Ok(_) => false,
+                Err(e) => {
+                    errors.push(e);
+                    true
+                },
+            });
+            if remaining.is_empty() {
+                return Ok(info);
+            } else if remaining.len() == count {
+                return Err(CreateError::Properties(errors));
+            }
+        }
     }
+    /// Creates a component configured as one of its presets.
+    pub fn create_preset(&self, id: &str, preset: &str) -> Result<ComponentInfo, CreateError> {
+        let metadata = self.get_metadata(id).ok_or_else(|| MissingComponentError { id: id.into() })?;
+        let properties = &metadata.presets.iter()
+            .find(|p| p.name == preset)
+            .ok_or_else(|| CreateError::MissingPreset { id: id.into(), preset: preset.into() })?
+            .properties;
+        self.create_with(id, properties)
+    }
+    /// Adds all of the components from another library, replacing any
+    /// existing components with the same ids.
     pub fn extend(&mut self, other: Library) {
         self.components.extend(other.components.into_iter());
+        self.aliases.extend(other.aliases);
+    }
+    /// Adds all of the components from another library. If any of the ids
+    /// are already present, nothing is added and the colliding ids are
+    /// returned.
+    pub fn extend_checked(&mut self, other: Library) -> Result<(), CollisionError> {
+        let ids: Vec<_> = other.components.keys()
+            .filter(|id| self.components.contains_key(*id))
+            .cloned()
+            .collect();
+        if ids.is_empty() {
+            self.extend(other);
+            Ok(())
+        } else {
+            Err(CollisionError { ids })
+        }
+    }
+    /// Returns the library with every id placed in the given namespace.
+    pub fn namespaced(self, prefix: &str) -> Library {
+        let components = self.components.into_iter().map(|(id, entry)| {
+            let id = format!("{}{}{}", prefix, NAMESPACE_SEPARATOR, id);
+            let mut metadata = (*entry.metadata).clone();
+            metadata.id = id.clone().into();
+            (id, ComponentEntry {
+                metadata: Arc::new(metadata),
+                ..entry
+            })
+        }).collect();
+        let aliases = self.aliases.into_iter().map(|(old_id, new_id)| (
+            format!("{}{}{}", prefix, NAMESPACE_SEPARATOR, old_id),
+            format!("{}{}{}", prefix, NAMESPACE_SEPARATOR, new_id),
+        )).collect();
+        Library { components, aliases }
+    }
+    /// Returns the library with the same order hint applied to every
+    /// component, such as to place its categories after those of another.
+    pub fn with_order(self, order: i32) -> Library {
+        let components = self.components.into_iter().map(|(id, entry)| {
+            let mut metadata = (*entry.metadata).clone();
+            metadata.order = order;
+            (id, ComponentEntry {
+                metadata: Arc::new(metadata),
+                ..entry
+            })
+        }).collect();
+        Library { components, aliases: self.aliases }
+    }
+    /// Adds the components from another library, placed in the namespace
+    /// `prefix`. Fails without adding anything if any of the ids collide.
+    pub fn merge_with_prefix(&mut self, prefix: &str, other: Library) -> Result<(), CollisionError> {
+        self.extend_checked(other.namespaced(prefix))
     }
     pub fn add<F: Fn() -> Box<AnyComponent> + Send + Sync + 'static>(&mut self, metadata: ComponentMetadata, f: F) {
         let id = metadata.id.clone().into_owned();
+        let metadata = Arc::new(metadata);
+        let defaults = Arc::new(ComponentInfo::new(f(), metadata.clone()).initial_properties());
         self.components.insert(id, ComponentEntry {
-            metadata: Arc::new(metadata),
+            metadata,
             factory: Arc::new(f),
+            migrations: Migrations::default(),
+            defaults,
         });
     }
+    /// Registers a migration converting the saved properties of a component
+    /// from `from_version` to the next schema version.
+    pub fn add_migration<F>(&mut self, id: &str, from_version: u32, f: F) -> Result<(), MissingComponentError>
+    where
+        F: Fn(&mut SavedProperties) -> Result<(), String> + Send + Sync + 'static
+    {
+        let id = self.resolve_id(id).to_string();
+        let entry = self.components.get_mut(&id)
+            .ok_or(MissingComponentError { id })?;
+        entry.migrations.insert(from_version, Arc::new(f) as Migration);
+        Ok(())
+    }
+    /// Upgrades properties saved under an older schema version of a
+    /// component to its current version.
+    pub fn migrate(&self, id: &str, version: u32, properties: &mut SavedProperties) -> Result<(), MigrationError> {
+        match self.entry(id) {
+            Some(entry) => entry.migrations.apply(id, version, entry.metadata.schema_version, properties),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A component with no pins or properties, for tests which only need a
+/// library entry to exist.
+#[cfg(test)]
+#[derive(Debug, Clone, crate::component::Component)]
+pub(crate) struct StubComponent {}
+
+#[cfg(test)]
+impl crate::component::Component for StubComponent {
+    fn get_shape(&self) -> crate::component::Shape {
+        crate::component::Shape { width: 2, height: 2, pins: Vec::new(), image_name: "stub".into() }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn stub_component() -> Box<AnyComponent> {
+    Box::new(StubComponent {})
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::libraries::{gates, io};
+
+    #[test]
+    fn namespaces() {
+        let mut library = gates::library();
+        assert!(library.extend_checked(io::library()).is_ok());
+        let error = library.extend_checked(gates::library()).unwrap_err();
+        assert!(error.ids.contains(&"or_gate".to_string()));
+
+        library.merge_with_prefix("extra", gates::library()).unwrap();
+        let gate = library.create("extra/or_gate").unwrap();
+        assert_eq!(gate.metadata().id, "extra/or_gate");
+        assert!(library.contains("or_gate"));
+        assert!(library.merge_with_prefix("extra", gates::library()).is_err());
+    }
+
+    #[test]
+    fn queries() {
+        let mut library = Library::new();
+        for &(id, name, category, order) in &[
+            ("b", "Beta", "Second", 1),
+            ("a", "Alpha", "Second", 2),
+            ("c", "Gamma", "First", 0),
+        ] {
+            library.add(
+                ComponentMetadata::new(id, name, category, "").with_order(order),
+                stub_component
+            );
+        }
+        assert_eq!(library.list_categories(), vec!["First", "Second"]);
+        let ids: Vec<_> = library.list_by_category("Second").iter().map(|m| m.id.clone()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+
+        let ids: Vec<_> = library.search("A").iter().map(|m| m.id.clone()).collect();
+        assert_eq!(ids, vec!["a", "c", "b"]);
+        assert_eq!(library.search("gma")[0].id, "c");
+        assert_eq!(library.search("gm")[0].id, "c");
+        assert!(library.search("z").is_empty());
+
+        let page = library.search_page("a", 1, 1);
+        assert_eq!((page.items.len(), page.total, page.has_more()), (1, 3, true));
+    }
+
+    #[test]
+    fn aliases_and_deprecation() {
+        let mut library = gates::library();
+        assert!(library.add_alias("legacy_and", "nope").is_err());
+        library.add_alias("older_and", "legacy_and").unwrap_err();
+        library.add_alias("legacy_and", "and_gate").unwrap();
+        library.add_alias("oldest_and", "legacy_and").unwrap();
+        assert_eq!(library.alias_of("oldest_and"), Some("and_gate"));
+        assert_eq!(library.alias_of("and_gate"), None);
+        assert!(library.contains("legacy_and"));
+        assert_eq!(library.create("legacy_and").unwrap().metadata().id, "and_gate");
+        assert_eq!(library.get_metadata("oldest_and").unwrap().id, "and_gate");
+
+        // A registered component takes precedence over an alias
+        library.add_alias("and_gate", "or_gate").unwrap();
+        assert_eq!(library.create("and_gate").unwrap().metadata().id, "and_gate");
+        let namespaced = library.clone().namespaced("std");
+        assert_eq!(namespaced.create("std/legacy_and").unwrap().metadata().id, "std/and_gate");
+
+        let metadata = (*library.get_metadata("xor_gate").unwrap()).clone().deprecated();
+        let factory = library.components["xor_gate"].factory.clone();
+        library.add(metadata, move || factory());
+        assert!(library.create("xor_gate").unwrap().metadata().deprecated);
+        assert!(!library.search("xor").iter().any(|m| m.id == "xor_gate"));
+        assert!(!library.list_by_category("Gates").iter().any(|m| m.id == "xor_gate"));
+        assert!(library.list().iter().any(|m| m.id == "xor_gate"));
+    }
+
+    #[test]
+    fn create_with_properties() {
+        let library = gates::library();
+        // Inverting the fourth input is only valid once there are four
+        let properties = json!({ "invert_inputs": [false, false, false, true], "num_inputs": 4, "label": "x" });
+        let gate = library.create_with("and_gate", properties.as_object().unwrap()).unwrap();
+        assert_eq!(gate.get_property("num_inputs"), Some(4.into()));
+        assert_eq!(gate.get_property("invert_inputs"), Some(json!([false, false, false, true])));
+        assert_eq!(gate.label().as_deref(), Some("x"));
+
+        let properties = json!({ "num_inputs": 0, "missing": 1, "label": "y" });
+        match library.create_with("and_gate", properties.as_object().unwrap()) {
+            Err(CreateError::Properties(errors)) => {
+                let mut names: Vec<_> = errors.iter().map(|e| &*e.name).collect();
+                names.sort();
+                assert_eq!(names, vec!["missing", "num_inputs"]);
+            },
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(matches!(library.create_with("nope", &Map::new()), Err(CreateError::Missing(_))));
+    }
+
+    #[test]
+    fn presets() {
+        let library = gates::library();
+        let presets = &library.get_metadata("and_gate").unwrap().presets;
+        assert_eq!(presets.iter().map(|p| &*p.name).collect::<Vec<_>>(), vec!["4 inputs", "8 bits wide"]);
+        let gate = library.create_preset("and_gate", "4 inputs").unwrap();
+        assert_eq!(gate.get_property("num_inputs"), Some(4.into()));
+        assert_eq!(gate.metadata().id, "and_gate");
+        assert!(matches!(library.create_preset("and_gate", "nope"), Err(CreateError::MissingPreset { .. })));
+        assert!(matches!(library.create_preset("nope", "4 inputs"), Err(CreateError::Missing(_))));
+    }
+
+    #[test]
+    fn documentation() {
+        let mut library = gates::library();
+        library.add(
+            ComponentMetadata::new("half_adder", "Half Adder", "Arithmetic", "Adds two bits")
+                .with_tags(vec!["sum"])
+                .with_doc_url("https://example.com/half_adder"),
+            stub_component
+        );
+        assert_eq!(library.search("sum")[0].id, "half_adder");
+        assert_eq!(library.get_metadata("half_adder").unwrap().doc_url.as_deref(), Some("https://example.com/half_adder"));
+        assert_eq!(library.search("formula")[0].id, "expression");
+
+        let docs = library.documentation("expression").unwrap();
+        assert!(docs.documentation.unwrap().contains("becomes an input pin"));
+        assert_eq!(docs.examples.len(), 1);
+        assert_eq!(docs.tags, vec!["formula", "boolean"]);
+        let properties: Vec<_> = docs.properties.iter().map(|p| &*p.property).collect();
+        assert_eq!(properties[..2], ["expression", "num_bits"]);
+        assert!(docs.properties[0].description.is_some());
+        assert_eq!(docs.pins.len(), 4);
+
+        let docs = library.documentation("and_gate").unwrap();
+        assert_eq!(docs.name, "AND Gate");
+        assert!(docs.documentation.is_none());
+        let properties: Vec<_> = docs.properties.iter().map(|p| &*p.property).collect();
+        assert_eq!(properties.last(), Some(&"flipped"));
+        assert!(properties.contains(&"num_inputs"));
+        assert_eq!(docs.pins.len(), 3);
+        assert_eq!(docs.pins.iter().filter(|p| p.direction == PinDirection::Output).count(), 1);
+        assert!(library.documentation("nope").is_err());
+    }
 }
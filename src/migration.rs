@@ -0,0 +1,160 @@
+//! Upgrading saved components whose schemas have changed.
+//!
+//! Each component has a schema version, recorded alongside its properties
+//! when it is saved. When a component's properties change in an
+//! incompatible way, its version is increased and a migration is registered
+//! to convert properties saved under the previous version.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::error::Error;
+use std::sync::Arc;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+
+use crate::circuit::ComponentId;
+
+/// The schema version of components which do not specify one, and of
+/// components saved before versions were recorded.
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+pub(crate) fn initial_schema_version() -> u32 {
+    INITIAL_SCHEMA_VERSION
+}
+
+pub type SavedProperties = BTreeMap<String, serde_json::Value>;
+
+/// Converts the saved properties of a component from one schema version to
+/// the next.
+pub type Migration = Arc<Fn(&mut SavedProperties) -> Result<(), String> + Send + Sync>;
+
+/// A component which was migrated while loading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MigrationRecord {
+    pub component: ComponentId,
+    pub id: String,
+    pub from_version: u32,
+    pub to_version: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum MigrationError {
+    /// The component was saved by a newer version of its library.
+    NewerVersion { id: String, version: u32, supported: u32 },
+    Failed { id: String, version: u32, message: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::NewerVersion { id, version, supported } =>
+                write!(f, "Component `{}` has schema version {}, but only versions up to {} are supported", id, version, supported),
+            MigrationError::Failed { id, version, message } =>
+                write!(f, "Failed to migrate component `{}` from schema version {}: {}", id, version, message),
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+/// The migrations registered for a single component, keyed by the version
+/// they migrate from.
+#[derive(Clone, Default)]
+pub(crate) struct Migrations {
+    steps: BTreeMap<u32, Migration>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.steps.keys()).finish()
+    }
+}
+
+impl Migrations {
+    pub(crate) fn insert(&mut self, from_version: u32, migration: Migration) {
+        self.steps.insert(from_version, migration);
+    }
+    /// Upgrades properties saved under `version` to `current`. Versions
+    /// without a registered migration are assumed to need no changes.
+    pub(crate) fn apply(
+        &self,
+        id: &str,
+        mut version: u32,
+        current: u32,
+        properties: &mut SavedProperties,
+    ) -> Result<(), MigrationError> {
+        if version > current {
+            return Err(MigrationError::NewerVersion { id: id.into(), version, supported: current });
+        }
+        while version < current {
+            if let Some(migration) = self.steps.get(&version) {
+                migration(properties)
+                    .map_err(|message| MigrationError::Failed { id: id.into(), version, message })?;
+            }
+            version += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{Component, Shape};
+    use crate::library::{ComponentMetadata, Library};
+    use crate::persist::{load_with_migrations, LoadError};
+
+    #[derive(Debug, Clone, Component)]
+    struct Widget {
+        #[property(name = "Size")]
+        size: u32,
+    }
+
+    impl Component for Widget {
+        fn get_shape(&self) -> Shape {
+            Shape { width: 2, height: 2, pins: Vec::new(), image_name: "widget".into() }
+        }
+    }
+
+    #[test]
+    fn migrate_on_load() {
+        let mut library = Library::new();
+        let metadata = ComponentMetadata::new("widget", "Widget", "Test", "").with_schema_version(3);
+        library.add(metadata, || Box::new(Widget { size: 1 }));
+        // Version 1 called the property `width`, and version 2 measured it
+        // in half units.
+        library.add_migration("widget", 1, |properties| {
+            let width = properties.remove("width").ok_or("Missing width")?;
+            properties.insert("size".into(), width);
+            Ok(())
+        }).unwrap();
+        library.add_migration("widget", 2, |properties| {
+            let size = properties["size"].as_u64().ok_or("Invalid size")?;
+            properties.insert("size".into(), (size * 2).into());
+            Ok(())
+        }).unwrap();
+        assert!(library.add_migration("gadget", 1, |_| Ok(())).is_err());
+
+        let json = r#"{"version":1,"components":[
+            {"id":0,"component":"widget","x":0,"y":0,"properties":{"width":3}},
+            {"id":1,"component":"widget","x":0,"y":4,"schema_version":2,"properties":{"size":3}},
+            {"id":2,"component":"widget","x":0,"y":8,"schema_version":3,"properties":{"size":3}}
+        ],"wires":[]}"#;
+        let (circuit, records) = load_with_migrations(serde_json::from_str(json).unwrap(), &library).unwrap();
+        let size = |id| circuit.get_component(ComponentId(id)).unwrap().get_property("size");
+        assert_eq!((size(0), size(1), size(2)), (Some(6.into()), Some(6.into()), Some(3.into())));
+        assert_eq!(records, vec![
+            MigrationRecord { component: ComponentId(0), id: "widget".into(), from_version: 1, to_version: 3 },
+            MigrationRecord { component: ComponentId(1), id: "widget".into(), from_version: 2, to_version: 3 },
+        ]);
+
+        let json = r#"{"version":1,"components":[
+            {"id":0,"component":"widget","x":0,"y":0,"schema_version":4,"properties":{}}
+        ],"wires":[]}"#;
+        match load_with_migrations(serde_json::from_str(json).unwrap(), &library) {
+            Err(LoadError::Migration { error: MigrationError::NewerVersion { version: 4, .. }, .. }) => {},
+            other => panic!("Unexpected result: {:?}", other.map(|(_, records)| records)),
+        }
+    }
+}
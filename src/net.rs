@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::circuit::{Circuit, ComponentId, WireId};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DisjointSet {
+    parent: Vec<usize>,
+}
+
+impl DisjointSet {
+    pub(crate) fn add(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.parent.len() - 1
+    }
+    pub(crate) fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+    pub(crate) fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parent[a] = b;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetId(pub usize);
+
+impl fmt::Display for NetId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "n{}", self.0)
+    }
+}
+
+/// Identifies a single pin of a component in a circuit.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PinRef {
+    pub component: ComponentId,
+    pub pin: usize,
+}
+
+impl PinRef {
+    pub fn new(component: ComponentId, pin: usize) -> Self {
+        Self { component, pin }
+    }
+}
+
+/// A group of pins and wires which are geometrically connected.
+#[derive(Debug, Clone)]
+pub struct Net {
+    pub id: NetId,
+    pub pins: Vec<(PinRef, u32)>,
+    pub wires: Vec<WireId>,
+}
+
+impl Net {
+    /// The width of the widest pin on the net, or zero if there are no pins.
+    pub fn width(&self) -> u32 {
+        self.pins.iter().map(|&(_, bits)| bits).max().unwrap_or(0)
+    }
+    /// Returns the pins and their widths if they do not all agree.
+    pub fn width_conflict(&self) -> Option<WidthConflict> {
+        WidthConflict::find(self.id, self.pins.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WidthConflict {
+    pub net: NetId,
+    pub pins: Vec<(PinRef, u32)>,
+    /// The distinct widths of the pins, in increasing order.
+    pub widths: Vec<u32>,
+}
+
+impl WidthConflict {
+    /// Returns a conflict if the pins are not all the same width.
+    pub fn find(net: NetId, pins: Vec<(PinRef, u32)>) -> Option<Self> {
+        let mut widths: Vec<_> = pins.iter().map(|&(_, bits)| bits).collect();
+        widths.sort();
+        widths.dedup();
+        if widths.len() > 1 {
+            Some(WidthConflict { net, pins, widths })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for WidthConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pins of different widths are connected by net {}:", self.net)?;
+        for &(pin, bits) in &self.pins {
+            write!(f, " {}.{} ({} bits)", pin.component, pin.pin, bits)?;
+        }
+        Ok(())
+    }
+}
+
+/// The nets formed by the wires of a circuit. Wires are connected to each
+/// other and to pins wherever an endpoint or pin lies on the wire: wires
+/// which merely cross are not connected.
+///
+/// This only considers geometry, so components such as splitters and
+/// tunnels which connect individual bits of different nets are handled by
+/// the simulator.
+#[derive(Debug, Clone, Default)]
+pub struct Connectivity {
+    nets: Vec<Net>,
+    pins: HashMap<PinRef, NetId>,
+    wires: HashMap<WireId, NetId>,
+    points: HashMap<(i32, i32), NetId>,
+}
+
+impl Connectivity {
+    pub fn build(circuit: &Circuit) -> Self {
+        let mut set = DisjointSet::default();
+        let mut indices = HashMap::new();
+        let mut intern = |set: &mut DisjointSet, p: (i32, i32)| {
+            *indices.entry(p).or_insert_with(|| set.add())
+        };
+
+        let mut pins = Vec::new();
+        for (id, info) in circuit.components().filter(|(_, info)| !info.is_annotation()) {
+            let (x, y) = info.position();
+            for (i, pin) in info.get_shape().pins.iter().enumerate() {
+                let point = intern(&mut set, (x + pin.x, y + pin.y));
+                pins.push((PinRef { component: id, pin: i }, pin.bits, point));
+            }
+        }
+        let wires: Vec<_> = circuit.wires()
+            .map(|(id, wire)| (id, *wire, intern(&mut set, wire.start), intern(&mut set, wire.end)))
+            .collect();
+        // Index the points by column and by row, so that each wire only
+        // checks the points within its bounds along its shorter side
+        let by_column: BTreeMap<_, _> = indices.iter().map(|(&p, &i)| (p, i)).collect();
+        let by_row: BTreeMap<_, _> = indices.iter().map(|(&(x, y), &i)| ((y, x), i)).collect();
+        for &(_, wire, start, end) in &wires {
+            set.union(start, end);
+            let (x0, x1) = (wire.start.0.min(wire.end.0), wire.start.0.max(wire.end.0));
+            let (y0, y1) = (wire.start.1.min(wire.end.1), wire.start.1.max(wire.end.1));
+            let points: Vec<_> = if x1 - x0 <= y1 - y0 {
+                by_column.range((x0, y0)..=(x1, y1)).filter(|(&p, _)| wire.contains(p)).map(|(_, &i)| i).collect()
+            } else {
+                by_row.range((y0, x0)..=(y1, x1)).filter(|(&(y, x), _)| wire.contains((x, y))).map(|(_, &i)| i).collect()
+            };
+            for i in points {
+                set.union(start, i);
+            }
+        }
+
+        // Number the nets in order of first appearance, so that the ids are
+        // stable for a given circuit.
+        let mut result = Connectivity::default();
+        let mut roots = HashMap::new();
+        let mut net_of = |result: &mut Connectivity, set: &mut DisjointSet, point: usize| {
+            let root = set.find(point);
+            *roots.entry(root).or_insert_with(|| {
+                let id = NetId(result.nets.len());
+                result.nets.push(Net { id, pins: Vec::new(), wires: Vec::new() });
+                id
+            })
+        };
+        for (pin, bits, point) in pins {
+            let net = net_of(&mut result, &mut set, point);
+            result.nets[net.0].pins.push((pin, bits));
+            result.pins.insert(pin, net);
+        }
+        for (id, _, start, _) in wires {
+            let net = net_of(&mut result, &mut set, start);
+            result.nets[net.0].wires.push(id);
+            result.wires.insert(id, net);
+        }
+        for (p, i) in indices {
+            let net = net_of(&mut result, &mut set, i);
+            result.points.insert(p, net);
+        }
+        result
+    }
+    pub fn nets(&self) -> &[Net] {
+        &self.nets
+    }
+    pub fn net(&self, id: NetId) -> Option<&Net> {
+        self.nets.get(id.0)
+    }
+    pub fn net_of_pin(&self, pin: PinRef) -> Option<NetId> {
+        self.pins.get(&pin).cloned()
+    }
+    pub fn net_of_wire(&self, wire: WireId) -> Option<NetId> {
+        self.wires.get(&wire).cloned()
+    }
+    /// Returns the net with a pin or wire endpoint at the given point.
+    pub fn net_at(&self, point: (i32, i32)) -> Option<NetId> {
+        self.points.get(&point).cloned()
+    }
+    pub fn width_conflicts(&self) -> Vec<WidthConflict> {
+        self.nets.iter().filter_map(|net| net.width_conflict()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::libraries::wiring;
+
+    #[test]
+    fn connect_pins_and_wires() {
+        let lib = wiring::library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("constant").unwrap(), 0, 0);
+        let b = circuit.add_component(lib.create("constant").unwrap(), 10, 0);
+        let pin_a = PinRef { component: a, pin: 0 };
+        let pin_b = PinRef { component: b, pin: 0 };
+        let (x, y) = (2, 1);
+        assert_eq!(circuit.get_component(a).unwrap().get_shape().pins[0].x, x);
+
+        let connectivity = Connectivity::build(&circuit);
+        assert_ne!(connectivity.net_of_pin(pin_a), connectivity.net_of_pin(pin_b));
+
+        // The second wire meets the middle of the first, and the last
+        // crosses it without connecting.
+        let w1 = circuit.add_wire(Wire::new((x, y), (x, y + 4)));
+        let w2 = circuit.add_wire(Wire::new((x, y + 2), (x + 10, y + 2)));
+        circuit.add_wire(Wire::new((x + 10, y + 2), (x + 10, y)));
+        let w4 = circuit.add_wire(Wire::new((x - 1, y + 3), (x + 1, y + 3)));
+        let connectivity = Connectivity::build(&circuit);
+        let net = connectivity.net_of_pin(pin_a).unwrap();
+        assert_eq!(connectivity.net_of_pin(pin_b), Some(net));
+        assert_eq!(connectivity.net_of_wire(w1), Some(net));
+        assert_eq!(connectivity.net_of_wire(w2), Some(net));
+        assert_ne!(connectivity.net_of_wire(w4), Some(net));
+        assert_eq!(connectivity.net_at((x + 10, y + 2)), Some(net));
+        assert!(connectivity.width_conflicts().is_empty());
+
+        circuit.get_component_mut(b).unwrap().set_property("num_bits", 4.into()).unwrap();
+        let conflicts = Connectivity::build(&circuit).width_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].pins, vec![(pin_a, 1), (pin_b, 4)]);
+        assert_eq!(conflicts[0].widths, vec![1, 4]);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::library::{Library, ComponentMetadata};
+
+pub const DEFAULT_RECENT_CAPACITY: usize = 10;
+
+/// How the recently-used list is ordered when building a `PaletteView`.
+/// Eviction from the stored list is always least-recently-used, regardless
+/// of which ordering is chosen for display.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecentOrder {
+    /// Most recently used first.
+    Recency,
+    /// Highest use count first, ties broken by recency.
+    Frequency,
+}
+
+impl Default for RecentOrder {
+    fn default() -> Self {
+        RecentOrder::Recency
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecentEntry {
+    pub id: String,
+    pub uses: u32,
+}
+
+/// Per-user palette state. Ids are stored as plain strings and are never
+/// pruned against a library automatically, so state saved against one set
+/// of libraries can be loaded against another: entries for components that
+/// are not present are simply omitted from the view.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "SavedPaletteState")]
+pub struct PaletteState {
+    favourites: Vec<String>,
+    // Most recently used first.
+    recent: Vec<RecentEntry>,
+    capacity: usize,
+    order: RecentOrder,
+}
+
+// The state as it was saved, which may list more recent entries than its
+// capacity allows
+#[derive(Deserialize)]
+struct SavedPaletteState {
+    #[serde(default)]
+    favourites: Vec<String>,
+    #[serde(default)]
+    recent: Vec<RecentEntry>,
+    #[serde(default = "default_capacity")]
+    capacity: usize,
+    #[serde(default)]
+    order: RecentOrder,
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_RECENT_CAPACITY
+}
+
+impl From<SavedPaletteState> for PaletteState {
+    fn from(saved: SavedPaletteState) -> Self {
+        let mut state = Self {
+            favourites: saved.favourites,
+            recent: saved.recent,
+            capacity: saved.capacity,
+            order: saved.order,
+        };
+        state.recent.truncate(state.capacity);
+        state
+    }
+}
+
+impl Default for PaletteState {
+    fn default() -> Self {
+        Self {
+            favourites: Vec::new(),
+            recent: Vec::new(),
+            capacity: DEFAULT_RECENT_CAPACITY,
+            order: RecentOrder::default(),
+        }
+    }
+}
+
+impl PaletteState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.recent.truncate(capacity);
+    }
+    pub fn order(&self) -> RecentOrder {
+        self.order
+    }
+    pub fn set_order(&mut self, order: RecentOrder) {
+        self.order = order;
+    }
+    pub fn favourites(&self) -> &[String] {
+        &self.favourites
+    }
+    pub fn recent(&self) -> &[RecentEntry] {
+        &self.recent
+    }
+    pub fn is_pinned(&self, id: &str) -> bool {
+        self.favourites.iter().any(|f| f == id)
+    }
+    pub fn record_use(&mut self, id: &str) {
+        let uses = match self.recent.iter().position(|e| e.id == id) {
+            Some(index) => self.recent.remove(index).uses.saturating_add(1),
+            None => 1,
+        };
+        self.recent.insert(0, RecentEntry { id: id.into(), uses });
+        self.recent.truncate(self.capacity);
+    }
+    /// Favourites keep the order in which they were pinned.
+    pub fn pin(&mut self, id: &str) {
+        if !self.is_pinned(id) {
+            self.favourites.push(id.into());
+        }
+    }
+    pub fn unpin(&mut self, id: &str) {
+        self.favourites.retain(|f| f != id);
+    }
+    pub fn clear_recent(&mut self) {
+        self.recent.clear();
+    }
+    /// Explicitly forget ids which are not present in `library`.
+    pub fn prune(&mut self, library: &Library) {
+        self.favourites.retain(|id| library.contains(id));
+        self.recent.retain(|e| library.contains(&e.id));
+    }
+    fn ordered_recent(&self) -> Vec<&RecentEntry> {
+        let mut result: Vec<_> = self.recent.iter().collect();
+        if self.order == RecentOrder::Frequency {
+            // Stable sort, so equal counts retain their recency order
+            result.sort_by(|a, b| b.uses.cmp(&a.uses));
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PaletteCategory {
+    pub name: String,
+    pub components: Vec<Arc<ComponentMetadata>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PaletteView {
+    pub favourites: Vec<Arc<ComponentMetadata>>,
+    pub recent: Vec<Arc<ComponentMetadata>>,
+    pub categories: Vec<PaletteCategory>,
+}
+
+impl Library {
+    pub fn palette_view(&self, state: &PaletteState) -> PaletteView {
+        let mut seen = BTreeSet::new();
+
+        let favourites = state.favourites.iter()
+            .filter(|id| seen.insert(id.as_str()))
+            .filter_map(|id| self.get_metadata(id))
+            .collect();
+
+        let recent = state.ordered_recent().into_iter()
+            .filter(|e| seen.insert(e.id.as_str()))
+            .filter_map(|e| self.get_metadata(&e.id))
+            .collect();
+
+        let categories = self.list_categories().into_iter()
+            .map(|name| PaletteCategory { components: self.list_by_category(&name), name })
+            .collect();
+
+        PaletteView { favourites, recent, categories }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::stub_component;
+
+    fn library(ids: &[&'static str]) -> Library {
+        let mut result = Library::new();
+        for &id in ids {
+            result.add(
+                ComponentMetadata::new(id, id, "Test", ""),
+                stub_component
+            );
+        }
+        result
+    }
+
+    fn ids(items: &[Arc<ComponentMetadata>]) -> Vec<&str> {
+        items.iter().map(|m| &*m.id).collect()
+    }
+
+    #[test]
+    fn recent_is_ordered_by_recency() {
+        let lib = library(&["a", "b", "c"]);
+        let mut state = PaletteState::new();
+        state.record_use("a");
+        state.record_use("b");
+        state.record_use("a");
+        state.record_use("c");
+        let view = lib.palette_view(&state);
+        assert_eq!(ids(&view.recent), vec!["c", "a", "b"]);
+        assert_eq!(state.recent()[1], RecentEntry { id: "a".into(), uses: 2 });
+    }
+
+    #[test]
+    fn frequency_order_breaks_ties_by_recency() {
+        let lib = library(&["a", "b", "c", "d"]);
+        let mut state = PaletteState::new();
+        state.set_order(RecentOrder::Frequency);
+        for id in &["a", "b", "b", "c", "d", "c"] {
+            state.record_use(id);
+        }
+        let view = lib.palette_view(&state);
+        assert_eq!(ids(&view.recent), vec!["c", "b", "d", "a"]);
+    }
+
+    #[test]
+    fn capacity_evicts_least_recently_used() {
+        let mut state = PaletteState::with_capacity(2);
+        state.record_use("a");
+        state.record_use("a");
+        state.record_use("b");
+        state.record_use("c");
+        let recent: Vec<_> = state.recent().iter().map(|e| &*e.id).collect();
+        assert_eq!(recent, vec!["c", "b"]);
+    }
+
+    #[test]
+    fn favourites_are_excluded_from_recent() {
+        let lib = library(&["a", "b", "c"]);
+        let mut state = PaletteState::new();
+        state.record_use("a");
+        state.record_use("b");
+        state.pin("b");
+        state.pin("c");
+        state.pin("b");
+        let view = lib.palette_view(&state);
+        assert_eq!(ids(&view.favourites), vec!["b", "c"]);
+        assert_eq!(ids(&view.recent), vec!["a"]);
+
+        state.unpin("b");
+        let view = lib.palette_view(&state);
+        assert_eq!(ids(&view.favourites), vec!["c"]);
+        assert_eq!(ids(&view.recent), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn missing_components_are_dropped_but_remembered() {
+        let mut state = PaletteState::new();
+        state.record_use("a");
+        state.record_use("gone");
+        state.pin("removed");
+
+        let view = library(&["a"]).palette_view(&state);
+        assert!(view.favourites.is_empty());
+        assert_eq!(ids(&view.recent), vec!["a"]);
+
+        let view = library(&["a", "gone", "removed"]).palette_view(&state);
+        assert_eq!(ids(&view.favourites), vec!["removed"]);
+        assert_eq!(ids(&view.recent), vec!["gone", "a"]);
+    }
+
+    #[test]
+    fn state_survives_library_changes_across_sessions() {
+        let mut state = PaletteState::new();
+        state.set_order(RecentOrder::Frequency);
+        state.record_use("a");
+        state.record_use("b");
+        state.pin("c");
+        let saved = serde_json::to_string(&state).unwrap();
+
+        let restored: PaletteState = serde_json::from_str(&saved).unwrap();
+        assert_eq!(restored.order(), RecentOrder::Frequency);
+        let view = library(&["b", "c", "new"]).palette_view(&restored);
+        assert_eq!(ids(&view.favourites), vec!["c"]);
+        assert_eq!(ids(&view.recent), vec!["b"]);
+        assert_eq!(ids(&view.categories[0].components), vec!["b", "c", "new"]);
+
+        let partial: PaletteState = serde_json::from_str(r#"{"favourites":["b"]}"#).unwrap();
+        assert_eq!(partial.capacity(), DEFAULT_RECENT_CAPACITY);
+        assert!(partial.recent().is_empty());
+
+        let over: PaletteState = serde_json::from_str(r#"{"recent":[{"id":"a","uses":1},{"id":"b","uses":2}],"capacity":1}"#).unwrap();
+        assert_eq!(over.recent(), &[RecentEntry { id: "a".into(), uses: 1 }]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Parameters of generic subcircuits, such as the width of an adder.
+//!
+//! A circuit declares its parameters, and binds properties of its components
+//! to integer expressions over them, such as `WIDTH - 1`. Each instance of
+//! the circuit gives its own values for the parameters, and simulates a copy
+//! of the circuit with the bound properties set from those values.
+//!
+//! Expressions may use whole numbers, parameter names, parentheses, unary
+//! minus and the operators `+`, `-`, `*`, `/` and `%`, which have their
+//! usual precedence. Division rounds towards zero.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::error::Error;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::PropertyError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    pub default: i64,
+    pub min: i64,
+    pub max: i64,
+}
+
+/// A property of a component in the circuit whose value is computed from the
+/// parameters.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub component: ComponentId,
+    pub property: String,
+    pub expression: String,
+}
+
+/// The parameters declared by a circuit, and the properties bound to them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Parameters {
+    pub declared: Vec<Parameter>,
+    pub bindings: Vec<Binding>,
+}
+
+impl Parameters {
+    pub fn is_empty(&self) -> bool {
+        self.declared.is_empty() && self.bindings.is_empty()
+    }
+    pub fn get(&self, name: &str) -> Option<&Parameter> {
+        self.declared.iter().find(|p| p.name == name)
+    }
+    /// The default value of every parameter.
+    pub fn defaults(&self) -> BTreeMap<String, i64> {
+        self.declared.iter().map(|p| (p.name.clone(), p.default)).collect()
+    }
+    /// Binds a property to an expression, replacing any previous binding.
+    pub fn bind(&mut self, component: ComponentId, property: &str, expression: &str) {
+        self.unbind(component, property);
+        self.bindings.push(Binding { component, property: property.into(), expression: expression.into() });
+    }
+    pub fn unbind(&mut self, component: ComponentId, property: &str) -> bool {
+        let len = self.bindings.len();
+        self.bindings.retain(|b| b.component != component || b.property != property);
+        self.bindings.len() != len
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    /// The offset in characters of the problem within the expression.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ExpressionError {
+    fn new<S: Into<String>>(position: usize, message: S) -> Self {
+        ExpressionError { position, message: message.into() }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid expression at character {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for ExpressionError {}
+
+struct Evaluator<'a> {
+    chars: Vec<char>,
+    index: usize,
+    values: &'a BTreeMap<String, i64>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.index).map_or(false, |c| c.is_whitespace()) {
+            self.index += 1;
+        }
+    }
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.index) == Some(&c) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn error<T, S: Into<String>>(&self, message: S) -> Result<T, ExpressionError> {
+        Err(ExpressionError::new(self.index, message))
+    }
+    fn overflow<T>(&self) -> Result<T, ExpressionError> {
+        self.error("Arithmetic overflow")
+    }
+    fn sum(&mut self) -> Result<i64, ExpressionError> {
+        let mut result = self.product()?;
+        loop {
+            let value = if self.eat('+') {
+                result.checked_add(self.product()?)
+            } else if self.eat('-') {
+                result.checked_sub(self.product()?)
+            } else {
+                return Ok(result);
+            };
+            result = match value {
+                Some(value) => value,
+                None => return self.overflow(),
+            };
+        }
+    }
+    fn product(&mut self) -> Result<i64, ExpressionError> {
+        let mut result = self.unary()?;
+        loop {
+            let value = if self.eat('*') {
+                result.checked_mul(self.unary()?)
+            } else if self.eat('/') {
+                result.checked_div(self.divisor()?)
+            } else if self.eat('%') {
+                result.checked_rem(self.divisor()?)
+            } else {
+                return Ok(result);
+            };
+            result = match value {
+                Some(value) => value,
+                None => return self.overflow(),
+            };
+        }
+    }
+    fn divisor(&mut self) -> Result<i64, ExpressionError> {
+        let start = self.index;
+        match self.unary()? {
+            0 => Err(ExpressionError::new(start, "Division by zero")),
+            value => Ok(value),
+        }
+    }
+    fn unary(&mut self) -> Result<i64, ExpressionError> {
+        if self.eat('-') {
+            return match self.unary()?.checked_neg() {
+                Some(value) => Ok(value),
+                None => self.overflow(),
+            };
+        }
+        if self.eat('(') {
+            let result = self.sum()?;
+            if !self.eat(')') {
+                return self.error("Expected `)`");
+            }
+            return Ok(result);
+        }
+        self.skip_whitespace();
+        let start = self.index;
+        let len = self.chars[start..].iter().take_while(|&&c| c.is_alphanumeric() || c == '_').count();
+        let word: String = self.chars[start..start + len].iter().collect();
+        self.index += len;
+        match word.chars().next() {
+            None => self.error("Expected a number or parameter"),
+            Some(c) if c.is_ascii_digit() => word.parse()
+                .map_err(|_| ExpressionError::new(start, format!("Invalid number `{}`", word))),
+            Some(_) => self.values.get(&word).cloned()
+                .ok_or_else(|| ExpressionError::new(start, format!("Unknown parameter `{}`", word))),
+        }
+    }
+}
+
+/// Evaluates an expression, given the value of each parameter.
+pub fn evaluate(expression: &str, values: &BTreeMap<String, i64>) -> Result<i64, ExpressionError> {
+    let mut evaluator = Evaluator { chars: expression.chars().collect(), index: 0, values };
+    let result = evaluator.sum()?;
+    evaluator.skip_whitespace();
+    if evaluator.index < evaluator.chars.len() {
+        return evaluator.error(format!("Unexpected `{}`", evaluator.chars[evaluator.index]));
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone)]
+pub enum ElaborationError {
+    Expression {
+        component: ComponentId,
+        property: String,
+        error: ExpressionError,
+    },
+    Property {
+        component: ComponentId,
+        error: PropertyError,
+    },
+}
+
+impl fmt::Display for ElaborationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElaborationError::Expression { component, property, error } =>
+                write!(f, "Property `{}` of component {}: {}", property, component, error),
+            ElaborationError::Property { component, error } =>
+                write!(f, "Component {}: {}", component, error),
+        }
+    }
+}
+
+impl Error for ElaborationError {}
+
+/// A copy of the circuit with each bound property set from the given
+/// parameter values. Parameters without a value take their default.
+pub fn elaborate(circuit: &Circuit, values: &BTreeMap<String, i64>) -> Result<Circuit, ElaborationError> {
+    let parameters = circuit.parameters();
+    let mut all_values = parameters.defaults();
+    all_values.extend(values.iter().map(|(name, &value)| (name.clone(), value)));
+    let mut result = circuit.clone();
+    for binding in &parameters.bindings {
+        let value = evaluate(&binding.expression, &all_values).map_err(|error| ElaborationError::Expression {
+            component: binding.component,
+            property: binding.property.clone(),
+            error,
+        })?;
+        if let Some(info) = result.get_component_mut(binding.component) {
+            info.set_property(&binding.property, value.into())
+                .map_err(|error| ElaborationError::Property { component: binding.component, error })?;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions() {
+        let values: BTreeMap<_, _> = vec![("WIDTH".to_string(), 8)].into_iter().collect();
+        assert_eq!(evaluate("WIDTH-1", &values), Ok(7));
+        assert_eq!(evaluate(" 2 * (WIDTH + 1) % 5 ", &values), Ok(3));
+        assert_eq!(evaluate("-WIDTH / 3", &values), Ok(-2));
+        assert_eq!(evaluate("WIDTH - 2 - 3", &values), Ok(3));
+        assert_eq!(evaluate("DEPTH", &values).unwrap_err().position, 0);
+        assert_eq!(evaluate("WIDTH / (WIDTH - 8)", &values).unwrap_err().message, "Division by zero");
+        assert_eq!(evaluate("(WIDTH", &values).unwrap_err().position, 6);
+        assert!(evaluate("WIDTH 2", &values).is_err());
+        assert!(evaluate("9223372036854775807 + 1", &values).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+//! A compact binary encoding of project files.
+//!
+//! The file starts with `MAGIC` and the binary format version, followed by
+//! a table of the strings used as component ids and property names, so that
+//! each is only stored once. Integers are stored as LEB128 varints, with
+//! signed values zigzag encoded first. Wire ends are stored relative to
+//! their starts, as most wires are short. Since version 2, the wires are
+//! followed by the circuit's symbol appearance, and since version 3 by its
+//! parameters, each encoded as a JSON value. Since version 4, these are
+//! followed by the uuids of the components and wires, as raw bytes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::error::Error;
+
+use serde_json::{self, Value};
+use uuid::Uuid;
+
+use crate::circuit::{ComponentId, Wire};
+use crate::parameters::Parameters;
+use super::{CircuitFile, ComponentRecord};
+
+/// The first bytes of every binary project file.
+pub const MAGIC: &[u8; 4] = b"TNRB";
+/// The version of the binary framing, independent of `FORMAT_VERSION`.
+pub const BINARY_VERSION: u32 = 4;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UNSIGNED: u8 = 3;
+const SIGNED: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const OBJECT: u8 = 8;
+
+/// The deepest nesting of arrays and objects which will be decoded, so that
+/// a malicious file can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryError {
+    /// The offset of the byte which could not be decoded.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid binary project file at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl Error for BinaryError {}
+
+/// Returns true if the bytes start with the binary format's magic number.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl Writer {
+    fn byte(&mut self, b: u8) {
+        self.out.push(b);
+    }
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.out.push(v as u8);
+    }
+    fn signed(&mut self, v: i64) {
+        self.varint(zigzag(v));
+    }
+    fn uuid(&mut self, uuid: &Uuid) {
+        self.out.extend_from_slice(uuid.as_bytes());
+    }
+    fn string(&mut self, s: &str) {
+        self.varint(s.len() as u64);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+    // Writes a reference to a string in the string table
+    fn interned(&mut self, s: &str) {
+        let next = self.strings.len() as u64;
+        let index = *self.indices.entry(s.into()).or_insert(next);
+        if index == next {
+            self.strings.push(s.into());
+        }
+        self.varint(index);
+    }
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.byte(NULL),
+            Value::Bool(false) => self.byte(FALSE),
+            Value::Bool(true) => self.byte(TRUE),
+            Value::Number(n) => if let Some(u) = n.as_u64() {
+                self.byte(UNSIGNED);
+                self.varint(u);
+            } else if let Some(i) = n.as_i64() {
+                self.byte(SIGNED);
+                self.signed(i);
+            } else {
+                self.byte(FLOAT);
+                let f = n.as_f64().unwrap_or(0.0);
+                self.out.extend_from_slice(&f.to_bits().to_le_bytes());
+            },
+            Value::String(s) => {
+                self.byte(STRING);
+                self.string(s);
+            },
+            Value::Array(items) => {
+                self.byte(ARRAY);
+                self.varint(items.len() as u64);
+                for item in items {
+                    self.value(item);
+                }
+            },
+            Value::Object(map) => {
+                self.byte(OBJECT);
+                self.varint(map.len() as u64);
+                for (key, item) in map {
+                    self.interned(key);
+                    self.value(item);
+                }
+            },
+        }
+    }
+}
+
+/// Encodes a project file in the binary format.
+pub fn encode(file: &CircuitFile) -> Vec<u8> {
+    let mut body = Writer::default();
+    body.varint(file.version as u64);
+    body.varint(file.components.len() as u64);
+    for record in &file.components {
+        body.varint(record.id.0);
+        body.interned(&record.component);
+        body.signed(record.x as i64);
+        body.signed(record.y as i64);
+        body.varint(record.schema_version as u64);
+        body.varint(record.properties.len() as u64);
+        for (name, value) in &record.properties {
+            body.interned(name);
+            body.value(value);
+        }
+    }
+    body.varint(file.wires.len() as u64);
+    for wire in &file.wires {
+        body.signed(wire.start.0 as i64);
+        body.signed(wire.start.1 as i64);
+        body.signed(wire.end.0 as i64 - wire.start.0 as i64);
+        body.signed(wire.end.1 as i64 - wire.start.1 as i64);
+    }
+    let appearance = serde_json::to_value(&file.appearance).expect("Appearance should always be serializable");
+    body.value(&appearance);
+    let parameters = serde_json::to_value(&file.parameters).expect("Parameters should always be serializable");
+    body.value(&parameters);
+    for record in &file.components {
+        match record.uuid {
+            Some(ref uuid) => {
+                body.byte(TRUE);
+                body.uuid(uuid);
+            },
+            None => body.byte(FALSE),
+        }
+    }
+    body.varint(file.wire_uuids.len() as u64);
+    for uuid in &file.wire_uuids {
+        body.uuid(uuid);
+    }
+
+    // The string table is only complete once the body has been written
+    let mut header = Writer::default();
+    header.out.extend_from_slice(MAGIC);
+    header.varint(BINARY_VERSION as u64);
+    header.varint(body.strings.len() as u64);
+    for s in &body.strings {
+        header.string(s);
+    }
+    header.out.extend_from_slice(&body.out);
+    header.out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    strings: Vec<String>,
+}
+
+impl<'a> Reader<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, BinaryError> {
+        Err(BinaryError { offset: self.offset, message: message.into() })
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BinaryError> {
+        if self.bytes.len() - self.offset < len {
+            return self.error("Unexpected end of file");
+        }
+        let result = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(result)
+    }
+    fn byte(&mut self) -> Result<u8, BinaryError> {
+        Ok(self.take(1)?[0])
+    }
+    fn varint(&mut self) -> Result<u64, BinaryError> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        self.error("Integer is too large")
+    }
+    fn uuid(&mut self) -> Result<Uuid, BinaryError> {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(self.take(16)?);
+        Ok(Uuid::from_bytes(bytes))
+    }
+    fn u32(&mut self) -> Result<u32, BinaryError> {
+        let v = self.varint()?;
+        if v > u32::max_value() as u64 {
+            return self.error("Integer is too large");
+        }
+        Ok(v as u32)
+    }
+    // Reads a coordinate stored relative to `base`
+    fn coordinate(&mut self, base: i32) -> Result<i32, BinaryError> {
+        let v = (base as i64).wrapping_add(unzigzag(self.varint()?));
+        if v < i32::min_value() as i64 || v > i32::max_value() as i64 {
+            return self.error("Coordinate is out of range");
+        }
+        Ok(v as i32)
+    }
+    // Reads a length, checking it against the bytes remaining so that a
+    // corrupt file cannot cause a huge allocation.
+    fn len(&mut self) -> Result<usize, BinaryError> {
+        let len = self.varint()?;
+        if len > (self.bytes.len() - self.offset) as u64 {
+            return self.error("Length exceeds the size of the file");
+        }
+        Ok(len as usize)
+    }
+    fn string(&mut self) -> Result<String, BinaryError> {
+        let len = self.len()?;
+        let start = self.offset;
+        match String::from_utf8(self.take(len)?.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(BinaryError { offset: start, message: "String is not valid UTF-8".into() }),
+        }
+    }
+    fn interned(&mut self) -> Result<String, BinaryError> {
+        let index = self.varint()?;
+        match self.strings.get(index as usize) {
+            Some(s) => Ok(s.clone()),
+            None => self.error("String index is out of range"),
+        }
+    }
+    fn value(&mut self, depth: usize) -> Result<Value, BinaryError> {
+        Ok(match self.byte()? {
+            NULL => Value::Null,
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            UNSIGNED => self.varint()?.into(),
+            SIGNED => unzigzag(self.varint()?).into(),
+            FLOAT => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(self.take(8)?);
+                match serde_json::Number::from_f64(f64::from_bits(u64::from_le_bytes(bits))) {
+                    Some(n) => Value::Number(n),
+                    None => return self.error("Number is not finite"),
+                }
+            },
+            STRING => Value::String(self.string()?),
+            ARRAY | OBJECT if depth >= MAX_DEPTH => {
+                self.offset -= 1;
+                return self.error("Values are nested too deeply");
+            },
+            ARRAY => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            },
+            OBJECT => {
+                let len = self.len()?;
+                let mut map = serde_json::Map::new();
+                for _ in 0..len {
+                    let key = self.interned()?;
+                    map.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(map)
+            },
+            _ => {
+                self.offset -= 1;
+                return self.error("Unknown value tag");
+            },
+        })
+    }
+}
+
+/// Decodes a project file from the binary format.
+pub fn decode(bytes: &[u8]) -> Result<CircuitFile, BinaryError> {
+    let mut reader = Reader { bytes, offset: 0, strings: Vec::new() };
+    if !is_binary(bytes) {
+        return reader.error("Missing header");
+    }
+    reader.offset = MAGIC.len();
+    let binary_version = reader.u32()?;
+    if binary_version > BINARY_VERSION {
+        return reader.error(&format!("Unsupported binary version {}", binary_version));
+    }
+    let num_strings = reader.len()?;
+    for _ in 0..num_strings {
+        let s = reader.string()?;
+        reader.strings.push(s);
+    }
+
+    let version = reader.u32()?;
+    let num_components = reader.len()?;
+    let mut components = Vec::with_capacity(num_components);
+    for _ in 0..num_components {
+        let id = ComponentId(reader.varint()?);
+        let component = reader.interned()?;
+        let x = reader.coordinate(0)?;
+        let y = reader.coordinate(0)?;
+        let schema_version = reader.u32()?;
+        let mut properties = BTreeMap::new();
+        for _ in 0..reader.len()? {
+            let name = reader.interned()?;
+            properties.insert(name, reader.value(0)?);
+        }
+        components.push(ComponentRecord { id, component, x, y, schema_version, properties, uuid: None });
+    }
+    let num_wires = reader.len()?;
+    let mut wires = Vec::with_capacity(num_wires);
+    for _ in 0..num_wires {
+        let start = (reader.coordinate(0)?, reader.coordinate(0)?);
+        let end = (reader.coordinate(start.0)?, reader.coordinate(start.1)?);
+        wires.push(Wire::new(start, end));
+    }
+    let appearance = if binary_version >= 2 {
+        let offset = reader.offset;
+        serde_json::from_value(reader.value(0)?)
+            .map_err(|e| BinaryError { offset, message: format!("Invalid symbol appearance: {}", e) })?
+    } else {
+        None
+    };
+    let parameters = if binary_version >= 3 {
+        let offset = reader.offset;
+        serde_json::from_value(reader.value(0)?)
+            .map_err(|e| BinaryError { offset, message: format!("Invalid parameters: {}", e) })?
+    } else {
+        Parameters::default()
+    };
+    let mut wire_uuids = Vec::new();
+    if binary_version >= 4 {
+        for record in &mut components {
+            record.uuid = match reader.byte()? {
+                FALSE => None,
+                TRUE => Some(reader.uuid()?),
+                _ => {
+                    reader.offset -= 1;
+                    return reader.error("Invalid uuid flag");
+                },
+            };
+        }
+        for _ in 0..reader.len()? {
+            wire_uuids.push(reader.uuid()?);
+        }
+    }
+    if reader.offset != bytes.len() {
+        return reader.error("Unexpected data after the end of the project");
+    }
+    Ok(CircuitFile { version, components, wires, wire_uuids, appearance, parameters })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::SymbolAppearance;
+    use crate::parameters::{Parameter, Binding};
+
+    #[test]
+    fn encode_values() {
+        let mut properties = BTreeMap::new();
+        properties.insert("value".into(), serde_json::json!({
+            "list": [null, true, false, 0, 300, -5, 1.5, "text"],
+            "nested": { "list": [] },
+        }));
+        let file = CircuitFile {
+            version: 1,
+            components: vec![ComponentRecord {
+                id: ComponentId(7),
+                component: "widget".into(),
+                x: -3,
+                y: 9,
+                schema_version: 2,
+                properties,
+                uuid: Some(Uuid::new_v4()),
+            }],
+            wires: vec![Wire::new((-1, 4), (-1, 10))],
+            wire_uuids: vec![Uuid::new_v4()],
+            appearance: Some(SymbolAppearance::new(3, 2)),
+            parameters: Parameters {
+                declared: vec![Parameter { name: "WIDTH".into(), default: 8, min: 1, max: 64 }],
+                bindings: vec![Binding { component: ComponentId(7), property: "num_bits".into(), expression: "WIDTH".into() }],
+            },
+        };
+        let bytes = encode(&file);
+        assert!(is_binary(&bytes));
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.components[0].properties, file.components[0].properties);
+        assert_eq!((decoded.components[0].x, decoded.components[0].y), (-3, 9));
+        assert_eq!(decoded.components[0].schema_version, 2);
+        assert_eq!(decoded.components[0].uuid, file.components[0].uuid);
+        assert_eq!(decoded.wires, file.wires);
+        assert_eq!(decoded.wire_uuids, file.wire_uuids);
+        assert_eq!(decoded.appearance, file.appearance);
+        assert_eq!(decoded.parameters, file.parameters);
+
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| {
+            let mut value = Value::Null;
+            for _ in 0..depth {
+                value = Value::Array(vec![value]);
+            }
+            let mut properties = BTreeMap::new();
+            properties.insert("value".into(), value);
+            encode(&CircuitFile {
+                version: 1,
+                components: vec![ComponentRecord {
+                    id: ComponentId(0),
+                    component: "widget".into(),
+                    x: 0,
+                    y: 0,
+                    schema_version: 1,
+                    properties,
+                    uuid: None,
+                }],
+                wires: Vec::new(),
+                wire_uuids: Vec::new(),
+                appearance: None,
+                parameters: Parameters::default(),
+            })
+        };
+        assert!(decode(&nested(MAX_DEPTH)).is_ok());
+        let error = decode(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(error.message, "Values are nested too deeply");
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::error::Error;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+use uuid::Uuid;
+
+use crate::circuit::{Circuit, ComponentId, Wire};
+use crate::component::{ComponentInfo, PropertyError};
+use crate::library::{Library, MissingComponentError};
+use crate::migration::{self, MigrationError, MigrationRecord};
+use crate::symbol::SymbolAppearance;
+use crate::parameters::Parameters;
+
+pub mod binary;
+
+pub use self::binary::BinaryError;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComponentRecord {
+    pub id: ComponentId,
+    pub component: String,
+    pub x: i32,
+    pub y: i32,
+    /// The schema version of the component when it was saved.
+    #[serde(default = "migration::initial_schema_version")]
+    pub schema_version: u32,
+    pub properties: BTreeMap<String, serde_json::Value>,
+    /// Identifies the component across saves. Components without one, such
+    /// as those in older files, are given a new uuid when loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CircuitFile {
+    pub version: u32,
+    pub components: Vec<ComponentRecord>,
+    pub wires: Vec<Wire>,
+    /// The uuid of each wire, in the same order as `wires`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wire_uuids: Vec<Uuid>,
+    /// The symbol drawn for instances of the circuit, if customised.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appearance: Option<SymbolAppearance>,
+    /// The parameters of the circuit, if it is generic.
+    #[serde(default, skip_serializing_if = "Parameters::is_empty")]
+    pub parameters: Parameters,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Json(serde_json::Error),
+    Binary(BinaryError),
+    UnsupportedVersion(u32),
+    MissingComponent(MissingComponentError),
+    Property {
+        component: ComponentId,
+        error: PropertyError,
+    },
+    Migration {
+        component: ComponentId,
+        error: MigrationError,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Json(e) => write!(f, "Invalid project file: {}", e),
+            LoadError::Binary(e) => e.fmt(f),
+            LoadError::UnsupportedVersion(v) => write!(f, "Unsupported project version {}", v),
+            LoadError::MissingComponent(e) => e.fmt(f),
+            LoadError::Property { component, error } => write!(f, "Component {}: {}", component, error),
+            LoadError::Migration { component, error } => write!(f, "Component {}: {}", component, error),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            LoadError::Json(e) => Some(e),
+            LoadError::Binary(e) => Some(e),
+            LoadError::MissingComponent(e) => Some(e),
+            LoadError::Property { error, .. } => Some(error),
+            LoadError::Migration { error, .. } => Some(error),
+            LoadError::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::Json(e)
+    }
+}
+
+impl From<BinaryError> for LoadError {
+    fn from(e: BinaryError) -> Self {
+        LoadError::Binary(e)
+    }
+}
+
+impl From<MissingComponentError> for LoadError {
+    fn from(e: MissingComponentError) -> Self {
+        LoadError::MissingComponent(e)
+    }
+}
+
+/// Captures every writable property of a component, as described by its
+/// schema.
+pub fn save_properties(info: &ComponentInfo) -> BTreeMap<String, serde_json::Value> {
+    info.schema().into_iter()
+        .filter(|(_, field)| !field.read_only)
+        .filter_map(|(name, _)| {
+            let value = info.get_property(&name)?;
+            Some((name.into_owned(), value))
+        })
+        .collect()
+}
+
+/// Applies saved properties to a component. Some properties are only valid
+/// once others have been set (eg. `invert_inputs` must have `num_inputs`
+/// elements) so properties which fail are retried in further passes
+/// until no more progress can be made.
+pub fn load_properties(
+    info: &mut ComponentInfo,
+    mut properties: BTreeMap<String, serde_json::Value>,
+) -> Result<(), PropertyError> {
+    while !properties.is_empty() {
+        let remaining = properties.len();
+        let mut error = None;
+        let names: Vec<_> = properties.keys().cloned().collect();
+        for name in names {
+            match info.set_property(&name, properties[&name].clone()) {
+                Ok(_) => { properties.remove(&name); },
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) if properties.len() == remaining => return Err(e),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+pub fn save_component(id: ComponentId, info: &ComponentInfo, uuid: Option<Uuid>) -> ComponentRecord {
+    let (x, y) = info.position();
+    ComponentRecord {
+        id,
+        component: info.metadata().id.clone().into_owned(),
+        x,
+        y,
+        schema_version: info.metadata().schema_version,
+        properties: save_properties(info),
+        uuid,
+    }
+}
+
+pub fn save(circuit: &Circuit) -> CircuitFile {
+    CircuitFile {
+        version: FORMAT_VERSION,
+        components: circuit.components()
+            .map(|(id, info)| save_component(id, info, circuit.component_uuid(id)))
+            .collect(),
+        wires: circuit.wires().map(|(_, &wire)| wire).collect(),
+        wire_uuids: circuit.wires().filter_map(|(id, _)| circuit.wire_uuid(id)).collect(),
+        appearance: circuit.appearance().cloned(),
+        parameters: circuit.parameters().clone(),
+    }
+}
+
+/// Creates a component from a saved record, first migrating its properties
+/// if it was saved under an older schema. Returns whether it was migrated.
+pub fn load_component(record: &ComponentRecord, library: &Library) -> Result<(ComponentInfo, bool), LoadError> {
+    let mut info = library.create(&record.component)?;
+    let mut properties = record.properties.clone();
+    library.migrate(&record.component, record.schema_version, &mut properties)
+        .map_err(|error| LoadError::Migration { component: record.id, error })?;
+    load_properties(&mut info, properties)
+        .map_err(|error| LoadError::Property { component: record.id, error })?;
+    info.set_position(record.x, record.y);
+    let migrated = record.schema_version != info.metadata().schema_version;
+    Ok((info, migrated))
+}
+
+pub fn load(file: CircuitFile, library: &Library) -> Result<Circuit, LoadError> {
+    load_with_migrations(file, library).map(|(circuit, _)| circuit)
+}
+
+/// Loads a circuit, also returning a record of each component which was
+/// saved under an older schema and has been migrated.
+pub fn load_with_migrations(file: CircuitFile, library: &Library) -> Result<(Circuit, Vec<MigrationRecord>), LoadError> {
+    let mut partial = load_partial(file, library)?;
+    if !partial.errors.is_empty() {
+        return Err(partial.errors.remove(0).1);
+    }
+    Ok((partial.circuit, partial.migrations))
+}
+
+/// A circuit loaded by `load_partial`.
+#[derive(Debug)]
+pub struct PartialCircuit {
+    pub circuit: Circuit,
+    /// Each component which was saved under an older schema and has been
+    /// migrated.
+    pub migrations: Vec<MigrationRecord>,
+    /// Each component which could not be loaded, and was left out.
+    pub errors: Vec<(ComponentId, LoadError)>,
+}
+
+/// Loads the parts of a circuit which can be loaded, leaving out components
+/// which cannot. Only fails if the file itself cannot be loaded.
+pub fn load_partial(file: CircuitFile, library: &Library) -> Result<PartialCircuit, LoadError> {
+    if file.version > FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(file.version));
+    }
+    let mut circuit = Circuit::new();
+    let mut migrations = Vec::new();
+    let mut errors = Vec::new();
+    for record in &file.components {
+        let (info, migrated) = match load_component(record, library) {
+            Ok(result) => result,
+            Err(error) => {
+                errors.push((record.id, error));
+                continue;
+            },
+        };
+        if migrated {
+            migrations.push(MigrationRecord {
+                component: record.id,
+                id: record.component.clone(),
+                from_version: record.schema_version,
+                to_version: info.metadata().schema_version,
+            });
+        }
+        circuit.insert_component_with_uuid(record.id, info, record.uuid);
+    }
+    for (index, wire) in file.wires.into_iter().enumerate() {
+        circuit.add_wire_with_uuid(wire, file.wire_uuids.get(index).cloned());
+    }
+    circuit.set_appearance(file.appearance);
+    *circuit.parameters_mut() = file.parameters;
+    Ok(PartialCircuit { circuit, migrations, errors })
+}
+
+impl Circuit {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&save(self))
+            .expect("Circuit should always be serializable")
+    }
+    pub fn from_json(json: &str, library: &Library) -> Result<Circuit, LoadError> {
+        load(serde_json::from_str(json)?, library)
+    }
+    /// Saves the circuit in the compact binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::encode(&save(self))
+    }
+    /// Loads a circuit saved in either format. Binary files are recognised
+    /// by their header, and anything else is parsed as JSON.
+    pub fn from_bytes(bytes: &[u8], library: &Library) -> Result<Circuit, LoadError> {
+        load(parse(bytes)?, library)
+    }
+}
+
+/// Parses a project file in either the binary or JSON format.
+pub fn parse(bytes: &[u8]) -> Result<CircuitFile, LoadError> {
+    if binary::is_binary(bytes) {
+        Ok(binary::decode(bytes)?)
+    } else {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn round_trip() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let mut gate = lib.create("or_gate").unwrap();
+        gate.set_property("orientation", serde_json::json!("East")).unwrap();
+        gate.set_property("num_inputs", serde_json::json!(3)).unwrap();
+        gate.set_property("invert_inputs", serde_json::json!([false, false, true])).unwrap();
+        let id = circuit.add_component(gate, 4, 5);
+        circuit.add_wire(Wire::new((0, 0), (0, 4)));
+
+        let loaded = Circuit::from_json(&circuit.to_json(), &lib).unwrap();
+        let gate = loaded.get_component(id).unwrap();
+        assert_eq!(gate.position(), (4, 5));
+        assert_eq!(save_properties(gate), save_properties(circuit.get_component(id).unwrap()));
+        assert_eq!(loaded.wires().map(|(_, &w)| w).collect::<Vec<_>>(), vec![Wire::new((0, 0), (0, 4))]);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        for i in 0..20 {
+            let mut gate = lib.create("and_gate").unwrap();
+            gate.set_property("num_inputs", serde_json::json!(2 + i % 3)).unwrap();
+            circuit.add_component(gate, i * 6, -i);
+            circuit.add_wire(Wire::new((i * 6, 10), (i * 6 + 4, 10)));
+        }
+        let json = circuit.to_json();
+        let bytes = circuit.to_bytes();
+        assert!(bytes.len() * 4 < json.len());
+
+        for loaded in &[Circuit::from_bytes(&bytes, &lib).unwrap(), Circuit::from_bytes(json.as_bytes(), &lib).unwrap()] {
+            assert_eq!(loaded.to_json(), json);
+        }
+        match Circuit::from_bytes(&bytes[..bytes.len() - 1], &lib) {
+            Err(LoadError::Binary(_)) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_component() {
+        let json = r#"{"version":1,"components":[
+            {"id":0,"component":"flux_capacitor","x":0,"y":0,"properties":{}}
+        ],"wires":[]}"#;
+        match Circuit::from_json(json, &gates::library()) {
+            Err(LoadError::MissingComponent(e)) => assert_eq!(e.id, "flux_capacitor"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
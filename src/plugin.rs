@@ -0,0 +1,260 @@
+//! Component libraries loaded from dynamic libraries at runtime.
+//!
+//! A plugin is a `cdylib` crate which depends on this one, and exports a
+//! function adding its components to a library:
+//!
+//! ```ignore
+//! fn register(library: &mut Library) {
+//!     library.add(ComponentMetadata::new("blinker", "Blinker", "Plugins", "Flashes"),
+//!         || Box::new(Blinker::default()));
+//! }
+//!
+//! tenorite_ui::export_plugin!(register);
+//! ```
+//!
+//! Components are passed back to the application as trait objects, so a
+//! plugin only works with the version of this crate it was built against,
+//! and must be built with the same compiler. The version is checked when
+//! the plugin is loaded, but the compiler cannot be.
+//!
+//! Loading plugins requires the `plugins` feature.
+
+use std::fmt;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::library::{Library, CollisionError};
+
+/// Incremented whenever a change to this crate would break existing
+/// plugins, independently of the crate version.
+pub const API_VERSION: u32 = 1;
+
+/// The name of the function returning the `PluginVersion` a plugin was
+/// built against.
+pub const VERSION_SYMBOL: &'static str = "tenorite_plugin_version";
+/// The name of the function adding a plugin's components to a library.
+pub const REGISTER_SYMBOL: &'static str = "tenorite_register";
+
+pub type VersionFn = extern "C" fn() -> PluginVersion;
+#[allow(improper_ctypes_definitions)]
+pub type RegisterFn = extern "C" fn(&mut Library);
+
+/// Identifies the version of this crate a plugin was built against.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PluginVersion {
+    pub api: u32,
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl PluginVersion {
+    pub fn current() -> Self {
+        PluginVersion {
+            api: API_VERSION,
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().expect("Version is a number"),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().expect("Version is a number"),
+        }
+    }
+    /// Whether a plugin built against this version can be loaded by the
+    /// current one. Even a minor release may change the layout of the
+    /// types shared with plugins, so only patch releases are compatible.
+    pub fn is_compatible(self) -> bool {
+        self == Self::current()
+    }
+}
+
+impl fmt::Display for PluginVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{} (plugin API {})", self.major, self.minor, self.api)
+    }
+}
+
+/// Defines the functions through which a plugin is loaded, given a
+/// function which adds the plugin's components to a library.
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:path) => {
+        #[no_mangle]
+        pub extern "C" fn tenorite_plugin_version() -> $crate::plugin::PluginVersion {
+            $crate::plugin::PluginVersion::current()
+        }
+        #[no_mangle]
+        #[allow(improper_ctypes_definitions)]
+        pub extern "C" fn tenorite_register(library: &mut $crate::library::Library) {
+            $register(library)
+        }
+    };
+}
+
+#[derive(Debug, Clone)]
+pub enum PluginError {
+    Load { path: PathBuf, message: String },
+    MissingSymbol { path: PathBuf, symbol: &'static str },
+    Incompatible { path: PathBuf, version: PluginVersion },
+    Collision { path: PathBuf, error: CollisionError },
+}
+
+impl PluginError {
+    pub fn path(&self) -> &Path {
+        match self {
+            PluginError::Load { path, .. } |
+            PluginError::MissingSymbol { path, .. } |
+            PluginError::Incompatible { path, .. } |
+            PluginError::Collision { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to load plugin `{}`: ", self.path().display())?;
+        match self {
+            PluginError::Load { message, .. } => write!(f, "{}", message),
+            PluginError::MissingSymbol { symbol, .. } => write!(f, "`{}` is not exported", symbol),
+            PluginError::Incompatible { version, .. } =>
+                write!(f, "it was built for version {}, but this is version {}", version, PluginVersion::current()),
+            PluginError::Collision { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+impl Error for PluginError {}
+
+/// A plugin which has been loaded, and the ids of the components it added.
+#[derive(Debug)]
+pub struct Plugin {
+    path: PathBuf,
+    components: Vec<String>,
+    #[cfg(feature = "plugins")]
+    _library: libloading::Library,
+}
+
+impl Plugin {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+}
+
+/// The plugins loaded by the application. Components created from a plugin
+/// run code within it, so they must all be dropped before the plugin is.
+#[derive(Debug, Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn plugins(&self) -> &[Plugin] {
+        &self.plugins
+    }
+    /// Loads a plugin and adds its components to `library`. Nothing is
+    /// added if any of the component ids are already in use.
+    #[cfg(feature = "plugins")]
+    pub fn load(&mut self, path: &Path, library: &mut Library) -> Result<&Plugin, PluginError> {
+        let loaded = libloading::Library::new(path)
+            .map_err(|e| PluginError::Load { path: path.into(), message: e.to_string() })?;
+        let components = {
+            let symbol = |name: &'static str| {
+                let mut bytes = name.as_bytes().to_vec();
+                bytes.push(0);
+                bytes
+            };
+            // Safety: the symbols are defined by `export_plugin!` with these
+            // signatures. A library exporting them some other way is not a
+            // plugin and cannot be loaded safely.
+            unsafe {
+                let version = loaded.get::<VersionFn>(&symbol(VERSION_SYMBOL))
+                    .map_err(|_| PluginError::MissingSymbol { path: path.into(), symbol: VERSION_SYMBOL })?;
+                let register = loaded.get::<RegisterFn>(&symbol(REGISTER_SYMBOL))
+                    .map_err(|_| PluginError::MissingSymbol { path: path.into(), symbol: REGISTER_SYMBOL })?;
+                register_plugin(path, *version, *register, library)?
+            }
+        };
+        self.plugins.push(Plugin { path: path.into(), components, _library: loaded });
+        Ok(self.plugins.last().expect("Plugin was just added"))
+    }
+    /// Loads every dynamic library in a directory as a plugin, in order of
+    /// file name, returning the errors for those which failed to load.
+    #[cfg(feature = "plugins")]
+    pub fn load_dir(&mut self, dir: &Path, library: &mut Library) -> Vec<PluginError> {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) => return vec![PluginError::Load { path: dir.into(), message: e.to_string() }],
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == std::env::consts::DLL_EXTENSION))
+            .collect();
+        paths.sort();
+        paths.iter().filter_map(|path| self.load(path, library).err()).collect()
+    }
+}
+
+// Checks that a plugin was built against this version of the crate and
+// adds its components to `library`, returning their ids.
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+fn register_plugin(
+    path: &Path,
+    version: VersionFn,
+    register: RegisterFn,
+    library: &mut Library,
+) -> Result<Vec<String>, PluginError> {
+    let version = version();
+    if !version.is_compatible() {
+        return Err(PluginError::Incompatible { path: path.into(), version });
+    }
+    let mut components = Library::new();
+    register(&mut components);
+    let ids = components.list().iter().map(|metadata| metadata.id.to_string()).collect();
+    library.extend_checked(components)
+        .map_err(|error| PluginError::Collision { path: path.into(), error })?;
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::{default_library, gates, io};
+
+    fn register(library: &mut Library) {
+        library.extend(gates::library().namespaced("plugin"));
+    }
+
+    crate::export_plugin!(register);
+
+    extern "C" fn future_version() -> PluginVersion {
+        PluginVersion { api: API_VERSION + 1, ..PluginVersion::current() }
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    extern "C" fn register_io(library: &mut Library) {
+        library.extend(io::library());
+    }
+
+    #[test]
+    fn registers_components() {
+        let path = Path::new("plugin.so");
+        let mut library = Library::new();
+        let ids = register_plugin(path, tenorite_plugin_version, tenorite_register, &mut library).unwrap();
+        assert!(ids.contains(&"plugin/and_gate".to_string()));
+        assert!(library.create("plugin/and_gate").is_ok());
+
+        let error = register_plugin(path, future_version, tenorite_register, &mut library).unwrap_err();
+        assert!(error.to_string().contains("plugin API 2"), "{}", error);
+
+        let mut library = default_library();
+        let count = library.list().len();
+        let error = register_plugin(path, tenorite_plugin_version, register_io, &mut library).unwrap_err();
+        match error {
+            PluginError::Collision { error, .. } => assert!(error.ids.contains(&"led".to_string())),
+            _ => panic!("{}", error),
+        }
+        assert_eq!(library.list().len(), count);
+    }
+}
@@ -0,0 +1,433 @@
+//! Loading projects from any of the supported file formats.
+//!
+//! The format is detected from the contents of the file: a project, a single
+//! circuit in either the JSON or binary format, or a Logisim project to be
+//! imported. Problems which only affect part of the file, such as a
+//! component missing from the library, are reported as diagnostics and the
+//! rest of the file is still loaded.
+
+use std::fmt;
+use std::error::Error;
+use std::io::{self, Read};
+use std::str::Utf8Error;
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use crate::check::Severity;
+use crate::circuit::{Circuit, ComponentId};
+use crate::import::logisim::{self, ImportError, ImportWarning};
+use crate::library::Library;
+use crate::migration::MigrationRecord;
+use crate::persist::{self, binary, BinaryError, CircuitFile, LoadError, FORMAT_VERSION};
+use crate::subcircuit::{CircuitRegistry, RecursionError};
+use super::{Project, ProjectFile, ProjectMetadata, LibraryReference, load_order, PROJECT_FORMAT_VERSION};
+
+/// The name given to the circuit of a file containing a single circuit.
+pub const SINGLE_CIRCUIT_NAME: &str = "main";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Project,
+    CircuitJson,
+    CircuitBinary,
+    Logisim,
+}
+
+impl Format {
+    /// Guesses the format of a file from its contents.
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        Format::detect_parsed(bytes).map(|(format, _)| format)
+    }
+    // As `detect`, also returning the parsed value of a valid JSON file so
+    // that loading it needn't parse it again
+    fn detect_parsed(bytes: &[u8]) -> Option<(Format, Option<Value>)> {
+        if binary::is_binary(bytes) {
+            return Some((Format::CircuitBinary, None));
+        }
+        let start = bytes.iter().position(|b| !b.is_ascii_whitespace())?;
+        match bytes[start] {
+            b'<' => Some((Format::Logisim, None)),
+            b'{' => {
+                let value = serde_json::from_slice::<Value>(bytes).ok();
+                // Only projects have a map of circuits
+                let format = match value {
+                    Some(ref value) if value.get("circuits").map_or(false, |c| c.is_object()) => Format::Project,
+                    _ => Format::CircuitJson,
+                };
+                Some((format, value))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Finds the external libraries referred to by a project.
+pub trait LibraryResolver {
+    /// The components of a library, or an explanation of why it could not
+    /// be found.
+    fn resolve(&self, reference: &LibraryReference) -> Result<Library, String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// The component is not in the library, and was left out.
+    MissingComponent { component: ComponentId, id: String },
+    /// The properties of the component could not be loaded or migrated, and
+    /// it was left out.
+    InvalidComponent { component: ComponentId, error: String },
+    /// The component was saved under an older schema and has been migrated.
+    Migrated(MigrationRecord),
+    /// The component is deprecated, or was saved under an alias of the
+    /// component which replaced it.
+    Deprecated { component: ComponentId, id: String, replacement: Option<String> },
+    UnresolvedLibrary { library: String, error: String },
+    /// The main circuit named by the project does not exist.
+    MissingMain { name: String },
+    /// The circuit cannot be used as a subcircuit, so other circuits cannot
+    /// contain it.
+    InvalidSubcircuit { error: String },
+    Import(ImportWarning),
+}
+
+/// A problem found while loading part of a project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadDiagnostic {
+    /// The circuit affected, if the problem is specific to one.
+    pub circuit: Option<String>,
+    pub kind: DiagnosticKind,
+}
+
+impl LoadDiagnostic {
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            DiagnosticKind::Migrated(_) => Severity::Info,
+            DiagnosticKind::MissingComponent { .. }
+            | DiagnosticKind::InvalidComponent { .. }
+            | DiagnosticKind::InvalidSubcircuit { .. } => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for LoadDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let DiagnosticKind::Import(ref warning) = self.kind {
+            return warning.fmt(f);
+        }
+        if let Some(ref circuit) = self.circuit {
+            write!(f, "In circuit `{}`: ", circuit)?;
+        }
+        match &self.kind {
+            DiagnosticKind::MissingComponent { component, id } =>
+                write!(f, "component {} is missing from the library: `{}`", component, id),
+            DiagnosticKind::InvalidComponent { component, error } =>
+                write!(f, "component {} could not be loaded: {}", component, error),
+            DiagnosticKind::Migrated(record) =>
+                write!(f, "component {} was migrated from version {} to {}", record.component, record.from_version, record.to_version),
+            DiagnosticKind::UnresolvedLibrary { library, error } =>
+                write!(f, "library `{}` could not be found: {}", library, error),
+            DiagnosticKind::Deprecated { component, id, replacement: Some(replacement) } =>
+                write!(f, "component {} uses `{}`, which has been replaced by `{}`", component, id, replacement),
+            DiagnosticKind::Deprecated { component, id, replacement: None } =>
+                write!(f, "component {} uses `{}`, which is deprecated", component, id),
+            DiagnosticKind::MissingMain { name } =>
+                write!(f, "the main circuit `{}` does not exist", name),
+            DiagnosticKind::InvalidSubcircuit { error } =>
+                write!(f, "the circuit cannot be used as a subcircuit: {}", error),
+            DiagnosticKind::Import(_) => Ok(()),
+        }
+    }
+}
+
+/// A problem which prevented a file from being loaded at all.
+#[derive(Debug)]
+pub enum ProjectLoaderError {
+    Io(io::Error),
+    UnknownFormat,
+    Encoding(Utf8Error),
+    Json(serde_json::Error),
+    Binary(BinaryError),
+    Import(ImportError),
+    UnsupportedVersion(u32),
+    Circuit {
+        name: String,
+        error: LoadError,
+    },
+    Recursion(RecursionError),
+}
+
+impl fmt::Display for ProjectLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectLoaderError::Io(e) => e.fmt(f),
+            ProjectLoaderError::UnknownFormat => write!(f, "The file is not in a recognised format"),
+            ProjectLoaderError::Encoding(e) => write!(f, "The file is not valid UTF-8: {}", e),
+            ProjectLoaderError::Json(e) => write!(f, "Invalid project file: {}", e),
+            ProjectLoaderError::Binary(e) => e.fmt(f),
+            ProjectLoaderError::Import(e) => e.fmt(f),
+            ProjectLoaderError::UnsupportedVersion(v) => write!(f, "Unsupported project version {}", v),
+            ProjectLoaderError::Circuit { name, error } => write!(f, "Circuit `{}`: {}", name, error),
+            ProjectLoaderError::Recursion(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ProjectLoaderError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            ProjectLoaderError::Io(e) => Some(e),
+            ProjectLoaderError::Encoding(e) => Some(e),
+            ProjectLoaderError::Json(e) => Some(e),
+            ProjectLoaderError::Binary(e) => Some(e),
+            ProjectLoaderError::Import(e) => Some(e),
+            ProjectLoaderError::Circuit { error, .. } => Some(error),
+            ProjectLoaderError::Recursion(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A loaded project, with any problems found along the way.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub project: Project,
+    pub format: Format,
+    pub diagnostics: Vec<LoadDiagnostic>,
+}
+
+pub struct ProjectLoader<'a> {
+    library: &'a Library,
+    resolver: Option<&'a LibraryResolver>,
+}
+
+impl<'a> ProjectLoader<'a> {
+    pub fn new(library: &'a Library) -> Self {
+        ProjectLoader { library, resolver: None }
+    }
+    /// Uses the resolver to find the external libraries a project refers to.
+    /// Without one, every reference is reported as unresolved.
+    pub fn with_resolver(mut self, resolver: &'a LibraryResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+    pub fn load<R: Read>(&self, mut reader: R) -> Result<LoadReport, ProjectLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(ProjectLoaderError::Io)?;
+        self.load_bytes(&bytes)
+    }
+    pub fn load_bytes(&self, bytes: &[u8]) -> Result<LoadReport, ProjectLoaderError> {
+        let (format, value) = Format::detect_parsed(bytes).ok_or(ProjectLoaderError::UnknownFormat)?;
+        let mut diagnostics = Vec::new();
+        let project = match format {
+            Format::Project => {
+                let file = from_json(value, bytes)?;
+                self.load_project(file, &mut diagnostics)?
+            },
+            Format::CircuitJson => {
+                let file = from_json(value, bytes)?;
+                self.load_single(file, &mut diagnostics)?
+            },
+            Format::CircuitBinary => {
+                let file = binary::decode(bytes).map_err(ProjectLoaderError::Binary)?;
+                self.load_single(file, &mut diagnostics)?
+            },
+            Format::Logisim => {
+                let source = std::str::from_utf8(bytes).map_err(ProjectLoaderError::Encoding)?;
+                let imported = logisim::import(source, self.library).map_err(ProjectLoaderError::Import)?;
+                diagnostics.extend(imported.warnings.into_iter().map(|warning| LoadDiagnostic {
+                    circuit: Some(warning.circuit.clone()),
+                    kind: DiagnosticKind::Import(warning),
+                }));
+                let mut project = Project {
+                    metadata: ProjectMetadata::default(),
+                    libraries: Vec::new(),
+                    circuits: imported.circuits,
+                    main: None,
+                    simulation: None,
+                };
+                if let Some(main) = imported.main {
+                    if !project.set_main(&main) {
+                        diagnostics.push(LoadDiagnostic { circuit: None, kind: DiagnosticKind::MissingMain { name: main } });
+                    }
+                }
+                project
+            },
+        };
+        Ok(LoadReport { project, format, diagnostics })
+    }
+    fn load_single(&self, file: CircuitFile, diagnostics: &mut Vec<LoadDiagnostic>) -> Result<Project, ProjectLoaderError> {
+        if file.version > FORMAT_VERSION {
+            return Err(ProjectLoaderError::UnsupportedVersion(file.version));
+        }
+        let mut project = Project::new(SINGLE_CIRCUIT_NAME);
+        let circuit = load_circuit(SINGLE_CIRCUIT_NAME, file, self.library, diagnostics)?;
+        project.insert_circuit(SINGLE_CIRCUIT_NAME, circuit).map_err(ProjectLoaderError::Recursion)?;
+        Ok(project)
+    }
+    fn load_project(&self, file: ProjectFile, diagnostics: &mut Vec<LoadDiagnostic>) -> Result<Project, ProjectLoaderError> {
+        if file.version > PROJECT_FORMAT_VERSION {
+            return Err(ProjectLoaderError::UnsupportedVersion(file.version));
+        }
+        let mut library = self.library.clone();
+        for reference in &file.libraries {
+            let resolved = match self.resolver {
+                Some(resolver) => resolver.resolve(reference),
+                None => Err("No library resolver was provided".into()),
+            };
+            match resolved {
+                Ok(resolved) => library.extend(resolved),
+                Err(error) => diagnostics.push(LoadDiagnostic {
+                    circuit: None,
+                    kind: DiagnosticKind::UnresolvedLibrary { library: reference.name.clone(), error },
+                }),
+            }
+        }
+
+        let mut circuits = CircuitRegistry::new();
+        for name in load_order(&file).map_err(ProjectLoaderError::Recursion)? {
+            let circuit = load_circuit(name, file.circuits[name].clone(), &library, diagnostics)?;
+            circuits.insert(name, circuit).map_err(ProjectLoaderError::Recursion)?;
+            if let Err(e) = circuits.register(&mut library) {
+                diagnostics.push(LoadDiagnostic {
+                    circuit: Some(e.name),
+                    kind: DiagnosticKind::InvalidSubcircuit { error: e.error.to_string() },
+                });
+            }
+        }
+        let mut project = Project {
+            metadata: file.metadata,
+            libraries: file.libraries,
+            circuits,
+            main: None,
+            simulation: file.simulation,
+        };
+        if let Some(main) = file.main {
+            if !project.set_main(&main) {
+                diagnostics.push(LoadDiagnostic { circuit: None, kind: DiagnosticKind::MissingMain { name: main } });
+            }
+        }
+        Ok(project)
+    }
+}
+
+// Deserializes a JSON file from the value parsed when detecting its format,
+// or from its bytes to report why it could not be parsed
+fn from_json<T: DeserializeOwned>(value: Option<Value>, bytes: &[u8]) -> Result<T, ProjectLoaderError> {
+    match value {
+        Some(value) => serde_json::from_value(value),
+        None => serde_json::from_slice(bytes),
+    }.map_err(ProjectLoaderError::Json)
+}
+
+// Loads the parts of a circuit which can be loaded, leaving out components
+// which cannot
+fn load_circuit(name: &str, file: CircuitFile, library: &Library, diagnostics: &mut Vec<LoadDiagnostic>) -> Result<Circuit, ProjectLoaderError> {
+    let ids: Vec<_> = file.components.iter().map(|record| (record.id, record.component.clone())).collect();
+    let mut partial = persist::load_partial(file, library)
+        .map_err(|error| ProjectLoaderError::Circuit { name: name.into(), error })?;
+    let mut diagnose = |kind| diagnostics.push(LoadDiagnostic { circuit: Some(name.into()), kind });
+    for (component, id) in ids {
+        if let Some(index) = partial.errors.iter().position(|&(c, _)| c == component) {
+            match partial.errors.remove(index).1 {
+                LoadError::MissingComponent(e) => diagnose(DiagnosticKind::MissingComponent { component, id: e.id }),
+                e => diagnose(DiagnosticKind::InvalidComponent { component, error: e.to_string() }),
+            }
+            continue;
+        }
+        if let Some(index) = partial.migrations.iter().position(|m| m.component == component) {
+            diagnose(DiagnosticKind::Migrated(partial.migrations.remove(index)));
+        }
+        let replacement = library.alias_of(&id);
+        let deprecated = partial.circuit.get_component(component).map_or(false, |info| info.metadata().deprecated);
+        if replacement.is_some() || deprecated {
+            diagnose(DiagnosticKind::Deprecated { component, id, replacement: replacement.map(Into::into) });
+        }
+    }
+    Ok(partial.circuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::{gates, io as io_library};
+
+    struct Resolver;
+
+    impl LibraryResolver for Resolver {
+        fn resolve(&self, reference: &LibraryReference) -> Result<Library, String> {
+            match &*reference.name {
+                "io" => Ok(io_library::library()),
+                _ => Err("Not found".into()),
+            }
+        }
+    }
+
+    #[test]
+    fn detects_formats_and_reports_problems() {
+        let full = {
+            let mut library = gates::library();
+            library.extend(io_library::library());
+            library
+        };
+        let mut circuit = Circuit::new();
+        circuit.add_component(full.create("input_pin").unwrap(), 0, 0);
+        circuit.add_component(full.create("not_gate").unwrap(), 2, 0);
+        let mut project = Project::new("test");
+        project.insert_circuit("top", circuit.clone()).unwrap();
+        project.libraries.push(LibraryReference { name: "io".into(), location: "io".into(), version: None });
+        project.libraries.push(LibraryReference { name: "other".into(), location: "other".into(), version: None });
+
+        // The input pin comes from the resolved library
+        let gates_only = gates::library();
+        let loader = ProjectLoader::new(&gates_only);
+        let report = loader.with_resolver(&Resolver).load(project.to_json().as_bytes()).unwrap();
+        assert_eq!(report.format, Format::Project);
+        assert_eq!(report.project.circuit("top").unwrap().components().count(), 2);
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].kind, DiagnosticKind::UnresolvedLibrary { library: "other".into(), error: "Not found".into() });
+
+        // Without the resolver the input pin is left out
+        let report = ProjectLoader::new(&gates_only).load_bytes(project.to_json().as_bytes()).unwrap();
+        let missing: Vec<_> = report.diagnostics.iter().filter(|d| d.severity() == Severity::Error).collect();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].circuit.as_deref(), Some("top"));
+        assert_eq!(report.project.circuit("top").unwrap().components().count(), 1);
+
+        // Single circuits in either format become the main circuit
+        for bytes in &[circuit.to_bytes(), circuit.to_json().into_bytes()] {
+            let report = ProjectLoader::new(&full).load_bytes(bytes).unwrap();
+            assert_ne!(report.format, Format::Project);
+            assert_eq!(report.project.main(), Some(SINGLE_CIRCUIT_NAME));
+            assert!(report.diagnostics.is_empty());
+        }
+
+        // Components saved under an old id load as their replacement
+        let mut renamed = full.clone();
+        renamed.add_alias("legacy_not", "not_gate").unwrap();
+        let mut file = persist::save(&circuit);
+        let not_gate = file.components[1].id;
+        file.components[1].component = "legacy_not".into();
+        let report = ProjectLoader::new(&renamed).load_bytes(serde_json::to_string(&file).unwrap().as_bytes()).unwrap();
+        let loaded = report.project.main_circuit().unwrap();
+        assert_eq!(loaded.get_component(not_gate).unwrap().metadata().id, "not_gate");
+        assert_eq!(report.diagnostics[0].kind, DiagnosticKind::Deprecated {
+            component: not_gate,
+            id: "legacy_not".into(),
+            replacement: Some("not_gate".into()),
+        });
+        assert_eq!(report.diagnostics[0].severity(), Severity::Warning);
+        assert!(file.components[1].uuid.is_some());
+        assert_eq!(loaded.component_uuid(not_gate), file.components[1].uuid);
+
+        // A circuit saved in a newer format cannot be loaded
+        let mut file = crate::project::save(&project);
+        file.circuits.get_mut("top").unwrap().version = FORMAT_VERSION + 1;
+        let result = ProjectLoader::new(&full).load_bytes(serde_json::to_string(&file).unwrap().as_bytes());
+        assert!(matches!(result, Err(ProjectLoaderError::Circuit { error: LoadError::UnsupportedVersion(_), .. })));
+
+        assert_eq!(Format::detect(b"  <project/>"), Some(Format::Logisim));
+        assert!(matches!(ProjectLoader::new(&full).load_bytes(b"hello"), Err(ProjectLoaderError::UnknownFormat)));
+        assert!(matches!(ProjectLoader::new(&full).load_bytes(b"{\"circuits\": {}"), Err(ProjectLoaderError::Json(_))));
+    }
+}
@@ -0,0 +1,390 @@
+//! Projects, which group the circuits of a design together with information
+//! about the design as a whole.
+//!
+//! Every circuit of a project can be used as a subcircuit within the others,
+//! and one of them may be designated as the main circuit, which is the one
+//! simulated or exported by default. A project may also refer to external
+//! component libraries it needs, which are resolved by the application.
+//!
+//! Projects can optionally be saved together with the state of a running
+//! simulation, such as the contents of registers and memories, so that the
+//! simulation can be resumed from the same point after loading.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::error::Error;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json;
+
+use crate::circuit::Circuit;
+use crate::library::Library;
+use crate::persist::{self, CircuitFile, LoadError};
+use crate::simulation::{SimState, Simulator, SnapshotError};
+use crate::subcircuit::{CircuitRegistry, RecursionError, SubcircuitError};
+
+pub mod loader;
+
+pub use self::loader::{ProjectLoader, LibraryResolver, LoadReport, LoadDiagnostic, ProjectLoaderError};
+
+pub const PROJECT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProjectMetadata {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// An external library of components used by a project.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LibraryReference {
+    pub name: String,
+    /// Where the library can be found, such as a path or URL, as understood
+    /// by the application.
+    pub location: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// The state of a simulation of one of the circuits of a project.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedSimulation {
+    pub circuit: String,
+    pub state: SimState,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Project {
+    pub metadata: ProjectMetadata,
+    pub libraries: Vec<LibraryReference>,
+    circuits: CircuitRegistry,
+    main: Option<String>,
+    simulation: Option<SavedSimulation>,
+}
+
+impl Project {
+    pub fn new(name: &str) -> Self {
+        Project {
+            metadata: ProjectMetadata { name: name.into(), ..ProjectMetadata::default() },
+            ..Project::default()
+        }
+    }
+    pub fn circuits(&self) -> &CircuitRegistry {
+        &self.circuits
+    }
+    pub fn circuit(&self, name: &str) -> Option<&Circuit> {
+        self.circuits.get(name)
+    }
+    /// Adds or replaces a circuit, as for `CircuitRegistry::insert`. The
+    /// first circuit added becomes the main circuit. Any saved simulation
+    /// of a replaced circuit is discarded.
+    pub fn insert_circuit(&mut self, name: &str, circuit: Circuit) -> Result<Option<Circuit>, RecursionError> {
+        let previous = self.circuits.insert(name, circuit)?;
+        if self.main.is_none() {
+            self.main = Some(name.into());
+        }
+        if previous.is_some() {
+            self.discard_simulation(name);
+        }
+        Ok(previous)
+    }
+    /// Removes a circuit, and makes no circuit the main one if it was.
+    pub fn remove_circuit(&mut self, name: &str) -> Option<Circuit> {
+        if self.main.as_deref() == Some(name) {
+            self.main = None;
+        }
+        self.discard_simulation(name);
+        self.circuits.remove(name)
+    }
+    pub fn main(&self) -> Option<&str> {
+        self.main.as_deref()
+    }
+    pub fn main_circuit(&self) -> Option<&Circuit> {
+        self.main.as_ref().and_then(|name| self.circuits.get(name))
+    }
+    /// Makes the named circuit the main one. Returns false if there is no
+    /// such circuit.
+    pub fn set_main(&mut self, name: &str) -> bool {
+        if self.circuits.get(name).is_none() {
+            return false;
+        }
+        self.main = Some(name.into());
+        true
+    }
+    /// Adds a component to the library for each circuit of the project.
+    /// Fails without changing the library if any circuit cannot be used as a
+    /// subcircuit.
+    pub fn register(&self, library: &mut Library) -> Result<(), SubcircuitError> {
+        self.circuits.register(library)
+    }
+    /// The saved simulation, if the project has one.
+    pub fn simulation(&self) -> Option<&SavedSimulation> {
+        self.simulation.as_ref()
+    }
+    /// Saves the current state of a simulation of the named circuit with the
+    /// project, replacing any simulation saved before. The simulator must be
+    /// simulating the project's copy of the circuit, unchanged. Returns false
+    /// if there is no such circuit.
+    pub fn save_simulation(&mut self, circuit: &str, simulator: &Simulator) -> bool {
+        if self.circuits.get(circuit).is_none() {
+            return false;
+        }
+        self.simulation = Some(SavedSimulation { circuit: circuit.into(), state: simulator.snapshot() });
+        true
+    }
+    /// Removes the saved simulation, so that the project is saved without
+    /// one.
+    pub fn clear_simulation(&mut self) -> Option<SavedSimulation> {
+        self.simulation.take()
+    }
+    /// A simulator of the circuit whose simulation was saved with the
+    /// project, resumed from the point at which it was saved.
+    pub fn resume_simulation(&self) -> Option<Result<Simulator, SnapshotError>> {
+        let saved = self.simulation.as_ref()?;
+        let mut simulator = Simulator::new(self.circuits.get(&saved.circuit)?.clone());
+        Some(simulator.restore(saved.state.clone()).map(|()| simulator))
+    }
+    fn discard_simulation(&mut self, circuit: &str) {
+        if self.simulation.as_ref().is_some_and(|saved| saved.circuit == circuit) {
+            self.simulation = None;
+        }
+    }
+}
+
+/// A project as it is saved.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectFile {
+    pub version: u32,
+    pub metadata: ProjectMetadata,
+    #[serde(default)]
+    pub libraries: Vec<LibraryReference>,
+    #[serde(default)]
+    pub main: Option<String>,
+    pub circuits: BTreeMap<String, CircuitFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulation: Option<SavedSimulation>,
+}
+
+#[derive(Debug)]
+pub enum ProjectLoadError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    Circuit {
+        name: String,
+        error: LoadError,
+    },
+    Recursion(RecursionError),
+    Subcircuit(SubcircuitError),
+    MissingMain(String),
+}
+
+impl fmt::Display for ProjectLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectLoadError::Json(e) => write!(f, "Invalid project file: {}", e),
+            ProjectLoadError::UnsupportedVersion(v) => write!(f, "Unsupported project version {}", v),
+            ProjectLoadError::Circuit { name, error } => write!(f, "Circuit `{}`: {}", name, error),
+            ProjectLoadError::Recursion(e) => e.fmt(f),
+            ProjectLoadError::Subcircuit(e) => e.fmt(f),
+            ProjectLoadError::MissingMain(name) => write!(f, "The main circuit `{}` does not exist", name),
+        }
+    }
+}
+
+impl Error for ProjectLoadError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            ProjectLoadError::Json(e) => Some(e),
+            ProjectLoadError::Circuit { error, .. } => Some(error),
+            ProjectLoadError::Recursion(e) => Some(e),
+            ProjectLoadError::Subcircuit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProjectLoadError {
+    fn from(error: serde_json::Error) -> Self {
+        ProjectLoadError::Json(error)
+    }
+}
+
+impl From<RecursionError> for ProjectLoadError {
+    fn from(error: RecursionError) -> Self {
+        ProjectLoadError::Recursion(error)
+    }
+}
+
+impl From<SubcircuitError> for ProjectLoadError {
+    fn from(error: SubcircuitError) -> Self {
+        ProjectLoadError::Subcircuit(error)
+    }
+}
+
+pub fn save(project: &Project) -> ProjectFile {
+    ProjectFile {
+        version: PROJECT_FORMAT_VERSION,
+        metadata: project.metadata.clone(),
+        libraries: project.libraries.clone(),
+        main: project.main.clone(),
+        circuits: project.circuits.names()
+            .map(|name| (name.to_string(), persist::save(project.circuits.get(name).expect("Name is registered"))))
+            .collect(),
+        simulation: project.simulation.clone(),
+    }
+}
+
+// The order in which to load circuits, so that each comes after the
+// circuits it uses as subcircuits
+fn load_order(file: &ProjectFile) -> Result<Vec<&str>, RecursionError> {
+    fn visit<'a>(
+        file: &'a ProjectFile,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<(), RecursionError> {
+        if let Some(index) = path.iter().position(|&n| n == name) {
+            let mut cycle: Vec<_> = path[index..].iter().map(|n| n.to_string()).collect();
+            cycle.push(name.into());
+            return Err(RecursionError { cycle });
+        }
+        if order.contains(&name) {
+            return Ok(());
+        }
+        path.push(name);
+        let dependencies: BTreeSet<_> = file.circuits[name].components.iter()
+            .map(|record| &*record.component)
+            .filter(|id| file.circuits.contains_key(*id))
+            .collect();
+        for dependency in dependencies {
+            visit(file, dependency, path, order)?;
+        }
+        path.pop();
+        order.push(name);
+        Ok(())
+    }
+    let mut order = Vec::new();
+    for name in file.circuits.keys() {
+        visit(file, name, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Loads a project. The circuits of the project are added to a copy of the
+/// library as they are loaded, so that each can use the others as
+/// subcircuits.
+pub fn load(file: ProjectFile, library: &Library) -> Result<Project, ProjectLoadError> {
+    if file.version > PROJECT_FORMAT_VERSION {
+        return Err(ProjectLoadError::UnsupportedVersion(file.version));
+    }
+    let mut library = library.clone();
+    let mut circuits = CircuitRegistry::new();
+    for name in load_order(&file)? {
+        let circuit = persist::load(file.circuits[name].clone(), &library)
+            .map_err(|error| ProjectLoadError::Circuit { name: name.into(), error })?;
+        circuits.insert(name, circuit)?;
+        circuits.register(&mut library)?;
+    }
+    if let Some(ref main) = file.main {
+        if circuits.get(main).is_none() {
+            return Err(ProjectLoadError::MissingMain(main.clone()));
+        }
+    }
+    Ok(Project {
+        metadata: file.metadata,
+        libraries: file.libraries,
+        circuits,
+        main: file.main,
+        simulation: file.simulation,
+    })
+}
+
+impl Project {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&save(self))
+            .expect("Project should always be serializable")
+    }
+    pub fn from_json(json: &str, library: &Library) -> Result<Project, ProjectLoadError> {
+        load(serde_json::from_str(json)?, library)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::{gates, io};
+    use crate::net::PinRef;
+
+    #[test]
+    fn round_trip() {
+        let mut library = gates::library();
+        library.extend(io::library());
+        let mut project = Project::new("cpu");
+        project.metadata.author = Some("A. Designer".into());
+        project.libraries.push(LibraryReference { name: "74xx".into(), location: "libs/74xx.json".into(), version: None });
+
+        let mut inverter = Circuit::new();
+        inverter.add_component(library.create("input_pin").unwrap(), 0, 0);
+        inverter.add_component(library.create("not_gate").unwrap(), 2, 0);
+        inverter.add_component(library.create("output_pin").unwrap(), 5, 0);
+        project.insert_circuit("inverter", inverter).unwrap();
+        assert_eq!(project.main(), Some("inverter"));
+
+        // `top` uses `inverter`, but is loaded before it in name order
+        let mut with_subcircuits = library.clone();
+        project.register(&mut with_subcircuits).unwrap();
+        let mut top = Circuit::new();
+        top.add_component(with_subcircuits.create("inverter").unwrap(), 2, 0);
+        project.insert_circuit("a_top", top).unwrap();
+        assert!(project.set_main("a_top"));
+        assert!(!project.set_main("missing"));
+
+        let loaded = Project::from_json(&project.to_json(), &library).unwrap();
+        assert_eq!(loaded.metadata, project.metadata);
+        assert_eq!(loaded.libraries, project.libraries);
+        assert_eq!(loaded.main(), Some("a_top"));
+        let (_, instance) = loaded.main_circuit().unwrap().components().next().unwrap();
+        assert_eq!(instance.subcircuit().unwrap().components().count(), 3);
+
+        let mut file = save(&project);
+        file.main = Some("missing".into());
+        assert!(matches!(load(file, &library), Err(ProjectLoadError::MissingMain(_))));
+    }
+
+    #[test]
+    fn saved_simulation() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let counter = circuit.add_component(library.create("counter").unwrap(), 0, 0);
+        circuit.add_component(library.create("clock").unwrap(), -2, 2);
+        let q = PinRef::new(counter, 3);
+        let mut project = Project::new("counter");
+        project.insert_circuit("main", circuit.clone()).unwrap();
+        assert!(!project.to_json().contains("\"simulation\""));
+        assert!(project.resume_simulation().is_none());
+
+        let mut sim = Simulator::new(circuit.clone());
+        for _ in 0..5 {
+            sim.tick().unwrap();
+        }
+        assert!(!project.save_simulation("missing", &sim));
+        assert!(project.save_simulation("main", &sim));
+
+        let loaded = Project::from_json(&project.to_json(), &library).unwrap();
+        assert_eq!(loaded.simulation(), project.simulation());
+        let mut resumed = loaded.resume_simulation().unwrap().unwrap();
+        assert_eq!(resumed.ticks(), 5);
+        for _ in 0..6 {
+            sim.tick().unwrap();
+            resumed.tick().unwrap();
+        }
+        assert_eq!(resumed.pin_value(q), sim.pin_value(q));
+
+        // Replacing the circuit discards its simulation
+        project.insert_circuit("main", circuit).unwrap();
+        assert!(project.simulation().is_none());
+    }
+}
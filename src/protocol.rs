@@ -0,0 +1,407 @@
+//! A JSON-RPC 2.0 interface to a simulation, so that a remote frontend such
+//! as a browser can drive a headless backend.
+//!
+//! The `Server` owns a simulator, and handles requests to edit its circuit
+//! and to run and inspect the simulation. Messages are plain JSON text, and
+//! carrying them over a WebSocket or any other transport is up to the
+//! application.
+//!
+//! | Method | Parameters | Result |
+//! |---|---|---|
+//! | `place_component` | `component`, `x`, `y`, and optionally initial `properties` | `{ id }` |
+//! | `remove_component` | `id` | `null` |
+//! | `move_component` | `id`, `x`, `y` | `null` |
+//! | `set_property` | `id`, `name`, `value` | `null` |
+//! | `add_wire` | `start`, `end` | `{ id }` |
+//! | `remove_wire` | `id` | `null` |
+//! | `undo`, `redo` | | whether there was an edit to undo or redo |
+//! | `interact` | `id`, `interaction` | whether the component handled it |
+//! | `step` | | whether further evaluations are pending |
+//! | `tick` | `count`, which defaults to 1 and may be at most `MAX_TICKS` across a batch | `{ time, ticks }` |
+//! | `net_value` | `probe`, a path as understood by `trace::find_probe` | `{ bits, unsigned }` |
+//! | `save` | | the circuit, as saved by `persist::save` |
+
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::{self, json, Map, Value};
+
+use crate::circuit::{ComponentId, Wire, WireId};
+use crate::component::Interaction;
+use crate::library::Library;
+use crate::persist;
+use crate::simulation::Simulator;
+use crate::undo::{Command, CommandStack};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// The most ticks a single `tick` request, or all of those in a batch, may
+/// run, so that one message cannot occupy the server indefinitely.
+pub const MAX_TICKS: u64 = 100_000;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// An edit could not be made, such as because the component does not exist.
+pub const EDIT_FAILED: i64 = -32000;
+/// The simulation stopped at a breakpoint or failed to settle.
+pub const SIMULATION_FAILED: i64 = -32001;
+/// A probe path did not refer to a pin of the circuit.
+pub const UNKNOWN_PROBE: i64 = -32002;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Requests without an id are notifications, which are not answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new<I: Into<Value>>(id: I, method: &str, params: Value) -> Self {
+        Request { jsonrpc: JSONRPC_VERSION.into(), id: Some(id.into()), method: method.into(), params }
+    }
+    pub fn notification(method: &str, params: Value) -> Self {
+        Request { jsonrpc: JSONRPC_VERSION.into(), id: None, method: method.into(), params }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// The answer to a request, holding either a result or an error.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response { jsonrpc: JSONRPC_VERSION.into(), id, result, error }
+    }
+}
+
+#[derive(Deserialize)]
+struct PlaceComponent {
+    component: String,
+    x: i32,
+    y: i32,
+    #[serde(default)]
+    properties: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct ComponentParams {
+    id: ComponentId,
+}
+
+#[derive(Deserialize)]
+struct MoveComponent {
+    id: ComponentId,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize)]
+struct SetProperty {
+    id: ComponentId,
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct AddWire {
+    start: (i32, i32),
+    end: (i32, i32),
+}
+
+#[derive(Deserialize)]
+struct WireParams {
+    id: WireId,
+}
+
+#[derive(Deserialize)]
+struct Interact {
+    id: ComponentId,
+    interaction: Interaction,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Deserialize)]
+struct Tick {
+    #[serde(default = "one")]
+    count: u64,
+}
+
+#[derive(Deserialize)]
+struct NetValue {
+    probe: String,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods whose parameters all have defaults may omit them entirely
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+// The number of ticks run by a request, if it is a valid `tick` request
+fn requested_ticks(request: &Value) -> u64 {
+    if request.get("method").and_then(Value::as_str) != Some("tick") {
+        return 0;
+    }
+    let params_value = request.get("params").cloned().unwrap_or(Value::Null);
+    params::<Tick>(params_value).map_or(0, |p| p.count)
+}
+
+fn edit_failed<E: fmt::Display>(e: E) -> RpcError {
+    RpcError::new(EDIT_FAILED, e.to_string())
+}
+
+/// Handles requests to edit and simulate a circuit.
+#[derive(Debug)]
+pub struct Server {
+    simulator: Simulator,
+    library: Library,
+    stack: CommandStack,
+}
+
+impl Server {
+    pub fn new(simulator: Simulator, library: Library) -> Self {
+        Server { simulator, library, stack: CommandStack::new() }
+    }
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+    pub fn into_simulator(self) -> Simulator {
+        self.simulator
+    }
+
+    fn execute(&mut self, command: Command) -> Result<Value, RpcError> {
+        self.stack.execute(self.simulator.circuit_mut(), command).map_err(edit_failed)?;
+        Ok(Value::Null)
+    }
+
+    fn call(&mut self, method: &str, params_value: Value) -> Result<Value, RpcError> {
+        match method {
+            "place_component" => {
+                let p: PlaceComponent = params(params_value)?;
+                let info = self.library.create_with(&p.component, &p.properties).map_err(edit_failed)?;
+                let id = self.stack.add_component(self.simulator.circuit_mut(), info, p.x, p.y);
+                Ok(json!({ "id": id }))
+            },
+            "remove_component" => {
+                let p: ComponentParams = params(params_value)?;
+                self.execute(Command::RemoveComponent { id: p.id })
+            },
+            "move_component" => {
+                let p: MoveComponent = params(params_value)?;
+                self.execute(Command::MoveComponent { id: p.id, x: p.x, y: p.y })
+            },
+            "set_property" => {
+                let p: SetProperty = params(params_value)?;
+                self.execute(Command::SetProperty { id: p.id, name: p.name, value: p.value })
+            },
+            "add_wire" => {
+                let p: AddWire = params(params_value)?;
+                let id = self.stack.add_wire(self.simulator.circuit_mut(), Wire::new(p.start, p.end));
+                Ok(json!({ "id": id }))
+            },
+            "remove_wire" => {
+                let p: WireParams = params(params_value)?;
+                self.execute(Command::RemoveWire { id: p.id })
+            },
+            "undo" => Ok(self.stack.undo(self.simulator.circuit_mut()).map_err(edit_failed)?.into()),
+            "redo" => Ok(self.stack.redo(self.simulator.circuit_mut()).map_err(edit_failed)?.into()),
+            "interact" => {
+                let p: Interact = params(params_value)?;
+                Ok(self.simulator.interact(p.id, p.interaction).into())
+            },
+            "step" => Ok(self.simulator.step().into()),
+            "tick" => {
+                let p: Tick = params(params_value)?;
+                if p.count > MAX_TICKS {
+                    return Err(RpcError::new(INVALID_PARAMS, format!("At most {} ticks may be run at once", MAX_TICKS)));
+                }
+                for _ in 0..p.count {
+                    self.simulator.tick().map_err(|e| RpcError::new(SIMULATION_FAILED, e.to_string()))?;
+                }
+                Ok(json!({ "time": self.simulator.time(), "ticks": self.simulator.ticks() }))
+            },
+            "net_value" => {
+                let p: NetValue = params(params_value)?;
+                let value = self.simulator.path_value(&p.probe)
+                    .ok_or_else(|| RpcError::new(UNKNOWN_PROBE, format!("No pin `{}`", p.probe)))?;
+                Ok(json!({ "bits": value.to_string(), "unsigned": value.to_u64() }))
+            },
+            "save" => Ok(serde_json::to_value(persist::save(self.simulator.circuit()))
+                .expect("Circuit should always be serializable")),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method `{}`", method))),
+        }
+    }
+
+    /// Handles a request, returning the response unless the request was a
+    /// notification.
+    pub fn handle(&mut self, request: Request) -> Option<Response> {
+        let result = if request.jsonrpc != JSONRPC_VERSION {
+            Err(RpcError::new(INVALID_REQUEST, format!("Unsupported JSON-RPC version `{}`", request.jsonrpc)))
+        } else {
+            self.call(&request.method, request.params)
+        };
+        request.id.map(|id| Response::new(id, result))
+    }
+
+    fn handle_value(&mut self, value: Value) -> Option<Response> {
+        match serde_json::from_value::<Request>(value) {
+            Ok(request) => self.handle(request),
+            Err(e) => Some(Response::new(Value::Null, Err(RpcError::new(INVALID_REQUEST, e.to_string())))),
+        }
+    }
+
+    /// Handles a message holding a single request or a batch of them,
+    /// returning the text of the response, if there is one.
+    pub fn handle_message(&mut self, message: &str) -> Option<String> {
+        let value: Value = match serde_json::from_str(message) {
+            Ok(value) => value,
+            Err(e) => {
+                let response = Response::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())));
+                return Some(serde_json::to_string(&response).expect("Response is serializable"));
+            },
+        };
+        let response = match value {
+            Value::Array(requests) => {
+                let ticks = requests.iter().map(requested_ticks).fold(0, u64::saturating_add);
+                if requests.is_empty() {
+                    let error = RpcError::new(INVALID_REQUEST, "Empty batch");
+                    serde_json::to_value(Response::new(Value::Null, Err(error)))
+                } else if ticks > MAX_TICKS {
+                    let error = RpcError::new(INVALID_REQUEST, format!("At most {} ticks may be run by a batch", MAX_TICKS));
+                    serde_json::to_value(Response::new(Value::Null, Err(error)))
+                } else {
+                    let responses: Vec<_> = requests.into_iter().filter_map(|r| self.handle_value(r)).collect();
+                    if responses.is_empty() {
+                        return None;
+                    }
+                    serde_json::to_value(responses)
+                }
+            },
+            value => serde_json::to_value(self.handle_value(value)?),
+        };
+        Some(response.expect("Response is serializable").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Circuit;
+    use crate::libraries::wiring;
+
+    fn call(server: &mut Server, method: &str, params: Value) -> Result<Value, RpcError> {
+        let response = server.handle(Request::new(1, method, params)).unwrap();
+        match response.error {
+            Some(error) => Err(error),
+            None => Ok(response.result.unwrap()),
+        }
+    }
+
+    #[test]
+    fn drives_simulation() {
+        let mut server = Server::new(Simulator::new(Circuit::new()), wiring::library());
+        let placed = call(&mut server, "place_component", json!({ "component": "constant", "x": 0, "y": 0 })).unwrap();
+        let id: ComponentId = serde_json::from_value(placed["id"].clone()).unwrap();
+        call(&mut server, "set_property", json!({ "id": id, "name": "num_bits", "value": 4 })).unwrap();
+        call(&mut server, "set_property", json!({ "id": id, "name": "value", "value": "0xa" })).unwrap();
+        call(&mut server, "tick", Value::Null).unwrap();
+        let probe = format!("{}.out", id);
+        let value = call(&mut server, "net_value", json!({ "probe": probe })).unwrap();
+        assert_eq!(value, json!({ "bits": "1010", "unsigned": 10 }));
+
+        assert_eq!(call(&mut server, "undo", Value::Null), Ok(true.into()));
+        call(&mut server, "tick", json!({ "count": 2 })).unwrap();
+        // The constant is back to its default of one
+        assert_eq!(call(&mut server, "net_value", json!({ "probe": probe })).unwrap()["bits"], json!("0001"));
+
+        // Components can be placed with their properties already set
+        let placed = call(&mut server, "place_component", json!({
+            "component": "constant", "x": 0, "y": 10, "properties": { "num_bits": 3, "value": "0x5" },
+        })).unwrap();
+        let other: ComponentId = serde_json::from_value(placed["id"].clone()).unwrap();
+        call(&mut server, "tick", Value::Null).unwrap();
+        let value = call(&mut server, "net_value", json!({ "probe": format!("{}.out", other) })).unwrap();
+        assert_eq!(value, json!({ "bits": "101", "unsigned": 5 }));
+        let invalid = json!({ "component": "constant", "x": 0, "y": 20, "properties": { "num_bits": "wide" } });
+        assert_eq!(call(&mut server, "place_component", invalid).unwrap_err().code, EDIT_FAILED);
+
+        assert_eq!(call(&mut server, "explode", Value::Null).unwrap_err().code, METHOD_NOT_FOUND);
+        assert_eq!(call(&mut server, "move_component", json!({ "id": id })).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(call(&mut server, "remove_wire", json!({ "id": 99 })).unwrap_err().code, EDIT_FAILED);
+        assert_eq!(call(&mut server, "tick", json!({ "count": MAX_TICKS + 1 })).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(call(&mut server, "net_value", json!({ "probe": "c9.out" })).unwrap_err().code, UNKNOWN_PROBE);
+    }
+
+    #[test]
+    fn messages_and_batches() {
+        let mut server = Server::new(Simulator::new(Circuit::new()), wiring::library());
+        let response: Value = serde_json::from_str(&server.handle_message("{").unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], json!(PARSE_ERROR));
+        assert_eq!(server.handle_message(r#"{"jsonrpc":"2.0","method":"step"}"#), None);
+
+        let batch = r#"[
+            {"jsonrpc":"2.0","id":1,"method":"add_wire","params":{"start":[0,0],"end":[0,4]}},
+            {"jsonrpc":"2.0","method":"step"},
+            {"jsonrpc":"1.0","id":2,"method":"step"},
+            {"id":3}
+        ]"#;
+        let responses: Vec<Response> = serde_json::from_str(&server.handle_message(batch).unwrap()).unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].id, json!(1));
+        assert!(responses[0].result.is_some());
+        assert_eq!(responses[1].error.as_ref().unwrap().code, INVALID_REQUEST);
+        assert_eq!(responses[2].id, Value::Null);
+        assert_eq!(server.simulator().circuit().num_wires(), 1);
+
+        let tick = json!({ "jsonrpc": "2.0", "id": 4, "method": "tick", "params": { "count": MAX_TICKS / 2 + 1 } });
+        let response: Value = serde_json::from_str(&server.handle_message(&json!([tick, tick]).to_string()).unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(server.simulator().ticks(), 0);
+    }
+}
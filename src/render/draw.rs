@@ -0,0 +1,103 @@
+/// A single primitive recorded by a `DrawContext`. Coordinates are in grid
+/// units, in the component's own coordinate space before rotation, with the
+/// origin at its top-left corner. Colours are CSS colour strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    Line { from: (f64, f64), to: (f64, f64), stroke: String, width: f64 },
+    /// An arc of a circle, between two angles in radians measured clockwise
+    /// from the positive x axis.
+    Arc { center: (f64, f64), radius: f64, start: f64, end: f64, stroke: String, width: f64 },
+    Rect { position: (f64, f64), size: (f64, f64), fill: Option<String>, stroke: Option<String> },
+    Circle { center: (f64, f64), radius: f64, fill: Option<String>, stroke: Option<String> },
+    Polygon { points: Vec<(f64, f64)>, fill: Option<String>, stroke: Option<String> },
+    /// Text centred on a point, with the given height.
+    Text { center: (f64, f64), size: f64, text: String, fill: String },
+}
+
+/// A backend-agnostic vector drawing surface. Components draw themselves by
+/// recording primitives, which each renderer then converts to its own
+/// format.
+#[derive(Debug, Clone)]
+pub struct DrawContext {
+    commands: Vec<DrawCommand>,
+    line_width: f64,
+}
+
+/// The stroke width used unless changed with `set_line_width`.
+pub const DEFAULT_LINE_WIDTH: f64 = 0.2;
+
+impl Default for DrawContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DrawContext {
+    pub fn new() -> Self {
+        Self { commands: Vec::new(), line_width: DEFAULT_LINE_WIDTH }
+    }
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+    /// Sets the width of subsequent lines and arcs, in grid units.
+    pub fn set_line_width(&mut self, width: f64) {
+        self.line_width = width;
+    }
+    pub fn line(&mut self, from: (f64, f64), to: (f64, f64), stroke: &str) {
+        self.commands.push(DrawCommand::Line { from, to, stroke: stroke.into(), width: self.line_width });
+    }
+    pub fn arc(&mut self, center: (f64, f64), radius: f64, start: f64, end: f64, stroke: &str) {
+        self.commands.push(DrawCommand::Arc {
+            center, radius, start, end, stroke: stroke.into(), width: self.line_width,
+        });
+    }
+    pub fn rect(&mut self, position: (f64, f64), size: (f64, f64), fill: Option<&str>, stroke: Option<&str>) {
+        self.commands.push(DrawCommand::Rect {
+            position, size, fill: fill.map(Into::into), stroke: stroke.map(Into::into),
+        });
+    }
+    pub fn circle(&mut self, center: (f64, f64), radius: f64, fill: Option<&str>, stroke: Option<&str>) {
+        self.commands.push(DrawCommand::Circle {
+            center, radius, fill: fill.map(Into::into), stroke: stroke.map(Into::into),
+        });
+    }
+    pub fn polygon(&mut self, points: Vec<(f64, f64)>, fill: Option<&str>, stroke: Option<&str>) {
+        self.commands.push(DrawCommand::Polygon { points, fill: fill.map(Into::into), stroke: stroke.map(Into::into) });
+    }
+    pub fn text(&mut self, center: (f64, f64), size: f64, text: &str, fill: &str) {
+        self.commands.push(DrawCommand::Text { center, size, text: text.into(), fill: fill.into() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::default_library;
+    use crate::simulation::Value;
+
+    #[test]
+    fn components_draw_their_state() {
+        let library = default_library();
+        let mut ctx = DrawContext::new();
+        library.create("and_gate").unwrap().draw(&mut ctx);
+        assert!(ctx.is_empty());
+
+        let mut led = library.create("led").unwrap();
+        led.clock_edge(&vec![Value::from_u64(1, 1)]);
+        led.draw(&mut ctx);
+        assert_eq!(ctx.commands(), &[DrawCommand::Circle {
+            center: (1.0, 1.0), radius: 1.0, fill: Some("#ff2222".into()), stroke: None,
+        }]);
+
+        let mut ctx = DrawContext::new();
+        ctx.set_line_width(0.5);
+        ctx.arc((0.0, 0.0), 1.0, 0.0, std::f64::consts::PI, "#000");
+        match ctx.commands()[0] {
+            DrawCommand::Arc { width, .. } => assert_eq!(width, 0.5),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::component::Color;
+use super::DrawContext;
+
+/// A rectangular grid of pixels shown by a display component, stored row by
+/// row from the top left.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, fill: Color) -> Self {
+        Self { width, height, pixels: vec![fill; (width * height) as usize] }
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+    pub fn get(&self, x: u32, y: u32) -> Option<Color> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+    /// Sets a pixel, ignoring positions outside of the framebuffer.
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = color;
+        }
+    }
+    pub fn fill(&mut self, color: Color) {
+        for pixel in &mut self.pixels {
+            *pixel = color;
+        }
+    }
+    /// Draws the pixels into a rectangle, with each run of identical pixels
+    /// in a row drawn as a single rectangle.
+    pub fn draw(&self, ctx: &mut DrawContext, position: (f64, f64), size: (f64, f64)) {
+        let (w, h) = (size.0 / self.width as f64, size.1 / self.height as f64);
+        for y in 0..self.height {
+            let row = &self.pixels[(y * self.width) as usize..((y + 1) * self.width) as usize];
+            let mut start = 0;
+            while start < row.len() {
+                let end = row[start..].iter().position(|&c| c != row[start]).map_or(row.len(), |n| start + n);
+                let x = position.0 + start as f64 * w;
+                ctx.rect((x, position.1 + y as f64 * h), ((end - start) as f64 * w, h), Some(&row[start].to_hex()), None);
+                start = end;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::DrawCommand;
+
+    #[test]
+    fn draws_runs_of_pixels() {
+        let (black, white) = (Color::rgb(0, 0, 0), Color::rgb(255, 255, 255));
+        let mut framebuffer = Framebuffer::new(4, 2, black);
+        framebuffer.set(3, 0, white);
+        framebuffer.set(4, 0, white);
+        assert_eq!(framebuffer.get(3, 0), Some(white));
+        assert_eq!(framebuffer.get(0, 2), None);
+
+        let mut ctx = DrawContext::new();
+        framebuffer.draw(&mut ctx, (1.0, 1.0), (8.0, 4.0));
+        assert_eq!(ctx.commands(), &[
+            DrawCommand::Rect { position: (1.0, 1.0), size: (6.0, 2.0), fill: Some("#000000".into()), stroke: None },
+            DrawCommand::Rect { position: (7.0, 1.0), size: (2.0, 2.0), fill: Some("#ffffff".into()), stroke: None },
+            DrawCommand::Rect { position: (1.0, 3.0), size: (8.0, 2.0), fill: Some("#000000".into()), stroke: None },
+        ]);
+    }
+}
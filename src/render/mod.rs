@@ -0,0 +1,9 @@
+//! Drawing of circuits and components.
+
+mod draw;
+mod framebuffer;
+pub mod svg;
+
+pub use self::draw::{DrawCommand, DrawContext, DEFAULT_LINE_WIDTH};
+pub use self::framebuffer::Framebuffer;
+pub use self::svg::{thumbnail, THUMBNAIL_SIZE};
@@ -0,0 +1,390 @@
+use std::fmt::Write;
+
+use crate::circuit::Circuit;
+use crate::component::{ComponentInfo, LabelPosition, Orientation, Rect};
+use crate::library::{Icon, Library, MissingComponentError};
+use crate::net::PinRef;
+use crate::simulation::{Bit, Simulator, Value};
+use super::{DrawCommand, DrawContext};
+
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// The size of a grid unit in pixels.
+    pub scale: f64,
+    /// Space left around the circuit, in grid units.
+    pub margin: i32,
+    /// If set, components are drawn using the image `{image_base}{image_name}.svg`.
+    /// Otherwise they are drawn as labelled boxes.
+    pub image_base: Option<String>,
+    /// Draws the label of each component beside it.
+    pub show_labels: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            scale: 10.0,
+            margin: 2,
+            image_base: None,
+            show_labels: true,
+        }
+    }
+}
+
+/// The colour used to draw a wire or pin carrying a value.
+pub fn value_color(value: &Value) -> &'static str {
+    let bits = value.bits();
+    if bits.contains(&Bit::Error) {
+        return "#d00";
+    }
+    match bits {
+        [Bit::Zero] => "#060",
+        [Bit::One] => "#2c2",
+        _ if value.is_floating() => "#33f",
+        _ if bits.contains(&Bit::Unknown) => "#888",
+        _ => "#222",
+    }
+}
+
+const NEUTRAL: &str = "#222";
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn paint(color: &Option<String>) -> String {
+    match color {
+        Some(color) => escape(color),
+        None => "none".into(),
+    }
+}
+
+// Writes the SVG element for each primitive, scaling from grid units to
+// pixels.
+fn write_commands(out: &mut String, ctx: &DrawContext, s: f64) {
+    let point = |(x, y): (f64, f64)| (x * s, y * s);
+    for command in ctx.commands() {
+        let _ = match command {
+            DrawCommand::Line { from, to, stroke, width } => {
+                let ((x1, y1), (x2, y2)) = (point(*from), point(*to));
+                writeln!(out, r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}"/>"#,
+                    x1, y1, x2, y2, escape(stroke), width * s)
+            },
+            DrawCommand::Arc { center, radius, start, end, stroke, width } => {
+                let (cx, cy) = point(*center);
+                let r = radius * s;
+                let (x1, y1) = (cx + r * start.cos(), cy + r * start.sin());
+                let (x2, y2) = (cx + r * end.cos(), cy + r * end.sin());
+                let large = ((end - start).abs() > std::f64::consts::PI) as u8;
+                let sweep = (end > start) as u8;
+                writeln!(out, r#"<path d="M {} {} A {} {} 0 {} {} {} {}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+                    x1, y1, r, r, large, sweep, x2, y2, escape(stroke), width * s)
+            },
+            DrawCommand::Rect { position, size, fill, stroke } => {
+                let (x, y) = point(*position);
+                writeln!(out, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="{}"/>"#,
+                    x, y, size.0 * s, size.1 * s, paint(fill), paint(stroke))
+            },
+            DrawCommand::Circle { center, radius, fill, stroke } => {
+                let (cx, cy) = point(*center);
+                writeln!(out, r#"<circle cx="{}" cy="{}" r="{}" fill="{}" stroke="{}"/>"#,
+                    cx, cy, radius * s, paint(fill), paint(stroke))
+            },
+            DrawCommand::Polygon { points, fill, stroke } => {
+                let points: Vec<_> = points.iter()
+                    .map(|&p| { let (x, y) = point(p); format!("{},{}", x, y) })
+                    .collect();
+                writeln!(out, r#"<polygon points="{}" fill="{}" stroke="{}"/>"#,
+                    points.join(" "), paint(fill), paint(stroke))
+            },
+            DrawCommand::Text { center, size, text, fill } => {
+                let (x, y) = point(*center);
+                writeln!(out,
+                    r#"<text x="{}" y="{}" font-size="{}" fill="{}" text-anchor="middle" dominant-baseline="central">{}</text>"#,
+                    x, y, size * s, escape(fill), escape(text))
+            },
+        };
+    }
+}
+
+// Maps the component's own coordinates onto the grid, matching the way
+// `Orientation` flips and rotates the pins of a shape.
+fn transform(info: &ComponentInfo, bounds: Rect, scale: f64, width: i32) -> String {
+    let (x, y) = (bounds.x as f64 * scale, bounds.y as f64 * scale);
+    let (w, h) = (bounds.width as f64 * scale, bounds.height as f64 * scale);
+    let mut result = match info.orientation() {
+        Orientation::North => format!("translate({} {})", x, y),
+        Orientation::East => format!("translate({} {}) rotate(90)", x + w, y),
+        Orientation::South => format!("translate({} {}) rotate(180)", x + w, y + h),
+        Orientation::West => format!("translate({} {}) rotate(270)", x, y + h),
+    };
+    if info.flipped() {
+        result.push_str(&format!(" translate({} 0) scale(-1 1)", width as f64 * scale));
+    }
+    result
+}
+
+fn draw_component(out: &mut String, info: &ComponentInfo, options: &SvgOptions, pin_values: &[Option<Value>]) {
+    let scale = options.scale;
+    let shape = info.get_shape();
+    let bounds = info.bounding_box();
+    // The size of the component before it was rotated
+    let (width, height) = match info.orientation() {
+        Orientation::North | Orientation::South => (shape.width, shape.height),
+        Orientation::East | Orientation::West => (shape.height, shape.width),
+    };
+
+    let mut ctx = DrawContext::new();
+    info.draw(&mut ctx);
+    let _ = writeln!(out, r#"<g class="component {}" transform="{}">"#,
+        escape(&shape.image_name), transform(info, bounds, scale, width));
+    match options.image_base {
+        Some(ref base) if ctx.is_empty() => {
+            let _ = writeln!(out, r#"<image href="{}{}.svg" width="{}" height="{}"/>"#,
+                escape(base), escape(&shape.image_name), width as f64 * scale, height as f64 * scale);
+        },
+        _ => {
+            if ctx.is_empty() {
+                let (width, height) = (width as f64, height as f64);
+                ctx.rect((0.0, 0.0), (width, height), Some("#fff"), Some(NEUTRAL));
+                ctx.text((width / 2.0, height / 2.0), 0.8, &info.metadata().name, NEUTRAL);
+            }
+            write_commands(out, &ctx, scale);
+        },
+    }
+    out.push_str("</g>\n");
+
+    // Pins and labels are drawn in grid coordinates, so that they are not
+    // rotated along with the component.
+    let mut ctx = DrawContext::new();
+    for (i, pin) in shape.pins.iter().enumerate() {
+        let color = pin_values.get(i).and_then(|v| v.as_ref()).map(value_color).unwrap_or(NEUTRAL);
+        ctx.circle(((bounds.x + pin.x) as f64, (bounds.y + pin.y) as f64), 0.2, Some(color), None);
+    }
+    if options.show_labels {
+        if let Some(label) = info.label() {
+            let (x, y) = (bounds.x as f64, bounds.y as f64);
+            let (width, height) = (bounds.width as f64, bounds.height as f64);
+            // Text is centred, so leave room for half its width at the sides
+            let half_width = label.chars().count() as f64 * 0.25;
+            let center = match info.label_position() {
+                LabelPosition::Above => (x + width / 2.0, y - 0.6),
+                LabelPosition::Below => (x + width / 2.0, y + height + 0.6),
+                LabelPosition::Left => (x - 0.4 - half_width, y + height / 2.0),
+                LabelPosition::Right => (x + width + 0.4 + half_width, y + height / 2.0),
+            };
+            ctx.text(center, 0.8, &label, NEUTRAL);
+        }
+    }
+    write_commands(out, &ctx, scale);
+}
+
+// Draws the circuit, scaled to fit within a square of `size` pixels if
+// given, and otherwise at the scale of the options.
+fn render(circuit: &Circuit, options: &SvgOptions, sim: Option<&Simulator>, size: Option<u32>) -> String {
+    // Find the area covered by the circuit
+    let mut points: Vec<(i32, i32)> = Vec::new();
+    for (_, info) in circuit.components() {
+        let b = info.bounding_box();
+        points.push((b.x, b.y));
+        points.push((b.x + b.width, b.y + b.height));
+    }
+    for (_, wire) in circuit.wires() {
+        points.push(wire.start);
+        points.push(wire.end);
+    }
+    let min_x = points.iter().map(|p| p.0).min().unwrap_or(0) - options.margin;
+    let min_y = points.iter().map(|p| p.1).min().unwrap_or(0) - options.margin;
+    let max_x = points.iter().map(|p| p.0).max().unwrap_or(0) + options.margin;
+    let max_y = points.iter().map(|p| p.1).max().unwrap_or(0) + options.margin;
+    let s = options.scale;
+    let (width, height) = ((max_x - min_x) as f64 * s, (max_y - min_y) as f64 * s);
+    let (width_px, height_px) = match size {
+        Some(size) => {
+            let fit = size as f64 / width.max(height);
+            (width * fit, height * fit)
+        },
+        None => (width, height),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        min_x as f64 * s, min_y as f64 * s, width, height, width_px, height_px);
+
+    let connectivity = sim.and_then(|sim| sim.connectivity());
+    let mut ctx = DrawContext::new();
+    for (id, wire) in circuit.wires() {
+        let value = connectivity
+            .and_then(|c| c.net_of_wire(id))
+            .and_then(|net| sim?.net_value(net));
+        let color = value.as_ref().map(value_color).unwrap_or(NEUTRAL);
+        let point = |(x, y): (i32, i32)| (x as f64, y as f64);
+        ctx.line(point(wire.start), point(wire.end), color);
+    }
+    write_commands(&mut out, &ctx, s);
+
+    for (id, info) in circuit.components() {
+        let values: Vec<_> = (0..info.get_shape().pins.len())
+            .map(|pin| sim.and_then(|sim| sim.pin_value(PinRef::new(id, pin))))
+            .collect();
+        draw_component(&mut out, info, options, &values);
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Draws a circuit as a standalone SVG document.
+pub fn render_circuit(circuit: &Circuit, options: &SvgOptions) -> String {
+    render(circuit, options, None, None)
+}
+
+/// Draws the circuit being simulated, colouring wires and pins by their
+/// current values.
+pub fn render_simulation(sim: &Simulator, options: &SvgOptions) -> String {
+    render(sim.circuit(), options, Some(sim), None)
+}
+
+/// Draws a single component, such as for a palette icon.
+pub fn render_component(info: &ComponentInfo, options: &SvgOptions) -> String {
+    let mut circuit = Circuit::new();
+    circuit.add_component(info.clone(), 0, 0);
+    render(&circuit, options, None, None)
+}
+
+/// The width and height in pixels of the square a thumbnail fits within.
+pub const THUMBNAIL_SIZE: u32 = 48;
+
+/// A small preview of a component for a palette, drawn as it is when
+/// created with its default properties. If the component has an SVG icon,
+/// that is returned instead.
+pub fn thumbnail(id: &str, library: &Library) -> Result<String, MissingComponentError> {
+    let metadata = library.get_metadata(id).ok_or_else(|| MissingComponentError { id: id.into() })?;
+    if let Some(Icon::Svg(ref svg)) = metadata.icon {
+        return Ok(svg.to_string());
+    }
+    let info = library.create(id)?;
+    let options = SvgOptions { margin: 1, show_labels: false, ..SvgOptions::default() };
+    let mut circuit = Circuit::new();
+    circuit.add_component(info, 0, 0);
+    Ok(render(&circuit, &options, None, Some(THUMBNAIL_SIZE)))
+}
+
+// The area covered by the primitives, as the minimum and maximum corners.
+// Text is assumed to be about half as wide as it is tall per character.
+fn drawing_bounds(ctx: &DrawContext) -> ((f64, f64), (f64, f64)) {
+    let mut points = Vec::new();
+    let mut around = |(x, y): (f64, f64), (rx, ry): (f64, f64)| {
+        points.push((x - rx, y - ry));
+        points.push((x + rx, y + ry));
+    };
+    for command in ctx.commands() {
+        match command {
+            DrawCommand::Line { from, to, .. } => {
+                around(*from, (0.0, 0.0));
+                around(*to, (0.0, 0.0));
+            },
+            DrawCommand::Arc { center, radius, .. } |
+            DrawCommand::Circle { center, radius, .. } => around(*center, (*radius, *radius)),
+            DrawCommand::Rect { position, size, .. } => {
+                around(*position, (0.0, 0.0));
+                around((position.0 + size.0, position.1 + size.1), (0.0, 0.0));
+            },
+            DrawCommand::Polygon { points, .. } => {
+                for &point in points {
+                    around(point, (0.0, 0.0));
+                }
+            },
+            DrawCommand::Text { center, size, text, .. } => {
+                around(*center, (text.chars().count() as f64 * size * 0.3, size / 2.0));
+            },
+        }
+    }
+    let min = points.iter().fold((0.0f64, 0.0f64), |a, p| (a.0.min(p.0), a.1.min(p.1)));
+    let max = points.iter().fold((0.0f64, 0.0f64), |a, p| (a.0.max(p.0), a.1.max(p.1)));
+    (min, max)
+}
+
+/// Draws the diagram of a component's behaviour, such as the states of a
+/// state machine, as a standalone SVG document. Returns `None` if the
+/// component has no diagram.
+pub fn render_diagram(info: &ComponentInfo, options: &SvgOptions) -> Option<String> {
+    let mut ctx = DrawContext::new();
+    info.draw_diagram(&mut ctx);
+    if ctx.is_empty() {
+        return None;
+    }
+    let ((min_x, min_y), (max_x, max_y)) = drawing_bounds(&ctx);
+    let (s, margin) = (options.scale, options.margin as f64);
+    let (x, y) = ((min_x - margin) * s, (min_y - margin) * s);
+    let (width, height) = ((max_x - min_x + 2.0 * margin) * s, (max_y - min_y + 2.0 * margin) * s);
+
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        x, y, width, height, width, height);
+    write_commands(&mut out, &ctx, s);
+    out.push_str("</svg>\n");
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::component::Interaction;
+    use crate::libraries::default_library;
+    use crate::library::{stub_component, ComponentMetadata};
+
+    #[test]
+    fn render_svg() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(library.create("input_pin").unwrap(), 0, 0);
+        let mut led = library.create("led").unwrap();
+        led.set_property("label", "Ready".into()).unwrap();
+        led.set_property("label_position", "Below".into()).unwrap();
+        circuit.add_component(led, 4, 0);
+        circuit.add_wire(Wire::new((2, 1), (4, 1)));
+        let mut tunnel = library.create("tunnel").unwrap();
+        tunnel.set_property("label", "a < b".into()).unwrap();
+        tunnel.set_property("orientation", "East".into()).unwrap();
+        circuit.add_component(tunnel, 0, 4);
+
+        let svg = render_circuit(&circuit, &SvgOptions::default());
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-20 -20 100 110""#), "{}", svg);
+        assert!(svg.contains("<line x1=\"20\" y1=\"10\" x2=\"40\" y2=\"10\" stroke=\"#222\""));
+        assert!(svg.contains(">a &lt; b</text>"));
+        assert!(svg.contains(r#"<text x="50" y="26" "#), "{}", svg);
+        assert!(svg.contains("rotate(90)"));
+
+        let mut sim = Simulator::new(circuit);
+        sim.interact(input, Interaction::Set(Value::from_u64(1, 1)));
+        sim.run_until_stable().unwrap();
+        let svg = render_simulation(&sim, &SvgOptions { image_base: Some("img/".into()), ..Default::default() });
+        assert!(svg.contains("stroke=\"#2c2\""));
+        assert!(svg.contains(r#"<image href="img/input_pin.svg""#));
+        // The LED draws itself lit
+        assert!(svg.contains("fill=\"#ff2222\""));
+
+        let icon = render_component(&library.create("and_gate").unwrap(), &SvgOptions::default());
+        assert!(icon.contains("AND Gate"));
+
+        assert_eq!(render_diagram(&library.create("and_gate").unwrap(), &SvgOptions::default()), None);
+        let diagram = render_diagram(&library.create("state_machine").unwrap(), &SvgOptions::default()).unwrap();
+        assert!(diagram.contains(">idle</text>"), "{}", diagram);
+    }
+
+    #[test]
+    fn thumbnails() {
+        let mut library = default_library();
+        // An AND gate is 4 units wide and 4 high
+        let svg = thumbnail("and_gate", &library).unwrap();
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-10 -10 60 60" width="48" height="48">"#), "{}", svg);
+        assert!(thumbnail("nope", &library).is_err());
+
+        let icon = r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#;
+        let metadata = ComponentMetadata::new("icon_gate", "Icon Gate", "Gates", "").with_icon(Icon::Svg(icon.into()));
+        library.add(metadata, stub_component);
+        assert_eq!(thumbnail("icon_gate", &library).unwrap(), icon);
+        assert!(thumbnail("and_gate", &library).unwrap().starts_with("<svg"));
+    }
+}
@@ -0,0 +1,321 @@
+//! Automatic routing of wires between pins.
+//!
+//! Routes are found with an A* search over the grid, moving only
+//! horizontally and vertically. Routes may not pass through components, and
+//! may only cross existing wires at right angles, so that they never connect
+//! to anything other than their two ends.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::circuit::{Circuit, ComponentId, WireId, Wire};
+use crate::component::Edge;
+use crate::net::PinRef;
+use crate::undo::Command;
+
+/// The extra cost of a bend, relative to a single step, so that straighter
+/// routes are preferred.
+pub const BEND_COST: u32 = 4;
+/// How far outside the circuit a route may stray, in grid units.
+pub const MARGIN: i32 = 4;
+
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+fn direction_of(edge: Edge) -> usize {
+    match edge {
+        Edge::Right => 0,
+        Edge::Bottom => 1,
+        Edge::Left => 2,
+        Edge::Top => 3,
+    }
+}
+
+// Which directions of existing wire run through each point
+#[derive(Debug, Default, Copy, Clone)]
+struct Occupied {
+    horizontal: bool,
+    vertical: bool,
+    endpoint: bool,
+}
+
+/// Finds routes within a snapshot of a circuit.
+pub struct Router {
+    blocked: HashSet<(i32, i32)>,
+    wires: HashMap<(i32, i32), Occupied>,
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl Router {
+    pub fn new(circuit: &Circuit) -> Self {
+        let mut router = Router {
+            blocked: HashSet::new(),
+            wires: HashMap::new(),
+            min: (0, 0),
+            max: (0, 0),
+        };
+        for (_, info) in circuit.components() {
+            let b = info.bounding_box();
+            for x in b.x..=b.x + b.width {
+                for y in b.y..=b.y + b.height {
+                    router.blocked.insert((x, y));
+                }
+            }
+            router.include((b.x, b.y));
+            router.include((b.x + b.width, b.y + b.height));
+        }
+        for (_, wire) in circuit.wires() {
+            router.add_wire(wire);
+        }
+        router
+    }
+    fn include(&mut self, (x, y): (i32, i32)) {
+        self.min = (self.min.0.min(x - MARGIN), self.min.1.min(y - MARGIN));
+        self.max = (self.max.0.max(x + MARGIN), self.max.1.max(y + MARGIN));
+    }
+    /// Treats a wire as an obstacle for subsequent routes.
+    pub fn add_wire(&mut self, wire: &Wire) {
+        let (dx, dy) = ((wire.end.0 - wire.start.0).signum(), (wire.end.1 - wire.start.1).signum());
+        let mut point = wire.start;
+        loop {
+            let occupied = self.wires.entry(point).or_default();
+            occupied.horizontal |= wire.is_horizontal();
+            occupied.vertical |= wire.is_vertical();
+            if point == wire.end {
+                break;
+            }
+            point = (point.0 + dx, point.1 + dy);
+        }
+        self.wires.entry(wire.start).or_default().endpoint = true;
+        self.wires.entry(wire.end).or_default().endpoint = true;
+        self.include(wire.start);
+        self.include(wire.end);
+    }
+    /// Finds a route between two points. If a point is on the edge of a
+    /// component, the route leaves it in the direction that edge faces.
+    /// Returns `None` if there is no route.
+    pub fn route(&self, from: ((i32, i32), Option<Edge>), to: ((i32, i32), Option<Edge>)) -> Option<Vec<Wire>> {
+        let ((start, start_edge), (goal, goal_edge)) = (from, to);
+        if start == goal {
+            return Some(Vec::new());
+        }
+        let (min, max) = (
+            (self.min.0.min(start.0.min(goal.0) - MARGIN), self.min.1.min(start.1.min(goal.1) - MARGIN)),
+            (self.max.0.max(start.0.max(goal.0) + MARGIN), self.max.1.max(start.1.max(goal.1) + MARGIN)),
+        );
+        let heuristic = |(x, y): (i32, i32)| ((x - goal.0).abs() + (y - goal.1).abs()) as u32;
+        // A route arriving at a pin must approach it from outside
+        let arrival = goal_edge.map(|edge| (direction_of(edge) + 2) % 4);
+
+        // Each state is a point and the direction used to reach it
+        let mut costs: HashMap<((i32, i32), usize), u32> = HashMap::new();
+        let mut previous: HashMap<((i32, i32), usize), ((i32, i32), usize)> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let initial: Vec<usize> = match start_edge {
+            Some(edge) => vec![direction_of(edge)],
+            None => (0..4).collect(),
+        };
+        for direction in initial {
+            costs.insert((start, direction), 0);
+            queue.push(Reverse((heuristic(start), 0, start, direction)));
+        }
+
+        while let Some(Reverse((_, cost, point, direction))) = queue.pop() {
+            if costs.get(&(point, direction)).map_or(false, |&c| c < cost) {
+                continue;
+            }
+            if point == goal {
+                return Some(self.segments(start, (point, direction), &previous));
+            }
+            let occupied = self.wires.get(&point).cloned().unwrap_or_default();
+            for turn in &[0, 1, 3] {
+                let next_direction = (direction + turn) % 4;
+                // Routes may not bend where a wire already runs, as the bend
+                // would join the two.
+                if *turn != 0 && (point == start || occupied.horizontal || occupied.vertical) {
+                    continue;
+                }
+                let (dx, dy) = DIRECTIONS[next_direction];
+                let next = (point.0 + dx, point.1 + dy);
+                if next.0 < min.0 || next.1 < min.1 || next.0 > max.0 || next.1 > max.1 {
+                    continue;
+                }
+                if next == goal {
+                    if arrival.map_or(false, |d| d != next_direction) {
+                        continue;
+                    }
+                } else {
+                    if self.blocked.contains(&next) {
+                        continue;
+                    }
+                    if let Some(o) = self.wires.get(&next) {
+                        let parallel = if dx != 0 { o.horizontal } else { o.vertical };
+                        if o.endpoint || parallel {
+                            continue;
+                        }
+                    }
+                }
+                let next_cost = cost + 1 + if *turn != 0 { BEND_COST } else { 0 };
+                if costs.get(&(next, next_direction)).map_or(true, |&c| next_cost < c) {
+                    costs.insert((next, next_direction), next_cost);
+                    previous.insert((next, next_direction), (point, direction));
+                    queue.push(Reverse((next_cost + heuristic(next), next_cost, next, next_direction)));
+                }
+            }
+        }
+        None
+    }
+    // Converts the path ending at a state into a wire for each straight run
+    fn segments(
+        &self,
+        start: (i32, i32),
+        mut state: ((i32, i32), usize),
+        previous: &HashMap<((i32, i32), usize), ((i32, i32), usize)>,
+    ) -> Vec<Wire> {
+        let mut wires = Vec::new();
+        let mut end = state.0;
+        while state.0 != start {
+            let prev = previous[&state];
+            if prev.0 == start || prev.1 != state.1 {
+                wires.push(Wire::new(prev.0, end));
+                end = prev.0;
+            }
+            state = prev;
+        }
+        wires.reverse();
+        wires
+    }
+}
+
+fn pin_location(circuit: &Circuit, pin: PinRef) -> Option<((i32, i32), Option<Edge>)> {
+    let info = circuit.get_component(pin.component)?;
+    let placed = info.pins().into_iter().nth(pin.pin)?;
+    Some((placed.world, Some(placed.edge)))
+}
+
+/// Finds a route between two pins which avoids components and other wires.
+pub fn route(circuit: &Circuit, from: PinRef, to: PinRef) -> Option<Vec<Wire>> {
+    let from = pin_location(circuit, from)?;
+    let to = pin_location(circuit, to)?;
+    Router::new(circuit).route(from, to)
+}
+
+// Follows a chain of wires from a point, through any simple bends, until it
+// reaches a pin, a junction or a dead end. Returns the wires and the far end.
+fn trace(circuit: &Circuit, pins: &HashSet<(i32, i32)>, start: (i32, i32), first: WireId) -> (Vec<WireId>, (i32, i32)) {
+    let mut chain = vec![first];
+    let mut point = start;
+    let mut wire = first;
+    loop {
+        let w = circuit.get_wire(wire).expect("Traced wires exist");
+        point = if w.start == point { w.end } else { w.start };
+        if pins.contains(&point) {
+            break;
+        }
+        let touching: Vec<_> = circuit.wires()
+            .filter(|&(id, other)| id != wire && other.contains(point))
+            .collect();
+        match touching.as_slice() {
+            [(id, other)] if other.start == point || other.end == point => {
+                if chain.contains(id) {
+                    break;
+                }
+                wire = *id;
+                chain.push(wire);
+            },
+            _ => break,
+        }
+    }
+    (chain, point)
+}
+
+/// Moves a component, rerouting only the wires attached to its pins. Each
+/// chain of wires from a pin to another pin or junction is replaced with a
+/// new route. Chains which cannot be rerouted are left unchanged. Returns
+/// `None` if there is no such component.
+pub fn move_and_reroute(circuit: &Circuit, id: ComponentId, x: i32, y: i32) -> Option<Command> {
+    let info = circuit.get_component(id)?;
+    let all_pins: HashSet<_> = circuit.components()
+        .flat_map(|(_, info)| info.pins().into_iter().map(|pin| pin.world))
+        .collect();
+
+    // Find the chains of wires attached to the component
+    let mut chains = Vec::new();
+    let mut seen = HashSet::new();
+    for pin in info.pins() {
+        for (wire_id, wire) in circuit.wires() {
+            if (wire.start == pin.world || wire.end == pin.world) && !seen.contains(&wire_id) {
+                let (chain, end) = trace(circuit, &all_pins, pin.world, wire_id);
+                seen.extend(chain.iter().cloned());
+                chains.push((pin.index, chain, end));
+            }
+        }
+    }
+
+    // Route in a copy of the circuit with the component moved and the old
+    // wires removed.
+    let mut moved = circuit.clone();
+    moved.move_component(id, x, y);
+    for &(_, ref chain, _) in &chains {
+        for &wire in chain {
+            moved.remove_wire(wire);
+        }
+    }
+    let mut router = Router::new(&moved);
+    let mut commands = vec![Command::MoveComponent { id, x, y }];
+    for (pin, chain, end) in chains {
+        let start = pin_location(&moved, PinRef::new(id, pin))?;
+        let end_edge = circuit.components()
+            .flat_map(|(_, info)| info.pins())
+            .find(|p| p.world == end)
+            .map(|p| p.edge);
+        if let Some(wires) = router.route(start, (end, end_edge)) {
+            commands.extend(chain.into_iter().map(|id| Command::RemoveWire { id }));
+            for wire in wires {
+                router.add_wire(&wire);
+                commands.push(Command::AddWire { wire });
+            }
+        }
+    }
+    Some(Command::Group(commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+    use crate::net::Connectivity;
+
+    #[test]
+    fn route_around_components() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        let b = circuit.add_component(lib.create("and_gate").unwrap(), 20, 10);
+        circuit.add_component(lib.create("or_gate").unwrap(), 10, 3);
+        let output = circuit.get_component(a).unwrap().pins().len() - 1;
+        let (from, to) = (PinRef::new(a, output), PinRef::new(b, 0));
+
+        let wires = route(&circuit, from, to).unwrap();
+        assert!(wires.iter().all(|w| w.is_horizontal() || w.is_vertical()));
+        for pair in wires.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for wire in wires {
+            circuit.add_wire(wire);
+        }
+        let connectivity = Connectivity::build(&circuit);
+        let net = connectivity.net_of_pin(from).unwrap();
+        assert_eq!(connectivity.net_of_pin(to), Some(net));
+        assert_eq!(connectivity.net(net).unwrap().pins.len(), 2);
+
+        // Moving a component only replaces the wires attached to it
+        let other = circuit.add_wire(Wire::new((40, 40), (44, 40)));
+        let command = move_and_reroute(&circuit, b, 24, 20).unwrap();
+        command.apply(&mut circuit).unwrap();
+        assert!(circuit.get_wire(other).is_some());
+        let connectivity = Connectivity::build(&circuit);
+        let net = connectivity.net_of_pin(from).unwrap();
+        assert_eq!(connectivity.net_of_pin(to), Some(net));
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::BTreeSet;
+
+use crate::circuit::{Circuit, ComponentId, WireId, Wire};
+use crate::component::Rect;
+use crate::undo::Command;
+
+/// Something in a circuit which can be selected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Item {
+    Component(ComponentId),
+    Wire(WireId),
+}
+
+impl Circuit {
+    /// Returns the items under a point, with the topmost first. Components
+    /// are drawn above wires, and later components above earlier ones.
+    pub fn hit_test(&self, x: i32, y: i32) -> Vec<Item> {
+        let mut result: Vec<_> = self.components()
+            .filter(|(_, info)| info.bounding_box().contains((x, y)))
+            .map(|(id, _)| Item::Component(id))
+            .collect();
+        result.reverse();
+        result.extend(self.wires()
+            .filter(|(_, wire)| wire.contains((x, y)))
+            .map(|(id, _)| Item::Wire(id)));
+        result
+    }
+    /// Returns the items lying entirely within a rectangle.
+    pub fn items_in(&self, rect: Rect) -> Vec<Item> {
+        let inside = |r: Rect| rect.contains((r.x, r.y)) && rect.contains((r.x + r.width, r.y + r.height));
+        let mut result: Vec<_> = self.components()
+            .filter(|(_, info)| inside(info.bounding_box()))
+            .map(|(id, _)| Item::Component(id))
+            .collect();
+        result.extend(self.wires()
+            .filter(|(_, wire)| rect.contains(wire.start) && rect.contains(wire.end))
+            .map(|(id, _)| Item::Wire(id)));
+        result
+    }
+}
+
+// Rotates a point a quarter turn clockwise about a pivot
+fn rotate_point((x, y): (i32, i32), (px, py): (i32, i32)) -> (i32, i32) {
+    (px - (y - py), py + (x - px))
+}
+
+/// The set of items selected in an editor. Edits to the selection are
+/// returned as commands, so that they can be executed through a
+/// `CommandStack` and undone as a single step.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    items: BTreeSet<Item>,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn items(&self) -> impl Iterator<Item=Item> + '_ {
+        self.items.iter().cloned()
+    }
+    pub fn contains(&self, item: Item) -> bool {
+        self.items.contains(&item)
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+    /// Replaces the selection with a single item.
+    pub fn select(&mut self, item: Item) {
+        self.items.clear();
+        self.items.insert(item);
+    }
+    /// Adds an item to the selection, or removes it if it was already
+    /// selected.
+    pub fn toggle(&mut self, item: Item) {
+        if !self.items.remove(&item) {
+            self.items.insert(item);
+        }
+    }
+    /// Selects the topmost item under a point. If `additive` is set, the item
+    /// is toggled instead of replacing the selection. Clicking on empty space
+    /// clears a non-additive selection. Returns the item which was hit.
+    pub fn click(&mut self, circuit: &Circuit, x: i32, y: i32, additive: bool) -> Option<Item> {
+        let hit = circuit.hit_test(x, y).into_iter().next();
+        match (hit, additive) {
+            (Some(item), true) => self.toggle(item),
+            (Some(item), false) => self.select(item),
+            (None, true) => {},
+            (None, false) => self.clear(),
+        }
+        hit
+    }
+    /// Selects every item lying within a rectangle, such as one dragged out
+    /// with the mouse. If `additive` is set, the items are added to the
+    /// existing selection.
+    pub fn select_rect(&mut self, circuit: &Circuit, rect: Rect, additive: bool) {
+        if !additive {
+            self.items.clear();
+        }
+        self.items.extend(circuit.items_in(rect));
+    }
+    /// Removes any items which no longer exist in the circuit.
+    pub fn retain_existing(&mut self, circuit: &Circuit) {
+        self.items.retain(|&item| match item {
+            Item::Component(id) => circuit.get_component(id).is_some(),
+            Item::Wire(id) => circuit.get_wire(id).is_some(),
+        });
+    }
+    /// The smallest rectangle containing every selected item.
+    pub fn bounding_box(&self, circuit: &Circuit) -> Option<Rect> {
+        let mut points = Vec::new();
+        for item in self.items() {
+            match item {
+                Item::Component(id) => if let Some(info) = circuit.get_component(id) {
+                    let b = info.bounding_box();
+                    points.push((b.x, b.y));
+                    points.push((b.x + b.width, b.y + b.height));
+                },
+                Item::Wire(id) => if let Some(wire) = circuit.get_wire(id) {
+                    points.push(wire.start);
+                    points.push(wire.end);
+                },
+            }
+        }
+        let min_x = points.iter().map(|p| p.0).min()?;
+        let min_y = points.iter().map(|p| p.1).min()?;
+        let max_x = points.iter().map(|p| p.0).max()?;
+        let max_y = points.iter().map(|p| p.1).max()?;
+        Some(Rect { x: min_x, y: min_y, width: max_x - min_x, height: max_y - min_y })
+    }
+
+    // Replaces a wire while preserving its id and uuid
+    fn replace_wire(circuit: &Circuit, id: WireId, wire: Wire) -> Command {
+        let uuid = circuit.wire_uuid(id);
+        Command::Group(vec![Command::RemoveWire { id }, Command::InsertWire { id, wire, uuid }])
+    }
+
+    /// Moves every selected item by an offset.
+    pub fn move_by(&self, circuit: &Circuit, dx: i32, dy: i32) -> Command {
+        let offset = |(x, y): (i32, i32)| (x + dx, y + dy);
+        Command::Group(self.items().filter_map(|item| match item {
+            Item::Component(id) => circuit.get_component(id).map(|info| {
+                let (x, y) = offset(info.position());
+                Command::MoveComponent { id, x, y }
+            }),
+            Item::Wire(id) => circuit.get_wire(id).map(|wire| {
+                Self::replace_wire(circuit, id, Wire::new(offset(wire.start), offset(wire.end)))
+            }),
+        }).collect())
+    }
+    /// Rotates the selection a quarter turn clockwise about the centre of
+    /// its bounding box, turning each component to match.
+    pub fn rotate(&self, circuit: &Circuit) -> Command {
+        let pivot = match self.bounding_box(circuit) {
+            Some(b) => (b.x + b.width / 2, b.y + b.height / 2),
+            None => return Command::Group(Vec::new()),
+        };
+        let mut commands = Vec::new();
+        for item in self.items() {
+            match item {
+                Item::Component(id) => if let Some(info) = circuit.get_component(id) {
+                    // The bottom-left corner becomes the top-left corner
+                    let b = info.bounding_box();
+                    let (x, y) = rotate_point((b.x, b.y + b.height), pivot);
+                    commands.push(Command::rotate(id, info.orientation().rotate_clockwise()));
+                    commands.push(Command::MoveComponent { id, x, y });
+                },
+                Item::Wire(id) => if let Some(wire) = circuit.get_wire(id) {
+                    let wire = Wire::new(rotate_point(wire.start, pivot), rotate_point(wire.end, pivot));
+                    commands.push(Self::replace_wire(circuit, id, wire));
+                },
+            }
+        }
+        Command::Group(commands)
+    }
+    /// Mirrors the selection from left to right about the centre of its
+    /// bounding box, flipping each component to match.
+    pub fn flip(&self, circuit: &Circuit) -> Command {
+        let bounds = match self.bounding_box(circuit) {
+            Some(b) => b,
+            None => return Command::Group(Vec::new()),
+        };
+        let mirror = |x: i32| 2 * bounds.x + bounds.width - x;
+        let mut commands = Vec::new();
+        for item in self.items() {
+            match item {
+                Item::Component(id) => if let Some(info) = circuit.get_component(id) {
+                    let b = info.bounding_box();
+                    commands.push(Command::rotate(id, info.orientation().mirrored()));
+                    commands.push(Command::SetProperty { id, name: "flipped".into(), value: (!info.flipped()).into() });
+                    commands.push(Command::MoveComponent { id, x: mirror(b.x + b.width), y: b.y });
+                },
+                Item::Wire(id) => if let Some(wire) = circuit.get_wire(id) {
+                    let (start, end) = ((mirror(wire.start.0), wire.start.1), (mirror(wire.end.0), wire.end.1));
+                    commands.push(Self::replace_wire(circuit, id, Wire::new(start, end)));
+                },
+            }
+        }
+        Command::Group(commands)
+    }
+    /// Removes every selected item from the circuit.
+    pub fn delete(&self, circuit: &Circuit) -> Command {
+        Command::Group(self.items().filter_map(|item| match item {
+            Item::Component(id) => circuit.get_component(id).map(|_| Command::RemoveComponent { id }),
+            Item::Wire(id) => circuit.get_wire(id).map(|_| Command::RemoveWire { id }),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+    use crate::undo::CommandStack;
+
+    #[test]
+    fn select_and_edit() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let a = circuit.add_component(lib.create("and_gate").unwrap(), 0, 0);
+        let b = circuit.add_component(lib.create("and_gate").unwrap(), 2, 0);
+        let w = circuit.add_wire(Wire::new((10, 0), (10, 4)));
+        assert_eq!(circuit.hit_test(3, 1), vec![Item::Component(b), Item::Component(a)]);
+        assert_eq!(circuit.hit_test(10, 2), vec![Item::Wire(w)]);
+
+        let mut selection = Selection::new();
+        assert_eq!(selection.click(&circuit, 3, 1, false), Some(Item::Component(b)));
+        selection.click(&circuit, 10, 3, true);
+        assert_eq!(selection.len(), 2);
+        selection.click(&circuit, 10, 3, true);
+        assert!(!selection.contains(Item::Wire(w)));
+        selection.click(&circuit, 50, 50, false);
+        assert!(selection.is_empty());
+
+        selection.select_rect(&circuit, Rect { x: 1, y: -1, width: 20, height: 20 }, false);
+        assert_eq!(selection.items().collect::<Vec<_>>(), vec![Item::Component(b), Item::Wire(w)]);
+
+        let mut stack = CommandStack::new();
+        let command = selection.move_by(&circuit, 1, 2);
+        stack.execute(&mut circuit, command).unwrap();
+        assert_eq!(circuit.get_component(b).unwrap().position(), (3, 2));
+        assert_eq!(circuit.get_wire(w), Some(&Wire::new((11, 2), (11, 6))));
+
+        // Rotating four times returns everything to where it started
+        let before = circuit.get_component(b).unwrap().bounding_box();
+        let command = selection.rotate(&circuit);
+        stack.execute(&mut circuit, command).unwrap();
+        assert!(circuit.get_wire(w).unwrap().is_horizontal());
+        for _ in 0..3 {
+            let command = selection.rotate(&circuit);
+            stack.execute(&mut circuit, command).unwrap();
+        }
+        assert_eq!(circuit.get_component(b).unwrap().bounding_box(), before);
+        assert_eq!(circuit.get_wire(w), Some(&Wire::new((11, 2), (11, 6))));
+
+        // Flipping mirrors pins from left to right
+        let pins = |circuit: &Circuit| {
+            let info = circuit.get_component(b).unwrap();
+            let (x, y) = info.position();
+            info.get_shape().pins.iter().map(|p| (p.x + x, p.y + y)).collect::<Vec<_>>()
+        };
+        let before = pins(&circuit);
+        let command = selection.flip(&circuit);
+        stack.execute(&mut circuit, command).unwrap();
+        let bounds = selection.bounding_box(&circuit).unwrap();
+        for (a, b) in before.iter().zip(pins(&circuit)) {
+            assert_eq!((2 * bounds.x + bounds.width - a.0, a.1), b);
+        }
+
+        let command = selection.delete(&circuit);
+        stack.execute(&mut circuit, command).unwrap();
+        assert_eq!(circuit.num_components(), 1);
+        assert_eq!(circuit.num_wires(), 0);
+        selection.retain_existing(&circuit);
+        assert!(selection.is_empty());
+        stack.undo(&mut circuit).unwrap();
+        assert_eq!(circuit.num_wires(), 1);
+    }
+}
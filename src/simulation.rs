@@ -0,0 +1,1532 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::error::Error;
+use std::mem;
+use std::ops::Not;
+use std::time::Duration;
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
+
+use serde_derive::{Serialize, Deserialize};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::{ComponentInfo, PinDirection, PinBit, Interaction, FieldType, ReflectType};
+use crate::net::{Connectivity, DisjointSet, NetId, PinRef};
+use crate::trace::{self, Waveform, Capture, Probe, Instance};
+use crate::render::Framebuffer;
+use crate::audio;
+use crate::debugger::{Debugger, BreakpointId, ConditionError, WatchEvent, Breakpoint};
+
+pub const MAX_ITERATIONS: usize = 1000;
+pub const DEFAULT_TICK_FREQUENCY: f64 = 1.0;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Bit {
+    Zero,
+    One,
+    HighZ,
+    /// Driven, but to a value which cannot be determined, such as the output
+    /// of a gate with an unknown input.
+    Unknown,
+    Error,
+}
+
+impl Bit {
+    pub fn from_bool(v: bool) -> Self {
+        if v { Bit::One } else { Bit::Zero }
+    }
+    pub fn to_bool(self) -> Option<bool> {
+        match self {
+            Bit::Zero => Some(false),
+            Bit::One => Some(true),
+            _ => None,
+        }
+    }
+    pub fn is_defined(self) -> bool {
+        self.to_bool().is_some()
+    }
+    /// Combine two drivers of the same wire. High-impedance yields to the
+    /// other driver, and conflicting drivers produce an error. An unknown
+    /// driver makes the result unknown, unless it is already an error.
+    pub fn resolve(self, other: Bit) -> Bit {
+        match (self, other) {
+            (Bit::HighZ, b) | (b, Bit::HighZ) => b,
+            (a, b) if a == b => a,
+            (Bit::Error, _) | (_, Bit::Error) => Bit::Error,
+            (Bit::Unknown, _) | (_, Bit::Unknown) => Bit::Unknown,
+            _ => Bit::Error,
+        }
+    }
+    pub fn to_char(self) -> char {
+        match self {
+            Bit::Zero => '0',
+            Bit::One => '1',
+            Bit::HighZ => 'Z',
+            Bit::Unknown => 'X',
+            Bit::Error => 'E',
+        }
+    }
+    pub fn from_char(c: char) -> Option<Bit> {
+        match c.to_ascii_uppercase() {
+            '0' => Some(Bit::Zero),
+            '1' => Some(Bit::One),
+            'Z' => Some(Bit::HighZ),
+            'X' => Some(Bit::Unknown),
+            'E' => Some(Bit::Error),
+            _ => None,
+        }
+    }
+}
+
+/// How a net driven by more than one output is resolved.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// Conflicting drivers produce an error.
+    Error,
+    /// Any driver outputting zero pulls the net to zero, as with open
+    /// collector outputs sharing a pull-up.
+    WiredAnd,
+    /// Any driver outputting one pulls the net to one.
+    WiredOr,
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution::Error
+    }
+}
+
+impl ReflectType for Resolution {
+    fn field_type() -> FieldType {
+        use self::Resolution::*;
+        FieldType::for_enum(&[Error, WiredAnd, WiredOr])
+    }
+}
+
+impl Resolution {
+    /// Combine two drivers of the same wire according to this policy.
+    /// High-impedance always yields to the other driver.
+    pub fn resolve(self, a: Bit, b: Bit) -> Bit {
+        let dominant = match self {
+            Resolution::Error => return a.resolve(b),
+            Resolution::WiredAnd => Bit::Zero,
+            Resolution::WiredOr => Bit::One,
+        };
+        match (a, b) {
+            (Bit::HighZ, b) | (b, Bit::HighZ) => b,
+            (a, b) if a == dominant || b == dominant => dominant,
+            _ => a.resolve(b),
+        }
+    }
+}
+
+impl Not for Bit {
+    type Output = Bit;
+    fn not(self) -> Bit {
+        match self {
+            Bit::Zero => Bit::One,
+            Bit::One => Bit::Zero,
+            Bit::Unknown => Bit::Unknown,
+            _ => Bit::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationError {
+    /// The circuit did not settle within the iteration budget. The nets are
+    /// those which were still changing.
+    Oscillation { nets: Vec<NetId> },
+    /// The simulation was paused by a breakpoint. Stepping again continues
+    /// from where it stopped.
+    Breakpoint { id: BreakpointId },
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::Oscillation { nets } => {
+                write!(f, "The circuit is oscillating")?;
+                if !nets.is_empty() {
+                    let nets: Vec<_> = nets.iter().map(|n| n.to_string()).collect();
+                    write!(f, " on nets {}", nets.join(", "))?;
+                }
+                Ok(())
+            },
+            SimulationError::Breakpoint { id } => write!(f, "Stopped at breakpoint {}", id),
+        }
+    }
+}
+
+impl Error for SimulationError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The circuit has changed since the snapshot was taken, so its nets no
+    /// longer match.
+    CircuitChanged,
+    /// The saved state of a component could not be restored.
+    InvalidState { component: ComponentId, message: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::CircuitChanged => write!(f, "The circuit has changed since the snapshot was taken"),
+            SnapshotError::InvalidState { component, message } => {
+                write!(f, "Invalid saved state for component {}: {}", component, message)
+            },
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// The complete state of a running simulation: the value of every net, the
+/// outputs and stored state of every component, and the evaluations which
+/// are still pending. It can be restored into a simulator of the same
+/// circuit to resume from the same point.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimState {
+    time: u64,
+    ticks: u64,
+    partial_tick: f64,
+    evaluations: u64,
+    // The components in the order used by the netlist, or empty if the
+    // simulation had not started
+    components: Vec<ComponentId>,
+    values: Vec<Bit>,
+    outputs: Vec<Vec<Value>>,
+    queue: BTreeMap<u64, BTreeSet<usize>>,
+    states: Vec<(ComponentId, serde_json::Value)>,
+}
+
+impl SimState {
+    /// The simulation time at which the snapshot was taken.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+/// A bit of a net which several outputs are driving to conflicting values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Contention {
+    pub net: NetId,
+    pub bit: u32,
+    /// The output pins which are not high-impedance.
+    pub drivers: Vec<PinRef>,
+}
+
+impl fmt::Display for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let drivers: Vec<_> = self.drivers.iter().map(|pin| format!("{}.{}", pin.component, pin.pin)).collect();
+        write!(f, "Bit {} of net {} is driven by conflicting outputs: {}", self.bit, self.net, drivers.join(", "))
+    }
+}
+
+/// A multi-bit logic value. Bit 0 is the least significant bit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Value {
+    bits: Vec<Bit>,
+}
+
+impl Value {
+    pub fn new(bits: Vec<Bit>) -> Self {
+        Self { bits }
+    }
+    pub fn repeat(bit: Bit, width: u32) -> Self {
+        Self { bits: vec![bit; width as usize] }
+    }
+    pub fn floating(width: u32) -> Self {
+        Self::repeat(Bit::HighZ, width)
+    }
+    pub fn error(width: u32) -> Self {
+        Self::repeat(Bit::Error, width)
+    }
+    pub fn unknown(width: u32) -> Self {
+        Self::repeat(Bit::Unknown, width)
+    }
+    pub fn from_u64(v: u64, width: u32) -> Self {
+        Self {
+            bits: (0..width).map(|i| Bit::from_bool(i < 64 && (v >> i) & 1 != 0)).collect()
+        }
+    }
+    /// Like `from_u64`, but bits beyond the 64th are copied from the sign bit.
+    pub fn from_i64(v: i64, width: u32) -> Self {
+        Self {
+            bits: (0..width).map(|i| Bit::from_bool((v >> i.min(63)) & 1 != 0)).collect()
+        }
+    }
+    pub fn to_u64(&self) -> Option<u64> {
+        let mut result = 0;
+        for (i, bit) in self.bits.iter().enumerate() {
+            if bit.to_bool()? && i < 64 {
+                result |= 1 << i;
+            }
+        }
+        Some(result)
+    }
+    pub fn width(&self) -> u32 {
+        self.bits.len() as u32
+    }
+    pub fn bits(&self) -> &[Bit] {
+        &self.bits
+    }
+    pub fn bit(&self, index: u32) -> Bit {
+        self.bits.get(index as usize).cloned().unwrap_or(Bit::HighZ)
+    }
+    pub fn is_defined(&self) -> bool {
+        self.bits.iter().all(|b| b.is_defined())
+    }
+    pub fn is_floating(&self) -> bool {
+        self.bits.iter().all(|&b| b == Bit::HighZ)
+    }
+    /// Combine two drivers of the same net bit-by-bit. Drivers of
+    /// different widths cannot be combined and produce an error value.
+    pub fn resolve(&self, other: &Value) -> Value {
+        if self.is_floating() && self.width() != other.width() {
+            other.clone()
+        } else if other.is_floating() && self.width() != other.width() {
+            self.clone()
+        } else if self.width() != other.width() {
+            Value::error(self.width().max(other.width()))
+        } else {
+            Value {
+                bits: self.bits.iter().zip(&other.bits).map(|(&a, &b)| a.resolve(b)).collect()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bit in self.bits.iter().rev() {
+            write!(f, "{}", bit.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct NetPin {
+    // The net attached to each bit of the pin
+    pub(crate) nets: Vec<usize>,
+    pub(crate) direction: PinDirection,
+}
+
+/// Connectivity is tracked per bit: a splitter may route a single bit of a
+/// bus onto a different wire, so each net here carries exactly one bit.
+#[derive(Debug, Clone)]
+pub(crate) struct Netlist {
+    pub(crate) components: Vec<(ComponentId, Vec<NetPin>)>,
+    index: HashMap<ComponentId, usize>,
+    // The component, pin and bit of every output driving each net
+    pub(crate) drivers: Vec<Vec<(usize, usize, u32)>>,
+    // The components with an input attached to each net
+    readers: Vec<Vec<usize>>,
+    pub(crate) pulls: Vec<Option<Bit>>,
+    // The policy for resolving each net with more than one driver, if
+    // overridden, and the policy used otherwise
+    pub(crate) resolutions: Vec<Option<Resolution>>,
+    default_resolution: Resolution,
+    // The pairs of nets each component can switch together, and the
+    // components able to switch each net
+    switches: Vec<Vec<(usize, usize)>>,
+    net_switches: Vec<Vec<usize>>,
+    // The time between a component's inputs changing and its outputs
+    // reflecting the change.
+    delays: Vec<u64>,
+    connectivity: Connectivity,
+    // The nets attached to each bit of each net in `connectivity`
+    net_bits: Vec<Vec<usize>>,
+    // Found when first needed by a multi-threaded simulation
+    partitions: Option<Partitions>,
+}
+
+impl Netlist {
+    pub(crate) fn build(circuit: &Circuit) -> Self {
+        // First find which pins are geometrically connected by wires
+        let connectivity = Connectivity::build(circuit);
+
+        // Then assign a node to each bit of each net
+        let mut bits = DisjointSet::default();
+        let mut nodes = HashMap::new();
+        let mut component_pins = Vec::new();
+        let mut pin_nodes: Vec<Vec<Vec<usize>>> = Vec::new();
+        for (id, info) in circuit.components() {
+            let mut shape = info.get_shape();
+            if info.is_annotation() {
+                shape.pins.clear();
+            }
+            pin_nodes.push(shape.pins.iter().enumerate().map(|(i, pin)| {
+                let net = connectivity.net_of_pin(PinRef { component: id, pin: i })
+                    .expect("Every pin has a net");
+                (0..pin.bits).map(|bit| *nodes.entry((net, bit)).or_insert_with(|| bits.add())).collect()
+            }).collect());
+            component_pins.push((id, info, shape.pins.iter().map(|pin| pin.direction).collect::<Vec<_>>()));
+        }
+
+        let mut tunnels: HashMap<String, Vec<usize>> = HashMap::new();
+        for (&(_, info, _), pins) in component_pins.iter().zip(&pin_nodes) {
+            let node = |pb: PinBit| pins.get(pb.pin).and_then(|p| p.get(pb.bit as usize)).cloned();
+            for (a, b) in info.connections() {
+                if let (Some(a), Some(b)) = (node(a), node(b)) {
+                    bits.union(a, b);
+                }
+            }
+            match info.tunnel_label() {
+                Some(ref label) if !label.is_empty() => {
+                    for pin in pins {
+                        let first = tunnels.entry(label.clone()).or_insert_with(|| pin.clone());
+                        for (&a, &b) in first.iter().zip(pin) {
+                            bits.union(a, b);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // Then number the resulting nets
+        let mut nets = HashMap::new();
+        let mut net_of = |bits: &mut DisjointSet, node: usize| {
+            let root = bits.find(node);
+            let next = nets.len();
+            *nets.entry(root).or_insert(next)
+        };
+        let mut pulls = Vec::new();
+        let mut resolutions = Vec::new();
+        let mut components = Vec::new();
+        let mut switches = Vec::new();
+        let mut delays = Vec::new();
+        for ((id, info, pins), nodes) in component_pins.into_iter().zip(pin_nodes) {
+            let pins: Vec<_> = pins.into_iter().zip(nodes).map(|(direction, nodes)| NetPin {
+                nets: nodes.into_iter().map(|n| net_of(&mut bits, n)).collect(),
+                direction,
+            }).collect();
+            if let Some(bit) = info.pull() {
+                pulls.extend(pins.iter().flat_map(|p| p.nets.iter().map(move |&n| (n, bit))));
+            }
+            if let Some(resolution) = info.resolution() {
+                resolutions.extend(pins.iter().flat_map(|p| p.nets.iter().map(move |&n| (n, resolution))));
+            }
+            let net = |pb: PinBit| pins.get(pb.pin).and_then(|p| p.nets.get(pb.bit as usize)).cloned();
+            switches.push(info.switches().into_iter()
+                .filter_map(|(a, b)| Some((net(a)?, net(b)?)))
+                .collect::<Vec<_>>());
+            components.push((id, pins));
+            delays.push(info.delay().max(1));
+        }
+        let net_bits = connectivity.nets().iter().map(|net| {
+            (0..net.width()).map(|bit| net_of(&mut bits, nodes[&(net.id, bit)])).collect()
+        }).collect();
+
+        // Finally record which components drive and read each net
+        let num_nets = nets.len();
+        let mut drivers = vec![Vec::new(); num_nets];
+        let mut readers = vec![Vec::new(); num_nets];
+        let mut index = HashMap::new();
+        for (i, &(id, ref pins)) in components.iter().enumerate() {
+            index.insert(id, i);
+            for (p, pin) in pins.iter().enumerate() {
+                for (bit, &net) in pin.nets.iter().enumerate() {
+                    if pin.direction.is_output() {
+                        drivers[net].push((i, p, bit as u32));
+                    }
+                    if pin.direction.is_input() && readers[net].last() != Some(&i) {
+                        readers[net].push(i);
+                    }
+                }
+            }
+        }
+        let mut net_pulls = vec![None; num_nets];
+        for (net, bit) in pulls {
+            net_pulls[net] = Some(bit);
+        }
+        let mut net_resolutions = vec![None; num_nets];
+        for (net, resolution) in resolutions {
+            net_resolutions[net] = Some(resolution);
+        }
+        let net_switches = switches_by_net(&switches, num_nets);
+
+        Netlist {
+            components,
+            index,
+            drivers,
+            readers,
+            pulls: net_pulls,
+            resolutions: net_resolutions,
+            default_resolution: Resolution::default(),
+            switches,
+            net_switches,
+            delays,
+            connectivity,
+            net_bits,
+            partitions: None,
+        }
+    }
+    // The policy for resolving a group of nets which are joined together
+    fn resolution(&self, group: &[usize]) -> Resolution {
+        group.iter().filter_map(|&n| self.resolutions[n]).next().unwrap_or(self.default_resolution)
+    }
+    // The nets joined to `net` by switches which currently conduct,
+    // including `net` itself
+    fn switched_group<C: NetlistComponents>(&self, net: usize, components: &mut C, values: &[Bit]) -> Vec<usize> {
+        let mut group = vec![net];
+        let mut next = 0;
+        while next < group.len() {
+            let current = group[next];
+            next += 1;
+            for &index in &self.net_switches[current] {
+                let (id, ref pins) = self.components[index];
+                let inputs: Vec<_> = pins.iter()
+                    .map(|pin| Value::new(pin.nets.iter().map(|&n| values[n]).collect()))
+                    .collect();
+                let conducting = components.component(index, id).conducting(&inputs);
+                for (&(a, b), on) in self.switches[index].iter().zip(conducting) {
+                    let other = if a == current { b } else if b == current { a } else { continue };
+                    if on && !group.contains(&other) {
+                        group.push(other);
+                    }
+                }
+            }
+        }
+        group
+    }
+    // Splits the netlist into groups of components which share no nets
+    fn partition(&self) -> Partitions {
+        let mut set = DisjointSet::default();
+        for _ in &self.components {
+            set.add();
+        }
+        let mut first_on_net = vec![None; self.pulls.len()];
+        for (i, (_, pins)) in self.components.iter().enumerate() {
+            for &net in pins.iter().flat_map(|pin| &pin.nets) {
+                match first_on_net[net] {
+                    Some(first) => set.union(first, i),
+                    None => first_on_net[net] = Some(i),
+                }
+            }
+        }
+
+        let mut roots = HashMap::new();
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        let mut of_component = Vec::with_capacity(self.components.len());
+        for i in 0..self.components.len() {
+            let root = set.find(i);
+            let next = roots.len();
+            let p = *roots.entry(root).or_insert(next);
+            if p == members.len() {
+                members.push(Vec::new());
+            }
+            of_component.push((p, members[p].len()));
+            members[p].push(i);
+        }
+
+        let parts = members.into_iter().map(|components| {
+            let mut local_nets = HashMap::new();
+            let mut nets = Vec::new();
+            let mut local_net = |net: usize| *local_nets.entry(net).or_insert_with(|| {
+                nets.push(net);
+                nets.len() - 1
+            });
+            let local_components: Vec<_> = components.iter().map(|&c| {
+                let (id, ref pins) = self.components[c];
+                (id, pins.iter().map(|pin| NetPin {
+                    nets: pin.nets.iter().map(|&n| local_net(n)).collect(),
+                    direction: pin.direction,
+                }).collect())
+            }).collect();
+            let local = |c: usize| of_component[c].1;
+            let switches: Vec<Vec<_>> = components.iter()
+                .map(|&c| self.switches[c].iter().map(|&(a, b)| (local_nets[&a], local_nets[&b])).collect())
+                .collect();
+            let netlist = Netlist {
+                index: local_components.iter().enumerate().map(|(i, &(id, _))| (id, i)).collect(),
+                components: local_components,
+                drivers: nets.iter().map(|&n| self.drivers[n].iter().map(|&(c, p, b)| (local(c), p, b)).collect()).collect(),
+                readers: nets.iter().map(|&n| self.readers[n].iter().map(|&c| local(c)).collect()).collect(),
+                pulls: nets.iter().map(|&n| self.pulls[n]).collect(),
+                resolutions: nets.iter().map(|&n| self.resolutions[n]).collect(),
+                default_resolution: self.default_resolution,
+                net_switches: switches_by_net(&switches, nets.len()),
+                switches,
+                delays: components.iter().map(|&c| self.delays[c]).collect(),
+                connectivity: Connectivity::default(),
+                net_bits: Vec::new(),
+                partitions: None,
+            };
+            Partition { netlist, components, nets }
+        }).collect();
+        Partitions { parts, of_component }
+    }
+}
+
+fn switches_by_net(switches: &[Vec<(usize, usize)>], num_nets: usize) -> Vec<Vec<usize>> {
+    let mut result = vec![Vec::new(); num_nets];
+    for (index, pairs) in switches.iter().enumerate() {
+        for &(a, b) in pairs {
+            for &net in &[a, b] {
+                if result[net].last() != Some(&index) {
+                    result[net].push(index);
+                }
+            }
+        }
+    }
+    result
+}
+
+// A group of components which shares no nets with the rest of the circuit,
+// and so can be simulated separately. Its netlist is numbered locally.
+#[derive(Debug, Clone)]
+struct Partition {
+    netlist: Netlist,
+    // The index in the full netlist of each component and net
+    components: Vec<usize>,
+    nets: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Partitions {
+    parts: Vec<Partition>,
+    // The partition and local index of each component
+    of_component: Vec<(usize, usize)>,
+}
+
+type Queue = BTreeMap<u64, BTreeSet<usize>>;
+
+// Access to the components of a netlist while stepping
+trait NetlistComponents {
+    fn component(&mut self, index: usize, id: ComponentId) -> &mut ComponentInfo;
+}
+
+impl NetlistComponents for Circuit {
+    fn component(&mut self, _index: usize, id: ComponentId) -> &mut ComponentInfo {
+        self.get_component_mut(id).expect("Netlist out of sync with circuit")
+    }
+}
+
+impl<'a> NetlistComponents for Vec<&'a mut ComponentInfo> {
+    fn component(&mut self, index: usize, _id: ComponentId) -> &mut ComponentInfo {
+        &mut *self[index]
+    }
+}
+
+// Evaluates the components due next, and updates the nets from the results,
+// adding those which changed to `changed`. Returns the time of the step, or
+// `None` if nothing was due.
+fn step_netlist<C: NetlistComponents>(
+    netlist: &Netlist,
+    components: &mut C,
+    values: &mut [Bit],
+    outputs: &mut [Vec<Value>],
+    queue: &mut Queue,
+    evaluations: &mut u64,
+    changed: &mut BTreeSet<usize>,
+) -> Option<u64> {
+    let time = *queue.keys().next()?;
+    let due = queue.remove(&time).unwrap_or_default();
+
+    // All components due at the same time see the same input values
+    let mut dirty = BTreeSet::new();
+    for index in due {
+        let (id, ref pins) = netlist.components[index];
+        let info = components.component(index, id);
+        let inputs: Vec<_> = pins.iter()
+            .map(|pin| Value::new(pin.nets.iter().map(|&net| values[net]).collect()))
+            .collect();
+        info.clock_edge(&inputs);
+        let result = info.evaluate(&inputs);
+        *evaluations += 1;
+        // Whether a switch conducts may have changed even if no output did
+        for &(a, b) in &netlist.switches[index] {
+            dirty.insert(a);
+            dirty.insert(b);
+        }
+        if result != outputs[index] {
+            // Values reported for input pins are not driven onto the net
+            for pin in pins.iter().filter(|pin| pin.direction.is_output()) {
+                dirty.extend(pin.nets.iter().cloned());
+            }
+            outputs[index] = result;
+        }
+    }
+
+    // Nets joined by conducting switches are resolved together, from the
+    // drivers of every net in the group
+    let mut resolved = BTreeSet::new();
+    for net in dirty {
+        if resolved.contains(&net) {
+            continue;
+        }
+        let group = netlist.switched_group(net, components, values);
+        let resolution = netlist.resolution(&group);
+        let mut bit = group.iter().flat_map(|&n| &netlist.drivers[n]).fold(Bit::HighZ, |acc, &(c, p, b)| {
+            resolution.resolve(acc, outputs[c].get(p).map(|v| v.bit(b)).unwrap_or(Bit::HighZ))
+        });
+        if bit == Bit::HighZ {
+            bit = group.iter().filter_map(|&n| netlist.pulls[n]).next().unwrap_or(Bit::HighZ);
+        }
+        for net in group {
+            resolved.insert(net);
+            if bit != values[net] {
+                values[net] = bit;
+                changed.insert(net);
+                for &reader in &netlist.readers[net] {
+                    queue.entry(time + netlist.delays[reader])
+                        .or_insert_with(BTreeSet::new)
+                        .insert(reader);
+                }
+            }
+        }
+    }
+    Some(time)
+}
+
+// The state of a partition while it settles separately from the others
+struct Region<'a> {
+    partition: &'a Partition,
+    components: Vec<&'a mut ComponentInfo>,
+    values: Vec<Bit>,
+    outputs: Vec<Vec<Value>>,
+    queue: Queue,
+    evaluations: u64,
+    time: Option<u64>,
+}
+
+impl<'a> Region<'a> {
+    // As for `Simulator::run_until_stable`, returning the nets which were
+    // still changing if the partition does not settle.
+    fn run(&mut self, max_iterations: usize) -> Result<(), BTreeSet<usize>> {
+        let mut changed = BTreeSet::new();
+        for i in 0..max_iterations {
+            if i == max_iterations / 2 {
+                changed.clear();
+            }
+            let time = step_netlist(
+                &self.partition.netlist, &mut self.components, &mut self.values,
+                &mut self.outputs, &mut self.queue, &mut self.evaluations, &mut changed,
+            );
+            if time.is_some() {
+                self.time = time;
+            }
+            if self.queue.is_empty() {
+                return Ok(());
+            }
+        }
+        Err(changed)
+    }
+}
+
+/// Options controlling how a circuit is simulated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatorConfig {
+    /// The number of threads used by `run_until_stable`. With more than one,
+    /// parts of the circuit which share no nets settle independently, which
+    /// gives the same results as settling the circuit as a whole.
+    pub threads: usize,
+    /// How nets with more than one driver are resolved, unless overridden
+    /// for the net by a component attached to it.
+    pub resolution: Resolution,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig { threads: 1, resolution: Resolution::default() }
+    }
+}
+
+/// Evaluates the components of a circuit, propagating values across the
+/// nets formed by its wires and pins. The simulator owns the circuit, and
+/// any structural change made through `circuit_mut` causes the nets to be
+/// rebuilt on the next step.
+///
+/// The simulation is event driven: a component is only evaluated when one of
+/// its inputs changes, or when it reports a change to its own state through
+/// an interaction or a tick. Its outputs then reach the attached nets after
+/// the component's propagation delay.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    circuit: Circuit,
+    netlist: Option<Netlist>,
+    values: Vec<Bit>,
+    // The outputs of each component as of its last evaluation
+    outputs: Vec<Vec<Value>>,
+    // The components due to be evaluated at each point in time
+    queue: BTreeMap<u64, BTreeSet<usize>>,
+    time: u64,
+    evaluations: u64,
+    max_iterations: usize,
+    ticks: u64,
+    tick_frequency: f64,
+    // The fraction of a tick carried over between calls to `run_for`
+    partial_tick: f64,
+    waveform: Waveform,
+    debugger: Debugger,
+    // The breakpoint hit by the last step
+    breakpoint_hit: Option<BreakpointId>,
+    config: SimulatorConfig,
+    #[cfg(feature = "parallel")]
+    pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+impl Simulator {
+    pub fn new(circuit: Circuit) -> Self {
+        Self::with_config(circuit, SimulatorConfig::default())
+    }
+    pub fn with_config(circuit: Circuit, config: SimulatorConfig) -> Self {
+        let mut result = Self {
+            circuit,
+            netlist: None,
+            values: Vec::new(),
+            outputs: Vec::new(),
+            queue: BTreeMap::new(),
+            time: 0,
+            evaluations: 0,
+            max_iterations: MAX_ITERATIONS,
+            ticks: 0,
+            tick_frequency: DEFAULT_TICK_FREQUENCY,
+            partial_tick: 0.0,
+            waveform: Waveform::new(),
+            debugger: Debugger::new(),
+            breakpoint_hit: None,
+            config: SimulatorConfig::default(),
+            #[cfg(feature = "parallel")]
+            pool: None,
+        };
+        result.set_config(config);
+        result
+    }
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+    pub fn set_config(&mut self, config: SimulatorConfig) {
+        #[cfg(feature = "parallel")]
+        {
+            self.pool = if config.threads > 1 {
+                rayon::ThreadPoolBuilder::new().num_threads(config.threads).build().ok().map(Arc::new)
+            } else {
+                None
+            };
+        }
+        // Every net is resolved again under the new policy
+        if let Some(netlist) = self.netlist.as_mut() {
+            if netlist.default_resolution != config.resolution {
+                netlist.default_resolution = config.resolution;
+                netlist.partitions = None;
+                self.outputs = vec![Vec::new(); netlist.components.len()];
+                self.queue.entry(self.time).or_insert_with(BTreeSet::new).extend(0..netlist.components.len());
+            }
+        }
+        self.config = config;
+    }
+    pub fn circuit(&self) -> &Circuit {
+        &self.circuit
+    }
+    pub fn circuit_mut(&mut self) -> &mut Circuit {
+        self.netlist = None;
+        &mut self.circuit
+    }
+    pub fn into_circuit(self) -> Circuit {
+        self.circuit
+    }
+    /// Captures the state of the simulation, to be restored later.
+    pub fn snapshot(&self) -> SimState {
+        let components = self.netlist.as_ref()
+            .map(|netlist| netlist.components.iter().map(|&(id, _)| id).collect())
+            .unwrap_or_default();
+        SimState {
+            time: self.time,
+            ticks: self.ticks,
+            partial_tick: self.partial_tick,
+            evaluations: self.evaluations,
+            components,
+            values: self.values.clone(),
+            outputs: self.outputs.clone(),
+            queue: self.queue.clone(),
+            states: self.circuit.components()
+                .filter_map(|(id, info)| Some((id, info.state()?)))
+                .collect(),
+        }
+    }
+    /// Returns the simulation to a state captured by `snapshot`. The circuit
+    /// must not have been changed structurally in between.
+    pub fn restore(&mut self, state: SimState) -> Result<(), SnapshotError> {
+        self.netlist = None;
+        if !state.components.is_empty() {
+            self.ensure_netlist();
+            let netlist = self.netlist.as_ref().unwrap();
+            let matches = netlist.components.iter().map(|&(id, _)| id).eq(state.components.iter().cloned())
+                && netlist.pulls.len() == state.values.len()
+                && state.outputs.len() == state.components.len()
+                && state.queue.values().flatten().all(|&index| index < state.components.len());
+            if !matches {
+                return Err(SnapshotError::CircuitChanged);
+            }
+        }
+        for (id, component_state) in state.states {
+            let info = self.circuit.get_component_mut(id).ok_or(SnapshotError::CircuitChanged)?;
+            info.set_state(component_state)
+                .map_err(|e| SnapshotError::InvalidState { component: id, message: e.to_string() })?;
+        }
+        self.time = state.time;
+        self.ticks = state.ticks;
+        self.partial_tick = state.partial_tick;
+        self.evaluations = state.evaluations;
+        self.values = state.values;
+        self.outputs = state.outputs;
+        self.queue = state.queue;
+        self.breakpoint_hit = None;
+        self.debugger.reset();
+        Ok(())
+    }
+    pub fn reset(&mut self) {
+        self.netlist = None;
+        self.values.clear();
+        self.outputs.clear();
+        self.queue.clear();
+        self.time = 0;
+        self.ticks = 0;
+        self.partial_tick = 0.0;
+        self.waveform.clear();
+        self.debugger.reset();
+        self.breakpoint_hit = None;
+    }
+    fn ensure_netlist(&mut self) {
+        if self.netlist.is_none() {
+            let mut netlist = Netlist::build(&self.circuit);
+            netlist.default_resolution = self.config.resolution;
+            // Nothing is driven yet, so only pulled nets have a value
+            self.values = netlist.pulls.iter().map(|p| p.unwrap_or(Bit::HighZ)).collect();
+            self.outputs = vec![Vec::new(); netlist.components.len()];
+            // Every component must be evaluated at least once
+            self.queue.clear();
+            self.queue.insert(self.time, (0..netlist.components.len()).collect());
+            self.netlist = Some(netlist);
+        }
+    }
+    fn schedule(&mut self, id: ComponentId) {
+        if let Some(&index) = self.netlist.as_ref().and_then(|n| n.index.get(&id)) {
+            self.queue.entry(self.time).or_insert_with(BTreeSet::new).insert(index);
+        }
+    }
+    /// The current simulation time, measured in the units of
+    /// `Component::delay`.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+    /// The total number of times any component has been evaluated.
+    pub fn evaluations(&self) -> u64 {
+        self.evaluations
+    }
+    /// Evaluates the components which are due to be evaluated next, and
+    /// updates the nets from the results. Returns true if there are further
+    /// evaluations pending, or false if the circuit is stable.
+    pub fn step(&mut self) -> bool {
+        self.step_recording(&mut BTreeSet::new())
+    }
+    // Steps, adding the nets which changed to `changed`
+    fn step_recording(&mut self, changed: &mut BTreeSet<usize>) -> bool {
+        let pending = self.step_nets(changed);
+        if !self.waveform.signals().is_empty() {
+            let mut waveform = std::mem::take(&mut self.waveform);
+            waveform.record(self.time, |probe| self.probe_value(probe));
+            self.waveform = waveform;
+        }
+        if !self.debugger.is_empty() {
+            let mut debugger = std::mem::take(&mut self.debugger);
+            if let Some(id) = debugger.observe(self.time, |pin| self.pin_value(pin)) {
+                self.breakpoint_hit = Some(id);
+            }
+            self.debugger = debugger;
+        }
+        pending
+    }
+    fn step_nets(&mut self, changed: &mut BTreeSet<usize>) -> bool {
+        self.ensure_netlist();
+        let Simulator { circuit, netlist, values, outputs, queue, evaluations, time, .. } = self;
+        if let Some(t) = step_netlist(netlist.as_ref().unwrap(), circuit, values, outputs, queue, evaluations, changed) {
+            *time = t;
+        }
+        !queue.is_empty()
+    }
+    /// Forwards a user interaction to a component without invalidating the
+    /// netlist. Returns true if the component handled it.
+    pub fn interact(&mut self, id: ComponentId, interaction: Interaction) -> bool {
+        let handled = self.circuit.get_component_mut(id)
+            .map(|info| info.interact(interaction))
+            .unwrap_or(false);
+        if handled {
+            self.schedule(id);
+        }
+        handled
+    }
+    pub fn display_value(&self, id: ComponentId) -> Option<Value> {
+        self.circuit.get_component(id).and_then(|info| info.display_value())
+    }
+    /// The samples recorded by a logic analyzer component.
+    pub fn capture(&self, id: ComponentId) -> Option<&Capture> {
+        self.circuit.get_component(id).and_then(|info| info.capture())
+    }
+    /// The tones played by all of the components in the circuit between two
+    /// ticks, mixed into 16-bit PCM samples at the given sample rate. Ticks
+    /// last as long as they do when running at the current tick frequency.
+    pub fn render_audio(&self, start: u64, end: u64, sample_rate: u32) -> Vec<i16> {
+        let tracks: Vec<_> = self.circuit.components()
+            .filter_map(|(_, info)| info.audio())
+            .map(|track| track.render(start, end, 1.0 / self.tick_frequency, sample_rate))
+            .collect();
+        audio::to_pcm(&audio::mix(&tracks))
+    }
+    /// The pixels shown by a display component.
+    pub fn framebuffer(&self, id: ComponentId) -> Option<Framebuffer> {
+        self.circuit.get_component(id).and_then(|info| info.framebuffer())
+    }
+    /// The text shown by a terminal component.
+    pub fn terminal_text(&self, id: ComponentId) -> Option<String> {
+        self.circuit.get_component(id).and_then(|info| info.terminal_text())
+    }
+    /// The nets of the circuit being simulated, once the simulation has
+    /// been stepped.
+    pub fn connectivity(&self) -> Option<&Connectivity> {
+        self.netlist.as_ref().map(|netlist| &netlist.connectivity)
+    }
+    /// The current value of a net, as wide as the widest pin attached to it.
+    pub fn net_value(&self, id: NetId) -> Option<Value> {
+        let netlist = self.netlist.as_ref()?;
+        let bits = netlist.net_bits.get(id.0)?;
+        Some(Value::new(bits.iter().map(|&net| self.values[net]).collect()))
+    }
+    /// The bits of nets which are in error because more than one output is
+    /// driving them, as of the last step. Nets joined by a splitter or tunnel
+    /// are only reported once.
+    pub fn contentions(&self) -> Vec<Contention> {
+        let netlist = match self.netlist.as_ref() {
+            Some(netlist) => netlist,
+            None => return Vec::new(),
+        };
+        let mut seen = BTreeSet::new();
+        let mut result = Vec::new();
+        for (id, bits) in netlist.net_bits.iter().enumerate() {
+            for (bit, &net) in bits.iter().enumerate() {
+                if self.values[net] != Bit::Error || !seen.insert(net) {
+                    continue;
+                }
+                let drivers: BTreeSet<_> = netlist.drivers[net].iter()
+                    .filter(|&&(c, p, b)| self.outputs[c].get(p).map_or(false, |v| v.bit(b) != Bit::HighZ))
+                    .map(|&(c, p, _)| PinRef::new(netlist.components[c].0, p))
+                    .collect();
+                if drivers.len() > 1 {
+                    result.push(Contention { net: NetId(id), bit: bit as u32, drivers: drivers.into_iter().collect() });
+                }
+            }
+        }
+        result
+    }
+    /// The current value of the net attached to a pin.
+    pub fn pin_value(&self, pin: PinRef) -> Option<Value> {
+        let netlist = self.netlist.as_ref()?;
+        let &index = netlist.index.get(&pin.component)?;
+        let nets = &netlist.components[index].1.get(pin.pin)?.nets;
+        Some(Value::new(nets.iter().map(|&net| self.values[net]).collect()))
+    }
+    /// The current value of the net attached to a pin, which may be inside
+    /// a subcircuit instance. Values inside an instance are those of its
+    /// last update.
+    pub fn probe_value(&self, probe: &Probe) -> Option<Value> {
+        let mut sim = self;
+        for &id in &probe.instances {
+            sim = sim.circuit.get_component(id)?.simulator()?;
+        }
+        sim.pin_value(probe.pin)
+    }
+    /// As for `probe_value`, given a path such as `cpu/alu/carry_out`.
+    pub fn path_value(&self, path: &str) -> Option<Value> {
+        self.probe_value(&trace::find_probe(&self.circuit, path)?)
+    }
+    /// The subcircuit instances of the circuit, and those nested within
+    /// them.
+    pub fn instances(&self) -> Vec<Instance> {
+        trace::instances(&self.circuit)
+    }
+    /// Starts recording the value of the net attached to a pin into the
+    /// waveform. The signal is named after the label of the component, if it
+    /// has one. Returns false if the pin does not exist or is already traced.
+    pub fn trace(&mut self, pin: PinRef) -> bool {
+        self.trace_probe(pin.into())
+    }
+    pub fn untrace(&mut self, pin: PinRef) -> bool {
+        self.untrace_probe(&pin.into())
+    }
+    /// As for `trace`, for a pin which may be inside a subcircuit instance.
+    /// The signal is named by its path.
+    pub fn trace_probe(&mut self, probe: Probe) -> bool {
+        let width = match trace::probe_width(&self.circuit, &probe) {
+            Some(width) => width,
+            None => return false,
+        };
+        let name = trace::probe_path(&self.circuit, &probe).unwrap_or_default();
+        self.waveform.add_signal(name, probe, width)
+    }
+    pub fn untrace_probe(&mut self, probe: &Probe) -> bool {
+        self.waveform.remove_signal(probe)
+    }
+    /// The values recorded for the traced pins since the simulation was
+    /// last reset.
+    pub fn waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+    /// Adds a breakpoint which stops `run_until_stable`, and so `tick` and
+    /// `run_for`, at the step where its condition becomes true. See
+    /// `debugger` for the syntax of conditions.
+    pub fn add_breakpoint(&mut self, condition: &str) -> Result<BreakpointId, ConditionError> {
+        self.debugger.add_breakpoint(&self.circuit, condition)
+    }
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.debugger.remove_breakpoint(id)
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.debugger.breakpoints()
+    }
+    /// The breakpoint hit by the last call to `step`, if any.
+    pub fn take_breakpoint_hit(&mut self) -> Option<BreakpointId> {
+        self.breakpoint_hit.take()
+    }
+    /// Starts reporting changes to a signal, named as for breakpoints.
+    pub fn add_watch(&mut self, name: &str) -> Result<(), ConditionError> {
+        self.debugger.add_watch(&self.circuit, name)
+    }
+    pub fn remove_watch(&mut self, name: &str) -> bool {
+        self.debugger.remove_watch(name)
+    }
+    /// The changes to watched signals at each step since this was last
+    /// called.
+    pub fn take_watch_events(&mut self) -> Vec<WatchEvent> {
+        self.debugger.take_events()
+    }
+    /// The number of ticks since the simulation started.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+    /// The number of ticks per second of real time used by `run_for`.
+    pub fn tick_frequency(&self) -> f64 {
+        self.tick_frequency
+    }
+    pub fn set_tick_frequency(&mut self, frequency: f64) {
+        assert!(frequency > 0.0, "Tick frequency must be positive");
+        self.tick_frequency = frequency;
+    }
+    /// Advances every clocked component by one tick, and then steps until
+    /// the circuit settles.
+    pub fn tick(&mut self) -> Result<(), SimulationError> {
+        self.ensure_netlist();
+        let changed: Vec<_> = self.circuit.components_mut()
+            .filter_map(|(id, info)| if !info.is_annotation() && info.tick() { Some(id) } else { None })
+            .collect();
+        for id in changed {
+            self.schedule(id);
+        }
+        self.ticks += 1;
+        self.run_until_stable()
+    }
+    /// Performs as many ticks as fit into the given amount of real time at
+    /// the current tick frequency, stopping at the first error.
+    pub fn run_for(&mut self, elapsed: Duration) -> Result<(), SimulationError> {
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        let ticks = elapsed * self.tick_frequency + self.partial_tick;
+        self.partial_tick = ticks.fract();
+        for _ in 0..ticks as u64 {
+            self.tick()?;
+        }
+        Ok(())
+    }
+    /// The number of steps `run_until_stable` may take before the circuit is
+    /// considered to be oscillating.
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+    pub fn set_max_iterations(&mut self, max_iterations: usize) {
+        self.max_iterations = max_iterations;
+    }
+    /// Steps until no net changes. If the circuit is still changing after
+    /// `max_iterations` steps, the nets which changed during the second half
+    /// of those steps are reported as oscillating.
+    ///
+    /// Breakpoints, watches and waveforms need to see each step of the
+    /// circuit as a whole, so while any are in use the circuit is settled on
+    /// a single thread.
+    pub fn run_until_stable(&mut self) -> Result<(), SimulationError> {
+        if self.config.threads > 1 && self.waveform.signals().is_empty() && self.debugger.is_empty() {
+            self.ensure_netlist();
+            let netlist = self.netlist.as_mut().unwrap();
+            if netlist.partitions.is_none() {
+                netlist.partitions = Some(netlist.partition());
+            }
+            if netlist.partitions.as_ref().map_or(0, |p| p.parts.len()) > 1 {
+                return self.run_partitioned();
+            }
+        }
+        let mut changed = BTreeSet::new();
+        for i in 0..self.max_iterations {
+            if i == self.max_iterations / 2 {
+                changed.clear();
+            }
+            let pending = self.step_recording(&mut changed);
+            if let Some(id) = self.breakpoint_hit.take() {
+                return Err(SimulationError::Breakpoint { id });
+            }
+            if !pending {
+                return Ok(());
+            }
+        }
+        Err(self.oscillation(&changed))
+    }
+    fn oscillation(&self, changed: &BTreeSet<usize>) -> SimulationError {
+        let netlist = self.netlist.as_ref().unwrap();
+        let nets = netlist.net_bits.iter().enumerate()
+            .filter(|(_, bits)| bits.iter().any(|bit| changed.contains(bit)))
+            .map(|(i, _)| NetId(i))
+            .collect();
+        SimulationError::Oscillation { nets }
+    }
+    // Settles each partition of the circuit separately, on the thread pool if
+    // there is one. Each partition is allowed `max_iterations` steps.
+    fn run_partitioned(&mut self) -> Result<(), SimulationError> {
+        let Simulator { circuit, netlist, values, outputs, queue, evaluations, time, max_iterations, .. } = self;
+        let netlist = netlist.as_ref().unwrap();
+        let partitions = netlist.partitions.as_ref().unwrap();
+
+        let mut slots: Vec<Vec<Option<&mut ComponentInfo>>> = partitions.parts.iter()
+            .map(|p| p.components.iter().map(|_| None).collect())
+            .collect();
+        for (id, info) in circuit.components_mut() {
+            if let Some(&index) = netlist.index.get(&id) {
+                let (p, local) = partitions.of_component[index];
+                slots[p][local] = Some(info);
+            }
+        }
+        let mut regions: Vec<_> = partitions.parts.iter().zip(slots).map(|(partition, slots)| Region {
+            partition,
+            components: slots.into_iter().map(|c| c.expect("Netlist out of sync with circuit")).collect(),
+            values: partition.nets.iter().map(|&n| values[n]).collect(),
+            outputs: partition.components.iter().map(|&c| mem::take(&mut outputs[c])).collect(),
+            queue: Queue::new(),
+            evaluations: 0,
+            time: None,
+        }).collect();
+        for (t, due) in mem::take(queue) {
+            for index in due {
+                let (p, local) = partitions.of_component[index];
+                regions[p].queue.entry(t).or_insert_with(BTreeSet::new).insert(local);
+            }
+        }
+
+        let max_iterations = *max_iterations;
+        #[cfg(feature = "parallel")]
+        let results: Vec<_> = match self.pool {
+            Some(ref pool) => pool.install(|| regions.par_iter_mut().map(|r| r.run(max_iterations)).collect()),
+            None => regions.iter_mut().map(|r| r.run(max_iterations)).collect(),
+        };
+        #[cfg(not(feature = "parallel"))]
+        let results: Vec<_> = regions.iter_mut().map(|r| r.run(max_iterations)).collect();
+
+        let mut changed = BTreeSet::new();
+        for (region, result) in regions.into_iter().zip(results) {
+            let partition = region.partition;
+            for (&net, value) in partition.nets.iter().zip(region.values) {
+                values[net] = value;
+            }
+            for (&c, output) in partition.components.iter().zip(region.outputs) {
+                outputs[c] = output;
+            }
+            for (t, due) in region.queue {
+                queue.entry(t).or_insert_with(BTreeSet::new).extend(due.into_iter().map(|local| partition.components[local]));
+            }
+            *evaluations += region.evaluations;
+            *time = region.time.map_or(*time, |t| t.max(*time));
+            if let Err(nets) = result {
+                changed.extend(nets.into_iter().map(|local| partition.nets[local]));
+            }
+        }
+        if changed.is_empty() && self.queue.is_empty() {
+            Ok(())
+        } else {
+            Err(self.oscillation(&changed))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::component::{Component, Properties, ComponentInfo, Schema, PropertyError, Shape, Pin, PinDirection, PinValues};
+    use crate::library::ComponentMetadata;
+    use crate::libraries::gates;
+
+    #[derive(Debug, Clone)]
+    struct Driver(Value);
+
+    impl Properties for Driver {
+        fn schema(&self) -> Schema {
+            Schema::new()
+        }
+        fn set_property(&mut self, name: &str, _value: serde_json::Value) -> Result<(), PropertyError> {
+            Err(PropertyError::unknown(name))
+        }
+        fn get_property(&self, _name: &str) -> Option<serde_json::Value> {
+            None
+        }
+    }
+
+    impl Component for Driver {
+        fn get_shape(&self) -> Shape {
+            Shape {
+                width: 1,
+                height: 1,
+                pins: vec![Pin {
+                    x: 0,
+                    y: 0,
+                    name: "out".into(),
+                    bits: self.0.width(),
+                    direction: PinDirection::Output,
+                }],
+                image_name: "driver".into(),
+            }
+        }
+        fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+            vec![self.0.clone()]
+        }
+    }
+
+    fn driver(v: u64) -> ComponentInfo {
+        let metadata = ComponentMetadata::new("driver", "Driver", "Test", "");
+        ComponentInfo::new(Box::new(Driver(Value::from_u64(v, 1))), Arc::new(metadata))
+    }
+
+    #[test]
+    fn resolve_drivers() {
+        let a = Value::new(vec![Bit::Zero, Bit::HighZ, Bit::One]);
+        let b = Value::new(vec![Bit::HighZ, Bit::One, Bit::Zero]);
+        assert_eq!(a.resolve(&b).to_string(), "E10");
+        let c = Value::new(vec![Bit::Unknown, Bit::Unknown, Bit::HighZ]);
+        assert_eq!(c.resolve(&a).to_string(), "1XX");
+        assert_eq!(Bit::Unknown.resolve(Bit::Error), Bit::Error);
+        assert_eq!(Value::from_u64(5, 4).to_u64(), Some(5));
+        assert_eq!(Value::from_i64(-2, 4).to_u64(), Some(14));
+        assert_eq!(Value::from_i64(-2, 66).bit(65), Bit::One);
+        assert_eq!(Value::from_i64(2, 66).bit(65), Bit::Zero);
+        assert_eq!(Value::floating(2).resolve(&Value::from_u64(1, 4)).to_u64(), Some(1));
+    }
+
+    #[test]
+    fn run_for_carries_partial_ticks() {
+        let mut sim = Simulator::new(Circuit::new());
+        sim.set_tick_frequency(10.0);
+        sim.run_for(Duration::from_millis(250)).unwrap();
+        assert_eq!(sim.ticks(), 2);
+        sim.run_for(Duration::from_millis(50)).unwrap();
+        assert_eq!(sim.ticks(), 3);
+    }
+
+    #[test]
+    fn only_changed_components_are_evaluated() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(library.create("input_pin").unwrap(), -2, 0);
+        let chain: Vec<_> = (0..10)
+            .map(|i| circuit.add_component(library.create("not_gate").unwrap(), i * 3, 0))
+            .collect();
+        let unrelated = circuit.add_component(library.create("not_gate").unwrap(), 0, 10);
+        let last = chain[9];
+
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(last, 1)).unwrap().to_u64(), Some(0));
+        let evaluations = sim.evaluations();
+
+        assert!(sim.interact(input, Interaction::Press));
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(last, 1)).unwrap().to_u64(), Some(1));
+        assert_eq!(sim.evaluations() - evaluations, 11);
+        assert_eq!(sim.pin_value(PinRef::new(unrelated, 1)).unwrap().bits(), &[Bit::Error]);
+    }
+
+    #[test]
+    fn partitions_match_single_thread() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let mut inputs = Vec::new();
+        for row in 0..3 {
+            inputs.push(circuit.add_component(library.create("input_pin").unwrap(), -2, row * 10));
+            for i in 0..4 {
+                circuit.add_component(library.create("not_gate").unwrap(), i * 3, row * 10);
+            }
+        }
+        circuit.add_component(library.create("counter").unwrap(), 0, 40);
+        circuit.add_component(library.create("clock").unwrap(), -2, 42);
+
+        let run = |threads| {
+            let mut sim = Simulator::with_config(circuit.clone(), SimulatorConfig { threads, ..SimulatorConfig::default() });
+            for &input in &inputs[1..] {
+                sim.interact(input, Interaction::Press);
+                sim.tick().unwrap();
+            }
+            for _ in 0..5 {
+                sim.tick().unwrap();
+            }
+            let pins: Vec<_> = sim.circuit().components()
+                .flat_map(|(id, info)| (0..info.get_shape().pins.len()).map(move |p| PinRef::new(id, p)))
+                .collect();
+            let values: Vec<_> = pins.into_iter().map(|pin| sim.pin_value(pin)).collect();
+            (values, sim.time(), sim.evaluations())
+        };
+        let single = run(1);
+        assert_eq!(run(4), single);
+        let mut sim = Simulator::with_config(circuit.clone(), SimulatorConfig { threads: 2, ..SimulatorConfig::default() });
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.netlist.as_ref().unwrap().partitions.as_ref().unwrap().parts.len(), 4);
+    }
+
+    #[test]
+    fn snapshots_resume() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let counter = circuit.add_component(library.create("counter").unwrap(), 0, 0);
+        circuit.add_component(library.create("clock").unwrap(), -2, 2);
+        let q = PinRef::new(counter, 3);
+
+        let mut sim = Simulator::new(circuit);
+        for _ in 0..5 {
+            sim.tick().unwrap();
+        }
+        let saved = serde_json::to_string(&sim.snapshot()).unwrap();
+        let count = |sim: &mut Simulator| {
+            for _ in 0..6 {
+                sim.tick().unwrap();
+            }
+            sim.pin_value(q).unwrap().to_u64()
+        };
+        let expected = count(&mut sim);
+        assert_eq!(expected, Some(5));
+
+        let state: SimState = serde_json::from_str(&saved).unwrap();
+        assert_eq!(state.ticks(), 5);
+        sim.restore(state.clone()).unwrap();
+        assert_eq!(count(&mut sim), expected);
+
+        // A fresh simulator of the same circuit resumes from the same point
+        let mut other = Simulator::new(sim.circuit().clone());
+        other.restore(state.clone()).unwrap();
+        assert_eq!(count(&mut other), expected);
+
+        sim.circuit_mut().add_component(library.create("not_gate").unwrap(), 10, 10);
+        assert_eq!(sim.restore(state), Err(SnapshotError::CircuitChanged));
+    }
+
+    #[test]
+    fn oscillation_is_reported() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let and = circuit.add_component(library.create("and_gate").unwrap(), 0, 0);
+        let pins: Vec<_> = circuit.get_component(and).unwrap().get_shape().pins
+            .iter().map(|p| (p.x, p.y)).collect();
+        let enable = circuit.add_component(library.create("input_pin").unwrap(), pins[0].0 - 2, pins[0].1 - 1);
+        let ((ix, iy), (ox, oy)) = (pins[1], pins[2]);
+        let not = circuit.add_component(library.create("not_gate").unwrap(), ox, oy - 1);
+        // Feed the output of the NOT gate back into the AND gate
+        circuit.add_wire(Wire::new((ox + 3, oy), (ox + 3, iy + 2)));
+        circuit.add_wire(Wire::new((ox + 3, iy + 2), (ix - 1, iy + 2)));
+        circuit.add_wire(Wire::new((ix - 1, iy + 2), (ix - 1, iy)));
+        circuit.add_wire(Wire::new((ix - 1, iy), (ix, iy)));
+
+        let mut sim = Simulator::new(circuit);
+        sim.set_max_iterations(50);
+        sim.run_until_stable().unwrap();
+        sim.interact(enable, Interaction::Press);
+        let nets = match sim.run_until_stable().unwrap_err() {
+            SimulationError::Oscillation { nets } => nets,
+            e => panic!("Unexpected error: {}", e),
+        };
+        let connectivity = sim.connectivity().unwrap();
+        let net = |id, pin| connectivity.net_of_pin(PinRef::new(id, pin)).unwrap();
+        assert!(nets.contains(&net(not, 1)));
+        assert!(nets.contains(&net(and, 2)));
+        assert!(!nets.contains(&net(enable, 0)));
+    }
+
+    #[test]
+    fn delays_show_hazards() {
+        // A AND NOT A is always zero once settled, but briefly becomes one
+        // when A rises, for as long as the NOT gate takes to switch
+        let glitch = |delay: u64| {
+            let library = crate::libraries::default_library();
+            let mut circuit = Circuit::new();
+            let and = circuit.add_component(library.create("and_gate").unwrap(), 0, 0);
+            let pins: Vec<_> = circuit.get_component(and).unwrap().get_shape().pins
+                .iter().map(|p| (p.x, p.y)).collect();
+            let ((ax, ay), (bx, by)) = (pins[0], pins[1]);
+            let mut not = library.create("not_gate").unwrap();
+            not.set_property("delay", delay.into()).unwrap();
+            circuit.add_component(not, bx - 3, by - 1);
+            let input = circuit.add_component(library.create("input_pin").unwrap(), ax - 6, ay - 1);
+            circuit.add_wire(Wire::new((ax - 4, ay), (ax, ay)));
+            circuit.add_wire(Wire::new((ax - 4, ay), (ax - 4, by)));
+            circuit.add_wire(Wire::new((ax - 4, by), (bx - 3, by)));
+
+            let mut sim = Simulator::new(circuit);
+            sim.run_until_stable().unwrap();
+            let out = PinRef::new(and, 2);
+            assert_eq!(sim.pin_value(out).unwrap().to_u64(), Some(0));
+            assert!(sim.interact(input, Interaction::Press));
+            let mut high = Vec::new();
+            loop {
+                let pending = sim.step();
+                if sim.pin_value(out).unwrap().to_u64() == Some(1) {
+                    high.push(sim.time());
+                }
+                if !pending {
+                    break;
+                }
+            }
+            assert_eq!(sim.pin_value(out).unwrap().to_u64(), Some(0));
+            let start = high.first().cloned().unwrap_or(sim.time());
+            sim.time() - start
+        };
+        assert_eq!(glitch(1), 1);
+        assert_eq!(glitch(3), 3);
+    }
+
+    #[test]
+    fn or_gate_computes_output() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let gate = circuit.add_component(lib.create("or_gate").unwrap(), 0, 0);
+        let inputs: Vec<_> = circuit.get_component(gate).unwrap().get_shape().pins
+            .iter().take(2).map(|p| (p.x, p.y)).collect();
+        circuit.add_component(driver(1), inputs[0].0 - 2, inputs[0].1);
+        circuit.add_wire(Wire::new((inputs[0].0 - 2, inputs[0].1), inputs[0]));
+        let zero = circuit.add_component(driver(0), inputs[1].0, inputs[1].1);
+
+        let mut sim = Simulator::new(circuit);
+        assert!(sim.net_value(NetId(0)).is_none());
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(gate, 2)).unwrap().to_u64(), Some(1));
+        let net = sim.connectivity().unwrap().net_of_pin(PinRef::new(gate, 0)).unwrap();
+        assert_eq!(sim.net_value(net).unwrap().to_u64(), Some(1));
+
+        sim.circuit_mut().remove_component(zero);
+        sim.circuit_mut().add_component(driver(1), inputs[0].0, inputs[0].1);
+        sim.circuit_mut().add_component(driver(0), inputs[0].0, inputs[0].1);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.pin_value(PinRef::new(gate, 0)).unwrap().bits(), &[Bit::Error]);
+        assert_eq!(sim.pin_value(PinRef::new(gate, 2)).unwrap().bits(), &[Bit::Error]);
+    }
+}
@@ -0,0 +1,562 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::error::Error;
+
+use serde_json;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::{
+    Component, Properties, Schema, PropertyError, Shape, Pin, PinDirection, Interaction, FieldSchema, FieldType, PinValues,
+};
+use crate::library::{Library, ComponentMetadata};
+use crate::libraries::{spaced_height, spaced_y};
+use crate::simulation::{Simulator, Value};
+use crate::export::verilog::{identifier, HdlEmit, ModuleWriter, ExportError};
+use crate::render::DrawContext;
+use crate::parameters::{self, ElaborationError};
+
+pub const CATEGORY: &'static str = "Subcircuits";
+
+pub(crate) const INPUT_PIN: &'static str = "input_pin";
+pub(crate) const OUTPUT_PIN: &'static str = "output_pin";
+
+// The input or output pin components of a circuit, ordered from top to
+// bottom and then left to right, along with their widths.
+pub(crate) fn ports(circuit: &Circuit, id: &str) -> Vec<(ComponentId, u32)> {
+    let mut result: Vec<_> = circuit.components()
+        .filter(|(_, info)| info.metadata().id == id)
+        .map(|(cid, info)| {
+            let (x, y) = info.position();
+            let bits = info.get_shape().pins.get(0).map(|p| p.bits).unwrap_or(1);
+            ((y, x), cid, bits)
+        })
+        .collect();
+    result.sort();
+    result.into_iter().map(|(_, cid, bits)| (cid, bits)).collect()
+}
+
+// The names of the input and output ports of a circuit. Pins are named after
+// their labels where these form unique identifiers, and are otherwise named
+// `in0`, `out0` and so on.
+pub(crate) fn port_names(circuit: &Circuit) -> (Vec<String>, Vec<String>) {
+    let (inputs, outputs) = (ports(circuit, INPUT_PIN), ports(circuit, OUTPUT_PIN));
+    let labels: Vec<Option<String>> = inputs.iter().chain(&outputs)
+        .map(|&(id, _)| circuit.get_component(id).and_then(|info| info.label()).map(|l| identifier(&l)))
+        .collect();
+    // Labels which could be mistaken for net names are not used either
+    let is_net = |name: &str| name.starts_with('n') && name.len() > 1 && name[1..].chars().all(|c| c.is_ascii_digit());
+    let mut used = BTreeSet::new();
+    let accepted: Vec<_> = labels.iter().map(|label| match label {
+        Some(l) if !is_net(l) && labels.iter().filter(|&other| other == label).count() == 1 => {
+            used.insert(l.clone());
+            Some(l.clone())
+        },
+        _ => None,
+    }).collect();
+    let mut name = |index: usize, prefix: &str, i: usize| {
+        accepted[index].clone().unwrap_or_else(|| {
+            let mut fallback = format!("{}{}", prefix, i);
+            while !used.insert(fallback.clone()) {
+                fallback.push('_');
+            }
+            fallback
+        })
+    };
+    let input_names = (0..inputs.len()).map(|i| name(i, "in", i)).collect();
+    let output_names = (0..outputs.len()).map(|i| name(inputs.len() + i, "out", i)).collect();
+    (input_names, output_names)
+}
+
+/// A circuit used as a component. Its inputs and outputs correspond to the
+/// input and output pins of the inner circuit, which is simulated to
+/// completion whenever the component is updated. It is drawn using the
+/// symbol appearance of the inner circuit, if it has one.
+///
+/// The parameters of a generic circuit are properties of the component.
+/// Changing one simulates a newly elaborated copy of the circuit.
+#[derive(Debug, Clone)]
+pub struct SubcircuitComponent {
+    name: String,
+    // The circuit before its parameters are applied
+    template: Circuit,
+    arguments: BTreeMap<String, i64>,
+    simulator: Simulator,
+    inputs: Vec<(ComponentId, u32)>,
+    outputs: Vec<(ComponentId, u32)>,
+    input_names: Vec<String>,
+    output_names: Vec<String>,
+    output_values: Vec<Value>,
+}
+
+impl SubcircuitComponent {
+    /// Fails if the circuit cannot be elaborated with the default values of
+    /// its parameters.
+    pub fn new(name: String, circuit: Circuit) -> Result<Self, ElaborationError> {
+        let elaborated = parameters::elaborate(&circuit, &BTreeMap::new())?;
+        let mut result = Self {
+            name,
+            template: circuit,
+            arguments: BTreeMap::new(),
+            simulator: Simulator::new(Circuit::new()),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            input_names: Vec::new(),
+            output_names: Vec::new(),
+            output_values: Vec::new(),
+        };
+        result.instantiate(elaborated);
+        Ok(result)
+    }
+    fn instantiate(&mut self, circuit: Circuit) {
+        self.inputs = ports(&circuit, INPUT_PIN);
+        self.outputs = ports(&circuit, OUTPUT_PIN);
+        self.output_values = self.outputs.iter().map(|&(_, bits)| Value::floating(bits)).collect();
+        let (input_names, output_names) = port_names(&circuit);
+        self.input_names = input_names;
+        self.output_names = output_names;
+        self.simulator = Simulator::new(circuit);
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The value of each parameter of the circuit for this instance.
+    pub fn arguments(&self) -> BTreeMap<String, i64> {
+        let mut result = self.template.parameters().defaults();
+        result.extend(self.arguments.iter().map(|(name, &value)| (name.clone(), value)));
+        result
+    }
+    // Instances with different parameters are different modules
+    fn module_name(&self) -> String {
+        let mut result = self.name.clone();
+        for (name, value) in self.arguments() {
+            result.push_str(&format!("_{}_{}", name, value));
+        }
+        result
+    }
+    pub fn circuit(&self) -> &Circuit {
+        self.simulator.circuit()
+    }
+}
+
+impl Properties for SubcircuitComponent {
+    fn schema(&self) -> Schema {
+        self.template.parameters().declared.iter().map(|p| {
+            (p.name.clone().into(), FieldSchema::new(p.name.clone(), FieldType::Integer { min: p.min, max: p.max, unit: None }))
+        }).collect()
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        let parameter = self.template.parameters().get(name).ok_or_else(|| PropertyError::unknown(name))?;
+        let value: i64 = serde_json::from_value(value)
+            .map_err(|e| PropertyError::from_serde(e, name))?;
+        if value < parameter.min || value > parameter.max {
+            return Err(PropertyError::invalid(name, format!("Must be between {} and {}", parameter.min, parameter.max)));
+        }
+        let mut arguments = self.arguments.clone();
+        arguments.insert(name.into(), value);
+        let circuit = parameters::elaborate(&self.template, &arguments)
+            .map_err(|e| PropertyError::invalid(name, e.to_string()))?;
+        self.arguments = arguments;
+        self.instantiate(circuit);
+        Ok(())
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        self.arguments().get(name).map(|&value| value.into())
+    }
+}
+
+impl Component for SubcircuitComponent {
+    fn get_shape(&self) -> Shape {
+        let appearance = self.circuit().appearance();
+        let (num_inputs, num_outputs) = (self.inputs.len() as u32, self.outputs.len() as u32);
+        let (width, height) = match appearance {
+            Some(appearance) => (appearance.width, appearance.height),
+            None => (4, spaced_height(num_inputs.max(num_outputs))),
+        };
+        let mut pins: Vec<_> = self.inputs.iter().enumerate().map(|(i, &(_, bits))| Pin {
+            x: 0,
+            y: spaced_y(i as u32, num_inputs),
+            name: self.input_names[i].clone(),
+            bits,
+            direction: PinDirection::Input,
+        }).collect();
+        pins.extend(self.outputs.iter().enumerate().map(|(i, &(_, bits))| Pin {
+            x: width,
+            y: spaced_y(i as u32, num_outputs),
+            name: self.output_names[i].clone(),
+            bits,
+            direction: PinDirection::Output,
+        }));
+        if let Some(appearance) = appearance {
+            for pin in &mut pins {
+                if let Some((x, y)) = appearance.placement(&pin.name) {
+                    pin.x = x;
+                    pin.y = y;
+                }
+            }
+        }
+        Shape {
+            width,
+            height,
+            pins,
+            image_name: "subcircuit".into(),
+        }
+    }
+    fn draw(&self, ctx: &mut DrawContext) {
+        if let Some(appearance) = self.circuit().appearance() {
+            appearance.draw(ctx, &self.name);
+        }
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        for (&(id, bits), value) in self.inputs.iter().zip(inputs) {
+            if value.width() == bits {
+                self.simulator.interact(id, Interaction::Set(value.clone()));
+            }
+        }
+        let stable = self.simulator.run_until_stable().is_ok();
+        for (&(id, bits), value) in self.outputs.iter().zip(&mut self.output_values) {
+            *value = if stable {
+                self.simulator.display_value(id).unwrap_or_else(|| Value::floating(bits))
+            } else {
+                Value::error(bits)
+            };
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value((self.simulator.snapshot(), &self.output_values)).ok()
+    }
+    fn set_state(&mut self, state: serde_json::Value) -> Result<(), Box<Error>> {
+        let (snapshot, output_values) = serde_json::from_value(state)?;
+        self.simulator.restore(snapshot)?;
+        self.output_values = output_values;
+        Ok(())
+    }
+    fn tick(&mut self) -> bool {
+        // Clocks within the subcircuit advance along with the outer circuit.
+        // Any oscillation is reported as errors on the outputs by `clock_edge`.
+        let _ = self.simulator.tick();
+        true
+    }
+    fn evaluate(&self, _inputs: &PinValues) -> PinValues {
+        let mut result: Vec<_> = self.inputs.iter().map(|&(_, bits)| Value::floating(bits)).collect();
+        result.extend(self.output_values.iter().cloned());
+        result
+    }
+    fn subcircuit(&self) -> Option<&Circuit> {
+        Some(self.circuit())
+    }
+    fn simulator(&self) -> Option<&Simulator> {
+        Some(&self.simulator)
+    }
+    fn hdl(&self) -> Option<&HdlEmit> {
+        Some(self)
+    }
+}
+
+impl HdlEmit for SubcircuitComponent {
+    fn emit_verilog(&self, pins: &[String], module: &mut ModuleWriter) -> Result<(), ExportError> {
+        let name = module.module(&self.module_name(), self.circuit())?;
+        let instance = module.fresh("u");
+        let shape = self.get_shape();
+        let connections: Vec<_> = shape.pins.iter().zip(pins)
+            .map(|(pin, signal)| format!(".{}({})", pin.name, signal))
+            .collect();
+        module.statement(format!("{} {} ({});", name, instance, connections.join(", ")));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecursionError {
+    /// The names of the circuits forming the cycle, starting and ending with
+    /// the same circuit.
+    pub cycle: Vec<String>,
+}
+
+impl fmt::Display for RecursionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Circuit `{}` contains itself: {}", self.cycle[0], self.cycle.join(" -> "))
+    }
+}
+
+impl Error for RecursionError {}
+
+/// A circuit which cannot be used as a subcircuit, because it does not
+/// elaborate with the default values of its parameters.
+#[derive(Debug, Clone)]
+pub struct SubcircuitError {
+    pub name: String,
+    pub error: ElaborationError,
+}
+
+impl fmt::Display for SubcircuitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Circuit `{}` cannot be used as a subcircuit: {}", self.name, self.error)
+    }
+}
+
+impl Error for SubcircuitError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The named circuits of a project, each of which can be used as a
+/// subcircuit within the others.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitRegistry {
+    circuits: BTreeMap<String, Circuit>,
+}
+
+impl CircuitRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Adds or replaces a named circuit. Fails if the circuit would end up
+    /// containing an instance of itself, in which case the registry is left
+    /// unchanged.
+    pub fn insert(&mut self, name: &str, circuit: Circuit) -> Result<Option<Circuit>, RecursionError> {
+        let previous = self.circuits.insert(name.into(), circuit);
+        if let Err(e) = self.check_recursion() {
+            match previous {
+                Some(previous) => self.circuits.insert(name.into(), previous),
+                None => self.circuits.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(previous)
+    }
+    pub fn remove(&mut self, name: &str) -> Option<Circuit> {
+        self.circuits.remove(name)
+    }
+    pub fn get(&self, name: &str) -> Option<&Circuit> {
+        self.circuits.get(name)
+    }
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.circuits.keys().map(|k| k.as_str())
+    }
+    // The registered circuits which are directly instantiated by a circuit
+    fn dependencies(&self, circuit: &Circuit) -> BTreeSet<String> {
+        circuit.components()
+            .map(|(_, info)| info.metadata())
+            .filter(|m| m.category == CATEGORY && self.circuits.contains_key(&*m.id))
+            .map(|m| m.id.clone().into_owned())
+            .collect()
+    }
+    fn visit(&self, name: &str, path: &mut Vec<String>, done: &mut BTreeSet<String>) -> Result<(), RecursionError> {
+        if let Some(index) = path.iter().position(|n| n == name) {
+            let mut cycle = path[index..].to_vec();
+            cycle.push(name.into());
+            return Err(RecursionError { cycle });
+        }
+        if done.contains(name) {
+            return Ok(());
+        }
+        path.push(name.into());
+        for dependency in self.dependencies(&self.circuits[name]) {
+            self.visit(&dependency, path, done)?;
+        }
+        path.pop();
+        done.insert(name.into());
+        Ok(())
+    }
+    /// Checks that no circuit contains itself, directly or indirectly.
+    pub fn check_recursion(&self) -> Result<(), RecursionError> {
+        let mut done = BTreeSet::new();
+        for name in self.circuits.keys() {
+            self.visit(name, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+    /// Adds a component to the library for each circuit, replacing any
+    /// previously registered version. Instances take a copy of the circuit
+    /// as it was when this was called.
+    ///
+    /// Fails without changing the library if any circuit cannot be used as a
+    /// subcircuit.
+    pub fn register(&self, library: &mut Library) -> Result<(), SubcircuitError> {
+        let mut instances = Vec::new();
+        for (name, circuit) in &self.circuits {
+            let instance = SubcircuitComponent::new(name.clone(), circuit.clone())
+                .map_err(|error| SubcircuitError { name: name.clone(), error })?;
+            instances.push(instance);
+        }
+        for instance in instances {
+            let description = format!("The `{}` circuit", instance.name);
+            library.add(
+                ComponentMetadata::new(instance.name.clone(), instance.name.clone(), CATEGORY, description),
+                move || Box::new(instance.clone())
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Wire;
+    use crate::net::PinRef;
+    use crate::trace;
+    use crate::symbol::SymbolAppearance;
+    use crate::parameters::Parameter;
+    use crate::libraries::{gates, io};
+
+    // A circuit which inverts its single input
+    fn inverter(library: &Library) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit.add_component(library.create("input_pin").unwrap(), 0, 0);
+        circuit.add_component(library.create("not_gate").unwrap(), 2, 0);
+        circuit.add_component(library.create("output_pin").unwrap(), 5, 0);
+        circuit
+    }
+
+    #[test]
+    fn simulates_inner_circuit() {
+        let mut library = gates::library();
+        library.extend(io::library());
+        let mut registry = CircuitRegistry::new();
+        registry.insert("inverter", inverter(&library)).unwrap();
+        registry.register(&mut library).unwrap();
+
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(library.create("input_pin").unwrap(), 0, 0);
+        let sub = circuit.add_component(library.create("inverter").unwrap(), 2, 0);
+        let led = circuit.add_component(library.create("led").unwrap(), 6, 0);
+        let shape = circuit.get_component(sub).unwrap().get_shape();
+        assert_eq!(shape.pins.len(), 2);
+        assert_eq!((shape.pins[0].x, shape.pins[0].y), (0, 1));
+
+        let mut sim = Simulator::new(circuit);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.display_value(led).unwrap().to_u64(), Some(1));
+        sim.interact(input, Interaction::Press);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.display_value(led).unwrap().to_u64(), Some(0));
+    }
+
+    #[test]
+    fn probes_nested_instances() {
+        let mut library = gates::library();
+        library.extend(io::library());
+        let mut registry = CircuitRegistry::new();
+        let inner = inverter(&library);
+        let (not, _) = inner.components().find(|(_, info)| info.metadata().id == "not_gate").unwrap();
+        registry.insert("inverter", inner).unwrap();
+        registry.register(&mut library).unwrap();
+
+        // A circuit wrapping a labelled inverter instance
+        let mut wrapper = Circuit::new();
+        wrapper.add_component(library.create("input_pin").unwrap(), 0, 0);
+        let mut instance = library.create("inverter").unwrap();
+        instance.set_property("label", "inv".into()).unwrap();
+        let inv = wrapper.add_component(instance, 2, 0);
+        wrapper.add_component(library.create("output_pin").unwrap(), 6, 0);
+        registry.insert("wrapper", wrapper).unwrap();
+        registry.register(&mut library).unwrap();
+
+        let mut circuit = Circuit::new();
+        let input = circuit.add_component(library.create("input_pin").unwrap(), 0, 0);
+        let mut instance = library.create("wrapper").unwrap();
+        instance.set_property("label", "w".into()).unwrap();
+        let w = circuit.add_component(instance, 2, 0);
+        let mut sim = Simulator::new(circuit);
+
+        let instances = sim.instances();
+        assert_eq!((instances[0].id, &*instances[0].name, &*instances[0].circuit), (w, "w", "wrapper"));
+        assert_eq!((instances[0].children[0].id, &*instances[0].children[0].name), (inv, "inv"));
+
+        let path = format!("w/inv/{}.out", not);
+        let probe = trace::find_probe(sim.circuit(), &path).unwrap();
+        assert_eq!(probe, trace::Probe::new(vec![w, inv], PinRef::new(not, 1)));
+        assert_eq!(trace::probe_path(sim.circuit(), &probe).unwrap(), path);
+        assert!(trace::find_probe(sim.circuit(), "w/missing/c1.out").is_none());
+
+        assert!(sim.trace_probe(probe.clone()));
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.path_value(&path).unwrap().to_u64(), Some(1));
+        sim.interact(input, Interaction::Press);
+        sim.run_until_stable().unwrap();
+        assert_eq!(sim.probe_value(&probe).unwrap().to_u64(), Some(0));
+
+        let signal = &sim.waveform().signals()[0];
+        assert_eq!(signal.name, path);
+        assert_eq!(signal.changes().iter().map(|(_, v)| v.to_u64()).collect::<Vec<_>>(), vec![None, Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn custom_symbol() {
+        let mut library = gates::library();
+        library.extend(io::library());
+        let mut circuit = inverter(&library);
+        let mut appearance = SymbolAppearance::new(2, 2);
+        appearance.place_pin("out0", 1, 0);
+        circuit.set_appearance(Some(appearance.clone()));
+        let circuit = Circuit::from_json(&circuit.to_json(), &library).unwrap();
+        assert_eq!(circuit.appearance(), Some(&appearance));
+
+        let instance = SubcircuitComponent::new("inverter".into(), circuit).unwrap();
+        let shape = instance.get_shape();
+        assert_eq!((shape.width, shape.height), (2, 2));
+        assert_eq!((shape.pins[0].x, shape.pins[0].y), (0, 1));
+        assert_eq!((shape.pins[1].x, shape.pins[1].y), (1, 0));
+        let mut ctx = DrawContext::new();
+        instance.draw(&mut ctx);
+        assert_eq!(ctx.commands().len(), 2);
+    }
+
+    #[test]
+    fn parameterized_instances() {
+        let mut library = gates::library();
+        library.extend(io::library());
+        let mut circuit = inverter(&library);
+        let ids: Vec<_> = circuit.components().map(|(id, _)| id).collect();
+        let parameters = circuit.parameters_mut();
+        parameters.declared.push(Parameter { name: "WIDTH".into(), default: 2, min: 1, max: 16 });
+        for id in ids {
+            parameters.bind(id, "num_bits", "WIDTH");
+        }
+
+        let mut instance = SubcircuitComponent::new("inverter".into(), circuit.clone()).unwrap();
+        assert_eq!(instance.get_shape().pins[0].bits, 2);
+        assert_eq!(instance.get_property("WIDTH"), Some(2.into()));
+        instance.set_property("WIDTH", 4.into()).unwrap();
+        assert_eq!(instance.get_shape().pins[1].bits, 4);
+        instance.clock_edge(&vec![Value::from_u64(0b0110, 4)]);
+        assert_eq!(instance.evaluate(&vec![])[1].to_u64(), Some(0b1001));
+        assert!(instance.set_property("WIDTH", 17.into()).is_err());
+        assert_eq!(instance.module_name(), "inverter_WIDTH_4");
+
+        // A bad expression is reported without changing the instance
+        instance.template.parameters_mut().bind(ComponentId(0), "num_bits", "WIDTH / 0");
+        assert!(instance.set_property("WIDTH", 3.into()).is_err());
+        assert_eq!(instance.get_property("WIDTH"), Some(4.into()));
+
+        // As is a circuit which does not elaborate with its defaults
+        circuit.parameters_mut().bind(ComponentId(0), "num_bits", "WIDTH / 0");
+        assert!(SubcircuitComponent::new("inverter".into(), circuit.clone()).is_err());
+        let mut registry = CircuitRegistry::new();
+        registry.insert("inverter", circuit).unwrap();
+        let error = registry.register(&mut library).unwrap_err();
+        assert_eq!(error.name, "inverter");
+        assert!(!library.contains("inverter"));
+    }
+
+    #[test]
+    fn rejects_recursion() {
+        let mut library = io::library();
+        let mut registry = CircuitRegistry::new();
+        registry.insert("a", Circuit::new()).unwrap();
+        registry.insert("b", Circuit::new()).unwrap();
+        registry.register(&mut library).unwrap();
+
+        let mut a = Circuit::new();
+        a.add_component(library.create("b").unwrap(), 0, 0);
+        registry.insert("a", a).unwrap();
+        let mut b = Circuit::new();
+        b.add_component(library.create("a").unwrap(), 0, 0);
+        b.add_wire(Wire::new((0, 0), (0, 1)));
+        let error = registry.insert("b", b).unwrap_err();
+        assert_eq!(error.cycle, vec!["a", "b", "a"]);
+        assert!(registry.get("b").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Custom symbols for subcircuits.
+//!
+//! By default an instance of a subcircuit is drawn as a box, with its inputs
+//! down the left side and its outputs down the right. A circuit may instead
+//! carry a `SymbolAppearance`, which gives the outline to draw, where each
+//! of its pins is placed and where text is written.
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::render::DrawContext;
+
+/// A step in drawing the outline of a symbol, in grid units relative to its
+/// top-left corner.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PathCommand {
+    /// Starts a new part of the outline.
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    /// Joins the current part back to its start, so that it is filled.
+    Close,
+}
+
+/// The position of the pin with the given name, which is either the label
+/// of an input or output pin of the circuit or its default name, such as
+/// `in0`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PinPlacement {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum LabelContent {
+    /// The name of the circuit.
+    CircuitName,
+    Text(String),
+}
+
+/// Text drawn centred on a point of the symbol.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LabelAnchor {
+    pub content: LabelContent,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+}
+
+/// How instances of a circuit are drawn, and where their pins are.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SymbolAppearance {
+    pub width: i32,
+    pub height: i32,
+    pub outline: Vec<PathCommand>,
+    /// Pins without a placement are positioned as on the default box.
+    pub pins: Vec<PinPlacement>,
+    pub labels: Vec<LabelAnchor>,
+}
+
+impl SymbolAppearance {
+    /// A plain rectangle of the given size, with the name of the circuit in
+    /// the middle.
+    pub fn new(width: i32, height: i32) -> Self {
+        let (w, h) = (width as f64, height as f64);
+        Self {
+            width,
+            height,
+            outline: vec![
+                PathCommand::MoveTo(0.0, 0.0),
+                PathCommand::LineTo(w, 0.0),
+                PathCommand::LineTo(w, h),
+                PathCommand::LineTo(0.0, h),
+                PathCommand::Close,
+            ],
+            pins: Vec::new(),
+            labels: vec![LabelAnchor { content: LabelContent::CircuitName, x: w / 2.0, y: h / 2.0, size: 0.8 }],
+        }
+    }
+    pub fn placement(&self, name: &str) -> Option<(i32, i32)> {
+        self.pins.iter().find(|p| p.name == name).map(|p| (p.x, p.y))
+    }
+    /// Places a pin, replacing any previous placement of it.
+    pub fn place_pin(&mut self, name: &str, x: i32, y: i32) {
+        self.pins.retain(|p| p.name != name);
+        self.pins.push(PinPlacement { name: name.into(), x, y });
+    }
+    /// The parts of the outline, each with whether it is closed.
+    pub fn outline_parts(&self) -> Vec<(Vec<(f64, f64)>, bool)> {
+        let mut result = Vec::new();
+        let mut current: Vec<(f64, f64)> = Vec::new();
+        for &command in &self.outline {
+            match command {
+                PathCommand::MoveTo(x, y) => {
+                    if current.len() > 1 {
+                        result.push((current, false));
+                    }
+                    current = vec![(x, y)];
+                },
+                PathCommand::LineTo(x, y) => current.push((x, y)),
+                PathCommand::Close => if !current.is_empty() {
+                    let start = current[0];
+                    result.push((current, true));
+                    current = vec![start];
+                },
+            }
+        }
+        if current.len() > 1 {
+            result.push((current, false));
+        }
+        result
+    }
+    pub fn draw(&self, ctx: &mut DrawContext, circuit_name: &str) {
+        for (points, closed) in self.outline_parts() {
+            if closed {
+                ctx.polygon(points, Some("#fff"), Some("#222"));
+            } else {
+                for pair in points.windows(2) {
+                    ctx.line(pair[0], pair[1], "#222");
+                }
+            }
+        }
+        for label in &self.labels {
+            let text = match label.content {
+                LabelContent::CircuitName => circuit_name,
+                LabelContent::Text(ref text) => text,
+            };
+            ctx.text((label.x, label.y), label.size, text, "#222");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outline_parts() {
+        let mut appearance = SymbolAppearance::new(4, 2);
+        appearance.outline.extend(vec![
+            PathCommand::MoveTo(1.0, 0.0),
+            PathCommand::LineTo(1.0, 2.0),
+            PathCommand::MoveTo(3.0, 0.0),
+        ]);
+        let parts = appearance.outline_parts();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], (vec![(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)], true));
+        assert_eq!(parts[1], (vec![(1.0, 0.0), (1.0, 2.0)], false));
+
+        appearance.place_pin("a", 0, 1);
+        appearance.place_pin("a", 2, 0);
+        assert_eq!(appearance.placement("a"), Some((2, 0)));
+        assert_eq!(appearance.pins.len(), 1);
+    }
+}
@@ -0,0 +1,381 @@
+//! Checking the behaviour of a circuit against test vectors, without a UI.
+//!
+//! Each vector sets the circuit's input pins, lets the circuit settle, and
+//! compares its output pins against the expected values. Vectors are applied
+//! in order to the same simulation, so sequential circuits can be tested by
+//! ticking the clock between vectors.
+//!
+//! Pins are named as they are for subcircuits: by their labels, or
+//! otherwise as `in0`, `out0` and so on. Vectors can be written as a table:
+//!
+//! ```text
+//! # A full adder
+//! a b cin | sum cout
+//! 0 0 0   | 0   0
+//! 1 1 1   | 1   1
+//! ```
+//!
+//! Columns may be separated by whitespace or commas, and the `|` between
+//! inputs and outputs is optional: the pins of the circuit determine which
+//! columns are inputs. Values are binary unless prefixed with `0x` for
+//! hexadecimal or `0d` for decimal, and `x` marks bits which are not checked.
+
+use std::fmt;
+use std::error::Error;
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::component::Interaction;
+use crate::simulation::{Bit, Simulator, SimulationError, Value};
+use crate::subcircuit::{ports, port_names, INPUT_PIN, OUTPUT_PIN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestError {
+    /// A line of a table could not be parsed.
+    Parse { line: usize, message: String },
+    /// A column does not name an input or output pin of the circuit.
+    UnknownSignal(String),
+    /// A value does not fit in the pin it is for.
+    TooWide { signal: String, bits: u32 },
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestError::Parse { line, message } => write!(f, "Invalid test vector on line {}: {}", line, message),
+            TestError::UnknownSignal(name) => write!(f, "The circuit has no input or output pin named `{}`", name),
+            TestError::TooWide { signal, bits } => write!(f, "A value for `{}` does not fit in {} bits", signal, bits),
+        }
+    }
+}
+
+impl Error for TestError {}
+
+/// A value in a test vector. Bits which are `None` match any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Number(u64),
+    /// Bits ordered from least significant.
+    Bits(Vec<Option<Bit>>),
+    Any,
+}
+
+impl From<u64> for Pattern {
+    fn from(value: u64) -> Self {
+        Pattern::Number(value)
+    }
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lower = text.to_ascii_lowercase();
+        let invalid = || format!("Invalid value `{}`", text);
+        if lower == "x" {
+            Ok(Pattern::Any)
+        } else if let Some(hex) = lower.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).map(Pattern::Number).map_err(|_| invalid())
+        } else if let Some(decimal) = lower.strip_prefix("0d") {
+            decimal.parse().map(Pattern::Number).map_err(|_| invalid())
+        } else {
+            let digits = lower.strip_prefix("0b").unwrap_or(&lower);
+            if digits.is_empty() {
+                return Err(invalid());
+            }
+            digits.chars().rev().map(|c| match c {
+                '0' => Ok(Some(Bit::Zero)),
+                '1' => Ok(Some(Bit::One)),
+                'z' => Ok(Some(Bit::HighZ)),
+                'x' => Ok(None),
+                _ => Err(invalid()),
+            }).collect::<Result<_, _>>().map(Pattern::Bits)
+        }
+    }
+    /// The value to drive onto a pin of the given width, or `None` if the
+    /// pattern does not fit. Unchecked bits are driven as zero.
+    pub fn to_value(&self, width: u32) -> Option<Value> {
+        match self {
+            Pattern::Number(n) => {
+                if width < 64 && n >> width != 0 {
+                    return None;
+                }
+                Some(Value::from_u64(*n, width))
+            },
+            Pattern::Bits(bits) => {
+                if bits.len() > width as usize && bits[width as usize..].iter().any(|b| *b != Some(Bit::Zero)) {
+                    return None;
+                }
+                Some(Value::new((0..width as usize)
+                    .map(|i| bits.get(i).cloned().unwrap_or(Some(Bit::Zero)).unwrap_or(Bit::Zero))
+                    .collect()))
+            },
+            Pattern::Any => Some(Value::from_u64(0, width)),
+        }
+    }
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Bits(bits) => (0..value.width()).all(|i| {
+                match bits.get(i as usize).cloned().unwrap_or(Some(Bit::Zero)) {
+                    Some(bit) => value.bit(i) == bit,
+                    None => true,
+                }
+            }),
+            Pattern::Number(_) => self.to_value(value.width()).as_ref() == Some(value),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Number(n) => write!(f, "0x{:x}", n),
+            Pattern::Bits(bits) => {
+                for bit in bits.iter().rev() {
+                    write!(f, "{}", bit.map_or('x', |b| b.to_char()))?;
+                }
+                Ok(())
+            },
+            Pattern::Any => write!(f, "x"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    /// A value for each of the inputs of the test.
+    pub inputs: Vec<Pattern>,
+    /// The value expected for each of the outputs of the test.
+    pub expected: Vec<Pattern>,
+    /// The number of clock ticks to run after setting the inputs, before
+    /// checking the outputs.
+    pub ticks: u32,
+    /// The line of the table the vector was read from.
+    pub line: Option<usize>,
+}
+
+impl TestVector {
+    pub fn new(inputs: Vec<Pattern>, expected: Vec<Pattern>) -> Self {
+        Self { inputs, expected, ticks: 0, line: None }
+    }
+    pub fn with_ticks(mut self, ticks: u32) -> Self {
+        self.ticks = ticks;
+        self
+    }
+}
+
+/// A sequence of test vectors for the named pins of a circuit.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TestVectors {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub vectors: Vec<TestVector>,
+}
+
+fn split_columns(line: &str) -> impl Iterator<Item=&str> {
+    line.split(|c: char| c.is_whitespace() || c == ',' || c == '|').filter(|s| !s.is_empty())
+}
+
+impl TestVectors {
+    pub fn new(inputs: Vec<String>, outputs: Vec<String>) -> Self {
+        Self { inputs, outputs, vectors: Vec::new() }
+    }
+    pub fn add(&mut self, vector: TestVector) {
+        self.vectors.push(vector);
+    }
+    /// Reads a table of vectors. The columns are sorted into inputs and
+    /// outputs using the pins of the circuit.
+    pub fn parse(text: &str, circuit: &Circuit) -> Result<Self, TestError> {
+        let pins = Pins::new(circuit);
+        let mut lines = text.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or("").trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let header: Vec<_> = match lines.next() {
+            Some((_, line)) => split_columns(line).collect(),
+            None => return Ok(Self::default()),
+        };
+        let mut result = Self::default();
+        // Whether each column is an input
+        let mut columns = Vec::new();
+        for name in header {
+            let (is_input, _, _) = pins.find(name)?;
+            columns.push(is_input);
+            if is_input { &mut result.inputs } else { &mut result.outputs }.push(name.into());
+        }
+        for (line, text) in lines {
+            let values: Vec<_> = split_columns(text).collect();
+            if values.len() != columns.len() {
+                return Err(TestError::Parse { line, message: format!("Expected {} values, found {}", columns.len(), values.len()) });
+            }
+            let mut vector = TestVector::new(Vec::new(), Vec::new());
+            vector.line = Some(line);
+            for (&is_input, value) in columns.iter().zip(values) {
+                let pattern = Pattern::parse(value).map_err(|message| TestError::Parse { line, message })?;
+                if is_input { &mut vector.inputs } else { &mut vector.expected }.push(pattern);
+            }
+            result.vectors.push(vector);
+        }
+        Ok(result)
+    }
+    /// Applies every vector in turn to a simulation of the circuit.
+    pub fn run(&self, circuit: &Circuit) -> Result<TestReport, TestError> {
+        let pins = Pins::new(circuit);
+        let resolve = |names: &[String]| names.iter()
+            .map(|name| pins.find(name).map(|(_, id, bits)| (id, bits)))
+            .collect::<Result<Vec<_>, _>>();
+        let (inputs, outputs) = (resolve(&self.inputs)?, resolve(&self.outputs)?);
+
+        let mut sim = Simulator::new(circuit.clone());
+        let mut results = Vec::new();
+        for (index, vector) in self.vectors.iter().enumerate() {
+            for ((&(id, bits), pattern), name) in inputs.iter().zip(&vector.inputs).zip(&self.inputs) {
+                let value = pattern.to_value(bits).ok_or_else(|| TestError::TooWide { signal: name.clone(), bits })?;
+                sim.interact(id, Interaction::Set(value));
+            }
+            let mut error = sim.run_until_stable().err();
+            for _ in 0..vector.ticks {
+                if error.is_some() {
+                    break;
+                }
+                error = sim.tick().err();
+            }
+            let actual: Vec<_> = outputs.iter()
+                .map(|&(id, bits)| sim.display_value(id).unwrap_or_else(|| Value::floating(bits)))
+                .collect();
+            let mismatched = vector.expected.iter().zip(&actual).enumerate()
+                .filter(|(_, (pattern, value))| !pattern.matches(value))
+                .map(|(i, _)| i)
+                .collect();
+            results.push(VectorResult { index, line: vector.line, actual, mismatched, error });
+        }
+        Ok(TestReport { outputs: self.outputs.clone(), expected: self.vectors.iter().map(|v| v.expected.clone()).collect(), results })
+    }
+}
+
+// The input and output pins of a circuit, by name
+struct Pins {
+    pins: Vec<(String, bool, ComponentId, u32)>,
+}
+
+impl Pins {
+    fn new(circuit: &Circuit) -> Self {
+        let (input_names, output_names) = port_names(circuit);
+        let inputs = ports(circuit, INPUT_PIN).into_iter().zip(input_names).map(|((id, bits), name)| (name, true, id, bits));
+        let outputs = ports(circuit, OUTPUT_PIN).into_iter().zip(output_names).map(|((id, bits), name)| (name, false, id, bits));
+        Pins { pins: inputs.chain(outputs).collect() }
+    }
+    fn find(&self, name: &str) -> Result<(bool, ComponentId, u32), TestError> {
+        self.pins.iter()
+            .find(|(n, _, _, _)| n == name)
+            .map(|&(_, is_input, id, bits)| (is_input, id, bits))
+            .ok_or_else(|| TestError::UnknownSignal(name.into()))
+    }
+}
+
+/// The outcome of applying a single vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorResult {
+    pub index: usize,
+    pub line: Option<usize>,
+    /// The value of each output after the vector was applied.
+    pub actual: Vec<Value>,
+    /// The outputs which did not have their expected values.
+    pub mismatched: Vec<usize>,
+    /// The error which stopped the simulation from settling, if any.
+    pub error: Option<SimulationError>,
+}
+
+impl VectorResult {
+    pub fn passed(&self) -> bool {
+        self.mismatched.is_empty() && self.error.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestReport {
+    outputs: Vec<String>,
+    expected: Vec<Vec<Pattern>>,
+    pub results: Vec<VectorResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed())
+    }
+    pub fn num_passed(&self) -> usize {
+        self.results.iter().filter(|r| r.passed()).count()
+    }
+    pub fn failures(&self) -> impl Iterator<Item=&VectorResult> {
+        self.results.iter().filter(|r| !r.passed())
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} of {} test vectors passed", self.num_passed(), self.results.len())?;
+        for result in self.failures() {
+            write!(f, "Vector {}", result.index + 1)?;
+            if let Some(line) = result.line {
+                write!(f, " (line {})", line)?;
+            }
+            if let Some(error) = &result.error {
+                writeln!(f, ": {}", error)?;
+                continue;
+            }
+            let details: Vec<_> = result.mismatched.iter().map(|&i| format!(
+                "{} expected {}, got {}", self.outputs[i], self.expected[result.index][i], result.actual[i]
+            )).collect();
+            writeln!(f, ": {}", details.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::default_library;
+
+    #[test]
+    fn patterns() {
+        assert_eq!(Pattern::parse("1x0").unwrap(), Pattern::Bits(vec![Some(Bit::Zero), None, Some(Bit::One)]));
+        assert_eq!(Pattern::parse("0x1f").unwrap(), Pattern::Number(31));
+        assert_eq!(Pattern::parse("0d12").unwrap(), Pattern::Number(12));
+        assert!(Pattern::parse("12").is_err());
+        assert!(Pattern::parse("1x0").unwrap().matches(&Value::from_u64(0b110, 3)));
+        assert!(!Pattern::parse("1x0").unwrap().matches(&Value::from_u64(0b111, 3)));
+        assert_eq!(Pattern::Number(4).to_value(2), None);
+        assert_eq!(Pattern::parse("0010").unwrap().to_value(2), Some(Value::from_u64(2, 2)));
+    }
+
+    #[test]
+    fn and_gate_vectors() {
+        let library = default_library();
+        let mut circuit = Circuit::new();
+        let and = circuit.add_component(library.create("and_gate").unwrap(), 0, 0);
+        let pins: Vec<_> = circuit.get_component(and).unwrap().get_shape().pins
+            .iter().map(|p| (p.x, p.y)).collect();
+        for (i, name) in ["a", "b"].iter().enumerate() {
+            let mut input = library.create("input_pin").unwrap();
+            input.set_property("label", (*name).into()).unwrap();
+            circuit.add_component(input, pins[i].0 - 2, pins[i].1 - 1);
+        }
+        let mut output = library.create("output_pin").unwrap();
+        output.set_property("label", "y".into()).unwrap();
+        circuit.add_component(output, pins[2].0, pins[2].1 - 1);
+
+        let table = "# AND\ny, a, b\n0, 0, 0\n0, 1, 0\n1, 1, 1\n1, 0, 1\nx, 0, x\n";
+        let tests = TestVectors::parse(table, &circuit).unwrap();
+        assert_eq!((tests.inputs.clone(), tests.outputs.clone()), (vec!["a".into(), "b".into()], vec!["y".into()]));
+        let report = tests.run(&circuit).unwrap();
+        assert_eq!(report.num_passed(), 4);
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!((failures[0].index, failures[0].line), (3, Some(6)));
+        assert_eq!(failures[0].actual, vec![Value::from_u64(0, 1)]);
+        assert!(report.to_string().contains("Vector 4 (line 6): y expected 1, got 0"), "{}", report);
+
+        assert_eq!(TestVectors::parse("a c\n", &circuit), Err(TestError::UnknownSignal("c".into())));
+        assert!(TestVectors::parse("a b\n0\n", &circuit).is_err());
+        let mut tests = TestVectors::new(vec!["a".into()], vec![]);
+        tests.add(TestVector::new(vec![2.into()], vec![]));
+        assert!(tests.run(&circuit).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+//! Recording the values of signals over simulated time, for display as a
+//! waveform or export as a VCD file.
+//!
+//! Signals are identified by a pin rather than a net, as nets are numbered
+//! afresh whenever the circuit changes. A signal carries the value of the
+//! net attached to its pin.
+//!
+//! Pins inside subcircuit instances are reached through the instances
+//! containing them, and named by a path such as `cpu/alu/carry_out`: the
+//! name of each instance, outermost first, followed by the name of the
+//! signal within the innermost circuit.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::circuit::{Circuit, ComponentId};
+use crate::net::PinRef;
+use crate::simulation::{Bit, Value};
+
+/// A pin of a circuit, or of a circuit nested within subcircuit instances.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Probe {
+    /// The subcircuit component at each level of the hierarchy, outermost
+    /// first. Empty for a pin of the top-level circuit.
+    pub instances: Vec<ComponentId>,
+    /// A pin of the innermost circuit.
+    pub pin: PinRef,
+}
+
+impl Probe {
+    pub fn new(instances: Vec<ComponentId>, pin: PinRef) -> Self {
+        Self { instances, pin }
+    }
+}
+
+impl From<PinRef> for Probe {
+    fn from(pin: PinRef) -> Self {
+        Self::new(Vec::new(), pin)
+    }
+}
+
+/// A subcircuit instance, and the instances within it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub id: ComponentId,
+    /// As used in paths.
+    pub name: String,
+    /// The name of the circuit it is an instance of.
+    pub circuit: String,
+    pub children: Vec<Instance>,
+}
+
+/// The name of a subcircuit instance within a path: the label of the
+/// component, if it has one, or otherwise its id.
+pub fn instance_name(circuit: &Circuit, id: ComponentId) -> String {
+    circuit.get_component(id).and_then(|info| info.label()).unwrap_or_else(|| id.to_string())
+}
+
+fn find_instance(circuit: &Circuit, name: &str) -> Option<ComponentId> {
+    circuit.components()
+        .filter(|(_, info)| info.subcircuit().is_some())
+        .find(|(id, info)| info.label().as_deref() == Some(name) || id.to_string() == name)
+        .map(|(id, _)| id)
+}
+
+/// The tree of subcircuit instances within a circuit.
+pub fn instances(circuit: &Circuit) -> Vec<Instance> {
+    circuit.components().filter_map(|(id, info)| {
+        let inner = info.subcircuit()?;
+        Some(Instance {
+            id,
+            name: instance_name(circuit, id),
+            circuit: info.metadata().id.to_string(),
+            children: instances(inner),
+        })
+    }).collect()
+}
+
+// The circuit containing the pin of a probe
+fn innermost<'a>(circuit: &'a Circuit, instances: &[ComponentId]) -> Option<&'a Circuit> {
+    instances.iter().try_fold(circuit, |circuit, &id| circuit.get_component(id)?.subcircuit())
+}
+
+/// The path naming a probe, or `None` if one of its instances does not
+/// exist.
+pub fn probe_path(circuit: &Circuit, probe: &Probe) -> Option<String> {
+    let mut path = String::new();
+    let mut current = circuit;
+    for &id in &probe.instances {
+        path.push_str(&instance_name(current, id));
+        path.push('/');
+        current = current.get_component(id)?.subcircuit()?;
+    }
+    path.push_str(&signal_name(current, probe.pin));
+    Some(path)
+}
+
+/// Finds the probe named by a path, as given by `probe_path`.
+pub fn find_probe(circuit: &Circuit, path: &str) -> Option<Probe> {
+    let mut segments: Vec<_> = path.split('/').collect();
+    let signal = segments.pop()?;
+    let mut instances = Vec::new();
+    let mut current = circuit;
+    for segment in segments {
+        let id = find_instance(current, segment)?;
+        current = current.get_component(id)?.subcircuit()?;
+        instances.push(id);
+    }
+    Some(Probe::new(instances, find_signal(current, signal)?))
+}
+
+/// The width of the pin of a probe, or `None` if it does not exist.
+pub fn probe_width(circuit: &Circuit, probe: &Probe) -> Option<u32> {
+    let info = innermost(circuit, &probe.instances)?.get_component(probe.pin.component)?;
+    info.get_shape().pins.get(probe.pin.pin).map(|p| p.bits)
+}
+
+/// The changes in value of a single traced pin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub probe: Probe,
+    pub width: u32,
+    // Each time the value changed, in increasing order of time
+    changes: Vec<(u64, Value)>,
+}
+
+impl Signal {
+    pub fn changes(&self) -> &[(u64, Value)] {
+        &self.changes
+    }
+    /// The value of the signal at the given time, or `None` if it was not
+    /// yet being recorded.
+    pub fn value_at(&self, time: u64) -> Option<&Value> {
+        match self.changes.binary_search_by_key(&time, |&(t, _)| t) {
+            Ok(i) => Some(&self.changes[i].1),
+            Err(0) => None,
+            Err(i) => Some(&self.changes[i - 1].1),
+        }
+    }
+}
+
+/// The recorded values of a set of signals.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Waveform {
+    signals: Vec<Signal>,
+    end_time: u64,
+}
+
+/// The name of a signal for a pin: the label of its component, if it has
+/// one, or otherwise the component and the name of the pin.
+pub fn signal_name(circuit: &Circuit, pin: PinRef) -> String {
+    let info = match circuit.get_component(pin.component) {
+        Some(info) => info,
+        None => return format!("{}.{}", pin.component, pin.pin),
+    };
+    match info.label() {
+        Some(label) => label,
+        None => {
+            let pin_name = info.get_shape().pins.get(pin.pin).map(|p| p.name.clone())
+                .unwrap_or_else(|| pin.pin.to_string());
+            format!("{}.{}", pin.component, pin_name)
+        },
+    }
+}
+
+/// The samples held by a logic analyzer, taken once per tick with one value
+/// for each probe. Only the most recent samples are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Capture {
+    samples: VecDeque<Vec<Value>>,
+    // The index of the sample at which the trigger fired
+    trigger: Option<usize>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    /// The values of every probe at a sample, oldest first.
+    pub fn sample(&self, index: usize) -> Option<&[Value]> {
+        self.samples.get(index).map(|s| s.as_slice())
+    }
+    /// The values of one probe over every sample, oldest first.
+    pub fn probe(&self, probe: usize) -> Vec<Value> {
+        self.samples.iter().filter_map(|s| s.get(probe).cloned()).collect()
+    }
+    /// The sample at which the trigger fired, if it has.
+    pub fn trigger_index(&self) -> Option<usize> {
+        self.trigger
+    }
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.trigger = None;
+    }
+    /// Adds a sample, discarding the oldest once there are more than
+    /// `depth`.
+    pub fn push(&mut self, sample: Vec<Value>, depth: usize) {
+        if self.samples.front().map_or(false, |s| s.len() != sample.len()) {
+            // The number of probes has changed, so older samples are meaningless
+            self.clear();
+        }
+        self.samples.push_back(sample);
+        while self.samples.len() > depth {
+            self.samples.pop_front();
+            self.trigger = self.trigger.and_then(|t| t.checked_sub(1));
+        }
+    }
+    /// Marks the most recent sample as the one at which the trigger fired.
+    pub fn set_triggered(&mut self) {
+        self.trigger = self.samples.len().checked_sub(1);
+    }
+}
+
+/// Finds the pin named by `signal_name`: either the first pin of the
+/// component with that label, or a component and the name of one of its pins.
+pub fn find_signal(circuit: &Circuit, name: &str) -> Option<PinRef> {
+    if let Some((id, _)) = circuit.components().find(|(_, info)| info.label().as_deref() == Some(name)) {
+        return Some(PinRef::new(id, 0));
+    }
+    let (component, pin_name) = name.split_at(name.find('.')?);
+    let (id, info) = circuit.components().find(|(id, _)| id.to_string() == component)?;
+    let pin = info.get_shape().pins.iter().position(|p| p.name == pin_name[1..])?;
+    Some(PinRef::new(id, pin))
+}
+
+// Characters which are valid in a VCD identifier code
+const FIRST_ID_CHAR: u8 = b'!';
+const NUM_ID_CHARS: usize = (b'~' - b'!' + 1) as usize;
+
+fn vcd_id(mut index: usize) -> String {
+    let mut result = String::new();
+    loop {
+        result.push((FIRST_ID_CHAR + (index % NUM_ID_CHARS) as u8) as char);
+        index /= NUM_ID_CHARS;
+        if index == 0 {
+            return result;
+        }
+        index -= 1;
+    }
+}
+
+fn vcd_bit(bit: Bit) -> char {
+    match bit {
+        Bit::Zero => '0',
+        Bit::One => '1',
+        Bit::HighZ => 'z',
+        Bit::Unknown | Bit::Error => 'x',
+    }
+}
+
+fn vcd_value(value: &Value, width: u32, id: &str) -> String {
+    if width == 1 {
+        format!("{}{}", vcd_bit(value.bit(0)), id)
+    } else {
+        let bits: String = (0..width).rev().map(|b| vcd_bit(value.bit(b))).collect();
+        format!("b{} {}", bits, id)
+    }
+}
+
+impl Waveform {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn signals(&self) -> &[Signal] {
+        &self.signals
+    }
+    /// The latest time at which the signals were recorded.
+    pub fn end_time(&self) -> u64 {
+        self.end_time
+    }
+    /// Starts recording a pin. Returns false if it was already recorded.
+    pub fn add_signal(&mut self, name: String, probe: Probe, width: u32) -> bool {
+        if self.signals.iter().any(|s| s.probe == probe) {
+            return false;
+        }
+        self.signals.push(Signal { name, probe, width, changes: Vec::new() });
+        true
+    }
+    pub fn remove_signal(&mut self, probe: &Probe) -> bool {
+        let len = self.signals.len();
+        self.signals.retain(|s| s.probe != *probe);
+        self.signals.len() != len
+    }
+    /// Records the value of each signal at the given time, given a way of
+    /// finding the current value of a pin. Values which have not changed are
+    /// not stored.
+    pub fn record<F: Fn(&Probe) -> Option<Value>>(&mut self, time: u64, value_of: F) {
+        for signal in &mut self.signals {
+            let value = match value_of(&signal.probe) {
+                Some(value) => value,
+                None => continue,
+            };
+            // Several changes at the same time only keep the last, which
+            // is dropped too if it reverts to the value from before
+            if signal.changes.last().map_or(false, |&(t, _)| t == time) {
+                signal.changes.pop();
+            }
+            if signal.changes.last().map_or(true, |(_, last)| *last != value) {
+                signal.changes.push((time, value));
+            }
+        }
+        self.end_time = self.end_time.max(time);
+    }
+    /// Discards the recorded values, keeping the set of signals.
+    pub fn clear(&mut self) {
+        for signal in &mut self.signals {
+            signal.changes.clear();
+        }
+        self.end_time = 0;
+    }
+
+    /// Writes the waveform as a Value Change Dump, as read by GTKWave and
+    /// most HDL simulators. The timescale gives the length of one unit of
+    /// simulation time, such as `1ns`.
+    pub fn to_vcd(&self, timescale: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "$version tenorite-ui $end");
+        let _ = writeln!(out, "$timescale {} $end", timescale);
+        let _ = writeln!(out, "$scope module top $end");
+        let ids: Vec<_> = (0..self.signals.len()).map(vcd_id).collect();
+        for (signal, id) in self.signals.iter().zip(&ids) {
+            // References cannot contain whitespace
+            let name: String = signal.name.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect();
+            let _ = writeln!(out, "$var wire {} {} {} $end", signal.width, id, name);
+        }
+        let _ = writeln!(out, "$upscope $end");
+        let _ = writeln!(out, "$enddefinitions $end");
+
+        // Merge the changes of every signal in order of time
+        let mut events: Vec<(u64, usize, &Value)> = self.signals.iter().enumerate()
+            .flat_map(|(i, s)| s.changes.iter().map(move |(t, v)| (*t, i, v)))
+            .collect();
+        events.sort_by_key(|&(t, i, _)| (t, i));
+        let mut current = None;
+        for (time, i, value) in events {
+            if current != Some(time) {
+                let _ = writeln!(out, "#{}", time);
+                current = Some(time);
+            }
+            let _ = writeln!(out, "{}", vcd_value(value, self.signals[i].width, &ids[i]));
+        }
+        if current != Some(self.end_time) {
+            let _ = writeln!(out, "#{}", self.end_time);
+        }
+        out
+    }
+
+    /// Writes the waveform as comma separated values, with a row for each
+    /// time at which any signal changed and a column for each signal. Values
+    /// are written as bits, most significant first, and are empty before a
+    /// signal was first recorded.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("time");
+        for signal in &self.signals {
+            out.push(',');
+            if signal.name.contains(&[',', '"'][..]) {
+                let _ = write!(out, "\"{}\"", signal.name.replace('"', "\"\""));
+            } else {
+                out.push_str(&signal.name);
+            }
+        }
+        out.push('\n');
+        let times: BTreeSet<u64> = self.signals.iter()
+            .flat_map(|s| s.changes.iter().map(|&(t, _)| t))
+            .collect();
+        for time in times {
+            let _ = write!(out, "{}", time);
+            for signal in &self.signals {
+                out.push(',');
+                if let Some(value) = signal.value_at(time) {
+                    let _ = write!(out, "{}", value);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Circuit, ComponentId};
+    use crate::component::Interaction;
+    use crate::simulation::Simulator;
+
+    #[test]
+    fn record_and_export() {
+        let (a, b) = (PinRef::new(ComponentId(1), 0), PinRef::new(ComponentId(2), 1));
+        let mut waveform = Waveform::new();
+        assert!(waveform.add_signal("clock".into(), a.into(), 1));
+        assert!(waveform.add_signal("data bus".into(), b.into(), 4));
+        assert!(!waveform.add_signal("again".into(), a.into(), 1));
+
+        let values = |time: u64| move |probe: &Probe| Some(if probe.pin == a {
+            Value::from_u64(time % 2, 1)
+        } else {
+            Value::from_u64(time / 2, 4)
+        });
+        for time in 0..4 {
+            waveform.record(time, values(time));
+        }
+        waveform.record(4, |_| None);
+        let clock = &waveform.signals()[0];
+        assert_eq!(clock.changes().len(), 4);
+        assert_eq!(waveform.signals()[1].value_at(3), Some(&Value::from_u64(1, 4)));
+        assert_eq!(clock.value_at(10).and_then(|v| v.to_u64()), Some(1));
+
+        let vcd = waveform.to_vcd("1ns");
+        assert!(vcd.contains("$var wire 4 \" data_bus $end"), "{}", vcd);
+        assert!(vcd.contains("#0\n0!\nb0000 \"\n#1\n1!\n#2\n0!\nb0001 \"\n"), "{}", vcd);
+        assert!(vcd.ends_with("#3\n1!\n#4\n"), "{}", vcd);
+        assert_eq!(vcd_id(NUM_ID_CHARS), "!!");
+
+        let csv = waveform.to_csv();
+        assert_eq!(csv, "time,clock,data bus\n0,0,0000\n1,1,0000\n2,0,0001\n3,1,0001\n");
+
+        // A glitch which settles back to the previous value isn't a change
+        waveform.record(5, values(4));
+        waveform.record(5, values(3));
+        assert_eq!(waveform.signals()[0].changes().len(), 4);
+        assert_eq!(waveform.signals()[1].changes().len(), 2);
+    }
+
+    #[test]
+    fn trace_simulation() {
+        let library = crate::libraries::default_library();
+        let mut circuit = Circuit::new();
+        let mut input = library.create("input_pin").unwrap();
+        input.set_property("label", "a".into()).unwrap();
+        let input = circuit.add_component(input, -2, 0);
+        let not = circuit.add_component(library.create("not_gate").unwrap(), 0, 0);
+
+        let mut sim = Simulator::new(circuit);
+        assert!(sim.trace(PinRef::new(input, 0)));
+        assert!(sim.trace(PinRef::new(not, 1)));
+        assert!(!sim.trace(PinRef::new(not, 5)));
+        sim.run_until_stable().unwrap();
+        sim.interact(input, Interaction::Press);
+        sim.run_until_stable().unwrap();
+
+        let waveform = sim.waveform();
+        assert_eq!(waveform.signals()[0].name, "a");
+        assert_eq!(waveform.signals()[1].name, format!("{}.out", not));
+        let out = &waveform.signals()[1];
+        let press = waveform.signals()[0].changes()[1].0;
+        assert_eq!(out.value_at(press).and_then(|v| v.to_u64()), Some(1));
+        assert_eq!(out.value_at(waveform.end_time()).and_then(|v| v.to_u64()), Some(0));
+
+        sim.reset();
+        assert!(sim.waveform().signals()[0].changes().is_empty());
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::error::Error;
+
+use serde_json;
+
+use uuid::Uuid;
+
+use crate::circuit::{Circuit, ComponentId, WireId, Wire};
+use crate::component::{ComponentInfo, Orientation, PropertyError};
+use crate::journal::JournalEntry;
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+/// A reversible edit to a circuit. Applying a command produces another
+/// command which reverses it.
+#[derive(Debug, Clone)]
+pub enum Command {
+    AddComponent {
+        component: ComponentInfo,
+        x: i32,
+        y: i32,
+    },
+    /// Restores a component under a specific id, such as after it was
+    /// removed, along with its uuid if it had one.
+    InsertComponent {
+        id: ComponentId,
+        component: ComponentInfo,
+        uuid: Option<Uuid>,
+    },
+    RemoveComponent {
+        id: ComponentId,
+    },
+    MoveComponent {
+        id: ComponentId,
+        x: i32,
+        y: i32,
+    },
+    SetProperty {
+        id: ComponentId,
+        name: String,
+        value: serde_json::Value,
+    },
+    AddWire {
+        wire: Wire,
+    },
+    InsertWire {
+        id: WireId,
+        wire: Wire,
+        uuid: Option<Uuid>,
+    },
+    RemoveWire {
+        id: WireId,
+    },
+    /// Several commands applied in order, and undone as a single edit.
+    Group(Vec<Command>),
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    MissingComponent(ComponentId),
+    MissingWire(WireId),
+    Property {
+        component: ComponentId,
+        error: PropertyError,
+    },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::MissingComponent(id) => write!(f, "Component `{}` does not exist", id),
+            EditError::MissingWire(id) => write!(f, "Wire `{}` does not exist", id),
+            EditError::Property { component, error } => write!(f, "Component `{}`: {}", component, error),
+        }
+    }
+}
+
+impl Error for EditError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            EditError::Property { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Command {
+    /// Rotation is expressed as a change to the orientation property.
+    pub fn rotate(id: ComponentId, orientation: Orientation) -> Self {
+        Command::SetProperty {
+            id,
+            name: "orientation".into(),
+            value: serde_json::to_value(orientation).expect("Orientation is serializable"),
+        }
+    }
+
+    /// Applies the command, returning the command which reverses it. The
+    /// circuit is left unchanged if an error is returned.
+    pub fn apply(self, circuit: &mut Circuit) -> Result<Command, EditError> {
+        self.apply_inner(circuit, None)
+    }
+
+    /// Applies the command as `apply` does, also appending an entry to the
+    /// journal for each edit it made. Nothing is appended if an error is
+    /// returned.
+    pub fn apply_journaled(self, circuit: &mut Circuit, journal: &mut Vec<JournalEntry>) -> Result<Command, EditError> {
+        self.apply_inner(circuit, Some(journal))
+    }
+
+    fn apply_inner(self, circuit: &mut Circuit, mut journal: Option<&mut Vec<JournalEntry>>) -> Result<Command, EditError> {
+        let inverse = match self {
+            Command::AddComponent { component, x, y } => {
+                let id = circuit.add_component(component, x, y);
+                Command::RemoveComponent { id }
+            },
+            Command::InsertComponent { id, component, uuid } => {
+                circuit.insert_component_with_uuid(id, component, uuid);
+                Command::RemoveComponent { id }
+            },
+            Command::RemoveComponent { id } => {
+                let uuid = circuit.component_uuid(id);
+                let component = circuit.remove_component(id)
+                    .ok_or(EditError::MissingComponent(id))?;
+                Command::InsertComponent { id, component, uuid }
+            },
+            Command::MoveComponent { id, x, y } => {
+                let (old_x, old_y) = circuit.move_component(id, x, y)
+                    .ok_or(EditError::MissingComponent(id))?;
+                Command::MoveComponent { id, x: old_x, y: old_y }
+            },
+            Command::SetProperty { id, name, value } => {
+                let info = circuit.get_component(id)
+                    .ok_or(EditError::MissingComponent(id))?;
+                let old_value = info.get_property(&name)
+                    .ok_or_else(|| EditError::Property { component: id, error: PropertyError::unknown(&name) })?;
+                circuit.set_property(id, &name, value)
+                    .ok_or(EditError::MissingComponent(id))?
+                    .map_err(|error| EditError::Property { component: id, error })?;
+                Command::SetProperty { id, name, value: old_value }
+            },
+            Command::AddWire { wire } => {
+                let id = circuit.add_wire(wire);
+                Command::RemoveWire { id }
+            },
+            Command::InsertWire { id, wire, uuid } => {
+                circuit.insert_wire_with_uuid(id, wire, uuid);
+                Command::RemoveWire { id }
+            },
+            Command::RemoveWire { id } => {
+                let uuid = circuit.wire_uuid(id);
+                let wire = circuit.remove_wire(id)
+                    .ok_or(EditError::MissingWire(id))?;
+                Command::InsertWire { id, wire, uuid }
+            },
+            Command::Group(commands) => {
+                let journaled = journal.as_ref().map_or(0, |journal| journal.len());
+                let mut inverses = Vec::with_capacity(commands.len());
+                for command in commands {
+                    match command.apply_inner(circuit, journal.as_deref_mut()) {
+                        Ok(inverse) => inverses.push(inverse),
+                        Err(e) => {
+                            // Roll back the commands which did succeed
+                            for inverse in inverses.into_iter().rev() {
+                                inverse.apply(circuit).expect("Failed to roll back group");
+                            }
+                            if let Some(journal) = journal {
+                                journal.truncate(journaled);
+                            }
+                            return Err(e);
+                        },
+                    }
+                }
+                inverses.reverse();
+                // The entries for each command were appended as it was applied
+                return Ok(Command::Group(inverses));
+            },
+        };
+        if let Some(journal) = journal {
+            journal.extend(JournalEntry::from_inverse(&inverse, circuit));
+        }
+        Ok(inverse)
+    }
+}
+
+/// Records the edits made to a circuit so that they can be undone and redone.
+#[derive(Debug, Clone)]
+pub struct CommandStack {
+    undo: VecDeque<Command>,
+    redo: Vec<Command>,
+    depth: usize,
+    // Commands recorded since the outermost `begin_group`, and the nesting level
+    group: Option<(Vec<Command>, usize)>,
+    // Entries for edits applied since the journal was last taken
+    journal: Option<Vec<JournalEntry>>,
+}
+
+impl Default for CommandStack {
+    fn default() -> Self {
+        Self::with_depth(DEFAULT_HISTORY_DEPTH)
+    }
+}
+
+impl CommandStack {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn with_depth(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+            group: None,
+            journal: None,
+        }
+    }
+    /// Starts keeping a journal of every edit applied through the stack,
+    /// including undoing and redoing, to be collected with `take_journal`.
+    pub fn enable_journal(&mut self) {
+        if self.journal.is_none() {
+            self.journal = Some(Vec::new());
+        }
+    }
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+    /// Returns the journal entries for edits applied since this was last
+    /// called, in the order they were applied.
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+    fn apply(&mut self, circuit: &mut Circuit, command: Command) -> Result<Command, EditError> {
+        match self.journal {
+            Some(ref mut journal) => command.apply_journaled(circuit, journal),
+            None => command.apply(circuit),
+        }
+    }
+    fn journal(&mut self, circuit: &Circuit, inverse: &Command) {
+        if let Some(ref mut journal) = self.journal {
+            journal.extend(JournalEntry::from_inverse(inverse, circuit));
+        }
+    }
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    /// Sets the maximum number of edits which can be undone, discarding the
+    /// oldest edits if there are already more than that.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+    fn trim(&mut self) {
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+    fn record(&mut self, inverse: Command) {
+        self.redo.clear();
+        match self.group {
+            Some((ref mut commands, _)) => commands.push(inverse),
+            None => {
+                self.undo.push_back(inverse);
+                self.trim();
+            },
+        }
+    }
+    /// Applies a command and records it so that it can be undone. Any edits
+    /// which had been undone can no longer be redone.
+    pub fn execute(&mut self, circuit: &mut Circuit, command: Command) -> Result<(), EditError> {
+        let inverse = self.apply(circuit, command)?;
+        self.record(inverse);
+        Ok(())
+    }
+    pub fn add_component(&mut self, circuit: &mut Circuit, component: ComponentInfo, x: i32, y: i32) -> ComponentId {
+        let id = circuit.add_component(component, x, y);
+        let inverse = Command::RemoveComponent { id };
+        self.journal(circuit, &inverse);
+        self.record(inverse);
+        id
+    }
+    pub fn add_wire(&mut self, circuit: &mut Circuit, wire: Wire) -> WireId {
+        let id = circuit.add_wire(wire);
+        let inverse = Command::RemoveWire { id };
+        self.journal(circuit, &inverse);
+        self.record(inverse);
+        id
+    }
+    /// Starts grouping subsequent commands into a single edit. Groups may be
+    /// nested, in which case the edit is recorded by the outermost `end_group`.
+    pub fn begin_group(&mut self) {
+        match self.group {
+            Some((_, ref mut level)) => *level += 1,
+            None => self.group = Some((Vec::new(), 1)),
+        }
+    }
+    pub fn end_group(&mut self) {
+        if let Some((commands, level)) = self.group.take() {
+            if level > 1 {
+                self.group = Some((commands, level - 1));
+            } else if !commands.is_empty() {
+                let mut commands = commands;
+                commands.reverse();
+                self.record(Command::Group(commands));
+            }
+        }
+    }
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+    /// Reverses the most recent edit. Returns false if there was nothing to
+    /// undo. If the edit cannot be reversed, it is kept so that it can be
+    /// undone later.
+    pub fn undo(&mut self, circuit: &mut Circuit) -> Result<bool, EditError> {
+        match self.undo.back().cloned() {
+            Some(command) => {
+                let inverse = self.apply(circuit, command)?;
+                self.undo.pop_back();
+                self.redo.push(inverse);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+    /// Reapplies the most recently undone edit. Returns false if there was
+    /// nothing to redo. If the edit cannot be reapplied, it is kept so that
+    /// it can be redone later.
+    pub fn redo(&mut self, circuit: &mut Circuit) -> Result<bool, EditError> {
+        match self.redo.last().cloned() {
+            Some(command) => {
+                let inverse = self.apply(circuit, command)?;
+                self.redo.pop();
+                self.undo.push_back(inverse);
+                self.trim();
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::gates;
+
+    #[test]
+    fn undo_and_redo() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let mut stack = CommandStack::new();
+
+        let gate = stack.add_component(&mut circuit, lib.create("and_gate").unwrap(), 0, 0);
+        let wire = stack.add_wire(&mut circuit, Wire::new((0, 0), (0, 4)));
+        stack.execute(&mut circuit, Command::MoveComponent { id: gate, x: 5, y: 6 }).unwrap();
+        let uuid = circuit.wire_uuid(wire);
+        stack.execute(&mut circuit, Command::RemoveWire { id: wire }).unwrap();
+        assert_eq!(circuit.num_wires(), 0);
+
+        assert!(stack.undo(&mut circuit).unwrap());
+        assert_eq!(circuit.get_wire(wire), Some(&Wire::new((0, 0), (0, 4))));
+        assert_eq!(circuit.wire_uuid(wire), uuid);
+        assert!(stack.undo(&mut circuit).unwrap());
+        assert_eq!(circuit.get_component(gate).unwrap().position(), (0, 0));
+        assert!(stack.redo(&mut circuit).unwrap());
+        assert_eq!(circuit.get_component(gate).unwrap().position(), (5, 6));
+
+        while stack.undo(&mut circuit).unwrap() {}
+        assert!(circuit.is_empty());
+        assert!(stack.redo(&mut circuit).unwrap());
+        assert!(circuit.get_component(gate).is_some());
+        assert!(stack.execute(&mut circuit, Command::RemoveWire { id: wire }).is_err());
+        assert!(stack.can_redo());
+
+        // An edit which fails to apply stays on its stack
+        let component = circuit.remove_component(gate).unwrap();
+        assert!(stack.undo(&mut circuit).is_err());
+        assert!(stack.can_undo());
+        circuit.insert_component_with_uuid(gate, component, None);
+        assert!(stack.undo(&mut circuit).unwrap());
+        assert!(stack.redo(&mut circuit).unwrap());
+        assert!(stack.redo(&mut circuit).unwrap());
+        let component = circuit.remove_component(gate).unwrap();
+        assert!(stack.redo(&mut circuit).is_err());
+        assert!(stack.can_redo());
+        circuit.insert_component_with_uuid(gate, component, None);
+        assert!(stack.redo(&mut circuit).unwrap());
+        assert_eq!(circuit.get_component(gate).unwrap().position(), (5, 6));
+    }
+
+    #[test]
+    fn groups_and_depth() {
+        let lib = gates::library();
+        let mut circuit = Circuit::new();
+        let mut stack = CommandStack::with_depth(2);
+
+        stack.begin_group();
+        let a = stack.add_component(&mut circuit, lib.create("or_gate").unwrap(), 0, 0);
+        stack.begin_group();
+        stack.add_wire(&mut circuit, Wire::new((0, 0), (4, 0)));
+        stack.end_group();
+        let result = stack.execute(&mut circuit, Command::Group(vec![
+            Command::MoveComponent { id: a, x: 1, y: 1 },
+            Command::RemoveComponent { id: ComponentId(100) },
+        ]));
+        assert!(result.is_err());
+        assert_eq!(circuit.get_component(a).unwrap().position(), (0, 0));
+        stack.end_group();
+
+        assert!(stack.undo(&mut circuit).unwrap());
+        assert!(circuit.is_empty());
+        assert!(!stack.can_undo());
+
+        for i in 0..3 {
+            stack.add_wire(&mut circuit, Wire::new((i, 0), (i, 1)));
+        }
+        while stack.undo(&mut circuit).unwrap() {}
+        assert_eq!(circuit.num_wires(), 1);
+    }
+}
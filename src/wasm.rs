@@ -0,0 +1,311 @@
+//! Components implemented in WebAssembly, as a sandboxed alternative to
+//! native plugins. Each component runs in its own instance of the module,
+//! which can only access its own memory, and each call into the module is
+//! limited to a fixed amount of work so that a faulty component cannot hang
+//! the simulation.
+//!
+//! Data is exchanged as JSON through the module's memory. Arguments are
+//! copied into buffers the module allocates, and results are returned as a
+//! pointer in the upper 32 bits of an `i64` and a length in the lower 32.
+//! Values are written as strings of bits, most significant first, such as
+//! `"01XZ"`. The module must export:
+//!
+//! ```text
+//! memory
+//! alloc(len: i32) -> i32
+//! metadata() -> i64                  {"id", "name", "category", "description"}
+//! schema() -> i64                    the `Schema` of the component's properties
+//! shape() -> i64                     the `Shape` of the component
+//! get_property(name) -> i64          a JSON value, or nothing if there is no such property
+//! set_property(name, value) -> i64   nothing on success, otherwise an error message
+//! evaluate(inputs) -> i64            the value driven onto each pin, given the value on each pin
+//! ```
+//!
+//! and may also export `update(inputs)`, which is called before each
+//! evaluation as for `Component::clock_edge`. The schema and shape are fetched
+//! again whenever a property changes.
+//!
+//! Requires the `wasm` feature.
+
+use std::fmt;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json;
+use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store};
+
+use crate::component::{Component, Properties, Schema, PropertyError, Shape, PinValues};
+use crate::library::{Library, ComponentMetadata};
+use crate::simulation::{Bit, Value};
+
+/// The amount of work a single call into a module may do before it is
+/// abandoned, in the units used by `wasmi`.
+pub const FUEL_PER_CALL: u64 = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub enum WasmError {
+    /// The module could not be read, compiled or instantiated.
+    Load(String),
+    MissingExport(&'static str),
+    /// A function of the module failed, or ran out of fuel.
+    Trap { function: &'static str, message: String },
+    /// A function of the module returned something which could not be
+    /// understood.
+    InvalidResult { function: &'static str, message: String },
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WasmError::Load(message) => write!(f, "Failed to load WebAssembly module: {}", message),
+            WasmError::MissingExport(name) => write!(f, "The module does not export `{}`", name),
+            WasmError::Trap { function, message } => write!(f, "`{}` failed: {}", function, message),
+            WasmError::InvalidResult { function, message } =>
+                write!(f, "`{}` returned an invalid result: {}", function, message),
+        }
+    }
+}
+
+impl Error for WasmError {}
+
+#[derive(Deserialize)]
+struct GuestMetadata {
+    id: String,
+    name: String,
+    category: String,
+    description: String,
+}
+
+// A compiled module, from which each component is instantiated.
+struct WasmModule {
+    engine: Engine,
+    module: Module,
+}
+
+// A running instance of a module.
+struct Guest {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+    has_update: bool,
+}
+
+impl Guest {
+    fn new(module: &WasmModule) -> Result<Self, WasmError> {
+        let mut store = Store::new(&module.engine, ());
+        store.set_fuel(FUEL_PER_CALL).map_err(|e| WasmError::Load(e.to_string()))?;
+        let instance = Linker::<()>::new(&module.engine)
+            .instantiate(&mut store, &module.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| WasmError::Load(e.to_string()))?;
+        let memory = instance.get_memory(&store, "memory").ok_or(WasmError::MissingExport("memory"))?;
+        let has_update = instance.get_typed_func::<(i32, i32), i64>(&store, "update").is_ok();
+        Ok(Guest { store, instance, memory, has_update })
+    }
+    // Copies an argument into a buffer allocated by the module
+    fn write(&mut self, bytes: &[u8]) -> Result<(i32, i32), WasmError> {
+        let alloc = self.instance.get_typed_func::<i32, i32>(&self.store, "alloc")
+            .map_err(|_| WasmError::MissingExport("alloc"))?;
+        self.store.set_fuel(FUEL_PER_CALL).map_err(|e| WasmError::Load(e.to_string()))?;
+        let len = bytes.len() as i32;
+        let ptr = alloc.call(&mut self.store, len)
+            .map_err(|e| WasmError::Trap { function: "alloc", message: e.to_string() })?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|e| WasmError::InvalidResult { function: "alloc", message: e.to_string() })?;
+        Ok((ptr, len))
+    }
+    fn call(&mut self, function: &'static str, args: &[&[u8]]) -> Result<Vec<u8>, WasmError> {
+        let args = args.iter().map(|arg| self.write(arg)).collect::<Result<Vec<_>, _>>()?;
+        self.store.set_fuel(FUEL_PER_CALL).map_err(|e| WasmError::Load(e.to_string()))?;
+        let missing = |_| WasmError::MissingExport(function);
+        let result = match *args {
+            [] => self.instance.get_typed_func::<(), i64>(&self.store, function).map_err(missing)?
+                .call(&mut self.store, ()),
+            [(p0, l0)] => self.instance.get_typed_func::<(i32, i32), i64>(&self.store, function).map_err(missing)?
+                .call(&mut self.store, (p0, l0)),
+            [(p0, l0), (p1, l1)] => self.instance.get_typed_func::<(i32, i32, i32, i32), i64>(&self.store, function).map_err(missing)?
+                .call(&mut self.store, (p0, l0, p1, l1)),
+            _ => unreachable!("Functions take at most two arguments"),
+        };
+        let packed = result.map_err(|e| WasmError::Trap { function, message: e.to_string() })?;
+        let (ptr, len) = unpack(packed);
+        let mut bytes = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut bytes)
+            .map_err(|e| WasmError::InvalidResult { function, message: e.to_string() })?;
+        Ok(bytes)
+    }
+    fn call_json<T: DeserializeOwned>(&mut self, function: &'static str, args: &[&[u8]]) -> Result<T, WasmError> {
+        let bytes = self.call(function, args)?;
+        serde_json::from_slice(&bytes).map_err(|e| WasmError::InvalidResult { function, message: e.to_string() })
+    }
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    ((packed as u64 >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize)
+}
+
+fn encode_values(values: &[Value]) -> Vec<u8> {
+    let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+    serde_json::to_vec(&values).expect("Strings can be serialized")
+}
+
+fn decode_values(text: &[String]) -> Option<Vec<Value>> {
+    text.iter().map(|v| {
+        let bits: Option<Vec<_>> = v.chars().rev().map(Bit::from_char).collect();
+        bits.map(Value::new)
+    }).collect()
+}
+
+/// A component whose behaviour is implemented by a WebAssembly module.
+pub struct WasmComponent {
+    module: Arc<WasmModule>,
+    guest: Mutex<Guest>,
+    schema: Schema,
+    shape: Shape,
+}
+
+impl WasmComponent {
+    fn new(module: Arc<WasmModule>) -> Result<Self, WasmError> {
+        let mut guest = Guest::new(&module)?;
+        let schema = guest.call_json("schema", &[])?;
+        let shape = guest.call_json("shape", &[])?;
+        Ok(WasmComponent { module, guest: Mutex::new(guest), schema, shape })
+    }
+    fn guest(&self) -> MutexGuard<'_, Guest> {
+        // A panic while the guest was locked cannot leave it in a worse
+        // state than a trap would
+        self.guest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    // The schema and shape may depend on the values of properties
+    fn refresh(&mut self) -> Result<(), WasmError> {
+        let mut guest = self.guest();
+        let schema = guest.call_json("schema", &[])?;
+        let shape = guest.call_json("shape", &[])?;
+        drop(guest);
+        self.schema = schema;
+        self.shape = shape;
+        Ok(())
+    }
+}
+
+impl Clone for WasmComponent {
+    // The module's memory is not copied. Instead a new instance is given the
+    // same property values.
+    fn clone(&self) -> Self {
+        let mut result = WasmComponent::new(self.module.clone())
+            .expect("Module was instantiated when it was loaded");
+        for name in self.schema.keys() {
+            if let Some(value) = self.get_property(name) {
+                let _ = result.set_property(name, value);
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Debug for WasmComponent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WasmComponent")
+            .field("schema", &self.schema)
+            .field("shape", &self.shape)
+            .finish()
+    }
+}
+
+impl Properties for WasmComponent {
+    fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+    fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<(), PropertyError> {
+        let value = serde_json::to_vec(&value).expect("Values can be serialized");
+        let invalid = |e: WasmError| PropertyError::invalid(name, e.to_string());
+        let message = self.guest().call("set_property", &[name.as_bytes(), &value]).map_err(invalid)?;
+        if !message.is_empty() {
+            return Err(PropertyError::invalid(name, String::from_utf8_lossy(&message)));
+        }
+        self.refresh().map_err(invalid)
+    }
+    fn get_property(&self, name: &str) -> Option<serde_json::Value> {
+        let bytes = self.guest().call("get_property", &[name.as_bytes()]).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+impl Component for WasmComponent {
+    fn get_shape(&self) -> Shape {
+        self.shape.clone()
+    }
+    fn evaluate(&self, inputs: &PinValues) -> PinValues {
+        let result = self.guest().call_json::<Vec<String>>("evaluate", &[&encode_values(inputs)]);
+        match result.ok().and_then(|values| decode_values(&values)) {
+            Some(values) => values,
+            // Outputs are in error if the module fails
+            None => self.shape.pins.iter()
+                .map(|pin| if pin.direction.is_output() { Value::error(pin.bits) } else { Value::floating(pin.bits) })
+                .collect(),
+        }
+    }
+    fn clock_edge(&mut self, inputs: &PinValues) {
+        let mut guest = self.guest();
+        if guest.has_update {
+            let _ = guest.call("update", &[&encode_values(inputs)]);
+        }
+    }
+}
+
+/// Loads a WebAssembly module, returning a library containing the component
+/// it implements.
+pub fn load(wasm: &[u8]) -> Result<Library, WasmError> {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm).map_err(|e| WasmError::Load(e.to_string()))?;
+    let module = Arc::new(WasmModule { engine, module });
+
+    // Check that the module works before adding it
+    let mut guest = Guest::new(&module)?;
+    let metadata: GuestMetadata = guest.call_json("metadata", &[])?;
+    WasmComponent::new(module.clone())?;
+
+    let mut library = Library::new();
+    library.add(
+        ComponentMetadata::new(metadata.id, metadata.name, metadata.category, metadata.description),
+        move || Box::new(WasmComponent::new(module.clone()).expect("Module was instantiated when it was loaded"))
+    );
+    Ok(library)
+}
+
+/// As for `load`, reading the module from a `.wasm` file.
+pub fn load_file(path: &Path) -> Result<Library, WasmError> {
+    let wasm = fs::read(path).map_err(|e| WasmError::Load(format!("{}: {}", path.display(), e)))?;
+    load(&wasm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchanges_values() {
+        assert_eq!(unpack(0x0000_1000_0000_0020), (0x1000, 0x20));
+        assert_eq!(unpack(-1), (0xffff_ffff, 0xffff_ffff));
+
+        let values = vec![Value::from_u64(5, 4), Value::new(vec![Bit::HighZ, Bit::Unknown])];
+        let encoded: Vec<String> = serde_json::from_slice(&encode_values(&values)).unwrap();
+        assert_eq!(encoded, vec!["0101", "XZ"]);
+        assert_eq!(decode_values(&encoded), Some(values));
+        assert_eq!(decode_values(&["01q".to_string()]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_modules() {
+        match load(b"not a module") {
+            Err(WasmError::Load(_)) => {},
+            other => panic!("{:?}", other.map(|library| library.list())),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Derives the property handling part of a component from its fields.
+//!
+//! ```ignore
+//! #[derive(Debug, Clone, Component)]
+//! struct Gate {
+//!     #[property(name = "Number of inputs", min = 2, max = 32)]
+//!     num_inputs: u32,
+//!     // Fields without a `property` attribute are not exposed
+//!     state: bool,
+//! }
+//! ```
+//!
+//! This implements `Properties` for the type. The field type determines the
+//! `FieldType` via `ReflectType`, and the following attributes are accepted:
+//!
+//! - `name = "..."`: the display name, defaulting to the field name.
+//! - `id = "..."`: the property id, defaulting to the field name.
+//! - `description = "..."`
+//! - `min = n`, `max = n`: bounds for integer and float fields.
+//! - `step = x`: the step of a float field.
+//! - `unit = "..."`: the unit a numeric field is displayed in, without SI
+//!   prefixes.
+//! - `group = "..."`, `order = n`: where the field is shown in a property
+//!   panel.
+//! - `depends_on = "..."`: another property which affects this one. May be
+//!   repeated.
+//! - `read_only`
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+struct Property {
+    field: String,
+    ty: String,
+    id: Option<String>,
+    name: Option<String>,
+    description: Option<String>,
+    min: Option<String>,
+    max: Option<String>,
+    step: Option<String>,
+    unit: Option<String>,
+    group: Option<String>,
+    order: Option<String>,
+    depends_on: Vec<String>,
+    read_only: bool,
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    match token {
+        TokenTree::Punct(p) => p.as_char() == c,
+        _ => false,
+    }
+}
+
+// Splits a token list on commas which are not nested within angle brackets
+fn split_commas(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut result = vec![Vec::new()];
+    let mut depth = 0;
+    for token in tokens {
+        if is_punct(&token, '<') {
+            depth += 1;
+        } else if is_punct(&token, '>') {
+            depth -= 1;
+        } else if depth == 0 && is_punct(&token, ',') {
+            result.push(Vec::new());
+            continue;
+        }
+        result.last_mut().unwrap().push(token);
+    }
+    result.retain(|part| !part.is_empty());
+    result
+}
+
+fn string_literal(tokens: &[TokenTree]) -> Result<String, String> {
+    match tokens {
+        [TokenTree::Literal(lit)] => {
+            let s = lit.to_string();
+            if s.starts_with('"') && s.ends_with('"') {
+                Ok(s)
+            } else {
+                Err(format!("expected a string literal, found `{}`", s))
+            }
+        },
+        _ => Err("expected a string literal".into()),
+    }
+}
+
+fn integer_literal(tokens: &[TokenTree]) -> Result<String, String> {
+    let text: String = tokens.iter().map(|t| t.to_string()).collect();
+    text.parse::<i64>()
+        .map(|_| text.clone())
+        .map_err(|_| format!("expected an integer, found `{}`", text))
+}
+
+fn float_literal(tokens: &[TokenTree]) -> Result<String, String> {
+    let text: String = tokens.iter().map(|t| t.to_string()).collect();
+    text.parse::<f64>()
+        .map(|_| if text.contains('.') { text.clone() } else { format!("{}.0", text) })
+        .map_err(|_| format!("expected a number, found `{}`", text))
+}
+
+fn parse_property_attr(property: &mut Property, args: TokenStream) -> Result<(), String> {
+    for arg in split_commas(args.into_iter().collect()) {
+        let key = arg[0].to_string();
+        let value = if arg.len() > 2 && is_punct(&arg[1], '=') { &arg[2..] } else { &arg[1..] };
+        match key.as_str() {
+            "id" => property.id = Some(string_literal(value)?),
+            "name" => property.name = Some(string_literal(value)?),
+            "description" => property.description = Some(string_literal(value)?),
+            "min" => property.min = Some(integer_literal(value)?),
+            "max" => property.max = Some(integer_literal(value)?),
+            "step" => property.step = Some(float_literal(value)?),
+            "unit" => property.unit = Some(string_literal(value)?),
+            "group" => property.group = Some(string_literal(value)?),
+            "order" => property.order = Some(integer_literal(value)?),
+            "depends_on" => property.depends_on.push(string_literal(value)?),
+            "read_only" if value.is_empty() => property.read_only = true,
+            _ => return Err(format!("unknown property attribute `{}`", key)),
+        }
+    }
+    Ok(())
+}
+
+// Returns the properties declared on the fields of a struct
+fn parse_fields(body: TokenStream) -> Result<Vec<Property>, String> {
+    let mut result = Vec::new();
+    for field in split_commas(body.into_iter().collect()) {
+        let mut attrs = Vec::new();
+        let mut i = 0;
+        while i + 1 < field.len() && is_punct(&field[i], '#') {
+            if let TokenTree::Group(g) = &field[i + 1] {
+                let inner: Vec<_> = g.stream().into_iter().collect();
+                if inner.len() == 2 && inner[0].to_string() == "property" {
+                    if let TokenTree::Group(args) = &inner[1] {
+                        attrs.push(args.stream());
+                    }
+                }
+            }
+            i += 2;
+        }
+        if field.get(i).map(|t| t.to_string()) == Some("pub".into()) {
+            i += 1;
+            if let Some(TokenTree::Group(g)) = field.get(i) {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    i += 1;
+                }
+            }
+        }
+        if attrs.is_empty() {
+            continue;
+        }
+        if field.len() < i + 3 || !is_punct(&field[i + 1], ':') {
+            return Err("expected a named field".into());
+        }
+        let ty: TokenStream = field[i + 2..].iter().cloned().collect();
+        let mut property = Property {
+            field: field[i].to_string(),
+            ty: ty.to_string(),
+            id: None,
+            name: None,
+            description: None,
+            min: None,
+            max: None,
+            step: None,
+            unit: None,
+            group: None,
+            order: None,
+            depends_on: Vec::new(),
+            read_only: false,
+        };
+        for args in attrs {
+            parse_property_attr(&mut property, args)?;
+        }
+        result.push(property);
+    }
+    Ok(result)
+}
+
+fn parse_struct(input: TokenStream) -> Result<(String, Vec<Property>), String> {
+    let tokens: Vec<_> = input.into_iter().collect();
+    let position = tokens.iter().position(|t| t.to_string() == "struct")
+        .ok_or("`Component` can only be derived for structs")?;
+    let name = tokens.get(position + 1).map(|t| t.to_string())
+        .ok_or("expected a struct name")?;
+    if tokens.get(position + 2).map_or(false, |t| is_punct(t, '<')) {
+        return Err("`Component` cannot be derived for generic structs".into());
+    }
+    let properties = match tokens.get(position + 2) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => parse_fields(g.stream())?,
+        _ => Vec::new(),
+    };
+    Ok((name, properties))
+}
+
+fn generate(name: &str, properties: &[Property]) -> String {
+    let krate = "::tenorite_ui::component";
+    let mut schema = String::new();
+    let mut set = String::new();
+    let mut get = String::new();
+    for p in properties {
+        let id = p.id.clone().unwrap_or_else(|| format!("{:?}", p.field));
+        let display_name = p.name.clone().unwrap_or_else(|| format!("{:?}", p.field));
+        let mut type_ = format!("<{} as {}::ReflectType>::field_type()", p.ty, krate);
+        if p.min.is_some() || p.max.is_some() {
+            type_ = format!(
+                "{}.with_range({}, {})",
+                type_,
+                p.min.as_ref().map_or("None".into(), |m| format!("Some({})", m)),
+                p.max.as_ref().map_or("None".into(), |m| format!("Some({})", m)),
+            );
+        }
+        if let Some(step) = &p.step {
+            type_ = format!("{}.with_step({})", type_, step);
+        }
+        if let Some(unit) = &p.unit {
+            type_ = format!("{}.with_unit({}::Unit::new({}))", type_, krate, unit);
+        }
+        let mut field_schema = format!("{}::FieldSchema::new({}, {})", krate, display_name, type_);
+        if let Some(description) = &p.description {
+            field_schema = format!("{}.with_description({})", field_schema, description);
+        }
+        if let Some(group) = &p.group {
+            field_schema = format!("{}.with_group({})", field_schema, group);
+        }
+        if let Some(order) = &p.order {
+            field_schema = format!("{}.with_order({})", field_schema, order);
+        }
+        for dependency in &p.depends_on {
+            field_schema = format!("{}.depends_on({})", field_schema, dependency);
+        }
+        if p.read_only {
+            field_schema = format!("{}.read_only()", field_schema);
+        }
+        schema += &format!("result.insert({}.into(), {});", id, field_schema);
+        if p.read_only {
+            set += &format!("{} => Err({}::PropertyError::read_only(name)),", id, krate);
+        } else {
+            set += &format!("{} => {}::set_field(&mut self.{}, name, value),", id, krate, p.field);
+        }
+        get += &format!("{} => {}::get_field(&self.{}),", id, krate, p.field);
+    }
+    format!("
+        impl {krate}::Properties for {name} {{
+            fn schema(&self) -> {krate}::Schema {{
+                #[allow(unused_mut)]
+                let mut result = {krate}::Schema::new();
+                {schema}
+                result
+            }}
+            fn set_property(&mut self, name: &str, value: ::serde_json::Value) -> Result<(), {krate}::PropertyError> {{
+                #[allow(unused_variables)]
+                let value = value;
+                match name {{
+                    {set}
+                    _ => Err({krate}::PropertyError::unknown(name)),
+                }}
+            }}
+            fn get_property(&self, name: &str) -> Option<::serde_json::Value> {{
+                match name {{
+                    {get}
+                    _ => None,
+                }}
+            }}
+        }}
+    ", krate = krate, name = name, schema = schema, set = set, get = get)
+}
+
+#[proc_macro_derive(Component, attributes(property))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let code = match parse_struct(input) {
+        Ok((name, properties)) => generate(&name, &properties),
+        Err(e) => format!("compile_error!({:?});", e),
+    };
+    code.parse().expect("Generated invalid code")
+}